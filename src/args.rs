@@ -1,5 +1,14 @@
+use crate::filter::FilterRule;
+use crate::packet::layer::tcp::UrgentPointer;
+use crate::pcap::inject::{ClassWeights, InjectorKind};
+use crate::pool::ExhaustedPolicy;
+use crate::route::{Route, RouteRule};
+use crate::socks::{SelectMode, SocksVersion};
 use clap::{crate_description, crate_version, Clap};
+use pnet::datalink::MacAddr;
 use std::clone::Clone;
+#[cfg(feature = "metrics")]
+use std::net::SocketAddr;
 use std::net::{Ipv4Addr, SocketAddrV4};
 
 /// Represents the flags of the application.
@@ -16,17 +25,159 @@ pub struct Flags {
         parse(from_occurrences)
     )]
     pub verbose: usize,
+    #[clap(
+        long = "log",
+        about = "Log filters per subsystem, e.g., pcap2socks::socks=trace,pcap2socks::tcp=debug",
+        value_name = "FILTERS"
+    )]
+    pub log_filters: Option<String>,
     #[clap(
         long = "interface",
         short,
-        about = "Interface for listening",
+        about = "Interfaces for listening",
         value_name = "INTERFACE"
     )]
-    pub inter: Option<String>,
-    #[clap(long, about = "MTU", value_name = "VALUE", default_value = "1400")]
+    pub inter: Vec<String>,
+    #[clap(
+        long = "read",
+        short = "r",
+        about = "Reads frames from a pcap file instead of listening",
+        value_name = "FILE"
+    )]
+    pub file: Option<String>,
+    #[clap(
+        long,
+        about = "Replays frames from the pcap file with the recorded timing",
+        requires = "file"
+    )]
+    pub realtime: bool,
+    #[clap(
+        long,
+        about = "Dumps frames sent into a pcap file",
+        value_name = "FILE"
+    )]
+    pub dump: Option<String>,
+    #[clap(
+        long = "dump-snaplen",
+        about = "Max bytes of each frame dumped",
+        value_name = "VALUE",
+        default_value = "65535"
+    )]
+    pub dump_snaplen: u32,
+    #[clap(
+        long,
+        about = "MTU, up to 9216 for jumbo frames",
+        value_name = "VALUE",
+        default_value = "1400"
+    )]
     pub mtu: u16,
+    #[clap(
+        long = "enforce-mtu",
+        about = "Reply ICMP fragmentation needed to packets exceeding the MTU which cannot be fragmented"
+    )]
+    pub enforce_mtu: bool,
+    #[clap(
+        long = "tcp-mss",
+        about = "TCP maximum segment size advertised",
+        value_name = "VALUE"
+    )]
+    pub tcp_mss: Option<u16>,
+    #[clap(
+        long = "tcp-wscale",
+        about = "TCP window scale shift count advertised",
+        value_name = "VALUE"
+    )]
+    pub tcp_wscale: Option<u8>,
+    #[clap(
+        long = "tcp-ecn",
+        about = "Negotiate TCP ECN with sources requesting it"
+    )]
+    pub tcp_ecn: bool,
+    #[clap(
+        long = "tcp-urgent-pointer",
+        about = "Interpretation of TCP urgent pointers, bsd or rfc1122",
+        value_name = "INTERPRETATION",
+        default_value = "bsd"
+    )]
+    pub tcp_urgent_pointer: UrgentPointer,
+    #[clap(
+        long,
+        about = "DSCP of packets sent, instead of the one received",
+        value_name = "VALUE"
+    )]
+    pub dscp: Option<u8>,
+    #[clap(
+        long,
+        about = "Fixed TTL of packets sent, instead of the initial one",
+        value_name = "VALUE",
+        conflicts_with = "decrement-ttl"
+    )]
+    pub ttl: Option<u8>,
+    #[clap(
+        long = "decrement-ttl",
+        about = "Decrements the TTL of packets sent, as packets forwarded by a router"
+    )]
+    pub decrement_ttl: bool,
     #[clap(long, short, about = "ARP publishing address", value_name = "ADDRESS")]
     pub publish: Option<Ipv4Addr>,
+    #[clap(
+        long = "gratuitous-arp-interval",
+        about = "Seconds between gratuitous ARPs announcing the publishing address (default only on startup)",
+        value_name = "SECONDS",
+        requires = "publish"
+    )]
+    pub gratuitous_arp_interval: Option<u64>,
+    #[clap(
+        long = "hardware-address",
+        about = "Source hardware address of frames sent, instead of the interface's",
+        value_name = "ADDRESS"
+    )]
+    pub hardware_addr: Option<MacAddr>,
+    #[clap(
+        long = "injector",
+        about = "Backend injecting frames, pcap or raw (Linux only)",
+        value_name = "INJECTOR",
+        default_value = "pcap"
+    )]
+    pub injector: InjectorKind,
+    #[clap(
+        long = "checksum-offload",
+        about = "Leave IPv4, TCP and UDP checksums of frames sent to the checksum offload of the link"
+    )]
+    pub checksum_offload: bool,
+    #[clap(
+        long = "queue-weights",
+        about = "Queue frames sent in high, normal and low priority classes with weights, e.g., 4,2,1",
+        value_name = "WEIGHTS"
+    )]
+    pub queue_weights: Option<ClassWeights>,
+    #[clap(
+        long = "pool-policy",
+        about = "Policy of buffer pools when all buffers are in use, drop or block",
+        value_name = "POLICY",
+        default_value = "drop",
+        possible_values = &["drop", "block"]
+    )]
+    pub pool_policy: ExhaustedPolicy,
+    #[clap(
+        long,
+        about = "Deliver captured frames immediately with a smaller read buffer"
+    )]
+    pub immediate: bool,
+    #[clap(
+        long = "buffer-size",
+        about = "Capture buffer size in bytes",
+        value_name = "VALUE",
+        default_value = "262144"
+    )]
+    pub buffer_size: usize,
+    #[clap(
+        long,
+        about = "Max bytes captured of each frame, frames truncated are dropped",
+        value_name = "VALUE",
+        default_value = "65535"
+    )]
+    pub snaplen: usize,
     #[clap(long = "source", short, about = "Source", value_name = "ADDRESS")]
     pub src: Ipv4Addr,
     #[clap(
@@ -37,6 +188,221 @@ pub struct Flags {
         default_value = "127.0.0.1:1080"
     )]
     pub dst: SocketAddrV4,
+    #[clap(
+        long = "backup-destination",
+        about = "Backup proxies connected in order when the destination fails",
+        value_name = "ADDRESS",
+        number_of_values = 1
+    )]
+    pub backup_dsts: Vec<SocketAddrV4>,
+    #[clap(
+        long = "select-mode",
+        about = "Mode of selecting proxies, failover or load-balance among healthy ones",
+        value_name = "MODE",
+        default_value = "failover",
+        possible_values = &["failover", "load-balance"]
+    )]
+    pub select_mode: SelectMode,
+    #[clap(
+        long = "socks-version",
+        about = "Version of the SOCKS protocol of the proxy, 5 or 4 (TCP only, with SOCKS4a)",
+        value_name = "VERSION",
+        default_value = "5",
+        possible_values = &["4", "5"]
+    )]
+    pub socks_version: SocksVersion,
+    #[clap(
+        long = "proxy-cooldown",
+        about = "Seconds a failing proxy is skipped for",
+        value_name = "SECONDS",
+        default_value = "30"
+    )]
+    pub proxy_cooldown: u64,
+    #[clap(
+        long = "bind",
+        about = "Local address which connections to the proxy are bound to",
+        value_name = "ADDRESS"
+    )]
+    pub bind: Option<Ipv4Addr>,
+    #[clap(
+        long = "connect-timeout",
+        about = "Milliseconds waiting for connecting to the proxy, 0 for the system default",
+        value_name = "MILLISECONDS",
+        default_value = "0"
+    )]
+    pub connect_timeout: u64,
+    #[clap(
+        long = "connect-retries",
+        about = "Max number of retries connecting to the proxy",
+        value_name = "VALUE",
+        default_value = "0"
+    )]
+    pub connect_retries: usize,
+    #[clap(
+        long = "retry-delay",
+        about = "Milliseconds waiting before the first retry, doubled after each retry",
+        value_name = "MILLISECONDS",
+        default_value = "200"
+    )]
+    pub retry_delay: u64,
+    #[clap(
+        long = "grace-period",
+        about = "Seconds waiting for TCP connections to close on shutdown",
+        value_name = "SECONDS",
+        default_value = "5"
+    )]
+    pub grace_period: u64,
+    #[clap(
+        long = "max-flows",
+        about = "Max number of TCP connections tracked",
+        value_name = "VALUE",
+        default_value = "4096"
+    )]
+    pub max_flows: usize,
+    #[clap(
+        long = "max-connections",
+        about = "Max number of simultaneous TCP connections proxied, new connections over it are reset",
+        value_name = "VALUE"
+    )]
+    pub max_connections: Option<usize>,
+    #[clap(
+        long = "drop-over-max-connections",
+        about = "Drops instead of resets new connections over the max connections",
+        requires = "max-connections"
+    )]
+    pub drop_over_max_connections: bool,
+    #[clap(
+        long = "idle-timeout",
+        about = "Seconds before an idle TCP connection is reset",
+        value_name = "SECONDS",
+        default_value = "300"
+    )]
+    pub idle_timeout: u64,
+    #[clap(
+        long = "half-open-timeout",
+        about = "Seconds before a TCP connection without completing the handshake is reset",
+        value_name = "SECONDS",
+        default_value = "30"
+    )]
+    pub half_open_timeout: u64,
+    #[clap(
+        long = "udp-dedup-window",
+        about = "Milliseconds in which identical UDP datagrams of a flow are dropped as duplicates",
+        value_name = "MILLISECONDS"
+    )]
+    pub udp_dedup_window: Option<u64>,
+    #[clap(
+        long = "keepalive",
+        about = "Seconds before a keep-alive probe is sent to the source in an idle TCP connection",
+        value_name = "SECONDS"
+    )]
+    pub keepalive: Option<u64>,
+    #[clap(
+        long = "keepalive-proxy",
+        about = "Also send keep-alive probes to the proxy in idle TCP connections",
+        requires = "keepalive"
+    )]
+    pub keepalive_proxy: bool,
+    #[clap(
+        long = "rate-limit",
+        about = "Bytes per second sent to the proxy",
+        value_name = "VALUE"
+    )]
+    pub rate_limit: Option<u64>,
+    #[clap(
+        long,
+        about = "Burst size in bytes of the rate limit (default as the rate)",
+        value_name = "VALUE",
+        requires = "rate-limit"
+    )]
+    pub burst: Option<u64>,
+    #[clap(
+        long = "drop-over-limit",
+        about = "Drops instead of queues traffic over the rate limit",
+        requires = "rate-limit"
+    )]
+    pub drop_over_limit: bool,
+    #[clap(
+        long = "route",
+        about = "Routes destinations in the network to the proxy, directly or drops them",
+        value_name = "CIDR=ROUTE",
+        number_of_values = 1
+    )]
+    pub routes: Vec<RouteRule>,
+    #[clap(
+        long = "default-route",
+        about = "Route of destinations matching no routes",
+        value_name = "ROUTE",
+        default_value = "proxy",
+        possible_values = &["proxy", "direct", "drop"]
+    )]
+    pub default_route: Route,
+    #[clap(
+        long = "exclude",
+        about = "Drops packets matching all the predicates of the rule, e.g., proto=udp,port=53",
+        value_name = "RULE",
+        number_of_values = 1
+    )]
+    pub excludes: Vec<FilterRule>,
+    #[clap(
+        long = "log-payloads",
+        about = "Logs the flow, direction and length of forwarded payloads, but not their bytes"
+    )]
+    pub log_payloads: bool,
+    #[clap(
+        long = "nat64",
+        about = "Translates IPv6 flows to destinations in 64:ff9b::/96 into IPv4 flows"
+    )]
+    pub nat64: bool,
+    #[clap(
+        long = "active-ftp",
+        about = "Bridges the data connections of active-mode FTP with SOCKS BIND commands"
+    )]
+    pub active_ftp: bool,
+    #[clap(
+        long = "redact",
+        about = "Masks occurrences of the pattern in forwarded payloads with asterisks",
+        value_name = "PATTERN",
+        number_of_values = 1
+    )]
+    pub redacts: Vec<String>,
+    #[clap(
+        long = "dns-cache",
+        about = "Caches DNS responses and answers repeated queries locally"
+    )]
+    pub dns_cache: bool,
+    #[clap(
+        long = "dns-names",
+        about = "Connects to addresses resolved from DNS responses by their names"
+    )]
+    pub dns_names: bool,
+    #[cfg(feature = "metrics")]
+    #[clap(
+        long,
+        about = "Address serving metrics in Prometheus text format, better on loopback",
+        value_name = "ADDRESS"
+    )]
+    pub metrics: Option<SocketAddr>,
+    #[clap(
+        long = "dry-run",
+        about = "Logs what would be forwarded without sending anything"
+    )]
+    pub dry_run: bool,
+    #[clap(
+        long,
+        short,
+        about = "Username for SOCKS5 authentication",
+        value_name = "USERNAME",
+        requires = "password"
+    )]
+    pub username: Option<String>,
+    #[clap(
+        long,
+        about = "Password for SOCKS5 authentication",
+        value_name = "PASSWORD",
+        requires = "username"
+    )]
+    pub password: Option<String>,
 }
 
 /// Parses the arguments.
@@ -0,0 +1,613 @@
+use lru::LruCache;
+use std::collections::HashMap;
+use std::fmt::{self, Display, Formatter};
+use std::io;
+use std::net::Ipv4Addr;
+use std::time::{Duration, Instant};
+
+/// Represents the port of DNS.
+pub const DNS_PORT: u16 = 53;
+
+/// Represents the size of the DNS header.
+const HEADER_SIZE: usize = 12;
+/// Represents the max length of a domain name.
+const MAX_NAME_LENGTH: usize = 255;
+/// Represents the max number of compression pointers followed in a domain name.
+const MAX_POINTERS: usize = 16;
+/// Represents the size of the type, class, TTL and data length of a resource record.
+const RECORD_FIXED_SIZE: usize = 10;
+
+/// Represents the header of a DNS message.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct DnsHeader {
+    pub id: u16,
+    pub flags: u16,
+    pub qdcount: u16,
+    pub ancount: u16,
+    pub nscount: u16,
+    pub arcount: u16,
+}
+
+impl DnsHeader {
+    /// Deserializes a `DnsHeader` from the given byte-array.
+    pub fn deserialize(buffer: &[u8]) -> io::Result<DnsHeader> {
+        if buffer.len() < HEADER_SIZE {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "DNS truncated"));
+        }
+
+        Ok(DnsHeader {
+            id: read_u16(buffer, 0),
+            flags: read_u16(buffer, 2),
+            qdcount: read_u16(buffer, 4),
+            ancount: read_u16(buffer, 6),
+            nscount: read_u16(buffer, 8),
+            arcount: read_u16(buffer, 10),
+        })
+    }
+
+    /// Returns if the message is a query.
+    pub fn is_query(&self) -> bool {
+        self.flags & 0x8000 == 0
+    }
+
+    /// Returns if the message is a response.
+    pub fn is_response(&self) -> bool {
+        !self.is_query()
+    }
+
+    /// Get the opcode of the message.
+    pub fn get_opcode(&self) -> u8 {
+        ((self.flags >> 11) & 0x0f) as u8
+    }
+
+    /// Get the response code of the message.
+    pub fn get_rcode(&self) -> u8 {
+        (self.flags & 0x0f) as u8
+    }
+}
+
+/// Represents a question of a DNS message.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct DnsQuestion {
+    pub name: String,
+    pub qtype: u16,
+    pub qclass: u16,
+}
+
+impl Display for DnsQuestion {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} (Type = {}, Class = {})",
+            self.name, self.qtype, self.qclass
+        )
+    }
+}
+
+/// Represents the type of A records.
+const TYPE_A: u16 = 1;
+/// Represents the class of Internet records.
+const CLASS_IN: u16 = 1;
+
+/// Represents a DNS message with its first question.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct Dns {
+    header: DnsHeader,
+    question: Option<DnsQuestion>,
+    min_ttl: Option<u32>,
+    addresses: Vec<Ipv4Addr>,
+}
+
+impl Dns {
+    /// Deserializes a `Dns` from the given byte-array. Only the header and the first question
+    /// are parsed, and the TTLs and IPv4 addresses of the answer records if the message is a
+    /// response.
+    pub fn deserialize(buffer: &[u8]) -> io::Result<Dns> {
+        let header = DnsHeader::deserialize(buffer)?;
+
+        let mut offset = HEADER_SIZE;
+        let mut question = None;
+        for i in 0..header.qdcount {
+            let (name, size) = read_name(buffer, offset)?;
+            offset += size;
+            if buffer.len() < offset + 4 {
+                return Err(io::Error::new(io::ErrorKind::InvalidData, "DNS truncated"));
+            }
+            if i == 0 {
+                question = Some(DnsQuestion {
+                    name,
+                    qtype: read_u16(buffer, offset),
+                    qclass: read_u16(buffer, offset + 2),
+                });
+            }
+            offset += 4;
+
+            // Only the first question is parsed in a query
+            if header.is_query() {
+                break;
+            }
+        }
+
+        let mut min_ttl: Option<u32> = None;
+        let mut addresses = Vec::new();
+        if header.is_response() {
+            for _ in 0..header.ancount {
+                let (_, size) = read_name(buffer, offset)?;
+                offset += size;
+                if buffer.len() < offset + RECORD_FIXED_SIZE {
+                    return Err(io::Error::new(io::ErrorKind::InvalidData, "DNS truncated"));
+                }
+                let ttl = (read_u16(buffer, offset + 4) as u32) << 16
+                    | read_u16(buffer, offset + 6) as u32;
+                let data_length = read_u16(buffer, offset + 8) as usize;
+                let rtype = read_u16(buffer, offset);
+                let rclass = read_u16(buffer, offset + 2);
+                offset += RECORD_FIXED_SIZE + data_length;
+                if buffer.len() < offset {
+                    return Err(io::Error::new(io::ErrorKind::InvalidData, "DNS truncated"));
+                }
+                if rtype == TYPE_A && rclass == CLASS_IN && data_length == 4 {
+                    let data = &buffer[offset - 4..offset];
+                    addresses.push(Ipv4Addr::new(data[0], data[1], data[2], data[3]));
+                }
+
+                min_ttl = Some(min_ttl.map_or(ttl, |min_ttl| min_ttl.min(ttl)));
+            }
+        }
+
+        Ok(Dns {
+            header,
+            question,
+            min_ttl,
+            addresses,
+        })
+    }
+
+    /// Get the header of the message.
+    pub fn get_header(&self) -> &DnsHeader {
+        &self.header
+    }
+
+    /// Get the first question of the message.
+    pub fn get_question(&self) -> Option<&DnsQuestion> {
+        self.question.as_ref()
+    }
+
+    /// Get the queried hostname of the first question.
+    pub fn get_hostname(&self) -> Option<&str> {
+        self.question
+            .as_ref()
+            .map(|question| question.name.as_str())
+    }
+
+    /// Get the minimum TTL in seconds across the answer records of a response. Returns `None` if
+    /// the message is a query or there are no answer records.
+    pub fn get_min_ttl(&self) -> Option<u32> {
+        self.min_ttl
+    }
+
+    /// Get the IPv4 addresses in the A records of the answers of a response.
+    pub fn get_addresses(&self) -> &[Ipv4Addr] {
+        &self.addresses
+    }
+}
+
+impl Display for Dns {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        let t = match self.header.is_query() {
+            true => "Query",
+            false => "Response",
+        };
+        match self.question {
+            Some(ref question) => write!(f, "DNS {}: {} {}", t, self.header.id, question),
+            None => write!(f, "DNS {}: {}", t, self.header.id),
+        }
+    }
+}
+
+/// Represents a cache of DNS responses keyed on the name and type of the question. A cached
+/// response expires after the minimum TTL across its answer records.
+#[derive(Debug, Default)]
+pub struct DnsCache {
+    entries: HashMap<(String, u16), (Vec<u8>, Instant)>,
+}
+
+impl DnsCache {
+    /// Creates a new `DnsCache`.
+    pub fn new() -> DnsCache {
+        DnsCache {
+            entries: HashMap::new(),
+        }
+    }
+
+    fn get_key(dns: &Dns) -> Option<(String, u16)> {
+        dns.get_question()
+            .map(|question| (question.name.to_ascii_lowercase(), question.qtype))
+    }
+
+    /// Inserts the given DNS response into the cache. Only successful responses with answer
+    /// records of a positive TTL are accepted. An existing entry of the same question will be
+    /// overwritten. Returns if the response is cached.
+    pub fn insert(&mut self, response: &[u8]) -> bool {
+        let dns = match Dns::deserialize(response) {
+            Ok(dns) => dns,
+            Err(_) => return false,
+        };
+        let header = dns.get_header();
+        if !header.is_response() || header.get_opcode() != 0 || header.get_rcode() != 0 {
+            return false;
+        }
+        let ttl = match dns.get_min_ttl() {
+            Some(ttl) if ttl > 0 => ttl,
+            _ => return false,
+        };
+        let key = match DnsCache::get_key(&dns) {
+            Some(key) => key,
+            None => return false,
+        };
+
+        self.entries.insert(
+            key,
+            (
+                response.to_vec(),
+                Instant::now() + Duration::from_secs(ttl as u64),
+            ),
+        );
+
+        true
+    }
+
+    /// Looks up the response of the given DNS query, and returns it with the transaction ID
+    /// rewritten to the query's. Returns `None` if there is no entry or the entry is expired.
+    pub fn lookup(&self, query: &[u8]) -> Option<Vec<u8>> {
+        self.lookup_at(query, Instant::now())
+    }
+
+    /// Looks up the response of the given DNS query as of the given instant.
+    fn lookup_at(&self, query: &[u8], now: Instant) -> Option<Vec<u8>> {
+        let dns = Dns::deserialize(query).ok()?;
+        if !dns.get_header().is_query() {
+            return None;
+        }
+        let key = DnsCache::get_key(&dns)?;
+
+        match self.entries.get(&key) {
+            Some((response, expiry)) if *expiry > now => {
+                let mut response = response.clone();
+                response[..2].copy_from_slice(&query[..2]);
+
+                Some(response)
+            }
+            _ => None,
+        }
+    }
+
+    /// Removes all expired entries and returns the number of entries removed.
+    pub fn purge(&mut self) -> usize {
+        self.purge_at(Instant::now())
+    }
+
+    /// Removes all entries expired as of the given instant and returns the number of entries
+    /// removed.
+    fn purge_at(&mut self, now: Instant) -> usize {
+        let len = self.entries.len();
+        self.entries.retain(|_, (_, expiry)| *expiry > now);
+
+        len - self.entries.len()
+    }
+
+    /// Get the number of entries in the cache, including expired entries which are not purged.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Returns if the cache contains no entries.
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+/// Represents the default number of IP addresses remembered by a `DnsNames`.
+pub const DEFAULT_DNS_NAMES_CAPACITY: usize = 4096;
+
+/// Represents the names of IP addresses learned from DNS responses, which lets connections to
+/// the addresses be requested by their names, so the names are resolved by the proxy again. An
+/// entry expires after the minimum TTL across the answer records of its response, and the least
+/// recently used entries are evicted when the map is full.
+#[derive(Debug)]
+pub struct DnsNames {
+    entries: LruCache<Ipv4Addr, (String, Instant)>,
+}
+
+impl DnsNames {
+    /// Creates a new `DnsNames`.
+    pub fn new() -> DnsNames {
+        DnsNames::with_capacity(DEFAULT_DNS_NAMES_CAPACITY)
+    }
+
+    /// Creates a new `DnsNames` with the given number of IP addresses remembered.
+    pub fn with_capacity(capacity: usize) -> DnsNames {
+        DnsNames {
+            entries: LruCache::new(capacity.max(1)),
+        }
+    }
+
+    /// Inserts the IP addresses in the given DNS response with the queried name. Only successful
+    /// responses with answer records of a positive TTL are accepted. Returns the number of IP
+    /// addresses inserted.
+    pub fn insert(&mut self, response: &[u8]) -> usize {
+        let dns = match Dns::deserialize(response) {
+            Ok(dns) => dns,
+            Err(_) => return 0,
+        };
+        let header = dns.get_header();
+        if !header.is_response() || header.get_opcode() != 0 || header.get_rcode() != 0 {
+            return 0;
+        }
+        let ttl = match dns.get_min_ttl() {
+            Some(ttl) if ttl > 0 => ttl,
+            _ => return 0,
+        };
+        let name = match dns.get_hostname() {
+            Some(name) if !name.is_empty() => name,
+            _ => return 0,
+        };
+
+        let expiry = Instant::now() + Duration::from_secs(ttl as u64);
+        for ip_addr in dns.get_addresses() {
+            self.entries.put(*ip_addr, (name.to_string(), expiry));
+        }
+
+        dns.get_addresses().len()
+    }
+
+    /// Looks up the name of the given IP address. Returns `None` if there is no entry or the
+    /// entry is expired.
+    pub fn lookup(&mut self, ip_addr: Ipv4Addr) -> Option<String> {
+        match self.entries.get(&ip_addr) {
+            Some((name, expiry)) if *expiry > Instant::now() => Some(name.clone()),
+            _ => None,
+        }
+    }
+
+    /// Get the number of entries in the map, including expired entries.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Returns if the map contains no entries.
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+impl Default for DnsNames {
+    fn default() -> DnsNames {
+        DnsNames::new()
+    }
+}
+
+fn read_u16(buffer: &[u8], offset: usize) -> u16 {
+    (buffer[offset] as u16) << 8 | buffer[offset + 1] as u16
+}
+
+/// Reads a domain name from the given offset of the byte-array, and returns it with the number of
+/// bytes it takes at the offset. Compression pointers are followed.
+fn read_name(buffer: &[u8], offset: usize) -> io::Result<(String, usize)> {
+    let mut labels: Vec<String> = Vec::new();
+    let mut length = 0;
+    let mut pos = offset;
+    let mut size = None;
+    let mut pointers = 0;
+
+    loop {
+        let b = *buffer
+            .get(pos)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "DNS truncated"))?;
+        match b & 0xc0 {
+            0x00 => {
+                // End of name
+                if b == 0 {
+                    if size.is_none() {
+                        size = Some(pos + 1 - offset);
+                    }
+                    break;
+                }
+
+                // Label
+                let label_length = b as usize;
+                let label = buffer
+                    .get(pos + 1..pos + 1 + label_length)
+                    .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "DNS truncated"))?;
+                length += label_length + 1;
+                if length > MAX_NAME_LENGTH {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        "DNS name too long",
+                    ));
+                }
+                labels.push(String::from_utf8_lossy(label).into_owned());
+                pos += 1 + label_length;
+            }
+            0xc0 => {
+                // Compression pointer
+                if pos + 1 >= buffer.len() {
+                    return Err(io::Error::new(io::ErrorKind::InvalidData, "DNS truncated"));
+                }
+                pointers += 1;
+                if pointers > MAX_POINTERS {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        "DNS too many pointers",
+                    ));
+                }
+                if size.is_none() {
+                    size = Some(pos + 2 - offset);
+                }
+                pos = (read_u16(buffer, pos) & 0x3fff) as usize;
+            }
+            _ => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "DNS invalid label",
+                ))
+            }
+        }
+    }
+
+    Ok((labels.join("."), size.unwrap()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A query of the A record of example.com
+    #[rustfmt::skip]
+    const QUERY: [u8; 29] = [
+        0x1a, 0x2b, 0x01, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x07, b'e', b'x', b'a', b'm', b'p', b'l', b'e', 0x03, b'c', b'o', b'm', 0x00,
+        0x00, 0x01, 0x00, 0x01,
+    ];
+
+    /// Builds a response to `QUERY` answering the given IP address with the given TTL. The name
+    /// of the answer record is compressed.
+    fn build_response(ip_addr: Ipv4Addr, ttl: u32) -> Vec<u8> {
+        let mut response = QUERY.to_vec();
+        // Flags and the number of answers
+        response[2..4].copy_from_slice(&[0x81, 0x80]);
+        response[6..8].copy_from_slice(&[0x00, 0x01]);
+        response.extend_from_slice(&[0xc0, 0x0c, 0x00, 0x01, 0x00, 0x01]);
+        response.extend_from_slice(&ttl.to_be_bytes());
+        response.extend_from_slice(&[0x00, 0x04]);
+        response.extend_from_slice(&ip_addr.octets());
+
+        response
+    }
+
+    #[test]
+    fn deserialize_query() {
+        let dns = Dns::deserialize(&QUERY).unwrap();
+
+        let header = dns.get_header();
+        assert_eq!(header.id, 0x1a2b);
+        assert!(header.is_query());
+        assert_eq!(header.qdcount, 1);
+        assert_eq!(dns.get_hostname(), Some("example.com"));
+        let question = dns.get_question().unwrap();
+        assert_eq!(question.qtype, TYPE_A);
+        assert_eq!(question.qclass, CLASS_IN);
+    }
+
+    #[test]
+    fn deserialize_truncated() {
+        assert!(Dns::deserialize(&QUERY[..HEADER_SIZE - 1]).is_err());
+        assert!(Dns::deserialize(&QUERY[..QUERY.len() - 1]).is_err());
+    }
+
+    #[test]
+    fn read_name_compressed() {
+        let response = build_response(Ipv4Addr::new(93, 184, 216, 34), 3600);
+
+        let (name, size) = read_name(&response, QUERY.len()).unwrap();
+        assert_eq!(name, "example.com");
+        assert_eq!(size, 2);
+    }
+
+    #[test]
+    fn read_name_pointer_loop() {
+        let mut buffer = QUERY.to_vec();
+        // The name points to itself
+        buffer[HEADER_SIZE..HEADER_SIZE + 2].copy_from_slice(&[0xc0, 0x0c]);
+
+        assert!(read_name(&buffer, HEADER_SIZE).is_err());
+    }
+
+    #[test]
+    fn deserialize_response() {
+        let ip_addr = Ipv4Addr::new(93, 184, 216, 34);
+        let dns = Dns::deserialize(&build_response(ip_addr, 3600)).unwrap();
+
+        assert!(dns.get_header().is_response());
+        assert_eq!(dns.get_min_ttl(), Some(3600));
+        assert_eq!(dns.get_addresses(), [ip_addr]);
+    }
+
+    #[test]
+    fn names_lookup() {
+        let ip_addr = Ipv4Addr::new(93, 184, 216, 34);
+        let mut names = DnsNames::new();
+
+        assert_eq!(names.insert(&build_response(ip_addr, 3600)), 1);
+        assert_eq!(names.lookup(ip_addr), Some("example.com".to_string()));
+        assert_eq!(names.lookup(Ipv4Addr::new(93, 184, 216, 35)), None);
+    }
+
+    #[test]
+    fn names_insert_zero_ttl() {
+        let ip_addr = Ipv4Addr::new(93, 184, 216, 34);
+        let mut names = DnsNames::new();
+
+        assert_eq!(names.insert(&build_response(ip_addr, 0)), 0);
+        assert!(names.is_empty());
+    }
+
+    #[test]
+    fn deserialize_min_ttl() {
+        let mut response = build_response(Ipv4Addr::new(93, 184, 216, 34), 300);
+        response[6..8].copy_from_slice(&[0x00, 0x02]);
+        response.extend_from_slice(&[0xc0, 0x0c, 0x00, 0x01, 0x00, 0x01]);
+        response.extend_from_slice(&60u32.to_be_bytes());
+        response.extend_from_slice(&[0x00, 0x04, 93, 184, 216, 35]);
+
+        assert_eq!(Dns::deserialize(&response).unwrap().get_min_ttl(), Some(60));
+        assert_eq!(Dns::deserialize(&QUERY).unwrap().get_min_ttl(), None);
+    }
+
+    #[test]
+    fn cache_answer_repeated_query() {
+        let mut cache = DnsCache::new();
+        let response = build_response(Ipv4Addr::new(93, 184, 216, 34), 300);
+        assert!(cache.insert(&response));
+
+        // The name is case-insensitive
+        let mut query = QUERY;
+        query[..2].copy_from_slice(&[0x3c, 0x4d]);
+        query[13] = b'E';
+        let answer = cache.lookup(&query).unwrap();
+        assert_eq!(answer[..2], [0x3c, 0x4d]);
+        assert_eq!(answer[2..], response[2..]);
+
+        // Questions of another type are not answered
+        let mut query = QUERY;
+        query[26] = 0x1c;
+        assert!(cache.lookup(&query).is_none());
+    }
+
+    #[test]
+    fn cache_reject() {
+        let ip_addr = Ipv4Addr::new(93, 184, 216, 34);
+        let mut cache = DnsCache::new();
+        assert!(!cache.insert(&QUERY));
+        assert!(!cache.insert(&build_response(ip_addr, 0)));
+        // NXDOMAIN
+        let mut response = build_response(ip_addr, 300);
+        response[3] = 0x83;
+        assert!(!cache.insert(&response));
+
+        assert!(cache.is_empty());
+    }
+
+    #[test]
+    fn cache_expire() {
+        let mut cache = DnsCache::new();
+        assert!(cache.insert(&build_response(Ipv4Addr::new(93, 184, 216, 34), 1)));
+        assert!(cache.lookup(&QUERY).is_some());
+        assert_eq!(cache.purge(), 0);
+
+        let later = Instant::now() + Duration::from_secs(1);
+        assert!(cache.lookup_at(&QUERY, later).is_none());
+        assert_eq!(cache.purge_at(later), 1);
+        assert!(cache.is_empty());
+    }
+}
@@ -0,0 +1,145 @@
+use crate::packet::layer::{ParseError, SerializeError};
+use std::error;
+use std::fmt::{self, Display, Formatter};
+use std::io;
+
+/// Represents an error of the crate.
+#[derive(Debug)]
+pub enum Error {
+    /// A layer cannot be parsed.
+    Parse(ParseError),
+    /// A layer cannot be serialized.
+    Serialize(SerializeError),
+    /// The SOCKS5 proxy fails.
+    Socks(async_socks5::Error),
+    /// An I/O error occurs.
+    Io(io::Error),
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            Error::Parse(e) => write!(f, "parse: {}", e),
+            Error::Serialize(e) => write!(f, "serialize: {}", e),
+            Error::Socks(e) => write!(f, "socks: {}", e),
+            Error::Io(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            Error::Parse(e) => Some(e),
+            Error::Serialize(e) => Some(e),
+            Error::Socks(e) => Some(e),
+            Error::Io(e) => Some(e),
+        }
+    }
+}
+
+impl From<ParseError> for Error {
+    fn from(e: ParseError) -> Error {
+        Error::Parse(e)
+    }
+}
+
+impl From<SerializeError> for Error {
+    fn from(e: SerializeError) -> Error {
+        Error::Serialize(e)
+    }
+}
+
+impl From<async_socks5::Error> for Error {
+    fn from(e: async_socks5::Error) -> Error {
+        match e {
+            async_socks5::Error::Io(e) => Error::Io(e),
+            _ => Error::Socks(e),
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    /// Converts an `io::Error` into an `Error`. Errors of the crate carried by the `io::Error`
+    /// are unwrapped.
+    fn from(e: io::Error) -> Error {
+        if let Some(inner) = e.get_ref() {
+            if let Some(parse) = inner.downcast_ref::<ParseError>() {
+                return Error::Parse(*parse);
+            }
+            if let Some(serialize) = inner.downcast_ref::<SerializeError>() {
+                return Error::Serialize(*serialize);
+            }
+        }
+
+        Error::Io(e)
+    }
+}
+
+impl From<Error> for io::Error {
+    fn from(e: Error) -> io::Error {
+        match e {
+            Error::Parse(e) => io::Error::from(e),
+            Error::Serialize(e) => io::Error::from(e),
+            Error::Socks(e) => io::Error::new(io::ErrorKind::Other, e),
+            Error::Io(e) => e,
+        }
+    }
+}
+
+/// Represents the result of the crate.
+pub type Result<T> = std::result::Result<T, Error>;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::packet::layer::LayerTypes;
+    use std::error::Error as _;
+
+    #[test]
+    fn source_io() {
+        let e = Error::from(io::Error::new(io::ErrorKind::TimedOut, "timed out"));
+        let source = e.source().unwrap().downcast_ref::<io::Error>().unwrap();
+        assert_eq!(source.kind(), io::ErrorKind::TimedOut);
+    }
+
+    #[test]
+    fn source_socks_io() {
+        // I/O errors of the SOCKS5 proxy are flattened
+        let e = Error::from(async_socks5::Error::Io(io::Error::from(
+            io::ErrorKind::ConnectionRefused,
+        )));
+        assert!(matches!(e, Error::Io(_)));
+        let source = e.source().unwrap().downcast_ref::<io::Error>().unwrap();
+        assert_eq!(source.kind(), io::ErrorKind::ConnectionRefused);
+    }
+
+    #[test]
+    fn from_io_unwrap_parse() {
+        let e = io::Error::from(ParseError::Truncated(LayerTypes::Tcp));
+        let e = Error::from(e);
+        assert!(matches!(e, Error::Parse(ParseError::Truncated(t)) if t == LayerTypes::Tcp));
+        assert!(e.source().unwrap().downcast_ref::<ParseError>().is_some());
+    }
+
+    #[test]
+    fn into_io() {
+        let e = io::Error::from(Error::Io(io::Error::from(io::ErrorKind::BrokenPipe)));
+        assert_eq!(e.kind(), io::ErrorKind::BrokenPipe);
+    }
+
+    #[test]
+    fn from_io_unwrap_serialize() {
+        let e = io::Error::from(SerializeError::SelfAddressed(LayerTypes::Ethernet));
+        let e = Error::from(e);
+        assert!(matches!(
+            e,
+            Error::Serialize(SerializeError::SelfAddressed(t)) if t == LayerTypes::Ethernet
+        ));
+        assert!(e
+            .source()
+            .unwrap()
+            .downcast_ref::<SerializeError>()
+            .is_some());
+    }
+}
@@ -0,0 +1,535 @@
+use crate::packet::layer::{Layer, LayerType, LayerTypes, Layers};
+use ipnetwork::IpNetwork;
+use std::fmt::{self, Display, Formatter};
+use std::io;
+use std::net::IpAddr;
+use std::str::FromStr;
+
+/// Trait for filtering packets before they are forwarded.
+pub trait Filter: Send {
+    /// Returns if the packet of the given layers is accepted. The layers are in encapsulation
+    /// order.
+    fn accept(&self, layers: &[Layers]) -> bool;
+}
+
+/// Get the source and destination IP address of the given layers.
+fn get_ip_addrs(layers: &[Layers]) -> Option<(IpAddr, IpAddr)> {
+    layers.iter().find_map(|layer| match layer {
+        Layers::Ipv4(ref ipv4) => Some((IpAddr::V4(ipv4.get_src()), IpAddr::V4(ipv4.get_dst()))),
+        Layers::Ipv6(ref ipv6) => Some((IpAddr::V6(ipv6.get_src()), IpAddr::V6(ipv6.get_dst()))),
+        _ => None,
+    })
+}
+
+/// Get the source and destination port of the given layers.
+fn get_ports(layers: &[Layers]) -> Option<(u16, u16)> {
+    layers.iter().find_map(|layer| match layer {
+        Layers::Tcp(ref tcp) => Some((tcp.get_src(), tcp.get_dst())),
+        Layers::Udp(ref udp) => Some((udp.get_src(), udp.get_dst())),
+        _ => None,
+    })
+}
+
+/// Parses a port or an inclusive range of ports, in the form of `PORT` or `BEGIN-END`.
+fn parse_port_range(s: &str) -> io::Result<(u16, u16)> {
+    let invalid = || io::Error::new(io::ErrorKind::InvalidInput, "invalid port");
+
+    let mut parts = s.splitn(2, '-');
+    let begin = parts.next().unwrap_or_default().trim();
+    let begin = u16::from_str(begin).map_err(|_| invalid())?;
+    let end = match parts.next() {
+        Some(end) => u16::from_str(end.trim()).map_err(|_| invalid())?,
+        None => begin,
+    };
+    if begin > end {
+        return Err(invalid());
+    }
+
+    Ok((begin, end))
+}
+
+/// Represents a predicate on a field of packets, in the form of `KEY=VALUE`, e.g., `proto=udp`,
+/// `port=53`, `dport=6000-7000` or `net=224.0.0.0/4`.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum Predicate {
+    /// Matches packets containing a layer of the type.
+    Protocol(LayerType),
+    /// Matches packets whose source or destination port is in the inclusive range.
+    Port(u16, u16),
+    /// Matches packets whose source port is in the inclusive range.
+    SrcPort(u16, u16),
+    /// Matches packets whose destination port is in the inclusive range.
+    DstPort(u16, u16),
+    /// Matches packets whose source or destination IP address is in the network.
+    Network(IpNetwork),
+    /// Matches packets whose source IP address is in the network.
+    Src(IpNetwork),
+    /// Matches packets whose destination IP address is in the network.
+    Dst(IpNetwork),
+}
+
+impl Predicate {
+    /// Returns if the packet of the given layers matches the predicate.
+    pub fn matches(&self, layers: &[Layers]) -> bool {
+        match *self {
+            Predicate::Protocol(t) => layers.iter().any(|layer| layer.get_type() == t),
+            Predicate::Port(begin, end) => get_ports(layers).map_or(false, |(src, dst)| {
+                (begin..=end).contains(&src) || (begin..=end).contains(&dst)
+            }),
+            Predicate::SrcPort(begin, end) => {
+                get_ports(layers).map_or(false, |(src, _)| (begin..=end).contains(&src))
+            }
+            Predicate::DstPort(begin, end) => {
+                get_ports(layers).map_or(false, |(_, dst)| (begin..=end).contains(&dst))
+            }
+            Predicate::Network(network) => get_ip_addrs(layers).map_or(false, |(src, dst)| {
+                network.contains(src) || network.contains(dst)
+            }),
+            Predicate::Src(network) => {
+                get_ip_addrs(layers).map_or(false, |(src, _)| network.contains(src))
+            }
+            Predicate::Dst(network) => {
+                get_ip_addrs(layers).map_or(false, |(_, dst)| network.contains(dst))
+            }
+        }
+    }
+}
+
+impl Display for Predicate {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        let fmt_range = |begin: u16, end: u16| match begin == end {
+            true => begin.to_string(),
+            false => format!("{}-{}", begin, end),
+        };
+
+        match *self {
+            Predicate::Protocol(t) => write!(f, "proto={}", t.to_string().to_ascii_lowercase()),
+            Predicate::Port(begin, end) => write!(f, "port={}", fmt_range(begin, end)),
+            Predicate::SrcPort(begin, end) => write!(f, "sport={}", fmt_range(begin, end)),
+            Predicate::DstPort(begin, end) => write!(f, "dport={}", fmt_range(begin, end)),
+            Predicate::Network(network) => write!(f, "net={}", network),
+            Predicate::Src(network) => write!(f, "src={}", network),
+            Predicate::Dst(network) => write!(f, "dst={}", network),
+        }
+    }
+}
+
+impl FromStr for Predicate {
+    type Err = io::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.splitn(2, '=');
+        let key = parts.next().unwrap_or_default().trim();
+        let value = parts
+            .next()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "missing value"))?
+            .trim();
+        let parse_network = |value: &str| {
+            IpNetwork::from_str(value)
+                .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "invalid network"))
+        };
+
+        match key.to_ascii_lowercase().as_str() {
+            "proto" => {
+                let t = match value.to_ascii_lowercase().as_str() {
+                    "arp" => LayerTypes::Arp,
+                    "ipv4" => LayerTypes::Ipv4,
+                    "ipv6" => LayerTypes::Ipv6,
+                    "icmp" => LayerTypes::Icmp,
+                    "icmpv6" => LayerTypes::Icmpv6,
+                    "igmp" => LayerTypes::Igmp,
+                    "gre" => LayerTypes::Gre,
+                    "tcp" => LayerTypes::Tcp,
+                    "udp" => LayerTypes::Udp,
+                    _ => {
+                        return Err(io::Error::new(
+                            io::ErrorKind::InvalidInput,
+                            "unknown protocol",
+                        ))
+                    }
+                };
+                Ok(Predicate::Protocol(t))
+            }
+            "port" => parse_port_range(value).map(|(begin, end)| Predicate::Port(begin, end)),
+            "sport" => parse_port_range(value).map(|(begin, end)| Predicate::SrcPort(begin, end)),
+            "dport" => parse_port_range(value).map(|(begin, end)| Predicate::DstPort(begin, end)),
+            "net" => parse_network(value).map(Predicate::Network),
+            "src" => parse_network(value).map(Predicate::Src),
+            "dst" => parse_network(value).map(Predicate::Dst),
+            _ => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "unknown predicate",
+            )),
+        }
+    }
+}
+
+impl Filter for Predicate {
+    fn accept(&self, layers: &[Layers]) -> bool {
+        self.matches(layers)
+    }
+}
+
+/// Represents a rule of predicates separated by commas, e.g., `proto=udp,port=53`. A packet
+/// matches the rule if it matches all the predicates.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct FilterRule {
+    pub predicates: Vec<Predicate>,
+}
+
+impl FilterRule {
+    /// Returns if the packet of the given layers matches all the predicates of the rule.
+    pub fn matches(&self, layers: &[Layers]) -> bool {
+        self.predicates
+            .iter()
+            .all(|predicate| predicate.matches(layers))
+    }
+}
+
+impl Display for FilterRule {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        let predicates: Vec<String> = self
+            .predicates
+            .iter()
+            .map(|predicate| predicate.to_string())
+            .collect();
+
+        write!(f, "{}", predicates.join(","))
+    }
+}
+
+impl FromStr for FilterRule {
+    type Err = io::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let predicates = s
+            .split(',')
+            .map(Predicate::from_str)
+            .collect::<io::Result<Vec<_>>>()?;
+
+        Ok(FilterRule { predicates })
+    }
+}
+
+impl Filter for FilterRule {
+    fn accept(&self, layers: &[Layers]) -> bool {
+        self.matches(layers)
+    }
+}
+
+/// Represents a filter accepting packets which are accepted by all the filters.
+pub struct All(pub Vec<Box<dyn Filter>>);
+
+impl Filter for All {
+    fn accept(&self, layers: &[Layers]) -> bool {
+        self.0.iter().all(|filter| filter.accept(layers))
+    }
+}
+
+/// Represents a filter accepting packets which are accepted by any of the filters.
+pub struct Any(pub Vec<Box<dyn Filter>>);
+
+impl Filter for Any {
+    fn accept(&self, layers: &[Layers]) -> bool {
+        self.0.iter().any(|filter| filter.accept(layers))
+    }
+}
+
+/// Represents a filter accepting packets which are rejected by the filter.
+pub struct Not(pub Box<dyn Filter>);
+
+impl Filter for Not {
+    fn accept(&self, layers: &[Layers]) -> bool {
+        !self.0.accept(layers)
+    }
+}
+
+/// Represents a filter rejecting packets from or to the given IP addresses, e.g., the addresses
+/// of the proxies. The connections to a proxy reachable through the interface are captured
+/// too, and would be redirected to the proxy again in a loop. IPv4-mapped IPv6 addresses are
+/// deemed to be the same as their IPv4 addresses.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct LoopbackGuard {
+    ip_addrs: Vec<IpAddr>,
+}
+
+/// Converts the given IP address into its IPv4 address if it is IPv4-mapped.
+fn to_canonical(ip_addr: IpAddr) -> IpAddr {
+    match ip_addr {
+        IpAddr::V6(ipv6_addr) => match ipv6_addr.to_ipv4_mapped() {
+            Some(ipv4_addr) => IpAddr::V4(ipv4_addr),
+            None => ip_addr,
+        },
+        IpAddr::V4(_) => ip_addr,
+    }
+}
+
+impl LoopbackGuard {
+    /// Creates a new `LoopbackGuard` of the given IP addresses.
+    pub fn new(ip_addrs: &[IpAddr]) -> LoopbackGuard {
+        let mut guard = LoopbackGuard::default();
+        for ip_addr in ip_addrs {
+            guard.insert(*ip_addr);
+        }
+
+        guard
+    }
+
+    /// Inserts an IP address into the guard.
+    pub fn insert(&mut self, ip_addr: IpAddr) {
+        let ip_addr = to_canonical(ip_addr);
+        if !self.ip_addrs.contains(&ip_addr) {
+            self.ip_addrs.push(ip_addr);
+        }
+    }
+
+    /// Returns if the given IP address is guarded.
+    pub fn contains(&self, ip_addr: IpAddr) -> bool {
+        self.ip_addrs.contains(&to_canonical(ip_addr))
+    }
+
+    /// Get the IP addresses guarded.
+    pub fn get_ip_addrs(&self) -> &[IpAddr] {
+        &self.ip_addrs
+    }
+}
+
+impl Filter for LoopbackGuard {
+    fn accept(&self, layers: &[Layers]) -> bool {
+        get_ip_addrs(layers).map_or(true, |(src, dst)| {
+            !self.contains(src) && !self.contains(dst)
+        })
+    }
+}
+
+/// Returns if the given IP address cannot be the source of packets on the wire, which is
+/// unspecified, loopback, broadcast, multicast or reserved. IPv4-mapped IPv6 addresses are
+/// checked as their IPv4 addresses.
+pub fn is_martian(ip_addr: IpAddr) -> bool {
+    match to_canonical(ip_addr) {
+        IpAddr::V4(ipv4_addr) => {
+            let first = ipv4_addr.octets()[0];
+            // 0.0.0.0/8 and 240.0.0.0/4, which covers the broadcast address
+            first == 0 || first >= 240 || ipv4_addr.is_loopback() || ipv4_addr.is_multicast()
+        }
+        IpAddr::V6(ipv6_addr) => {
+            ipv6_addr.is_unspecified() || ipv6_addr.is_loopback() || ipv6_addr.is_multicast()
+        }
+    }
+}
+
+/// Represents a filter rejecting packets from martian source addresses, which cannot
+/// legitimately appear on the wire.
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq)]
+pub struct MartianGuard;
+
+impl Filter for MartianGuard {
+    fn accept(&self, layers: &[Layers]) -> bool {
+        get_ip_addrs(layers).map_or(true, |(src, _)| !is_martian(src))
+    }
+}
+
+/// Creates a filter rejecting packets which match any of the given rules.
+pub fn exclude(rules: Vec<FilterRule>) -> Not {
+    let filters = rules
+        .into_iter()
+        .map(|rule| Box::new(rule) as Box<dyn Filter>)
+        .collect();
+
+    Not(Box::new(Any(filters)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::packet::layer::ipv4::Ipv4;
+    use crate::packet::layer::tcp::Tcp;
+    use crate::packet::layer::udp::Udp;
+    use std::net::Ipv4Addr;
+
+    /// Builds the layers of an IPv4 packet of the given transport layer.
+    fn ipv4_layers(src: Ipv4Addr, dst: Ipv4Addr, transport: Layers) -> Vec<Layers> {
+        let ipv4 = Ipv4::new(0, transport.get_type(), src, dst).unwrap();
+
+        vec![Layers::Ipv4(ipv4), transport]
+    }
+
+    fn dns() -> Vec<Layers> {
+        ipv4_layers(
+            Ipv4Addr::new(10, 6, 0, 1),
+            Ipv4Addr::new(8, 8, 8, 8),
+            Layers::Udp(Udp::new(1024, 53)),
+        )
+    }
+
+    fn http() -> Vec<Layers> {
+        ipv4_layers(
+            Ipv4Addr::new(10, 6, 0, 1),
+            Ipv4Addr::new(93, 184, 216, 34),
+            Layers::Tcp(Tcp::new_syn(1025, 80, 0, 65535)),
+        )
+    }
+
+    #[test]
+    fn predicate_from_str() {
+        assert_eq!(
+            Predicate::from_str("proto=UDP").unwrap(),
+            Predicate::Protocol(LayerTypes::Udp)
+        );
+        assert_eq!(
+            Predicate::from_str("port=53").unwrap(),
+            Predicate::Port(53, 53)
+        );
+        assert_eq!(
+            Predicate::from_str(" dport = 6000-7000 ").unwrap(),
+            Predicate::DstPort(6000, 7000)
+        );
+        assert_eq!(
+            Predicate::from_str("net=224.0.0.0/4").unwrap(),
+            Predicate::Network("224.0.0.0/4".parse().unwrap())
+        );
+
+        for s in [
+            "port",
+            "port=7000-6000",
+            "port=65536",
+            "proto=sctp",
+            "host=1.1.1.1",
+        ] {
+            let e = Predicate::from_str(s).unwrap_err();
+            assert_eq!(e.kind(), io::ErrorKind::InvalidInput, "{}", s);
+        }
+    }
+
+    #[test]
+    fn rule_display_round_trip() {
+        let s = "proto=udp,sport=1024-2047,dst=8.8.8.8/32";
+        let rule = FilterRule::from_str(s).unwrap();
+        assert_eq!(rule.predicates.len(), 3);
+        assert_eq!(rule.to_string(), s);
+        assert_eq!(FilterRule::from_str(&rule.to_string()).unwrap(), rule);
+    }
+
+    #[test]
+    fn predicate_matches() {
+        assert!(Predicate::SrcPort(1024, 1024).matches(&dns()));
+        assert!(!Predicate::DstPort(1024, 1024).matches(&dns()));
+        assert!(Predicate::Dst("8.8.0.0/16".parse().unwrap()).matches(&dns()));
+        assert!(!Predicate::Src("8.8.0.0/16".parse().unwrap()).matches(&dns()));
+        assert!(Predicate::Network("10.6.0.0/24".parse().unwrap()).matches(&http()));
+        assert!(Predicate::Protocol(LayerTypes::Tcp).matches(&http()));
+        assert!(!Predicate::Protocol(LayerTypes::Udp).matches(&http()));
+
+        // Packets without the fields never match
+        let layers = vec![Layers::Udp(Udp::new(1024, 53))];
+        assert!(!Predicate::Network("0.0.0.0/0".parse().unwrap()).matches(&layers));
+    }
+
+    #[test]
+    fn exclude_dns() {
+        let filter = exclude(vec![FilterRule::from_str("port=53").unwrap()]);
+        assert!(!filter.accept(&dns()));
+        assert!(filter.accept(&http()));
+    }
+
+    #[test]
+    fn compose_filters() {
+        let udp = || Box::new(Predicate::Protocol(LayerTypes::Udp)) as Box<dyn Filter>;
+        let port = || Box::new(Predicate::Port(80, 80)) as Box<dyn Filter>;
+
+        let all = All(vec![udp(), port()]);
+        assert!(!all.accept(&dns()));
+        assert!(!all.accept(&http()));
+
+        let any = Any(vec![udp(), port()]);
+        assert!(any.accept(&dns()));
+        assert!(any.accept(&http()));
+
+        // Empty compositions accept all and none respectively
+        assert!(All(vec![]).accept(&dns()));
+        assert!(!Any(vec![]).accept(&dns()));
+
+        let not = Not(Box::new(All(vec![udp()])));
+        assert!(!not.accept(&dns()));
+        assert!(not.accept(&http()));
+    }
+
+    #[test]
+    fn loopback_guard_ipv4() {
+        let proxy = Ipv4Addr::new(192, 0, 2, 10);
+        let guard = LoopbackGuard::new(&[IpAddr::V4(proxy), IpAddr::V4(proxy)]);
+        assert_eq!(guard.get_ip_addrs(), [IpAddr::V4(proxy)]);
+
+        // Either direction of the traffic of the proxy is rejected
+        let tcp = || Layers::Tcp(Tcp::new_syn(1025, 1080, 0, 65535));
+        assert!(!guard.accept(&ipv4_layers(Ipv4Addr::new(10, 6, 0, 1), proxy, tcp())));
+        assert!(!guard.accept(&ipv4_layers(proxy, Ipv4Addr::new(10, 6, 0, 1), tcp())));
+        assert!(guard.accept(&http()));
+        // Packets without IP layers are accepted
+        assert!(guard.accept(&[Layers::Tcp(Tcp::new_syn(1025, 1080, 0, 65535))]));
+    }
+
+    #[test]
+    fn loopback_guard_ipv6() {
+        use crate::packet::layer::ipv6::Ipv6;
+
+        let mut guard = LoopbackGuard::default();
+        guard.insert("2001:db8::10".parse().unwrap());
+        // IPv4-mapped addresses are the same as their IPv4 addresses
+        guard.insert("::ffff:192.0.2.10".parse().unwrap());
+        assert!(guard.contains(IpAddr::V4(Ipv4Addr::new(192, 0, 2, 10))));
+        assert!(guard.contains("::ffff:192.0.2.10".parse().unwrap()));
+        assert!(!guard.contains("2001:db8::11".parse().unwrap()));
+
+        let ipv6 = Ipv6::new(
+            LayerTypes::Tcp,
+            "2001:db8::1".parse().unwrap(),
+            "2001:db8::10".parse().unwrap(),
+        )
+        .unwrap();
+        let layers = [
+            Layers::Ipv6(ipv6),
+            Layers::Tcp(Tcp::new_syn(1025, 1080, 0, 65535)),
+        ];
+        assert!(!guard.accept(&layers));
+    }
+
+    #[test]
+    fn is_martian_addrs() {
+        let martians: [IpAddr; 8] = [
+            "0.0.0.0".parse().unwrap(),
+            "0.1.2.3".parse().unwrap(),
+            "127.0.0.1".parse().unwrap(),
+            "255.255.255.255".parse().unwrap(),
+            "224.0.0.1".parse().unwrap(),
+            "::".parse().unwrap(),
+            "::1".parse().unwrap(),
+            "::ffff:127.0.0.1".parse().unwrap(),
+        ];
+        for ip_addr in martians.iter() {
+            assert!(is_martian(*ip_addr), "{}", ip_addr);
+        }
+
+        let legitimates: [IpAddr; 5] = [
+            "10.6.0.1".parse().unwrap(),
+            "192.168.1.2".parse().unwrap(),
+            "93.184.216.34".parse().unwrap(),
+            "2001:db8::1".parse().unwrap(),
+            "fe80::1".parse().unwrap(),
+        ];
+        for ip_addr in legitimates.iter() {
+            assert!(!is_martian(*ip_addr), "{}", ip_addr);
+        }
+    }
+
+    #[test]
+    fn martian_guard_source() {
+        let udp = || Layers::Udp(Udp::new(1024, 53));
+        let loopback = Ipv4Addr::new(127, 0, 0, 1);
+        assert!(!MartianGuard.accept(&ipv4_layers(loopback, Ipv4Addr::new(8, 8, 8, 8), udp())));
+        // Only the source is checked
+        assert!(MartianGuard.accept(&ipv4_layers(Ipv4Addr::new(10, 6, 0, 1), loopback, udp())));
+        assert!(MartianGuard.accept(&dns()));
+        assert!(MartianGuard.accept(&[udp()]));
+    }
+}
@@ -0,0 +1,214 @@
+use std::net::{Ipv4Addr, SocketAddrV4};
+use std::ops::Range;
+use std::str;
+
+/// Represents the port of the FTP control connection.
+pub const FTP_PORT: u16 = 21;
+/// Represents the port the server connects from for the data connection in active mode.
+pub const FTP_DATA_PORT: u16 = 20;
+
+/// Represents the command advertising the address of the data connection (RFC 959).
+const PORT_COMMAND: &str = "PORT";
+/// Represents the extended command advertising the address of the data connection (RFC 2428).
+const EPRT_COMMAND: &str = "EPRT";
+/// Represents the network protocol of IPv4 in an extended command.
+const EPRT_PROTOCOL_IPV4: &str = "1";
+
+/// Represents a command advertising the address of the data connection of active-mode FTP in the
+/// payload of a control connection, e.g., `PORT 192,168,1,2,4,1` or `EPRT |1|192.168.1.2|1025|`.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct DataPort {
+    range: Range<usize>,
+    addr: SocketAddrV4,
+    is_extended: bool,
+}
+
+impl DataPort {
+    /// Finds the first `PORT` or `EPRT` command of an IPv4 address in the given payload of an FTP
+    /// control connection. Only commands in complete lines are found.
+    pub fn find(payload: &[u8]) -> Option<DataPort> {
+        let mut begin = 0;
+        while let Some(n) = payload[begin..].iter().position(|b| *b == b'\n') {
+            let end = begin + n;
+            let line = &payload[begin..end];
+            let line = line.strip_suffix(b"\r").unwrap_or(line);
+            if let Some((addr, is_extended)) = parse_command(line) {
+                // Commands are of 4 characters followed by a space
+                return Some(DataPort {
+                    range: begin + PORT_COMMAND.len() + 1..begin + line.len(),
+                    addr,
+                    is_extended,
+                });
+            }
+            begin = end + 1;
+        }
+
+        None
+    }
+
+    /// Get the range of the argument of the command in the payload.
+    pub fn get_range(&self) -> Range<usize> {
+        self.range.clone()
+    }
+
+    /// Get the address of the data connection advertised.
+    pub fn get_addr(&self) -> SocketAddrV4 {
+        self.addr
+    }
+
+    /// Returns if the command is an extended `EPRT` command.
+    pub fn is_extended(&self) -> bool {
+        self.is_extended
+    }
+
+    /// Rewrites the argument of the command in the given payload to advertise the given address,
+    /// and returns the payload rewritten.
+    pub fn rewrite(&self, payload: &[u8], addr: SocketAddrV4) -> Vec<u8> {
+        let argument = match self.is_extended {
+            true => format!("|{}|{}|{}|", EPRT_PROTOCOL_IPV4, addr.ip(), addr.port()),
+            false => {
+                let octets = addr.ip().octets();
+                format!(
+                    "{},{},{},{},{},{}",
+                    octets[0],
+                    octets[1],
+                    octets[2],
+                    octets[3],
+                    addr.port() >> 8,
+                    addr.port() & 0xff
+                )
+            }
+        };
+
+        let mut rewritten = Vec::with_capacity(payload.len() + argument.len());
+        rewritten.extend_from_slice(&payload[..self.range.start]);
+        rewritten.extend_from_slice(argument.as_bytes());
+        rewritten.extend_from_slice(&payload[self.range.end..]);
+
+        rewritten
+    }
+}
+
+/// Parses a command line without the line ending, and returns the address advertised and if the
+/// command is extended.
+fn parse_command(line: &[u8]) -> Option<(SocketAddrV4, bool)> {
+    let line = str::from_utf8(line).ok()?;
+    if line.len() <= PORT_COMMAND.len() + 1 || !line.is_char_boundary(PORT_COMMAND.len()) {
+        return None;
+    }
+    let (command, argument) = line.split_at(PORT_COMMAND.len());
+    let argument = argument.strip_prefix(' ')?;
+
+    if command.eq_ignore_ascii_case(PORT_COMMAND) {
+        Some((parse_port(argument)?, false))
+    } else if command.eq_ignore_ascii_case(EPRT_COMMAND) {
+        Some((parse_eprt(argument)?, true))
+    } else {
+        None
+    }
+}
+
+/// Parses the argument `h1,h2,h3,h4,p1,p2` of a `PORT` command.
+fn parse_port(argument: &str) -> Option<SocketAddrV4> {
+    let mut values = [0u8; 6];
+    let mut parts = argument.trim().split(',');
+    for value in values.iter_mut() {
+        *value = parts.next()?.trim().parse().ok()?;
+    }
+    if parts.next().is_some() {
+        return None;
+    }
+
+    Some(SocketAddrV4::new(
+        Ipv4Addr::new(values[0], values[1], values[2], values[3]),
+        (values[4] as u16) << 8 | values[5] as u16,
+    ))
+}
+
+/// Parses the argument `<d><protocol><d><address><d><port><d>` of an `EPRT` command of IPv4.
+fn parse_eprt(argument: &str) -> Option<SocketAddrV4> {
+    let argument = argument.trim();
+    let delimiter = argument.chars().next()?;
+    let mut parts = argument.split(delimiter);
+    if !parts.next()?.is_empty() || parts.next()? != EPRT_PROTOCOL_IPV4 {
+        return None;
+    }
+    let ip_addr = parts.next()?.parse().ok()?;
+    let port = parts.next()?.parse().ok()?;
+    if !parts.next()?.is_empty() || parts.next().is_some() {
+        return None;
+    }
+
+    Some(SocketAddrV4::new(ip_addr, port))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn find_port() {
+        let payload = b"USER anonymous\r\nPORT 10,6,0,1,4,1\r\n";
+        let port = DataPort::find(payload).unwrap();
+
+        assert_eq!(
+            port.get_addr(),
+            SocketAddrV4::new(Ipv4Addr::new(10, 6, 0, 1), 1025)
+        );
+        assert!(!port.is_extended());
+        assert_eq!(&payload[port.get_range()], b"10,6,0,1,4,1");
+    }
+
+    #[test]
+    fn find_eprt() {
+        let payload = b"eprt |1|10.6.0.1|1025|\n";
+        let port = DataPort::find(payload).unwrap();
+
+        assert_eq!(
+            port.get_addr(),
+            SocketAddrV4::new(Ipv4Addr::new(10, 6, 0, 1), 1025)
+        );
+        assert!(port.is_extended());
+        assert_eq!(&payload[port.get_range()], b"|1|10.6.0.1|1025|");
+    }
+
+    #[test]
+    fn find_none() {
+        // Incomplete lines
+        assert!(DataPort::find(b"PORT 10,6,0,1,4,1").is_none());
+        // Other commands
+        assert!(DataPort::find(b"PASV\r\nPORTS 10,6,0,1,4,1\r\n").is_none());
+        // IPv6
+        assert!(DataPort::find(b"EPRT |2|2001:db8::1|1025|\r\n").is_none());
+        // Invalid arguments
+        assert!(DataPort::find(b"PORT 10,6,0,1,4\r\n").is_none());
+        assert!(DataPort::find(b"PORT 10,6,0,1,4,1,0\r\n").is_none());
+        assert!(DataPort::find(b"PORT 10,6,0,256,4,1\r\n").is_none());
+        assert!(DataPort::find(b"EPRT |1|10.6.0.1|1025\r\n").is_none());
+        assert!(DataPort::find(b"PORT \xff\r\n").is_none());
+    }
+
+    #[test]
+    fn rewrite_port() {
+        let payload = b"PORT 10,6,0,1,4,1\r\nLIST\r\n";
+        let port = DataPort::find(payload).unwrap();
+        let addr = SocketAddrV4::new(Ipv4Addr::new(192, 0, 2, 1), 2000);
+
+        assert_eq!(
+            port.rewrite(payload, addr),
+            b"PORT 192,0,2,1,7,208\r\nLIST\r\n"
+        );
+    }
+
+    #[test]
+    fn rewrite_eprt() {
+        let payload = b"EPRT |1|10.6.0.1|1025|\r\n";
+        let port = DataPort::find(payload).unwrap();
+        let addr = SocketAddrV4::new(Ipv4Addr::new(192, 0, 2, 1), 2000);
+
+        // Rewritten commands are found again
+        let rewritten = port.rewrite(payload, addr);
+        assert_eq!(rewritten, b"EPRT |1|192.0.2.1|2000|\r\n");
+        assert_eq!(DataPort::find(&rewritten).unwrap().get_addr(), addr);
+    }
+}
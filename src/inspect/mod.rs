@@ -0,0 +1,258 @@
+use crate::packet::flow::FlowKey;
+use log::info;
+use std::borrow::Cow;
+use std::fmt::{self, Display, Formatter};
+use std::sync::Arc;
+
+/// Represents the direction of a payload forwarded.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum Direction {
+    /// Represents a payload sent from the source to the proxy.
+    Up,
+    /// Represents a payload sent from the proxy to the source.
+    Down,
+}
+
+impl Display for Direction {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            Direction::Up => write!(f, "up"),
+            Direction::Down => write!(f, "down"),
+        }
+    }
+}
+
+/// Trait for inspecting payloads before they are forwarded, e.g., for logging the metadata of
+/// payloads or redacting credentials.
+pub trait InspectionHook: Send + Sync {
+    /// Inspects the payload of the given flow in the given direction, and returns the
+    /// replacement of the payload, or `None` for forwarding the payload unchanged. The flow is
+    /// always from the source to the destination in both directions. The packets carrying a
+    /// replacement are serialized with the length of the replacement, and the TCP sequences
+    /// acknowledged to the source still cover the original payload.
+    fn on_payload(&self, flow: &FlowKey, dir: Direction, data: &[u8]) -> Option<Vec<u8>>;
+}
+
+/// Inspects the given payload with the given hook, and returns the payload to be forwarded.
+pub fn inspect<'a>(
+    hook: Option<&Arc<dyn InspectionHook>>,
+    flow: &FlowKey,
+    dir: Direction,
+    data: &'a [u8],
+) -> Cow<'a, [u8]> {
+    match hook.and_then(|hook| hook.on_payload(flow, dir, data)) {
+        Some(replacement) => Cow::Owned(replacement),
+        None => Cow::Borrowed(data),
+    }
+}
+
+/// Represents a hook logging the metadata of payloads, but not the payloads themselves.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct MetadataLogger;
+
+impl InspectionHook for MetadataLogger {
+    fn on_payload(&self, flow: &FlowKey, dir: Direction, data: &[u8]) -> Option<Vec<u8>> {
+        info!("payload {} ({}, {} Bytes)", flow, dir, data.len());
+
+        None
+    }
+}
+
+/// Represents a hook replacing each occurrence of patterns in payloads with the replacement.
+/// Patterns split across TCP segments are not matched.
+#[derive(Clone, Debug)]
+pub struct Redactor {
+    patterns: Vec<Vec<u8>>,
+    replacement: Option<Vec<u8>>,
+}
+
+impl Redactor {
+    /// Creates a new `Redactor` replacing the given patterns with the given replacement. Empty
+    /// patterns are ignored.
+    pub fn new(patterns: Vec<Vec<u8>>, replacement: &[u8]) -> Redactor {
+        Redactor {
+            patterns: patterns.into_iter().filter(|p| !p.is_empty()).collect(),
+            replacement: Some(replacement.to_vec()),
+        }
+    }
+
+    /// Creates a new `Redactor` masking the given patterns with asterisks of the same length,
+    /// which keeps length-prefixed framings of the application, e.g., `Content-Length`, valid.
+    pub fn new_masking(patterns: Vec<Vec<u8>>) -> Redactor {
+        Redactor {
+            patterns: patterns.into_iter().filter(|p| !p.is_empty()).collect(),
+            replacement: None,
+        }
+    }
+
+    /// Returns the given data with the patterns replaced. Returns `None` if no pattern matches.
+    fn redact(&self, data: &[u8]) -> Option<Vec<u8>> {
+        let mut redacted: Option<Vec<u8>> = None;
+        for pattern in &self.patterns {
+            let source = redacted.as_deref().unwrap_or(data);
+            if let Some(replaced) = self.replace(source, pattern) {
+                redacted = Some(replaced);
+            }
+        }
+
+        redacted
+    }
+
+    fn replace(&self, data: &[u8], pattern: &[u8]) -> Option<Vec<u8>> {
+        let mask;
+        let replacement = match self.replacement {
+            Some(ref replacement) => replacement.as_slice(),
+            None => {
+                mask = vec![b'*'; pattern.len()];
+                mask.as_slice()
+            }
+        };
+
+        let mut replaced = Vec::with_capacity(data.len());
+        let mut is_replaced = false;
+        let mut i = 0;
+        while i < data.len() {
+            if data[i..].starts_with(pattern) {
+                replaced.extend_from_slice(replacement);
+                i += pattern.len();
+                is_replaced = true;
+            } else {
+                replaced.push(data[i]);
+                i += 1;
+            }
+        }
+
+        match is_replaced {
+            true => Some(replaced),
+            false => None,
+        }
+    }
+}
+
+impl InspectionHook for Redactor {
+    fn on_payload(&self, _: &FlowKey, _: Direction, data: &[u8]) -> Option<Vec<u8>> {
+        self.redact(data)
+    }
+}
+
+/// Represents a hook applying the hooks in order, each inspects the payload returned by the
+/// previous one.
+pub struct Chain(pub Vec<Box<dyn InspectionHook>>);
+
+impl InspectionHook for Chain {
+    fn on_payload(&self, flow: &FlowKey, dir: Direction, data: &[u8]) -> Option<Vec<u8>> {
+        let mut payload: Option<Vec<u8>> = None;
+        for hook in &self.0 {
+            let source = payload.as_deref().unwrap_or(data);
+            if let Some(replacement) = hook.on_payload(flow, dir, source) {
+                payload = Some(replacement);
+            }
+        }
+
+        payload
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pnet::packet::ip::IpNextHeaderProtocols;
+    use std::sync::Mutex;
+
+    fn flow() -> FlowKey {
+        FlowKey::new(
+            IpNextHeaderProtocols::Tcp,
+            "10.6.0.1:1024".parse().unwrap(),
+            "93.184.216.34:80".parse().unwrap(),
+        )
+    }
+
+    /// Represents a hook keeping the payloads inspected.
+    #[derive(Default)]
+    struct Recorder(Mutex<Vec<(Direction, Vec<u8>)>>);
+
+    impl InspectionHook for Recorder {
+        fn on_payload(&self, _: &FlowKey, dir: Direction, data: &[u8]) -> Option<Vec<u8>> {
+            self.0.lock().unwrap().push((dir, data.to_vec()));
+
+            None
+        }
+    }
+
+    #[test]
+    fn redact_replacement() {
+        let redactor = Redactor::new(vec![b"secret".to_vec(), Vec::new()], b"x");
+
+        assert_eq!(
+            redactor.on_payload(&flow(), Direction::Up, b"a secret, another secret"),
+            Some(b"a x, another x".to_vec())
+        );
+        assert_eq!(
+            redactor.on_payload(&flow(), Direction::Down, b"nothing"),
+            None
+        );
+    }
+
+    #[test]
+    fn redact_masking() {
+        let redactor = Redactor::new_masking(vec![b"hunter2".to_vec(), b"token".to_vec()]);
+
+        // The length is kept
+        assert_eq!(
+            redactor.on_payload(&flow(), Direction::Up, b"token=hunter2"),
+            Some(b"*****=*******".to_vec())
+        );
+    }
+
+    #[test]
+    fn inspect_chain() {
+        let recorder = Arc::new(Recorder::default());
+        let hook: Arc<dyn InspectionHook> = Arc::new(Chain(vec![
+            Box::new(Redactor::new(vec![b"secret".to_vec()], b"x")),
+            Box::new(MetadataLogger),
+        ]));
+
+        // Each hook inspects the payload returned by the previous one
+        let chain = Chain(vec![
+            Box::new(Redactor::new(vec![b"secret".to_vec()], b"x")),
+            Box::new(ArcHook(Arc::clone(&recorder))),
+        ]);
+        assert_eq!(
+            chain.on_payload(&flow(), Direction::Up, b"my secret"),
+            Some(b"my x".to_vec())
+        );
+        assert_eq!(
+            *recorder.0.lock().unwrap(),
+            [(Direction::Up, b"my x".to_vec())]
+        );
+
+        assert_eq!(
+            &*inspect(Some(&hook), &flow(), Direction::Down, b"my secret"),
+            b"my x"
+        );
+        // Payloads are borrowed if unchanged
+        assert!(matches!(
+            inspect(Some(&hook), &flow(), Direction::Down, b"hello"),
+            Cow::Borrowed(b"hello")
+        ));
+        assert!(matches!(
+            inspect(None, &flow(), Direction::Down, b"my secret"),
+            Cow::Borrowed(_)
+        ));
+    }
+
+    /// Represents a hook delegating to a shared hook.
+    struct ArcHook<T>(Arc<T>);
+
+    impl<T: InspectionHook> InspectionHook for ArcHook<T> {
+        fn on_payload(&self, flow: &FlowKey, dir: Direction, data: &[u8]) -> Option<Vec<u8>> {
+            self.0.on_payload(flow, dir, data)
+        }
+    }
+
+    #[test]
+    fn direction_display() {
+        assert_eq!(Direction::Up.to_string(), "up");
+        assert_eq!(Direction::Down.to_string(), "down");
+    }
+}
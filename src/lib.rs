@@ -1,33 +1,82 @@
 use env_logger::fmt::{Color, Target};
-use log::{debug, trace, warn, Level, LevelFilter};
+use log::{debug, info, trace, warn, Level, LevelFilter};
 use lru::LruCache;
+use pnet::packet::ethernet::EtherTypes;
+use pnet::packet::ip::{IpNextHeaderProtocol, IpNextHeaderProtocols};
+use pnet::packet::ipv6::Ipv6Packet;
 use std::cmp::{max, min};
 use std::collections::HashMap;
+use std::fs::File;
 use std::io::Write;
-use std::net::{Ipv4Addr, SocketAddrV4};
-use std::sync::{Arc, Mutex};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV4};
+use std::slice;
+use std::sync::{mpsc, Arc, Mutex};
 use std::thread;
 use std::time::{Duration, Instant};
 use tokio::io;
+use tokio::time;
 
 pub mod args;
 pub mod cacher;
+pub mod dns;
+pub mod error;
+pub mod filter;
+pub mod ftp;
+pub mod inspect;
+pub mod limiter;
 pub mod packet;
 pub mod pcap;
+pub mod pool;
+pub mod route;
+pub mod shutdown;
 pub mod socks;
+pub mod stats;
 
-use self::socks::{DatagramWorker, Forward, StreamWorker};
+pub use error::Error;
+
+use self::socks::{
+    Address, ConnectOptions, DatagramPool, DatagramWorker, Forward, SelectMode, SocksAuth,
+    SocksListener, SocksPool, SocksVersion, StreamWorker, DEFAULT_BIND_TIMEOUT,
+    DEFAULT_DATAGRAM_POOL_CAPACITY, DEFAULT_DATAGRAM_POOL_IDLE_TIMEOUT,
+    DEFAULT_SOCKS_POOL_COOLDOWN,
+};
 use args::Flags;
-use cacher::{Cacher, RandomCacher};
-use packet::layer::arp::Arp;
-use packet::layer::ethernet::Ethernet;
-use packet::layer::ipv4::Ipv4;
-use packet::layer::tcp::Tcp;
-use packet::layer::udp::Udp;
-use packet::layer::{Layer, LayerType, LayerTypes, Layers};
-use packet::{Defraggler, Indicator};
+use cacher::Cacher;
+use dns::{Dns, DnsCache, DnsNames, DNS_PORT};
+use filter::{Filter, LoopbackGuard, MartianGuard};
+use ftp::{DataPort, FTP_DATA_PORT, FTP_PORT};
+use inspect::{Direction, InspectionHook};
+use limiter::TokenBucket;
+use packet::layer::arp::{self as arp, Arp, ArpCache, DEFAULT_ARP_CACHE_TTL};
+use packet::layer::ethernet::{Ethernet, VlanTag};
+use packet::layer::icmp::{
+    self as icmp, Icmp, PmtuCache, DEFAULT_PMTU_CACHE_TTL, ORIGINAL_DATAGRAM_DATA_SIZE,
+};
+use packet::layer::icmpv6::{self as icmpv6, Icmpv6, MAX_ORIGINAL_DATAGRAM_SIZE, NDP_HOP_LIMIT};
+use packet::layer::ipv4::{Ipv4, TtlMode};
+use packet::layer::ipv6::{self as ipv6, find_fragment_header, Ipv6};
+use packet::layer::sll::Sll;
+use packet::layer::tcp::state::{
+    self, Action, Connection, ConnectionLimitPolicy, ConnectionTable, FastOpenCookies,
+    FlowCounters, FlowStat, ReceiveWindow, Reorder, ReorderBuffer, SackBlocks, State,
+};
+use packet::layer::tcp::{self as tcp, Tcp, UrgentPointer, MAX_WINDOW_SCALE};
+use packet::layer::udp::{DuplicateGuard, Udp};
+use packet::layer::{Layer, LayerTypes, Layers, ParseError};
+use packet::nat64::{self as nat64, Nat64, NAT64_HEADER_OVERHEAD, NAT64_WELL_KNOWN_PREFIX};
+use packet::{Defraggler, FlowKey, Indicator, Ipv6Defraggler, LinkType};
+use pcap::file::PcapWriter;
+use pcap::inject::Injector;
+#[cfg(feature = "async")]
+use pcap::stream::CaptureStream;
 use pcap::Interface;
-use pcap::{HardwareAddr, Receiver, Sender};
+use pcap::{HardwareAddr, Receiver};
+use pool::{BufferPool, ExhaustedPolicy, PooledBuffer};
+use route::{Route, RouteTable};
+use shutdown::Shutdown;
+#[cfg(feature = "metrics")]
+use stats::metrics::Metrics;
+use stats::{DropReason, Stats};
 
 /// Sets the logger.
 pub fn set_logger(flags: &Flags) {
@@ -36,9 +85,13 @@ pub fn set_logger(flags: &Flags) {
         1 => LevelFilter::Debug,
         _ => LevelFilter::Trace,
     };
-    env_logger::builder()
-        .target(Target::Stdout)
-        .filter_level(level)
+    let mut builder = env_logger::builder();
+    builder.target(Target::Stdout).filter_level(level);
+    // Tune the verbosity per subsystem, e.g., `pcap2socks::socks=trace,pcap2socks::pcap=warn`
+    if let Some(ref filters) = flags.log_filters {
+        builder.parse_filters(filters);
+    }
+    builder
         .format(|buf, record| {
             let mut style = buf.style();
 
@@ -79,6 +132,54 @@ fn auto_interfaces() -> Vec<Interface> {
     inters
 }
 
+/// Represents the size of buffers for frames captured by `capture` and `CaptureStream`.
+pub(crate) const CAPTURE_BUFFER_SIZE: usize = 65536;
+/// Represents the maximum number of frames captured by `capture` waiting in the channel.
+const CAPTURE_POOL_CAPACITY: usize = 64;
+
+/// Captures frames from the given `Receiver` in a new thread, and sends them with the given
+/// index to the channel. Frames are copied into pooled buffers, and are dropped or wait for a
+/// returned buffer by the given policy if the channel is backlogged. The thread exits when the
+/// channel is closed or the capture fails.
+pub fn capture(
+    i: usize,
+    mut rx: Receiver,
+    tx: mpsc::Sender<(usize, PooledBuffer)>,
+    policy: ExhaustedPolicy,
+) -> thread::JoinHandle<()> {
+    let pool = BufferPool::new(CAPTURE_BUFFER_SIZE, CAPTURE_POOL_CAPACITY, policy);
+    thread::spawn(move || loop {
+        match rx.next() {
+            Ok(frame) => {
+                if frame.len() > pool.get_size() {
+                    warn!("capture {}: frame of {} Bytes is too large", i, frame.len());
+                    continue;
+                }
+                let mut buffer = match pool.get() {
+                    Some(buffer) => buffer,
+                    None => {
+                        trace!("capture {}: drop frame because the channel is full", i);
+                        continue;
+                    }
+                };
+                buffer.truncate(frame.len());
+                buffer.copy_from_slice(frame);
+                if tx.send((i, buffer)).is_err() {
+                    return;
+                }
+            }
+            Err(e) => {
+                if e.kind() == io::ErrorKind::TimedOut {
+                    thread::sleep(Duration::from_millis(TIMEDOUT_WAIT));
+                    continue;
+                }
+                warn!("capture {}: {}", i, e);
+                return;
+            }
+        }
+    })
+}
+
 /// Gets an available network interface match the name.
 pub fn interface(name: Option<String>) -> Option<Interface> {
     let inters = match name {
@@ -97,6 +198,9 @@ pub fn interface(name: Option<String>) -> Option<Interface> {
     }
 }
 
+/// Represents the log target of TCP connections, which is not a module.
+pub const TCP_LOG_TARGET: &str = "pcap2socks::tcp";
+
 /// Represents the wait time after a `TimedOut` `IoError`.
 const TIMEDOUT_WAIT: u64 = 20;
 
@@ -108,32 +212,77 @@ const MAX_U32_WINDOW_SIZE: usize = 256 * 1024;
 /// Exclude the 4 bytes used in FCS, the minimum packet size in pcap2socks is 60 Bytes.
 const MINIMUM_PACKET_SIZE: usize = 60;
 
+/// Represents the size of the IPv4 and TCP header without options.
+const TCP_IPV4_HEADER_SIZE: u16 = 40;
+/// Represents the size of the TCP timestamps option with its padding.
+const TCP_TIMESTAMPS_OPTION_SIZE: usize = 12;
+/// Represents the size of the Ethernet header.
+const ETHERNET_HEADER_SIZE: usize = 14;
+/// Represents the max number of buffers in the buffer pool for sending.
+const BUFFER_POOL_CAPACITY: usize = 64;
+
+/// Represents the maximum MTU supported, which covers jumbo frames of most NICs.
+pub const MAXIMUM_MTU: u16 = 9216;
+
 /// Represents the channel forward traffic to the source in pcap.
 pub struct Forwarder {
-    tx: Sender,
+    tx: Box<dyn Injector>,
     mtu: u16,
     src_hardware_addr: HardwareAddr,
     local_hardware_addr: HardwareAddr,
     src_ip_addr: Ipv4Addr,
     local_ip_addr: Ipv4Addr,
     ipv4_identification_map: HashMap<Ipv4Addr, u16>,
-    tcp_send_window_map: HashMap<(u16, SocketAddrV4), u16>,
-    tcp_sequence_map: HashMap<(u16, SocketAddrV4), u32>,
-    tcp_acknowledgement_map: HashMap<(u16, SocketAddrV4), u32>,
-    tcp_window_map: HashMap<(u16, SocketAddrV4), u16>,
-    tcp_cache_map: HashMap<(u16, SocketAddrV4), Cacher>,
-    tcp_cache2_map: HashMap<(u16, SocketAddrV4), Cacher>,
+    /// Represents the last DSCP received from the source to each IP address.
+    ipv4_dscp_map: HashMap<Ipv4Addr, u8>,
+    tcp_send_window_map: HashMap<(SocketAddrV4, SocketAddrV4), u32>,
+    tcp_sequence_map: HashMap<(SocketAddrV4, SocketAddrV4), u32>,
+    tcp_acknowledgement_map: HashMap<(SocketAddrV4, SocketAddrV4), u32>,
+    tcp_window_map: HashMap<(SocketAddrV4, SocketAddrV4), u16>,
+    tcp_window_scale_map: HashMap<(SocketAddrV4, SocketAddrV4), (u8, u8)>,
+    tcp_sack_map: HashMap<(SocketAddrV4, SocketAddrV4), SackBlocks>,
+    /// Represents the last timestamp value received in TCP connections negotiating timestamps.
+    tcp_timestamps_map: HashMap<(SocketAddrV4, SocketAddrV4), u32>,
+    /// Represents the fast open cookies replied in the SYN of TCP connections.
+    tcp_fast_open_map: HashMap<(SocketAddrV4, SocketAddrV4), Vec<u8>>,
+    /// Represents if ECN echo is pending in TCP connections negotiating ECN.
+    tcp_ecn_map: HashMap<(SocketAddrV4, SocketAddrV4), bool>,
+    tcp_counters_map: HashMap<(SocketAddrV4, SocketAddrV4), Arc<FlowCounters>>,
+    tcp_cache_map: HashMap<(SocketAddrV4, SocketAddrV4), Cacher>,
+    tcp_cache2_map: HashMap<(SocketAddrV4, SocketAddrV4), Cacher>,
+    pool: BufferPool,
+    stats: Arc<Stats>,
+    writer: Option<PcapWriter<File>>,
+    tcp_mss: Option<u16>,
+    tcp_window_scale: Option<u8>,
+    tcp_ecn: bool,
+    /// Represents if the checksums are left to the checksum offload of the link.
+    checksum_offload: bool,
+    /// Represents the DSCP overriding the preserved one.
+    dscp: Option<u8>,
+    ttl_mode: TtlMode,
+    /// Represents the origin of the clock of TCP timestamps.
+    tcp_timestamp_origin: Instant,
+    pmtu_cache: PmtuCache,
+    arp_cache: ArpCache,
+    /// Represents the VLAN tags of frames from each hardware address, which replies carry.
+    vlans_map: HashMap<HardwareAddr, Vec<VlanTag>>,
+    dns_cache: Option<DnsCache>,
+    dns_names: Option<DnsNames>,
+    inspection_hook: Option<Arc<dyn InspectionHook>>,
+    nat64: Option<Nat64>,
 }
 
 impl Forwarder {
-    /// Creates a new `Forwarder`.
+    /// Creates a new `Forwarder`. The MTU is limited by `MAXIMUM_MTU`.
     pub fn new(
-        tx: Sender,
+        tx: Box<dyn Injector>,
         mtu: u16,
         local_hardware_addr: HardwareAddr,
         src_ip_addr: Ipv4Addr,
         local_ip_addr: Ipv4Addr,
     ) -> Forwarder {
+        let mtu = min(mtu, MAXIMUM_MTU);
         Forwarder {
             tx,
             mtu,
@@ -142,15 +291,129 @@ impl Forwarder {
             src_ip_addr,
             local_ip_addr,
             ipv4_identification_map: HashMap::new(),
+            ipv4_dscp_map: HashMap::new(),
             tcp_send_window_map: HashMap::new(),
             tcp_sequence_map: HashMap::new(),
             tcp_acknowledgement_map: HashMap::new(),
             tcp_window_map: HashMap::new(),
+            tcp_window_scale_map: HashMap::new(),
+            tcp_sack_map: HashMap::new(),
+            tcp_timestamps_map: HashMap::new(),
+            tcp_fast_open_map: HashMap::new(),
+            tcp_ecn_map: HashMap::new(),
+            tcp_counters_map: HashMap::new(),
             tcp_cache_map: HashMap::new(),
             tcp_cache2_map: HashMap::new(),
+            pool: BufferPool::new(
+                max(mtu as usize + ETHERNET_HEADER_SIZE, MINIMUM_PACKET_SIZE),
+                BUFFER_POOL_CAPACITY,
+                ExhaustedPolicy::Drop,
+            ),
+            stats: Arc::new(Stats::new()),
+            writer: None,
+            tcp_mss: None,
+            tcp_window_scale: None,
+            tcp_ecn: false,
+            checksum_offload: false,
+            dscp: None,
+            ttl_mode: TtlMode::Initial,
+            tcp_timestamp_origin: Instant::now(),
+            pmtu_cache: PmtuCache::new(),
+            arp_cache: ArpCache::new(),
+            vlans_map: HashMap::new(),
+            dns_cache: None,
+            dns_names: None,
+            inspection_hook: None,
+            nat64: None,
         }
     }
 
+    /// Sets the maximum segment size advertised in TCP ACK/SYN packets. The value is limited by
+    /// the MTU.
+    pub fn set_tcp_mss(&mut self, mss: u16) {
+        self.tcp_mss = Some(mss);
+    }
+
+    /// Sets the window scale shift count advertised in TCP ACK/SYN packets, or `None` for not
+    /// advertising the option. The option is only advertised if the SYN also carries it.
+    pub fn set_tcp_window_scale(&mut self, wscale: Option<u8>) {
+        self.tcp_window_scale = wscale.map(|wscale| min(wscale, MAX_WINDOW_SCALE));
+    }
+
+    /// Sets if ECN is negotiated in TCP ACK/SYN packets (RFC 3168). ECN is only negotiated if the
+    /// SYN also requests it.
+    pub fn set_tcp_ecn(&mut self, ecn: bool) {
+        self.tcp_ecn = ecn;
+    }
+
+    /// Sets if the IPv4, TCP and UDP checksums of frames sent are left to the checksum offload of
+    /// the link, which are written as 0 and filled by the NIC or the kernel. Other checksums, e.g.,
+    /// ICMP, are always computed.
+    pub fn set_checksum_offload(&mut self, offload: bool) {
+        self.checksum_offload = offload;
+    }
+
+    /// Sets the NAT64 translator, or `None` for not translating. Frames sent to the addresses of
+    /// the NAT64 pool are translated back into IPv6 frames, and the MSS is reduced for the larger
+    /// IPv6 header.
+    pub fn set_nat64(&mut self, nat64: Option<Nat64>) {
+        self.nat64 = nat64;
+    }
+
+    /// Translates the given IPv6 frame into an IPv4 frame with the NAT64 translator. Returns
+    /// `None` if NAT64 is disabled or the frame is not translated.
+    pub fn translate_nat64(&mut self, frame: &[u8]) -> io::Result<Option<Vec<u8>>> {
+        match self.nat64 {
+            Some(ref mut nat64) => nat64.translate_ipv6(frame),
+            None => Ok(None),
+        }
+    }
+
+    /// Returns if the given IPv6 address is a destination translated by the NAT64 translator.
+    pub fn is_nat64_dst(&self, ip_addr: Ipv6Addr) -> bool {
+        self.nat64.as_ref().map_or(false, |nat64| {
+            nat64::extract_ipv4(&ip_addr, &nat64.get_prefix()).is_some()
+        })
+    }
+
+    /// Returns if the given IP address is mapped to an IPv6 source by the NAT64 translator.
+    pub fn is_nat64_addr(&self, ip_addr: Ipv4Addr) -> bool {
+        self.nat64
+            .as_ref()
+            .map_or(false, |nat64| nat64.contains(ip_addr))
+    }
+
+    /// Sets the hook inspecting the payloads sent to the source.
+    pub fn set_inspection_hook(&mut self, hook: Arc<dyn InspectionHook>) {
+        self.inspection_hook = Some(hook);
+    }
+
+    /// Sets the policy of the buffer pool for sending when all of its buffers are in use. Frames
+    /// are dropped and counted under `ExhaustedPolicy::Drop`.
+    pub fn set_pool_policy(&mut self, policy: ExhaustedPolicy) {
+        self.pool = BufferPool::new(self.pool.get_size(), BUFFER_POOL_CAPACITY, policy);
+    }
+
+    /// Sets the statistics where frames dropped by the forwarder are counted.
+    pub fn set_stats(&mut self, stats: Arc<Stats>) {
+        self.stats = stats;
+    }
+
+    /// Get the flow from the source to the given destination.
+    fn get_flow_key(
+        &self,
+        dst: SocketAddrV4,
+        src: SocketAddrV4,
+        protocol: IpNextHeaderProtocol,
+    ) -> FlowKey {
+        FlowKey::new(protocol, SocketAddr::V4(src), SocketAddr::V4(dst))
+    }
+
+    /// Sets the writer which dumps every frame sent.
+    pub fn set_writer(&mut self, writer: PcapWriter<File>) {
+        self.writer = Some(writer);
+    }
+
     /// Sets the source hardware address.
     pub fn set_src_hardware_addr(&mut self, hardware_addr: HardwareAddr) {
         self.src_hardware_addr = hardware_addr;
@@ -163,6 +426,36 @@ impl Forwarder {
         trace!("set local IP address to {}", ip_addr);
     }
 
+    /// Sets the DSCP of IPv4 packets sent, or `None` for preserving the DSCP of packets
+    /// received. The ECN bits are not affected.
+    pub fn set_dscp(&mut self, dscp: Option<u8>) {
+        self.dscp = dscp;
+    }
+
+    /// Sets how the TTL of IPv4 packets sent is set. The TTL is also the hop limit of the
+    /// packets translated back by NAT64.
+    pub fn set_ttl_mode(&mut self, mode: TtlMode) {
+        self.ttl_mode = mode;
+    }
+
+    /// Sets the DSCP of the packets received from the source to the given IP address, which is
+    /// preserved in the packets sent from the IP address.
+    pub fn set_ipv4_dscp(&mut self, ip_addr: Ipv4Addr, dscp: u8) {
+        if dscp == 0 {
+            self.ipv4_dscp_map.remove(&ip_addr);
+        } else {
+            self.ipv4_dscp_map.insert(ip_addr, dscp);
+        }
+    }
+
+    /// Get the DSCP of the packets sent from the given IP address.
+    fn get_ipv4_dscp(&self, ip_addr: Ipv4Addr) -> u8 {
+        match self.dscp {
+            Some(dscp) => dscp,
+            None => *self.ipv4_dscp_map.get(&ip_addr).unwrap_or(&0),
+        }
+    }
+
     fn increase_ipv4_identification(&mut self, ip_addr: Ipv4Addr) {
         let entry = self.ipv4_identification_map.entry(ip_addr).or_insert(0);
         *entry = entry.checked_add(1).unwrap_or(0);
@@ -170,91 +463,286 @@ impl Forwarder {
     }
 
     /// Sets the send window size of a TCP connection. This window
-    pub fn set_tcp_send_window(&mut self, dst: SocketAddrV4, src_port: u16, window: u16) {
-        self.tcp_send_window_map.insert((src_port, dst), window);
-        trace!(
-            "set TCP send window of {} -> {} to {}",
-            src_port,
-            dst,
-            window,
-        );
+    pub fn set_tcp_send_window(&mut self, dst: SocketAddrV4, src: SocketAddrV4, window: u16) {
+        let key = (src, dst);
+
+        // Scale
+        let shift = match self.tcp_window_scale_map.get(&key) {
+            Some((_, remote)) => *remote,
+            None => 0,
+        };
+        let window = (window as u32) << shift;
+
+        self.tcp_send_window_map.insert(key, window);
+        trace!("set TCP send window of {} -> {} to {}", src, dst, window,);
     }
 
     /// Sets the sequence of a TCP connection. In fact, this function should never be used.
     #[deprecated(note = "this function should never be used")]
-    pub fn set_tcp_sequence(&mut self, dst: SocketAddrV4, src_port: u16, acknowledgement: u32) {
-        self.tcp_sequence_map
-            .insert((src_port, dst), acknowledgement);
+    pub fn set_tcp_sequence(&mut self, dst: SocketAddrV4, src: SocketAddrV4, acknowledgement: u32) {
+        self.tcp_sequence_map.insert((src, dst), acknowledgement);
         trace!(
             "set TCP sequence of {} -> {} to {}",
             dst,
-            src_port,
+            src,
             acknowledgement
         );
     }
 
     /// Sets the acknowledgement of a TCP connection.
-    pub fn set_tcp_acknowledgement(&mut self, dst: SocketAddrV4, src_port: u16, sequence: u32) {
-        self.tcp_acknowledgement_map
-            .insert((src_port, dst), sequence);
+    pub fn set_tcp_acknowledgement(&mut self, dst: SocketAddrV4, src: SocketAddrV4, sequence: u32) {
+        self.tcp_acknowledgement_map.insert((src, dst), sequence);
+        if let Some(sack) = self.tcp_sack_map.get_mut(&(src, dst)) {
+            sack.acknowledge(sequence);
+        }
         trace!(
             "set TCP acknowledgement of {} -> {} to {}",
             dst,
-            src_port,
+            src,
             sequence
         );
     }
 
+    /// Get the sequence of a TCP connection.
+    pub fn get_tcp_sequence(&self, dst: SocketAddrV4, src: SocketAddrV4) -> u32 {
+        *self.tcp_sequence_map.get(&(src, dst)).unwrap_or(&0)
+    }
+
+    /// Get the acknowledgement of a TCP connection.
+    pub fn get_tcp_acknowledgement(&self, dst: SocketAddrV4, src: SocketAddrV4) -> u32 {
+        *self.tcp_acknowledgement_map.get(&(src, dst)).unwrap_or(&0)
+    }
+
     /// Adds acknowledgement to a TCP connection.
-    pub fn add_tcp_acknowledgement(&mut self, dst: SocketAddrV4, src_port: u16, n: u32) {
-        let entry = self
-            .tcp_acknowledgement_map
-            .entry((src_port, dst))
-            .or_insert(0);
+    pub fn add_tcp_acknowledgement(&mut self, dst: SocketAddrV4, src: SocketAddrV4, n: u32) {
+        let entry = self.tcp_acknowledgement_map.entry((src, dst)).or_insert(0);
         *entry = entry
             .checked_add(n)
             .unwrap_or_else(|| n - (u32::MAX - *entry));
-        trace!(
-            "add TCP acknowledgement of {} -> {} to {}",
-            dst,
-            src_port,
-            entry
-        );
+        if let Some(sack) = self.tcp_sack_map.get_mut(&(src, dst)) {
+            sack.acknowledge(*entry);
+        }
+        trace!("add TCP acknowledgement of {} -> {} to {}", dst, src, entry);
     }
 
     /// Sets the window size of a TCP connection.
-    pub fn set_tcp_window(&mut self, dst: SocketAddrV4, src_port: u16, window: u16) {
-        self.tcp_window_map.insert((src_port, dst), window);
-        trace!("set TCP window of {} -> {} to {}", dst, src_port, window);
+    pub fn set_tcp_window(&mut self, dst: SocketAddrV4, src: SocketAddrV4, window: u16) {
+        self.tcp_window_map.insert((src, dst), window);
+        trace!(target: TCP_LOG_TARGET, "set TCP window of {} -> {} to {}", dst, src, window);
+    }
+
+    /// Sets the window scale shift count in the SYN of a TCP connection, or `None` if the SYN
+    /// does not carry the option. Window scaling is enabled if both sides advertise the option.
+    pub fn set_tcp_remote_window_scale(
+        &mut self,
+        dst: SocketAddrV4,
+        src: SocketAddrV4,
+        wscale: Option<u8>,
+    ) {
+        let key = (src, dst);
+
+        match (self.tcp_window_scale, wscale) {
+            (Some(local), Some(remote)) => {
+                self.tcp_window_scale_map
+                    .insert(key, (local, min(remote, MAX_WINDOW_SCALE)));
+            }
+            _ => {
+                self.tcp_window_scale_map.remove(&key);
+            }
+        }
+    }
+
+    /// Sets if selective acknowledgement is permitted in the SYN of a TCP connection.
+    pub fn set_tcp_sack_permitted(
+        &mut self,
+        dst: SocketAddrV4,
+        src: SocketAddrV4,
+        permitted: bool,
+    ) {
+        let key = (src, dst);
+
+        if permitted {
+            self.tcp_sack_map.entry(key).or_insert_with(SackBlocks::new);
+        } else {
+            self.tcp_sack_map.remove(&key);
+        }
+    }
+
+    /// Sets if ECN is requested in the SYN of a TCP connection. ECN is negotiated if it is also
+    /// enabled in the forwarder.
+    pub fn set_tcp_remote_ecn(&mut self, dst: SocketAddrV4, src: SocketAddrV4, requested: bool) {
+        let key = (src, dst);
+
+        if self.tcp_ecn && requested {
+            self.tcp_ecn_map.insert(key, false);
+        } else {
+            self.tcp_ecn_map.remove(&key);
+        }
+    }
+
+    /// Updates the ECN echo of a TCP connection negotiating ECN. The ECE flag is set in the
+    /// following ACKs once a segment marked congestion experienced is received, until a segment
+    /// with the CWR flag is received.
+    pub fn update_tcp_ecn_echo(
+        &mut self,
+        dst: SocketAddrV4,
+        src: SocketAddrV4,
+        cwr: bool,
+        congestion_experienced: bool,
+    ) {
+        let key = (src, dst);
+
+        if let Some(pending) = self.tcp_ecn_map.get_mut(&key) {
+            if congestion_experienced {
+                if !*pending {
+                    trace!("congestion experienced {} -> {}", src, dst);
+                }
+                *pending = true;
+            } else if cwr {
+                *pending = false;
+            }
+        }
+    }
+
+    /// Sets the timestamps option in the SYN of a TCP connection, or `None` if the SYN does not
+    /// carry the option. Timestamps are enabled if the SYN carries the option, and the timestamp
+    /// value is echoed in the following segments.
+    pub fn set_tcp_remote_timestamps(
+        &mut self,
+        dst: SocketAddrV4,
+        src: SocketAddrV4,
+        timestamps: Option<(u32, u32)>,
+    ) {
+        let key = (src, dst);
+
+        match timestamps {
+            Some((tsval, _)) => {
+                self.tcp_timestamps_map.insert(key, tsval);
+            }
+            None => {
+                self.tcp_timestamps_map.remove(&key);
+            }
+        }
+    }
+
+    /// Sets the fast open cookie replied in the SYN of a TCP connection, or `None` if no cookie is
+    /// replied.
+    pub fn set_tcp_fast_open_cookie(
+        &mut self,
+        dst: SocketAddrV4,
+        src: SocketAddrV4,
+        cookie: Option<&[u8]>,
+    ) {
+        let key = (src, dst);
+
+        match cookie {
+            Some(cookie) => {
+                self.tcp_fast_open_map.insert(key, cookie.to_vec());
+            }
+            None => {
+                self.tcp_fast_open_map.remove(&key);
+            }
+        }
+    }
+
+    /// Updates the timestamp value received in a TCP connection negotiating timestamps. Values
+    /// older than the last one are ignored.
+    pub fn update_tcp_timestamp(&mut self, dst: SocketAddrV4, src: SocketAddrV4, tsval: u32) {
+        if let Some(recent) = self.tcp_timestamps_map.get_mut(&(src, dst)) {
+            if tsval.wrapping_sub(*recent) < 1 << 31 {
+                *recent = tsval;
+            }
+        }
+    }
+
+    /// Get the timestamp value and the timestamp echo reply to be sent in a TCP connection.
+    /// Returns `None` if the connection does not negotiate timestamps.
+    fn get_tcp_timestamps(&self, key: &(SocketAddrV4, SocketAddrV4)) -> Option<(u32, u32)> {
+        let tsecr = *self.tcp_timestamps_map.get(key)?;
+        // The clock ticks every 1 ms
+        let tsval = self.tcp_timestamp_origin.elapsed().as_millis() as u32;
+
+        Some((tsval, tsecr))
+    }
+
+    /// Returns if the ECE flag should be set in the segments sent in a TCP connection.
+    fn is_tcp_ecn_echo_pending(&self, key: &(SocketAddrV4, SocketAddrV4)) -> bool {
+        *self.tcp_ecn_map.get(key).unwrap_or(&false)
+    }
+
+    /// Adds the range of out-of-order data received in a TCP connection, which is reported in
+    /// the following ACKs if selective acknowledgement is permitted.
+    pub fn add_tcp_sack_block(
+        &mut self,
+        dst: SocketAddrV4,
+        src: SocketAddrV4,
+        left: u32,
+        right: u32,
+    ) {
+        if let Some(sack) = self.tcp_sack_map.get_mut(&(src, dst)) {
+            sack.insert(left, right);
+            trace!(
+                "add TCP SACK block of {} -> {}: {}-{}",
+                dst,
+                src,
+                left,
+                right
+            );
+        }
+    }
+
+    /// Sets the counters of a TCP connection, which counts the payload sent to the source.
+    pub fn set_tcp_counters(
+        &mut self,
+        dst: SocketAddrV4,
+        src: SocketAddrV4,
+        counters: Arc<FlowCounters>,
+    ) {
+        self.tcp_counters_map.insert((src, dst), counters);
+    }
+
+    /// Get the window size of a TCP connection to be advertised, scaled down by the window scale.
+    fn get_tcp_window(&self, key: &(SocketAddrV4, SocketAddrV4)) -> u16 {
+        let window = *self.tcp_window_map.get(key).unwrap_or(&65535);
+        match self.tcp_window_scale_map.get(key) {
+            Some((local, _)) => window >> *local,
+            None => window,
+        }
     }
 
     /// Invalidates TCP cache to the given sequence.
-    pub fn invalidate_cache_to(&mut self, dst: SocketAddrV4, src_port: u16, sequence: u32) {
-        if let Some(cache) = self.tcp_cache_map.get_mut(&(src_port, dst)) {
+    pub fn invalidate_cache_to(&mut self, dst: SocketAddrV4, src: SocketAddrV4, sequence: u32) {
+        if let Some(cache) = self.tcp_cache_map.get_mut(&(src, dst)) {
             cache.invalidate_to(sequence);
         }
         trace!(
             "invalidate cache {} -> {} to sequence {}",
             dst,
-            src_port,
+            src,
             sequence
         );
     }
 
     /// Removes all information related to a TCP connection.
-    pub fn remove(&mut self, dst: SocketAddrV4, src_port: u16) {
-        let key = (src_port, dst);
+    pub fn remove(&mut self, dst: SocketAddrV4, src: SocketAddrV4) {
+        let key = (src, dst);
 
         self.tcp_sequence_map.remove(&key);
         self.tcp_acknowledgement_map.remove(&key);
         self.tcp_window_map.remove(&key);
+        self.tcp_window_scale_map.remove(&key);
+        self.tcp_sack_map.remove(&key);
+        self.tcp_timestamps_map.remove(&key);
+        self.tcp_fast_open_map.remove(&key);
+        self.tcp_ecn_map.remove(&key);
+        self.tcp_counters_map.remove(&key);
         self.tcp_cache_map.remove(&key);
-        trace!("remove {} -> {}", dst, src_port);
+        trace!("remove {} -> {}", dst, src);
     }
 
     /// Get the size of the cache of a TCP connection.
-    pub fn get_cache_size(&mut self, dst: SocketAddrV4, src_port: u16) -> usize {
-        let key = (src_port, dst);
+    pub fn get_cache_size(&mut self, dst: SocketAddrV4, src: SocketAddrV4) -> usize {
+        let key = (src, dst);
 
         let mut size = 0;
         if let Some(cache) = self.tcp_cache_map.get(&key) {
@@ -267,6 +755,145 @@ impl Forwarder {
         size
     }
 
+    /// Get the local hardware address of the forwarder.
+    pub fn get_local_hardware_addr(&self) -> HardwareAddr {
+        self.local_hardware_addr
+    }
+
+    /// Get the MTU of the forwarder.
+    pub fn get_mtu(&self) -> u16 {
+        self.mtu
+    }
+
+    /// Get the link-local IPv6 address of the local hardware address, which is the address of the
+    /// gateway to IPv6 sources.
+    pub fn get_local_ipv6_addr(&self) -> Ipv6Addr {
+        ipv6::link_local_addr(self.local_hardware_addr)
+    }
+
+    /// Get the maximum segment size of TCP according to the path MTU to the source.
+    pub fn get_mss(&self) -> u16 {
+        let overhead = match self.nat64 {
+            Some(_) => NAT64_HEADER_OVERHEAD,
+            None => 0,
+        };
+
+        self.get_path_mtu(self.src_ip_addr)
+            .saturating_sub(TCP_IPV4_HEADER_SIZE + overhead)
+    }
+
+    /// Get the maximum segment size advertised in TCP ACK/SYN packets, which is limited by the
+    /// path MTU.
+    pub fn get_advertised_mss(&self) -> u16 {
+        match self.tcp_mss {
+            Some(mss) => min(mss, self.get_mss()),
+            None => self.get_mss(),
+        }
+    }
+
+    /// Sets the path MTU to the given IP address learned from an ICMP fragmentation needed
+    /// message. Expired entries are purged at the same time.
+    pub fn set_path_mtu(&mut self, ip_addr: Ipv4Addr, mtu: u16) {
+        self.pmtu_cache.purge();
+        self.pmtu_cache.insert(ip_addr, mtu, DEFAULT_PMTU_CACHE_TTL);
+    }
+
+    /// Get the path MTU to the given IP address, which is limited by the MTU.
+    pub fn get_path_mtu(&self, ip_addr: Ipv4Addr) -> u16 {
+        match self.pmtu_cache.lookup(ip_addr) {
+            Some(mtu) => min(mtu, self.mtu),
+            None => self.mtu,
+        }
+    }
+
+    /// Updates the ARP cache according to the given `Arp`. Returns if the cache is updated.
+    pub fn update_arp_cache(&mut self, arp: &Arp) -> bool {
+        self.arp_cache.update(arp, DEFAULT_ARP_CACHE_TTL)
+    }
+
+    /// Removes expired entries from the ARP cache and returns the number of entries removed.
+    pub fn purge_arp_cache(&mut self) -> usize {
+        self.arp_cache.purge()
+    }
+
+    /// Get the ARP cache learnt from ARP replies and gratuitous ARPs.
+    pub fn get_arp_cache(&self) -> &ArpCache {
+        &self.arp_cache
+    }
+
+    /// Sets the VLAN tags of frames from the given hardware address. Frames sent to the hardware
+    /// address will carry the same tags.
+    pub fn set_vlans(&mut self, hardware_addr: HardwareAddr, vlans: &[VlanTag]) {
+        if vlans.is_empty() {
+            self.vlans_map.remove(&hardware_addr);
+        } else if self.get_vlans(hardware_addr) != vlans {
+            self.vlans_map.insert(hardware_addr, vlans.to_vec());
+        }
+    }
+
+    /// Get the VLAN tags of frames sent to the given hardware address. Broadcast frames carry
+    /// the tags of the source hardware address.
+    pub fn get_vlans(&self, hardware_addr: HardwareAddr) -> &[VlanTag] {
+        let hardware_addr = match hardware_addr == HardwareAddr::broadcast() {
+            true => self.src_hardware_addr,
+            false => hardware_addr,
+        };
+        match self.vlans_map.get(&hardware_addr) {
+            Some(vlans) => vlans,
+            None => &[],
+        }
+    }
+
+    /// Get the hardware address the given IP address is sent to, which is looked up in the ARP
+    /// cache, or the source hardware address if there is no entry.
+    fn get_dst_hardware_addr(&self, ip_addr: Ipv4Addr) -> HardwareAddr {
+        if let Some(hardware_addr) = self
+            .nat64
+            .as_ref()
+            .and_then(|nat64| nat64.get_hardware_addr(ip_addr))
+        {
+            return hardware_addr;
+        }
+
+        self.arp_cache
+            .lookup(ip_addr)
+            .unwrap_or(self.src_hardware_addr)
+    }
+
+    /// Sets if DNS responses received from the SOCKS5 proxy are cached, which answer repeated
+    /// queries locally.
+    pub fn set_dns_cache(&mut self, enabled: bool) {
+        self.dns_cache = match enabled {
+            true => Some(DnsCache::new()),
+            false => None,
+        };
+    }
+
+    /// Looks up the cached response of the given DNS query. Returns `None` if the DNS cache is
+    /// disabled or there is no response cached.
+    pub fn lookup_dns(&self, query: &[u8]) -> Option<Vec<u8>> {
+        self.dns_cache
+            .as_ref()
+            .and_then(|dns_cache| dns_cache.lookup(query))
+    }
+
+    /// Sets if the names of IP addresses in DNS responses received from the SOCKS5 proxy are
+    /// remembered, which lets TCP connections to the addresses be requested by their names.
+    pub fn set_dns_names(&mut self, enabled: bool) {
+        self.dns_names = match enabled {
+            true => Some(DnsNames::new()),
+            false => None,
+        };
+    }
+
+    /// Looks up the name of the given IP address learned from DNS responses. Returns `None` if
+    /// the names are not remembered or the IP address is not resolved from a name.
+    pub fn lookup_dns_name(&mut self, ip_addr: Ipv4Addr) -> Option<String> {
+        self.dns_names
+            .as_mut()
+            .and_then(|dns_names| dns_names.lookup(ip_addr))
+    }
+
     /// Sends an ARP reply packet.
     pub fn send_arp_reply(&mut self) -> io::Result<()> {
         // ARP
@@ -277,13 +904,45 @@ impl Forwarder {
             self.src_ip_addr,
         );
 
+        self.send_arp(arp)
+    }
+
+    /// Sends an ARP reply packet to the given ARP request.
+    pub fn send_arp_reply_to(&mut self, request: &Arp) -> io::Result<()> {
+        // ARP
+        let arp = arp::build_arp_reply(request, self.local_hardware_addr);
+
+        self.send_arp(arp)
+    }
+
+    /// Sends a gratuitous ARP announcing the given IP address with the given hardware address.
+    /// The ARP is broadcast.
+    pub fn send_gratuitous_arp(
+        &mut self,
+        ip_addr: Ipv4Addr,
+        hardware_addr: HardwareAddr,
+    ) -> io::Result<()> {
+        // ARP
+        let arp = Arp::new_gratuitous(hardware_addr, ip_addr);
+
+        self.send_arp_to(arp, HardwareAddr::broadcast())
+    }
+
+    fn send_arp(&mut self, arp: Arp) -> io::Result<()> {
+        let dst_hardware_addr = arp.get_dst_hardware_addr();
+
+        self.send_arp_to(arp, dst_hardware_addr)
+    }
+
+    fn send_arp_to(&mut self, arp: Arp, dst_hardware_addr: HardwareAddr) -> io::Result<()> {
         // Ethernet
-        let ethernet = Ethernet::new(
+        let mut ethernet = Ethernet::new(
             arp.get_type(),
             arp.get_src_hardware_addr(),
-            arp.get_dst_hardware_addr(),
+            dst_hardware_addr,
         )
         .unwrap();
+        ethernet.set_vlans(self.get_vlans(dst_hardware_addr).to_vec());
 
         // Indicator
         let indicator = Indicator::new(Layers::Ethernet(ethernet), Some(Layers::Arp(arp)), None);
@@ -296,10 +955,10 @@ impl Forwarder {
     pub fn append_to_cache(
         &mut self,
         dst: SocketAddrV4,
-        src_port: u16,
+        src: SocketAddrV4,
         payload: &[u8],
     ) -> io::Result<()> {
-        let key = (src_port, dst);
+        let key = (src, dst);
 
         // TCP sequence
         let sequence = *self.tcp_sequence_map.get(&key).unwrap_or(&0);
@@ -311,12 +970,12 @@ impl Forwarder {
             .or_insert_with(|| Cacher::new_unbounded(sequence));
         cache.append(payload)?;
 
-        self.send_tcp_ack(dst, src_port)
+        self.send_tcp_ack(dst, src)
     }
 
     /// Resends TCP ACK packets from first (sent) cache.
-    pub fn resend_tcp_ack(&mut self, dst: SocketAddrV4, src_port: u16) -> io::Result<()> {
-        let key = (src_port, dst);
+    pub fn resend_tcp_ack(&mut self, dst: SocketAddrV4, src: SocketAddrV4) -> io::Result<()> {
+        let key = (src, dst);
 
         // Resend
         let payload;
@@ -335,15 +994,15 @@ impl Forwarder {
         };
 
         if payload.len() > 0 {
-            return self.send_tcp_ack_raw(dst, src_port, sequence, payload.as_slice());
+            self.send_tcp_ack_raw(dst, src, sequence, payload.as_slice())?;
         }
 
         Ok(())
     }
 
     /// Sends TCP ACK packets from second (unsent) cache.
-    pub fn send_tcp_ack(&mut self, dst: SocketAddrV4, src_port: u16) -> io::Result<()> {
-        let key = (src_port, dst);
+    pub fn send_tcp_ack(&mut self, dst: SocketAddrV4, src: SocketAddrV4) -> io::Result<()> {
+        let key = (src, dst);
 
         if let None = self.tcp_cache2_map.get(&key) {
             return Ok(());
@@ -378,7 +1037,14 @@ impl Forwarder {
                 cache.append(&payload)?;
 
                 // Send
-                self.send_tcp_ack_raw(dst, src_port, sequence, &payload)?;
+                let n = self.send_tcp_ack_raw(dst, src, sequence, &payload)?;
+
+                // Count the payload which is sent for the first time, which also responds to
+                // the request acknowledged
+                if let Some(counters) = self.tcp_counters_map.get(&key) {
+                    counters.add_down(size, n);
+                    counters.on_response(*self.tcp_acknowledgement_map.get(&key).unwrap_or(&0));
+                }
             }
         }
 
@@ -388,11 +1054,11 @@ impl Forwarder {
     fn send_tcp_ack_raw(
         &mut self,
         dst: SocketAddrV4,
-        src_port: u16,
+        src: SocketAddrV4,
         sequence: u32,
         payload: &[u8],
-    ) -> io::Result<()> {
-        let key = (src_port, dst);
+    ) -> io::Result<usize> {
+        let key = (src, dst);
 
         // Pseudo headers
         let tcp = Tcp::new_ack(0, 0, 0, 0, 0);
@@ -405,32 +1071,37 @@ impl Forwarder {
         .unwrap();
 
         // Segmentation
-        let header_size = ipv4.get_size() + tcp.get_size();
-        let max_payload_size = self.mtu as usize - header_size;
-        let mut i = 0;
-        while max_payload_size * i < payload.len() {
-            let length = min(max_payload_size, payload.len() - i * max_payload_size);
-            let payload = &payload[i * max_payload_size..i * max_payload_size + length];
-            let sequence = sequence
-                .checked_add((i * max_payload_size) as u32)
-                .unwrap_or_else(|| (i * max_payload_size) as u32 - (u32::MAX - sequence));
-
-            // TCP
-            let tcp = Tcp::new_ack(
-                dst.port(),
-                src_port,
-                sequence,
-                *self.tcp_acknowledgement_map.get(&key).unwrap_or(&0),
-                *self.tcp_window_map.get(&key).unwrap_or(&65535),
-            );
+        let timestamps = self.get_tcp_timestamps(&key);
+        let mut header_size = ipv4.get_size() + tcp.get_size();
+        if timestamps.is_some() {
+            header_size += TCP_TIMESTAMPS_OPTION_SIZE;
+        }
+        let max_payload_size =
+            (self.get_path_mtu(self.src_ip_addr) as usize).saturating_sub(header_size);
+        let acknowledgement = *self.tcp_acknowledgement_map.get(&key).unwrap_or(&0);
+        let window = self.get_tcp_window(&key);
+        let ece = self.is_tcp_ecn_echo_pending(&key);
+        let segments = tcp::segment(payload, max_payload_size, sequence);
+        let n = segments.len();
+        for mut tcp in segments {
+            tcp.set_ports(dst.port(), src.port());
+            tcp.set_acknowledgement(acknowledgement);
+            tcp.set_window(window);
+            tcp.set_timestamps(timestamps);
+            tcp.set_ece(ece);
+            let offset = tcp.get_sequence().wrapping_sub(sequence) as usize;
+            let length = tcp.get_payload().len();
+            let next_sequence = tcp.get_sequence().wrapping_add(length as u32);
 
             // Send
-            self.send_ipv4_with_transport(dst.ip().clone(), Layers::Tcp(tcp), Some(payload))?;
+            self.send_ipv4_with_transport(
+                *dst.ip(),
+                *src.ip(),
+                Layers::Tcp(tcp),
+                Some(&payload[offset..offset + length]),
+            )?;
 
             // Update TCP sequence
-            let next_sequence = sequence
-                .checked_add(length as u32)
-                .unwrap_or_else(|| length as u32 - (u32::MAX - sequence));
             let record_sequence = *self.tcp_sequence_map.get(&key).unwrap_or(&0);
             let sub_sequence = next_sequence
                 .checked_sub(record_sequence)
@@ -438,45 +1109,116 @@ impl Forwarder {
             if (sub_sequence as usize) < MAX_U32_WINDOW_SIZE {
                 self.tcp_sequence_map.insert(key, next_sequence);
             }
-
-            i = i + 1;
         }
 
-        Ok(())
+        Ok(n)
     }
 
     /// Sends an TCP ACK packet without payload.
-    pub fn send_tcp_ack_0(&mut self, dst: SocketAddrV4, src_port: u16) -> io::Result<()> {
-        let key = (src_port, dst);
+    pub fn send_tcp_ack_0(&mut self, dst: SocketAddrV4, src: SocketAddrV4) -> io::Result<()> {
+        let key = (src, dst);
 
         // TCP
-        let tcp = Tcp::new_ack(
+        let mut tcp = Tcp::new_ack(
             dst.port(),
-            src_port,
+            src.port(),
             *self.tcp_sequence_map.get(&key).unwrap_or(&0),
             *self.tcp_acknowledgement_map.get(&key).unwrap_or(&0),
-            *self.tcp_window_map.get(&key).unwrap_or(&65535),
+            self.get_tcp_window(&key),
+        );
+        // Timestamps
+        tcp.set_timestamps(self.get_tcp_timestamps(&key));
+        // Selective acknowledgement
+        if let Some(sack) = self.tcp_sack_map.get(&key) {
+            tcp.set_sack_blocks(sack.get_blocks());
+        }
+        // Explicit congestion notification
+        tcp.set_ece(self.is_tcp_ecn_echo_pending(&key));
+
+        // Send
+        self.send_ipv4_with_transport(*dst.ip(), *src.ip(), Layers::Tcp(tcp), None)
+    }
+
+    /// Sends an TCP keep-alive probe, which carries the sequence right before the next sequence
+    /// and no payload, so the source acknowledges it.
+    pub fn send_tcp_keepalive(&mut self, dst: SocketAddrV4, src: SocketAddrV4) -> io::Result<()> {
+        let key = (src, dst);
+
+        // TCP
+        let mut tcp = Tcp::new_ack(
+            dst.port(),
+            src.port(),
+            self.tcp_sequence_map
+                .get(&key)
+                .unwrap_or(&0)
+                .wrapping_sub(1),
+            *self.tcp_acknowledgement_map.get(&key).unwrap_or(&0),
+            self.get_tcp_window(&key),
         );
+        // Timestamps
+        tcp.set_timestamps(self.get_tcp_timestamps(&key));
 
         // Send
-        self.send_ipv4_with_transport(dst.ip().clone(), Layers::Tcp(tcp), None)
+        self.send_ipv4_with_transport(*dst.ip(), *src.ip(), Layers::Tcp(tcp), None)
     }
 
     /// Sends an TCP ACK/SYN packet.
-    pub fn send_tcp_ack_syn(&mut self, dst: SocketAddrV4, src_port: u16) -> io::Result<()> {
-        let key = (src_port, dst);
+    pub fn send_tcp_ack_syn(&mut self, dst: SocketAddrV4, src: SocketAddrV4) -> io::Result<()> {
+        let key = (src, dst);
 
         // TCP
-        let tcp = Tcp::new_ack_syn(
+        let mut tcp = Tcp::new_ack_syn(
             dst.port(),
-            src_port,
+            src.port(),
             *self.tcp_sequence_map.get(&key).unwrap_or(&0),
             *self.tcp_acknowledgement_map.get(&key).unwrap_or(&0),
             *self.tcp_window_map.get(&key).unwrap_or(&65535),
         );
+        // Clamp MSS
+        tcp.clamp_mss(self.get_advertised_mss());
+        // Selective acknowledgement permitted
+        tcp.set_sack_permitted(self.tcp_sack_map.contains_key(&key));
+        // Window scale
+        if let Some((local, _)) = self.tcp_window_scale_map.get(&key) {
+            tcp.set_window_scale(Some(*local));
+        }
+        // Timestamps
+        tcp.set_timestamps(self.get_tcp_timestamps(&key));
+        // Fast open
+        if let Some(cookie) = self.tcp_fast_open_map.get(&key) {
+            tcp.set_fast_open_cookie(Some(cookie));
+        }
+        // Explicit congestion notification
+        tcp.set_ece(self.tcp_ecn_map.contains_key(&key));
+
+        // Send
+        self.send_ipv4_with_transport(*dst.ip(), *src.ip(), Layers::Tcp(tcp), None)?;
+
+        // Update TCP sequence
+        let tcp_sequence_entry = self.tcp_sequence_map.entry(key).or_insert(0);
+        *tcp_sequence_entry = tcp_sequence_entry.checked_add(1).unwrap_or(0);
+
+        Ok(())
+    }
+
+    /// Sends an TCP SYN packet, which opens a TCP connection to the source actively.
+    pub fn send_tcp_syn(&mut self, dst: SocketAddrV4, src: SocketAddrV4) -> io::Result<()> {
+        let key = (src, dst);
+
+        // TCP
+        let mut tcp = Tcp::new_syn(
+            dst.port(),
+            src.port(),
+            *self.tcp_sequence_map.get(&key).unwrap_or(&0),
+            *self.tcp_window_map.get(&key).unwrap_or(&65535),
+        );
+        // Clamp MSS
+        tcp.clamp_mss(self.get_advertised_mss());
+        // Selective acknowledgement permitted
+        tcp.set_sack_permitted(true);
 
         // Send
-        self.send_ipv4_with_transport(dst.ip().clone(), Layers::Tcp(tcp), None)?;
+        self.send_ipv4_with_transport(*dst.ip(), *src.ip(), Layers::Tcp(tcp), None)?;
 
         // Update TCP sequence
         let tcp_sequence_entry = self.tcp_sequence_map.entry(key).or_insert(0);
@@ -486,197 +1228,297 @@ impl Forwarder {
     }
 
     /// Sends an TCP ACK/RST packet.
-    pub fn send_tcp_ack_rst(&mut self, dst: SocketAddrV4, src_port: u16) -> io::Result<()> {
-        let key = (src_port, dst);
+    pub fn send_tcp_ack_rst(&mut self, dst: SocketAddrV4, src: SocketAddrV4) -> io::Result<()> {
+        let key = (src, dst);
 
         // TCP
         let tcp = Tcp::new_ack_rst(
             dst.port(),
-            src_port,
+            src.port(),
             *self.tcp_sequence_map.get(&key).unwrap_or(&0),
             *self.tcp_acknowledgement_map.get(&key).unwrap_or(&0),
-            *self.tcp_window_map.get(&key).unwrap_or(&65535),
+            self.get_tcp_window(&key),
         );
 
         // Send
-        self.send_ipv4_with_transport(dst.ip().clone(), Layers::Tcp(tcp), None)
+        self.send_ipv4_with_transport(*dst.ip(), *src.ip(), Layers::Tcp(tcp), None)
     }
 
     /// Sends an TCP ACK/FIN packet.
-    pub fn send_tcp_ack_fin(&mut self, dst: SocketAddrV4, src_port: u16) -> io::Result<()> {
-        let key = (src_port, dst);
+    pub fn send_tcp_ack_fin(&mut self, dst: SocketAddrV4, src: SocketAddrV4) -> io::Result<()> {
+        let key = (src, dst);
 
         // TCP
-        let tcp = Tcp::new_ack_fin(
+        let mut tcp = Tcp::new_ack_fin(
             dst.port(),
-            src_port,
+            src.port(),
             *self.tcp_sequence_map.get(&key).unwrap_or(&0),
             *self.tcp_acknowledgement_map.get(&key).unwrap_or(&0),
-            *self.tcp_window_map.get(&key).unwrap_or(&65535),
+            self.get_tcp_window(&key),
         );
+        // Timestamps
+        tcp.set_timestamps(self.get_tcp_timestamps(&key));
+        // Explicit congestion notification
+        tcp.set_ece(self.is_tcp_ecn_echo_pending(&key));
 
         // Send
-        self.send_ipv4_with_transport(dst.ip().clone(), Layers::Tcp(tcp), None)
+        self.send_ipv4_with_transport(*dst.ip(), *src.ip(), Layers::Tcp(tcp), None)
     }
 
     /// Sends an TCP RST packet.
-    pub fn send_tcp_rst(&mut self, dst: SocketAddrV4, src_port: u16) -> io::Result<()> {
-        let key = (src_port, dst);
+    pub fn send_tcp_rst(&mut self, dst: SocketAddrV4, src: SocketAddrV4) -> io::Result<()> {
+        let key = (src, dst);
 
         // TCP
         let tcp = Tcp::new_rst(
             dst.port(),
-            src_port,
+            src.port(),
             *self.tcp_sequence_map.get(&key).unwrap_or(&0),
             0,
-            *self.tcp_window_map.get(&key).unwrap_or(&65535),
+            self.get_tcp_window(&key),
         );
 
         // Send
-        self.send_ipv4_with_transport(dst.ip().clone(), Layers::Tcp(tcp), None)
+        self.send_ipv4_with_transport(*dst.ip(), *src.ip(), Layers::Tcp(tcp), None)
     }
 
-    /// Sends UDP packets.
-    pub fn send_udp(&mut self, dst: SocketAddrV4, src_port: u16, payload: &[u8]) -> io::Result<()> {
-        // Pseudo headers
-        let udp = Udp::new(0, 0);
-        let ipv4 = Ipv4::new(
-            0,
-            udp.get_type(),
-            Ipv4Addr::UNSPECIFIED,
-            Ipv4Addr::UNSPECIFIED,
+    /// Sends a TCP RST in reply to a TCP segment which does not belong to any connection.
+    pub fn send_tcp_rst_to(&mut self, segment: &Tcp, payload_length: usize) -> io::Result<()> {
+        let rst = tcp::build_rst(
+            segment,
+            payload_length,
+            segment.get_dst_ip_addr(),
+            segment.get_src_ip_addr(),
+        );
+
+        // Send
+        self.send_ipv4_with_transport(
+            segment.get_dst_ip_addr(),
+            segment.get_src_ip_addr(),
+            rst,
+            None,
         )
-        .unwrap();
+    }
 
-        // Fragmentation
-        let ipv4_header_size = ipv4.get_size();
-        let udp_header_size = udp.get_size();
+    /// Sends an ICMP echo reply packet.
+    pub fn send_icmp_echo_reply(
+        &mut self,
+        dst_ip_addr: Ipv4Addr,
+        identifier: u16,
+        sequence: u16,
+        payload: &[u8],
+    ) -> io::Result<()> {
+        // ICMP
+        let icmp = Icmp::new_echo_reply(identifier, sequence);
 
-        let size = udp_header_size + payload.len();
-        let mut n = 0;
-        while n < size {
-            let mut length = min(size - n, self.mtu as usize - ipv4_header_size);
-            let mut remain = size - n - length;
+        // Send
+        self.send_ipv4_with_transport(
+            dst_ip_addr,
+            self.src_ip_addr,
+            Layers::Icmp(icmp),
+            Some(payload),
+        )
+    }
 
-            // Alignment
-            if remain > 0 {
-                length = length / 8 * 8;
-                remain = size - n - length;
-            }
+    /// Sends an ICMP time exceeded message from the given source IP address, quoting the leading
+    /// part of the original datagram.
+    pub fn send_icmp_time_exceeded(
+        &mut self,
+        src_ip_addr: Ipv4Addr,
+        datagram: &[u8],
+    ) -> io::Result<()> {
+        // ICMP
+        let icmp = Icmp::new_time_exceeded();
 
-            // Leave at least 8 Bytes for last fragment
-            if remain > 0 && remain < 8 {
-                length = length - 8;
-            }
+        // Send
+        self.send_ipv4_with_transport(
+            src_ip_addr,
+            self.src_ip_addr,
+            Layers::Icmp(icmp),
+            Some(datagram),
+        )
+    }
 
-            // Send
-            if n == 0 {
-                if remain > 0 {
-                    // UDP
-                    let udp = Udp::new(dst.port(), src_port);
-
-                    self.send_ipv4_more_fragment(
-                        dst.ip().clone(),
-                        udp.get_type(),
-                        (n / 8) as u16,
-                        Some(Layers::Udp(udp)),
-                        &payload[..length - udp_header_size],
-                    )?;
-                } else {
-                    self.send_udp_raw(dst, src_port, payload)?;
-                }
-            } else {
-                if remain > 0 {
-                    self.send_ipv4_more_fragment(
-                        dst.ip().clone(),
-                        udp.get_type(),
-                        (n / 8) as u16,
-                        None,
-                        &payload[n - udp_header_size..n + length - udp_header_size],
-                    )?;
-                } else {
-                    self.send_ipv4_last_fragment(
-                        dst.ip().clone(),
-                        udp.get_type(),
-                        (n / 8) as u16,
-                        &payload[n - udp_header_size..n + length - udp_header_size],
-                    )?;
-                }
+    /// Sends an ICMPv6 time exceeded message from the given source IP address to the given
+    /// destination of the given hardware address, quoting the leading part of the original
+    /// datagram.
+    pub fn send_icmpv6_time_exceeded(
+        &mut self,
+        src_ip_addr: Ipv6Addr,
+        dst_ip_addr: Ipv6Addr,
+        dst_hardware_addr: HardwareAddr,
+        datagram: &[u8],
+    ) -> io::Result<()> {
+        // ICMPv6
+        let mut icmpv6 = Icmpv6::new_time_exceeded();
+        icmpv6.src = src_ip_addr;
+        icmpv6.dst = dst_ip_addr;
+        let datagram = &datagram[..min(datagram.len(), MAX_ORIGINAL_DATAGRAM_SIZE)];
+
+        // Send
+        self.send_ipv6_with_icmpv6(icmpv6, None, dst_hardware_addr, datagram)
+    }
+
+    /// Sends a Neighbor Advertisement in answer to the given Neighbor Solicitation from the given
+    /// hardware address, advertising the local hardware address.
+    pub fn send_neighbor_advertisement_to(
+        &mut self,
+        solicitation: &Icmpv6,
+        src_hardware_addr: HardwareAddr,
+    ) -> io::Result<()> {
+        // ICMPv6
+        let icmpv6 =
+            match icmpv6::build_neighbor_advertisement(solicitation, self.local_hardware_addr) {
+                Some(icmpv6) => icmpv6,
+                None => return Ok(()),
+            };
+
+        // Advertisements to multicast addresses are sent to the mapped hardware address
+        let dst_hardware_addr = match icmpv6.dst.is_multicast() {
+            true => {
+                let octets = icmpv6.dst.octets();
+                HardwareAddr::new(0x33, 0x33, octets[12], octets[13], octets[14], octets[15])
             }
+            false => src_hardware_addr,
+        };
+
+        // Send
+        self.send_ipv6_with_icmpv6(icmpv6, Some(NDP_HOP_LIMIT), dst_hardware_addr, &[])
+    }
 
-            n = n + length;
+    /// Sends the ICMPv6 layer in IPv6 to the given hardware address. The hop limit is left
+    /// default if unspecified.
+    fn send_ipv6_with_icmpv6(
+        &mut self,
+        icmpv6: Icmpv6,
+        hop_limit: Option<u8>,
+        dst_hardware_addr: HardwareAddr,
+        payload: &[u8],
+    ) -> io::Result<()> {
+        // IPv6
+        let mut ipv6 = Ipv6::new(icmpv6.get_type(), icmpv6.src, icmpv6.dst).unwrap();
+        if let Some(hop_limit) = hop_limit {
+            ipv6.set_hop_limit(hop_limit);
         }
 
-        Ok(())
-    }
+        // Ethernet
+        let mut ethernet =
+            Ethernet::new(ipv6.get_type(), self.local_hardware_addr, dst_hardware_addr).unwrap();
+        ethernet.set_vlans(self.get_vlans(dst_hardware_addr).to_vec());
 
-    fn send_udp_raw(&mut self, dst: SocketAddrV4, src_port: u16, payload: &[u8]) -> io::Result<()> {
-        // UDP
-        let udp = Udp::new(dst.port(), src_port);
+        // Indicator
+        let indicator = Indicator::new(
+            Layers::Ethernet(ethernet),
+            Some(Layers::Ipv6(ipv6)),
+            Some(Layers::Icmpv6(icmpv6)),
+        );
 
-        self.send_ipv4_with_transport(dst.ip().clone(), Layers::Udp(udp), Some(payload))
+        // Send
+        self.send_with_payload(&indicator, payload)
     }
 
-    fn send_ipv4_more_fragment(
+    /// Sends an ICMP communication administratively prohibited message from the given source IP
+    /// address, quoting the leading part of the original datagram.
+    pub fn send_icmp_administratively_prohibited(
         &mut self,
-        dst_ip_addr: Ipv4Addr,
-        t: LayerType,
-        fragment_offset: u16,
-        mut transport: Option<Layers>,
-        payload: &[u8],
+        src_ip_addr: Ipv4Addr,
+        datagram: &[u8],
     ) -> io::Result<()> {
-        // IPv4
-        let ipv4 = Ipv4::new_more_fragment(
-            *self.ipv4_identification_map.get(&dst_ip_addr).unwrap_or(&0),
-            t,
-            fragment_offset,
-            dst_ip_addr,
+        // ICMP
+        let icmp = Icmp::new_administratively_prohibited();
+
+        // Send
+        self.send_ipv4_with_transport(
+            src_ip_addr,
             self.src_ip_addr,
+            Layers::Icmp(icmp),
+            Some(datagram),
         )
-        .unwrap();
+    }
 
-        // Set IPv4 layer for checksum
-        if let Some(ref mut transport) = transport {
-            match transport {
-                Layers::Tcp(ref mut tcp) => tcp.set_ipv4_layer(&ipv4),
-                Layers::Udp(ref mut udp) => udp.set_ipv4_layer(&ipv4),
-                _ => {}
-            }
-        };
+    /// Sends an ICMP fragmentation needed message to the given IPv4 packet with the given datagram
+    /// data, reporting the given next-hop MTU.
+    pub fn send_icmp_fragmentation_needed(
+        &mut self,
+        src_ip_addr: Ipv4Addr,
+        original: &Ipv4,
+        data: &[u8],
+        next_hop_mtu: u16,
+    ) -> io::Result<()> {
+        // ICMP
+        let icmp = icmp::build_frag_needed(original, data, next_hop_mtu);
+        let datagram = icmp.payload().unwrap_or_default().to_vec();
 
         // Send
-        self.send_ethernet(Layers::Ipv4(ipv4), transport, Some(payload))
+        self.send_ipv4_with_transport(src_ip_addr, self.src_ip_addr, icmp, Some(&datagram))
     }
 
-    fn send_ipv4_last_fragment(
+    /// Sends UDP packets.
+    pub fn send_udp(
         &mut self,
-        dst_ip_addr: Ipv4Addr,
-        t: LayerType,
-        fragment_offset: u16,
+        dst: SocketAddrV4,
+        src: SocketAddrV4,
         payload: &[u8],
     ) -> io::Result<()> {
         // IPv4
-        let ipv4 = Ipv4::new_last_fragment(
-            *self.ipv4_identification_map.get(&dst_ip_addr).unwrap_or(&0),
-            t,
-            fragment_offset,
-            dst_ip_addr,
-            self.src_ip_addr,
+        let ipv4 = Ipv4::new(
+            *self.ipv4_identification_map.get(dst.ip()).unwrap_or(&0),
+            LayerTypes::Udp,
+            dst.ip().clone(),
+            *src.ip(),
         )
         .unwrap();
 
-        // Send
-        self.send_ethernet(Layers::Ipv4(ipv4), None, Some(payload))?;
+        // UDP
+        let mut udp = Udp::new(dst.port(), src.port());
+        udp.set_ipv4_layer(&ipv4);
+
+        let size = udp.get_size() + payload.len();
+        let mtu = self.get_path_mtu(self.src_ip_addr) as usize;
+        if ipv4.get_size() + size <= mtu {
+            return self.send_udp_raw(dst, src, payload);
+        }
+
+        // Serialize the whole datagram so the UDP length and checksum cover the entire payload
+        let mut datagram = vec![0u8; size];
+        udp.serialize_with_payload(&mut datagram, payload, size)?;
+
+        // Fragmentation
+        let frags = ipv4.fragment(&datagram, mtu);
+        if frags.is_empty() {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "MTU too small"));
+        }
+        for frag in frags {
+            let begin = frag.get_fragment_offset() as usize * 8;
+            let end = begin + frag.get_total_length() as usize - frag.get_size();
+
+            // Send
+            self.send_ethernet(Layers::Ipv4(frag), None, Some(&datagram[begin..end]))?;
+        }
 
         // Update IPv4 identification
-        self.increase_ipv4_identification(dst_ip_addr);
+        self.increase_ipv4_identification(dst.ip().clone());
 
         Ok(())
     }
 
+    fn send_udp_raw(
+        &mut self,
+        dst: SocketAddrV4,
+        src: SocketAddrV4,
+        payload: &[u8],
+    ) -> io::Result<()> {
+        // UDP
+        let udp = Udp::new(dst.port(), src.port());
+
+        self.send_ipv4_with_transport(*dst.ip(), *src.ip(), Layers::Udp(udp), Some(payload))
+    }
+
+    /// Sends the transport layer from the destination to the source in IPv4.
     fn send_ipv4_with_transport(
         &mut self,
         dst_ip_addr: Ipv4Addr,
+        src_ip_addr: Ipv4Addr,
         mut transport: Layers,
         payload: Option<&[u8]>,
     ) -> io::Result<()> {
@@ -685,7 +1527,7 @@ impl Forwarder {
             *self.ipv4_identification_map.get(&dst_ip_addr).unwrap_or(&0),
             transport.get_type(),
             dst_ip_addr,
-            self.src_ip_addr,
+            src_ip_addr,
         )
         .unwrap();
 
@@ -707,17 +1549,44 @@ impl Forwarder {
 
     fn send_ethernet(
         &mut self,
-        network: Layers,
-        transport: Option<Layers>,
+        mut network: Layers,
+        mut transport: Option<Layers>,
         payload: Option<&[u8]>,
     ) -> io::Result<()> {
+        // DSCP
+        if let Layers::Ipv4(ref mut ipv4) = network {
+            ipv4.set_dscp(self.get_ipv4_dscp(ipv4.get_src()));
+        }
+
+        // TTL
+        if let Layers::Ipv4(ref mut ipv4) = network {
+            ipv4.apply_ttl_mode(self.ttl_mode);
+        }
+
+        // Checksum offload
+        if self.checksum_offload {
+            if let Layers::Ipv4(ref mut ipv4) = network {
+                ipv4.set_checksum_offload(true);
+            }
+            match transport {
+                Some(Layers::Tcp(ref mut tcp)) => tcp.set_checksum_offload(true),
+                Some(Layers::Udp(ref mut udp)) => udp.set_checksum_offload(true),
+                _ => {}
+            }
+        }
+
         // Ethernet
-        let ethernet = Ethernet::new(
+        let dst_hardware_addr = match network {
+            Layers::Ipv4(ref ipv4) => self.get_dst_hardware_addr(ipv4.get_dst()),
+            _ => self.src_hardware_addr,
+        };
+        let mut ethernet = Ethernet::new(
             network.get_type(),
             self.local_hardware_addr,
-            self.src_hardware_addr,
+            dst_hardware_addr,
         )
         .unwrap();
+        ethernet.set_vlans(self.get_vlans(dst_hardware_addr).to_vec());
 
         // Indicator
         let indicator = Indicator::new(Layers::Ethernet(ethernet), Some(network), transport);
@@ -729,15 +1598,80 @@ impl Forwarder {
         }
     }
 
+    /// Get a buffer from the buffer pool if the pool can hold the given size, or `Some(None)` if
+    /// a buffer should be allocated instead. Returns `None` and counts the drop if the pool is
+    /// exhausted.
+    fn get_buffer(&self, size: usize) -> Option<Option<PooledBuffer>> {
+        if size > self.pool.get_size() {
+            return Some(None);
+        }
+
+        match self.pool.get() {
+            Some(buffer) => Some(Some(buffer)),
+            None => {
+                self.stats.add_dropped(DropReason::BufferExhausted, 1);
+                None
+            }
+        }
+    }
+
+    /// Injects the frames held by the injector of the forwarder, e.g., in an egress queue.
+    pub fn flush_injector(&mut self) -> io::Result<()> {
+        self.tx.flush()
+    }
+
+    /// Injects the given frame and dumps it. Frames of the flows translated by NAT64 are
+    /// translated back into IPv6 frames.
+    fn inject(&mut self, frame: &[u8]) -> io::Result<()> {
+        let translated = match self.nat64 {
+            Some(ref mut nat64) => match nat64.translate_ipv4(frame) {
+                Ok(translated) => translated,
+                Err(ref e) => {
+                    trace!("drop NAT64 frame: {}", e);
+                    return Ok(());
+                }
+            },
+            None => None,
+        };
+        let frame = translated.as_deref().unwrap_or(frame);
+
+        self.tx.inject(frame)?;
+        self.dump(frame);
+
+        Ok(())
+    }
+
+    fn dump(&mut self, frame: &[u8]) {
+        if let Some(ref mut writer) = self.writer {
+            if let Err(ref e) = writer.write(frame) {
+                warn!("dump: {}", e);
+            }
+        }
+    }
+
     fn send(&mut self, indicator: &Indicator) -> io::Result<()> {
         // Serialize
         let size = indicator.get_size();
         let buffer_size = max(size, MINIMUM_PACKET_SIZE);
-        let mut buffer = vec![0u8; buffer_size];
+        let mut pooled = match self.get_buffer(buffer_size) {
+            Some(pooled) => pooled,
+            None => {
+                trace!("drop frame because the buffer pool is exhausted");
+                return Ok(());
+            }
+        };
+        let mut allocated = Vec::new();
+        let buffer = match pooled {
+            Some(ref mut pooled) => &mut pooled[..buffer_size],
+            None => {
+                allocated.resize(buffer_size, 0);
+                &mut allocated[..]
+            }
+        };
         indicator.serialize(&mut buffer[..size])?;
 
         // Send
-        self.tx.send_to(&buffer, None).unwrap_or(Ok(()))?;
+        self.inject(buffer)?;
         debug!("send to pcap: {} ({} Bytes)", indicator.brief(), size);
 
         Ok(())
@@ -747,11 +1681,25 @@ impl Forwarder {
         // Serialize
         let size = indicator.get_size();
         let buffer_size = max(size + payload.len(), MINIMUM_PACKET_SIZE);
-        let mut buffer = vec![0u8; buffer_size];
+        let mut pooled = match self.get_buffer(buffer_size) {
+            Some(pooled) => pooled,
+            None => {
+                trace!("drop frame because the buffer pool is exhausted");
+                return Ok(());
+            }
+        };
+        let mut allocated = Vec::new();
+        let buffer = match pooled {
+            Some(ref mut pooled) => &mut pooled[..buffer_size],
+            None => {
+                allocated.resize(buffer_size, 0);
+                &mut allocated[..]
+            }
+        };
         indicator.serialize_with_payload(&mut buffer[..size + payload.len()], payload)?;
 
         // Send
-        self.tx.send_to(&buffer, None).unwrap_or(Ok(()))?;
+        self.inject(buffer)?;
         debug!(
             "send to pcap: {} ({} + {} Bytes)",
             indicator.brief(),
@@ -764,12 +1712,64 @@ impl Forwarder {
 }
 
 impl Forward for Forwarder {
-    fn forward_tcp(&mut self, dst: SocketAddrV4, src_port: u16, payload: &[u8]) -> io::Result<()> {
-        self.append_to_cache(dst, src_port, payload)
+    fn forward_tcp(
+        &mut self,
+        dst: SocketAddrV4,
+        src: SocketAddrV4,
+        payload: &[u8],
+    ) -> io::Result<()> {
+        let flow = self.get_flow_key(dst, src, IpNextHeaderProtocols::Tcp);
+        let payload = inspect::inspect(
+            self.inspection_hook.as_ref(),
+            &flow,
+            Direction::Down,
+            payload,
+        );
+
+        self.append_to_cache(dst, src, &payload)
+    }
+
+    fn open_tcp(&mut self, dst: SocketAddrV4, src: SocketAddrV4) -> io::Result<()> {
+        // Clean up
+        self.remove(dst, src);
+        self.tcp_cache2_map.remove(&(src, dst));
+
+        // Send SYN
+        self.send_tcp_syn(dst, src)
     }
 
-    fn forward_udp(&mut self, dst: SocketAddrV4, src_port: u16, payload: &[u8]) -> io::Result<()> {
-        self.send_udp(dst, src_port, payload)
+    fn forward_udp(
+        &mut self,
+        dst: SocketAddrV4,
+        src: SocketAddrV4,
+        payload: &[u8],
+    ) -> io::Result<()> {
+        let flow = self.get_flow_key(dst, src, IpNextHeaderProtocols::Udp);
+        let payload = inspect::inspect(
+            self.inspection_hook.as_ref(),
+            &flow,
+            Direction::Down,
+            payload,
+        );
+        let payload = payload.as_ref();
+
+        // Cache DNS responses, expired entries are purged at the same time
+        if dst.port() == DNS_PORT {
+            if let Some(ref mut dns_cache) = self.dns_cache {
+                dns_cache.purge();
+                if dns_cache.insert(payload) {
+                    trace!("cache DNS response from {}", dst);
+                }
+            }
+            if let Some(ref mut dns_names) = self.dns_names {
+                let n = dns_names.insert(payload);
+                if n > 0 {
+                    trace!("remember {} names from DNS response from {}", n, dst);
+                }
+            }
+        }
+
+        self.send_udp(dst, src, payload)
     }
 }
 
@@ -780,6 +1780,28 @@ const RETRANSMISSION_COOL_DOWN: u128 = 1000;
 
 /// Represents the max limit of UDP port for binding in local.
 const PORT_COUNT: usize = 64;
+/// Represents the time before an idle UDP association is released into the pool.
+const DATAGRAM_IDLE_TIMEOUT: Duration = Duration::from_secs(60);
+/// Represents the interval between 2 releases of idle UDP associations.
+const DATAGRAM_PURGE_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Represents the default time in seconds waiting for TCP connections to close on shutdown.
+const DEFAULT_GRACE_PERIOD: u64 = 5;
+
+/// Represents the interval between 2 purges of the ARP cache.
+const ARP_CACHE_PURGE_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Represents the default capacity of the TCP connection table.
+const DEFAULT_CONNECTION_TABLE_CAPACITY: usize = 4096;
+/// Represents the default time in seconds before an idle TCP connection expires.
+const DEFAULT_CONNECTION_IDLE_TIMEOUT: u64 = 300;
+/// Represents the default time in seconds before a half-open TCP connection expires.
+const DEFAULT_CONNECTION_HALF_OPEN_TIMEOUT: u64 = 30;
+/// Represents the interval between 2 purges of the TCP connection table.
+const CONNECTION_TABLE_PURGE_INTERVAL: Duration = Duration::from_secs(10);
+/// Represents the interval between 2 updates of the metrics.
+#[cfg(feature = "metrics")]
+const METRICS_UPDATE_INTERVAL: Duration = Duration::from_secs(1);
 
 /// Represents the channel redirect traffic to the proxy of SOCKS or loopback to the source in pcap.
 pub struct Redirector {
@@ -787,19 +1809,175 @@ pub struct Redirector {
     is_tx_src_hardware_addr_set: bool,
     src_ip_addr: Ipv4Addr,
     local_ip_addr: Option<Ipv4Addr>,
-    remote: SocketAddrV4,
-    streams: HashMap<(u16, SocketAddrV4), StreamWorker>,
-    tcp_sequence_map: HashMap<(u16, SocketAddrV4), u32>,
-    tcp_acknowledgement_map: HashMap<(u16, SocketAddrV4), u32>,
-    tcp_duplicate_map: HashMap<(u16, SocketAddrV4), usize>,
-    tcp_last_retransmission_map: HashMap<(u16, SocketAddrV4), Instant>,
-    tcp_cache_map: HashMap<(u16, SocketAddrV4), RandomCacher>,
+    remotes: SocksPool,
+    auth: SocksAuth,
+    streams: HashMap<(SocketAddrV4, SocketAddrV4), StreamWorker>,
+    tcp_sequence_map: HashMap<(SocketAddrV4, SocketAddrV4), u32>,
+    tcp_acknowledgement_map: HashMap<(SocketAddrV4, SocketAddrV4), u32>,
+    tcp_duplicate_map: HashMap<(SocketAddrV4, SocketAddrV4), usize>,
+    tcp_last_retransmission_map: HashMap<(SocketAddrV4, SocketAddrV4), Instant>,
+    tcp_cache_map: HashMap<(SocketAddrV4, SocketAddrV4), ReorderBuffer>,
+    tcp_window_map: HashMap<(SocketAddrV4, SocketAddrV4), ReceiveWindow>,
     datagrams: HashMap<u16, DatagramWorker>,
-    /// Represents the map mapping a source port to a local port.
-    datagram_map: Vec<u16>,
-    /// Represents the LRU mapping a local port to a source port.
-    udp_lru: LruCache<u16, u16>,
+    /// Represents the map mapping a source to a local port.
+    datagram_map: HashMap<SocketAddrV4, u16>,
+    /// Represents the LRU mapping a local port to a source.
+    udp_lru: LruCache<u16, SocketAddrV4>,
+    udp_duplicate_guard: Option<DuplicateGuard>,
+    datagram_pool: DatagramPool,
+    datagrams_last_purge: Instant,
     defrag: Defraggler,
+    defrag6: Ipv6Defraggler,
+    arp_cache_last_purge: Instant,
+    /// Represents the interval of announcing the local IP address with gratuitous ARPs.
+    gratuitous_arp_interval: Option<Duration>,
+    gratuitous_arp_last_sent: Option<Instant>,
+    stats: Arc<Stats>,
+    shutdown: Option<Shutdown>,
+    grace_period: Duration,
+    shutdown_deadline: Option<Instant>,
+    /// Represents the TCP connections closing during the shutdown.
+    closing: HashMap<(SocketAddrV4, SocketAddrV4), Connection>,
+    connections: ConnectionTable<(SocketAddrV4, SocketAddrV4)>,
+    connections_last_purge: Instant,
+    /// Represents the max number of TCP connections and the policy of rejecting new connections
+    /// over it.
+    connection_limit: Option<(usize, ConnectionLimitPolicy)>,
+    limiter: Option<TokenBucket>,
+    dry_run: bool,
+    /// Represents the link type of frames received, which selects the parser of frames.
+    link_type: LinkType,
+    enforce_mtu: bool,
+    routes: RouteTable,
+    /// Represents if UDP to a direct route has been warned of, which is forwarded through the
+    /// proxy.
+    is_udp_direct_warned: bool,
+    connect_options: ConnectOptions,
+    urgent_pointer: UrgentPointer,
+    loopback_guard: LoopbackGuard,
+    fast_open_cookies: FastOpenCookies,
+    filter: Option<Box<dyn Filter>>,
+    inspection_hook: Option<Arc<dyn InspectionHook>>,
+    nat64: bool,
+    active_ftp: bool,
+    /// Represents the TCP streams of the inbound connections accepted by SOCKS BIND commands,
+    /// which are opened to the source and wait for the handshake. A stream is absent until the
+    /// inbound connection is accepted.
+    inbound_streams: Arc<Mutex<InboundStreams>>,
+    #[cfg(feature = "metrics")]
+    metrics: Option<Arc<Metrics>>,
+    #[cfg(feature = "metrics")]
+    metrics_last_update: Instant,
+}
+
+type InboundStreams = HashMap<(SocketAddrV4, SocketAddrV4), Option<(StreamWorker, Instant)>>;
+
+/// Get the number of bytes occupied in the buffers of a TCP connection, including the cache of
+/// the received data and the data buffered to the SOCKS5 proxy.
+fn get_tcp_occupied(cache: Option<&ReorderBuffer>, stream: Option<&StreamWorker>) -> usize {
+    let cached = cache.map_or(0, |cache| cache.len());
+    let pending = stream.map_or(0, |stream| stream.get_pending_size());
+
+    cached + pending
+}
+
+/// Opens a listener of the data connection of active-mode FTP from the given server through the
+/// given proxies in order.
+async fn listen_ftp_data(
+    remotes: &[SocketAddrV4],
+    options: &ConnectOptions,
+    auth: &SocksAuth,
+    server: Ipv4Addr,
+) -> io::Result<SocksListener> {
+    let mut last_error = None;
+    for remote in remotes {
+        match socks::listen(
+            *remote,
+            options,
+            &Address::from(server),
+            FTP_DATA_PORT,
+            auth,
+        )
+        .await
+        {
+            Ok(listener) => return Ok(listener),
+            Err(e) => {
+                warn!("listen {} via {}: {}", server, remote, e);
+                last_error = Some(e);
+            }
+        }
+    }
+
+    Err(last_error.unwrap_or_else(|| io::Error::from(io::ErrorKind::NotConnected)))
+}
+
+/// Bridges the data connection of active-mode FTP advertised in the given payload of a control
+/// connection through the given proxies, and returns the payload advertising the address the
+/// proxy listens on instead. Returns `None` if no data connection is advertised or bridged.
+async fn bridge_ftp_data(
+    tx: Arc<Mutex<Forwarder>>,
+    inbound_streams: Arc<Mutex<InboundStreams>>,
+    remotes: &[SocketAddrV4],
+    options: &ConnectOptions,
+    auth: &SocksAuth,
+    key: (SocketAddrV4, SocketAddrV4),
+    payload: &[u8],
+) -> Option<Vec<u8>> {
+    let (src, dst) = key;
+    let port = DataPort::find(payload)?;
+    // Only the source can be connected, or the proxy would relay FTP bounce attacks
+    if port.get_addr().ip() != src.ip() {
+        debug!(
+            target: TCP_LOG_TARGET,
+            "ignore FTP data connection to {} of {} -> {}",
+            port.get_addr(),
+            src,
+            dst
+        );
+        return None;
+    }
+
+    let listener = match listen_ftp_data(remotes, options, auth, *dst.ip()).await {
+        Ok(listener) => listener,
+        Err(ref e) => {
+            warn!(
+                "bridge FTP data connection {} -> {}: {}",
+                dst.ip(),
+                port.get_addr(),
+                e
+            );
+            return None;
+        }
+    };
+    let bound = listener.get_bound_addr();
+
+    // The data connection is from port 20 of the server
+    let key = (port.get_addr(), SocketAddrV4::new(*dst.ip(), FTP_DATA_PORT));
+    debug!(
+        target: TCP_LOG_TARGET,
+        "bridge FTP data connection {} -> {} via {}", key.1, key.0, bound
+    );
+    inbound_streams.lock().unwrap().insert(key, None);
+    tokio::spawn(async move {
+        match StreamWorker::accept(tx, key.0, key.1, listener, DEFAULT_BIND_TIMEOUT).await {
+            Ok((stream, peer)) => {
+                debug!(
+                    target: TCP_LOG_TARGET,
+                    "accept FTP data connection {} -> {} from {}", key.1, key.0, peer
+                );
+                inbound_streams
+                    .lock()
+                    .unwrap()
+                    .insert(key, Some((stream, Instant::now())));
+            }
+            Err(ref e) => {
+                warn!("accept FTP data connection {} -> {}: {}", key.1, key.0, e);
+                inbound_streams.lock().unwrap().remove(&key);
+            }
+        }
+    });
+
+    Some(port.rewrite(payload, bound))
 }
 
 impl Redirector {
@@ -810,670 +1988,4845 @@ impl Redirector {
         local_ip_addr: Option<Ipv4Addr>,
         remote: SocketAddrV4,
     ) -> Redirector {
-        let redirector = Redirector {
+        let mut redirector = Redirector {
             tx,
             is_tx_src_hardware_addr_set: false,
             src_ip_addr,
             local_ip_addr,
-            remote,
+            remotes: SocksPool::new(remote, SelectMode::Failover, DEFAULT_SOCKS_POOL_COOLDOWN),
+            auth: SocksAuth::None,
             streams: HashMap::new(),
             tcp_sequence_map: HashMap::new(),
             tcp_acknowledgement_map: HashMap::new(),
             tcp_duplicate_map: HashMap::new(),
             tcp_last_retransmission_map: HashMap::new(),
             tcp_cache_map: HashMap::new(),
+            tcp_window_map: HashMap::new(),
             datagrams: HashMap::new(),
-            datagram_map: vec![0u16; u16::MAX as usize],
+            datagram_map: HashMap::new(),
             udp_lru: LruCache::new(PORT_COUNT),
+            udp_duplicate_guard: None,
+            datagram_pool: DatagramPool::new(
+                DEFAULT_DATAGRAM_POOL_CAPACITY,
+                DEFAULT_DATAGRAM_POOL_IDLE_TIMEOUT,
+            ),
+            datagrams_last_purge: Instant::now(),
             defrag: Defraggler::new(),
+            defrag6: Ipv6Defraggler::new(),
+            arp_cache_last_purge: Instant::now(),
+            gratuitous_arp_interval: None,
+            gratuitous_arp_last_sent: None,
+            stats: Arc::new(Stats::new()),
+            shutdown: None,
+            grace_period: Duration::from_secs(DEFAULT_GRACE_PERIOD),
+            shutdown_deadline: None,
+            closing: HashMap::new(),
+            connections: ConnectionTable::new(
+                DEFAULT_CONNECTION_TABLE_CAPACITY,
+                Duration::from_secs(DEFAULT_CONNECTION_IDLE_TIMEOUT),
+            ),
+            connections_last_purge: Instant::now(),
+            connection_limit: None,
+            limiter: None,
+            dry_run: false,
+            link_type: LinkType::Ethernet,
+            enforce_mtu: false,
+            routes: RouteTable::default(),
+            is_udp_direct_warned: false,
+            connect_options: ConnectOptions::default(),
+            urgent_pointer: UrgentPointer::default(),
+            loopback_guard: LoopbackGuard::new(&[IpAddr::V4(*remote.ip())]),
+            fast_open_cookies: FastOpenCookies::new(),
+            filter: None,
+            inspection_hook: None,
+            nat64: false,
+            active_ftp: false,
+            inbound_streams: Arc::new(Mutex::new(HashMap::new())),
+            #[cfg(feature = "metrics")]
+            metrics: None,
+            #[cfg(feature = "metrics")]
+            metrics_last_update: Instant::now(),
         };
-        if let Some(local_ip_addr) = local_ip_addr {
-            redirector
-                .tx
-                .lock()
-                .unwrap()
-                .set_local_ip_addr(local_ip_addr);
+        redirector
+            .connections
+            .set_half_open_timeout(Duration::from_secs(DEFAULT_CONNECTION_HALF_OPEN_TIMEOUT));
+        redirector
+            .connections
+            .set_latency_histogram(Some(redirector.stats.get_latency()));
+        {
+            let mut tx = redirector.tx.lock().unwrap();
+            tx.set_stats(Arc::clone(&redirector.stats));
+            if let Some(local_ip_addr) = local_ip_addr {
+                tx.set_local_ip_addr(local_ip_addr);
+            }
         }
 
         redirector
     }
 
-    /// Opens an `Interface` for redirect.
-    pub async fn open(&mut self, rx: &mut Receiver) -> io::Result<()> {
-        loop {
-            match rx.next() {
-                Ok(frame) => {
-                    if let Some(ref indicator) = Indicator::from(frame) {
-                        if let Some(t) = indicator.get_network_type() {
-                            match t {
-                                LayerTypes::Arp => {
-                                    if let Err(ref e) = self.handle_arp(indicator) {
-                                        warn!("handle {}: {}", indicator.brief(), e);
-                                    }
-                                }
-                                LayerTypes::Ipv4 => {
-                                    if let Err(ref e) = self.handle_ipv4(indicator, frame).await {
-                                        warn!("handle {}: {}", indicator.brief(), e);
-                                    }
-                                }
-                                _ => unreachable!(),
-                            }
-                        }
-                    };
-                }
-                Err(e) => {
-                    if e.kind() == io::ErrorKind::TimedOut {
-                        thread::sleep(Duration::from_millis(TIMEDOUT_WAIT));
-                        continue;
-                    }
-                    return Err(e);
-                }
-            };
-        }
+    /// Sets the capacity and the idle timeout of the TCP connection table. Idle connections are
+    /// reset when they expire, or when the table is full and a new connection arrives.
+    pub fn set_connection_table(&mut self, capacity: usize, idle_timeout: Duration) {
+        let half_open_timeout = self.connections.get_half_open_timeout();
+        self.connections = ConnectionTable::new(capacity, idle_timeout);
+        self.connections.set_half_open_timeout(half_open_timeout);
+        self.connections
+            .set_latency_histogram(Some(self.stats.get_latency()));
     }
 
-    fn handle_arp(&mut self, indicator: &Indicator) -> io::Result<()> {
-        if let Some(local_ip_addr) = self.local_ip_addr {
-            if let Some(arp) = indicator.get_arp() {
-                if arp.is_request_of(self.src_ip_addr, local_ip_addr) {
-                    debug!(
-                        "receive from pcap: {} ({} Bytes)",
-                        indicator.brief(),
-                        indicator.get_size()
-                    );
+    /// Sets the timeout of half-open TCP connections, whose handshakes are not completed. Expired
+    /// half-open connections are reset.
+    pub fn set_half_open_timeout(&mut self, timeout: Duration) {
+        self.connections.set_half_open_timeout(timeout);
+    }
 
-                    // Set forwarder's hardware address
-                    if !self.is_tx_src_hardware_addr_set {
-                        self.tx
-                            .lock()
-                            .unwrap()
-                            .set_src_hardware_addr(arp.get_src_hardware_addr());
-                        self.is_tx_src_hardware_addr_set = true;
-                    }
+    /// Sets the window in which identical UDP datagrams of the same flow are dropped as
+    /// duplicates, or `None` for forwarding all the datagrams.
+    pub fn set_udp_duplicate_window(&mut self, window: Option<Duration>) {
+        self.udp_duplicate_guard = window.map(DuplicateGuard::new);
+    }
 
-                    // Send
-                    self.tx.lock().unwrap().send_arp_reply()?
-                }
-            }
-        }
+    /// Sets the interval of keep-alive probes sent to the source in idle TCP connections, or
+    /// `None` for not probing. The probes keep the NAT mappings and the connections alive while
+    /// the source responds.
+    pub fn set_keepalive_interval(&mut self, interval: Option<Duration>) {
+        self.connections.set_keepalive_interval(interval);
+    }
 
-        Ok(())
+    /// Sets the max number of simultaneous TCP connections. New connections over the limit are
+    /// rejected according to the policy until connections close, and idle connections are not
+    /// evicted for them.
+    pub fn set_connection_limit(&mut self, max: usize, policy: ConnectionLimitPolicy) {
+        self.connection_limit = Some((max, policy));
     }
 
-    async fn handle_ipv4(&mut self, indicator: &Indicator, buffer: &[u8]) -> io::Result<()> {
-        if let Some(ref ipv4) = indicator.get_ipv4() {
-            let buffer_without_padding = &buffer
-                [..indicator.get_ethernet().unwrap().get_size() + ipv4.get_total_length() as usize];
-            if ipv4.get_src() == self.src_ip_addr {
-                debug!(
-                    "receive from pcap: {} ({} + {} Bytes)",
-                    indicator.brief(),
-                    indicator.get_size(),
-                    buffer_without_padding.len() - indicator.get_size()
-                );
-                // Set forwarder's hardware address
-                if !self.is_tx_src_hardware_addr_set {
-                    self.tx
-                        .lock()
-                        .unwrap()
-                        .set_src_hardware_addr(indicator.get_ethernet().unwrap().get_src());
-                    self.is_tx_src_hardware_addr_set = true;
-                }
+    /// Sets the rate limiter of traffic sent to the SOCKS5 proxy. The limiter may be shared
+    /// between redirectors.
+    pub fn set_limiter(&mut self, limiter: TokenBucket) {
+        self.limiter = Some(limiter);
+    }
 
-                if ipv4.is_fragment() {
-                    // Fragmentation
-                    let frag = match self.defrag.add(indicator, buffer_without_padding) {
-                        Some(frag) => frag,
-                        None => return Ok(()),
-                    };
-                    let (indicator, buffer_without_padding) = frag.concatenate();
+    /// Sets if the redirector runs in dry run mode, which logs the actions that would be taken
+    /// without sending anything or tracking any connection.
+    pub fn set_dry_run(&mut self, dry_run: bool) {
+        self.dry_run = dry_run;
+    }
 
-                    if let Some(t) = indicator.get_transport_type() {
-                        match t {
-                            LayerTypes::Tcp => {
-                                self.handle_tcp(&indicator, buffer_without_padding).await?
-                            }
-                            LayerTypes::Udp => {
-                                self.handle_udp(&indicator, buffer_without_padding).await?
-                            }
-                            _ => unreachable!(),
-                        }
-                    }
-                } else {
-                    if let Some(t) = indicator.get_transport_type() {
-                        match t {
-                            LayerTypes::Tcp => {
-                                self.handle_tcp(indicator, buffer_without_padding).await?
-                            }
-                            LayerTypes::Udp => {
-                                self.handle_udp(indicator, buffer_without_padding).await?
-                            }
-                            _ => unreachable!(),
-                        }
+    /// Sets the link type of frames received, e.g., the link type of the interface captured.
+    /// Frames sent are always Ethernet frames.
+    pub fn set_link_type(&mut self, link_type: LinkType) {
+        self.link_type = link_type;
+    }
+
+    /// Sets if packets exceeding the MTU with the don't fragment flag are replied with ICMP
+    /// fragmentation needed messages and dropped, so the source discovers the path MTU.
+    pub fn set_enforce_mtu(&mut self, enforce_mtu: bool) {
+        self.enforce_mtu = enforce_mtu;
+    }
+
+    /// Sets the routing table which decides if traffic to a destination is forwarded through the
+    /// SOCKS5 proxy, connected directly or dropped.
+    pub fn set_route_table(&mut self, routes: RouteTable) {
+        self.routes = routes;
+    }
+
+    /// Sets the filter of packets. Packets rejected by the filter are dropped without being
+    /// forwarded.
+    pub fn set_filter(&mut self, filter: Box<dyn Filter>) {
+        self.filter = Some(filter);
+    }
+
+    /// Sets if IPv6 flows to the destinations of the NAT64 well-known prefix 64:ff9b::/96 are
+    /// translated into IPv4 flows, so that IPv6-only sources reach IPv4 destinations through the
+    /// proxy. Each IPv6 source is mapped to a dedicated IPv4 address of the pool 100.64.0.0/24,
+    /// which never collides with the flows of the IPv4 source. IPv6 sources reach the gateway at
+    /// the link-local address of the local hardware address. Only TCP and UDP are translated.
+    pub fn set_nat64(&mut self, nat64: bool) {
+        self.nat64 = nat64;
+        let translator = match nat64 {
+            true => Some(Nat64::new(NAT64_WELL_KNOWN_PREFIX)),
+            false => None,
+        };
+        self.tx.lock().unwrap().set_nat64(translator);
+    }
+
+    /// Sets if the data connections of active-mode FTP are bridged. The address advertised in
+    /// `PORT` and `EPRT` commands of FTP control connections through the proxy is replaced by the
+    /// address the proxy listens on with a SOCKS BIND command, and the inbound connection is
+    /// opened to the source from port 20 of the server.
+    pub fn set_active_ftp(&mut self, active_ftp: bool) {
+        self.active_ftp = active_ftp;
+    }
+
+    /// Sets the hook inspecting the payloads forwarded in both directions. The payloads are
+    /// forwarded as the hook returns.
+    pub fn set_inspection_hook(&mut self, hook: Arc<dyn InspectionHook>) {
+        self.tx
+            .lock()
+            .unwrap()
+            .set_inspection_hook(Arc::clone(&hook));
+        self.inspection_hook = Some(hook);
+    }
+
+    fn get_route(&self, ip_addr: Ipv4Addr) -> Route {
+        self.routes.lookup(IpAddr::V4(ip_addr))
+    }
+
+    /// Waits for the rate limiter before sending the given number of bytes to the SOCKS5 proxy.
+    /// Returns if the bytes can be sent.
+    async fn wait_limiter(&self, n: usize) -> bool {
+        match self.limiter {
+            Some(ref limiter) => match limiter.acquire(n) {
+                Some(wait) => {
+                    if wait > Duration::from_secs(0) {
+                        time::delay_for(wait).await;
                     }
+                    true
                 }
-            }
+                None => false,
+            },
+            None => true,
         }
+    }
 
-        Ok(())
+    /// Sets the SOCKS5 proxies, which replace the proxy the redirector is created with. A proxy
+    /// which cannot be connected is skipped for a cooldown and the next one is attempted. Traffic
+    /// from or to the proxies is not redirected.
+    pub fn set_remotes(&mut self, remotes: SocksPool) {
+        for remote in remotes.get_remotes() {
+            self.loopback_guard.insert(IpAddr::V4(*remote.ip()));
+        }
+        self.remotes = remotes;
     }
 
-    async fn handle_tcp(&mut self, indicator: &Indicator, buffer: &[u8]) -> io::Result<()> {
-        if let Some(ref tcp) = indicator.get_tcp() {
-            if tcp.is_rst() {
-                self.handle_tcp_rst(indicator);
-            } else if tcp.is_ack() {
-                return self.handle_tcp_ack(indicator, buffer).await;
-            } else if tcp.is_syn() {
-                // Pure TCP SYN
-                return self.handle_tcp_syn(indicator).await;
-            } else if tcp.is_fin() {
-                // Pure TCP FIN
-                return self.handle_tcp_fin(indicator);
+    /// Sets the authentication of the SOCKS5 proxy.
+    pub fn set_auth(&mut self, auth: SocksAuth) {
+        self.auth = auth;
+    }
+
+    /// Sets the options of connecting to the SOCKS5 proxy, including the local IP address bound
+    /// to for egressing a specific interface on multi-homed hosts, and the timeout and retries.
+    pub fn set_connect_options(&mut self, options: ConnectOptions) {
+        self.connect_options = options;
+    }
+
+    /// Sets the interpretation of the urgent pointer of TCP segments received. The urgent data
+    /// is delivered inline to the proxy, in the same position of the stream.
+    pub fn set_urgent_pointer(&mut self, interpretation: UrgentPointer) {
+        self.urgent_pointer = interpretation;
+    }
+
+    /// Sets the interval of announcing the local IP address with gratuitous ARPs, or `None` for
+    /// announcing only once on startup. No announcement is sent without a local IP address.
+    pub fn set_gratuitous_arp_interval(&mut self, interval: Option<Duration>) {
+        self.gratuitous_arp_interval = interval;
+    }
+
+    /// Sets the metrics updated with the TCP connections tracked.
+    #[cfg(feature = "metrics")]
+    pub fn set_metrics(&mut self, metrics: Arc<Metrics>) {
+        self.metrics = Some(metrics);
+    }
+
+    /// Sets the shutdown coordinator of the redirector, and the grace period waiting for TCP
+    /// connections to close on shutdown.
+    pub fn set_shutdown(&mut self, shutdown: Shutdown, grace_period: Duration) {
+        self.shutdown = Some(shutdown);
+        self.grace_period = grace_period;
+    }
+
+    fn is_shutting_down(&self) -> bool {
+        self.shutdown_deadline.is_some()
+    }
+
+    /// Starts the shutdown by flushing and closing the streams of all tracked TCP connections.
+    /// FIN is sent on a connection once the data cached for the source are all acknowledged.
+    async fn begin_shutdown(&mut self) {
+        let deadline = Instant::now() + self.grace_period;
+        self.shutdown_deadline = Some(deadline);
+
+        let keys: Vec<_> = self.streams.keys().cloned().collect();
+        for key in keys {
+            let (src, dst) = key;
+            let stream = self.streams.get_mut(&key).unwrap();
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            match time::timeout(remaining, stream.flush()).await {
+                Ok(Ok(_)) => {}
+                Ok(Err(ref e)) => warn!("shutdown: flush {} -> {}: {}", src, dst, e),
+                Err(_) => warn!("shutdown: flush {} -> {} timed out", src, dst),
+            }
+            stream.close();
+
+            let connection = {
+                let tx_locked = self.tx.lock().unwrap();
+                Connection::new_established(
+                    tx_locked.get_tcp_sequence(dst, src),
+                    tx_locked.get_tcp_acknowledgement(dst, src),
+                )
+            };
+            self.closing.insert(key, connection);
+            if let Err(ref e) = self.close_tcp_if_sent(key) {
+                warn!("shutdown: close {} -> {}: {}", src, dst, e);
+            }
+        }
+        debug!(
+            "shutdown: wait {} TCP connections to close",
+            self.closing.len()
+        );
+    }
+
+    /// Sends FIN on a TCP connection closing during the shutdown if the data cached for the
+    /// source are all acknowledged, or the FIN would take the sequence of the data. The remaining
+    /// data are sent otherwise.
+    fn close_tcp_if_sent(&mut self, key: (SocketAddrV4, SocketAddrV4)) -> io::Result<()> {
+        let (src, dst) = key;
+        let connection = match self.closing.get_mut(&key) {
+            Some(connection) => connection,
+            None => return Ok(()),
+        };
+        if !matches!(
+            connection.get_state(),
+            State::Established | State::CloseWait
+        ) {
+            return Ok(());
+        }
+
+        let mut tx_locked = self.tx.lock().unwrap();
+        if tx_locked.get_cache_size(dst, src) > 0 {
+            // Trigger sending remaining data
+            return tx_locked.send_tcp_ack(dst, src);
+        }
+
+        // Advance the sequence by the data sent since the connection is tracked
+        let sequence = tx_locked.get_tcp_sequence(dst, src);
+        connection.on_send(sequence.wrapping_sub(connection.get_sequence()) as usize);
+        for action in connection.close() {
+            if action == Action::SendAckFin {
+                tx_locked.set_tcp_acknowledgement(dst, src, connection.get_acknowledgement());
+                tx_locked.send_tcp_ack_fin(dst, src)?;
             }
         }
 
         Ok(())
     }
 
-    async fn handle_tcp_ack(&mut self, indicator: &Indicator, buffer: &[u8]) -> io::Result<()> {
+    /// Returns if the shutdown is completed, or the grace period is passed.
+    fn is_shutdown_completed(&self) -> bool {
+        match self.shutdown_deadline {
+            Some(deadline) => self.closing.is_empty() || Instant::now() > deadline,
+            None => false,
+        }
+    }
+
+    fn handle_tcp_shutdown(&mut self, indicator: &Indicator, buffer: &[u8]) -> io::Result<()> {
         if let Some(tcp) = indicator.get_tcp() {
+            let src = SocketAddrV4::new(tcp.get_src_ip_addr(), tcp.get_src());
             let dst = SocketAddrV4::new(tcp.get_dst_ip_addr(), tcp.get_dst());
-            let key = (tcp.get_src(), dst);
-            let is_exist = self.streams.get(&key).is_some();
-            let is_alive = match self.streams.get(&key) {
-                Some(ref stream) => !stream.is_closed(),
-                None => false,
-            };
+            let key = (src, dst);
 
-            if is_exist {
-                if is_alive {
-                    // ACK
-                    self.update_tcp_sequence(indicator);
-                    self.update_tcp_acknowledgement(indicator);
-                    {
+            let connection = match self.closing.get_mut(&key) {
+                Some(connection) => connection,
+                None => {
+                    // Stop accepting new connections
+                    if !tcp.is_rst() {
                         let mut tx_locked = self.tx.lock().unwrap();
-                        tx_locked.invalidate_cache_to(
+                        tx_locked.set_tcp_acknowledgement(
                             dst,
-                            tcp.get_src(),
-                            tcp.get_acknowledgement(),
+                            src,
+                            tcp.get_sequence().checked_add(1).unwrap_or(0),
                         );
-                        tx_locked.set_tcp_send_window(dst, tcp.get_src(), tcp.get_window());
+                        tx_locked.send_tcp_ack_rst(dst, src)?;
+                        tx_locked.remove(dst, src);
                     }
+                    return Ok(());
+                }
+            };
 
-                    let cache = self
-                        .tcp_cache_map
-                        .entry(key)
-                        .or_insert_with(|| RandomCacher::new(tcp.get_sequence()));
-                    let stream = self.streams.get_mut(&key).unwrap();
-                    if buffer.len() > indicator.get_size() {
-                        // ACK
-                        // Append to cache
-                        let payload =
-                            cache.append(tcp.get_sequence(), &buffer[indicator.get_size()..])?;
+            // Release the data acknowledged by the source before FIN is sent
+            let is_fin_pending = matches!(
+                connection.get_state(),
+                State::Established | State::CloseWait
+            );
+            if is_fin_pending && tcp.is_ack() {
+                let mut tx_locked = self.tx.lock().unwrap();
+                tx_locked.invalidate_cache_to(dst, src, tcp.get_acknowledgement());
+                tx_locked.set_tcp_send_window(dst, src, tcp.get_window());
+            }
 
-                        match payload {
-                            Some(payload) => {
-                                // Send
-                                match stream.send(payload.as_slice()).await {
-                                    Ok(_) => {
-                                        // Update window size
-                                        let mut tx_locked = self.tx.lock().unwrap();
-                                        tx_locked.set_tcp_window(
-                                            dst,
-                                            tcp.get_src(),
-                                            cache.get_remaining_size(),
-                                        );
+            let actions = connection.on_segment_with_payload(tcp, &buffer[indicator.get_size()..]);
+            let acknowledgement = connection.get_acknowledgement();
+            let mut is_closed = false;
+            let mut tx_locked = self.tx.lock().unwrap();
+            for action in actions {
+                match action {
+                    Action::SendAck => {
+                        tx_locked.set_tcp_acknowledgement(dst, src, acknowledgement);
+                        tx_locked.send_tcp_ack_0(dst, src)?;
+                    }
+                    Action::SendAckFin => tx_locked.send_tcp_ack_fin(dst, src)?,
+                    Action::SendRst => tx_locked.send_tcp_rst(dst, src)?,
+                    Action::Close => is_closed = true,
+                    // The proxy is closed, the payload is dropped
+                    Action::SendAckSyn | Action::Deliver(_, _) => {}
+                }
+            }
+            if is_closed {
+                tx_locked.remove(dst, src);
+                drop(tx_locked);
+                self.closing.remove(&key);
+                self.remove(indicator);
+                trace!(target: TCP_LOG_TARGET, "shutdown: {} -> {} closed", tcp.get_src(), dst);
+            } else if is_fin_pending {
+                drop(tx_locked);
+                self.close_tcp_if_sent(key)?;
+            }
+        }
 
-                                        // Update TCP acknowledgement
-                                        tx_locked.add_tcp_acknowledgement(
-                                            dst,
-                                            tcp.get_src(),
-                                            payload.len() as u32,
-                                        );
+        Ok(())
+    }
 
-                                        // Send ACK0
-                                        // If there is a heavy traffic, the ACK reported may be inaccurate, which would results in retransmission
-                                        tx_locked.send_tcp_ack_0(dst, tcp.get_src())?;
-                                    }
-                                    Err(e) => {
-                                        // Clean up
-                                        self.remove(indicator);
+    /// Get the statistics of the redirector.
+    pub fn get_stats(&self) -> Arc<Stats> {
+        Arc::clone(&self.stats)
+    }
 
-                                        // Send ACK/RST
-                                        let mut tx_locked = self.tx.lock().unwrap();
-                                        tx_locked.send_tcp_ack_rst(dst, tcp.get_src())?;
+    /// Returns a snapshot of the bytes and packets transferred in each TCP connection tracked.
+    pub fn flows(&self) -> Vec<FlowStat<(SocketAddrV4, SocketAddrV4)>> {
+        self.connections.flows()
+    }
 
-                                        // Clean up
-                                        tx_locked.remove(dst, tcp.get_src());
+    fn add_seen(&self, indicator: &Indicator) {
+        self.stats.add_seen(indicator.get_link().get_type());
+        if let Some(t) = indicator.get_network_type() {
+            self.stats.add_seen(t);
+        }
+        if let Some(t) = indicator.get_transport_type() {
+            self.stats.add_seen(t);
+        }
+    }
 
-                                        return Err(e);
-                                    }
-                                }
-                            }
-                            None => {
-                                // Retransmission or unordered
-                                // Update window size
-                                let mut tx_locked = self.tx.lock().unwrap();
-                                tx_locked.set_tcp_window(
-                                    dst,
-                                    tcp.get_src(),
-                                    cache.get_remaining_size(),
-                                );
+    fn add_forwarded(&self, indicator: &Indicator) {
+        if let Some(t) = indicator.get_network_type() {
+            self.stats.add_forwarded(t);
+        }
+        if let Some(t) = indicator.get_transport_type() {
+            self.stats.add_forwarded(t);
+        }
+    }
 
-                                // Send ACK0
-                                tx_locked.send_tcp_ack_0(dst, tcp.get_src())?;
-                            }
-                        }
-                    } else {
-                        // ACK0
-                        if *self.tcp_duplicate_map.get(&key).unwrap_or(&0)
-                            >= DUPLICATES_BEFORE_FAST_RETRANSMISSION
-                        {
-                            if !tcp.is_zero_window() {
-                                let is_cooled_down =
-                                    match self.tcp_last_retransmission_map.get(&key) {
-                                        Some(ref instant) => {
-                                            instant.elapsed().as_millis() < RETRANSMISSION_COOL_DOWN
-                                        }
-                                        None => false,
-                                    };
-                                if !is_cooled_down {
-                                    if tcp.is_fin() {
-                                        // Because we cannot distinguish whether the server still have data to send,
-                                        // we wait for some continuos ACKs before sending an ACK/FIN to close.
-                                        // Expect all the data is handled by the server.
-                                        let mut tx_locked = self.tx.lock().unwrap();
-                                        // Check if all the data are sent
-                                        if tx_locked.get_cache_size(dst, tcp.get_src()) == 0 {
-                                            tx_locked.set_tcp_acknowledgement(
-                                                dst,
-                                                tcp.get_src(),
-                                                tcp.get_sequence().checked_add(1).unwrap_or(0),
-                                            );
-                                            // Send ACK/FIN
-                                            tx_locked.send_tcp_ack_fin(dst, tcp.get_src())?;
-                                        }
-                                    } else {
-                                        // Fast retransmit
-                                        // TODO: the procedure is in back N
-                                        self.tx
-                                            .lock()
-                                            .unwrap()
-                                            .resend_tcp_ack(dst, tcp.get_src())?;
+    /// Get the reason of a frame which cannot be parsed into a network layer.
+    fn get_drop_reason(frame: &[u8], link_type: LinkType) -> DropReason {
+        let link = match link_type {
+            LinkType::Ethernet => Ethernet::deserialize(frame)
+                .map(|(ethernet, size)| (ethernet.get_ethertype(), size)),
+            LinkType::LinuxSll => {
+                Sll::deserialize(frame).map(|(sll, size)| (sll.get_protocol(), size))
+            }
+        };
+        let (ethertype, size) = match link {
+            Ok(link) => link,
+            Err(_) => return DropReason::Malformed,
+        };
+        match ethertype {
+            EtherTypes::Arp => DropReason::Malformed,
+            EtherTypes::Ipv4 => match Ipv4::deserialize(&frame[size..]) {
+                Err(ParseError::ChecksumMismatch(_)) => DropReason::ChecksumMismatch,
+                _ => DropReason::Malformed,
+            },
+            // Unknown extension headers and chains too long are rejected
+            EtherTypes::Ipv6 => DropReason::Malformed,
+            _ => DropReason::Unsupported,
+        }
+    }
 
-                                        self.tcp_duplicate_map.insert(key, 0);
-                                        self.tcp_last_retransmission_map
-                                            .insert(key, Instant::now());
-                                    }
-                                }
-                            }
-                        }
-                    }
+    /// Opens an `Interface` for redirect.
+    pub async fn open(&mut self, rx: &mut Receiver) -> io::Result<()> {
+        loop {
+            if self.maintain().await? {
+                return Ok(());
+            }
 
-                    // FIN
-                    if tcp.is_fin() && cache.is_empty() {
-                        stream.finish();
+            match rx.next() {
+                Ok(frame) => self.handle_frame(frame).await,
+                Err(e) => {
+                    if e.kind() == io::ErrorKind::TimedOut {
+                        thread::sleep(Duration::from_millis(TIMEDOUT_WAIT));
+                        continue;
                     }
+                    return Err(e);
+                }
+            };
+        }
+    }
 
-                    // Trigger sending remaining data
-                    self.tx.lock().unwrap().send_tcp_ack(dst, tcp.get_src())?;
-                } else {
-                    // Expect in LAST_ACK state (or the stream met an error)
-                    if tcp.is_fin() {
-                        let mut tx_locked = self.tx.lock().unwrap();
-                        tx_locked.set_tcp_acknowledgement(
-                            dst,
-                            tcp.get_src(),
-                            tcp.get_sequence().checked_add(1).unwrap_or(0),
-                        );
-                        // Send ACK/FIN
-                        tx_locked.send_tcp_ack_fin(dst, tcp.get_src())?;
-                    } else {
-                        self.remove(indicator);
-                        self.tx.lock().unwrap().remove(dst, tcp.get_src());
+    /// Redirects frames from the given `CaptureStream` without blocking the runtime. The next
+    /// frame is not received until the current one is handled, including writing the payload to
+    /// the proxy.
+    #[cfg(feature = "async")]
+    pub async fn open_stream(&mut self, stream: &mut CaptureStream) -> io::Result<()> {
+        loop {
+            if self.maintain().await? {
+                return Ok(());
+            }
+
+            match stream.next().await {
+                Some(Ok(frame)) => self.handle_frame(&frame).await,
+                Some(Err(e)) => {
+                    if e.kind() == io::ErrorKind::TimedOut {
+                        time::delay_for(Duration::from_millis(TIMEDOUT_WAIT)).await;
+                        continue;
                     }
+                    return Err(e);
                 }
-            } else {
-                if tcp.is_fin() {
-                    // Though a RST is enough, reply with respect
-                    let mut tx_locked = self.tx.lock().unwrap();
-                    #[allow(deprecated)]
-                    tx_locked.set_tcp_sequence(dst, tcp.get_src(), tcp.get_acknowledgement());
-                    tx_locked.set_tcp_acknowledgement(
-                        dst,
-                        tcp.get_src(),
-                        tcp.get_sequence().checked_add(1).unwrap_or(0),
-                    );
-                    // Send ACK/FIN
-                    tx_locked.send_tcp_ack_fin(dst, tcp.get_src())?;
+                None => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::BrokenPipe,
+                        "capture is closed",
+                    ))
+                }
+            };
+        }
+    }
 
-                    // Clean up
-                    tx_locked.remove(dst, tcp.get_src());
-                } else {
-                    let mut tx_locked = self.tx.lock().unwrap();
-                    #[allow(deprecated)]
-                    tx_locked.set_tcp_sequence(dst, tcp.get_src(), tcp.get_acknowledgement());
-                    tx_locked.set_tcp_acknowledgement(
+    /// Redirects frames captured from multiple `Interface`s. Each frame from the channel is
+    /// tagged with the index of the redirector of its originating interface, so replies are sent
+    /// from the same interface.
+    pub async fn open_multiple(
+        redirectors: &mut [Redirector],
+        rx: &mpsc::Receiver<(usize, PooledBuffer)>,
+    ) -> io::Result<()> {
+        loop {
+            let mut is_completed = true;
+            for redirector in redirectors.iter_mut() {
+                is_completed = redirector.maintain().await? && is_completed;
+            }
+            if is_completed {
+                return Ok(());
+            }
+
+            match rx.recv_timeout(Duration::from_millis(TIMEDOUT_WAIT)) {
+                Ok((i, frame)) => match redirectors.get_mut(i) {
+                    Some(redirector) => redirector.handle_frame(&frame).await,
+                    None => warn!("receive from unknown interface {}", i),
+                },
+                Err(mpsc::RecvTimeoutError::Timeout) => continue,
+                Err(mpsc::RecvTimeoutError::Disconnected) => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::BrokenPipe,
+                        "all captures are closed",
+                    ))
+                }
+            }
+        }
+    }
+
+    /// Performs periodic tasks of the redirector, and returns if the redirector is shut down.
+    async fn maintain(&mut self) -> io::Result<bool> {
+        // Shutdown
+        if !self.is_shutting_down() {
+            let is_triggered = self
+                .shutdown
+                .as_ref()
+                .map_or(false, |shutdown| shutdown.is_triggered());
+            if is_triggered {
+                self.begin_shutdown().await;
+            }
+        }
+        if self.is_shutdown_completed() {
+            if !self.closing.is_empty() {
+                warn!(
+                    "shutdown: {} TCP connections are not closed in time",
+                    self.closing.len()
+                );
+                self.closing.clear();
+            }
+            return Ok(true);
+        }
+
+        // Drain egress queue
+        if let Err(ref e) = self.tx.lock().unwrap().flush_injector() {
+            warn!("flush injector: {}", e);
+        }
+
+        // Expire ARP cache
+        if self.arp_cache_last_purge.elapsed() > ARP_CACHE_PURGE_INTERVAL {
+            let count = self.tx.lock().unwrap().purge_arp_cache();
+            if count > 0 {
+                trace!("purge {} entries from ARP cache", count);
+            }
+            self.arp_cache_last_purge = Instant::now();
+        }
+
+        // Announce local IP address
+        if let Some(local_ip_addr) = self.local_ip_addr {
+            let is_due = match self.gratuitous_arp_last_sent {
+                Some(instant) => self
+                    .gratuitous_arp_interval
+                    .map_or(false, |interval| instant.elapsed() > interval),
+                None => !self.dry_run,
+            };
+            if is_due {
+                let mut tx = self.tx.lock().unwrap();
+                let local_hardware_addr = tx.get_local_hardware_addr();
+                match tx.send_gratuitous_arp(local_ip_addr, local_hardware_addr) {
+                    Ok(_) => trace!("announce {} -> {}", local_ip_addr, local_hardware_addr),
+                    Err(ref e) => warn!("announce {}: {}", local_ip_addr, e),
+                }
+                self.gratuitous_arp_last_sent = Some(Instant::now());
+            }
+        }
+
+        // Update metrics
+        #[cfg(feature = "metrics")]
+        {
+            if self.metrics_last_update.elapsed() > METRICS_UPDATE_INTERVAL {
+                if let Some(ref metrics) = self.metrics {
+                    metrics.update(&self.connections.flows());
+                }
+                self.metrics_last_update = Instant::now();
+            }
+        }
+
+        // Expire idle TCP connections
+        if self.connections_last_purge.elapsed() > CONNECTION_TABLE_PURGE_INTERVAL {
+            for key in self.connections.keepalive() {
+                trace!(target: TCP_LOG_TARGET, "keep alive {} -> {}", key.0, key.1);
+                if let Err(ref e) = self.tx.lock().unwrap().send_tcp_keepalive(key.1, key.0) {
+                    warn!("keep alive {} -> {}: {}", key.0, key.1, e);
+                }
+            }
+            let purged = self.connections.purge_half_open();
+            if !purged.is_empty() {
+                self.stats
+                    .add_dropped(DropReason::HalfOpenTimeout, purged.len() as u64);
+            }
+            for (key, _) in purged {
+                debug!(
+                    target: TCP_LOG_TARGET,
+                    "reset half-open TCP connection {} -> {}", key.0, key.1
+                );
+                if let Err(ref e) = self.reset(key) {
+                    warn!("reset {} -> {}: {}", key.0, key.1, e);
+                }
+            }
+            let timeout = self.connections.get_half_open_timeout();
+            let expired: Vec<_> = self
+                .inbound_streams
+                .lock()
+                .unwrap()
+                .iter()
+                .filter_map(|(key, stream)| match stream {
+                    Some((_, instant)) if instant.elapsed() >= timeout => Some(*key),
+                    _ => None,
+                })
+                .collect();
+            for key in expired {
+                debug!(
+                    target: TCP_LOG_TARGET,
+                    "close half-open inbound connection {} -> {}", key.1, key.0
+                );
+                let inbound_stream = self.inbound_streams.lock().unwrap().remove(&key);
+                if let Some(Some((mut stream, _))) = inbound_stream {
+                    stream.close();
+                }
+                self.tx.lock().unwrap().remove(key.1, key.0);
+                self.stats.add_dropped(DropReason::HalfOpenTimeout, 1);
+            }
+            let purged = self.connections.purge();
+            for (key, _) in purged {
+                debug!(target: TCP_LOG_TARGET, "reset idle TCP connection {} -> {}", key.0, key.1);
+                if let Err(ref e) = self.reset(key) {
+                    warn!("reset {} -> {}: {}", key.0, key.1, e);
+                }
+            }
+            self.connections_last_purge = Instant::now();
+        }
+
+        // Release idle UDP associations
+        if self.datagrams_last_purge.elapsed() > DATAGRAM_PURGE_INTERVAL {
+            let idle: Vec<_> = self
+                .datagrams
+                .iter()
+                .filter(|(_, worker)| worker.get_last_active().elapsed() > DATAGRAM_IDLE_TIMEOUT)
+                .map(|(port, _)| *port)
+                .collect();
+            for port in idle {
+                self.release_datagram(port);
+            }
+            let count = self.datagram_pool.purge();
+            if count > 0 {
+                trace!("recycle {} UDP associations from pool", count);
+            }
+            self.datagrams_last_purge = Instant::now();
+        }
+
+        Ok(false)
+    }
+
+    async fn handle_frame(&mut self, frame: &[u8]) {
+        if let Some(ref indicator) = Indicator::from_link_type(frame, self.link_type) {
+            self.add_seen(indicator);
+            if let Some(e) = indicator.get_error() {
+                self.stats.add_parse_error(e.get_type());
+            }
+            // Ignore the traffic of the proxy, or it will be redirected in a loop
+            let is_loopback = indicator.get_network().map_or(false, |network| {
+                !self.loopback_guard.accept(slice::from_ref(network))
+            });
+            if is_loopback {
+                trace!("ignore traffic of the proxy: {}", indicator.brief());
+                self.stats.add_dropped(DropReason::Loopback, 1);
+                return;
+            }
+            let is_martian = indicator.get_network().map_or(false, |network| {
+                !MartianGuard.accept(slice::from_ref(network))
+            });
+            if is_martian {
+                debug!("drop martian source: {}", indicator.brief());
+                self.stats.add_dropped(DropReason::Martian, 1);
+                return;
+            }
+            if let Some(ref filter) = self.filter {
+                if !filter.accept(&indicator.get_layers()) {
+                    trace!("filter {}", indicator.brief());
+                    self.stats.add_dropped(DropReason::Filtered, 1);
+                    return;
+                }
+            }
+            if self.dry_run {
+                self.log_action(indicator, frame);
+                return;
+            }
+            // Replies carry the VLAN tags of the source
+            if let Some(ethernet) = indicator.get_ethernet() {
+                self.tx
+                    .lock()
+                    .unwrap()
+                    .set_vlans(ethernet.get_src(), ethernet.get_vlans());
+            }
+            if let Some(t) = indicator.get_network_type() {
+                let result = match t {
+                    LayerTypes::Arp => self.handle_arp(indicator),
+                    LayerTypes::Ipv4 => self.handle_ipv4(indicator, frame).await,
+                    LayerTypes::Ipv6 => self.handle_ipv6(indicator, frame).await,
+                    _ => {
+                        // Unsupported network layers are logged and ignored
+                        debug!("ignore {}", indicator.brief());
+                        self.stats.add_dropped(DropReason::Unsupported, 1);
+                        return;
+                    }
+                };
+                match result {
+                    Ok(_) => self.add_forwarded(indicator),
+                    Err(ref e) => warn!("handle {}: {}", indicator.brief(), e),
+                }
+            } else {
+                self.stats
+                    .add_dropped(Redirector::get_drop_reason(frame, self.link_type), 1);
+            }
+        };
+    }
+
+    /// Returns if the given IP address is the source, or an address of the NAT64 pool mapped to
+    /// an IPv6 source.
+    fn is_source(&self, ip_addr: Ipv4Addr) -> bool {
+        ip_addr == self.src_ip_addr
+            || (self.nat64 && self.tx.lock().unwrap().is_nat64_addr(ip_addr))
+    }
+
+    /// Logs the action would be taken to the given frame in dry run mode.
+    fn log_action(&self, indicator: &Indicator, frame: &[u8]) {
+        if let Some(arp) = indicator.get_arp() {
+            if let Some(local_ip_addr) = self.local_ip_addr {
+                if arp.is_request_of(self.src_ip_addr, local_ip_addr) {
+                    info!("dry run: reply {}", indicator.brief());
+                    return;
+                }
+            }
+        } else if let Some(ipv4) = indicator.get_ipv4() {
+            if self.is_source(ipv4.get_src()) {
+                let route = self.get_route(ipv4.get_dst());
+                if route == Route::Drop && indicator.get_icmp().is_none() {
+                    info!("dry run: drop {}", indicator.brief());
+                    return;
+                }
+                let payload_length = frame.len().saturating_sub(indicator.get_size());
+                if let Some(tcp) = indicator.get_tcp() {
+                    let dst = SocketAddrV4::new(tcp.get_dst_ip_addr(), tcp.get_dst());
+                    if tcp.is_syn() && !tcp.is_ack() {
+                        let mss = self.tx.lock().unwrap().get_advertised_mss();
+                        let via = match route {
+                            Route::Direct => String::from("direct"),
+                            _ => self.remotes.peek().to_string(),
+                        };
+                        info!(
+                            "dry run: connect {} -> {} via {} (MSS = {})",
+                            tcp.get_src(),
+                            dst,
+                            via,
+                            mss
+                        );
+                    } else if payload_length > 0 {
+                        info!(
+                            "dry run: forward {} -> {} ({} Bytes) via {}",
+                            tcp.get_src(),
+                            dst,
+                            payload_length,
+                            self.remotes.peek()
+                        );
+                    } else {
+                        info!("dry run: handle {}", indicator.brief());
+                    }
+                    return;
+                }
+                if let Some(udp) = indicator.get_udp() {
+                    let dst = SocketAddrV4::new(udp.get_dst_ip_addr(), udp.get_dst());
+                    info!(
+                        "dry run: forward {} -> {} ({} Bytes) via {}",
+                        udp.get_src(),
                         dst,
-                        tcp.get_src(),
-                        tcp.get_sequence().checked_add(1).unwrap_or(0),
+                        payload_length,
+                        self.remotes.peek()
                     );
-                    // Send ACK/RST
-                    tx_locked.send_tcp_ack_rst(dst, tcp.get_src())?;
+                    return;
+                }
+                if let Some(icmp) = indicator.get_icmp() {
+                    if icmp.is_echo_request() {
+                        info!("dry run: reply {}", indicator.brief());
+                        return;
+                    }
+                }
+            }
+        }
 
-                    // Clean up
-                    tx_locked.remove(dst, tcp.get_src());
+        debug!("dry run: ignore {}", indicator.brief());
+    }
+
+    fn handle_arp(&mut self, indicator: &Indicator) -> io::Result<()> {
+        // Learn from ARP replies and gratuitous ARPs
+        if let Some(arp) = indicator.get_arp() {
+            if self.tx.lock().unwrap().update_arp_cache(arp) {
+                trace!(
+                    "update ARP cache: {} -> {}",
+                    arp.get_src(),
+                    arp.get_src_hardware_addr()
+                );
+            }
+        }
+
+        if let Some(local_ip_addr) = self.local_ip_addr {
+            if let Some(arp) = indicator.get_arp() {
+                if arp.is_request_of(self.src_ip_addr, local_ip_addr) {
+                    debug!(
+                        "receive from pcap: {} ({} Bytes)",
+                        indicator.brief(),
+                        indicator.get_size()
+                    );
+
+                    // Set forwarder's hardware address
+                    if !self.is_tx_src_hardware_addr_set {
+                        self.tx
+                            .lock()
+                            .unwrap()
+                            .set_src_hardware_addr(arp.get_src_hardware_addr());
+                        self.is_tx_src_hardware_addr_set = true;
+                    }
+
+                    // Send
+                    self.tx.lock().unwrap().send_arp_reply_to(arp)?
+                } else if arp.is_request() {
+                    // Ignore requests of other IP addresses, or the LAN will be poisoned
+                    trace!("ignore ARP request: {} -> {}", arp.get_src(), arp.get_dst());
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn handle_ipv4(&mut self, indicator: &Indicator, buffer: &[u8]) -> io::Result<()> {
+        if let Some(ref ipv4) = indicator.get_ipv4() {
+            // The frame may be truncated by the snaplen of the capture
+            let size = indicator.get_link().get_size() + ipv4.get_total_length() as usize;
+            if buffer.len() < size {
+                debug!(
+                    "drop truncated {} ({} of {} Bytes)",
+                    indicator.brief(),
+                    buffer.len(),
+                    size
+                );
+                self.stats.add_dropped(DropReason::Truncated, 1);
+                return Ok(());
+            }
+            let buffer_without_padding = &buffer[..size];
+            if self.is_source(ipv4.get_src()) {
+                debug!(
+                    "receive from pcap: {} ({} + {} Bytes)",
+                    indicator.brief(),
+                    indicator.get_size(),
+                    buffer_without_padding.len() - indicator.get_size()
+                );
+                // ICMP errors are only sent to the IPv4 source, frames translated by NAT64 are
+                // checked before the translation
+                let is_native = ipv4.get_src() == self.src_ip_addr;
+                // Set forwarder's hardware address
+                if !self.is_tx_src_hardware_addr_set && is_native {
+                    // Frames of links without hardware addresses are replied once an ARP arrives
+                    if let Some(hardware_addr) = indicator.get_src_hardware_addr() {
+                        self.tx.lock().unwrap().set_src_hardware_addr(hardware_addr);
+                        self.is_tx_src_hardware_addr_set = true;
+                    }
+                }
+
+                // Preserve DSCP
+                self.tx
+                    .lock()
+                    .unwrap()
+                    .set_ipv4_dscp(ipv4.get_dst(), ipv4.get_dscp());
+
+                // TTL, drop packets which would expire when forwarded by the gateway
+                if is_native && ipv4.get_ttl() <= 1 && Some(ipv4.get_dst()) != self.local_ip_addr {
+                    return self.handle_ttl_exceeded(indicator, buffer_without_padding);
+                }
+
+                // MTU, drop packets which cannot be forwarded without fragmentation
+                if is_native && self.enforce_mtu && ipv4.is_dont_fragment() {
+                    let mtu = self.tx.lock().unwrap().get_mtu();
+                    if ipv4.get_total_length() > mtu {
+                        return self.handle_fragmentation_needed(
+                            indicator,
+                            buffer_without_padding,
+                            mtu,
+                        );
+                    }
+                }
+
+                if ipv4.is_fragment() {
+                    // Fragmentation
+                    let frag = self.defrag.add(indicator, buffer_without_padding);
+                    let expired = self.defrag.take_expired();
+                    if expired > 0 {
+                        self.stats
+                            .add_dropped(DropReason::ReassemblyTimeout, expired as u64);
+                    }
+                    let frag = match frag {
+                        Some(frag) => frag,
+                        None => return Ok(()),
+                    };
+                    let (indicator, buffer_without_padding) = frag.concatenate();
+
+                    if let Some(t) = indicator.get_transport_type() {
+                        match t {
+                            LayerTypes::Tcp => {
+                                self.handle_tcp(&indicator, buffer_without_padding).await?
+                            }
+                            LayerTypes::Udp => {
+                                self.handle_udp(&indicator, buffer_without_padding).await?
+                            }
+                            LayerTypes::Icmp => {
+                                self.handle_icmp(&indicator, buffer_without_padding)?
+                            }
+                            LayerTypes::Igmp => self.handle_igmp(&indicator),
+                            _ => unreachable!(),
+                        }
+                    }
+                } else {
+                    if let Some(t) = indicator.get_transport_type() {
+                        match t {
+                            LayerTypes::Tcp => {
+                                self.handle_tcp(indicator, buffer_without_padding).await?
+                            }
+                            LayerTypes::Udp => {
+                                self.handle_udp(indicator, buffer_without_padding).await?
+                            }
+                            LayerTypes::Icmp => {
+                                self.handle_icmp(indicator, buffer_without_padding)?
+                            }
+                            LayerTypes::Igmp => self.handle_igmp(indicator),
+                            _ => unreachable!(),
+                        }
+                    } else {
+                        let reason = match ipv4.get_next_level_protocol() {
+                            IpNextHeaderProtocols::Tcp
+                            | IpNextHeaderProtocols::Udp
+                            | IpNextHeaderProtocols::Icmp
+                            | IpNextHeaderProtocols::Igmp => DropReason::Malformed,
+                            _ => DropReason::Unsupported,
+                        };
+                        self.stats.add_dropped(reason, 1);
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Handles an IPv6 frame. Fragments are reassembled before handled, and Neighbor Solicitations
+    /// of the link-local address of the gateway are answered. Only the flows translated by NAT64
+    /// are redirected, which are handled as IPv4 frames after the translation. Native IPv6 flows
+    /// are not proxied, because the flows are keyed by IPv4 socket addresses, and are dropped as
+    /// unsupported.
+    async fn handle_ipv6(&mut self, indicator: &Indicator, buffer: &[u8]) -> io::Result<()> {
+        if let Some(ipv6) = indicator.get_ipv6() {
+            // The frame may be truncated by the snaplen of the capture
+            let size = indicator.get_link().get_size()
+                + Ipv6Packet::minimum_packet_size()
+                + ipv6.get_payload_length() as usize;
+            if buffer.len() < size {
+                debug!(
+                    "drop truncated {} ({} of {} Bytes)",
+                    indicator.brief(),
+                    buffer.len(),
+                    size
+                );
+                self.stats.add_dropped(DropReason::Truncated, 1);
+                return Ok(());
+            }
+            let buffer_without_padding = &buffer[..size];
+
+            // Fragmentation
+            let begin = indicator.get_link().get_size();
+            let is_fragment = find_fragment_header(&buffer_without_padding[begin..])
+                .map_or(false, |frag| frag.is_some());
+            if is_fragment {
+                let frag = self.defrag6.add(&buffer_without_padding[begin..]);
+                let expired = self.defrag6.take_expired();
+                if expired > 0 {
+                    self.stats
+                        .add_dropped(DropReason::ReassemblyTimeout, expired as u64);
+                }
+                let packet = match frag {
+                    Ok(Some(packet)) => packet,
+                    Ok(None) => return Ok(()),
+                    Err(ref e) => {
+                        debug!("drop IPv6 fragment {}: {}", indicator.brief(), e);
+                        self.stats.add_dropped(DropReason::Malformed, 1);
+                        return Ok(());
+                    }
+                };
+
+                // Reassembled packets are handled in the original link layer header
+                let mut frame = buffer_without_padding[..begin].to_vec();
+                frame.extend_from_slice(&packet);
+
+                return match Indicator::from_link_type(&frame, self.link_type) {
+                    Some(ref indicator) => self.handle_ipv6_datagram(indicator, &frame).await,
+                    None => {
+                        self.stats.add_dropped(DropReason::Malformed, 1);
+                        Ok(())
+                    }
+                };
+            }
+
+            self.handle_ipv6_datagram(indicator, buffer_without_padding)
+                .await?;
+        }
+
+        Ok(())
+    }
+
+    /// Handles an unfragmented or reassembled IPv6 frame without padding.
+    async fn handle_ipv6_datagram(
+        &mut self,
+        indicator: &Indicator,
+        buffer: &[u8],
+    ) -> io::Result<()> {
+        if let Some(ipv6) = indicator.get_ipv6() {
+            // Neighbor Discovery
+            if let Some(icmpv6) = indicator.get_icmpv6() {
+                if icmpv6.is_neighbor_solicitation() {
+                    return self.handle_neighbor_solicitation(indicator);
+                }
+            }
+
+            // The extension headers are skipped to the upper-layer protocol
+            let protocol = ipv6.get_transport_protocol();
+            if ipv6.get_extensions_length() > 0 {
+                trace!(
+                    "skip {} Bytes of IPv6 extension headers to {}",
+                    ipv6.get_extensions_length(),
+                    protocol
+                );
+            }
+
+            // Translate IPv6 frames of NAT64 into IPv4 frames
+            let is_translatable =
+                protocol == IpNextHeaderProtocols::Tcp || protocol == IpNextHeaderProtocols::Udp;
+            // The translator only translates Ethernet frames
+            if self.nat64 && is_translatable && indicator.get_ethernet().is_some() {
+                // Hop limit, drop packets which would expire when forwarded by the gateway
+                let is_nat64_dst = self.tx.lock().unwrap().is_nat64_dst(ipv6.get_dst());
+                if is_nat64_dst && ipv6.get_hop_limit() <= 1 {
+                    return self.handle_hop_limit_exceeded(indicator, buffer);
+                }
+
+                let translated = match self.tx.lock().unwrap().translate_nat64(buffer) {
+                    Ok(translated) => translated,
+                    Err(ref e) => {
+                        debug!("drop NAT64 frame: {}", e);
+                        self.stats.add_dropped(DropReason::Unsupported, 1);
+                        return Ok(());
+                    }
+                };
+                if let Some(ref translated) = translated {
+                    return match Indicator::from(translated) {
+                        Some(ref indicator) => self.handle_ipv4(indicator, translated).await,
+                        None => Ok(()),
+                    };
                 }
             }
+
+            debug!("ignore {}", indicator.brief());
+            self.stats.add_dropped(DropReason::Unsupported, 1);
+        }
+
+        Ok(())
+    }
+
+    fn handle_neighbor_solicitation(&mut self, indicator: &Indicator) -> io::Result<()> {
+        if let (Some(ipv6), Some(icmpv6)) = (indicator.get_ipv6(), indicator.get_icmpv6()) {
+            // Neighbor Discovery messages are never forwarded by routers
+            if ipv6.get_hop_limit() != NDP_HOP_LIMIT {
+                trace!(
+                    "drop {} of hop limit {}",
+                    indicator.brief(),
+                    ipv6.get_hop_limit()
+                );
+                self.stats.add_dropped(DropReason::Malformed, 1);
+                return Ok(());
+            }
+
+            let mut tx_locked = self.tx.lock().unwrap();
+            let local_ip_addr = tx_locked.get_local_ipv6_addr();
+            if icmpv6.get_target_addr() == Some(local_ip_addr) {
+                debug!(
+                    "receive from pcap: {} ({} Bytes)",
+                    indicator.brief(),
+                    indicator.get_size()
+                );
+
+                // Send
+                let src_hardware_addr = match indicator.get_src_hardware_addr() {
+                    Some(hardware_addr) => hardware_addr,
+                    None => {
+                        trace!("ignore Neighbor Solicitation without hardware address");
+                        return Ok(());
+                    }
+                };
+                tx_locked.send_neighbor_advertisement_to(icmpv6, src_hardware_addr)?;
+            } else {
+                // Ignore solicitations of other IP addresses, or the LAN will be poisoned
+                trace!(
+                    "ignore Neighbor Solicitation: {} -> {:?}",
+                    icmpv6.src,
+                    icmpv6.get_target_addr()
+                );
+            }
+        }
+
+        Ok(())
+    }
+
+    fn handle_hop_limit_exceeded(
+        &mut self,
+        indicator: &Indicator,
+        buffer: &[u8],
+    ) -> io::Result<()> {
+        if let Some(ipv6) = indicator.get_ipv6() {
+            debug!("hop limit exceeded {}", indicator.brief());
+            self.stats.add_dropped(DropReason::TtlExceeded, 1);
+
+            let begin = indicator.get_link().get_size();
+            let dst_hardware_addr = match indicator.get_src_hardware_addr() {
+                Some(hardware_addr) => hardware_addr,
+                None => return Ok(()),
+            };
+
+            let mut tx_locked = self.tx.lock().unwrap();
+            let src_ip_addr = tx_locked.get_local_ipv6_addr();
+            tx_locked.send_icmpv6_time_exceeded(
+                src_ip_addr,
+                ipv6.get_src(),
+                dst_hardware_addr,
+                &buffer[begin..],
+            )?;
+        }
+
+        Ok(())
+    }
+
+    fn handle_fragmentation_needed(
+        &mut self,
+        indicator: &Indicator,
+        buffer: &[u8],
+        mtu: u16,
+    ) -> io::Result<()> {
+        if let Some(ipv4) = indicator.get_ipv4() {
+            debug!("fragmentation needed {}", indicator.brief());
+            self.stats.add_dropped(DropReason::FragmentationNeeded, 1);
+
+            // Never reply an ICMP error message
+            if let Some(icmp) = indicator.get_icmp() {
+                if icmp.is_error() {
+                    return Ok(());
+                }
+            }
+
+            let src_ip_addr = self.local_ip_addr.unwrap_or_else(|| ipv4.get_dst());
+            let begin = min(
+                indicator.get_link().get_size() + ipv4.get_size(),
+                buffer.len(),
+            );
+
+            self.tx.lock().unwrap().send_icmp_fragmentation_needed(
+                src_ip_addr,
+                ipv4,
+                &buffer[begin..],
+                mtu,
+            )?;
+        }
+
+        Ok(())
+    }
+
+    fn handle_igmp(&mut self, indicator: &Indicator) {
+        if let Some(igmp) = indicator.get_igmp() {
+            // Multicast is not routed by the gateway, the group memberships are never proxied
+            debug!("ignore {}", indicator.brief());
+            trace!(
+                "ignore IGMP {} of group {}",
+                igmp.get_igmp_type(),
+                igmp.get_group()
+            );
+            self.stats.add_dropped(DropReason::MulticastControl, 1);
+        }
+    }
+
+    fn handle_ttl_exceeded(&mut self, indicator: &Indicator, buffer: &[u8]) -> io::Result<()> {
+        if let Some(ipv4) = indicator.get_ipv4() {
+            debug!("TTL exceeded {}", indicator.brief());
+            self.stats.add_dropped(DropReason::TtlExceeded, 1);
+
+            // Only the first fragment is replied, and never reply an ICMP error message
+            if ipv4.get_fragment_offset() > 0 {
+                return Ok(());
+            }
+            if let Some(icmp) = indicator.get_icmp() {
+                if icmp.is_error() {
+                    return Ok(());
+                }
+            }
+
+            let begin = indicator.get_link().get_size();
+            let end = min(
+                begin + ipv4.get_size() + ORIGINAL_DATAGRAM_DATA_SIZE,
+                buffer.len(),
+            );
+            let src_ip_addr = self.local_ip_addr.unwrap_or_else(|| ipv4.get_dst());
+
+            self.tx
+                .lock()
+                .unwrap()
+                .send_icmp_time_exceeded(src_ip_addr, &buffer[begin..end])?;
+        }
+
+        Ok(())
+    }
+
+    async fn handle_tcp(&mut self, indicator: &Indicator, buffer: &[u8]) -> io::Result<()> {
+        if self.is_shutting_down() {
+            return self.handle_tcp_shutdown(indicator, buffer);
+        }
+
+        if let Some(ref tcp) = indicator.get_tcp() {
+            let src = SocketAddrV4::new(tcp.get_src_ip_addr(), tcp.get_src());
+            // Renew the connection
+            let dst = SocketAddrV4::new(tcp.get_dst_ip_addr(), tcp.get_dst());
+            self.connections.get(&(src, dst));
+
+            // Explicit congestion notification
+            let congestion_experienced = indicator
+                .get_ipv4()
+                .map_or(false, |ipv4| ipv4.is_congestion_experienced());
+            if tcp.is_cwr() || congestion_experienced {
+                self.tx.lock().unwrap().update_tcp_ecn_echo(
+                    dst,
+                    src,
+                    tcp.is_cwr(),
+                    congestion_experienced,
+                );
+            }
+
+            if tcp.is_rst() {
+                self.handle_tcp_rst(indicator);
+            } else if tcp.is_ack() {
+                return self.handle_tcp_ack(indicator, buffer).await;
+            } else if tcp.is_syn() {
+                // Pure TCP SYN
+                return self.handle_tcp_syn(indicator, buffer).await;
+            } else if tcp.is_fin() {
+                // Pure TCP FIN
+                return self.handle_tcp_fin(indicator).await;
+            } else {
+                // Segment without any flag
+                return self
+                    .tx
+                    .lock()
+                    .unwrap()
+                    .send_tcp_rst_to(tcp, buffer.len() - indicator.get_size());
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Writes the data buffered to the SOCKS5 proxy of a TCP connection without waiting, and
+    /// updates the window size. Returns if the window opens, in which case a window update should
+    /// be sent.
+    async fn flush_tcp(&mut self, key: (SocketAddrV4, SocketAddrV4)) -> io::Result<bool> {
+        if let Some(stream) = self.streams.get_mut(&key) {
+            if stream.get_pending_size() > 0 {
+                stream.try_flush().await?;
+            }
+        }
+
+        let previous = self
+            .tcp_window_map
+            .get(&key)
+            .map(|window| window.get_window());
+        let current = self.update_tcp_window(key);
+
+        Ok(previous.map_or(false, |previous| current as usize > previous))
+    }
+
+    /// Updates the window size of a TCP connection according to the occupancy of its cache and
+    /// the data buffered to the SOCKS5 proxy, and returns the window size.
+    fn update_tcp_window(&mut self, key: (SocketAddrV4, SocketAddrV4)) -> u16 {
+        let occupied = get_tcp_occupied(self.tcp_cache_map.get(&key), self.streams.get(&key));
+
+        let mut tx_locked = self.tx.lock().unwrap();
+        let mss = tx_locked.get_advertised_mss() as usize;
+        let window = self
+            .tcp_window_map
+            .entry(key)
+            .or_insert_with(|| ReceiveWindow::new(u16::MAX as usize, mss))
+            .update(occupied) as u16;
+        tx_locked.set_tcp_window(key.1, key.0, window);
+
+        window
+    }
+
+    async fn handle_tcp_ack(&mut self, indicator: &Indicator, buffer: &[u8]) -> io::Result<()> {
+        if let Some(tcp) = indicator.get_tcp() {
+            let src = SocketAddrV4::new(tcp.get_src_ip_addr(), tcp.get_src());
+            let dst = SocketAddrV4::new(tcp.get_dst_ip_addr(), tcp.get_dst());
+            let key = (src, dst);
+            let is_exist = self.streams.get(&key).is_some();
+            let is_alive = match self.streams.get(&key) {
+                Some(ref stream) => !stream.is_closed(),
+                None => false,
+            };
+
+            // Handshake of a connection opened to the source
+            if tcp.is_syn() && self.handle_tcp_ack_syn(indicator)? {
+                return Ok(());
+            }
+
+            if is_exist {
+                if is_alive {
+                    // Complete the handshake, or the connection would expire as half-open
+                    if let Some(connection) = self.connections.get(&key) {
+                        if connection.is_half_open() {
+                            *connection = Connection::new_established(
+                                tcp.get_acknowledgement(),
+                                tcp.get_sequence(),
+                            );
+                        }
+                    }
+
+                    // Timestamps
+                    if let Some((tsval, _)) = tcp.get_timestamps() {
+                        self.tx
+                            .lock()
+                            .unwrap()
+                            .update_tcp_timestamp(dst, src, tsval);
+                    }
+
+                    // Write the data buffered, the window may open
+                    let is_window_opened = self.flush_tcp(key).await?;
+
+                    // Keep-alive
+                    let payload_length = buffer.len() - indicator.get_size();
+                    if !tcp.is_fin()
+                        && state::is_keep_alive(
+                            tcp.get_sequence(),
+                            payload_length,
+                            self.tx.lock().unwrap().get_tcp_acknowledgement(dst, src),
+                        )
+                    {
+                        trace!(target: TCP_LOG_TARGET, "keep-alive {} -> {}", tcp.get_src(), dst);
+                        let mut tx_locked = self.tx.lock().unwrap();
+                        tx_locked.set_tcp_send_window(dst, src, tcp.get_window());
+                        // Send ACK0
+                        return tx_locked.send_tcp_ack_0(dst, src);
+                    }
+
+                    // ACK
+                    self.update_tcp_sequence(indicator);
+                    self.update_tcp_acknowledgement(indicator);
+                    {
+                        let mut tx_locked = self.tx.lock().unwrap();
+                        tx_locked.invalidate_cache_to(dst, src, tcp.get_acknowledgement());
+                        tx_locked.set_tcp_send_window(dst, src, tcp.get_window());
+                    }
+
+                    // Urgent data, which is delivered inline
+                    if let Some(end) = tcp.get_urgent_end(self.urgent_pointer) {
+                        trace!(
+                            target: TCP_LOG_TARGET,
+                            "urgent data {} -> {} until sequence {}",
+                            tcp.get_src(),
+                            dst,
+                            end
+                        );
+                    }
+
+                    // Rate limit, the dropped segment will be retransmitted
+                    if payload_length > 0 && !self.wait_limiter(payload_length).await {
+                        trace!(target: TCP_LOG_TARGET, "rate limit {} -> {}", tcp.get_src(), dst);
+                        return Ok(());
+                    }
+
+                    // Bridge the data connections of active-mode FTP through the proxy
+                    let is_ftp_bridged = self.active_ftp
+                        && dst.port() == FTP_PORT
+                        && self.get_route(*dst.ip()) == Route::Proxy;
+
+                    // Expect the sequence acknowledged, the first segment may be out of order
+                    let acknowledgement = self.tx.lock().unwrap().get_tcp_acknowledgement(dst, src);
+                    let cache = self
+                        .tcp_cache_map
+                        .entry(key)
+                        .or_insert_with(|| ReorderBuffer::new(acknowledgement, u16::MAX as usize));
+                    let stream = self.streams.get_mut(&key).unwrap();
+                    if buffer.len() > indicator.get_size() {
+                        // ACK
+                        // Reorder
+                        let reorder =
+                            cache.insert(tcp.get_sequence(), &buffer[indicator.get_size()..]);
+                        let payload = match reorder {
+                            Reorder::Accepted => cache.pop(),
+                            Reorder::Duplicate => None,
+                            Reorder::OutOfWindow => {
+                                trace!(
+                                    target: TCP_LOG_TARGET,
+                                    "out of window {} -> {}: {}",
+                                    tcp.get_src(),
+                                    dst,
+                                    tcp.get_sequence()
+                                );
+                                None
+                            }
+                        };
+
+                        match payload {
+                            Some(payload) => {
+                                // Inspect, the acknowledgement still covers the original payload
+                                let flow = FlowKey::new(
+                                    IpNextHeaderProtocols::Tcp,
+                                    SocketAddr::V4(SocketAddrV4::new(
+                                        tcp.get_src_ip_addr(),
+                                        tcp.get_src(),
+                                    )),
+                                    SocketAddr::V4(dst),
+                                );
+                                let data = inspect::inspect(
+                                    self.inspection_hook.as_ref(),
+                                    &flow,
+                                    Direction::Up,
+                                    payload.as_slice(),
+                                );
+                                let data = match is_ftp_bridged {
+                                    true => match bridge_ftp_data(
+                                        Arc::clone(&self.tx),
+                                        Arc::clone(&self.inbound_streams),
+                                        &self.remotes.select(),
+                                        &self.connect_options,
+                                        &self.auth,
+                                        key,
+                                        &data,
+                                    )
+                                    .await
+                                    {
+                                        Some(rewritten) => rewritten.into(),
+                                        None => data,
+                                    },
+                                    false => data,
+                                };
+
+                                // Send
+                                match stream.send(&data).await {
+                                    Ok(_) => {
+                                        // Count the payload delivered, retransmissions are not
+                                        // delivered again
+                                        let counters = self.connections.get_counters(&key);
+                                        if let Some(ref counters) = counters {
+                                            counters.add_up(payload.len(), 1);
+                                        }
+
+                                        // Update window size
+                                        let mut tx_locked = self.tx.lock().unwrap();
+                                        let mss = tx_locked.get_advertised_mss() as usize;
+                                        let window = self
+                                            .tcp_window_map
+                                            .entry(key)
+                                            .or_insert_with(|| {
+                                                ReceiveWindow::new(u16::MAX as usize, mss)
+                                            })
+                                            .update(get_tcp_occupied(Some(cache), Some(stream)));
+                                        tx_locked.set_tcp_window(dst, src, window as u16);
+
+                                        // Update TCP acknowledgement
+                                        tx_locked.add_tcp_acknowledgement(
+                                            dst,
+                                            src,
+                                            payload.len() as u32,
+                                        );
+                                        if let Some(counters) = counters {
+                                            counters.on_request(
+                                                tx_locked.get_tcp_acknowledgement(dst, src),
+                                            );
+                                        }
+
+                                        // Send ACK0
+                                        // If there is a heavy traffic, the ACK reported may be inaccurate, which would results in retransmission
+                                        tx_locked.send_tcp_ack_0(dst, src)?;
+                                    }
+                                    Err(e) => {
+                                        // Clean up
+                                        self.remove(indicator);
+
+                                        // Send ACK/RST
+                                        let mut tx_locked = self.tx.lock().unwrap();
+                                        tx_locked.send_tcp_ack_rst(dst, src)?;
+
+                                        // Clean up
+                                        tx_locked.remove(dst, src);
+
+                                        return Err(e);
+                                    }
+                                }
+                            }
+                            None => {
+                                // Retransmission or unordered
+                                let mut tx_locked = self.tx.lock().unwrap();
+                                let acknowledgement = tx_locked.get_tcp_acknowledgement(dst, src);
+                                if reorder == Reorder::Accepted
+                                    && state::sequence_lt(acknowledgement, tcp.get_sequence())
+                                {
+                                    // Report the range received out of order, bytes beyond the
+                                    // window are not buffered
+                                    let end = tcp
+                                        .get_sequence()
+                                        .wrapping_add((buffer.len() - indicator.get_size()) as u32);
+                                    let window_end = cache
+                                        .get_sequence()
+                                        .wrapping_add(cache.get_window() as u32);
+                                    let end = match state::sequence_lt(window_end, end) {
+                                        true => window_end,
+                                        false => end,
+                                    };
+                                    tx_locked.add_tcp_sack_block(dst, src, tcp.get_sequence(), end);
+                                }
+
+                                // Update window size
+                                let mss = tx_locked.get_advertised_mss() as usize;
+                                let window = self
+                                    .tcp_window_map
+                                    .entry(key)
+                                    .or_insert_with(|| ReceiveWindow::new(u16::MAX as usize, mss))
+                                    .update(get_tcp_occupied(Some(cache), Some(stream)));
+                                tx_locked.set_tcp_window(dst, src, window as u16);
+
+                                // Send ACK0
+                                tx_locked.send_tcp_ack_0(dst, src)?;
+                            }
+                        }
+                    } else {
+                        // Window update
+                        if is_window_opened {
+                            trace!(target: TCP_LOG_TARGET, "window update {} -> {}", tcp.get_src(), dst);
+                            self.tx.lock().unwrap().send_tcp_ack_0(dst, src)?;
+                        }
+
+                        // ACK0
+                        if *self.tcp_duplicate_map.get(&key).unwrap_or(&0)
+                            >= DUPLICATES_BEFORE_FAST_RETRANSMISSION
+                        {
+                            if !tcp.is_zero_window() {
+                                let is_cooled_down =
+                                    match self.tcp_last_retransmission_map.get(&key) {
+                                        Some(ref instant) => {
+                                            instant.elapsed().as_millis() < RETRANSMISSION_COOL_DOWN
+                                        }
+                                        None => false,
+                                    };
+                                if !is_cooled_down {
+                                    if tcp.is_fin() {
+                                        // Because we cannot distinguish whether the server still have data to send,
+                                        // we wait for some continuos ACKs before sending an ACK/FIN to close.
+                                        // Expect all the data is handled by the server.
+                                        let mut tx_locked = self.tx.lock().unwrap();
+                                        // Check if all the data are sent
+                                        if tx_locked.get_cache_size(dst, src) == 0 {
+                                            tx_locked.set_tcp_acknowledgement(
+                                                dst,
+                                                src,
+                                                tcp.get_sequence().checked_add(1).unwrap_or(0),
+                                            );
+                                            // Send ACK/FIN
+                                            tx_locked.send_tcp_ack_fin(dst, src)?;
+                                        }
+                                    } else {
+                                        // Fast retransmit
+                                        // TODO: the procedure is in back N
+                                        self.tx.lock().unwrap().resend_tcp_ack(dst, src)?;
+
+                                        self.tcp_duplicate_map.insert(key, 0);
+                                        self.tcp_last_retransmission_map
+                                            .insert(key, Instant::now());
+                                    }
+                                }
+                            }
+                        }
+                    }
+
+                    // FIN
+                    if tcp.is_fin() && cache.is_empty() {
+                        stream.flush().await?;
+                        stream.finish();
+                    }
+
+                    // Trigger sending remaining data
+                    self.tx.lock().unwrap().send_tcp_ack(dst, src)?;
+                } else {
+                    // Expect in LAST_ACK state (or the stream met an error)
+                    if tcp.is_fin() {
+                        let mut tx_locked = self.tx.lock().unwrap();
+                        tx_locked.set_tcp_acknowledgement(
+                            dst,
+                            src,
+                            tcp.get_sequence().checked_add(1).unwrap_or(0),
+                        );
+                        // Send ACK/FIN
+                        tx_locked.send_tcp_ack_fin(dst, src)?;
+                    } else {
+                        self.remove(indicator);
+                        self.tx.lock().unwrap().remove(dst, src);
+                    }
+                }
+            } else {
+                if tcp.is_fin() {
+                    // Though a RST is enough, reply with respect
+                    let mut tx_locked = self.tx.lock().unwrap();
+                    #[allow(deprecated)]
+                    tx_locked.set_tcp_sequence(dst, src, tcp.get_acknowledgement());
+                    tx_locked.set_tcp_acknowledgement(
+                        dst,
+                        src,
+                        tcp.get_sequence().checked_add(1).unwrap_or(0),
+                    );
+                    // Send ACK/FIN
+                    tx_locked.send_tcp_ack_fin(dst, src)?;
+
+                    // Clean up
+                    tx_locked.remove(dst, src);
+                } else {
+                    let mut tx_locked = self.tx.lock().unwrap();
+                    // Send RST
+                    tx_locked.send_tcp_rst_to(tcp, buffer.len() - indicator.get_size())?;
+
+                    // Clean up
+                    tx_locked.remove(dst, src);
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn handle_tcp_syn(&mut self, indicator: &Indicator, buffer: &[u8]) -> io::Result<()> {
+        if let Some(tcp) = indicator.get_tcp() {
+            let src = SocketAddrV4::new(tcp.get_src_ip_addr(), tcp.get_src());
+            let dst = SocketAddrV4::new(tcp.get_dst_ip_addr(), tcp.get_dst());
+            let key = (src, dst);
+            let is_exist = self.streams.get(&key).is_some();
+
+            // Connect if not connected, drop if established
+            if !is_exist {
+                // Clean up
+                self.remove(indicator);
+
+                let route = self.get_route(tcp.get_dst_ip_addr());
+                if route == Route::Drop {
+                    debug!(target: TCP_LOG_TARGET, "route {} -> {} to drop", tcp.get_src(), dst);
+                    self.stats.add_dropped(DropReason::Route, 1);
+
+                    let mut tx_locked = self.tx.lock().unwrap();
+                    tx_locked.set_tcp_acknowledgement(
+                        dst,
+                        src,
+                        tcp.get_sequence().checked_add(1).unwrap_or(0),
+                    );
+                    // Send ACK/RST
+                    tx_locked.send_tcp_ack_rst(dst, src)?;
+
+                    // Clean up
+                    tx_locked.remove(dst, src);
+
+                    return Ok(());
+                }
+
+                // Reject if the connection limit is reached
+                if let Some((max, policy)) = self.connection_limit {
+                    if !self.connections.contains(&key) && self.connections.len() >= max {
+                        debug!(
+                            target: TCP_LOG_TARGET,
+                            "reject TCP connection {} -> {}: {} connections reached",
+                            tcp.get_src(),
+                            dst,
+                            max
+                        );
+                        if policy == ConnectionLimitPolicy::Drop {
+                            self.stats.add_dropped(DropReason::ConnectionLimit, 1);
+                            return Ok(());
+                        }
+
+                        let mut tx_locked = self.tx.lock().unwrap();
+                        tx_locked.set_tcp_acknowledgement(
+                            dst,
+                            src,
+                            tcp.get_sequence().checked_add(1).unwrap_or(0),
+                        );
+                        // Send ACK/RST
+                        tx_locked.send_tcp_ack_rst(dst, src)?;
+
+                        // Clean up
+                        tx_locked.remove(dst, src);
+
+                        return Ok(());
+                    }
+                }
+
+                // Track the connection, evicts an idle connection if the table is full
+                match self.connections.insert(key, Connection::new(0)) {
+                    Ok(Some((evicted, _))) => {
+                        debug!(target: TCP_LOG_TARGET, "evict TCP connection {} -> {}", evicted.0, evicted.1);
+                        self.reset(evicted)?;
+                    }
+                    Ok(None) => {}
+                    Err(_) => {
+                        let mut tx_locked = self.tx.lock().unwrap();
+                        tx_locked.set_tcp_acknowledgement(
+                            dst,
+                            src,
+                            tcp.get_sequence().checked_add(1).unwrap_or(0),
+                        );
+                        // Send ACK/RST
+                        tx_locked.send_tcp_ack_rst(dst, src)?;
+
+                        // Clean up
+                        tx_locked.remove(dst, src);
+
+                        return Err(io::Error::new(
+                            io::ErrorKind::Other,
+                            "connection table full",
+                        ));
+                    }
+                }
+
+                self.tcp_sequence_map.insert(key, tcp.get_sequence());
+
+                // Fast open, the data in the SYN is accepted only with a valid cookie, or it will
+                // be retransmitted after the handshake
+                let cookie = tcp.get_fast_open_cookie();
+                let is_cookie_valid = cookie.map_or(false, |cookie| {
+                    self.fast_open_cookies
+                        .validate(tcp.get_src_ip_addr(), cookie)
+                });
+                let mut fast_open_data = match is_cookie_valid {
+                    true => &buffer[indicator.get_size()..],
+                    false => &[],
+                };
+                if !fast_open_data.is_empty() && !self.wait_limiter(fast_open_data.len()).await {
+                    trace!(target: TCP_LOG_TARGET, "rate limit {} -> {}", tcp.get_src(), dst);
+                    fast_open_data = &[];
+                }
+
+                // Latency test
+                let timer = Instant::now();
+
+                // Connect
+                let stream = match route {
+                    Route::Direct => StreamWorker::connect_direct(self.get_tx(), src, dst).await,
+                    _ => self.connect(src, dst).await,
+                };
+
+                let stream = match stream {
+                    Ok(stream) => {
+                        // Latency test result (not accurate)
+                        debug!(
+                            "Latency to {}: {} ms (RTT)",
+                            dst,
+                            timer.elapsed().as_millis()
+                        );
+                        debug!(
+                            target: TCP_LOG_TARGET,
+                            "open connection {} -> {} via {}",
+                            src,
+                            dst,
+                            route
+                        );
+
+                        let mut tx_locked = self.tx.lock().unwrap();
+                        // Clean up
+                        tx_locked.remove(dst, src);
+
+                        tx_locked.set_tcp_acknowledgement(
+                            dst,
+                            src,
+                            tcp.get_sequence()
+                                .wrapping_add(1)
+                                .wrapping_add(fast_open_data.len() as u32),
+                        );
+                        // Reply a cookie to cookie requests and invalid cookies
+                        if cookie.is_some() && !is_cookie_valid {
+                            let cookie = self.fast_open_cookies.generate(tcp.get_src_ip_addr());
+                            tx_locked.set_tcp_fast_open_cookie(dst, src, Some(&cookie));
+                        }
+                        tx_locked.set_tcp_remote_window_scale(dst, src, tcp.get_window_scale());
+                        tx_locked.set_tcp_sack_permitted(dst, src, tcp.is_sack_permitted());
+                        tx_locked.set_tcp_remote_timestamps(dst, src, tcp.get_timestamps());
+                        tx_locked.set_tcp_remote_ecn(dst, src, tcp.is_ecn_setup_syn());
+                        if let Some(counters) = self.connections.get_counters(&key) {
+                            tx_locked.set_tcp_counters(dst, src, counters);
+                        }
+                        // Send ACK/SYN
+                        tx_locked.send_tcp_ack_syn(dst, src)?;
+
+                        stream
+                    }
+                    Err(e) => {
+                        // Clean up
+                        self.remove(indicator);
+
+                        let mut tx_locked = self.tx.lock().unwrap();
+                        tx_locked.set_tcp_acknowledgement(
+                            dst,
+                            src,
+                            tcp.get_sequence().checked_add(1).unwrap_or(0),
+                        );
+                        // Send ACK/RST
+                        tx_locked.send_tcp_ack_rst(dst, src)?;
+
+                        // Clean up
+                        tx_locked.remove(dst, src);
+
+                        return Err(e);
+                    }
+                };
+
+                self.streams.insert(key, stream);
+
+                // Send the data in the SYN
+                if !fast_open_data.is_empty() {
+                    trace!(
+                        target: TCP_LOG_TARGET,
+                        "fast open {} -> {} ({} Bytes)",
+                        tcp.get_src(),
+                        dst,
+                        fast_open_data.len()
+                    );
+                    let flow = FlowKey::new(
+                        IpNextHeaderProtocols::Tcp,
+                        SocketAddr::V4(SocketAddrV4::new(tcp.get_src_ip_addr(), tcp.get_src())),
+                        SocketAddr::V4(dst),
+                    );
+                    let data = inspect::inspect(
+                        self.inspection_hook.as_ref(),
+                        &flow,
+                        Direction::Up,
+                        fast_open_data,
+                    );
+                    self.streams.get_mut(&key).unwrap().send(&data).await?;
+                    if let Some(counters) = self.connections.get_counters(&key) {
+                        counters.add_up(fast_open_data.len(), 1);
+                        counters.on_request(
+                            tcp.get_sequence()
+                                .wrapping_add(1)
+                                .wrapping_add(fast_open_data.len() as u32),
+                        );
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Handles a TCP ACK/SYN of a connection opened to the source, e.g., the inbound connection
+    /// of a SOCKS BIND command, and returns if the segment is handled.
+    fn handle_tcp_ack_syn(&mut self, indicator: &Indicator) -> io::Result<bool> {
+        if let Some(tcp) = indicator.get_tcp() {
+            let src = SocketAddrV4::new(tcp.get_src_ip_addr(), tcp.get_src());
+            let dst = SocketAddrV4::new(tcp.get_dst_ip_addr(), tcp.get_dst());
+            let key = (src, dst);
+
+            // Retransmission, the ACK may be lost
+            if self.streams.contains_key(&key) {
+                self.tx.lock().unwrap().send_tcp_ack_0(dst, src)?;
+                return Ok(true);
+            }
+
+            let inbound_stream = self.inbound_streams.lock().unwrap().remove(&key);
+            let mut stream = match inbound_stream {
+                Some(Some((stream, _))) => stream,
+                Some(None) => {
+                    // The SYN is sent before the stream is ready, expect a retransmission
+                    trace!(target: TCP_LOG_TARGET, "wait for inbound stream {} -> {}", dst, src);
+                    self.inbound_streams.lock().unwrap().insert(key, None);
+                    return Ok(true);
+                }
+                None => return Ok(false),
+            };
+
+            let mut tx_locked = self.tx.lock().unwrap();
+            // Reject if the SYN is not acknowledged
+            if tcp.get_acknowledgement() != tx_locked.get_tcp_sequence(dst, src) {
+                drop(tx_locked);
+                self.inbound_streams
+                    .lock()
+                    .unwrap()
+                    .insert(key, Some((stream, Instant::now())));
+                return Ok(false);
+            }
+
+            tx_locked.set_tcp_acknowledgement(dst, src, tcp.get_sequence().wrapping_add(1));
+            tx_locked.set_tcp_send_window(dst, src, tcp.get_window());
+            tx_locked.set_tcp_sack_permitted(dst, src, tcp.is_sack_permitted());
+            tx_locked.set_tcp_remote_timestamps(dst, src, tcp.get_timestamps());
+            // Send ACK0
+            tx_locked.send_tcp_ack_0(dst, src)?;
+            drop(tx_locked);
+
+            // Track the connection, evicts an idle connection if the table is full
+            let connection = Connection::new_established(
+                tcp.get_acknowledgement(),
+                tcp.get_sequence().wrapping_add(1),
+            );
+            match self.connections.insert(key, connection) {
+                Ok(Some((evicted, _))) => {
+                    debug!(target: TCP_LOG_TARGET, "evict TCP connection {} -> {}", evicted.0, evicted.1);
+                    self.reset(evicted)?;
+                }
+                Ok(None) => {}
+                Err(_) => {
+                    stream.close();
+                    let mut tx_locked = self.tx.lock().unwrap();
+                    // Send ACK/RST
+                    tx_locked.send_tcp_ack_rst(dst, src)?;
+
+                    // Clean up
+                    tx_locked.remove(dst, src);
+
+                    return Err(io::Error::new(
+                        io::ErrorKind::Other,
+                        "connection table full",
+                    ));
+                }
+            }
+            debug!(target: TCP_LOG_TARGET, "open inbound connection {} -> {}", dst, src);
+            self.streams.insert(key, stream);
+
+            let mut tx_locked = self.tx.lock().unwrap();
+            if let Some(counters) = self.connections.get_counters(&key) {
+                tx_locked.set_tcp_counters(dst, src, counters);
+            }
+            // Send the data received before the handshake
+            tx_locked.send_tcp_ack(dst, src)?;
+
+            return Ok(true);
+        }
+
+        Ok(false)
+    }
+
+    fn handle_tcp_rst(&mut self, indicator: &Indicator) {
+        if let Some(ref tcp) = indicator.get_tcp() {
+            let src = SocketAddrV4::new(tcp.get_src_ip_addr(), tcp.get_src());
+            let dst = SocketAddrV4::new(tcp.get_dst_ip_addr(), tcp.get_dst());
+            let key = (src, dst);
+            let is_exist = self.streams.get(&key).is_some();
+
+            // Refused by the source
+            let inbound_stream = self.inbound_streams.lock().unwrap().remove(&key);
+            if let Some(Some((mut stream, _))) = inbound_stream {
+                debug!(target: TCP_LOG_TARGET, "inbound connection {} -> {} refused", dst, src);
+                stream.close();
+                self.tx.lock().unwrap().remove(dst, src);
+            }
+
+            if is_exist {
+                // Clean up
+                self.remove(indicator);
+                self.tx.lock().unwrap().remove(dst, src);
+            }
+        }
+    }
+
+    async fn handle_tcp_fin(&mut self, indicator: &Indicator) -> io::Result<()> {
+        if let Some(ref tcp) = indicator.get_tcp() {
+            let src = SocketAddrV4::new(tcp.get_src_ip_addr(), tcp.get_src());
+            let dst = SocketAddrV4::new(tcp.get_dst_ip_addr(), tcp.get_dst());
+            let key = (src, dst);
+            let is_exist = self.streams.get(&key).is_some();
+
+            if is_exist {
+                let remain_cache_size = self.tx.lock().unwrap().get_cache_size(dst, src);
+                if remain_cache_size > 0 {
+                    // Trigger sending remaining data
+                    self.tx.lock().unwrap().send_tcp_ack(dst, src)?;
+                } else {
+                    let stream = self.streams.get_mut(&key).unwrap();
+                    stream.flush().await?;
+                    stream.close();
+
+                    let mut tx_locked = self.tx.lock().unwrap();
+                    tx_locked.set_tcp_acknowledgement(
+                        dst,
+                        src,
+                        tcp.get_sequence().checked_add(1).unwrap_or(0),
+                    );
+                    // Send ACK/FIN
+                    tx_locked.send_tcp_ack_fin(dst, src)?;
+                }
+            } else {
+                // Though a RST is enough, reply with respect
+                let mut tx_locked = self.tx.lock().unwrap();
+                #[allow(deprecated)]
+                tx_locked.set_tcp_sequence(dst, src, tcp.get_acknowledgement());
+                tx_locked.set_tcp_acknowledgement(
+                    dst,
+                    src,
+                    tcp.get_sequence().checked_add(1).unwrap_or(0),
+                );
+                // Send ACK/FIN
+                tx_locked.send_tcp_ack_fin(dst, src)?;
+
+                // Clean up
+                tx_locked.remove(dst, src);
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn handle_udp(&mut self, indicator: &Indicator, buffer: &[u8]) -> io::Result<()> {
+        // Stop accepting new datagrams
+        if self.is_shutting_down() {
+            return Ok(());
+        }
+
+        if let Some(ref udp) = indicator.get_udp() {
+            let src = SocketAddrV4::new(udp.get_src_ip_addr(), udp.get_src());
+            match self.get_route(udp.get_dst_ip_addr()) {
+                Route::Drop => {
+                    trace!("route {} -> {} to drop", udp.get_src(), udp.get_dst());
+                    self.stats.add_dropped(DropReason::Route, 1);
+                    return Ok(());
+                }
+                // UDP is never connected directly
+                Route::Direct => {
+                    let dst = SocketAddrV4::new(udp.get_dst_ip_addr(), udp.get_dst());
+                    if !self.is_udp_direct_warned {
+                        warn!(
+                            "UDP {} -> {} is routed directly but forwarded through the proxy",
+                            src, dst
+                        );
+                        self.is_udp_direct_warned = true;
+                    } else {
+                        debug!("forward direct UDP {} -> {} through the proxy", src, dst);
+                    }
+                }
+                Route::Proxy => {}
+            }
+
+            // Duplicate
+            if let Some(ref mut guard) = self.udp_duplicate_guard {
+                let dst = SocketAddrV4::new(udp.get_dst_ip_addr(), udp.get_dst());
+                if guard.check(src, dst, &buffer[indicator.get_size()..]) {
+                    trace!("drop duplicate {} -> {}", src, dst);
+                    self.stats.add_dropped(DropReason::Duplicate, 1);
+                    return Ok(());
+                }
+            }
+
+            // DNS
+            if udp.get_dst() == DNS_PORT {
+                let payload = &buffer[indicator.get_size()..];
+                match Dns::deserialize(payload) {
+                    Ok(ref dns) => debug!("receive from pcap: {}", dns),
+                    Err(ref e) => trace!("parse DNS: {}", e),
+                }
+
+                // Answer from the DNS cache
+                let mut tx_locked = self.tx.lock().unwrap();
+                if let Some(response) = tx_locked.lookup_dns(payload) {
+                    let dst = SocketAddrV4::new(udp.get_dst_ip_addr(), udp.get_dst());
+                    debug!("answer DNS {} -> {} from cache", src, dst);
+
+                    return tx_locked.send_udp(dst, src, &response);
+                }
+            }
+
+            // SOCKS4 has no UDP association
+            if self.connect_options.version == SocksVersion::V4 {
+                trace!(
+                    "drop {} -> {} unsupported by SOCKS4",
+                    udp.get_src(),
+                    udp.get_dst()
+                );
+                self.stats.add_dropped(DropReason::Unsupported, 1);
+                return Ok(());
+            }
+
+            let mut port = self.get_local_udp_port(src);
+
+            // Bind
+            let is_create;
+            let is_set;
+            if port == 0 {
+                is_create = true;
+                is_set = false;
+            } else {
+                let worker = self.datagrams.get(&port).unwrap();
+                is_create = worker.is_closed();
+                is_set = worker.get_src() != Some(src);
+            }
+            if is_create {
+                // Drop the closed association
+                if port != 0 {
+                    self.datagrams.remove(&port);
+                    self.udp_lru.pop(&port);
+                }
+
+                let worker = self.bind(src).await?;
+                let bind_port = worker.get_local_port();
+                self.datagrams.insert(bind_port, worker);
+                self.datagram_map.insert(src, bind_port);
+
+                // Update LRU
+                self.udp_lru.put(bind_port, src);
+
+                port = bind_port;
+            } else if is_set {
+                // Replace
+                self.datagrams.get_mut(&port).unwrap().set_src(Some(src));
+            }
+
+            // Rate limit
+            if !self.wait_limiter(buffer.len() - indicator.get_size()).await {
+                trace!("rate limit {} -> {}", udp.get_src(), udp.get_dst());
+                return Ok(());
+            }
+
+            // Inspect
+            let dst = SocketAddrV4::new(udp.get_dst_ip_addr(), udp.get_dst());
+            let flow = FlowKey::new(
+                IpNextHeaderProtocols::Udp,
+                SocketAddr::V4(src),
+                SocketAddr::V4(dst),
+            );
+            let data = inspect::inspect(
+                self.inspection_hook.as_ref(),
+                &flow,
+                Direction::Up,
+                &buffer[indicator.get_size()..],
+            );
+
+            // Send
+            self.datagrams
+                .get_mut(&port)
+                .unwrap()
+                .send_to(&data, dst)
+                .await?;
+        }
+
+        Ok(())
+    }
+
+    fn handle_icmp(&mut self, indicator: &Indicator, buffer: &[u8]) -> io::Result<()> {
+        if let Some(icmp) = indicator.get_icmp() {
+            // ICMP cannot be proxied through SOCKS, only echo requests to the gateway are replied
+            if icmp.is_fragmentation_needed() {
+                match (icmp.get_next_hop_mtu(), icmp.get_original_dst()) {
+                    (Some(mtu), Some(dst)) => {
+                        self.tx.lock().unwrap().set_path_mtu(dst, mtu);
+
+                        debug!("set path MTU of {} to {}", dst, mtu);
+                    }
+                    _ => trace!("ignore {}", icmp),
+                }
+            } else if icmp.is_echo_request() {
+                let ipv4 = indicator.get_ipv4().unwrap();
+                if Some(ipv4.get_dst()) != self.local_ip_addr {
+                    return self.handle_icmp_prohibited(indicator, buffer);
+                }
+
+                // Send
+                self.tx.lock().unwrap().send_icmp_echo_reply(
+                    ipv4.get_dst(),
+                    icmp.get_identifier(),
+                    icmp.get_sequence(),
+                    &buffer[indicator.get_size()..],
+                )?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn handle_icmp_prohibited(&mut self, indicator: &Indicator, buffer: &[u8]) -> io::Result<()> {
+        if let Some(ipv4) = indicator.get_ipv4() {
+            // Replying echo requests to other hosts locally would hide unreachable hosts
+            debug!("prohibit {}", indicator.brief());
+            self.stats.add_dropped(DropReason::Unsupported, 1);
+
+            // Only the first fragment is replied
+            if ipv4.get_fragment_offset() > 0 {
+                return Ok(());
+            }
+
+            let begin = indicator.get_link().get_size();
+            let end = min(
+                begin + ipv4.get_size() + ORIGINAL_DATAGRAM_DATA_SIZE,
+                buffer.len(),
+            );
+            let src_ip_addr = self.local_ip_addr.unwrap_or_else(|| ipv4.get_dst());
+
+            self.tx
+                .lock()
+                .unwrap()
+                .send_icmp_administratively_prohibited(src_ip_addr, &buffer[begin..end])?;
+        }
+
+        Ok(())
+    }
+
+    fn update_tcp_sequence(&mut self, indicator: &Indicator) {
+        if let Some(tcp) = indicator.get_tcp() {
+            let src = SocketAddrV4::new(tcp.get_src_ip_addr(), tcp.get_src());
+            let dst = SocketAddrV4::new(tcp.get_dst_ip_addr(), tcp.get_dst());
+            let key = (src, dst);
+
+            let record_sequence = *self.tcp_sequence_map.get(&key).unwrap_or(&0);
+            let sub_sequence = tcp
+                .get_sequence()
+                .checked_sub(record_sequence)
+                .unwrap_or_else(|| tcp.get_sequence() + (u32::MAX - record_sequence));
+
+            if sub_sequence == 0 {
+                // Duplicate
+                trace!(
+                    "TCP retransmission of {} -> {} at {}",
+                    tcp.get_src(),
+                    dst,
+                    tcp.get_sequence()
+                );
+            } else if sub_sequence < MAX_U32_WINDOW_SIZE as u32 {
+                self.tcp_sequence_map.insert(key, tcp.get_sequence());
+
+                trace!(
+                    "set TCP sequence of {} -> {} to {}",
+                    tcp.get_src(),
+                    dst,
+                    tcp.get_sequence()
+                );
+            } else {
+                trace!(
+                    "TCP out of order of {} -> {} at {}",
+                    tcp.get_src(),
+                    dst,
+                    tcp.get_sequence()
+                );
+            }
+        }
+    }
+
+    fn update_tcp_acknowledgement(&mut self, indicator: &Indicator) {
+        if let Some(tcp) = indicator.get_tcp() {
+            let src = SocketAddrV4::new(tcp.get_src_ip_addr(), tcp.get_src());
+            let dst = SocketAddrV4::new(tcp.get_dst_ip_addr(), tcp.get_dst());
+            let key = (src, dst);
+
+            let record_acknowledgement = *self.tcp_acknowledgement_map.get(&key).unwrap_or(&0);
+            let sub_acknowledgement = tcp
+                .get_acknowledgement()
+                .checked_sub(record_acknowledgement)
+                .unwrap_or_else(|| tcp.get_acknowledgement() + (u32::MAX - record_acknowledgement));
+
+            if sub_acknowledgement == 0 {
+                // Duplicate
+                let entry = self.tcp_duplicate_map.entry(key).or_insert(0);
+                *entry = entry.checked_add(1).unwrap_or(usize::MAX);
+                trace!(
+                    "duplicate TCP acknowledgement of {} -> {} at {}",
+                    tcp.get_src(),
+                    dst,
+                    tcp.get_acknowledgement()
+                );
+            } else if sub_acknowledgement < MAX_U32_WINDOW_SIZE as u32 {
+                self.tcp_acknowledgement_map
+                    .insert(key, tcp.get_acknowledgement());
+
+                self.tcp_duplicate_map.insert(key, 0);
+                trace!(
+                    "set TCP acknowledgement of {} -> {} to {}",
+                    tcp.get_src(),
+                    dst,
+                    tcp.get_acknowledgement()
+                );
+            }
+        }
+    }
+
+    fn remove(&mut self, indicator: &Indicator) {
+        if let Some(tcp) = indicator.get_tcp() {
+            let src = SocketAddrV4::new(tcp.get_src_ip_addr(), tcp.get_src());
+            let dst = SocketAddrV4::new(tcp.get_dst_ip_addr(), tcp.get_dst());
+
+            self.remove_key((src, dst));
+        }
+    }
+
+    fn remove_key(&mut self, key: (SocketAddrV4, SocketAddrV4)) {
+        if self.streams.remove(&key).is_some() {
+            debug!(
+                target: TCP_LOG_TARGET,
+                "close connection {} -> {}",
+                key.0,
+                key.1
+            );
+        }
+        self.tcp_sequence_map.remove(&key);
+        self.tcp_acknowledgement_map.remove(&key);
+        self.tcp_duplicate_map.remove(&key);
+        self.tcp_last_retransmission_map.remove(&key);
+        self.tcp_cache_map.remove(&key);
+        self.tcp_window_map.remove(&key);
+        #[cfg(feature = "metrics")]
+        {
+            if let (Some(metrics), Some(counters)) =
+                (&self.metrics, self.connections.get_counters(&key))
+            {
+                metrics.close(key, counters.get_up_bytes(), counters.get_down_bytes());
+            }
+        }
+        self.connections.remove(&key);
+        trace!("remove {} -> {}", key.1, key.0);
+    }
+
+    /// Resets a TCP connection and removes all information related to it.
+    fn reset(&mut self, key: (SocketAddrV4, SocketAddrV4)) -> io::Result<()> {
+        let (src, dst) = key;
+
+        // Clean up
+        self.remove_key(key);
+
+        // Send ACK/RST
+        let mut tx_locked = self.tx.lock().unwrap();
+        let result = tx_locked.send_tcp_ack_rst(dst, src);
+
+        // Clean up, even if the ACK/RST is not sent
+        tx_locked.remove(dst, src);
+
+        result
+    }
+
+    fn get_tx(&self) -> Arc<Mutex<Forwarder>> {
+        Arc::clone(&self.tx)
+    }
+
+    /// Connects to the destination through the proxies in the order selected, and returns the
+    /// stream of the first proxy connected.
+    async fn connect(&mut self, src: SocketAddrV4, dst: SocketAddrV4) -> io::Result<StreamWorker> {
+        // Request by the name if the destination is resolved from a name
+        let addr = match self.tx.lock().unwrap().lookup_dns_name(*dst.ip()) {
+            Some(name) => {
+                trace!("connect {} by name {}", dst, name);
+                Address::Domain(name)
+            }
+            None => Address::from(*dst.ip()),
+        };
+
+        let mut last_error = None;
+        for remote in self.remotes.select() {
+            match StreamWorker::connect_with_address(
+                self.get_tx(),
+                src,
+                dst,
+                &addr,
+                remote,
+                &self.connect_options,
+                &self.auth,
+            )
+            .await
+            {
+                Ok(stream) => {
+                    self.remotes.mark_up(remote);
+                    return Ok(stream);
+                }
+                Err(e) => {
+                    warn!("connect {} via {}: {}", dst, remote, e);
+                    self.remotes.mark_down(remote);
+                    last_error = Some(e);
+                }
+            }
+        }
+
+        Err(last_error.unwrap_or_else(|| io::Error::from(io::ErrorKind::NotConnected)))
+    }
+
+    /// Reuses a pooled UDP association to the proxies in the order selected, or binds a new one
+    /// through the first proxy associated.
+    async fn bind(&mut self, src: SocketAddrV4) -> io::Result<DatagramWorker> {
+        let remotes = self.remotes.select();
+        for remote in remotes.iter() {
+            if !self.remotes.is_up(*remote) {
+                continue;
+            }
+            if let Some(mut worker) = self.datagram_pool.take(*remote) {
+                worker.set_src(Some(src));
+                trace!(
+                    "reuse pooled UDP association {} = {}",
+                    src,
+                    worker.get_local_port()
+                );
+                return Ok(worker);
+            }
+        }
+
+        let mut last_error = None;
+        for remote in remotes {
+            match DatagramWorker::bind(
+                self.get_tx(),
+                src,
+                remote,
+                &self.connect_options,
+                &self.auth,
+            )
+            .await
+            {
+                Ok((worker, _)) => {
+                    self.remotes.mark_up(remote);
+                    return Ok(worker);
+                }
+                Err(e) => {
+                    warn!("associate via {}: {}", remote, e);
+                    self.remotes.mark_down(remote);
+                    last_error = Some(e);
+                }
+            }
+        }
+
+        Err(last_error.unwrap_or_else(|| io::Error::from(io::ErrorKind::NotConnected)))
+    }
+
+    /// Releases the UDP association bound on the given local port into the pool.
+    fn release_datagram(&mut self, local_port: u16) {
+        if let Some(src) = self.udp_lru.pop(&local_port) {
+            if self.datagram_map.get(&src) == Some(&local_port) {
+                self.datagram_map.remove(&src);
+            }
+        }
+        if let Some(worker) = self.datagrams.remove(&local_port) {
+            trace!(
+                "release UDP association {:?} = {}",
+                worker.get_src(),
+                local_port
+            );
+            self.datagram_pool.put(worker.get_remote(), worker);
+        }
+    }
+
+    fn get_local_udp_port(&mut self, src: SocketAddrV4) -> u16 {
+        match self.datagram_map.get(&src) {
+            Some(&local_port) => {
+                // Update LRU
+                self.udp_lru.get(&local_port);
+
+                local_port
+            }
+            None => {
+                if self.udp_lru.len() < self.udp_lru.cap() {
+                    return 0;
+                }
+                let (local_port, prev_src) = self.udp_lru.pop_lru().unwrap();
+
+                // Reuse
+                self.datagram_map.remove(&prev_src);
+                trace!(
+                    "reuse UDP port {} = {} to {} = {}",
+                    prev_src,
+                    local_port,
+                    src,
+                    local_port
+                );
+                self.datagram_map.insert(src, local_port);
+
+                // Update LRU
+                self.udp_lru.put(local_port, src);
+
+                local_port
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use packet::PacketBuilder;
+    use pnet::packet::icmp::{IcmpCode, IcmpTypes};
+
+    const SRC_HARDWARE_ADDR: HardwareAddr = pnet::datalink::MacAddr(0x02, 0, 0, 0, 0, 0x01);
+    const LOCAL_HARDWARE_ADDR: HardwareAddr = pnet::datalink::MacAddr(0x02, 0, 0, 0, 0, 0x02);
+    const SRC_IP_ADDR: Ipv4Addr = Ipv4Addr::new(10, 6, 0, 1);
+    const LOCAL_IP_ADDR: Ipv4Addr = Ipv4Addr::new(10, 6, 0, 254);
+    const DST_IP_ADDR: Ipv4Addr = Ipv4Addr::new(93, 184, 216, 34);
+    const REMOTE: SocketAddrV4 = SocketAddrV4::new(Ipv4Addr::new(127, 0, 0, 1), 1080);
+
+    /// Represents an injector keeping the frames injected.
+    #[derive(Clone, Default)]
+    struct CaptureInjector {
+        frames: Arc<Mutex<Vec<Vec<u8>>>>,
+    }
+
+    impl Injector for CaptureInjector {
+        fn inject(&mut self, frame: &[u8]) -> io::Result<()> {
+            self.frames.lock().unwrap().push(frame.to_vec());
+            Ok(())
+        }
+    }
+
+    /// Creates a `Forwarder` of the source and the gateway, and returns it with the frames
+    /// injected by it.
+    fn new_forwarder() -> (Forwarder, Arc<Mutex<Vec<Vec<u8>>>>) {
+        let injector = CaptureInjector::default();
+        let frames = injector.frames.clone();
+        let forwarder = Forwarder::new(
+            Box::new(injector),
+            1500,
+            LOCAL_HARDWARE_ADDR,
+            SRC_IP_ADDR,
+            LOCAL_IP_ADDR,
+        );
+
+        (forwarder, frames)
+    }
+
+    /// Creates a `Redirector` of the source and the gateway, and returns it with the frames
+    /// injected by it.
+    fn new_redirector() -> (Redirector, Arc<Mutex<Vec<Vec<u8>>>>) {
+        new_redirector_to(REMOTE)
+    }
+
+    /// Creates a `Redirector` of the source and the gateway via the given proxy, and returns it
+    /// with the frames injected by it.
+    fn new_redirector_to(remote: SocketAddrV4) -> (Redirector, Arc<Mutex<Vec<Vec<u8>>>>) {
+        let (forwarder, frames) = new_forwarder();
+        let redirector = Redirector::new(
+            Arc::new(Mutex::new(forwarder)),
+            SRC_IP_ADDR,
+            Some(LOCAL_IP_ADDR),
+            remote,
+        );
+
+        (redirector, frames)
+    }
+
+    /// Builds a frame from the source to the given destination carrying the given transport
+    /// layer.
+    fn build_ipv4_frame(dst: Ipv4Addr, transport: Layers, payload: &[u8]) -> Vec<u8> {
+        let ethernet =
+            Ethernet::new(LayerTypes::Ipv4, SRC_HARDWARE_ADDR, LOCAL_HARDWARE_ADDR).unwrap();
+        let ipv4 = Ipv4::new(1, transport.get_type(), SRC_IP_ADDR, dst).unwrap();
+        let transport = match transport {
+            Layers::Tcp(mut tcp) => {
+                tcp.set_ipv4_layer(&ipv4);
+                Layers::Tcp(tcp)
+            }
+            Layers::Udp(mut udp) => {
+                udp.set_ipv4_layer(&ipv4);
+                Layers::Udp(udp)
+            }
+            transport => transport,
+        };
+
+        PacketBuilder::new()
+            .ethernet(ethernet)
+            .ipv4(ipv4)
+            .layer(transport)
+            .payload(payload)
+            .build()
+            .unwrap()
+    }
+
+    /// Builds a frame from the source carrying the given ARP layer.
+    fn build_arp_frame(arp: Arp) -> Vec<u8> {
+        let ethernet = Ethernet::new(
+            LayerTypes::Arp,
+            arp.get_src_hardware_addr(),
+            LOCAL_HARDWARE_ADDR,
+        )
+        .unwrap();
+        let indicator = Indicator::new(Layers::Ethernet(ethernet), Some(Layers::Arp(arp)), None);
+        let mut frame = vec![0u8; indicator.get_size()];
+        indicator.serialize(&mut frame).unwrap();
+
+        frame
+    }
+
+    /// Builds an ARP request from the source for the given IP address.
+    fn new_arp_request(ip_addr: Ipv4Addr) -> Arp {
+        let mut arp = Arp::new_gratuitous(SRC_HARDWARE_ADDR, SRC_IP_ADDR);
+        arp.layer.target_proto_addr = ip_addr;
+
+        arp
+    }
+
+    #[tokio::test]
+    async fn reply_arp_request_of_gateway() {
+        let (mut redirector, frames) = new_redirector();
+        let frame = build_arp_frame(new_arp_request(LOCAL_IP_ADDR));
+        redirector.handle_frame(&frame).await;
+
+        let frames = frames.lock().unwrap();
+        assert_eq!(frames.len(), 1);
+        let indicator = Indicator::from(&frames[0]).unwrap();
+        let arp = indicator.get_arp().unwrap();
+        assert!(arp.is_reply());
+        assert_eq!(arp.get_src_hardware_addr(), LOCAL_HARDWARE_ADDR);
+        assert_eq!(arp.get_src(), LOCAL_IP_ADDR);
+        assert_eq!(arp.layer.target_hw_addr, SRC_HARDWARE_ADDR);
+        assert_eq!(arp.get_dst(), SRC_IP_ADDR);
+    }
+
+    #[tokio::test]
+    async fn ignore_arp_request_of_others() {
+        let (mut redirector, frames) = new_redirector();
+        let frame = build_arp_frame(new_arp_request(DST_IP_ADDR));
+        redirector.handle_frame(&frame).await;
+
+        assert!(frames.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn reply_echo_request_to_gateway() {
+        let (mut redirector, frames) = new_redirector();
+        let frame = build_ipv4_frame(
+            LOCAL_IP_ADDR,
+            Layers::Icmp(Icmp::new_echo_request(0x1234, 1)),
+            b"ping",
+        );
+        redirector.handle_frame(&frame).await;
+
+        let frames = frames.lock().unwrap();
+        assert_eq!(frames.len(), 1);
+        let indicator = Indicator::from(&frames[0]).unwrap();
+        let ipv4 = indicator.get_ipv4().unwrap();
+        assert_eq!(ipv4.get_src(), LOCAL_IP_ADDR);
+        assert_eq!(ipv4.get_dst(), SRC_IP_ADDR);
+        let icmp = indicator.get_icmp().unwrap();
+        assert_eq!(icmp.get_icmp_type(), IcmpTypes::EchoReply);
+        // The reply may be padded to the minimum size of Ethernet frames
+        assert_eq!(
+            &frames[0][indicator.get_size()..indicator.get_size() + 4],
+            b"ping"
+        );
+    }
+
+    /// Replaces the Ethernet header of the given frame with an SLL header of the given
+    /// link-layer address.
+    fn to_sll_frame(frame: &[u8], address: &[u8]) -> Vec<u8> {
+        let mut sll_frame = vec![0x00, 0x00, 0x00, 0x01, 0x00, address.len() as u8];
+        sll_frame.extend_from_slice(address);
+        sll_frame.resize(14, 0);
+        sll_frame.extend_from_slice(&frame[12..]);
+
+        sll_frame
+    }
+
+    #[tokio::test]
+    async fn reply_sll_echo_request() {
+        let (mut redirector, frames) = new_redirector();
+        redirector.set_link_type(LinkType::LinuxSll);
+        let frame = build_ipv4_frame(
+            LOCAL_IP_ADDR,
+            Layers::Icmp(Icmp::new_echo_request(0x1234, 1)),
+            b"ping",
+        );
+        // Without a hardware address, the frame is still handled
+        redirector.handle_frame(&to_sll_frame(&frame, &[])).await;
+        redirector
+            .handle_frame(&to_sll_frame(&frame, &[0x02, 0, 0, 0, 0, 0x01]))
+            .await;
+
+        let frames = frames.lock().unwrap();
+        assert_eq!(frames.len(), 2);
+        // Replies are Ethernet frames to the hardware address of the SLL header
+        let indicator = Indicator::from(&frames[1]).unwrap();
+        assert_eq!(
+            indicator.get_ethernet().unwrap().get_dst(),
+            SRC_HARDWARE_ADDR
+        );
+        assert_eq!(indicator.get_ipv4().unwrap().get_dst(), SRC_IP_ADDR);
+        let icmp = indicator.get_icmp().unwrap();
+        assert_eq!(icmp.get_icmp_type(), IcmpTypes::EchoReply);
+        assert_eq!(
+            &frames[1][indicator.get_size()..indicator.get_size() + 4],
+            b"ping"
+        );
+    }
+
+    #[tokio::test]
+    async fn prohibit_echo_request_to_others() {
+        let (mut redirector, frames) = new_redirector();
+        let frame = build_ipv4_frame(
+            DST_IP_ADDR,
+            Layers::Icmp(Icmp::new_echo_request(0x1234, 1)),
+            b"ping",
+        );
+        redirector.handle_frame(&frame).await;
+
+        let frames = frames.lock().unwrap();
+        assert_eq!(frames.len(), 1);
+        let indicator = Indicator::from(&frames[0]).unwrap();
+        let icmp = indicator.get_icmp().unwrap();
+        assert_eq!(icmp.get_icmp_type(), IcmpTypes::DestinationUnreachable);
+        assert_eq!(icmp.get_icmp_code(), IcmpCode(13));
+        assert_eq!(icmp.get_original_dst(), Some(DST_IP_ADDR));
+    }
+
+    #[tokio::test]
+    async fn send_to_hardware_addr_learnt() {
+        let (mut redirector, frames) = new_redirector();
+        let hardware_addr = pnet::datalink::MacAddr(0x02, 0, 0, 0, 0, 0x03);
+        let frame = build_arp_frame(Arp::new_gratuitous(hardware_addr, SRC_IP_ADDR));
+        redirector.handle_frame(&frame).await;
+
+        let frame = build_ipv4_frame(
+            LOCAL_IP_ADDR,
+            Layers::Icmp(Icmp::new_echo_request(0x1234, 1)),
+            b"ping",
+        );
+        redirector.handle_frame(&frame).await;
+
+        let frames = frames.lock().unwrap();
+        assert_eq!(frames.len(), 1);
+        let indicator = Indicator::from(&frames[0]).unwrap();
+        let ethernet = indicator.get_ethernet().unwrap();
+        assert_eq!(ethernet.layer.destination, hardware_addr);
+    }
+
+    #[tokio::test]
+    async fn reply_with_vlan() {
+        let (mut redirector, frames) = new_redirector();
+        let mut frame = build_ipv4_frame(
+            LOCAL_IP_ADDR,
+            Layers::Icmp(Icmp::new_echo_request(0x1234, 1)),
+            b"ping",
+        );
+        // Tag the frame with VID 100 before the EtherType
+        frame.splice(12..12, [0x81, 0x00, 0x00, 0x64]);
+        redirector.handle_frame(&frame).await;
+
+        let frames = frames.lock().unwrap();
+        assert_eq!(frames.len(), 1);
+        let indicator = Indicator::from(&frames[0]).unwrap();
+        let ethernet = indicator.get_ethernet().unwrap();
+        assert_eq!(ethernet.get_vlan(), Some(VlanTag::new(0, false, 100)));
+        assert!(indicator.get_icmp().is_some());
+    }
+
+    #[tokio::test]
+    async fn count_stats() {
+        let (mut redirector, _) = new_redirector();
+        let stats = redirector.get_stats();
+        let frame = build_ipv4_frame(
+            LOCAL_IP_ADDR,
+            Layers::Icmp(Icmp::new_echo_request(0x1234, 1)),
+            b"ping",
+        );
+        redirector.handle_frame(&frame).await;
+
+        assert_eq!(stats.get_seen(LayerTypes::Ethernet), 1);
+        assert_eq!(stats.get_seen(LayerTypes::Ipv4), 1);
+        assert_eq!(stats.get_seen(LayerTypes::Icmp), 1);
+        assert_eq!(stats.get_forwarded(LayerTypes::Ipv4), 1);
+        assert_eq!(stats.get_forwarded(LayerTypes::Icmp), 1);
+
+        // Corrupt the IPv4 checksum
+        let mut frame = frame;
+        frame[ETHERNET_HEADER_SIZE + 10] ^= 0xff;
+        redirector.handle_frame(&frame).await;
+
+        assert_eq!(stats.get_seen(LayerTypes::Ethernet), 2);
+        assert_eq!(stats.get_forwarded(LayerTypes::Ipv4), 1);
+        assert_eq!(stats.get_dropped(DropReason::ChecksumMismatch), 1);
+    }
+
+    #[tokio::test]
+    async fn drop_unknown_ethertype() {
+        let (mut redirector, frames) = new_redirector();
+        let stats = redirector.get_stats();
+        let mut frame = Vec::new();
+        frame.extend_from_slice(&[0x02, 0, 0, 0, 0, 0x02]);
+        frame.extend_from_slice(&[0x02, 0, 0, 0, 0, 0x01]);
+        frame.extend_from_slice(&[0x88, 0xb5]);
+        frame.extend_from_slice(b"experimental");
+        redirector.handle_frame(&frame).await;
+
+        assert_eq!(stats.get_dropped(DropReason::Unsupported), 1);
+        assert!(frames.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn honor_fragmentation_needed() {
+        let (mut redirector, _) = new_redirector();
+        // Next-hop MTU 1400
+        let mut message = vec![0x03, 0x04, 0x00, 0x00, 0x00, 0x00, 0x05, 0x78];
+        let checksum = pnet::util::checksum(&message, 1);
+        message[2..4].copy_from_slice(&checksum.to_be_bytes());
+        let (icmp, _) = Icmp::deserialize(&message).unwrap();
+        // The quoted datagram sent to the source
+        let original = Ipv4::new(1, LayerTypes::Udp, DST_IP_ADDR, SRC_IP_ADDR).unwrap();
+        let n = original.get_size() + 8;
+        let mut quoted = vec![0u8; n];
+        original.serialize(&mut quoted, n).unwrap();
+        let frame = build_ipv4_frame(DST_IP_ADDR, Layers::Icmp(icmp), &quoted);
+        redirector.handle_frame(&frame).await;
+
+        let tx = redirector.tx.lock().unwrap();
+        assert_eq!(tx.get_path_mtu(SRC_IP_ADDR), 1400);
+        assert_eq!(tx.get_path_mtu(DST_IP_ADDR), 1500);
+    }
+
+    #[tokio::test]
+    async fn dry_run() {
+        let (mut redirector, frames) = new_redirector();
+        redirector.set_dry_run(true);
+        let stats = redirector.get_stats();
+        for frame in [
+            build_arp_frame(new_arp_request(LOCAL_IP_ADDR)),
+            build_ipv4_frame(
+                LOCAL_IP_ADDR,
+                Layers::Icmp(Icmp::new_echo_request(0x1234, 1)),
+                b"ping",
+            ),
+            build_ipv4_frame(
+                DST_IP_ADDR,
+                Layers::Tcp(Tcp::new_syn(1024, 80, 1000, 65535)),
+                &[],
+            ),
+        ] {
+            redirector.handle_frame(&frame).await;
+        }
+
+        // Frames are parsed, but nothing is sent or tracked
+        assert_eq!(stats.get_seen(LayerTypes::Ethernet), 3);
+        assert_eq!(stats.get_forwarded(LayerTypes::Ipv4), 0);
+        assert!(frames.lock().unwrap().is_empty());
+        assert!(redirector.flows().is_empty());
+    }
+
+    #[tokio::test]
+    async fn route_drop() {
+        let (mut redirector, frames) = new_redirector();
+        let stats = redirector.get_stats();
+        let mut routes = RouteTable::new(Route::Proxy);
+        routes.insert("93.184.216.0/24".parse().unwrap(), Route::Drop);
+        redirector.set_route_table(routes);
+
+        // The connection is reset without connecting to the proxy
+        let frame = build_ipv4_frame(
+            DST_IP_ADDR,
+            Layers::Tcp(Tcp::new_syn(1024, 80, 1000, 65535)),
+            &[],
+        );
+        redirector.handle_frame(&frame).await;
+        {
+            let frames = frames.lock().unwrap();
+            let indicator = Indicator::from(frames.last().unwrap()).unwrap();
+            let tcp = indicator.get_tcp().unwrap();
+            assert!(tcp.is_rst());
+            assert_eq!(tcp.get_acknowledgement(), 1001);
+        }
+
+        let frame = build_ipv4_frame(DST_IP_ADDR, Layers::Udp(Udp::new(1024, 53)), b"query");
+        redirector.handle_frame(&frame).await;
+        assert_eq!(stats.get_dropped(DropReason::Route), 2);
+        assert!(redirector.flows().is_empty());
+    }
+
+    /// Spawns a SOCKS5 proxy without authentication accepting UDP associations, and returns the
+    /// address of the proxy, the relay of the proxy and the control connections accepted by it.
+    fn spawn_udp_proxy() -> (
+        SocketAddrV4,
+        std::net::UdpSocket,
+        std::sync::mpsc::Receiver<std::net::TcpStream>,
+    ) {
+        use std::io::Read;
+
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = match listener.local_addr().unwrap() {
+            SocketAddr::V4(addr) => addr,
+            _ => unreachable!(),
+        };
+        let relay = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+        relay
+            .set_read_timeout(Some(Duration::from_secs(2)))
+            .unwrap();
+        let relay_port = relay.local_addr().unwrap().port().to_be_bytes();
+        let (tx, rx) = std::sync::mpsc::channel();
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let mut buffer = [0u8; 10];
+                stream.read_exact(&mut buffer[..2]).unwrap();
+                let n = buffer[1] as usize;
+                stream.read_exact(&mut buffer[..n]).unwrap();
+                stream.write_all(&[0x05, 0x00]).unwrap();
+                // A request of UDP ASSOCIATE
+                stream.read_exact(&mut buffer).unwrap();
+                let mut reply = vec![0x05, 0x00, 0x00, 0x01, 127, 0, 0, 1];
+                reply.extend_from_slice(&relay_port);
+                stream.write_all(&reply).unwrap();
+
+                if tx.send(stream).is_err() {
+                    break;
+                }
+            }
+        });
+
+        (addr, relay, rx)
+    }
+
+    #[tokio::test]
+    async fn reuse_pooled_datagram() {
+        let (remote, relay, connections) = spawn_udp_proxy();
+        let (mut redirector, _) = new_redirector_to(remote);
+        let frame = build_ipv4_frame(DST_IP_ADDR, Layers::Udp(Udp::new(1024, 9999)), b"first");
+        redirector.handle_frame(&frame).await;
+
+        let mut buffer = [0u8; 64];
+        let (n, first) = relay.recv_from(&mut buffer).unwrap();
+        assert_eq!(
+            &buffer[..n],
+            b"\x00\x00\x00\x01\x5d\xb8\xd8\x22\x27\x0ffirst"
+        );
+
+        // Release the idle association of the first flow
+        let local_port = *redirector.datagrams.keys().next().unwrap();
+        redirector.release_datagram(local_port);
+        assert!(redirector.datagrams.is_empty());
+        assert_eq!(redirector.datagram_pool.len(), 1);
+
+        let frame = build_ipv4_frame(DST_IP_ADDR, Layers::Udp(Udp::new(1025, 9999)), b"second");
+        redirector.handle_frame(&frame).await;
+
+        // The second flow is relayed through the same association
+        let (n, second) = relay.recv_from(&mut buffer).unwrap();
+        assert_eq!(&buffer[n - 6..n], b"second");
+        assert_eq!(second, first);
+        assert!(redirector.datagram_pool.is_empty());
+        assert_eq!(
+            redirector
+                .datagram_map
+                .get(&SocketAddrV4::new(SRC_IP_ADDR, 1025)),
+            Some(&local_port)
+        );
+        assert_eq!(connections.try_iter().count(), 1);
+    }
+
+    #[tokio::test]
+    async fn drop_duplicate_datagram() {
+        let (remote, relay, _connections) = spawn_udp_proxy();
+        let (mut redirector, _) = new_redirector_to(remote);
+        let stats = redirector.get_stats();
+        redirector.set_udp_duplicate_window(Some(Duration::from_millis(200)));
+        let query = build_ipv4_frame(DST_IP_ADDR, Layers::Udp(Udp::new(1024, 53)), b"query");
+        let other = build_ipv4_frame(DST_IP_ADDR, Layers::Udp(Udp::new(1024, 53)), b"other");
+
+        let mut buffer = [0u8; 64];
+        redirector.handle_frame(&query).await;
+        let (n, _) = relay.recv_from(&mut buffer).unwrap();
+        assert_eq!(&buffer[n - 5..n], b"query");
+
+        // The duplicate within the window is dropped
+        redirector.handle_frame(&query).await;
+        assert_eq!(stats.get_dropped(DropReason::Duplicate), 1);
+        redirector.handle_frame(&other).await;
+        let (n, _) = relay.recv_from(&mut buffer).unwrap();
+        assert_eq!(&buffer[n - 5..n], b"other");
+
+        // The datagram outside the window passes
+        std::thread::sleep(Duration::from_millis(300));
+        redirector.handle_frame(&query).await;
+        let (n, _) = relay.recv_from(&mut buffer).unwrap();
+        assert_eq!(&buffer[n - 5..n], b"query");
+        assert_eq!(stats.get_dropped(DropReason::Duplicate), 1);
+    }
+
+    /// Spawns a SOCKS5 proxy without authentication accepting a connection, which is served
+    /// until closed by the redirector. Returns the address of the proxy, and the handle joining
+    /// the bytes received from the connection.
+    fn spawn_proxy() -> (SocketAddrV4, std::thread::JoinHandle<Vec<u8>>) {
+        spawn_proxy_with_delay(Duration::from_secs(0))
+    }
+
+    /// Spawns a SOCKS5 proxy like `spawn_proxy`, which starts reading the data sent after the
+    /// given delay.
+    fn spawn_proxy_with_delay(delay: Duration) -> (SocketAddrV4, std::thread::JoinHandle<Vec<u8>>) {
+        use std::io::Read;
+
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = match listener.local_addr().unwrap() {
+            SocketAddr::V4(addr) => addr,
+            _ => unreachable!(),
+        };
+        let handle = std::thread::spawn(move || {
+            let mut stream = listener.accept().unwrap().0;
+            let mut buffer = [0u8; 1024];
+            stream.read_exact(&mut buffer[..2]).unwrap();
+            let n = buffer[1] as usize;
+            stream.read_exact(&mut buffer[..n]).unwrap();
+            stream.write_all(&[0x05, 0x00]).unwrap();
+            // A request of an IPv4 address
+            stream.read_exact(&mut buffer[..10]).unwrap();
+            stream
+                .write_all(&[0x05, 0x00, 0x00, 0x01, 0, 0, 0, 0, 0, 0])
+                .unwrap();
+            std::thread::sleep(delay);
+            let mut received = Vec::new();
+            stream.read_to_end(&mut received).unwrap_or(0);
+
+            received
+        });
+
+        (addr, handle)
+    }
+
+    /// Opens a TCP connection from the source to the destination via the proxy of the given
+    /// `Redirector`, and returns the next sequences of the source and the redirector.
+    async fn open_connection(
+        redirector: &mut Redirector,
+        frames: &Arc<Mutex<Vec<Vec<u8>>>>,
+    ) -> (u32, u32) {
+        let frame = build_ipv4_frame(
+            DST_IP_ADDR,
+            Layers::Tcp(Tcp::new_syn(1024, 80, 1000, 65535)),
+            &[],
+        );
+        redirector.handle_frame(&frame).await;
+
+        let sequence = {
+            let frames = frames.lock().unwrap();
+            let indicator = Indicator::from(frames.last().unwrap()).unwrap();
+            let tcp = indicator.get_tcp().unwrap();
+            assert!(tcp.is_syn() && tcp.is_ack());
+            tcp.get_sequence().wrapping_add(1)
+        };
+        let frame = build_ipv4_frame(
+            DST_IP_ADDR,
+            Layers::Tcp(Tcp::new_ack(1024, 80, 1001, sequence, 65535)),
+            &[],
+        );
+        redirector.handle_frame(&frame).await;
+        frames.lock().unwrap().clear();
+
+        (1001, sequence)
+    }
+
+    /// Spawns a SOCKS5 proxy without authentication accepting the given number of connections
+    /// in turn. The connections are held until all of them are accepted.
+    fn spawn_proxies(n: usize) -> (SocketAddrV4, std::thread::JoinHandle<()>) {
+        use std::io::Read;
+
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = match listener.local_addr().unwrap() {
+            SocketAddr::V4(addr) => addr,
+            _ => unreachable!(),
+        };
+        let handle = std::thread::spawn(move || {
+            let mut streams = Vec::new();
+            for _ in 0..n {
+                let mut stream = listener.accept().unwrap().0;
+                let mut buffer = [0u8; 10];
+                stream.read_exact(&mut buffer[..2]).unwrap();
+                let n = buffer[1] as usize;
+                stream.read_exact(&mut buffer[..n]).unwrap();
+                stream.write_all(&[0x05, 0x00]).unwrap();
+                stream.read_exact(&mut buffer).unwrap();
+                stream
+                    .write_all(&[0x05, 0x00, 0x00, 0x01, 0, 0, 0, 0, 0, 0])
+                    .unwrap();
+                streams.push(stream);
+            }
+        });
+
+        (addr, handle)
+    }
+
+    /// Handles a SYN from the given source port, and returns the last segment sent in answer.
+    async fn handle_syn(
+        redirector: &mut Redirector,
+        frames: &Arc<Mutex<Vec<Vec<u8>>>>,
+        src_port: u16,
+    ) -> Option<Tcp> {
+        let frame = build_ipv4_frame(
+            DST_IP_ADDR,
+            Layers::Tcp(Tcp::new_syn(src_port, 80, 1000, 65535)),
+            &[],
+        );
+        redirector.handle_frame(&frame).await;
+
+        let frames = frames.lock().unwrap();
+        let indicator = Indicator::from(frames.last()?).unwrap();
+        indicator.get_tcp().cloned()
+    }
+
+    #[tokio::test]
+    async fn connection_limit_reset() {
+        let (remote, handle) = spawn_proxies(2);
+        let (mut redirector, frames) = new_redirector_to(remote);
+        redirector.set_connection_limit(1, ConnectionLimitPolicy::Reset);
+        let (src_sequence, _) = open_connection(&mut redirector, &frames).await;
+
+        // The connection over the limit is reset
+        let tcp = handle_syn(&mut redirector, &frames, 1025).await.unwrap();
+        assert!(tcp.is_rst());
+        assert_eq!(tcp.get_dst(), 1025);
+        assert_eq!(tcp.get_acknowledgement(), 1001);
+        assert_eq!(redirector.flows().len(), 1);
+
+        // The capacity frees after the first connection is reset by the source
+        let frame = build_ipv4_frame(
+            DST_IP_ADDR,
+            Layers::Tcp(Tcp::new_rst(1024, 80, src_sequence, 0, 0)),
+            &[],
+        );
+        redirector.handle_frame(&frame).await;
+        frames.lock().unwrap().clear();
+        let tcp = handle_syn(&mut redirector, &frames, 1025).await.unwrap();
+        assert!(tcp.is_syn() && tcp.is_ack());
+        assert_eq!(tcp.get_dst(), 1025);
+
+        handle.join().unwrap();
+    }
+
+    #[tokio::test]
+    async fn connection_limit_drop() {
+        let (remote, handle) = spawn_proxies(1);
+        let (mut redirector, frames) = new_redirector_to(remote);
+        let stats = redirector.get_stats();
+        redirector.set_connection_limit(1, ConnectionLimitPolicy::Drop);
+        open_connection(&mut redirector, &frames).await;
+
+        // The SYN over the limit is dropped silently
+        assert!(handle_syn(&mut redirector, &frames, 1025).await.is_none());
+        assert_eq!(stats.get_dropped(DropReason::ConnectionLimit), 1);
+
+        // Retransmitted SYNs of the connections tracked are not rejected
+        let tcp = handle_syn(&mut redirector, &frames, 1024).await;
+        assert!(tcp.map_or(true, |tcp| !tcp.is_rst()));
+        assert_eq!(stats.get_dropped(DropReason::ConnectionLimit), 1);
+
+        handle.join().unwrap();
+    }
+
+    #[tokio::test]
+    async fn filter_excluded_packets() {
+        let (remote, handle) = spawn_proxies(1);
+        let (mut redirector, frames) = new_redirector_to(remote);
+        let stats = redirector.get_stats();
+        let rules = vec!["port=53".parse().unwrap()];
+        redirector.set_filter(Box::new(filter::exclude(rules)));
+
+        // DNS is dropped before being forwarded
+        let frame = build_ipv4_frame(DST_IP_ADDR, Layers::Udp(Udp::new(1024, 53)), b"query");
+        redirector.handle_frame(&frame).await;
+        assert_eq!(stats.get_dropped(DropReason::Filtered), 1);
+        assert!(frames.lock().unwrap().is_empty());
+        assert!(redirector.flows().is_empty());
+
+        // HTTP is permitted
+        let tcp = handle_syn(&mut redirector, &frames, 1024).await.unwrap();
+        assert!(tcp.is_syn() && tcp.is_ack());
+        assert_eq!(stats.get_dropped(DropReason::Filtered), 1);
+
+        handle.join().unwrap();
+    }
+
+    /// Get the DSCP and ECN of the IPv4 layer of the given frame.
+    fn get_dscp_and_ecn(frame: &[u8]) -> (u8, u8) {
+        let indicator = Indicator::from(frame).unwrap();
+        let ipv4 = indicator.get_ipv4().unwrap();
+
+        (ipv4.get_dscp(), ipv4.get_ecn())
+    }
+
+    #[tokio::test]
+    async fn preserve_dscp() {
+        let (mut redirector, frames) = new_redirector();
+        let ethernet =
+            Ethernet::new(LayerTypes::Ipv4, SRC_HARDWARE_ADDR, LOCAL_HARDWARE_ADDR).unwrap();
+        let mut ipv4 = Ipv4::new(1, LayerTypes::Udp, SRC_IP_ADDR, DST_IP_ADDR).unwrap();
+        ipv4.set_dscp(46);
+        ipv4.set_ecn(packet::layer::ipv4::ECN_CE);
+        let mut udp = Udp::new(1024, 9);
+        udp.set_ipv4_layer(&ipv4);
+        let frame = PacketBuilder::new()
+            .ethernet(ethernet)
+            .ipv4(ipv4)
+            .layer(Layers::Udp(udp))
+            .payload(b"discard")
+            .build()
+            .unwrap();
+        redirector.handle_frame(&frame).await;
+
+        // The DSCP is preserved in the packets sent back, but the ECN is not echoed
+        let dst = SocketAddrV4::new(DST_IP_ADDR, 80);
+        let src = SocketAddrV4::new(SRC_IP_ADDR, 1024);
+        frames.lock().unwrap().clear();
+        redirector
+            .tx
+            .lock()
+            .unwrap()
+            .send_tcp_ack_0(dst, src)
+            .unwrap();
+        let frame = frames.lock().unwrap().pop().unwrap();
+        assert_eq!(get_dscp_and_ecn(&frame), (46, 0));
+    }
+
+    #[test]
+    fn override_dscp() {
+        let (mut forwarder, frames) = new_forwarder();
+        let dst = SocketAddrV4::new(DST_IP_ADDR, 80);
+        let src = SocketAddrV4::new(SRC_IP_ADDR, 1024);
+        forwarder.send_tcp_ack_0(dst, src).unwrap();
+        forwarder.set_ipv4_dscp(DST_IP_ADDR, 46);
+        forwarder.send_tcp_ack_0(dst, src).unwrap();
+        forwarder.set_dscp(Some(10));
+        forwarder.send_tcp_ack_0(dst, src).unwrap();
+        // The preserved DSCP is back once the override is removed, and cleared by DSCP 0
+        forwarder.set_dscp(None);
+        forwarder.send_tcp_ack_0(dst, src).unwrap();
+        forwarder.set_ipv4_dscp(DST_IP_ADDR, 0);
+        forwarder.send_tcp_ack_0(dst, src).unwrap();
+
+        let frames = frames.lock().unwrap();
+        let dscps: Vec<(u8, u8)> = frames.iter().map(|frame| get_dscp_and_ecn(frame)).collect();
+        assert_eq!(dscps, [(0, 0), (46, 0), (10, 0), (46, 0), (0, 0)]);
+    }
+
+    #[tokio::test]
+    async fn reset_half_open_connection() {
+        let (remote, handle) = spawn_proxies(2);
+        let (mut redirector, frames) = new_redirector_to(remote);
+        let stats = redirector.get_stats();
+        redirector.set_connection_table(16, Duration::from_secs(300));
+        redirector.set_half_open_timeout(Duration::from_secs(0));
+        open_connection(&mut redirector, &frames).await;
+        // The handshake of the second connection is never completed
+        let tcp = handle_syn(&mut redirector, &frames, 1025).await.unwrap();
+        assert!(tcp.is_syn() && tcp.is_ack());
+        assert_eq!(redirector.flows().len(), 2);
+        frames.lock().unwrap().clear();
+
+        redirector.connections_last_purge =
+            Instant::now() - CONNECTION_TABLE_PURGE_INTERVAL - Duration::from_secs(1);
+        redirector.maintain().await.unwrap();
+        assert_eq!(stats.get_dropped(DropReason::HalfOpenTimeout), 1);
+        assert_eq!(redirector.flows().len(), 1);
+        let frames = frames.lock().unwrap();
+        let indicator = Indicator::from(frames.last().unwrap()).unwrap();
+        let tcp = indicator.get_tcp().unwrap();
+        assert!(tcp.is_rst());
+        assert_eq!(tcp.get_dst(), 1025);
+
+        handle.join().unwrap();
+    }
+
+    #[tokio::test]
+    async fn reorder_tcp_payload() {
+        let (remote, handle) = spawn_proxy();
+        let (mut redirector, frames) = new_redirector_to(remote);
+        let (src_sequence, sequence) = open_connection(&mut redirector, &frames).await;
+        let send = |src_sequence: u32, payload: &[u8]| {
+            build_ipv4_frame(
+                DST_IP_ADDR,
+                Layers::Tcp(Tcp::new_ack(1024, 80, src_sequence, sequence, 65535)),
+                payload,
+            )
+        };
+        let last_acknowledgement = || {
+            let frames = frames.lock().unwrap();
+            let indicator = Indicator::from(frames.last().unwrap()).unwrap();
+            indicator.get_tcp().unwrap().get_acknowledgement()
+        };
+
+        // The segment out of order is held, and the expected sequence is acknowledged
+        redirector
+            .handle_frame(&send(src_sequence + 5, b" world"))
+            .await;
+        assert_eq!(last_acknowledgement(), src_sequence);
+        redirector.handle_frame(&send(src_sequence, b"hello")).await;
+        assert_eq!(last_acknowledgement(), src_sequence + 11);
+        // Retransmissions are not forwarded twice
+        redirector
+            .handle_frame(&send(src_sequence + 5, b" world"))
+            .await;
+
+        drop(redirector);
+        assert_eq!(handle.join().unwrap(), b"hello world");
+    }
+
+    /// Get the ARP layers of the given frames and their destination hardware addresses.
+    fn get_arps(frames: &[Vec<u8>]) -> Vec<(Arp, HardwareAddr)> {
+        frames
+            .iter()
+            .filter_map(|frame| {
+                let indicator = Indicator::from(frame)?;
+                let dst_hardware_addr = indicator.get_ethernet()?.get_dst();
+                indicator
+                    .get_arp()
+                    .map(|arp| (arp.clone(), dst_hardware_addr))
+            })
+            .collect()
+    }
+
+    #[test]
+    fn send_gratuitous_arp() {
+        let (mut forwarder, frames) = new_forwarder();
+        forwarder
+            .send_gratuitous_arp(LOCAL_IP_ADDR, LOCAL_HARDWARE_ADDR)
+            .unwrap();
+
+        let arps = get_arps(&frames.lock().unwrap());
+        assert_eq!(arps.len(), 1);
+        let (ref arp, dst_hardware_addr) = arps[0];
+        assert_eq!(dst_hardware_addr, HardwareAddr::broadcast());
+        assert!(arp.is_request() && arp.is_gratuitous());
+        assert_eq!(arp.get_src_hardware_addr(), LOCAL_HARDWARE_ADDR);
+        assert_eq!(arp.get_src(), LOCAL_IP_ADDR);
+        assert_eq!(arp.get_dst(), LOCAL_IP_ADDR);
+    }
+
+    #[tokio::test]
+    async fn announce_on_startup() {
+        let (mut redirector, frames) = new_redirector();
+        redirector.maintain().await.unwrap();
+        redirector.maintain().await.unwrap();
+        // Announced only once without an interval
+        let arps = get_arps(&frames.lock().unwrap());
+        assert_eq!(arps.len(), 1);
+        assert_eq!(arps[0].0.get_src(), LOCAL_IP_ADDR);
+
+        frames.lock().unwrap().clear();
+        redirector.set_gratuitous_arp_interval(Some(Duration::from_secs(0)));
+        std::thread::sleep(Duration::from_millis(1));
+        redirector.maintain().await.unwrap();
+        assert_eq!(get_arps(&frames.lock().unwrap()).len(), 1);
+
+        // Nothing is sent in a dry run
+        let (mut redirector, frames) = new_redirector();
+        redirector.set_dry_run(true);
+        redirector.maintain().await.unwrap();
+        assert!(get_arps(&frames.lock().unwrap()).is_empty());
+    }
+
+    #[tokio::test]
+    async fn ignore_traffic_of_proxy() {
+        let proxy = SocketAddrV4::new(Ipv4Addr::new(10, 6, 0, 10), 1080);
+        let backup = SocketAddrV4::new(Ipv4Addr::new(192, 0, 2, 10), 1080);
+        let (mut redirector, frames) = new_redirector_to(proxy);
+        let stats = redirector.get_stats();
+        let mut remotes = SocksPool::new(proxy, SelectMode::Failover, DEFAULT_SOCKS_POOL_COOLDOWN);
+        remotes.insert(backup);
+        redirector.set_remotes(remotes);
+
+        for remote in [proxy, backup] {
+            let frame = build_ipv4_frame(
+                *remote.ip(),
+                Layers::Tcp(Tcp::new_syn(1024, remote.port(), 1000, 65535)),
+                &[],
+            );
+            redirector.handle_frame(&frame).await;
+        }
+        assert_eq!(stats.get_dropped(DropReason::Loopback), 2);
+        assert!(frames.lock().unwrap().is_empty());
+        assert!(redirector.flows().is_empty());
+    }
+
+    #[tokio::test]
+    async fn drop_igmp() {
+        use packet::layer::igmp::{Igmp, IgmpType};
+
+        let (mut redirector, frames) = new_redirector();
+        let stats = redirector.get_stats();
+        let igmp = Igmp::new(IgmpType::V2MembershipReport, 0, Ipv4Addr::new(239, 1, 1, 1));
+        let frame = build_ipv4_frame(Ipv4Addr::new(239, 1, 1, 1), Layers::Igmp(igmp), &[]);
+        redirector.handle_frame(&frame).await;
+
+        assert_eq!(stats.get_seen(LayerTypes::Igmp), 1);
+        assert_eq!(stats.get_dropped(DropReason::MulticastControl), 1);
+        assert!(frames.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn reply_fragmentation_needed() {
+        let (mut redirector, frames) = new_redirector();
+        let stats = redirector.get_stats();
+        redirector.set_enforce_mtu(true);
+        let payload = vec![0u8; 1600];
+        let mut frame = build_ipv4_frame(DST_IP_ADDR, Layers::Udp(Udp::new(1024, 53)), &payload);
+        // Don't fragment
+        frame[ETHERNET_HEADER_SIZE + 6] |= 0x40;
+        frame[ETHERNET_HEADER_SIZE + 10..ETHERNET_HEADER_SIZE + 12].copy_from_slice(&[0, 0]);
+        let checksum =
+            pnet::util::checksum(&frame[ETHERNET_HEADER_SIZE..ETHERNET_HEADER_SIZE + 20], 5);
+        frame[ETHERNET_HEADER_SIZE + 10..ETHERNET_HEADER_SIZE + 12]
+            .copy_from_slice(&checksum.to_be_bytes());
+        redirector.handle_frame(&frame).await;
+        assert_eq!(stats.get_dropped(DropReason::FragmentationNeeded), 1);
+
+        let frames = frames.lock().unwrap();
+        assert_eq!(frames.len(), 1);
+        let indicator = Indicator::from(&frames[0]).unwrap();
+        let ipv4 = indicator.get_ipv4().unwrap();
+        assert_eq!(ipv4.get_src(), LOCAL_IP_ADDR);
+        assert_eq!(ipv4.get_dst(), SRC_IP_ADDR);
+        let icmp = indicator.get_icmp().unwrap();
+        assert!(icmp.is_fragmentation_needed());
+        assert_eq!(icmp.get_next_hop_mtu(), Some(1500));
+        assert_eq!(icmp.get_original_dst(), Some(DST_IP_ADDR));
+        // The UDP header is quoted
+        let quoted = &frames[0][indicator.get_size()..];
+        assert_eq!(quoted.len(), 20 + 8);
+        assert_eq!(
+            quoted[20..],
+            frame[ETHERNET_HEADER_SIZE + 20..ETHERNET_HEADER_SIZE + 28]
+        );
+    }
+
+    #[tokio::test]
+    async fn fail_over_to_next_proxy() {
+        // The first proxy refuses connections
+        let unreachable = {
+            let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+            match listener.local_addr().unwrap() {
+                SocketAddr::V4(addr) => addr,
+                _ => unreachable!(),
+            }
+        };
+        let (remote, handle) = spawn_proxy();
+        let (mut redirector, frames) = new_redirector_to(unreachable);
+        let mut remotes = SocksPool::new(
+            unreachable,
+            SelectMode::Failover,
+            DEFAULT_SOCKS_POOL_COOLDOWN,
+        );
+        remotes.insert(remote);
+        redirector.set_remotes(remotes);
+        let (src_sequence, sequence) = open_connection(&mut redirector, &frames).await;
+        assert!(!redirector.remotes.is_up(unreachable));
+        assert!(redirector.remotes.is_up(remote));
+        assert_eq!(redirector.remotes.peek(), remote);
+
+        let frame = build_ipv4_frame(
+            DST_IP_ADDR,
+            Layers::Tcp(Tcp::new_ack(1024, 80, src_sequence, sequence, 65535)),
+            b"hello",
+        );
+        redirector.handle_frame(&frame).await;
+
+        drop(redirector);
+        assert_eq!(handle.join().unwrap(), b"hello");
+    }
+
+    #[tokio::test]
+    async fn drop_martian_source() {
+        let (mut redirector, frames) = new_redirector();
+        let stats = redirector.get_stats();
+        for src in [Ipv4Addr::new(127, 0, 0, 2), Ipv4Addr::BROADCAST].iter() {
+            let ethernet =
+                Ethernet::new(LayerTypes::Ipv4, SRC_HARDWARE_ADDR, LOCAL_HARDWARE_ADDR).unwrap();
+            let ipv4 = Ipv4::new(1, LayerTypes::Udp, *src, DST_IP_ADDR).unwrap();
+            let mut udp = Udp::new(1024, 53);
+            udp.set_ipv4_layer(&ipv4);
+            let frame = PacketBuilder::new()
+                .ethernet(ethernet)
+                .ipv4(ipv4)
+                .layer(Layers::Udp(udp))
+                .payload(b"query")
+                .build()
+                .unwrap();
+            redirector.handle_frame(&frame).await;
+        }
+
+        assert_eq!(stats.get_dropped(DropReason::Martian), 2);
+        assert!(frames.lock().unwrap().is_empty());
+        assert!(redirector.datagrams.is_empty());
+    }
+
+    /// Handles a SYN carrying the given fast open cookie and data, and returns the SYN/ACK
+    /// answered.
+    async fn handle_fast_open_syn(
+        redirector: &mut Redirector,
+        frames: &Arc<Mutex<Vec<Vec<u8>>>>,
+        cookie: &[u8],
+        payload: &[u8],
+    ) -> Tcp {
+        let mut tcp = Tcp::new_syn(1024, 80, 1000, 65535);
+        tcp.set_fast_open_cookie(Some(cookie));
+        let frame = build_ipv4_frame(DST_IP_ADDR, Layers::Tcp(tcp), payload);
+        redirector.handle_frame(&frame).await;
+
+        let frames = frames.lock().unwrap();
+        let indicator = Indicator::from(frames.last().unwrap()).unwrap();
+        let tcp = indicator.get_tcp().unwrap();
+        assert!(tcp.is_syn() && tcp.is_ack());
+
+        tcp.clone()
+    }
+
+    #[tokio::test]
+    async fn fast_open_cookie_request() {
+        let (remote, handle) = spawn_proxy();
+        let (mut redirector, frames) = new_redirector_to(remote);
+        let cookie = redirector.fast_open_cookies.generate(SRC_IP_ADDR);
+
+        let tcp = handle_fast_open_syn(&mut redirector, &frames, &[], &[]).await;
+        assert_eq!(tcp.get_acknowledgement(), 1001);
+        assert_eq!(tcp.get_fast_open_cookie(), Some(&cookie[..]));
+
+        drop(redirector);
+        assert!(handle.join().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn fast_open_forward_data() {
+        let (remote, handle) = spawn_proxy();
+        let (mut redirector, frames) = new_redirector_to(remote);
+        let cookie = redirector.fast_open_cookies.generate(SRC_IP_ADDR);
+
+        // The data in the SYN is acknowledged and forwarded
+        let tcp = handle_fast_open_syn(&mut redirector, &frames, &cookie, b"hello").await;
+        assert_eq!(tcp.get_acknowledgement(), 1006);
+        assert_eq!(tcp.get_fast_open_cookie(), None);
+        let flows = redirector.flows();
+        assert_eq!(flows[0].up_bytes, 5);
+
+        drop(redirector);
+        assert_eq!(handle.join().unwrap(), b"hello");
+    }
+
+    #[tokio::test]
+    async fn fast_open_invalid_cookie() {
+        let (remote, handle) = spawn_proxy();
+        let (mut redirector, frames) = new_redirector_to(remote);
+        let cookie = redirector.fast_open_cookies.generate(SRC_IP_ADDR);
+
+        // The data in the SYN is left to be retransmitted, and a valid cookie is replied
+        let tcp = handle_fast_open_syn(
+            &mut redirector,
+            &frames,
+            &[1, 2, 3, 4, 5, 6, 7, 8],
+            b"hello",
+        )
+        .await;
+        assert_eq!(tcp.get_acknowledgement(), 1001);
+        assert_eq!(tcp.get_fast_open_cookie(), Some(&cookie[..]));
+
+        drop(redirector);
+        assert!(handle.join().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn drop_truncated_frame() {
+        let (mut redirector, frames) = new_redirector();
+        let stats = redirector.get_stats();
+
+        // The frame is truncated in the payload
+        let frame = build_ipv4_frame(DST_IP_ADDR, Layers::Udp(Udp::new(1024, 53)), &[0u8; 100]);
+        redirector.handle_frame(&frame[..frame.len() - 50]).await;
+        assert_eq!(stats.get_dropped(DropReason::Truncated), 1);
+        assert_eq!(stats.get_parse_errors(LayerTypes::Ipv4), 1);
+
+        // The frame is truncated in the TCP header
+        let frame = build_ipv4_frame(
+            DST_IP_ADDR,
+            Layers::Tcp(Tcp::new_syn(1024, 80, 1000, 65535)),
+            &[],
+        );
+        redirector
+            .handle_frame(&frame[..ETHERNET_HEADER_SIZE + 20 + 10])
+            .await;
+        assert_eq!(stats.get_parse_errors(LayerTypes::Tcp), 1);
+
+        assert!(frames.lock().unwrap().is_empty());
+        assert!(redirector.datagrams.is_empty());
+        assert!(redirector.streams.is_empty());
+    }
+
+    #[test]
+    fn negotiate_ecn() {
+        let dst = SocketAddrV4::new(DST_IP_ADDR, 80);
+        let src = SocketAddrV4::new(SRC_IP_ADDR, 1024);
+        let ack_syn = |ecn: bool, requested: bool| {
+            let (mut forwarder, frames) = new_forwarder();
+            forwarder.set_tcp_ecn(ecn);
+            forwarder.set_tcp_remote_ecn(dst, src, requested);
+            forwarder.send_tcp_ack_syn(dst, src).unwrap();
+            let frame = frames.lock().unwrap().pop().unwrap();
+            let indicator = Indicator::from(&frame).unwrap();
+
+            indicator.get_tcp().unwrap().clone()
+        };
+
+        assert!(ack_syn(true, true).is_ecn_setup_ack_syn());
+        // ECN is not negotiated if it is disabled or not requested
+        assert!(!ack_syn(false, true).is_ece());
+        assert!(!ack_syn(true, false).is_ece());
+    }
+
+    #[test]
+    fn echo_congestion_experienced() {
+        let (mut forwarder, frames) = new_forwarder();
+        let dst = SocketAddrV4::new(DST_IP_ADDR, 80);
+        let src = SocketAddrV4::new(SRC_IP_ADDR, 1024);
+        forwarder.set_tcp_ecn(true);
+        forwarder.set_tcp_remote_ecn(dst, src, true);
+        let send_ack = |forwarder: &mut Forwarder| {
+            forwarder.send_tcp_ack_0(dst, src).unwrap();
+            let frame = frames.lock().unwrap().pop().unwrap();
+            let indicator = Indicator::from(&frame).unwrap();
+
+            indicator.get_tcp().unwrap().is_ece()
+        };
+        assert!(!send_ack(&mut forwarder));
+
+        // The ECE flag is set until a CWR is received
+        forwarder.update_tcp_ecn_echo(dst, src, false, true);
+        assert!(send_ack(&mut forwarder));
+        assert!(send_ack(&mut forwarder));
+        forwarder.update_tcp_ecn_echo(dst, src, true, false);
+        assert!(!send_ack(&mut forwarder));
+
+        // Marks are not echoed in connections not negotiating ECN
+        let other = SocketAddrV4::new(SRC_IP_ADDR, 1025);
+        forwarder.update_tcp_ecn_echo(dst, other, false, true);
+        forwarder.send_tcp_ack_0(dst, other).unwrap();
+        let frame = frames.lock().unwrap().pop().unwrap();
+        assert!(!Indicator::from(&frame).unwrap().get_tcp().unwrap().is_ece());
+    }
+
+    #[tokio::test]
+    async fn echo_congestion_experienced_redirected() {
+        let (remote, handle) = spawn_proxy();
+        let (mut redirector, frames) = new_redirector_to(remote);
+        redirector.tx.lock().unwrap().set_tcp_ecn(true);
+        let mut syn = Tcp::new_syn(1024, 80, 1000, 65535);
+        syn.set_flag(tcp::Flags::ECE | tcp::Flags::CWR, true);
+        redirector
+            .handle_frame(&build_ipv4_frame(DST_IP_ADDR, Layers::Tcp(syn), &[]))
+            .await;
+        let sequence = {
+            let frames = frames.lock().unwrap();
+            let indicator = Indicator::from(frames.last().unwrap()).unwrap();
+            let tcp = indicator.get_tcp().unwrap();
+            assert!(tcp.is_ecn_setup_ack_syn());
+            tcp.get_sequence().wrapping_add(1)
+        };
+        let frame = build_ipv4_frame(
+            DST_IP_ADDR,
+            Layers::Tcp(Tcp::new_ack(1024, 80, 1001, sequence, 65535)),
+            &[],
+        );
+        redirector.handle_frame(&frame).await;
+
+        // A segment marked congestion experienced
+        let ethernet =
+            Ethernet::new(LayerTypes::Ipv4, SRC_HARDWARE_ADDR, LOCAL_HARDWARE_ADDR).unwrap();
+        let mut ipv4 = Ipv4::new(1, LayerTypes::Tcp, SRC_IP_ADDR, DST_IP_ADDR).unwrap();
+        ipv4.set_ecn(packet::layer::ipv4::ECN_CE);
+        let mut tcp = Tcp::new_ack(1024, 80, 1001, sequence, 65535);
+        tcp.set_ipv4_layer(&ipv4);
+        let frame = PacketBuilder::new()
+            .ethernet(ethernet)
+            .ipv4(ipv4)
+            .layer(Layers::Tcp(tcp))
+            .payload(b"hello")
+            .build()
+            .unwrap();
+        frames.lock().unwrap().clear();
+        redirector.handle_frame(&frame).await;
+        {
+            let frames = frames.lock().unwrap();
+            let indicator = Indicator::from(frames.last().unwrap()).unwrap();
+            let tcp = indicator.get_tcp().unwrap();
+            assert_eq!(tcp.get_acknowledgement(), 1006);
+            assert!(tcp.is_ece());
+        }
+
+        drop(redirector);
+        assert_eq!(handle.join().unwrap(), b"hello");
+    }
+
+    #[test]
+    fn send_checksum_offload() {
+        let (mut forwarder, frames) = new_forwarder();
+        let dst = SocketAddrV4::new(DST_IP_ADDR, 80);
+        let src = SocketAddrV4::new(SRC_IP_ADDR, 1024);
+        forwarder.set_checksum_offload(true);
+        forwarder.send_tcp_ack_0(dst, src).unwrap();
+        forwarder.send_udp(dst, src, b"reply").unwrap();
+        forwarder
+            .send_icmp_echo_reply(SRC_IP_ADDR, 0x1234, 1, b"ping")
+            .unwrap();
+
+        let frames = frames.lock().unwrap();
+        // IPv4 and TCP
+        let ipv4 = ETHERNET_HEADER_SIZE;
+        let transport = ipv4 + 20;
+        assert_eq!(frames[0][ipv4 + 10..ipv4 + 12], [0, 0]);
+        assert_eq!(frames[0][transport + 16..transport + 18], [0, 0]);
+        // UDP
+        assert_eq!(frames[1][ipv4 + 10..ipv4 + 12], [0, 0]);
+        assert_eq!(frames[1][transport + 6..transport + 8], [0, 0]);
+        // ICMP is always computed
+        assert_eq!(frames[2][ipv4 + 10..ipv4 + 12], [0, 0]);
+        let icmp = &frames[2][transport..];
+        assert_eq!(icmp[2..4], pnet::util::checksum(icmp, 1).to_be_bytes());
+    }
+
+    #[tokio::test]
+    async fn keepalive_idle_connection() {
+        let (remote, handle) = spawn_proxy();
+        let (mut redirector, frames) = new_redirector_to(remote);
+        redirector.set_connection_table(16, Duration::from_secs(300));
+        redirector.set_keepalive_interval(Some(Duration::from_millis(0)));
+        let (src_sequence, sequence) = open_connection(&mut redirector, &frames).await;
+
+        // The idle connection is probed with the sequence right before the next
+        std::thread::sleep(Duration::from_millis(10));
+        redirector.connections_last_purge =
+            Instant::now() - CONNECTION_TABLE_PURGE_INTERVAL - Duration::from_secs(1);
+        redirector.maintain().await.unwrap();
+        {
+            let frames = frames.lock().unwrap();
+            let segments: Vec<_> = frames
+                .iter()
+                .filter_map(|frame| Indicator::from(frame).unwrap().get_tcp().cloned())
+                .collect();
+            assert_eq!(segments.len(), 1);
+            let tcp = &segments[0];
+            assert!(tcp.is_ack() && !tcp.is_syn() && !tcp.is_fin());
+            assert_eq!(tcp.get_src(), 80);
+            assert_eq!(tcp.get_dst(), 1024);
+            assert_eq!(tcp.get_sequence(), sequence.wrapping_sub(1));
+            assert_eq!(tcp.get_acknowledgement(), src_sequence);
+            assert!(tcp.get_payload().is_empty());
+        }
+        assert_eq!(redirector.flows().len(), 1);
+
+        drop(redirector);
+        handle.join().unwrap();
+    }
+
+    #[tokio::test]
+    async fn record_response_latency() {
+        use std::io::Read;
+
+        // The proxy responds to a request of 5 bytes after a while
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let remote = match listener.local_addr().unwrap() {
+            SocketAddr::V4(addr) => addr,
+            _ => unreachable!(),
+        };
+        let handle = std::thread::spawn(move || {
+            let mut stream = listener.accept().unwrap().0;
+            let mut buffer = [0u8; 10];
+            stream.read_exact(&mut buffer[..2]).unwrap();
+            let n = buffer[1] as usize;
+            stream.read_exact(&mut buffer[..n]).unwrap();
+            stream.write_all(&[0x05, 0x00]).unwrap();
+            stream.read_exact(&mut buffer).unwrap();
+            stream
+                .write_all(&[0x05, 0x00, 0x00, 0x01, 0, 0, 0, 0, 0, 0])
+                .unwrap();
+            stream.read_exact(&mut buffer[..5]).unwrap();
+            std::thread::sleep(Duration::from_millis(50));
+            stream.write_all(b"world").unwrap();
+            let mut received = Vec::new();
+            stream.read_to_end(&mut received).unwrap_or(0);
+        });
+        let (mut redirector, frames) = new_redirector_to(remote);
+        let latency = redirector.get_stats().get_latency();
+        let (src_sequence, sequence) = open_connection(&mut redirector, &frames).await;
+
+        let frame = build_ipv4_frame(
+            DST_IP_ADDR,
+            Layers::Tcp(Tcp::new_ack(1024, 80, src_sequence, sequence, 65535)),
+            b"hello",
+        );
+        redirector.handle_frame(&frame).await;
+        assert_eq!(latency.get_count(), 0);
+
+        // Wait for the response
+        for _ in 0..200 {
+            if latency.get_count() > 0 {
+                break;
+            }
+            time::delay_for(Duration::from_millis(10)).await;
+        }
+        assert_eq!(latency.get_count(), 1);
+        assert!(latency.get_max() >= Duration::from_millis(50));
+        assert!(latency.get_percentile(50.0).unwrap() >= Duration::from_millis(50));
+        assert_eq!(redirector.flows()[0].down_bytes, 5);
+
+        drop(redirector);
+        handle.join().unwrap();
+    }
+
+    #[tokio::test]
+    async fn redact_forwarded_payload() {
+        use inspect::Redactor;
+
+        let (remote, handle) = spawn_proxy();
+        let (mut redirector, frames) = new_redirector_to(remote);
+        redirector.set_inspection_hook(Arc::new(Redactor::new(vec![b"secret".to_vec()], b"x")));
+        let (src_sequence, sequence) = open_connection(&mut redirector, &frames).await;
+
+        let frame = build_ipv4_frame(
+            DST_IP_ADDR,
+            Layers::Tcp(Tcp::new_ack(1024, 80, src_sequence, sequence, 65535)),
+            b"my secret!",
+        );
+        redirector.handle_frame(&frame).await;
+
+        // The acknowledgement covers the original payload
+        {
+            let frames = frames.lock().unwrap();
+            let indicator = Indicator::from(frames.last().unwrap()).unwrap();
+            let tcp = indicator.get_tcp().unwrap();
+            assert_eq!(tcp.get_acknowledgement(), src_sequence + 10);
+        }
+
+        drop(redirector);
+        assert_eq!(handle.join().unwrap(), b"my x!");
+    }
+
+    #[test]
+    fn redact_payload_sent_to_source() {
+        use inspect::Redactor;
+
+        let (mut forwarder, frames) = new_forwarder();
+        forwarder.set_inspection_hook(Arc::new(Redactor::new(vec![b"secret".to_vec()], b"x")));
+        let dst = SocketAddrV4::new(DST_IP_ADDR, 53);
+        let src = SocketAddrV4::new(SRC_IP_ADDR, 1024);
+        forwarder.forward_udp(dst, src, b"token=secret").unwrap();
+
+        // The lengths are of the replacement
+        let frames = frames.lock().unwrap();
+        let indicator = Indicator::from(&frames[0]).unwrap();
+        assert_eq!(indicator.get_ipv4().unwrap().get_total_length(), 20 + 8 + 7);
+        let udp = indicator.get_udp().unwrap();
+        assert_eq!(udp.get_length(), 8 + 7);
+        let size = indicator.get_size();
+        assert_eq!(&frames[0][size..size + 7], b"token=x");
+    }
+
+    #[test]
+    fn drop_exhausted_buffer_pool() {
+        let (mut forwarder, frames) = new_forwarder();
+        let stats = Arc::new(Stats::new());
+        forwarder.set_stats(Arc::clone(&stats));
+        let held: Vec<PooledBuffer> = (0..BUFFER_POOL_CAPACITY)
+            .map(|_| forwarder.pool.get().unwrap())
+            .collect();
+        let dst = SocketAddrV4::new(DST_IP_ADDR, 53);
+        let src = SocketAddrV4::new(SRC_IP_ADDR, 1024);
+        forwarder.forward_udp(dst, src, b"answer").unwrap();
+        assert!(frames.lock().unwrap().is_empty());
+        assert_eq!(stats.get_dropped(DropReason::BufferExhausted), 1);
+
+        // Frames are sent again once buffers are returned
+        drop(held);
+        forwarder.forward_udp(dst, src, b"answer").unwrap();
+        assert_eq!(frames.lock().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn send_fixed_ttl() {
+        let (mut forwarder, frames) = new_forwarder();
+        forwarder.set_ttl_mode(TtlMode::Fixed(64));
+        let dst = SocketAddrV4::new(DST_IP_ADDR, 53);
+        let src = SocketAddrV4::new(SRC_IP_ADDR, 1024);
+        forwarder.forward_udp(dst, src, b"answer").unwrap();
+        forwarder.set_ttl_mode(TtlMode::Decrement);
+        forwarder.forward_udp(dst, src, b"answer").unwrap();
+
+        let frames = frames.lock().unwrap();
+        let ttls: Vec<u8> = frames
+            .iter()
+            .map(|frame| {
+                // The checksum of the header is correct
+                let header = &frame[ETHERNET_HEADER_SIZE..ETHERNET_HEADER_SIZE + 20];
+                assert_eq!(
+                    u16::from_be_bytes([header[10], header[11]]),
+                    pnet::util::checksum(header, 5)
+                );
+                Indicator::from(frame)
+                    .unwrap()
+                    .get_ipv4()
+                    .unwrap()
+                    .get_ttl()
+            })
+            .collect();
+        assert_eq!(ttls, [64, 127]);
+    }
+
+    #[tokio::test]
+    async fn bridge_active_ftp_data() {
+        use std::io::Read;
+
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let remote = match listener.local_addr().unwrap() {
+            SocketAddr::V4(addr) => addr,
+            _ => unreachable!(),
+        };
+        // Serves the control connection and the BIND command, and returns the BIND request and
+        // the command received with the connections
+        let handle = std::thread::spawn(move || {
+            let mut buffer = [0u8; 1024];
+            let mut streams = Vec::new();
+            let mut requests = Vec::new();
+            for reply in [[0, 0, 0, 0, 0, 0], [127, 0, 0, 1, 0x07, 0xd0]] {
+                let mut stream = listener.accept().unwrap().0;
+                stream.read_exact(&mut buffer[..2]).unwrap();
+                let n = buffer[1] as usize;
+                stream.read_exact(&mut buffer[..n]).unwrap();
+                stream.write_all(&[0x05, 0x00]).unwrap();
+                stream.read_exact(&mut buffer[..10]).unwrap();
+                requests.push(buffer[..10].to_vec());
+                stream.write_all(&[0x05, 0x00, 0x00, 0x01]).unwrap();
+                stream.write_all(&reply).unwrap();
+                streams.push(stream);
+            }
+            let command = b"PORT 127,0,0,1,7,208\r\n";
+            streams[0].read_exact(&mut buffer[..command.len()]).unwrap();
+            let received = buffer[..command.len()].to_vec();
+            // The server connects to the proxy
+            streams[1]
+                .write_all(&[0x05, 0x00, 0x00, 0x01, 93, 184, 216, 34, 0, 20])
+                .unwrap();
+
+            (requests, received, streams)
+        });
+        let (mut redirector, frames) = new_redirector_to(remote);
+        redirector.set_active_ftp(true);
+
+        // Open the control connection
+        let frame = build_ipv4_frame(
+            DST_IP_ADDR,
+            Layers::Tcp(Tcp::new_syn(1024, 21, 1000, 65535)),
+            &[],
+        );
+        redirector.handle_frame(&frame).await;
+        let sequence = {
+            let frames = frames.lock().unwrap();
+            let indicator = Indicator::from(frames.last().unwrap()).unwrap();
+            indicator.get_tcp().unwrap().get_sequence().wrapping_add(1)
+        };
+        let frame = build_ipv4_frame(
+            DST_IP_ADDR,
+            Layers::Tcp(Tcp::new_ack(1024, 21, 1001, sequence, 65535)),
+            &[],
+        );
+        redirector.handle_frame(&frame).await;
+        let frame = build_ipv4_frame(
+            DST_IP_ADDR,
+            Layers::Tcp(Tcp::new_ack(1024, 21, 1001, sequence, 65535)),
+            b"PORT 10,6,0,1,4,1\r\n",
+        );
+        redirector.handle_frame(&frame).await;
+
+        // The data connection is opened to the source from port 20 of the server
+        let mut syn = None;
+        for _ in 0..100 {
+            syn = frames
+                .lock()
+                .unwrap()
+                .iter()
+                .filter_map(|frame| Indicator::from(frame))
+                .filter_map(|indicator| indicator.get_tcp().cloned())
+                .find(|tcp| tcp.is_syn() && !tcp.is_ack());
+            if syn.is_some() {
+                break;
+            }
+            time::delay_for(Duration::from_millis(10)).await;
         }
-
-        Ok(())
+        let syn = syn.unwrap();
+        assert_eq!(syn.get_src_ip_addr(), DST_IP_ADDR);
+        assert_eq!(syn.get_src(), 20);
+        assert_eq!(syn.get_dst_ip_addr(), SRC_IP_ADDR);
+        assert_eq!(syn.get_dst(), 1025);
+
+        drop(redirector);
+        let (requests, received, _) = handle.join().unwrap();
+        assert_eq!(
+            requests[0],
+            [0x05, 0x01, 0x00, 0x01, 93, 184, 216, 34, 0, 21]
+        );
+        // The BIND command is of the server
+        assert_eq!(
+            requests[1],
+            [0x05, 0x02, 0x00, 0x01, 93, 184, 216, 34, 0, 20]
+        );
+        // The address the proxy listens on is advertised instead
+        assert_eq!(received, b"PORT 127,0,0,1,7,208\r\n");
     }
 
-    async fn handle_tcp_syn(&mut self, indicator: &Indicator) -> io::Result<()> {
-        if let Some(tcp) = indicator.get_tcp() {
-            let dst = SocketAddrV4::new(tcp.get_dst_ip_addr(), tcp.get_dst());
-            let key = (tcp.get_src(), dst);
-            let is_exist = self.streams.get(&key).is_some();
+    #[tokio::test]
+    async fn shutdown_close_connection() {
+        let (remote, handle) = spawn_proxy();
+        let (mut redirector, frames) = new_redirector_to(remote);
+        let (src_sequence, sequence) = open_connection(&mut redirector, &frames).await;
+
+        let shutdown = Shutdown::new();
+        redirector.set_shutdown(shutdown.clone(), Duration::from_secs(60));
+        shutdown.trigger();
+        redirector.begin_shutdown().await;
+        assert!(!redirector.is_shutdown_completed());
+        {
+            let frames = frames.lock().unwrap();
+            let indicator = Indicator::from(frames.last().unwrap()).unwrap();
+            let tcp = indicator.get_tcp().unwrap();
+            assert!(tcp.is_fin());
+            assert_eq!(tcp.get_sequence(), sequence);
+        }
 
-            // Connect if not connected, drop if established
-            if !is_exist {
-                // Clean up
-                self.remove(indicator);
+        // The source acknowledges the FIN and closes
+        let frame = build_ipv4_frame(
+            DST_IP_ADDR,
+            Layers::Tcp(Tcp::new_ack_fin(
+                1024,
+                80,
+                src_sequence,
+                sequence.wrapping_add(1),
+                65535,
+            )),
+            &[],
+        );
+        redirector.handle_frame(&frame).await;
+        assert!(redirector.is_shutdown_completed());
 
-                self.tcp_sequence_map.insert(key, tcp.get_sequence());
+        // The connection to the proxy is closed
+        handle.join().unwrap();
+    }
 
-                // Latency test
-                let timer = Instant::now();
+    #[tokio::test]
+    async fn shutdown_drain_connection() {
+        let (remote, handle) = spawn_proxy_with_delay(Duration::from_millis(200));
+        let (mut redirector, frames) = new_redirector_to(remote);
+        let (src_sequence, sequence) = open_connection(&mut redirector, &frames).await;
+        let src = SocketAddrV4::new(SRC_IP_ADDR, 1024);
+        let dst = SocketAddrV4::new(DST_IP_ADDR, 80);
+
+        // Data acknowledged to the source are still buffered to the proxy
+        let chunk = vec![0x5au8; 16384];
+        let mut sent = 0;
+        {
+            let stream = redirector.streams.get_mut(&(src, dst)).unwrap();
+            while stream.get_pending_size() == 0 {
+                stream.send(&chunk).await.unwrap();
+                sent += chunk.len();
+            }
+        }
+        // Data from the proxy are not sent to the source yet
+        {
+            let mut tx_locked = redirector.tx.lock().unwrap();
+            tx_locked.set_tcp_send_window(dst, src, 0);
+            tx_locked.append_to_cache(dst, src, b"data").unwrap();
+        }
+        assert!(frames.lock().unwrap().is_empty());
+
+        let shutdown = Shutdown::new();
+        redirector.set_shutdown(shutdown.clone(), Duration::from_secs(60));
+        shutdown.trigger();
+        redirector.begin_shutdown().await;
+        assert_eq!(redirector.streams[&(src, dst)].get_pending_size(), 0);
+        // FIN is deferred until the data are sent
+        assert!(frames.lock().unwrap().is_empty());
+
+        // The window of the source opens
+        let frame = build_ipv4_frame(
+            DST_IP_ADDR,
+            Layers::Tcp(Tcp::new_ack(1024, 80, src_sequence, sequence, 65535)),
+            &[],
+        );
+        redirector.handle_frame(&frame).await;
+        {
+            let mut frames = frames.lock().unwrap();
+            assert_eq!(frames.len(), 1);
+            let indicator = Indicator::from(&frames[0]).unwrap();
+            let tcp = indicator.get_tcp().unwrap();
+            assert!(!tcp.is_fin());
+            assert_eq!(tcp.get_sequence(), sequence);
+            assert_eq!(
+                &frames[0][indicator.get_size()..indicator.get_size() + 4],
+                b"data"
+            );
+            frames.clear();
+        }
 
-                // Connect
-                let stream =
-                    StreamWorker::connect(self.get_tx(), tcp.get_src(), dst, self.remote).await;
+        // The source acknowledges the data
+        let frame = build_ipv4_frame(
+            DST_IP_ADDR,
+            Layers::Tcp(Tcp::new_ack(
+                1024,
+                80,
+                src_sequence,
+                sequence.wrapping_add(4),
+                65535,
+            )),
+            &[],
+        );
+        redirector.handle_frame(&frame).await;
+        {
+            let frames = frames.lock().unwrap();
+            let indicator = Indicator::from(frames.last().unwrap()).unwrap();
+            let tcp = indicator.get_tcp().unwrap();
+            assert!(tcp.is_fin());
+            assert_eq!(tcp.get_sequence(), sequence.wrapping_add(4));
+        }
 
-                let stream = match stream {
-                    Ok(stream) => {
-                        // Latency test result (not accurate)
-                        debug!(
-                            "Latency to {}: {} ms (RTT)",
-                            dst,
-                            timer.elapsed().as_millis()
-                        );
+        // The source acknowledges the FIN and closes
+        let frame = build_ipv4_frame(
+            DST_IP_ADDR,
+            Layers::Tcp(Tcp::new_ack_fin(
+                1024,
+                80,
+                src_sequence,
+                sequence.wrapping_add(5),
+                65535,
+            )),
+            &[],
+        );
+        redirector.handle_frame(&frame).await;
+        assert!(redirector.is_shutdown_completed());
 
-                        let mut tx_locked = self.tx.lock().unwrap();
-                        // Clean up
-                        tx_locked.remove(dst, tcp.get_src());
+        // All the data buffered are written to the proxy
+        let received = handle.join().unwrap();
+        assert_eq!(received.len(), sent);
+    }
 
-                        tx_locked.set_tcp_acknowledgement(
-                            dst,
-                            tcp.get_src(),
-                            tcp.get_sequence().checked_add(1).unwrap_or(0),
-                        );
-                        // Send ACK/SYN
-                        tx_locked.send_tcp_ack_syn(dst, tcp.get_src())?;
+    #[tokio::test]
+    async fn count_flow_payload() {
+        let (remote, handle) = spawn_proxy();
+        let (mut redirector, frames) = new_redirector_to(remote);
+        let (src_sequence, sequence) = open_connection(&mut redirector, &frames).await;
+
+        // The retransmission is not counted again
+        let frame = build_ipv4_frame(
+            DST_IP_ADDR,
+            Layers::Tcp(Tcp::new_ack(1024, 80, src_sequence, sequence, 65535)),
+            b"hello",
+        );
+        redirector.handle_frame(&frame).await;
+        redirector.handle_frame(&frame).await;
+
+        let flows = redirector.flows();
+        assert_eq!(flows.len(), 1);
+        assert_eq!(
+            flows[0].key,
+            (
+                SocketAddrV4::new(SRC_IP_ADDR, 1024),
+                SocketAddrV4::new(DST_IP_ADDR, 80)
+            )
+        );
+        assert_eq!(flows[0].up_bytes, 5);
+        assert_eq!(flows[0].up_packets, 1);
+        assert_eq!(flows[0].down_bytes, 0);
 
-                        stream
-                    }
-                    Err(e) => {
-                        // Clean up
-                        self.remove(indicator);
+        drop(redirector);
+        assert_eq!(handle.join().unwrap(), b"hello");
+    }
 
-                        let mut tx_locked = self.tx.lock().unwrap();
-                        tx_locked.set_tcp_acknowledgement(
-                            dst,
-                            tcp.get_src(),
-                            tcp.get_sequence().checked_add(1).unwrap_or(0),
-                        );
-                        // Send ACK/RST
-                        tx_locked.send_tcp_ack_rst(dst, tcp.get_src())?;
+    #[cfg(feature = "async")]
+    #[tokio::test]
+    async fn open_stream_forward() {
+        use pcap::file::{Capture, PcapWriter};
+
+        let (remote, handle) = spawn_proxy();
+        let (mut redirector, frames) = new_redirector_to(remote);
+        let (src_sequence, sequence) = open_connection(&mut redirector, &frames).await;
+
+        // The rest of the flow is captured in a file
+        let path = std::env::temp_dir().join(format!(
+            "pcap2socks-{}-open-stream.pcap",
+            std::process::id()
+        ));
+        let mut writer = PcapWriter::create(&path).unwrap();
+        writer
+            .write(&build_ipv4_frame(
+                DST_IP_ADDR,
+                Layers::Tcp(Tcp::new_ack(1024, 80, src_sequence, sequence, 65535)),
+                b"hello",
+            ))
+            .unwrap();
+        writer
+            .write(&build_ipv4_frame(
+                DST_IP_ADDR,
+                Layers::Tcp(Tcp::new_ack_fin(
+                    1024,
+                    80,
+                    src_sequence + 5,
+                    sequence,
+                    65535,
+                )),
+                &[],
+            ))
+            .unwrap();
+        drop(writer);
+        let mut stream = CaptureStream::new(Capture::from_file(&path).unwrap().into_receiver());
+
+        let e = redirector.open_stream(&mut stream).await.unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::UnexpectedEof);
+        assert!(stream.is_closed());
+        std::fs::remove_file(&path).unwrap();
+
+        // The payload is acknowledged and forwarded to the proxy
+        let acknowledged = frames.lock().unwrap().iter().any(|frame| {
+            let indicator = Indicator::from(frame).unwrap();
+            indicator
+                .get_tcp()
+                .map_or(false, |tcp| tcp.get_acknowledgement() == src_sequence + 5)
+        });
+        assert!(acknowledged);
+        // The connection to the proxy is closed with the redirector
+        drop(redirector);
+        assert_eq!(handle.join().unwrap(), b"hello");
+    }
 
-                        // Clean up
-                        tx_locked.remove(dst, tcp.get_src());
+    #[tokio::test]
+    async fn open_multiple() {
+        let (redirector_0, frames_0) = new_redirector();
+        let (redirector_1, frames_1) = new_redirector();
+        let mut redirectors = [redirector_0, redirector_1];
+
+        // Both interfaces see the same source
+        let pool = BufferPool::new(1514, 2, ExhaustedPolicy::Drop);
+        let (tx, rx) = mpsc::channel();
+        for (i, frame) in [
+            (0, build_arp_frame(new_arp_request(LOCAL_IP_ADDR))),
+            (
+                1,
+                build_ipv4_frame(
+                    LOCAL_IP_ADDR,
+                    Layers::Icmp(Icmp::new_echo_request(0x1234, 1)),
+                    b"ping",
+                ),
+            ),
+        ] {
+            let mut buffer = pool.get().unwrap();
+            buffer[..frame.len()].copy_from_slice(&frame);
+            buffer.truncate(frame.len());
+            tx.send((i, buffer)).unwrap();
+        }
+        drop(tx);
 
-                        return Err(e);
-                    }
-                };
+        let e = Redirector::open_multiple(&mut redirectors, &rx)
+            .await
+            .unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::BrokenPipe);
 
-                self.streams.insert(key, stream);
-            }
-        }
+        // Replies are sent from the interface of their requests, besides the gratuitous ARPs
+        let is_arp_reply = |frame: &Vec<u8>| {
+            Indicator::from(frame)
+                .unwrap()
+                .get_arp()
+                .map_or(false, |arp| arp.is_reply())
+        };
+        let is_icmp = |frame: &Vec<u8>| Indicator::from(frame).unwrap().get_icmp().is_some();
+        let frames_0 = frames_0.lock().unwrap();
+        assert_eq!(
+            frames_0.iter().filter(|frame| is_arp_reply(frame)).count(),
+            1
+        );
+        assert!(!frames_0.iter().any(is_icmp));
+        let frames_1 = frames_1.lock().unwrap();
+        assert_eq!(frames_1.iter().filter(|frame| is_icmp(frame)).count(), 1);
+        assert!(!frames_1.iter().any(is_arp_reply));
+    }
 
-        Ok(())
+    #[tokio::test]
+    async fn drop_ipv6_unknown_extension() {
+        let (mut redirector, frames) = new_redirector();
+        let stats = redirector.get_stats();
+        let ethernet =
+            Ethernet::new(LayerTypes::Ipv6, SRC_HARDWARE_ADDR, LOCAL_HARDWARE_ADDR).unwrap();
+        let ipv6 = Ipv6::new(
+            LayerTypes::Udp,
+            "2001:db8::1".parse().unwrap(),
+            "2001:db8::2".parse().unwrap(),
+        )
+        .unwrap();
+        let mut frame = PacketBuilder::new()
+            .ethernet(ethernet)
+            .ipv6(ipv6)
+            .payload(&[0u8; 8])
+            .build()
+            .unwrap();
+        // The next header is an experimental protocol
+        frame[ETHERNET_HEADER_SIZE + 6] = 253;
+        redirector.handle_frame(&frame).await;
+
+        assert_eq!(stats.get_dropped(DropReason::Malformed), 1);
+        assert!(frames.lock().unwrap().is_empty());
     }
 
-    fn handle_tcp_rst(&mut self, indicator: &Indicator) {
-        if let Some(ref tcp) = indicator.get_tcp() {
-            let dst = SocketAddrV4::new(tcp.get_dst_ip_addr(), tcp.get_dst());
-            let key = (tcp.get_src(), dst);
-            let is_exist = self.streams.get(&key).is_some();
+    #[tokio::test]
+    async fn reply_ttl_exceeded() {
+        let (mut redirector, frames) = new_redirector();
+        let stats = redirector.get_stats();
+        let ethernet =
+            Ethernet::new(LayerTypes::Ipv4, SRC_HARDWARE_ADDR, LOCAL_HARDWARE_ADDR).unwrap();
+        let mut ipv4 = Ipv4::new(1, LayerTypes::Udp, SRC_IP_ADDR, DST_IP_ADDR).unwrap();
+        ipv4.set_ttl(1);
+        let mut udp = Udp::new(1024, 53);
+        udp.set_ipv4_layer(&ipv4);
+        let frame = PacketBuilder::new()
+            .ethernet(ethernet)
+            .ipv4(ipv4)
+            .udp(udp)
+            .payload(b"query with a long payload")
+            .build()
+            .unwrap();
+        redirector.handle_frame(&frame).await;
+
+        assert_eq!(stats.get_dropped(DropReason::TtlExceeded), 1);
+        let frames = frames.lock().unwrap();
+        assert_eq!(frames.len(), 1);
+        let indicator = Indicator::from(&frames[0]).unwrap();
+        let ipv4 = indicator.get_ipv4().unwrap();
+        assert_eq!(ipv4.get_src(), LOCAL_IP_ADDR);
+        assert_eq!(ipv4.get_dst(), SRC_IP_ADDR);
+        let icmp = indicator.get_icmp().unwrap();
+        assert_eq!(icmp.get_icmp_type(), IcmpTypes::TimeExceeded);
+        // The IPv4 header and the leading 8 bytes of the datagram are quoted
+        let quoted = &frames[0][indicator.get_size()..];
+        assert_eq!(
+            quoted,
+            &frame[ETHERNET_HEADER_SIZE..ETHERNET_HEADER_SIZE + 28]
+        );
+    }
 
-            if is_exist {
-                // Clean up
-                self.remove(indicator);
-                self.tx.lock().unwrap().remove(dst, tcp.get_src());
-            }
-        }
+    #[tokio::test]
+    async fn reply_hop_limit_exceeded() {
+        let (mut redirector, frames) = new_redirector();
+        redirector.set_nat64(true);
+        let ethernet =
+            Ethernet::new(LayerTypes::Ipv6, SRC_HARDWARE_ADDR, LOCAL_HARDWARE_ADDR).unwrap();
+        let src: Ipv6Addr = "2001:db8::1".parse().unwrap();
+        let mut ipv6 =
+            Ipv6::new(LayerTypes::Udp, src, "64:ff9b::5db8:d822".parse().unwrap()).unwrap();
+        ipv6.set_hop_limit(1);
+        let mut udp = Udp::new(1024, 53);
+        udp.set_ipv6_layer(&ipv6);
+        let frame = PacketBuilder::new()
+            .ethernet(ethernet)
+            .ipv6(ipv6)
+            .udp(udp)
+            .payload(b"query")
+            .build()
+            .unwrap();
+        redirector.handle_frame(&frame).await;
+
+        let frames = frames.lock().unwrap();
+        let indicator = frames
+            .iter()
+            .filter_map(|frame| Indicator::from(frame))
+            .find(|indicator| indicator.get_icmpv6().is_some())
+            .unwrap();
+        let ipv6 = indicator.get_ipv6().unwrap();
+        assert_eq!(
+            ipv6.get_src(),
+            packet::layer::ipv6::link_local_addr(LOCAL_HARDWARE_ADDR)
+        );
+        assert_eq!(ipv6.get_dst(), src);
+        assert_eq!(
+            indicator.get_icmpv6().unwrap().get_icmpv6_type(),
+            pnet::packet::icmpv6::Icmpv6Types::TimeExceeded
+        );
     }
 
-    fn handle_tcp_fin(&mut self, indicator: &Indicator) -> io::Result<()> {
-        if let Some(ref tcp) = indicator.get_tcp() {
-            let dst = SocketAddrV4::new(tcp.get_dst_ip_addr(), tcp.get_dst());
-            let key = (tcp.get_src(), dst);
-            let is_exist = self.streams.get(&key).is_some();
+    #[tokio::test]
+    async fn nat64_connect_ipv4_target() {
+        use std::io::Read;
 
-            if is_exist {
-                let remain_cache_size = self.tx.lock().unwrap().get_cache_size(dst, tcp.get_src());
-                if remain_cache_size > 0 {
-                    // Trigger sending remaining data
-                    self.tx.lock().unwrap().send_tcp_ack(dst, tcp.get_src())?;
-                } else {
-                    let stream = self.streams.get_mut(&key).unwrap();
-                    stream.close();
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let remote = match listener.local_addr().unwrap() {
+            SocketAddr::V4(addr) => addr,
+            _ => unreachable!(),
+        };
+        // Returns the request of the connection
+        let handle = std::thread::spawn(move || {
+            let mut stream = listener.accept().unwrap().0;
+            let mut buffer = [0u8; 10];
+            stream.read_exact(&mut buffer[..2]).unwrap();
+            let n = buffer[1] as usize;
+            stream.read_exact(&mut buffer[..n]).unwrap();
+            stream.write_all(&[0x05, 0x00]).unwrap();
+            stream.read_exact(&mut buffer).unwrap();
+            stream
+                .write_all(&[0x05, 0x00, 0x00, 0x01, 0, 0, 0, 0, 0, 0])
+                .unwrap();
+            let mut received = Vec::new();
+            stream.read_to_end(&mut received).unwrap_or(0);
+
+            buffer
+        });
+        let (mut redirector, frames) = new_redirector_to(remote);
+        redirector.set_nat64(true);
+
+        let ethernet =
+            Ethernet::new(LayerTypes::Ipv6, SRC_HARDWARE_ADDR, LOCAL_HARDWARE_ADDR).unwrap();
+        let src: Ipv6Addr = "2001:db8::1".parse().unwrap();
+        let dst: Ipv6Addr = "64:ff9b::5db8:d822".parse().unwrap();
+        let ipv6 = Ipv6::new(LayerTypes::Tcp, src, dst).unwrap();
+        let mut tcp = Tcp::new_syn(1024, 80, 1000, 65535);
+        tcp.set_ipv6_layer(&ipv6);
+        let frame = PacketBuilder::new()
+            .ethernet(ethernet)
+            .ipv6(ipv6)
+            .tcp(tcp)
+            .build()
+            .unwrap();
+        redirector.handle_frame(&frame).await;
+
+        // The SYN/ACK is translated back into IPv6
+        {
+            use pnet::packet::tcp::{TcpFlags, TcpPacket};
+
+            let frames = frames.lock().unwrap();
+            assert_eq!(frames.len(), 1);
+            let indicator = Indicator::from(&frames[0]).unwrap();
+            let ipv6 = indicator.get_ipv6().unwrap();
+            assert_eq!(ipv6.get_src(), dst);
+            assert_eq!(ipv6.get_dst(), src);
+            // Transport layers of IPv6 other than ICMPv6 are not parsed by the indicator
+            let tcp = TcpPacket::new(&frames[0][ETHERNET_HEADER_SIZE + 40..]).unwrap();
+            assert_eq!(tcp.get_flags(), TcpFlags::SYN | TcpFlags::ACK);
+            assert_eq!(tcp.get_acknowledgement(), 1001);
+        }
 
-                    let mut tx_locked = self.tx.lock().unwrap();
-                    tx_locked.set_tcp_acknowledgement(
-                        dst,
-                        tcp.get_src(),
-                        tcp.get_sequence().checked_add(1).unwrap_or(0),
-                    );
-                    // Send ACK/FIN
-                    tx_locked.send_tcp_ack_fin(dst, tcp.get_src())?;
-                }
-            } else {
-                // Though a RST is enough, reply with respect
-                let mut tx_locked = self.tx.lock().unwrap();
-                #[allow(deprecated)]
-                tx_locked.set_tcp_sequence(dst, tcp.get_src(), tcp.get_acknowledgement());
-                tx_locked.set_tcp_acknowledgement(
-                    dst,
-                    tcp.get_src(),
-                    tcp.get_sequence().checked_add(1).unwrap_or(0),
-                );
-                // Send ACK/FIN
-                tx_locked.send_tcp_ack_fin(dst, tcp.get_src())?;
+        drop(redirector);
+        // The request is of the embedded IPv4 address
+        assert_eq!(
+            handle.join().unwrap(),
+            [0x05, 0x01, 0x00, 0x01, 93, 184, 216, 34, 0, 80]
+        );
+    }
 
-                // Clean up
-                tx_locked.remove(dst, tcp.get_src());
-            }
-        }
+    /// Builds a Neighbor Solicitation frame from the source for the given target address with
+    /// the given hop limit.
+    fn build_neighbor_solicitation_frame(target_addr: Ipv6Addr, hop_limit: u8) -> Vec<u8> {
+        let ethernet =
+            Ethernet::new(LayerTypes::Ipv6, SRC_HARDWARE_ADDR, LOCAL_HARDWARE_ADDR).unwrap();
+        let mut ipv6 = Ipv6::new(
+            LayerTypes::Icmpv6,
+            "fe80::1".parse().unwrap(),
+            "ff02::1:ff00:2".parse().unwrap(),
+        )
+        .unwrap();
+        ipv6.set_hop_limit(hop_limit);
+        let mut icmpv6 = Icmpv6::new_neighbor_solicitation(target_addr, Some(SRC_HARDWARE_ADDR));
+        icmpv6.set_ipv6_layer(&ipv6);
+
+        PacketBuilder::new()
+            .ethernet(ethernet)
+            .ipv6(ipv6)
+            .icmpv6(icmpv6)
+            .build()
+            .unwrap()
+    }
 
-        Ok(())
+    #[tokio::test]
+    async fn answer_neighbor_solicitation() {
+        let (mut redirector, frames) = new_redirector();
+        let target_addr = packet::layer::ipv6::link_local_addr(LOCAL_HARDWARE_ADDR);
+        let frame = build_neighbor_solicitation_frame(target_addr, NDP_HOP_LIMIT);
+        redirector.handle_frame(&frame).await;
+
+        let frames = frames.lock().unwrap();
+        assert_eq!(frames.len(), 1);
+        let indicator = Indicator::from(&frames[0]).unwrap();
+        let ethernet = indicator.get_ethernet().unwrap();
+        assert_eq!(ethernet.get_src(), LOCAL_HARDWARE_ADDR);
+        assert_eq!(ethernet.get_dst(), SRC_HARDWARE_ADDR);
+        let ipv6 = indicator.get_ipv6().unwrap();
+        assert_eq!(ipv6.get_src(), target_addr);
+        assert_eq!(ipv6.get_dst(), "fe80::1".parse::<Ipv6Addr>().unwrap());
+        assert_eq!(ipv6.get_hop_limit(), NDP_HOP_LIMIT);
+        let icmpv6 = indicator.get_icmpv6().unwrap();
+        assert!(icmpv6.is_neighbor_advertisement());
+        assert_eq!(icmpv6.get_target_addr(), Some(target_addr));
+        assert_eq!(icmpv6.get_link_layer_addr(), Some(LOCAL_HARDWARE_ADDR));
+        let icmpv6_buffer = &frames[0][ETHERNET_HEADER_SIZE + 40..];
+        assert!(icmpv6.validate_checksum(icmpv6_buffer));
     }
 
-    async fn handle_udp(&mut self, indicator: &Indicator, buffer: &[u8]) -> io::Result<()> {
-        if let Some(ref udp) = indicator.get_udp() {
-            let mut port = self.get_local_udp_port(udp.get_src());
+    #[tokio::test]
+    async fn ignore_neighbor_solicitation() {
+        let (mut redirector, frames) = new_redirector();
+        // Solicitations of other addresses are not answered
+        let frame = build_neighbor_solicitation_frame("fe80::99".parse().unwrap(), NDP_HOP_LIMIT);
+        redirector.handle_frame(&frame).await;
+        // Solicitations forwarded by a router are dropped
+        let target_addr = packet::layer::ipv6::link_local_addr(LOCAL_HARDWARE_ADDR);
+        let frame = build_neighbor_solicitation_frame(target_addr, 64);
+        redirector.handle_frame(&frame).await;
+
+        assert!(frames.lock().unwrap().is_empty());
+    }
 
-            // Bind
-            let is_create;
-            let is_set;
-            if port == 0 {
-                is_create = true;
-                is_set = false;
-            } else {
-                let worker = self.datagrams.get(&port).unwrap();
-                is_create = worker.is_closed();
-                is_set = worker.get_src_port() != udp.get_src();
-            }
-            if is_create {
-                // Bind
-                let (worker, bind_port) =
-                    DatagramWorker::bind(self.get_tx(), udp.get_src(), self.remote).await?;
-                self.datagrams.insert(bind_port, worker);
+    #[test]
+    fn forward_through_injector() {
+        let (mut forwarder, frames) = new_forwarder();
+        forwarder.send_arp_reply().unwrap();
+
+        let frames = frames.lock().unwrap();
+        assert_eq!(frames.len(), 1);
+        let indicator = Indicator::from(&frames[0]).unwrap();
+        assert_eq!(
+            indicator.get_ethernet().unwrap().get_src(),
+            LOCAL_HARDWARE_ADDR
+        );
+        let arp = indicator.get_arp().unwrap();
+        assert_eq!(arp.get_src_hardware_addr(), LOCAL_HARDWARE_ADDR);
+        assert_eq!(arp.get_src(), LOCAL_IP_ADDR);
+        assert_eq!(arp.get_dst(), SRC_IP_ADDR);
+    }
 
-                // Update LRU
-                self.udp_lru.put(bind_port, udp.get_src());
+    #[test]
+    fn forward_injector_error() {
+        /// Represents an injector failing every frame.
+        struct FailingInjector;
 
-                port = bind_port;
-            } else if is_set {
-                // Replace
-                self.datagrams
-                    .get_mut(&port)
-                    .unwrap()
-                    .set_src_port(udp.get_src());
+        impl Injector for FailingInjector {
+            fn inject(&mut self, _: &[u8]) -> io::Result<()> {
+                Err(io::Error::new(io::ErrorKind::Other, "link down"))
             }
-
-            // Send
-            self.datagrams
-                .get_mut(&port)
-                .unwrap()
-                .send_to(
-                    &buffer[indicator.get_size()..],
-                    SocketAddrV4::new(udp.get_dst_ip_addr(), udp.get_dst()),
-                )
-                .await?;
         }
 
-        Ok(())
+        let mut forwarder = Forwarder::new(
+            Box::new(FailingInjector),
+            1500,
+            LOCAL_HARDWARE_ADDR,
+            SRC_IP_ADDR,
+            LOCAL_IP_ADDR,
+        );
+        let e = forwarder.send_arp_reply().unwrap_err();
+        assert_eq!(e.to_string(), "link down");
     }
 
-    fn update_tcp_sequence(&mut self, indicator: &Indicator) {
-        if let Some(tcp) = indicator.get_tcp() {
-            let dst = SocketAddrV4::new(tcp.get_dst_ip_addr(), tcp.get_dst());
-            let key = (tcp.get_src(), dst);
+    #[test]
+    fn send_segmented_payload() {
+        let (mut forwarder, frames) = new_forwarder();
+        let dst = SocketAddrV4::new(DST_IP_ADDR, 80);
+        let src = SocketAddrV4::new(SRC_IP_ADDR, 1024);
+        let payload: Vec<u8> = (0..5000).map(|i| i as u8).collect();
+        let n = forwarder
+            .send_tcp_ack_raw(dst, src, 1000, &payload)
+            .unwrap();
+        assert_eq!(n, 4);
+
+        // Segments fit in the MTU of 1500 Bytes
+        let frames = frames.lock().unwrap();
+        assert_eq!(frames.len(), 4);
+        let mut received = Vec::new();
+        for (i, frame) in frames.iter().enumerate() {
+            let indicator = Indicator::from(frame).unwrap();
+            let tcp = indicator.get_tcp().unwrap();
+            assert_eq!(tcp.get_src(), 80);
+            assert_eq!(tcp.get_dst(), 1024);
+            assert_eq!(tcp.get_sequence(), 1000 + 1460 * i as u32);
+            assert_eq!(tcp.is_psh(), i == 3);
+            received.extend_from_slice(&frame[indicator.get_size()..]);
+        }
+        assert_eq!(received, payload);
+    }
 
-            let record_sequence = *self.tcp_sequence_map.get(&key).unwrap_or(&0);
-            let sub_sequence = tcp
-                .get_sequence()
-                .checked_sub(record_sequence)
-                .unwrap_or_else(|| tcp.get_sequence() + (u32::MAX - record_sequence));
+    /// Represents a logger keeping the records of TCP connections with the threads logging them.
+    struct CaptureLogger {
+        records: Mutex<Vec<(std::thread::ThreadId, String)>>,
+    }
 
-            if sub_sequence == 0 {
-                // Duplicate
-                trace!(
-                    "TCP retransmission of {} -> {} at {}",
-                    tcp.get_src(),
-                    dst,
-                    tcp.get_sequence()
-                );
-            } else if sub_sequence < MAX_U32_WINDOW_SIZE as u32 {
-                self.tcp_sequence_map.insert(key, tcp.get_sequence());
+    impl log::Log for CaptureLogger {
+        fn enabled(&self, metadata: &log::Metadata) -> bool {
+            metadata.target() == TCP_LOG_TARGET
+        }
 
-                trace!(
-                    "set TCP sequence of {} -> {} to {}",
-                    tcp.get_src(),
-                    dst,
-                    tcp.get_sequence()
-                );
-            } else {
-                trace!(
-                    "TCP out of order of {} -> {} at {}",
-                    tcp.get_src(),
-                    dst,
-                    tcp.get_sequence()
-                );
+        fn log(&self, record: &log::Record) {
+            if self.enabled(record.metadata()) {
+                self.records
+                    .lock()
+                    .unwrap()
+                    .push((std::thread::current().id(), record.args().to_string()));
             }
         }
+
+        fn flush(&self) {}
     }
 
-    fn update_tcp_acknowledgement(&mut self, indicator: &Indicator) {
-        if let Some(tcp) = indicator.get_tcp() {
-            let dst = SocketAddrV4::new(tcp.get_dst_ip_addr(), tcp.get_dst());
-            let key = (tcp.get_src(), dst);
+    static LOGGER: CaptureLogger = CaptureLogger {
+        records: Mutex::new(Vec::new()),
+    };
 
-            let record_acknowledgement = *self.tcp_acknowledgement_map.get(&key).unwrap_or(&0);
-            let sub_acknowledgement = tcp
-                .get_acknowledgement()
-                .checked_sub(record_acknowledgement)
-                .unwrap_or_else(|| tcp.get_acknowledgement() + (u32::MAX - record_acknowledgement));
+    /// Installs the capturing logger, and returns the records of TCP connections logged by the
+    /// current thread so far.
+    fn tcp_log_records() -> Vec<String> {
+        static INIT: std::sync::Once = std::sync::Once::new();
+        INIT.call_once(|| {
+            log::set_logger(&LOGGER).unwrap();
+            log::set_max_level(LevelFilter::Trace);
+        });
+
+        let id = std::thread::current().id();
+        LOGGER
+            .records
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(thread_id, _)| *thread_id == id)
+            .map(|(_, record)| record.clone())
+            .collect()
+    }
 
-            if sub_acknowledgement == 0 {
-                // Duplicate
-                let entry = self.tcp_duplicate_map.entry(key).or_insert(0);
-                *entry = entry.checked_add(1).unwrap_or(usize::MAX);
-                trace!(
-                    "duplicate TCP acknowledgement of {} -> {} at {}",
-                    tcp.get_src(),
-                    dst,
-                    tcp.get_acknowledgement()
-                );
-            } else if sub_acknowledgement < MAX_U32_WINDOW_SIZE as u32 {
-                self.tcp_acknowledgement_map
-                    .insert(key, tcp.get_acknowledgement());
+    #[tokio::test]
+    async fn log_connection_open_and_close() {
+        tcp_log_records();
+        let (remote, handle) = spawn_proxy();
+        let (mut redirector, frames) = new_redirector_to(remote);
+        let (src_sequence, _) = open_connection(&mut redirector, &frames).await;
+
+        let records = tcp_log_records();
+        let open = format!(
+            "open connection {}:1024 -> {}:80 via",
+            SRC_IP_ADDR, DST_IP_ADDR
+        );
+        assert_eq!(records.iter().filter(|r| r.starts_with(&open)).count(), 1);
 
-                self.tcp_duplicate_map.insert(key, 0);
-                trace!(
-                    "set TCP acknowledgement of {} -> {} to {}",
-                    tcp.get_src(),
-                    dst,
-                    tcp.get_acknowledgement()
-                );
-            }
-        }
+        let frame = build_ipv4_frame(
+            DST_IP_ADDR,
+            Layers::Tcp(Tcp::new_rst(1024, 80, src_sequence, 0, 0)),
+            &[],
+        );
+        redirector.handle_frame(&frame).await;
+        drop(redirector);
+        handle.join().unwrap();
+
+        // The close event follows the open event with the same 4-tuple
+        let records = tcp_log_records();
+        let close = format!(
+            "close connection {}:1024 -> {}:80",
+            SRC_IP_ADDR, DST_IP_ADDR
+        );
+        let i = records.iter().position(|r| r.starts_with(&open)).unwrap();
+        assert_eq!(
+            records[i..].iter().filter(|r| r.as_str() == close).count(),
+            1
+        );
     }
 
-    fn remove(&mut self, indicator: &Indicator) {
-        if let Some(tcp) = indicator.get_tcp() {
-            let dst = SocketAddrV4::new(tcp.get_dst_ip_addr(), tcp.get_dst());
-            let key = (tcp.get_src(), dst);
+    #[test]
+    fn log_filters_per_subsystem() {
+        let filter = env_logger::filter::Builder::new()
+            .filter_level(LevelFilter::Info)
+            .parse("pcap2socks::tcp=trace,pcap2socks::pcap=warn")
+            .build();
+        let metadata = |target, level| log::Metadata::builder().target(target).level(level).build();
+
+        assert!(filter.enabled(&metadata(TCP_LOG_TARGET, log::Level::Trace)));
+        assert!(!filter.enabled(&metadata("pcap2socks::pcap", log::Level::Info)));
+        assert!(filter.enabled(&metadata("pcap2socks::socks", log::Level::Info)));
+        assert!(!filter.enabled(&metadata("pcap2socks::socks", log::Level::Debug)));
+    }
 
-            self.streams.remove(&key);
-            self.tcp_sequence_map.remove(&key);
-            self.tcp_acknowledgement_map.remove(&key);
-            self.tcp_duplicate_map.remove(&key);
-            self.tcp_last_retransmission_map.remove(&key);
-            self.tcp_cache_map.remove(&key);
-            trace!("remove {} -> {}", dst, tcp.get_dst());
-        }
+    #[test]
+    fn send_jumbo_udp() {
+        let injector = CaptureInjector::default();
+        let frames = injector.frames.clone();
+        let mut forwarder = Forwarder::new(
+            Box::new(injector),
+            9000,
+            LOCAL_HARDWARE_ADDR,
+            SRC_IP_ADDR,
+            LOCAL_IP_ADDR,
+        );
+        let dst = SocketAddrV4::new(DST_IP_ADDR, 53);
+        let src = SocketAddrV4::new(SRC_IP_ADDR, 1024);
+        // The datagram fills the MTU exactly
+        let payload = vec![0x5a; 9000 - 20 - 8];
+        forwarder.send_udp(dst, src, &payload).unwrap();
+
+        let frames = frames.lock().unwrap();
+        assert_eq!(frames.len(), 1);
+        assert_eq!(frames[0].len(), ETHERNET_HEADER_SIZE + 9000);
+        let indicator = Indicator::from(&frames[0]).unwrap();
+        assert_eq!(indicator.get_ipv4().unwrap().get_total_length(), 9000);
+        assert!(!indicator.get_ipv4().unwrap().is_fragment());
+        assert_eq!(&frames[0][indicator.get_size()..], payload.as_slice());
     }
 
-    fn get_tx(&self) -> Arc<Mutex<Forwarder>> {
-        Arc::clone(&self.tx)
+    #[test]
+    fn clamp_maximum_mtu() {
+        let forwarder = Forwarder::new(
+            Box::<CaptureInjector>::default(),
+            u16::MAX,
+            LOCAL_HARDWARE_ADDR,
+            SRC_IP_ADDR,
+            LOCAL_IP_ADDR,
+        );
+        assert_eq!(forwarder.get_mtu(), MAXIMUM_MTU);
     }
 
-    fn get_local_udp_port(&mut self, src_port: u16) -> u16 {
-        let local_port = self.datagram_map[src_port as usize];
-        if local_port == 0 {
-            if self.udp_lru.len() < self.udp_lru.cap() {
-                0
-            } else {
-                let pair = self.udp_lru.pop_lru().unwrap();
-                let local_port = pair.0;
-                let prev_src_port = pair.1;
+    #[test]
+    fn echo_tcp_timestamps() {
+        let (mut forwarder, frames) = new_forwarder();
+        let dst = SocketAddrV4::new(DST_IP_ADDR, 80);
+        let src = SocketAddrV4::new(SRC_IP_ADDR, 1024);
+        forwarder.set_tcp_remote_timestamps(dst, src, Some((5000, 0)));
+        forwarder.send_tcp_ack_0(dst, src).unwrap();
+        forwarder.update_tcp_timestamp(dst, src, 6000);
+        forwarder.send_tcp_ack_0(dst, src).unwrap();
+        // Older timestamp values are ignored
+        forwarder.update_tcp_timestamp(dst, src, 5500);
+        forwarder.send_tcp_ack_0(dst, src).unwrap();
+        forwarder.set_tcp_remote_timestamps(dst, src, None);
+        forwarder.send_tcp_ack_0(dst, src).unwrap();
+
+        let frames = frames.lock().unwrap();
+        let tsecrs: Vec<Option<u32>> = frames
+            .iter()
+            .map(|frame| {
+                let indicator = Indicator::from(frame).unwrap();
+                let timestamps = indicator.get_tcp().unwrap().get_timestamps();
+                timestamps.map(|(_, tsecr)| tsecr)
+            })
+            .collect();
+        assert_eq!(tsecrs, [Some(5000), Some(6000), Some(6000), None]);
+    }
 
-                if prev_src_port != 0 {
-                    // Reuse
-                    self.datagram_map[prev_src_port as usize] = 0;
-                    trace!(
-                        "reuse UDP port {} = {} to {} = {}",
-                        prev_src_port,
-                        local_port,
-                        src_port,
-                        local_port
-                    );
-                }
-                self.datagram_map[src_port as usize] = local_port;
+    #[test]
+    fn segment_with_tcp_timestamps() {
+        let (mut forwarder, frames) = new_forwarder();
+        let dst = SocketAddrV4::new(DST_IP_ADDR, 80);
+        let src = SocketAddrV4::new(SRC_IP_ADDR, 1024);
+        forwarder.set_tcp_remote_timestamps(dst, src, Some((5000, 0)));
+        // The option takes 12 Bytes of each segment
+        let n = forwarder
+            .send_tcp_ack_raw(dst, src, 1000, &[0u8; 1460])
+            .unwrap();
+        assert_eq!(n, 2);
+
+        let frames = frames.lock().unwrap();
+        let sizes: Vec<usize> = frames
+            .iter()
+            .map(|frame| frame.len() - Indicator::from(frame).unwrap().get_size())
+            .collect();
+        assert_eq!(sizes, [1448, 12]);
+        assert!(frames
+            .iter()
+            .all(|frame| frame.len() <= 1500 + ETHERNET_HEADER_SIZE));
+    }
 
-                // Update LRU
-                self.udp_lru.put(local_port, src_port);
+    #[tokio::test]
+    async fn echo_tcp_timestamps_in_syn_ack() {
+        let (remote, handle) = spawn_proxy();
+        let (mut redirector, frames) = new_redirector_to(remote);
+        let mut syn = Tcp::new_syn(1024, 80, 1000, 65535);
+        syn.set_timestamps(Some((777, 0)));
+        let frame = build_ipv4_frame(DST_IP_ADDR, Layers::Tcp(syn), &[]);
+        redirector.handle_frame(&frame).await;
+
+        {
+            let frames = frames.lock().unwrap();
+            let indicator = Indicator::from(frames.last().unwrap()).unwrap();
+            let tcp = indicator.get_tcp().unwrap();
+            assert!(tcp.is_syn() && tcp.is_ack());
+            assert_eq!(tcp.get_timestamps().map(|(_, tsecr)| tsecr), Some(777));
+        }
+        drop(redirector);
+        handle.join().unwrap();
+    }
 
-                local_port
-            }
-        } else {
-            // Update LRU
-            self.udp_lru.get(&local_port);
+    #[test]
+    fn advertise_mss_and_window_scale() {
+        let (mut forwarder, frames) = new_forwarder();
+        forwarder.set_tcp_mss(1200);
+        forwarder.set_tcp_window_scale(Some(7));
+        let dst = SocketAddrV4::new(DST_IP_ADDR, 80);
+        let src = SocketAddrV4::new(SRC_IP_ADDR, 1024);
+        forwarder.set_tcp_remote_window_scale(dst, src, Some(2));
+        forwarder.send_tcp_ack_syn(dst, src).unwrap();
+
+        // Window scaling is disabled if the SYN does not carry the option
+        let src = SocketAddrV4::new(SRC_IP_ADDR, 1025);
+        forwarder.set_tcp_remote_window_scale(dst, src, None);
+        forwarder.send_tcp_ack_syn(dst, src).unwrap();
+
+        let frames = frames.lock().unwrap();
+        let indicator = Indicator::from(&frames[0]).unwrap();
+        let tcp = indicator.get_tcp().unwrap();
+        assert_eq!(tcp.get_mss(), Some(1200));
+        assert_eq!(tcp.get_window_scale(), Some(7));
+        let indicator = Indicator::from(&frames[1]).unwrap();
+        let tcp = indicator.get_tcp().unwrap();
+        assert_eq!(tcp.get_mss(), Some(1200));
+        assert_eq!(tcp.get_window_scale(), None);
+    }
 
-            local_port
-        }
+    #[tokio::test]
+    async fn reset_stateless_segment() {
+        let (mut redirector, frames) = new_redirector();
+        let frame = build_ipv4_frame(
+            DST_IP_ADDR,
+            Layers::Tcp(Tcp::new_ack(1024, 80, 5001, 1001, 65535)),
+            b"hello",
+        );
+        redirector.handle_frame(&frame).await;
+
+        let frames = frames.lock().unwrap();
+        assert_eq!(frames.len(), 1);
+        let indicator = Indicator::from(&frames[0]).unwrap();
+        assert_eq!(indicator.get_ipv4().unwrap().get_src(), DST_IP_ADDR);
+        let tcp = indicator.get_tcp().unwrap();
+        assert!(tcp.is_rst());
+        assert_eq!(tcp.get_sequence(), 1001);
     }
 }
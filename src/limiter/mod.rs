@@ -0,0 +1,186 @@
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Represents the policy of a `TokenBucket` when there are not enough tokens.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum LimitPolicy {
+    /// Queues the bytes until the tokens are refilled.
+    Queue,
+    /// Rejects the bytes, the caller should drop what it is going to send.
+    Drop,
+}
+
+#[derive(Debug)]
+struct Bucket {
+    /// Represents the tokens in the bucket, which is negative if the bucket is in debt of queued
+    /// bytes.
+    tokens: f64,
+    last_refill: Instant,
+}
+
+#[derive(Debug)]
+struct Inner {
+    bucket: Mutex<Bucket>,
+    rate: u64,
+    burst: u64,
+    policy: LimitPolicy,
+}
+
+/// Represents a token bucket rate limiter which can be shared between threads. Tokens are
+/// refilled at the rate of bytes per second, and accumulated up to the burst size.
+#[derive(Clone, Debug)]
+pub struct TokenBucket {
+    inner: Arc<Inner>,
+}
+
+impl TokenBucket {
+    /// Creates a new `TokenBucket` with the given rate in bytes per second and the burst size in
+    /// bytes. The bucket is full at the beginning.
+    pub fn new(rate: u64, burst: u64, policy: LimitPolicy) -> TokenBucket {
+        let rate = rate.max(1);
+        let burst = burst.max(1);
+        TokenBucket {
+            inner: Arc::new(Inner {
+                bucket: Mutex::new(Bucket {
+                    tokens: burst as f64,
+                    last_refill: Instant::now(),
+                }),
+                rate,
+                burst,
+                policy,
+            }),
+        }
+    }
+
+    /// Acquires tokens for the given number of bytes. Returns the time should be waited before
+    /// sending the bytes, or `None` if the bytes should be dropped. Bytes more than the burst size
+    /// are always charged in full, they are only sent once the bucket is full and leave the bucket
+    /// in debt.
+    pub fn acquire(&self, n: usize) -> Option<Duration> {
+        let n = n as f64;
+        let threshold = n.min(self.inner.burst as f64);
+
+        let mut bucket = self.inner.bucket.lock().unwrap();
+
+        // Refill
+        let now = Instant::now();
+        let elapsed = now.duration_since(bucket.last_refill).as_secs_f64();
+        bucket.tokens =
+            (bucket.tokens + elapsed * self.inner.rate as f64).min(self.inner.burst as f64);
+        bucket.last_refill = now;
+
+        match self.inner.policy {
+            LimitPolicy::Queue => {
+                // Queued bytes borrow tokens from the future
+                bucket.tokens -= n;
+                if bucket.tokens >= 0.0 {
+                    Some(Duration::from_secs(0))
+                } else {
+                    Some(Duration::from_secs_f64(
+                        -bucket.tokens / self.inner.rate as f64,
+                    ))
+                }
+            }
+            LimitPolicy::Drop => {
+                if bucket.tokens >= threshold {
+                    bucket.tokens -= n;
+                    Some(Duration::from_secs(0))
+                } else {
+                    None
+                }
+            }
+        }
+    }
+
+    /// Get the rate in bytes per second of the bucket.
+    pub fn get_rate(&self) -> u64 {
+        self.inner.rate
+    }
+
+    /// Get the burst size in bytes of the bucket.
+    pub fn get_burst(&self) -> u64 {
+        self.inner.burst
+    }
+
+    /// Get the policy of the bucket.
+    pub fn get_policy(&self) -> LimitPolicy {
+        self.inner.policy
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    /// Asserts the given wait equals the expected one, less the time elapsed since the given
+    /// instant, in which the tokens are refilled.
+    fn assert_wait(wait: Duration, expected: Duration, start: Instant) {
+        let elapsed = start.elapsed();
+        assert!(wait <= expected + Duration::from_micros(1), "{:?}", wait);
+        assert!(wait + elapsed >= expected, "{:?} + {:?}", wait, elapsed);
+    }
+
+    #[test]
+    fn queue_respects_rate() {
+        let start = Instant::now();
+        let bucket = TokenBucket::new(10_000, 1_000, LimitPolicy::Queue);
+        let mut wait = Duration::from_secs(0);
+        for _ in 0..5 {
+            wait = bucket.acquire(1_000).unwrap();
+        }
+
+        // The first 1000 bytes are sent in the burst, the rest 4000 bytes take 0.4 seconds
+        assert_wait(wait, Duration::from_millis(400), start);
+    }
+
+    #[test]
+    fn drop_when_empty() {
+        let bucket = TokenBucket::new(1, 100, LimitPolicy::Drop);
+        assert_eq!(bucket.acquire(100), Some(Duration::from_secs(0)));
+        assert_eq!(bucket.acquire(100), None);
+        assert_eq!(bucket.acquire(1), None);
+    }
+
+    #[test]
+    fn drop_over_burst() {
+        let bucket = TokenBucket::new(1, 100, LimitPolicy::Drop);
+        // Bytes more than the burst size pass a full bucket and are charged in full
+        assert_eq!(bucket.acquire(1_000), Some(Duration::from_secs(0)));
+        assert_eq!(bucket.acquire(1), None);
+        assert_eq!(bucket.get_burst(), 100);
+    }
+
+    #[test]
+    fn queue_over_burst_respects_rate() {
+        let start = Instant::now();
+        let bucket = TokenBucket::new(10_000, 500, LimitPolicy::Queue);
+        let mut wait = Duration::from_secs(0);
+        for _ in 0..4 {
+            wait = bucket.acquire(1_500).unwrap();
+        }
+
+        // The first 500 bytes are sent in the burst, the rest 5500 bytes take 0.55 seconds
+        assert_wait(wait, Duration::from_millis(550), start);
+    }
+
+    #[test]
+    fn share_between_threads() {
+        let start = Instant::now();
+        let bucket = TokenBucket::new(10_000, 1_000, LimitPolicy::Queue);
+        let handles: Vec<_> = (0..4)
+            .map(|_| {
+                let bucket = bucket.clone();
+                thread::spawn(move || (0..5).map(|_| bucket.acquire(250).unwrap()).max().unwrap())
+            })
+            .collect();
+        let wait = handles
+            .into_iter()
+            .map(|handle| handle.join().unwrap())
+            .max()
+            .unwrap();
+
+        // 5000 bytes in total share the same bucket
+        assert_wait(wait, Duration::from_millis(400), start);
+    }
+}
@@ -1,10 +1,27 @@
-use log::{error, info};
-use std::net::Ipv4Addr;
-use std::sync::{Arc, Mutex};
+use log::{error, info, warn};
+use std::io;
+use std::net::{IpAddr, Ipv4Addr};
+use std::sync::{mpsc, Arc, Mutex};
+use std::time::Duration;
 
 use lib::args;
+use lib::filter::{self, Filter};
+use lib::inspect::{Chain, InspectionHook, MetadataLogger, Redactor};
+use lib::limiter::{LimitPolicy, TokenBucket};
+use lib::packet::layer::icmp::MINIMUM_IPV4_MTU;
+use lib::packet::layer::ipv4::TtlMode;
+use lib::packet::layer::tcp::state::ConnectionLimitPolicy;
+use lib::pcap::file::{Capture, NullSender, PcapWriter};
+use lib::pcap::inject::{Injector, InjectorKind, PcapInjector, QueuedInjector, RawSocketInjector};
+use lib::pcap::{self as pcap, CaptureOptions, Interface};
+use lib::route::RouteTable;
+use lib::shutdown::{self, Shutdown};
+use lib::socks::{ConnectOptions, SocksAuth, SocksPool};
+#[cfg(feature = "metrics")]
+use lib::stats::metrics::Metrics;
 use lib::{Forwarder, Redirector};
 use pcap2socks as lib;
+use pnet::datalink::MacAddr;
 
 #[tokio::main]
 async fn main() {
@@ -14,19 +31,87 @@ async fn main() {
     // Log
     lib::set_logger(&flags);
 
+    // MTU
+    if flags.mtu < MINIMUM_IPV4_MTU || flags.mtu > lib::MAXIMUM_MTU {
+        error!(
+            "MTU must be between {} and {}",
+            MINIMUM_IPV4_MTU,
+            lib::MAXIMUM_MTU
+        );
+        return;
+    }
+
+    // DSCP
+    if flags
+        .dscp
+        .map_or(false, |dscp| dscp > lib::packet::layer::ipv4::MAXIMUM_DSCP)
+    {
+        error!(
+            "DSCP must be between 0 and {}",
+            lib::packet::layer::ipv4::MAXIMUM_DSCP
+        );
+        return;
+    }
+
+    // TTL
+    if flags.ttl == Some(0) {
+        error!("TTL must be between 1 and {}", u8::MAX);
+        return;
+    }
+
+    // Capture buffer size
+    if flags.buffer_size < flags.mtu as usize {
+        error!("Capture buffer size must be at least the MTU");
+        return;
+    }
+
+    // Snaplen
+    if flags.snaplen < flags.mtu as usize {
+        warn!("Frames longer than the snaplen will be dropped as truncated");
+    }
+
+    // Replay
+    if let Some(ref file) = flags.file {
+        replay(&flags, file).await;
+        return;
+    }
+
     // Interface
-    let inter = match lib::interface(flags.inter) {
-        Some(inter) => inter,
-        None => {
-            println!("Cannot determine interface. Available interfaces are listed below, use -i <INTERFACE> to designate:");
-            for inter in lib::interfaces().iter() {
-                println!("    {}", inter);
+    let names = match flags.inter.len() {
+        0 => vec![None],
+        _ => flags.inter.iter().cloned().map(Some).collect(),
+    };
+    let mut inters = Vec::new();
+    for name in names {
+        match lib::interface(name) {
+            Some(inter) => inters.push(inter),
+            None => {
+                println!("Cannot determine interface. Available interfaces are listed below, use -i <INTERFACE> to designate:");
+                for inter in lib::interfaces().iter() {
+                    println!("    {}", inter);
+                }
+                return;
             }
-            return;
         }
-    };
-    info!("Listen on {}", inter);
+    }
+    for inter in inters.iter() {
+        info!("Listen on {}", inter);
+    }
+    // Local addresses, discovered from the interface unless configured
+    let mut local_addrs = Vec::new();
+    for inter in inters.iter() {
+        match get_local_addrs(&flags, inter) {
+            Ok(addrs) => local_addrs.push(addrs),
+            Err(ref e) => {
+                error!("{}: {}", inter.name, e);
+                return;
+            }
+        }
+    }
     info!("Break packets with MTU {}", flags.mtu);
+    if flags.dry_run {
+        info!("Dry run, nothing will be sent");
+    }
 
     // Publish
     if let Some(publish) = flags.publish {
@@ -34,36 +119,326 @@ async fn main() {
     }
 
     // Instructions
-    show_info(
-        flags.src,
-        flags.publish.unwrap_or(inter.ip_addrs[0]),
-        flags.mtu,
-    );
+    for (_, ip_addr) in local_addrs.iter() {
+        show_info(flags.src, flags.publish.unwrap_or(*ip_addr), flags.mtu);
+    }
+
+    // Shutdown
+    let shutdown = match shutdown::set_signal_handler() {
+        Ok(_) => Some(Shutdown::new()),
+        Err(ref e) => {
+            warn!("Cannot handle shutdown signals: {}", e);
+            None
+        }
+    };
+
+    // Rate limit
+    let limiter = get_limiter(&flags);
 
     // Proxy
-    let (tx, mut rx) = match inter.open() {
-        Ok((tx, rx)) => (tx, rx),
+    let capture_options = CaptureOptions::new(flags.immediate, flags.buffer_size, flags.snaplen);
+    let mut redirectors = Vec::new();
+    let mut rxs = Vec::new();
+    #[cfg(feature = "metrics")]
+    let mut metrics = Vec::new();
+    for (inter, (hardware_addr, ip_addr)) in inters.iter().zip(local_addrs) {
+        let (tx, rx) = match inter.open_with_options(&capture_options) {
+            Ok((tx, rx)) => (tx, rx),
+            Err(ref e) => {
+                error!("{}", e);
+                return;
+            }
+        };
+        let tx: Box<dyn Injector> = match flags.injector {
+            InjectorKind::Pcap => Box::new(PcapInjector::new(tx)),
+            InjectorKind::RawSocket => match RawSocketInjector::open(&inter.name) {
+                Ok(injector) => Box::new(injector),
+                Err(ref e) => {
+                    error!("{}", e);
+                    return;
+                }
+            },
+        };
+        let tx: Box<dyn Injector> = match flags.queue_weights {
+            Some(weights) => Box::new(QueuedInjector::new(tx, weights)),
+            None => tx,
+        };
+        // Every interface has its own forwarder, so replies are sent from the interface where
+        // the source is seen
+        let forwarder = Forwarder::new(tx, flags.mtu, hardware_addr, flags.src, ip_addr);
+        let dump = flags.dump.as_ref().map(|dump| match inters.len() {
+            1 => dump.clone(),
+            _ => format!("{}.{}", dump, inter.name),
+        });
+        let mut redirector = match configure(forwarder, &flags, dump, limiter.as_ref()) {
+            Ok(redirector) => redirector,
+            Err(ref e) => {
+                error!("{}", e);
+                return;
+            }
+        };
+        redirector.set_link_type(inter.link_type);
+        if let Some(ref shutdown) = shutdown {
+            redirector.set_shutdown(shutdown.clone(), Duration::from_secs(flags.grace_period));
+        }
+        #[cfg(feature = "metrics")]
+        {
+            if flags.metrics.is_some() {
+                let m = Arc::new(Metrics::new(&inter.name, redirector.get_stats()));
+                redirector.set_metrics(Arc::clone(&m));
+                metrics.push(m);
+            }
+        }
+        redirectors.push(redirector);
+        rxs.push(rx);
+    }
+    #[cfg(feature = "metrics")]
+    {
+        if let Some(addr) = flags.metrics {
+            if !addr.ip().is_loopback() {
+                warn!("Metrics are served without authentication on {}", addr);
+            }
+            if let Err(ref e) = lib::stats::metrics::serve(addr, metrics) {
+                error!("{}", e);
+                return;
+            }
+            info!("Serve metrics on {}", addr);
+        }
+    }
+    info!("Proxy {} to {}", flags.src, flags.dst);
+    let result = match rxs.len() {
+        1 => redirectors[0].open(&mut rxs[0]).await,
+        _ => {
+            let (tx, rx) = mpsc::channel();
+            for (i, inter_rx) in rxs.into_iter().enumerate() {
+                lib::capture(i, inter_rx, tx.clone(), flags.pool_policy);
+            }
+            drop(tx);
+            Redirector::open_multiple(&mut redirectors, &rx).await
+        }
+    };
+    if let Err(ref e) = result {
+        error!("{}", e);
+    }
+}
+
+async fn replay(flags: &args::Flags, file: &str) {
+    let mut capture = match Capture::from_file(file) {
+        Ok(capture) => capture,
         Err(ref e) => {
             error!("{}", e);
             return;
         }
     };
+    capture.set_realtime(flags.realtime);
+    capture.set_snaplen(flags.snaplen);
+    info!("Replay {}", file);
+
+    // Frames to be sent are discarded
     let forwarder = Forwarder::new(
-        tx,
+        Box::new(NullSender),
         flags.mtu,
-        inter.hardware_addr,
+        flags
+            .hardware_addr
+            .unwrap_or(lib::pcap::HARDWARE_ADDR_UNSPECIFIED),
         flags.src,
-        inter.ip_addrs[0],
+        flags.publish.unwrap_or(Ipv4Addr::UNSPECIFIED),
     );
+    let limiter = get_limiter(flags);
+    let mut redirector = match configure(forwarder, flags, flags.dump.clone(), limiter.as_ref()) {
+        Ok(redirector) => redirector,
+        Err(ref e) => {
+            error!("{}", e);
+            return;
+        }
+    };
+    info!("Proxy {} to {}", flags.src, flags.dst);
+    match redirector.open(&mut capture.into_receiver()).await {
+        Ok(_) => {}
+        Err(ref e) if e.kind() == io::ErrorKind::UnexpectedEof => info!("Replay completed"),
+        Err(ref e) => error!("{}", e),
+    }
+}
+
+/// Gets the local hardware address and IPv4 address of the given interface, where the hardware
+/// address is the configured one if any. The first non-link-local IPv4 address is preferred.
+fn get_local_addrs(flags: &args::Flags, inter: &Interface) -> io::Result<(MacAddr, Ipv4Addr)> {
+    let (hardware_addr, ip_addrs) = pcap::interface_addrs(&inter.name)?;
+    let ip_addr = ip_addrs
+        .into_iter()
+        .find_map(|ip_addr| match ip_addr {
+            IpAddr::V4(ip_addr) => Some(ip_addr),
+            IpAddr::V6(_) => None,
+        })
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "IPv4 address not found"))?;
+
+    Ok((flags.hardware_addr.unwrap_or(hardware_addr), ip_addr))
+}
+
+/// Configures the given forwarder and a redirector of it by the flags, which dumps frames sent
+/// into the given file and is rate limited by the given limiter if any.
+fn configure(
+    mut forwarder: Forwarder,
+    flags: &args::Flags,
+    dump: Option<String>,
+    limiter: Option<&TokenBucket>,
+) -> io::Result<Redirector> {
+    if let Some(mss) = flags.tcp_mss {
+        forwarder.set_tcp_mss(mss);
+    }
+    forwarder.set_tcp_window_scale(flags.tcp_wscale);
+    forwarder.set_tcp_ecn(flags.tcp_ecn);
+    forwarder.set_checksum_offload(flags.checksum_offload);
+    forwarder.set_pool_policy(flags.pool_policy);
+    forwarder.set_dscp(flags.dscp);
+    forwarder.set_ttl_mode(get_ttl_mode(flags));
+    forwarder.set_dns_cache(flags.dns_cache);
+    forwarder.set_dns_names(flags.dns_names);
+    if let Some(ref dump) = dump {
+        forwarder.set_writer(PcapWriter::create_with_snaplen(dump, flags.dump_snaplen)?);
+        info!("Dump to {}", dump);
+    }
+
     let mut redirector = Redirector::new(
         Arc::new(Mutex::new(forwarder)),
         flags.src,
         flags.publish,
         flags.dst,
     );
-    info!("Proxy {} to {}", flags.src, flags.dst);
-    if let Err(ref e) = redirector.open(&mut rx).await {
-        error!("{}", e);
+    redirector.set_connection_table(flags.max_flows, Duration::from_secs(flags.idle_timeout));
+    redirector.set_half_open_timeout(Duration::from_secs(flags.half_open_timeout));
+    redirector.set_udp_duplicate_window(flags.udp_dedup_window.map(Duration::from_millis));
+    redirector.set_keepalive_interval(flags.keepalive.map(Duration::from_secs));
+    redirector.set_gratuitous_arp_interval(flags.gratuitous_arp_interval.map(Duration::from_secs));
+    if let Some(max) = flags.max_connections {
+        redirector.set_connection_limit(max, get_connection_limit_policy(flags));
+    }
+    redirector.set_dry_run(flags.dry_run);
+    redirector.set_enforce_mtu(flags.enforce_mtu);
+    redirector.set_nat64(flags.nat64);
+    redirector.set_active_ftp(flags.active_ftp);
+    redirector.set_route_table(get_route_table(flags));
+    if !flags.excludes.is_empty() {
+        redirector.set_filter(get_filter(flags));
+    }
+    if let Some(hook) = get_inspection_hook(flags) {
+        redirector.set_inspection_hook(hook);
+    }
+    redirector.set_remotes(get_remotes(flags));
+    redirector.set_connect_options(get_connect_options(flags));
+    redirector.set_urgent_pointer(flags.tcp_urgent_pointer);
+    if let (Some(username), Some(password)) = (&flags.username, &flags.password) {
+        redirector.set_auth(SocksAuth::UserPass {
+            username: username.clone(),
+            password: password.clone(),
+        });
+    }
+    if let Some(limiter) = limiter {
+        redirector.set_limiter(limiter.clone());
+    }
+
+    Ok(redirector)
+}
+
+fn get_connection_limit_policy(flags: &args::Flags) -> ConnectionLimitPolicy {
+    match flags.drop_over_max_connections {
+        true => ConnectionLimitPolicy::Drop,
+        false => ConnectionLimitPolicy::Reset,
+    }
+}
+
+fn get_limiter(flags: &args::Flags) -> Option<TokenBucket> {
+    flags.rate_limit.map(|rate| {
+        let policy = match flags.drop_over_limit {
+            true => LimitPolicy::Drop,
+            false => LimitPolicy::Queue,
+        };
+        info!("Limit rate to {} Bytes/s", rate);
+        TokenBucket::new(rate, flags.burst.unwrap_or(rate), policy)
+    })
+}
+
+fn get_route_table(flags: &args::Flags) -> RouteTable {
+    let mut routes = RouteTable::new(flags.default_route);
+    routes.extend(flags.routes.iter().cloned());
+    for rule in routes.get_rules() {
+        info!("Route {} to {}", rule.network, rule.route);
+    }
+    if routes.has_direct() {
+        warn!("UDP routed directly is still forwarded through the proxy");
+    }
+
+    routes
+}
+
+fn get_filter(flags: &args::Flags) -> Box<dyn Filter> {
+    for rule in flags.excludes.iter() {
+        info!("Exclude {}", rule);
+    }
+
+    Box::new(filter::exclude(flags.excludes.clone()))
+}
+
+fn get_ttl_mode(flags: &args::Flags) -> TtlMode {
+    match (flags.ttl, flags.decrement_ttl) {
+        (Some(ttl), _) => TtlMode::Fixed(ttl),
+        (None, true) => TtlMode::Decrement,
+        (None, false) => TtlMode::Initial,
+    }
+}
+
+fn get_inspection_hook(flags: &args::Flags) -> Option<Arc<dyn InspectionHook>> {
+    let mut hooks: Vec<Box<dyn InspectionHook>> = Vec::new();
+    if !flags.redacts.is_empty() {
+        info!("Redact {} patterns", flags.redacts.len());
+        hooks.push(Box::new(Redactor::new_masking(
+            flags
+                .redacts
+                .iter()
+                .map(|s| s.as_bytes().to_vec())
+                .collect(),
+        )));
+    }
+    // Log the payloads as forwarded
+    if flags.log_payloads {
+        hooks.push(Box::new(MetadataLogger));
+    }
+
+    match hooks.len() {
+        0 => None,
+        _ => Some(Arc::new(Chain(hooks))),
+    }
+}
+
+fn get_remotes(flags: &args::Flags) -> SocksPool {
+    let mut remotes = SocksPool::new(
+        flags.dst,
+        flags.select_mode,
+        Duration::from_secs(flags.proxy_cooldown),
+    );
+    for backup_dst in flags.backup_dsts.iter() {
+        info!("Back up proxy {} with {}", flags.dst, backup_dst);
+        remotes.insert(*backup_dst);
+    }
+
+    remotes
+}
+
+fn get_connect_options(flags: &args::Flags) -> ConnectOptions {
+    ConnectOptions {
+        local: flags.bind,
+        timeout: match flags.connect_timeout {
+            0 => None,
+            timeout => Some(Duration::from_millis(timeout)),
+        },
+        max_retries: flags.connect_retries,
+        base_delay: Duration::from_millis(flags.retry_delay),
+        keepalive: match flags.keepalive_proxy {
+            true => flags.keepalive.map(Duration::from_secs),
+            false => None,
+        },
+        version: flags.socks_version,
+        ..ConnectOptions::default()
     }
 }
 
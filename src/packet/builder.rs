@@ -0,0 +1,175 @@
+use super::layer::arp::Arp;
+use super::layer::ethernet::Ethernet;
+use super::layer::icmp::Icmp;
+use super::layer::icmpv6::Icmpv6;
+use super::layer::ipv4::Ipv4;
+use super::layer::ipv6::Ipv6;
+use super::layer::tcp::Tcp;
+use super::layer::udp::Udp;
+use super::layer::{Layer, Layers};
+use std::io;
+
+/// Represents a builder assembling stacked layers into a frame.
+#[derive(Clone, Debug, Default)]
+pub struct PacketBuilder {
+    layers: Vec<Layers>,
+    payload: Vec<u8>,
+}
+
+impl PacketBuilder {
+    /// Creates a new `PacketBuilder`.
+    pub fn new() -> PacketBuilder {
+        PacketBuilder {
+            layers: Vec::new(),
+            payload: Vec::new(),
+        }
+    }
+
+    /// Appends a layer to the stack.
+    pub fn layer(mut self, layer: Layers) -> PacketBuilder {
+        self.layers.push(layer);
+        self
+    }
+
+    /// Appends an `Ethernet` to the stack.
+    pub fn ethernet(self, layer: Ethernet) -> PacketBuilder {
+        self.layer(Layers::Ethernet(layer))
+    }
+
+    /// Appends an `Arp` to the stack.
+    pub fn arp(self, layer: Arp) -> PacketBuilder {
+        self.layer(Layers::Arp(layer))
+    }
+
+    /// Appends an `Ipv4` to the stack.
+    pub fn ipv4(self, layer: Ipv4) -> PacketBuilder {
+        self.layer(Layers::Ipv4(layer))
+    }
+
+    /// Appends an `Ipv6` to the stack.
+    pub fn ipv6(self, layer: Ipv6) -> PacketBuilder {
+        self.layer(Layers::Ipv6(layer))
+    }
+
+    /// Appends a `Tcp` to the stack.
+    pub fn tcp(self, layer: Tcp) -> PacketBuilder {
+        self.layer(Layers::Tcp(layer))
+    }
+
+    /// Appends an `Udp` to the stack.
+    pub fn udp(self, layer: Udp) -> PacketBuilder {
+        self.layer(Layers::Udp(layer))
+    }
+
+    /// Appends an `Icmp` to the stack.
+    pub fn icmp(self, layer: Icmp) -> PacketBuilder {
+        self.layer(Layers::Icmp(layer))
+    }
+
+    /// Appends an `Icmpv6` to the stack. The checksum is computed with the addresses of the
+    /// `Ipv6` in the stack.
+    pub fn icmpv6(self, layer: Icmpv6) -> PacketBuilder {
+        self.layer(Layers::Icmpv6(layer))
+    }
+
+    /// Sets the payload after the stack.
+    pub fn payload(mut self, payload: &[u8]) -> PacketBuilder {
+        self.payload = payload.to_vec();
+        self
+    }
+
+    /// Get the size of the frame.
+    pub fn get_size(&self) -> usize {
+        let size: usize = self.layers.iter().map(|layer| layer.get_size()).sum();
+
+        size + self.payload.len()
+    }
+
+    /// Serializes the stack and the payload into a frame. Lengths and checksums of every layer
+    /// are filled in. The frame is not padded to the minimum Ethernet frame size.
+    pub fn build(&self) -> io::Result<Vec<u8>> {
+        if self.layers.is_empty() {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "no layer"));
+        }
+
+        let size = self.get_size();
+        let mut buffer = vec![0u8; size];
+
+        // Copies payload
+        buffer[size - self.payload.len()..].copy_from_slice(&self.payload);
+
+        let n = Layers::serialize_stack(&self.layers, &mut buffer)?;
+        buffer.truncate(n);
+
+        Ok(buffer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::packet::layer::LayerTypes;
+    use pnet::util::MacAddr;
+    use std::net::{IpAddr, Ipv4Addr};
+
+    #[test]
+    fn build_syn() {
+        let src = Ipv4Addr::new(192, 168, 1, 1);
+        let dst = Ipv4Addr::new(192, 168, 1, 2);
+        let ethernet = Ethernet::new(
+            LayerTypes::Ipv4,
+            MacAddr::new(0x02, 0, 0, 0, 0, 0x01),
+            MacAddr::new(0x02, 0, 0, 0, 0, 0x02),
+        )
+        .unwrap();
+        let ipv4 = Ipv4::new(1, LayerTypes::Tcp, src, dst).unwrap();
+        let tcp = Tcp::new_syn(1024, 80, 100, 65535);
+
+        let frame = PacketBuilder::new()
+            .ethernet(ethernet)
+            .ipv4(ipv4)
+            .tcp(tcp)
+            .build()
+            .unwrap();
+        #[rustfmt::skip]
+        let expected = [
+            // Ethernet
+            0x02, 0x00, 0x00, 0x00, 0x00, 0x02, 0x02, 0x00, 0x00, 0x00, 0x00, 0x01, 0x08, 0x00,
+            // IPv4
+            0x45, 0x00, 0x00, 0x28, 0x00, 0x01, 0x00, 0x00, 0x80, 0x06, 0xb7, 0x7b,
+            0xc0, 0xa8, 0x01, 0x01, 0xc0, 0xa8, 0x01, 0x02,
+            // TCP
+            0x04, 0x00, 0x00, 0x50, 0x00, 0x00, 0x00, 0x64, 0x00, 0x00, 0x00, 0x00,
+            0x50, 0x02, 0xff, 0xff, 0x27, 0xdb, 0x00, 0x00,
+        ];
+        assert_eq!(frame, expected);
+    }
+
+    #[test]
+    fn build_udp_ipv6() {
+        let src = "2001:db8::1".parse().unwrap();
+        let dst = "2001:db8::2".parse().unwrap();
+        let ethernet =
+            Ethernet::new(LayerTypes::Ipv6, MacAddr::zero(), MacAddr::broadcast()).unwrap();
+        let ipv6 = Ipv6::new(LayerTypes::Udp, src, dst).unwrap();
+        let udp = Udp::new(1024, 53);
+
+        let frame = PacketBuilder::new()
+            .ethernet(ethernet)
+            .ipv6(ipv6)
+            .udp(udp.clone())
+            .payload(b"query")
+            .build()
+            .unwrap();
+        // The checksum is computed with the IPv6 addresses
+        let checksum = udp
+            .compute_checksum(IpAddr::V6(src), IpAddr::V6(dst), b"query")
+            .unwrap();
+        assert_eq!(frame[14 + 40 + 6..14 + 40 + 8], checksum.to_be_bytes());
+    }
+
+    #[test]
+    fn build_empty() {
+        assert!(PacketBuilder::new().payload(b"payload").build().is_err());
+    }
+}
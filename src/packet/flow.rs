@@ -0,0 +1,143 @@
+use super::layer::Layers;
+use pnet::packet::ip::{IpNextHeaderProtocol, IpNextHeaderProtocols};
+use std::fmt::{self, Display, Formatter};
+use std::net::{IpAddr, SocketAddr};
+
+/// Represents the 5-tuple identifying a flow of TCP or UDP, which can be used as the key of a
+/// connection table.
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct FlowKey {
+    pub protocol: IpNextHeaderProtocol,
+    pub src: SocketAddr,
+    pub dst: SocketAddr,
+}
+
+impl FlowKey {
+    /// Creates a new `FlowKey`.
+    pub fn new(protocol: IpNextHeaderProtocol, src: SocketAddr, dst: SocketAddr) -> FlowKey {
+        FlowKey { protocol, src, dst }
+    }
+
+    /// Returns the `FlowKey` of the reverse direction.
+    pub fn reverse(&self) -> FlowKey {
+        FlowKey::new(self.protocol, self.dst, self.src)
+    }
+
+    /// Returns the canonical `FlowKey` of the flow, which is identical for both directions. The
+    /// lesser endpoint is taken as the source.
+    pub fn canonical(&self) -> FlowKey {
+        match self.src <= self.dst {
+            true => *self,
+            false => self.reverse(),
+        }
+    }
+
+    /// Returns if the `FlowKey` is canonical.
+    pub fn is_canonical(&self) -> bool {
+        self.src <= self.dst
+    }
+}
+
+impl Display for FlowKey {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        let protocol = match self.protocol {
+            IpNextHeaderProtocols::Tcp => String::from("TCP"),
+            IpNextHeaderProtocols::Udp => String::from("UDP"),
+            protocol => format!("{}", protocol.0),
+        };
+
+        write!(f, "{} {} -> {}", protocol, self.src, self.dst)
+    }
+}
+
+impl Layers {
+    /// Get the `FlowKey` of the layer. Returns `None` if the layer is not a TCP or UDP layer,
+    /// e.g., `Arp`. The IP addresses are taken from the network layer the layer is parsed with.
+    pub fn flow_key(&self) -> Option<FlowKey> {
+        match self {
+            Layers::Tcp(ref tcp) => Some(FlowKey::new(
+                IpNextHeaderProtocols::Tcp,
+                SocketAddr::new(IpAddr::V4(tcp.get_src_ip_addr()), tcp.get_src()),
+                SocketAddr::new(IpAddr::V4(tcp.get_dst_ip_addr()), tcp.get_dst()),
+            )),
+            Layers::Udp(ref udp) => Some(FlowKey::new(
+                IpNextHeaderProtocols::Udp,
+                SocketAddr::new(IpAddr::V4(udp.get_src_ip_addr()), udp.get_src()),
+                SocketAddr::new(IpAddr::V4(udp.get_dst_ip_addr()), udp.get_dst()),
+            )),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::packet::layer::arp::Arp;
+    use crate::packet::layer::ipv4::Ipv4;
+    use crate::packet::layer::tcp::Tcp;
+    use crate::packet::layer::udp::Udp;
+    use crate::packet::layer::LayerTypes;
+    use pnet::util::MacAddr;
+    use std::collections::HashMap;
+    use std::net::Ipv4Addr;
+
+    const CLIENT: Ipv4Addr = Ipv4Addr::new(192, 168, 1, 2);
+    const SERVER: Ipv4Addr = Ipv4Addr::new(93, 184, 216, 34);
+
+    /// Creates a TCP layer of the given direction between the client and the server.
+    fn tcp(src: (Ipv4Addr, u16), dst: (Ipv4Addr, u16)) -> Layers {
+        let ipv4 = Ipv4::new(1, LayerTypes::Tcp, src.0, dst.0).unwrap();
+        let mut tcp = Tcp::new_ack(src.1, dst.1, 0, 0, 65535);
+        tcp.set_ipv4_layer(&ipv4);
+
+        Layers::Tcp(tcp)
+    }
+
+    #[test]
+    fn flow_key_canonical() {
+        let forward = tcp((CLIENT, 50000), (SERVER, 80)).flow_key().unwrap();
+        let reverse = tcp((SERVER, 80), (CLIENT, 50000)).flow_key().unwrap();
+
+        assert_eq!(forward.protocol, IpNextHeaderProtocols::Tcp);
+        assert_eq!(forward.src, SocketAddr::new(IpAddr::V4(CLIENT), 50000));
+        assert_eq!(forward.dst, SocketAddr::new(IpAddr::V4(SERVER), 80));
+        assert_eq!(forward.reverse(), reverse);
+        assert_ne!(forward, reverse);
+        assert_eq!(forward.canonical(), reverse.canonical());
+        assert!(forward.canonical().is_canonical());
+        assert_eq!(forward.is_canonical(), !reverse.is_canonical());
+        // Canonicalization is idempotent
+        assert_eq!(forward.canonical().canonical(), forward.canonical());
+    }
+
+    #[test]
+    fn flow_key_lookup_both_directions() {
+        let mut table = HashMap::new();
+        let forward = tcp((CLIENT, 50000), (SERVER, 80)).flow_key().unwrap();
+        table.insert(forward.canonical(), "connection");
+
+        let reverse = tcp((SERVER, 80), (CLIENT, 50000)).flow_key().unwrap();
+        assert_eq!(table.get(&reverse.canonical()), Some(&"connection"));
+    }
+
+    #[test]
+    fn flow_key_udp() {
+        let ipv4 = Ipv4::new(1, LayerTypes::Udp, CLIENT, SERVER).unwrap();
+        let mut udp = Udp::new(50000, 53);
+        udp.set_ipv4_layer(&ipv4);
+        let key = Layers::Udp(udp).flow_key().unwrap();
+
+        // Flows of different protocols on the same ports are distinct
+        let tcp_key = tcp((CLIENT, 50000), (SERVER, 53)).flow_key().unwrap();
+        assert_eq!(key.protocol, IpNextHeaderProtocols::Udp);
+        assert_ne!(key, tcp_key);
+        assert_eq!(key.to_string(), "UDP 192.168.1.2:50000 -> 93.184.216.34:53");
+    }
+
+    #[test]
+    fn flow_key_arp() {
+        let arp = Arp::new_reply(MacAddr::zero(), CLIENT, MacAddr::broadcast(), SERVER);
+        assert!(Layers::Arp(arp).flow_key().is_none());
+    }
+}
@@ -0,0 +1,449 @@
+use super::layer::arp::Arp;
+use super::layer::ethernet::Ethernet;
+use super::layer::gre::Gre;
+use super::layer::icmp::Icmp;
+use super::layer::icmpv6::Icmpv6;
+use super::layer::igmp::Igmp;
+use super::layer::ipv4::Ipv4;
+use super::layer::ipv6::Ipv6;
+use super::layer::tcp::Tcp;
+use super::layer::udp::Udp;
+use super::layer::unknown::Unknown;
+use super::layer::{LayerType, LayerTypes, Layers, ParseError};
+use pnet::packet::ethernet::{EtherType, EtherTypes};
+use pnet::packet::ip::{IpNextHeaderProtocol, IpNextHeaderProtocols};
+use pnet::packet::ipv6::Ipv6Packet;
+use std::cmp::min;
+use std::net::{Ipv4Addr, Ipv6Addr};
+use std::ops::Range;
+
+/// Represents the next layer expected by a `LayerIter`.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+enum Next {
+    Ethernet,
+    Network(EtherType),
+    Transport(IpNextHeaderProtocol),
+    Done,
+}
+
+/// Represents a lazy iterator over the layers of an Ethernet frame in encapsulation order. Each
+/// layer is yielded with the byte range of its header in the frame. The layers encapsulated in
+/// GRE are iterated following the GRE layer. The iteration stops at the
+/// first layer which cannot be parsed, or which is not followed by a known layer. The length of
+/// the frame is its captured length, a network layer claiming more bytes than captured is
+/// truncated.
+#[derive(Clone, Debug)]
+pub struct LayerIter<'a> {
+    frame: &'a [u8],
+    offset: usize,
+    /// Represents the end of the current network layer, which excludes the Ethernet padding.
+    end: usize,
+    next: Next,
+    ipv4_addrs: Option<(Ipv4Addr, Ipv4Addr)>,
+    ipv6_addrs: Option<(Ipv6Addr, Ipv6Addr)>,
+    /// Represents the error of the first network layer exceeding the captured length, which is
+    /// reported after the layers in the captured bytes are iterated.
+    truncated: Option<ParseError>,
+    error: Option<ParseError>,
+}
+
+/// Iterates over the layers of the given Ethernet frame.
+pub fn layers(frame: &[u8]) -> LayerIter<'_> {
+    LayerIter {
+        frame,
+        offset: 0,
+        end: frame.len(),
+        next: Next::Ethernet,
+        ipv4_addrs: None,
+        ipv6_addrs: None,
+        truncated: None,
+        error: None,
+    }
+}
+
+/// Parses the layers of the given Ethernet frame in encapsulation order. The parsing stops at
+/// the first layer which is not known, and returns the layers parsed. The payload following the
+/// last layer is kept in the last layer. Returns an error if a known layer cannot be parsed.
+pub fn parse_ethernet_stack(buffer: &[u8]) -> Result<Vec<Layers>, ParseError> {
+    let mut iter = layers(buffer);
+    let stack: Vec<Layers> = iter.by_ref().map(|(layer, _)| layer).collect();
+
+    match iter.get_error() {
+        Some(e) => Err(e),
+        None => Ok(stack),
+    }
+}
+
+impl<'a> LayerIter<'a> {
+    /// Get the error which stops the iteration. Returns `None` if the iteration is not stopped
+    /// or all the known layers are parsed. A network layer exceeding the captured length is
+    /// reported as `ParseError::Truncated` when the iteration ends, unless a following layer
+    /// cannot be parsed.
+    pub fn get_error(&self) -> Option<ParseError> {
+        self.error
+    }
+
+    /// Get the bytes following the last layer yielded.
+    pub fn get_remaining(&self) -> &'a [u8] {
+        &self.frame[min(self.offset, self.end)..self.end]
+    }
+
+    fn parse(&mut self) -> Result<Option<(Layers, usize)>, ParseError> {
+        let buffer = self.get_remaining();
+
+        let (layer, n) = match self.next {
+            Next::Ethernet => {
+                let (ethernet, n) = Ethernet::deserialize(buffer)?;
+                self.next = Next::Network(ethernet.get_ethertype());
+                (Layers::Ethernet(ethernet), n)
+            }
+            Next::Network(ethertype) => match ethertype {
+                EtherTypes::Arp => {
+                    let (arp, n) = Arp::deserialize(buffer)?;
+                    self.next = Next::Done;
+                    (Layers::Arp(arp), n)
+                }
+                EtherTypes::Ipv4 => {
+                    let (ipv4, n) = Ipv4::deserialize(buffer)?;
+                    let length = ipv4.get_total_length() as usize;
+                    if length > buffer.len() {
+                        self.set_truncated(LayerTypes::Ipv4);
+                    }
+                    self.end = self.offset + min(length, buffer.len());
+                    // Only the first fragment carries the transport layer
+                    self.next = match ipv4.get_fragment_offset() {
+                        0 => Next::Transport(ipv4.get_next_level_protocol()),
+                        _ => Next::Done,
+                    };
+                    self.ipv4_addrs = Some((ipv4.get_src(), ipv4.get_dst()));
+                    self.ipv6_addrs = None;
+                    (Layers::Ipv4(ipv4), n)
+                }
+                EtherTypes::Ipv6 => {
+                    let (ipv6, n) = Ipv6::deserialize(buffer)?;
+                    let length =
+                        Ipv6Packet::minimum_packet_size() + ipv6.get_payload_length() as usize;
+                    if length > buffer.len() {
+                        self.set_truncated(LayerTypes::Ipv6);
+                    }
+                    self.end = self.offset + min(length, buffer.len());
+                    self.next = Next::Transport(ipv6.get_transport_protocol());
+                    self.ipv6_addrs = Some((ipv6.get_src(), ipv6.get_dst()));
+                    self.ipv4_addrs = None;
+                    (Layers::Ipv6(ipv6), n)
+                }
+                t => {
+                    self.next = Next::Done;
+                    (Layers::Unknown(Unknown::new(t, buffer)), buffer.len())
+                }
+            },
+            Next::Transport(protocol) => {
+                self.next = Next::Done;
+                match protocol {
+                    // Continue into the tunnel
+                    IpNextHeaderProtocols::Gre => {
+                        let (gre, n) = Gre::deserialize(buffer)?;
+                        self.next = Next::Network(gre.get_protocol_type());
+                        (Layers::Gre(gre), n)
+                    }
+                    IpNextHeaderProtocols::Tcp => {
+                        let (mut tcp, n) = Tcp::deserialize(buffer)?;
+                        if let Some((src, dst)) = self.ipv4_addrs {
+                            tcp.src = src;
+                            tcp.dst = dst;
+                        }
+                        tcp.ipv6 = self.ipv6_addrs;
+                        (Layers::Tcp(tcp), n)
+                    }
+                    IpNextHeaderProtocols::Udp => {
+                        let (mut udp, n) = Udp::deserialize(buffer)?;
+                        if let Some((src, dst)) = self.ipv4_addrs {
+                            udp.src = src;
+                            udp.dst = dst;
+                        }
+                        udp.ipv6 = self.ipv6_addrs;
+                        (Layers::Udp(udp), n)
+                    }
+                    IpNextHeaderProtocols::Icmp => {
+                        let (icmp, n) = Icmp::deserialize(buffer)?;
+                        (Layers::Icmp(icmp), n)
+                    }
+                    IpNextHeaderProtocols::Igmp => {
+                        let (igmp, n) = Igmp::deserialize(buffer)?;
+                        (Layers::Igmp(igmp), n)
+                    }
+                    IpNextHeaderProtocols::Icmpv6 => {
+                        let (mut icmpv6, n) = Icmpv6::deserialize(buffer)?;
+                        if let Some((src, dst)) = self.ipv6_addrs {
+                            icmpv6.src = src;
+                            icmpv6.dst = dst;
+                        }
+                        (Layers::Icmpv6(icmpv6), n)
+                    }
+                    _ => return Ok(None),
+                }
+            }
+            Next::Done => return Ok(None),
+        };
+
+        Ok(Some((layer, n)))
+    }
+
+    fn set_truncated(&mut self, t: LayerType) {
+        if self.truncated.is_none() {
+            self.truncated = Some(ParseError::Truncated(t));
+        }
+    }
+}
+
+impl<'a> Iterator for LayerIter<'a> {
+    type Item = (Layers, Range<usize>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.parse() {
+            Ok(Some((layer, n))) => {
+                let range = self.offset..self.offset + n;
+                self.offset += n;
+
+                Some((layer, range))
+            }
+            Ok(None) => {
+                self.next = Next::Done;
+                if self.error.is_none() {
+                    self.error = self.truncated;
+                }
+                None
+            }
+            Err(e) => {
+                self.next = Next::Done;
+                self.error = Some(e);
+                None
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::packet::layer::Layer;
+    use crate::packet::PacketBuilder;
+    use pnet::util::MacAddr;
+
+    /// Builds an Ethernet frame of a UDP datagram carrying the given payload, padded to the
+    /// minimum size of Ethernet frames.
+    fn build_udp_frame(payload: &[u8]) -> Vec<u8> {
+        let ethernet = Ethernet::new(
+            LayerTypes::Ipv4,
+            MacAddr::new(0x02, 0, 0, 0, 0, 0x01),
+            MacAddr::broadcast(),
+        )
+        .unwrap();
+        let ipv4 = Ipv4::new(
+            1,
+            LayerTypes::Udp,
+            Ipv4Addr::new(192, 168, 1, 1),
+            Ipv4Addr::new(192, 168, 1, 2),
+        )
+        .unwrap();
+        let mut udp = Udp::new(1024, 53);
+        udp.set_ipv4_layer(&ipv4);
+        let mut frame = PacketBuilder::new()
+            .ethernet(ethernet)
+            .ipv4(ipv4)
+            .udp(udp)
+            .payload(payload)
+            .build()
+            .unwrap();
+        frame.resize(60, 0);
+
+        frame
+    }
+
+    #[test]
+    fn iterate_ethernet_ipv4_udp() {
+        let frame = build_udp_frame(b"query");
+        let mut iter = layers(&frame);
+
+        let types: Vec<_> = iter
+            .by_ref()
+            .map(|(layer, range)| (layer.get_type(), range))
+            .collect();
+        assert_eq!(
+            types,
+            [
+                (LayerTypes::Ethernet, 0..14),
+                (LayerTypes::Ipv4, 14..34),
+                (LayerTypes::Udp, 34..42),
+            ]
+        );
+        // The padding is not a part of the remaining bytes
+        assert_eq!(iter.get_remaining(), b"query");
+        assert!(iter.get_error().is_none());
+    }
+
+    #[test]
+    fn iterate_stop_at_error() {
+        let mut frame = build_udp_frame(b"query");
+        // Corrupt the IPv4 checksum
+        frame[24] ^= 0xff;
+        let mut iter = layers(&frame);
+
+        assert_eq!(iter.next().unwrap().0.get_type(), LayerTypes::Ethernet);
+        assert!(iter.next().is_none());
+        assert_eq!(
+            iter.get_error(),
+            Some(ParseError::ChecksumMismatch(LayerTypes::Ipv4))
+        );
+        assert!(iter.next().is_none());
+    }
+
+    #[test]
+    fn iterate_truncated() {
+        let frame = build_udp_frame(b"query");
+
+        // The frame is captured below its IPv4 total length
+        let mut iter = layers(&frame[..14 + 20 + 8 + 2]);
+        let types: Vec<_> = iter.by_ref().map(|(layer, _)| layer.get_type()).collect();
+        assert_eq!(
+            types,
+            [LayerTypes::Ethernet, LayerTypes::Ipv4, LayerTypes::Udp]
+        );
+        assert_eq!(iter.get_remaining(), b"qu");
+        assert_eq!(
+            iter.get_error(),
+            Some(ParseError::Truncated(LayerTypes::Ipv4))
+        );
+        assert!(parse_ethernet_stack(&frame[..14 + 20 + 8 + 2]).is_err());
+
+        // The frame is captured in the middle of the UDP header
+        let mut iter = layers(&frame[..14 + 20 + 4]);
+        assert_eq!(iter.by_ref().count(), 2);
+        assert_eq!(
+            iter.get_error(),
+            Some(ParseError::Truncated(LayerTypes::Udp))
+        );
+    }
+
+    #[test]
+    fn parse_stack() {
+        let stack = parse_ethernet_stack(&build_udp_frame(b"query")).unwrap();
+
+        assert_eq!(stack.len(), 3);
+        assert_eq!(
+            stack[1].payload().unwrap(),
+            &build_udp_frame(b"query")[34..47]
+        );
+        assert!(parse_ethernet_stack(&[0u8; 13]).is_err());
+    }
+
+    #[test]
+    fn iterate_gre_tunnel() {
+        // IPv4 and UDP of the inner frame
+        let inner = build_udp_frame(b"query")[14..47].to_vec();
+        let ethernet = Ethernet::new(
+            LayerTypes::Ipv4,
+            MacAddr::new(0x02, 0, 0, 0, 0, 0x01),
+            MacAddr::new(0x02, 0, 0, 0, 0, 0x02),
+        )
+        .unwrap();
+        let ipv4 = Ipv4::new(
+            1,
+            LayerTypes::Gre,
+            Ipv4Addr::new(10, 0, 0, 1),
+            Ipv4Addr::new(10, 0, 0, 2),
+        )
+        .unwrap();
+        let mut gre = Gre::new(EtherTypes::Ipv4);
+        gre.set_key(Some(42));
+        let frame = PacketBuilder::new()
+            .ethernet(ethernet)
+            .ipv4(ipv4)
+            .layer(Layers::Gre(gre))
+            .payload(&inner)
+            .build()
+            .unwrap();
+        let mut iter = layers(&frame);
+
+        let types: Vec<_> = iter
+            .by_ref()
+            .map(|(layer, range)| (layer.get_type(), range))
+            .collect();
+        assert_eq!(
+            types,
+            [
+                (LayerTypes::Ethernet, 0..14),
+                (LayerTypes::Ipv4, 14..34),
+                (LayerTypes::Gre, 34..42),
+                (LayerTypes::Ipv4, 42..62),
+                (LayerTypes::Udp, 62..70),
+            ]
+        );
+        assert_eq!(iter.get_remaining(), b"query");
+        assert!(iter.get_error().is_none());
+
+        let (layer, _) = layers(&frame).nth(2).unwrap();
+        match layer {
+            Layers::Gre(gre) => assert_eq!(gre.get_key(), Some(42)),
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn parse_stack_ipv4_tcp() {
+        let ethernet = Ethernet::new(
+            LayerTypes::Ipv4,
+            MacAddr::new(0x02, 0, 0, 0, 0, 0x01),
+            MacAddr::new(0x02, 0, 0, 0, 0, 0x02),
+        )
+        .unwrap();
+        let ipv4 = Ipv4::new(
+            1,
+            LayerTypes::Tcp,
+            Ipv4Addr::new(192, 168, 1, 1),
+            Ipv4Addr::new(192, 168, 1, 2),
+        )
+        .unwrap();
+        let mut tcp = Tcp::new_ack(1024, 80, 1001, 5001, 65535);
+        tcp.set_ipv4_layer(&ipv4);
+        let frame = PacketBuilder::new()
+            .ethernet(ethernet)
+            .ipv4(ipv4)
+            .tcp(tcp)
+            .payload(b"hello")
+            .build()
+            .unwrap();
+        let stack = parse_ethernet_stack(&frame).unwrap();
+
+        let types: Vec<_> = stack.iter().map(|layer| layer.get_type()).collect();
+        assert_eq!(
+            types,
+            [LayerTypes::Ethernet, LayerTypes::Ipv4, LayerTypes::Tcp]
+        );
+        match stack[2] {
+            Layers::Tcp(ref tcp) => {
+                assert_eq!(tcp.get_src(), 1024);
+                assert_eq!(tcp.get_src_ip_addr(), Ipv4Addr::new(192, 168, 1, 1));
+            }
+            _ => unreachable!(),
+        }
+        // The payload is kept in the last layer
+        assert_eq!(stack[2].payload().unwrap(), b"hello");
+    }
+
+    #[test]
+    fn parse_stack_unknown_transport() {
+        let mut frame = build_udp_frame(b"query");
+        // Replace the protocol with SCTP, which is not known
+        frame[23] = 132;
+        frame[24..26].copy_from_slice(&[0, 0]);
+        let checksum = pnet::util::checksum(&frame[14..34], 5);
+        frame[24..26].copy_from_slice(&checksum.to_be_bytes());
+        let stack = parse_ethernet_stack(&frame).unwrap();
+
+        // The parsing stops gracefully with the datagram in the IPv4 layer
+        assert_eq!(stack.len(), 2);
+        assert_eq!(stack[1].get_type(), LayerTypes::Ipv4);
+        assert_eq!(stack[1].payload().unwrap(), &frame[34..47]);
+    }
+}
@@ -1,11 +1,16 @@
-use super::{Layer, LayerType, LayerTypes};
+use super::{fmt_hex_dump, Layer, LayerType, LayerTypes, ParseError, SizeBounds};
 use pnet::datalink::MacAddr;
 use pnet::packet::arp::{self, ArpHardwareTypes, ArpOperations, ArpPacket, MutableArpPacket};
 use pnet::packet::ethernet::EtherTypes;
 use std::clone::Clone;
+use std::collections::HashMap;
 use std::fmt::{self, Display, Formatter};
 use std::io;
 use std::net::Ipv4Addr;
+use std::time::{Duration, Instant};
+
+/// Represents the default time to live of entries in an `ArpCache`.
+pub const DEFAULT_ARP_CACHE_TTL: Duration = Duration::from_secs(300);
 
 /// Represents an ARP layer.
 #[derive(Clone, Debug)]
@@ -36,6 +41,24 @@ impl Arp {
         Arp::from(arp)
     }
 
+    /// Creates a gratuitous ARP request announcing the given IP address with the given hardware
+    /// address. The target IP address is the same as the sender's.
+    pub fn new_gratuitous(hardware_addr: MacAddr, ip_addr: Ipv4Addr) -> Arp {
+        let arp = arp::Arp {
+            hardware_type: ArpHardwareTypes::Ethernet,
+            protocol_type: EtherTypes::Ipv4,
+            hw_addr_len: 6,
+            proto_addr_len: 4,
+            operation: ArpOperations::Request,
+            sender_hw_addr: hardware_addr,
+            sender_proto_addr: ip_addr,
+            target_hw_addr: MacAddr::zero(),
+            target_proto_addr: ip_addr,
+            payload: vec![],
+        };
+        Arp::from(arp)
+    }
+
     /// Creates an `Arp` according to the given `Arp`.
     pub fn from(arp: arp::Arp) -> Arp {
         Arp { layer: arp }
@@ -58,6 +81,31 @@ impl Arp {
         Arp::from(arp)
     }
 
+    /// Deserializes an `Arp` from the given byte-array and returns it with the number of bytes
+    /// consumed.
+    pub fn deserialize(buffer: &[u8]) -> Result<(Arp, usize), ParseError> {
+        if buffer.len() < Arp::min_size() {
+            return Err(ParseError::Truncated(LayerTypes::Arp));
+        }
+        let packet = ArpPacket::new(buffer).ok_or(ParseError::Truncated(LayerTypes::Arp))?;
+        if packet.get_hw_addr_len() != 6 {
+            return Err(ParseError::InvalidValue(
+                LayerTypes::Arp,
+                "hardware address length",
+            ));
+        }
+        if packet.get_proto_addr_len() != 4 {
+            return Err(ParseError::InvalidValue(
+                LayerTypes::Arp,
+                "protocol address length",
+            ));
+        }
+        let arp = Arp::parse(&packet);
+        let size = arp.get_size();
+
+        Ok((arp, size))
+    }
+
     /// Creates an ARP reply according to a given `Arp`.
     pub fn reply(layer: &Arp, hardware_addr: MacAddr) -> Arp {
         let arp = arp::Arp {
@@ -95,6 +143,11 @@ impl Arp {
         }
     }
 
+    /// Returns if the `Arp` is a gratuitous ARP, which announces the sender's own address.
+    pub fn is_gratuitous(&self) -> bool {
+        self.layer.sender_proto_addr == self.layer.target_proto_addr
+    }
+
     /// Get the source hardware address of the layer.
     pub fn get_src_hardware_addr(&self) -> MacAddr {
         self.layer.sender_hw_addr
@@ -116,8 +169,26 @@ impl Arp {
     }
 }
 
+impl PartialEq for Arp {
+    fn eq(&self, other: &Arp) -> bool {
+        self.layer.hardware_type == other.layer.hardware_type
+            && self.layer.protocol_type == other.layer.protocol_type
+            && self.layer.hw_addr_len == other.layer.hw_addr_len
+            && self.layer.proto_addr_len == other.layer.proto_addr_len
+            && self.layer.operation == other.layer.operation
+            && self.layer.sender_hw_addr == other.layer.sender_hw_addr
+            && self.layer.sender_proto_addr == other.layer.sender_proto_addr
+            && self.layer.target_hw_addr == other.layer.target_hw_addr
+            && self.layer.target_proto_addr == other.layer.target_proto_addr
+    }
+}
+
 impl Display for Arp {
     fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        if f.alternate() {
+            return fmt_hex_dump(self, f);
+        }
+
         write!(
             f,
             "{}: {} -> {}, Operation = {}",
@@ -133,6 +204,16 @@ impl Display for Arp {
     }
 }
 
+impl SizeBounds for Arp {
+    fn min_size() -> usize {
+        ArpPacket::minimum_packet_size()
+    }
+
+    fn max_size() -> usize {
+        ArpPacket::minimum_packet_size()
+    }
+}
+
 impl Layer for Arp {
     fn get_type(&self) -> LayerType {
         LayerTypes::Arp
@@ -144,7 +225,7 @@ impl Layer for Arp {
 
     fn serialize(&self, buffer: &mut [u8], _: usize) -> io::Result<usize> {
         let mut packet = MutableArpPacket::new(buffer)
-            .ok_or(io::Error::new(io::ErrorKind::WriteZero, "buffer too small"))?;
+            .ok_or_else(|| io::Error::new(io::ErrorKind::WriteZero, "buffer too small"))?;
 
         packet.populate(&self.layer);
 
@@ -154,4 +235,203 @@ impl Layer for Arp {
     fn serialize_with_payload(&self, buffer: &mut [u8], _: &[u8], n: usize) -> io::Result<usize> {
         self.serialize(buffer, n)
     }
+
+    fn clone_boxed(&self) -> Box<dyn Layer> {
+        Box::new(self.clone())
+    }
+}
+
+/// Builds an ARP reply to the given ARP request, answering the target IP address of the request
+/// with the given hardware address. The sender and target fields of the request are swapped.
+pub fn build_arp_reply(request: &Arp, our_mac: MacAddr) -> Arp {
+    Arp::reply(request, our_mac)
+}
+
+/// Represents a cache mapping IP addresses to hardware addresses.
+#[derive(Debug, Default)]
+pub struct ArpCache {
+    entries: HashMap<Ipv4Addr, (MacAddr, Instant)>,
+}
+
+impl ArpCache {
+    /// Creates a new `ArpCache`.
+    pub fn new() -> ArpCache {
+        ArpCache {
+            entries: HashMap::new(),
+        }
+    }
+
+    /// Inserts an entry into the cache which expires after the given time to live. An existing
+    /// entry of the IP address will be overwritten.
+    pub fn insert(&mut self, ip_addr: Ipv4Addr, hardware_addr: MacAddr, ttl: Duration) {
+        self.entries
+            .insert(ip_addr, (hardware_addr, Instant::now() + ttl));
+    }
+
+    /// Updates the cache according to the given `Arp`. Only ARP replies and gratuitous ARPs are
+    /// accepted. Returns if the cache is updated.
+    pub fn update(&mut self, arp: &Arp, ttl: Duration) -> bool {
+        if !arp.is_reply() && !arp.is_gratuitous() {
+            return false;
+        }
+        if arp.get_src().is_unspecified() {
+            return false;
+        }
+
+        self.insert(arp.get_src(), arp.get_src_hardware_addr(), ttl);
+
+        true
+    }
+
+    /// Looks up the hardware address of the given IP address. Returns `None` if there is no
+    /// entry or the entry is expired.
+    pub fn lookup(&self, ip_addr: Ipv4Addr) -> Option<MacAddr> {
+        match self.entries.get(&ip_addr) {
+            Some((hardware_addr, expiry)) if *expiry > Instant::now() => Some(*hardware_addr),
+            _ => None,
+        }
+    }
+
+    /// Removes the entry of the given IP address.
+    pub fn remove(&mut self, ip_addr: Ipv4Addr) -> Option<MacAddr> {
+        self.entries
+            .remove(&ip_addr)
+            .map(|(hardware_addr, _)| hardware_addr)
+    }
+
+    /// Removes all expired entries and returns the number of entries removed.
+    pub fn purge(&mut self) -> usize {
+        let now = Instant::now();
+        let len = self.entries.len();
+        self.entries.retain(|_, (_, expiry)| *expiry > now);
+
+        len - self.entries.len()
+    }
+
+    /// Get the number of entries in the cache, including expired entries which are not purged.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Returns if the cache contains no entries.
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Creates an ARP request from 192.168.1.1 for 192.168.1.2.
+    fn new_request() -> Arp {
+        Arp::from(arp::Arp {
+            hardware_type: ArpHardwareTypes::Ethernet,
+            protocol_type: EtherTypes::Ipv4,
+            hw_addr_len: 6,
+            proto_addr_len: 4,
+            operation: ArpOperations::Request,
+            sender_hw_addr: MacAddr::new(0x02, 0, 0, 0, 0, 0x01),
+            sender_proto_addr: Ipv4Addr::new(192, 168, 1, 1),
+            target_hw_addr: MacAddr::zero(),
+            target_proto_addr: Ipv4Addr::new(192, 168, 1, 2),
+            payload: vec![],
+        })
+    }
+
+    #[test]
+    fn deserialize_serialized() {
+        let arp = Arp::new_reply(
+            MacAddr::new(0x02, 0, 0, 0, 0, 0x01),
+            Ipv4Addr::new(192, 168, 1, 1),
+            MacAddr::new(0x02, 0, 0, 0, 0, 0x02),
+            Ipv4Addr::new(192, 168, 1, 2),
+        );
+        let mut buffer = vec![0u8; arp.get_size()];
+        let n = arp.serialize(&mut buffer, arp.get_size()).unwrap();
+
+        let (deserialized, size) = Arp::deserialize(&buffer).unwrap();
+        assert_eq!(size, n);
+        assert_eq!(deserialized, arp);
+    }
+
+    #[test]
+    fn deserialize_truncated() {
+        assert!(matches!(
+            Arp::deserialize(&[0u8; 27]),
+            Err(ParseError::Truncated(LayerTypes::Arp))
+        ));
+    }
+
+    #[test]
+    fn cache_expire_after_ttl() {
+        let ip_addr = Ipv4Addr::new(192, 168, 1, 1);
+        let hardware_addr = MacAddr::new(0x02, 0, 0, 0, 0, 0x01);
+        let mut cache = ArpCache::new();
+        cache.insert(ip_addr, hardware_addr, Duration::from_millis(20));
+        assert_eq!(cache.lookup(ip_addr), Some(hardware_addr));
+
+        std::thread::sleep(Duration::from_millis(40));
+        assert_eq!(cache.lookup(ip_addr), None);
+        assert_eq!(cache.purge(), 1);
+        assert!(cache.is_empty());
+    }
+
+    #[test]
+    fn cache_update_gratuitous() {
+        let ip_addr = Ipv4Addr::new(192, 168, 1, 1);
+        let mut cache = ArpCache::new();
+        cache.insert(
+            ip_addr,
+            MacAddr::new(0x02, 0, 0, 0, 0, 0x01),
+            DEFAULT_ARP_CACHE_TTL,
+        );
+
+        let hardware_addr = MacAddr::new(0x02, 0, 0, 0, 0, 0x03);
+        let arp = Arp::new_gratuitous(hardware_addr, ip_addr);
+        assert!(cache.update(&arp, DEFAULT_ARP_CACHE_TTL));
+        assert_eq!(cache.lookup(ip_addr), Some(hardware_addr));
+    }
+
+    #[test]
+    fn cache_update_request() {
+        let arp = new_request();
+        let mut cache = ArpCache::new();
+        assert!(!cache.update(&arp, DEFAULT_ARP_CACHE_TTL));
+        assert!(cache.is_empty());
+    }
+
+    #[test]
+    fn build_reply() {
+        let hardware_addr = MacAddr::new(0x02, 0, 0, 0, 0, 0x02);
+        let reply = build_arp_reply(&new_request(), hardware_addr);
+        assert!(reply.is_reply());
+        assert_eq!(reply.layer.hardware_type, ArpHardwareTypes::Ethernet);
+        assert_eq!(reply.layer.protocol_type, EtherTypes::Ipv4);
+        assert_eq!(reply.get_src_hardware_addr(), hardware_addr);
+        assert_eq!(reply.get_src(), Ipv4Addr::new(192, 168, 1, 2));
+        assert_eq!(
+            reply.layer.target_hw_addr,
+            MacAddr::new(0x02, 0, 0, 0, 0, 0x01)
+        );
+        assert_eq!(reply.get_dst(), Ipv4Addr::new(192, 168, 1, 1));
+    }
+
+    #[test]
+    fn gratuitous_round_trip() {
+        let hardware_addr = MacAddr::new(0x02, 0, 0, 0, 0, 0x02);
+        let ip_addr = Ipv4Addr::new(192, 168, 1, 254);
+        let arp = Arp::new_gratuitous(hardware_addr, ip_addr);
+        let mut buffer = vec![0u8; arp.get_size()];
+        arp.serialize(&mut buffer, arp.get_size()).unwrap();
+
+        let (arp, _) = Arp::deserialize(&buffer).unwrap();
+        assert!(arp.is_request());
+        assert!(arp.is_gratuitous());
+        assert_eq!(arp.get_src_hardware_addr(), hardware_addr);
+        assert_eq!(arp.get_dst_hardware_addr(), MacAddr::zero());
+        assert_eq!(arp.get_src(), ip_addr);
+        assert_eq!(arp.get_dst(), ip_addr);
+        assert!(!new_request().is_gratuitous());
+    }
 }
@@ -1,14 +1,73 @@
-use super::{Layer, LayerType, LayerTypes};
-use pnet::packet::ethernet::{self, EtherTypes, EthernetPacket, MutableEthernetPacket};
+use super::{fmt_hex_dump, Layer, LayerType, LayerTypes, ParseError, SerializeError, SizeBounds};
+use pnet::packet::ethernet::{self, EtherType, EtherTypes, EthernetPacket, MutableEthernetPacket};
+use pnet::packet::{MutablePacket, Packet};
 use pnet::util::MacAddr;
 use std::clone::Clone;
 use std::fmt::{self, Display, Formatter};
 use std::io;
 
+/// Represents the size of an 802.1Q tag.
+const VLAN_TAG_SIZE: usize = 4;
+/// Represents the max number of stacked VLAN tags parsed, which guards against frames tagged
+/// repeatedly.
+pub const MAX_VLAN_TAGS: usize = 4;
+/// Represents the TPID of an 802.1ad service tag.
+pub const SERVICE_TAG_TPID: EtherType = EtherType(0x88a8);
+
+/// Returns if the given EtherType is the TPID of a VLAN tag.
+fn is_tpid(ethertype: EtherType) -> bool {
+    ethertype == EtherTypes::Vlan || ethertype == SERVICE_TAG_TPID
+}
+
+/// Represents an 802.1Q tag, or an 802.1ad service tag.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct VlanTag {
+    /// Tag protocol identifier, 0x8100 for 802.1Q tags and 0x88A8 for 802.1ad service tags.
+    pub tpid: EtherType,
+    /// Priority code point, 3 bits.
+    pub pcp: u8,
+    /// Drop eligible indicator.
+    pub dei: bool,
+    /// VLAN identifier, 12 bits.
+    pub vid: u16,
+}
+
+impl VlanTag {
+    /// Creates an 802.1Q `VlanTag`.
+    pub fn new(pcp: u8, dei: bool, vid: u16) -> VlanTag {
+        VlanTag {
+            tpid: EtherTypes::Vlan,
+            pcp: pcp & 0x07,
+            dei,
+            vid: vid & 0x0fff,
+        }
+    }
+
+    /// Creates an 802.1ad service `VlanTag`.
+    pub fn new_service(pcp: u8, dei: bool, vid: u16) -> VlanTag {
+        VlanTag {
+            tpid: SERVICE_TAG_TPID,
+            ..VlanTag::new(pcp, dei, vid)
+        }
+    }
+
+    /// Creates a `VlanTag` from the given tag control information.
+    pub fn from_tci(tci: u16) -> VlanTag {
+        VlanTag::new((tci >> 13) as u8, tci & 0x1000 != 0, tci)
+    }
+
+    /// Get the tag control information of the tag.
+    pub fn get_tci(&self) -> u16 {
+        ((self.pcp as u16) << 13) | ((self.dei as u16) << 12) | self.vid
+    }
+}
+
 /// Represents an Ethernet layer.
 #[derive(Clone, Debug)]
 pub struct Ethernet {
     pub layer: ethernet::Ethernet,
+    /// Represents the VLAN tags from the outermost.
+    vlans: Vec<VlanTag>,
 }
 
 impl Ethernet {
@@ -17,6 +76,7 @@ impl Ethernet {
         let ethertype = match t {
             LayerTypes::Arp => EtherTypes::Arp,
             LayerTypes::Ipv4 => EtherTypes::Ipv4,
+            LayerTypes::Ipv6 => EtherTypes::Ipv6,
             _ => return None,
         };
         let ethernet = ethernet::Ethernet {
@@ -30,18 +90,90 @@ impl Ethernet {
 
     /// Creates an `Ethernet` according to the given `Ethernet`.
     pub fn from(ethernet: ethernet::Ethernet) -> Ethernet {
-        Ethernet { layer: ethernet }
+        Ethernet {
+            layer: ethernet,
+            vlans: vec![],
+        }
     }
 
-    /// Creates an `Ethernet` according to the given Ethernet packet.
+    /// Creates an `Ethernet` according to the given Ethernet packet. The stacked 802.1Q and
+    /// 802.1ad tags are parsed if present, up to `MAX_VLAN_TAGS`, and the EtherType of the layer
+    /// will be the inner EtherType.
     pub fn parse(packet: &EthernetPacket) -> Ethernet {
-        let ethernet = ethernet::Ethernet {
+        let d_ethernet = ethernet::Ethernet {
             destination: packet.get_destination(),
             source: packet.get_source(),
             ethertype: packet.get_ethertype(),
             payload: vec![],
         };
-        Ethernet::from(ethernet)
+        let mut ethernet = Ethernet::from(d_ethernet);
+
+        // 802.1Q and 802.1ad
+        let mut payload = packet.payload();
+        while is_tpid(ethernet.layer.ethertype)
+            && payload.len() >= VLAN_TAG_SIZE
+            && ethernet.vlans.len() < MAX_VLAN_TAGS
+        {
+            let tci = (payload[0] as u16) << 8 | payload[1] as u16;
+            ethernet.vlans.push(VlanTag {
+                tpid: ethernet.layer.ethertype,
+                ..VlanTag::from_tci(tci)
+            });
+            ethernet.layer.ethertype = EtherType((payload[2] as u16) << 8 | payload[3] as u16);
+            payload = &payload[VLAN_TAG_SIZE..];
+        }
+
+        ethernet
+    }
+
+    /// Deserializes an `Ethernet` from the given byte-array and returns it with the number of
+    /// bytes consumed.
+    pub fn deserialize(buffer: &[u8]) -> Result<(Ethernet, usize), ParseError> {
+        if buffer.len() < Ethernet::min_size() {
+            return Err(ParseError::Truncated(LayerTypes::Ethernet));
+        }
+        let packet =
+            EthernetPacket::new(buffer).ok_or(ParseError::Truncated(LayerTypes::Ethernet))?;
+        let ethernet = Ethernet::parse(&packet);
+        let size = ethernet.get_size();
+        // The tags are not walked through
+        if is_tpid(ethernet.get_ethertype()) {
+            return match buffer.len() < size + VLAN_TAG_SIZE {
+                true => Err(ParseError::Truncated(LayerTypes::Ethernet)),
+                false => Err(ParseError::InvalidValue(
+                    LayerTypes::Ethernet,
+                    "VLAN tag depth",
+                )),
+            };
+        }
+
+        Ok((ethernet, size))
+    }
+
+    /// Sets the 802.1Q tag of the layer, replacing all the VLAN tags.
+    pub fn set_vlan(&mut self, vlan: Option<VlanTag>) {
+        self.vlans = vlan.into_iter().collect();
+    }
+
+    /// Get the innermost VLAN tag of the layer.
+    pub fn get_vlan(&self) -> Option<VlanTag> {
+        self.vlans.last().copied()
+    }
+
+    /// Sets the VLAN tags of the layer from the outermost, e.g., an 802.1ad service tag followed
+    /// by an 802.1Q customer tag.
+    pub fn set_vlans(&mut self, vlans: Vec<VlanTag>) {
+        self.vlans = vlans;
+    }
+
+    /// Get the VLAN tags of the layer from the outermost.
+    pub fn get_vlans(&self) -> &[VlanTag] {
+        &self.vlans
+    }
+
+    /// Get the EtherType of the layer. The inner EtherType is returned if the layer is tagged.
+    pub fn get_ethertype(&self) -> EtherType {
+        self.layer.ethertype
     }
 
     /// Get the source of the layer.
@@ -55,37 +187,260 @@ impl Ethernet {
     }
 }
 
+impl PartialEq for Ethernet {
+    fn eq(&self, other: &Ethernet) -> bool {
+        self.layer.destination == other.layer.destination
+            && self.layer.source == other.layer.source
+            && self.layer.ethertype == other.layer.ethertype
+            && self.vlans == other.vlans
+    }
+}
+
 impl Display for Ethernet {
     fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        if f.alternate() {
+            return fmt_hex_dump(self, f);
+        }
+
+        let mut vlan = String::new();
+        if !self.vlans.is_empty() {
+            let vids: Vec<String> = self.vlans.iter().map(|tag| tag.vid.to_string()).collect();
+            vlan = format!(", VLAN = {}", vids.join("."));
+        }
+
         write!(
             f,
-            "{}: {} -> {}",
+            "{}: {} -> {}{}",
             LayerTypes::Ethernet,
             self.layer.source,
-            self.layer.destination
+            self.layer.destination,
+            vlan
         )
     }
 }
 
+impl SizeBounds for Ethernet {
+    fn min_size() -> usize {
+        EthernetPacket::minimum_packet_size()
+    }
+
+    fn max_size() -> usize {
+        EthernetPacket::minimum_packet_size() + MAX_VLAN_TAGS * VLAN_TAG_SIZE
+    }
+}
+
 impl Layer for Ethernet {
     fn get_type(&self) -> LayerType {
         LayerTypes::Ethernet
     }
 
     fn get_size(&self) -> usize {
-        EthernetPacket::packet_size(&self.layer)
+        EthernetPacket::packet_size(&self.layer) + self.vlans.len() * VLAN_TAG_SIZE
     }
 
     fn serialize(&self, buffer: &mut [u8], _: usize) -> io::Result<usize> {
+        // A frame sent to its own source is a misconfiguration which causes loops
+        if self.layer.source == self.layer.destination {
+            return Err(SerializeError::SelfAddressed(LayerTypes::Ethernet).into());
+        }
+        if buffer.len() < self.get_size() {
+            return Err(io::Error::new(io::ErrorKind::WriteZero, "buffer too small"));
+        }
         let mut packet = MutableEthernetPacket::new(buffer)
-            .ok_or(io::Error::new(io::ErrorKind::WriteZero, "buffer too small"))?;
+            .ok_or_else(|| io::Error::new(io::ErrorKind::WriteZero, "buffer too small"))?;
 
         packet.populate(&self.layer);
 
+        // 802.1Q and 802.1ad, each tag is followed by the TPID of the next tag
+        if let Some(tag) = self.vlans.first() {
+            packet.set_ethertype(tag.tpid);
+        }
+        let payload = packet.payload_mut();
+        for (i, tag) in self.vlans.iter().enumerate() {
+            let tci = tag.get_tci();
+            let ethertype = match self.vlans.get(i + 1) {
+                Some(next) => next.tpid.0,
+                None => self.layer.ethertype.0,
+            };
+            payload[i * VLAN_TAG_SIZE..(i + 1) * VLAN_TAG_SIZE].copy_from_slice(&[
+                (tci >> 8) as u8,
+                tci as u8,
+                (ethertype >> 8) as u8,
+                ethertype as u8,
+            ]);
+        }
+
         Ok(self.get_size())
     }
 
     fn serialize_with_payload(&self, buffer: &mut [u8], _: &[u8], n: usize) -> io::Result<usize> {
         self.serialize(buffer, n)
     }
+
+    fn clone_boxed(&self) -> Box<dyn Layer> {
+        Box::new(self.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn deserialize_serialized() {
+        let ethernet = Ethernet::new(
+            LayerTypes::Ipv4,
+            MacAddr::new(0x02, 0, 0, 0, 0, 0x01),
+            MacAddr::new(0x02, 0, 0, 0, 0, 0x02),
+        )
+        .unwrap();
+        let mut buffer = vec![0u8; ethernet.get_size()];
+        let n = ethernet
+            .serialize(&mut buffer, ethernet.get_size())
+            .unwrap();
+
+        let (deserialized, size) = Ethernet::deserialize(&buffer).unwrap();
+        assert_eq!(size, n);
+        assert_eq!(deserialized, ethernet);
+    }
+
+    #[test]
+    fn serialize_self_addressed() {
+        let hardware_addr = MacAddr::new(0x02, 0, 0, 0, 0, 0x01);
+        let ethernet = Ethernet::new(LayerTypes::Ipv4, hardware_addr, hardware_addr).unwrap();
+        let mut buffer = vec![0u8; ethernet.get_size()];
+        let e = ethernet
+            .serialize(&mut buffer, ethernet.get_size())
+            .unwrap_err();
+
+        assert_eq!(e.kind(), io::ErrorKind::InvalidInput);
+        assert_eq!(
+            e.get_ref().unwrap().downcast_ref::<SerializeError>(),
+            Some(&SerializeError::SelfAddressed(LayerTypes::Ethernet))
+        );
+    }
+
+    #[test]
+    fn deserialize_truncated() {
+        assert!(matches!(
+            Ethernet::deserialize(&[0u8; 13]),
+            Err(ParseError::Truncated(LayerTypes::Ethernet))
+        ));
+    }
+
+    #[test]
+    fn deserialize_serialized_vlan() {
+        let mut ethernet = Ethernet::new(
+            LayerTypes::Ipv4,
+            MacAddr::new(0x02, 0, 0, 0, 0, 0x01),
+            MacAddr::new(0x02, 0, 0, 0, 0, 0x02),
+        )
+        .unwrap();
+        ethernet.set_vlan(Some(VlanTag::new(5, true, 100)));
+        assert_eq!(ethernet.get_size(), 18);
+        let mut buffer = vec![0u8; ethernet.get_size()];
+        ethernet
+            .serialize(&mut buffer, ethernet.get_size())
+            .unwrap();
+        // TPID, TCI and the inner EtherType
+        assert_eq!(buffer[12..], [0x81, 0x00, 0xb0, 0x64, 0x08, 0x00]);
+
+        let (deserialized, size) = Ethernet::deserialize(&buffer).unwrap();
+        assert_eq!(size, 18);
+        assert_eq!(deserialized.get_ethertype(), EtherTypes::Ipv4);
+        assert_eq!(deserialized, ethernet);
+    }
+
+    #[test]
+    fn deserialize_serialized_vlans() {
+        let mut ethernet = Ethernet::new(
+            LayerTypes::Ipv4,
+            MacAddr::new(0x02, 0, 0, 0, 0, 0x01),
+            MacAddr::new(0x02, 0, 0, 0, 0, 0x02),
+        )
+        .unwrap();
+        ethernet.set_vlans(vec![
+            VlanTag::new_service(0, false, 10),
+            VlanTag::new(0, false, 20),
+        ]);
+        let mut buffer = vec![0u8; ethernet.get_size()];
+        ethernet
+            .serialize(&mut buffer, ethernet.get_size())
+            .unwrap();
+
+        let (deserialized, size) = Ethernet::deserialize(&buffer).unwrap();
+        assert_eq!(size, 22);
+        assert_eq!(deserialized.get_vlan(), Some(VlanTag::new(0, false, 20)));
+        assert_eq!(deserialized, ethernet);
+    }
+
+    #[test]
+    fn vlan_tci() {
+        let vlan = VlanTag::from_tci(0xb064);
+
+        assert_eq!(vlan.pcp, 5);
+        assert!(vlan.dei);
+        assert_eq!(vlan.vid, 100);
+        assert_eq!(vlan.get_tci(), 0xb064);
+    }
+
+    /// Builds the header of an Ethernet frame carrying IPv4 with the given stacked VLAN tags of
+    /// their TPIDs and VIDs.
+    fn build_tagged_header(tags: &[(u16, u16)]) -> Vec<u8> {
+        let mut buffer = vec![0x02, 0, 0, 0, 0, 0x02, 0x02, 0, 0, 0, 0, 0x01];
+        for (tpid, vid) in tags {
+            buffer.extend_from_slice(&tpid.to_be_bytes());
+            buffer.extend_from_slice(&vid.to_be_bytes());
+        }
+        buffer.extend_from_slice(&[0x08, 0x00]);
+
+        buffer
+    }
+
+    #[test]
+    fn deserialize_qinq() {
+        let buffer = build_tagged_header(&[(0x88a8, 100), (0x8100, 200)]);
+        let (ethernet, size) = Ethernet::deserialize(&buffer).unwrap();
+
+        // The inner IPv4 header follows both tags
+        assert_eq!(size, 22);
+        assert_eq!(ethernet.get_ethertype(), EtherTypes::Ipv4);
+        let vlans = ethernet.get_vlans();
+        assert_eq!(vlans.len(), 2);
+        assert_eq!(vlans[0].tpid, SERVICE_TAG_TPID);
+        assert_eq!(vlans[0].vid, 100);
+        assert_eq!(vlans[1].tpid, EtherTypes::Vlan);
+        assert_eq!(vlans[1].vid, 200);
+        assert_eq!(ethernet.get_vlan().unwrap().vid, 200);
+        assert!(ethernet.to_string().contains("VLAN = 100.200"));
+
+        // Serialized back as is
+        let mut serialized = vec![0u8; ethernet.get_size()];
+        ethernet
+            .serialize(&mut serialized, ethernet.get_size())
+            .unwrap();
+        assert_eq!(serialized, buffer);
+    }
+
+    #[test]
+    fn deserialize_vlans_too_deep() {
+        let tags = vec![(0x8100, 1); MAX_VLAN_TAGS];
+        let (ethernet, size) = Ethernet::deserialize(&build_tagged_header(&tags)).unwrap();
+        assert_eq!(ethernet.get_vlans().len(), MAX_VLAN_TAGS);
+        assert_eq!(size, 14 + MAX_VLAN_TAGS * 4);
+        assert_eq!(Ethernet::max_size(), size);
+
+        let tags = vec![(0x8100, 1); MAX_VLAN_TAGS + 1];
+        assert_eq!(
+            Ethernet::deserialize(&build_tagged_header(&tags)).unwrap_err(),
+            ParseError::InvalidValue(LayerTypes::Ethernet, "VLAN tag depth")
+        );
+
+        // The inner tag is truncated
+        let buffer = build_tagged_header(&[(0x88a8, 100), (0x8100, 200)]);
+        assert_eq!(
+            Ethernet::deserialize(&buffer[..20]).unwrap_err(),
+            ParseError::Truncated(LayerTypes::Ethernet)
+        );
+    }
 }
@@ -0,0 +1,351 @@
+use super::{fmt_hex_dump, Layer, LayerType, LayerTypes, ParseError, SizeBounds};
+use pnet::packet::ethernet::EtherType;
+use pnet::util;
+use std::clone::Clone;
+use std::fmt::{self, Display, Formatter};
+use std::io;
+
+/// Represents the size of the fixed GRE header.
+const FIXED_HEADER_SIZE: usize = 4;
+/// Represents the size of each optional field of the GRE header.
+const OPTIONAL_FIELD_SIZE: usize = 4;
+
+/// Represents the checksum present bit of the GRE header.
+const CHECKSUM_PRESENT: u16 = 0x8000;
+/// Represents the key present bit of the GRE header.
+const KEY_PRESENT: u16 = 0x2000;
+/// Represents the sequence number present bit of the GRE header.
+const SEQUENCE_PRESENT: u16 = 0x1000;
+/// Represents the mask of the version of the GRE header.
+const VERSION_MASK: u16 = 0x0007;
+
+/// Represents a GRE layer (RFC 2784 and RFC 2890).
+#[derive(Clone, Debug)]
+pub struct Gre {
+    flags: u16,
+    protocol_type: EtherType,
+    checksum: Option<u16>,
+    key: Option<u32>,
+    sequence: Option<u32>,
+    payload: Vec<u8>,
+}
+
+impl Gre {
+    /// Creates a `Gre` encapsulating the given protocol.
+    pub fn new(protocol_type: EtherType) -> Gre {
+        Gre {
+            flags: 0,
+            protocol_type,
+            checksum: None,
+            key: None,
+            sequence: None,
+            payload: vec![],
+        }
+    }
+
+    /// Deserializes a `Gre` from the given byte-array and returns it with the number of bytes
+    /// consumed. The optional fields are sized by the C, K and S bits of the header.
+    pub fn deserialize(buffer: &[u8]) -> Result<(Gre, usize), ParseError> {
+        if buffer.len() < Gre::min_size() {
+            return Err(ParseError::Truncated(LayerTypes::Gre));
+        }
+        let flags = (buffer[0] as u16) << 8 | buffer[1] as u16;
+        if flags & VERSION_MASK != 0 {
+            return Err(ParseError::InvalidValue(LayerTypes::Gre, "version"));
+        }
+        let protocol_type = EtherType((buffer[2] as u16) << 8 | buffer[3] as u16);
+
+        let mut gre = Gre::new(protocol_type);
+        gre.flags = flags;
+        if buffer.len() < gre.get_size() {
+            return Err(ParseError::Truncated(LayerTypes::Gre));
+        }
+
+        // The checksum field is followed by 2 reserved bytes
+        let mut offset = FIXED_HEADER_SIZE;
+        if flags & CHECKSUM_PRESENT != 0 {
+            gre.checksum = Some((buffer[offset] as u16) << 8 | buffer[offset + 1] as u16);
+            offset += OPTIONAL_FIELD_SIZE;
+        }
+        if flags & KEY_PRESENT != 0 {
+            gre.key = Some(read_u32(&buffer[offset..]));
+            offset += OPTIONAL_FIELD_SIZE;
+        }
+        if flags & SEQUENCE_PRESENT != 0 {
+            gre.sequence = Some(read_u32(&buffer[offset..]));
+            offset += OPTIONAL_FIELD_SIZE;
+        }
+        gre.payload = buffer[offset..].to_vec();
+
+        if let Some(checksum) = gre.checksum {
+            if util::checksum(buffer, 2) != checksum {
+                return Err(ParseError::ChecksumMismatch(LayerTypes::Gre));
+            }
+        }
+
+        Ok((gre, offset))
+    }
+
+    /// Get the protocol type of the layer, which is the EtherType of the encapsulated layer.
+    pub fn get_protocol_type(&self) -> EtherType {
+        self.protocol_type
+    }
+
+    /// Get the checksum of the layer. Returns `None` if the checksum is not present.
+    pub fn get_checksum(&self) -> Option<u16> {
+        self.checksum
+    }
+
+    /// Sets if the checksum is present in the layer. The checksum covers the encapsulated
+    /// layers, so it is only correct when they are serialized as the payload of the layer.
+    pub fn set_checksum_present(&mut self, present: bool) {
+        self.set_flag(CHECKSUM_PRESENT, present);
+        self.checksum = match present {
+            true => Some(0),
+            false => None,
+        };
+    }
+
+    /// Get the key of the layer. Returns `None` if the key is not present.
+    pub fn get_key(&self) -> Option<u32> {
+        self.key
+    }
+
+    /// Sets the key of the layer, or `None` for removing the key.
+    pub fn set_key(&mut self, key: Option<u32>) {
+        self.set_flag(KEY_PRESENT, key.is_some());
+        self.key = key;
+    }
+
+    /// Get the sequence of the layer. Returns `None` if the sequence is not present.
+    pub fn get_sequence(&self) -> Option<u32> {
+        self.sequence
+    }
+
+    /// Sets the sequence of the layer, or `None` for removing the sequence.
+    pub fn set_sequence(&mut self, sequence: Option<u32>) {
+        self.set_flag(SEQUENCE_PRESENT, sequence.is_some());
+        self.sequence = sequence;
+    }
+
+    fn set_flag(&mut self, flag: u16, value: bool) {
+        match value {
+            true => self.flags |= flag,
+            false => self.flags &= !flag,
+        }
+    }
+
+    /// Get the payload of the layer when the layer is deserialized, which is the encapsulated
+    /// layer.
+    pub fn get_payload(&self) -> &[u8] {
+        &self.payload
+    }
+
+    fn serialize_header(&self, buffer: &mut [u8]) -> io::Result<usize> {
+        let size = self.get_size();
+        if buffer.len() < size {
+            return Err(io::Error::new(io::ErrorKind::WriteZero, "buffer too small"));
+        }
+
+        buffer[0] = (self.flags >> 8) as u8;
+        buffer[1] = self.flags as u8;
+        buffer[2] = (self.protocol_type.0 >> 8) as u8;
+        buffer[3] = self.protocol_type.0 as u8;
+        let mut offset = FIXED_HEADER_SIZE;
+        if self.checksum.is_some() {
+            // The checksum is computed after the payload is copied
+            buffer[offset..offset + OPTIONAL_FIELD_SIZE].copy_from_slice(&[0u8; 4]);
+            offset += OPTIONAL_FIELD_SIZE;
+        }
+        if let Some(key) = self.key {
+            buffer[offset..offset + OPTIONAL_FIELD_SIZE].copy_from_slice(&key.to_be_bytes());
+            offset += OPTIONAL_FIELD_SIZE;
+        }
+        if let Some(sequence) = self.sequence {
+            buffer[offset..offset + OPTIONAL_FIELD_SIZE].copy_from_slice(&sequence.to_be_bytes());
+        }
+
+        Ok(size)
+    }
+}
+
+/// Reads a big-endian `u32` from the head of the given byte-array.
+fn read_u32(buffer: &[u8]) -> u32 {
+    (buffer[0] as u32) << 24 | (buffer[1] as u32) << 16 | (buffer[2] as u32) << 8 | buffer[3] as u32
+}
+
+impl Display for Gre {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        if f.alternate() {
+            return fmt_hex_dump(self, f);
+        }
+
+        let key = match self.key {
+            Some(key) => format!(", Key = {}", key),
+            None => String::new(),
+        };
+        let sequence = match self.sequence {
+            Some(sequence) => format!(", Sequence = {}", sequence),
+            None => String::new(),
+        };
+
+        write!(
+            f,
+            "{}: Protocol = {}{}{}",
+            LayerTypes::Gre,
+            self.protocol_type,
+            key,
+            sequence
+        )
+    }
+}
+
+impl SizeBounds for Gre {
+    fn min_size() -> usize {
+        FIXED_HEADER_SIZE
+    }
+
+    fn max_size() -> usize {
+        FIXED_HEADER_SIZE + 3 * OPTIONAL_FIELD_SIZE
+    }
+}
+
+impl Layer for Gre {
+    fn get_type(&self) -> LayerType {
+        LayerTypes::Gre
+    }
+
+    fn get_size(&self) -> usize {
+        let fields = (self.flags & CHECKSUM_PRESENT != 0) as usize
+            + (self.flags & KEY_PRESENT != 0) as usize
+            + (self.flags & SEQUENCE_PRESENT != 0) as usize;
+
+        FIXED_HEADER_SIZE + fields * OPTIONAL_FIELD_SIZE
+    }
+
+    fn serialize(&self, buffer: &mut [u8], n: usize) -> io::Result<usize> {
+        let size = self.serialize_header(buffer)?;
+
+        // Compute checksum over the header and the payload following it
+        if self.checksum.is_some() {
+            let end = n.max(size).min(buffer.len());
+            let checksum = util::checksum(&buffer[..end], 2);
+            buffer[FIXED_HEADER_SIZE] = (checksum >> 8) as u8;
+            buffer[FIXED_HEADER_SIZE + 1] = checksum as u8;
+        }
+
+        Ok(size)
+    }
+
+    fn serialize_with_payload(
+        &self,
+        buffer: &mut [u8],
+        payload: &[u8],
+        _: usize,
+    ) -> io::Result<usize> {
+        let size = self.get_size();
+        if buffer.len() < size + payload.len() {
+            return Err(io::Error::new(io::ErrorKind::WriteZero, "buffer too small"));
+        }
+
+        // Copies payload
+        buffer[size..size + payload.len()].copy_from_slice(payload);
+
+        self.serialize(buffer, size + payload.len())?;
+
+        Ok(size + payload.len())
+    }
+
+    fn clone_boxed(&self) -> Box<dyn Layer> {
+        Box::new(self.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pnet::packet::ethernet::EtherTypes;
+
+    #[test]
+    fn deserialize_key() {
+        #[rustfmt::skip]
+        let buffer = [
+            0x20, 0x00, 0x08, 0x00,
+            0x00, 0x00, 0x00, 0x2a,
+            0x45, 0x00,
+        ];
+        let (gre, n) = Gre::deserialize(&buffer).unwrap();
+
+        assert_eq!(n, 8);
+        assert_eq!(gre.get_protocol_type(), EtherTypes::Ipv4);
+        assert_eq!(gre.get_key(), Some(42));
+        assert_eq!(gre.get_checksum(), None);
+        assert_eq!(gre.get_sequence(), None);
+        assert_eq!(gre.get_payload(), &[0x45, 0x00]);
+    }
+
+    #[test]
+    fn serialize_deserialize_all_fields() {
+        let mut gre = Gre::new(EtherTypes::Ipv6);
+        gre.set_checksum_present(true);
+        gre.set_key(Some(0x01020304));
+        gre.set_sequence(Some(7));
+        assert_eq!(gre.get_size(), Gre::max_size());
+
+        let payload = b"inner";
+        let mut buffer = vec![0u8; gre.get_size() + payload.len()];
+        gre.serialize_with_payload(&mut buffer, payload, 0).unwrap();
+        let (deserialized, n) = Gre::deserialize(&buffer).unwrap();
+
+        assert_eq!(n, 16);
+        assert_eq!(deserialized.get_protocol_type(), EtherTypes::Ipv6);
+        assert!(deserialized.get_checksum().is_some());
+        assert_eq!(deserialized.get_key(), Some(0x01020304));
+        assert_eq!(deserialized.get_sequence(), Some(7));
+        assert_eq!(deserialized.get_payload(), payload);
+
+        // The checksum covers the payload
+        buffer[n] ^= 0xff;
+        assert!(matches!(
+            Gre::deserialize(&buffer),
+            Err(ParseError::ChecksumMismatch(LayerTypes::Gre))
+        ));
+    }
+
+    #[test]
+    fn deserialize_truncated_fields() {
+        // The C and S bits claim 8 Bytes of optional fields
+        let buffer = [0x90, 0x00, 0x08, 0x00, 0x00, 0x00, 0x00, 0x00];
+        assert!(matches!(
+            Gre::deserialize(&buffer),
+            Err(ParseError::Truncated(LayerTypes::Gre))
+        ));
+        assert!(matches!(
+            Gre::deserialize(&buffer[..3]),
+            Err(ParseError::Truncated(LayerTypes::Gre))
+        ));
+    }
+
+    #[test]
+    fn deserialize_unknown_version() {
+        // Version 1 is the enhanced GRE of PPTP
+        let buffer = [0x00, 0x01, 0x88, 0x0b];
+        assert!(matches!(
+            Gre::deserialize(&buffer),
+            Err(ParseError::InvalidValue(LayerTypes::Gre, "version"))
+        ));
+    }
+
+    #[test]
+    fn remove_optional_fields() {
+        let mut gre = Gre::new(EtherTypes::Ipv4);
+        gre.set_key(Some(42));
+        gre.set_sequence(Some(7));
+        gre.set_key(None);
+
+        assert_eq!(gre.get_size(), 8);
+        let mut buffer = [0u8; 8];
+        gre.serialize(&mut buffer, 8).unwrap();
+        assert_eq!(buffer, [0x10, 0x00, 0x08, 0x00, 0x00, 0x00, 0x00, 0x07]);
+    }
+}
@@ -0,0 +1,588 @@
+use super::ipv4::Ipv4;
+use super::{fmt_hex_dump, Layer, LayerType, LayerTypes, Layers, ParseError, SizeBounds};
+use pnet::packet::icmp::{self, IcmpCode, IcmpPacket, IcmpType, IcmpTypes, MutableIcmpPacket};
+use pnet::packet::Packet;
+use pnet::util;
+use std::clone::Clone;
+use std::cmp::{max, min};
+use std::collections::HashMap;
+use std::fmt::{self, Display, Formatter};
+use std::io;
+use std::net::Ipv4Addr;
+use std::time::{Duration, Instant};
+
+/// Represents the size of the rest of the ICMP header.
+const REST_OF_HEADER_SIZE: usize = 4;
+/// Represents the code of the ICMP destination unreachable message for fragmentation needed.
+const FRAGMENTATION_NEEDED: IcmpCode = IcmpCode(4);
+/// Represents the code of the ICMP destination unreachable message for communication
+/// administratively prohibited (RFC 1812).
+const COMMUNICATION_ADMINISTRATIVELY_PROHIBITED: IcmpCode = IcmpCode(13);
+/// Represents the position of the destination in the IPv4 header.
+const IPV4_DESTINATION_POSITION: usize = 16;
+
+/// Represents the number of bytes of the original datagram data quoted in an ICMP error message,
+/// following the IP header.
+pub const ORIGINAL_DATAGRAM_DATA_SIZE: usize = 8;
+
+/// Represents the minimum MTU of an IPv4 link.
+pub const MINIMUM_IPV4_MTU: u16 = 68;
+/// Represents the default time to live of entries in a `PmtuCache`.
+pub const DEFAULT_PMTU_CACHE_TTL: Duration = Duration::from_secs(600);
+
+/// Represents an ICMP layer.
+#[derive(Clone, Debug)]
+pub struct Icmp {
+    layer: icmp::Icmp,
+    payload: Vec<u8>,
+}
+
+impl Icmp {
+    /// Creates an `Icmp` represents an ICMP echo request.
+    pub fn new_echo_request(identifier: u16, sequence: u16) -> Icmp {
+        Icmp::new_echo(IcmpTypes::EchoRequest, identifier, sequence)
+    }
+
+    /// Creates an `Icmp` represents an ICMP echo reply.
+    pub fn new_echo_reply(identifier: u16, sequence: u16) -> Icmp {
+        Icmp::new_echo(IcmpTypes::EchoReply, identifier, sequence)
+    }
+
+    fn new_echo(t: IcmpType, identifier: u16, sequence: u16) -> Icmp {
+        let d_icmp = icmp::Icmp {
+            icmp_type: t,
+            icmp_code: IcmpCode(0),
+            checksum: 0,
+            payload: vec![
+                (identifier >> 8) as u8,
+                identifier as u8,
+                (sequence >> 8) as u8,
+                sequence as u8,
+            ],
+        };
+        Icmp::from(d_icmp)
+    }
+
+    /// Creates an `Icmp` represents an ICMP time exceeded message for TTL exceeded in transit.
+    pub fn new_time_exceeded() -> Icmp {
+        let d_icmp = icmp::Icmp {
+            icmp_type: IcmpTypes::TimeExceeded,
+            icmp_code: IcmpCode(0),
+            checksum: 0,
+            payload: vec![0u8; REST_OF_HEADER_SIZE],
+        };
+        Icmp::from(d_icmp)
+    }
+
+    /// Creates an `Icmp` represents an ICMP destination unreachable message for fragmentation
+    /// needed, reporting the given next-hop MTU (RFC 1191).
+    pub fn new_fragmentation_needed(next_hop_mtu: u16) -> Icmp {
+        let d_icmp = icmp::Icmp {
+            icmp_type: IcmpTypes::DestinationUnreachable,
+            icmp_code: FRAGMENTATION_NEEDED,
+            checksum: 0,
+            payload: vec![0, 0, (next_hop_mtu >> 8) as u8, next_hop_mtu as u8],
+        };
+        Icmp::from(d_icmp)
+    }
+
+    /// Creates an `Icmp` represents an ICMP destination unreachable message for communication
+    /// administratively prohibited.
+    pub fn new_administratively_prohibited() -> Icmp {
+        let d_icmp = icmp::Icmp {
+            icmp_type: IcmpTypes::DestinationUnreachable,
+            icmp_code: COMMUNICATION_ADMINISTRATIVELY_PROHIBITED,
+            checksum: 0,
+            payload: vec![0u8; REST_OF_HEADER_SIZE],
+        };
+        Icmp::from(d_icmp)
+    }
+
+    /// Creates an `Icmp` according to the given `Icmp`.
+    pub fn from(icmp: icmp::Icmp) -> Icmp {
+        Icmp {
+            layer: icmp,
+            payload: vec![],
+        }
+    }
+
+    /// Creates an `Icmp` according to the given ICMP packet.
+    pub fn parse(packet: &IcmpPacket) -> Icmp {
+        let mut rest_of_header =
+            packet.payload()[..min(packet.payload().len(), REST_OF_HEADER_SIZE)].to_vec();
+        rest_of_header.resize(REST_OF_HEADER_SIZE, 0);
+        let d_icmp = icmp::Icmp {
+            icmp_type: packet.get_icmp_type(),
+            icmp_code: packet.get_icmp_code(),
+            checksum: packet.get_checksum(),
+            payload: rest_of_header,
+        };
+        Icmp::from(d_icmp)
+    }
+
+    /// Deserializes an `Icmp` from the given byte-array and returns it with the number of bytes
+    /// consumed. The byte-array should contain the whole ICMP message for validating the checksum.
+    pub fn deserialize(buffer: &[u8]) -> Result<(Icmp, usize), ParseError> {
+        let size = Icmp::min_size();
+        if buffer.len() < size {
+            return Err(ParseError::Truncated(LayerTypes::Icmp));
+        }
+        let packet = IcmpPacket::new(buffer).unwrap();
+        let mut icmp = Icmp::parse(&packet);
+        if !icmp.validate_checksum(buffer) {
+            return Err(ParseError::ChecksumMismatch(LayerTypes::Icmp));
+        }
+        icmp.payload = buffer[size..].to_vec();
+
+        Ok((icmp, size))
+    }
+
+    /// Returns if the checksum of the layer matches the given ICMP message.
+    pub fn validate_checksum(&self, buffer: &[u8]) -> bool {
+        util::checksum(buffer, 1) == self.layer.checksum
+    }
+
+    /// Get the type of the ICMP message.
+    pub fn get_icmp_type(&self) -> IcmpType {
+        self.layer.icmp_type
+    }
+
+    /// Get the code of the ICMP message.
+    pub fn get_icmp_code(&self) -> IcmpCode {
+        self.layer.icmp_code
+    }
+
+    /// Get the checksum of the layer.
+    pub fn get_checksum(&self) -> u16 {
+        self.layer.checksum
+    }
+
+    /// Get the identifier of the layer. The identifier is only meaningful in ICMP echo messages.
+    pub fn get_identifier(&self) -> u16 {
+        (self.layer.payload[0] as u16) << 8 | self.layer.payload[1] as u16
+    }
+
+    /// Get the sequence of the layer. The sequence is only meaningful in ICMP echo messages.
+    pub fn get_sequence(&self) -> u16 {
+        (self.layer.payload[2] as u16) << 8 | self.layer.payload[3] as u16
+    }
+
+    /// Returns if the `Icmp` is an ICMP echo request.
+    pub fn is_echo_request(&self) -> bool {
+        self.layer.icmp_type == IcmpTypes::EchoRequest
+    }
+
+    /// Returns if the `Icmp` is an ICMP echo reply.
+    pub fn is_echo_reply(&self) -> bool {
+        self.layer.icmp_type == IcmpTypes::EchoReply
+    }
+
+    /// Returns if the `Icmp` is an ICMP error message, which should never be replied with
+    /// another ICMP error message.
+    pub fn is_error(&self) -> bool {
+        matches!(
+            self.layer.icmp_type,
+            IcmpTypes::DestinationUnreachable
+                | IcmpTypes::SourceQuench
+                | IcmpTypes::RedirectMessage
+                | IcmpTypes::TimeExceeded
+                | IcmpTypes::ParameterProblem
+        )
+    }
+
+    /// Returns if the `Icmp` is an ICMP destination unreachable message for fragmentation
+    /// needed.
+    pub fn is_fragmentation_needed(&self) -> bool {
+        self.layer.icmp_type == IcmpTypes::DestinationUnreachable
+            && self.layer.icmp_code == FRAGMENTATION_NEEDED
+    }
+
+    /// Get the next-hop MTU of the layer. Returns `None` if the `Icmp` is not an ICMP
+    /// fragmentation needed message, or the router does not report the next-hop MTU.
+    pub fn get_next_hop_mtu(&self) -> Option<u16> {
+        if !self.is_fragmentation_needed() {
+            return None;
+        }
+        let mtu = (self.layer.payload[2] as u16) << 8 | self.layer.payload[3] as u16;
+        match mtu {
+            0 => None,
+            _ => Some(mtu),
+        }
+    }
+
+    /// Get the payload of the layer when the layer is deserialized. The payload of an ICMP error
+    /// message is the leading part of the original datagram.
+    pub fn get_payload(&self) -> &[u8] {
+        &self.payload
+    }
+
+    /// Get the destination of the original datagram quoted in an ICMP error message. Returns
+    /// `None` if the payload does not contain an IPv4 header.
+    pub fn get_original_dst(&self) -> Option<Ipv4Addr> {
+        if self.payload.len() < IPV4_DESTINATION_POSITION + 4 || self.payload[0] >> 4 != 4 {
+            return None;
+        }
+        let b = &self.payload[IPV4_DESTINATION_POSITION..IPV4_DESTINATION_POSITION + 4];
+
+        Some(Ipv4Addr::new(b[0], b[1], b[2], b[3]))
+    }
+}
+
+impl Display for Icmp {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        if f.alternate() {
+            return fmt_hex_dump(self, f);
+        }
+
+        let t = match self.layer.icmp_type {
+            IcmpTypes::EchoRequest => String::from("Echo Request"),
+            IcmpTypes::EchoReply => String::from("Echo Reply"),
+            IcmpTypes::TimeExceeded => String::from("Time Exceeded"),
+            _ => format!(
+                "Type = {}, Code = {}",
+                self.layer.icmp_type.0, self.layer.icmp_code.0
+            ),
+        };
+        let mut echo = String::new();
+        if self.is_echo_request() || self.is_echo_reply() {
+            echo = format!(
+                ", Identifier = {}, Sequence = {}",
+                self.get_identifier(),
+                self.get_sequence()
+            );
+        }
+
+        write!(f, "{}: {}{}", LayerTypes::Icmp, t, echo)
+    }
+}
+
+impl SizeBounds for Icmp {
+    fn min_size() -> usize {
+        IcmpPacket::minimum_packet_size() + REST_OF_HEADER_SIZE
+    }
+
+    fn max_size() -> usize {
+        IcmpPacket::minimum_packet_size() + REST_OF_HEADER_SIZE
+    }
+}
+
+impl Layer for Icmp {
+    fn get_type(&self) -> LayerType {
+        LayerTypes::Icmp
+    }
+
+    fn get_size(&self) -> usize {
+        IcmpPacket::packet_size(&self.layer)
+    }
+
+    fn serialize(&self, buffer: &mut [u8], _: usize) -> io::Result<usize> {
+        let mut packet = MutableIcmpPacket::new(buffer)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::WriteZero, "buffer too small"))?;
+
+        packet.populate(&self.layer);
+
+        // Compute checksum
+        let checksum = icmp::checksum(&packet.to_immutable());
+        packet.set_checksum(checksum);
+
+        Ok(self.get_size())
+    }
+
+    fn serialize_with_payload(
+        &self,
+        buffer: &mut [u8],
+        payload: &[u8],
+        _: usize,
+    ) -> io::Result<usize> {
+        let header_length = self.get_size();
+        if buffer.len() < header_length + payload.len() {
+            return Err(io::Error::new(io::ErrorKind::WriteZero, "buffer too small"));
+        }
+
+        // Copies payload
+        buffer[header_length..header_length + payload.len()].copy_from_slice(payload);
+
+        let mut packet = MutableIcmpPacket::new(&mut buffer[..header_length + payload.len()])
+            .ok_or_else(|| io::Error::new(io::ErrorKind::WriteZero, "buffer too small"))?;
+
+        packet.populate(&self.layer);
+
+        // Compute checksum
+        let checksum = icmp::checksum(&packet.to_immutable());
+        packet.set_checksum(checksum);
+
+        Ok(header_length + payload.len())
+    }
+
+    fn clone_boxed(&self) -> Box<dyn Layer> {
+        Box::new(self.clone())
+    }
+}
+
+/// Builds an ICMP fragmentation needed message to the given IPv4 packet with the given datagram
+/// data, reporting the given next-hop MTU. The payload of the message quotes the IPv4 header and
+/// the leading bytes of the original datagram data, which should be serialized following the
+/// message.
+pub fn build_frag_needed(original: &Ipv4, data: &[u8], next_hop_mtu: u16) -> Layers {
+    let header_length = original.get_size();
+    let data = &data[..min(data.len(), ORIGINAL_DATAGRAM_DATA_SIZE)];
+
+    // Keep the total length of the original packet
+    let total_length = max(
+        original.get_total_length() as usize,
+        header_length + data.len(),
+    );
+    let mut buffer = vec![0u8; total_length];
+    if original.serialize(&mut buffer, total_length).is_err() {
+        buffer = vec![0u8; header_length];
+    }
+    buffer.truncate(header_length);
+    buffer.extend_from_slice(data);
+
+    let mut icmp = Icmp::new_fragmentation_needed(next_hop_mtu);
+    icmp.payload = buffer;
+
+    Layers::Icmp(icmp)
+}
+
+/// Represents a cache mapping destinations to path MTUs learned from ICMP fragmentation needed
+/// messages.
+#[derive(Debug, Default)]
+pub struct PmtuCache {
+    entries: HashMap<Ipv4Addr, (u16, Instant)>,
+}
+
+impl PmtuCache {
+    /// Creates a new `PmtuCache`.
+    pub fn new() -> PmtuCache {
+        PmtuCache {
+            entries: HashMap::new(),
+        }
+    }
+
+    /// Inserts an entry into the cache which expires after the given time to live. An existing
+    /// entry of the IP address will be overwritten. MTUs less than the minimum MTU of IPv4 are
+    /// raised to it.
+    pub fn insert(&mut self, ip_addr: Ipv4Addr, mtu: u16, ttl: Duration) {
+        self.entries
+            .insert(ip_addr, (max(mtu, MINIMUM_IPV4_MTU), Instant::now() + ttl));
+    }
+
+    /// Updates the cache according to the given `Icmp`. Only ICMP fragmentation needed messages
+    /// reporting the next-hop MTU are accepted. Returns the destination and the path MTU if the
+    /// cache is updated.
+    pub fn update(&mut self, icmp: &Icmp, ttl: Duration) -> Option<(Ipv4Addr, u16)> {
+        let mtu = icmp.get_next_hop_mtu()?;
+        let ip_addr = icmp.get_original_dst()?;
+
+        self.insert(ip_addr, mtu, ttl);
+
+        self.lookup(ip_addr).map(|mtu| (ip_addr, mtu))
+    }
+
+    /// Looks up the path MTU of the given IP address. Returns `None` if there is no entry or the
+    /// entry is expired.
+    pub fn lookup(&self, ip_addr: Ipv4Addr) -> Option<u16> {
+        match self.entries.get(&ip_addr) {
+            Some((mtu, expiry)) if *expiry > Instant::now() => Some(*mtu),
+            _ => None,
+        }
+    }
+
+    /// Removes the entry of the given IP address.
+    pub fn remove(&mut self, ip_addr: Ipv4Addr) -> Option<u16> {
+        self.entries.remove(&ip_addr).map(|(mtu, _)| mtu)
+    }
+
+    /// Removes all expired entries and returns the number of entries removed.
+    pub fn purge(&mut self) -> usize {
+        let now = Instant::now();
+        let len = self.entries.len();
+        self.entries.retain(|_, (_, expiry)| *expiry > now);
+
+        len - self.entries.len()
+    }
+
+    /// Get the number of entries in the cache, including expired entries which are not purged.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Returns if the cache contains no entries.
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn serialize_echo_request() {
+        let icmp = Icmp::new_echo_request(0x1234, 1);
+        let mut buffer = vec![0u8; icmp.get_size()];
+        let n = icmp.serialize(&mut buffer, icmp.get_size()).unwrap();
+
+        // !(0x0800 + 0x1234 + 0x0001) = 0xe5ca
+        assert_eq!(n, 8);
+        assert_eq!(buffer, [0x08, 0x00, 0xe5, 0xca, 0x12, 0x34, 0x00, 0x01]);
+    }
+
+    #[test]
+    fn validate_checksum() {
+        let icmp = Icmp::new_echo_request(0x1234, 1);
+        let mut buffer = vec![0u8; icmp.get_size() + 4];
+        icmp.serialize_with_payload(&mut buffer, b"ping", icmp.get_size() + 4)
+            .unwrap();
+
+        let (deserialized, _) = Icmp::deserialize(&buffer).unwrap();
+        assert!(deserialized.validate_checksum(&buffer));
+    }
+
+    #[test]
+    fn validate_checksum_flipped() {
+        let icmp = Icmp::new_echo_request(0x1234, 1);
+        let mut buffer = vec![0u8; icmp.get_size() + 4];
+        icmp.serialize_with_payload(&mut buffer, b"ping", icmp.get_size() + 4)
+            .unwrap();
+        buffer[10] ^= 0x01;
+
+        assert!(matches!(
+            Icmp::deserialize(&buffer),
+            Err(ParseError::ChecksumMismatch(LayerTypes::Icmp))
+        ));
+    }
+
+    /// Builds an ICMP fragmentation needed message quoting a UDP datagram to the given
+    /// destination.
+    fn build_fragmentation_needed(mtu: u16, dst: Ipv4Addr) -> Vec<u8> {
+        let mut buffer = vec![0x03, 0x04, 0x00, 0x00, 0x00, 0x00];
+        buffer.extend_from_slice(&mtu.to_be_bytes());
+        // The quoted IPv4 header and the leading 8 bytes of the original datagram
+        #[rustfmt::skip]
+        buffer.extend_from_slice(&[
+            0x45, 0x00, 0x05, 0xdc, 0x00, 0x00, 0x40, 0x00,
+            0x40, 0x11, 0x00, 0x00, 0x0a, 0x06, 0x00, 0x01,
+        ]);
+        buffer.extend_from_slice(&dst.octets());
+        buffer.extend_from_slice(&[0x04, 0x00, 0x00, 0x35, 0x05, 0xc8, 0x00, 0x00]);
+        let checksum = pnet::util::checksum(&buffer, 1);
+        buffer[2..4].copy_from_slice(&checksum.to_be_bytes());
+
+        buffer
+    }
+
+    #[test]
+    fn parse_fragmentation_needed() {
+        let dst = Ipv4Addr::new(93, 184, 216, 34);
+        let buffer = build_fragmentation_needed(1400, dst);
+        let (icmp, _) = Icmp::deserialize(&buffer).unwrap();
+
+        assert!(icmp.is_fragmentation_needed());
+        assert!(!icmp.is_echo_request());
+        assert_eq!(icmp.get_next_hop_mtu(), Some(1400));
+        assert_eq!(icmp.get_original_dst(), Some(dst));
+    }
+
+    #[test]
+    fn parse_fragmentation_needed_without_mtu() {
+        // Routers before RFC 1191 do not report the next-hop MTU
+        let buffer = build_fragmentation_needed(0, Ipv4Addr::new(93, 184, 216, 34));
+        let (icmp, _) = Icmp::deserialize(&buffer).unwrap();
+
+        assert!(icmp.is_fragmentation_needed());
+        assert_eq!(icmp.get_next_hop_mtu(), None);
+    }
+
+    #[test]
+    fn pmtu_cache_update() {
+        let dst = Ipv4Addr::new(93, 184, 216, 34);
+        let (icmp, _) = Icmp::deserialize(&build_fragmentation_needed(1400, dst)).unwrap();
+        let mut cache = PmtuCache::new();
+
+        assert_eq!(
+            cache.update(&icmp, DEFAULT_PMTU_CACHE_TTL),
+            Some((dst, 1400))
+        );
+        assert_eq!(cache.lookup(dst), Some(1400));
+        assert_eq!(cache.lookup(Ipv4Addr::new(93, 184, 216, 35)), None);
+
+        // Echo requests are ignored
+        let echo = Icmp::new_echo_request(0x1234, 1);
+        assert_eq!(cache.update(&echo, DEFAULT_PMTU_CACHE_TTL), None);
+        assert_eq!(cache.len(), 1);
+    }
+
+    #[test]
+    fn pmtu_cache_minimum_and_expire() {
+        let dst = Ipv4Addr::new(93, 184, 216, 34);
+        let mut cache = PmtuCache::new();
+        cache.insert(dst, 20, DEFAULT_PMTU_CACHE_TTL);
+        assert_eq!(cache.lookup(dst), Some(MINIMUM_IPV4_MTU));
+
+        cache.insert(dst, 1400, Duration::from_secs(0));
+        assert_eq!(cache.lookup(dst), None);
+        assert_eq!(cache.purge(), 1);
+        assert!(cache.is_empty());
+    }
+
+    #[test]
+    fn serialize_time_exceeded() {
+        let icmp = Icmp::new_time_exceeded();
+        let mut buffer = vec![0u8; icmp.get_size()];
+        icmp.serialize(&mut buffer, icmp.get_size()).unwrap();
+
+        // !0x0b00 = 0xf4ff
+        assert_eq!(buffer, [0x0b, 0x00, 0xf4, 0xff, 0x00, 0x00, 0x00, 0x00]);
+        assert!(icmp.is_error());
+        assert!(!Icmp::new_echo_request(0x1234, 1).is_error());
+    }
+
+    #[test]
+    fn build_frag_needed_quote_original() {
+        let src = Ipv4Addr::new(10, 6, 0, 1);
+        let dst = Ipv4Addr::new(93, 184, 216, 34);
+        let ipv4 = Ipv4::new(1, LayerTypes::Udp, src, dst).unwrap();
+        let mut buffer = vec![0u8; 1600];
+        ipv4.serialize(&mut buffer, 1600).unwrap();
+        let (original, _) = Ipv4::deserialize_header(&buffer).unwrap();
+        let data: Vec<u8> = (0..100).collect();
+
+        let icmp = match build_frag_needed(&original, &data, 1500) {
+            Layers::Icmp(icmp) => icmp,
+            _ => unreachable!(),
+        };
+        let quoted = icmp.get_payload().to_vec();
+        // The IPv4 header with the original total length and the leading 8 bytes of the data
+        assert_eq!(quoted.len(), 20 + ORIGINAL_DATAGRAM_DATA_SIZE);
+        assert_eq!(quoted[..20], buffer[..20]);
+        assert_eq!(quoted[2..4], 1600u16.to_be_bytes());
+        assert_eq!(quoted[20..], data[..8]);
+
+        let mut message = vec![0u8; icmp.get_size() + quoted.len()];
+        let n = icmp.get_size() + quoted.len();
+        icmp.serialize_with_payload(&mut message, &quoted, n)
+            .unwrap();
+        let (deserialized, _) = Icmp::deserialize(&message).unwrap();
+        assert!(deserialized.is_fragmentation_needed());
+        assert!(deserialized.is_error());
+        assert_eq!(deserialized.get_next_hop_mtu(), Some(1500));
+        assert_eq!(deserialized.get_original_dst(), Some(dst));
+    }
+
+    #[test]
+    fn build_frag_needed_short_data() {
+        let ipv4 = Ipv4::new(
+            1,
+            LayerTypes::Udp,
+            Ipv4Addr::new(10, 6, 0, 1),
+            Ipv4Addr::new(93, 184, 216, 34),
+        )
+        .unwrap();
+        let icmp = build_frag_needed(&ipv4, b"abc", 1400);
+
+        assert_eq!(icmp.payload().unwrap().len(), 20 + 3);
+        assert_eq!(icmp.payload().unwrap()[20..], *b"abc");
+    }
+}
@@ -0,0 +1,531 @@
+use super::ipv6::Ipv6;
+use super::{fmt_hex_dump, Layer, LayerType, LayerTypes, ParseError, SizeBounds};
+use pnet::datalink::MacAddr;
+use pnet::packet::icmpv6::ndp::{NdpOptionType, NdpOptionTypes, NeighborAdvertFlags};
+use pnet::packet::icmpv6::{
+    self, Icmpv6Code, Icmpv6Packet, Icmpv6Type, Icmpv6Types, MutableIcmpv6Packet,
+};
+use pnet::packet::Packet;
+use std::clone::Clone;
+use std::cmp::min;
+use std::fmt::{self, Display, Formatter};
+use std::io;
+use std::net::Ipv6Addr;
+
+/// Represents the size of the rest of the ICMPv6 header.
+const REST_OF_HEADER_SIZE: usize = 4;
+/// Represents the size of the target address in Neighbor Discovery messages.
+const TARGET_ADDR_SIZE: usize = 16;
+/// Represents the unit of the length of Neighbor Discovery options.
+const NDP_OPTION_UNIT: usize = 8;
+/// Represents the size of a link-layer address option for Ethernet.
+const LINK_LAYER_ADDR_OPTION_SIZE: usize = 8;
+
+/// Represents the hop limit of Neighbor Discovery messages, which must not be forwarded by
+/// routers.
+pub const NDP_HOP_LIMIT: u8 = 255;
+/// Represents the link-local scope all-nodes multicast address.
+pub const ALL_NODES_MULTICAST: Ipv6Addr = Ipv6Addr::new(0xff02, 0, 0, 0, 0, 0, 0, 1);
+/// Represents the max number of bytes of the original datagram quoted in an ICMPv6 error
+/// message, which keeps the message in the minimum IPv6 MTU of 1280 Bytes (RFC 4443).
+pub const MAX_ORIGINAL_DATAGRAM_SIZE: usize = 1280 - 40 - 8;
+
+/// Represents an ICMPv6 layer. Neighbor Solicitation and Neighbor Advertisement messages are
+/// parsed with their target address and options.
+#[derive(Clone, Debug)]
+pub struct Icmpv6 {
+    layer: icmpv6::Icmpv6,
+    pub src: Ipv6Addr,
+    pub dst: Ipv6Addr,
+    payload: Vec<u8>,
+}
+
+impl Icmpv6 {
+    /// Creates an `Icmpv6` represents a Neighbor Solicitation message for the given target
+    /// address. The source link-layer address option is included if the hardware address is
+    /// specified.
+    pub fn new_neighbor_solicitation(
+        target_addr: Ipv6Addr,
+        hardware_addr: Option<MacAddr>,
+    ) -> Icmpv6 {
+        let mut body = vec![0u8; REST_OF_HEADER_SIZE];
+        body.extend_from_slice(&target_addr.octets());
+        if let Some(hardware_addr) = hardware_addr {
+            body.extend(link_layer_addr_option(
+                NdpOptionTypes::SourceLLAddr,
+                hardware_addr,
+            ));
+        }
+
+        Icmpv6::new_with_body(Icmpv6Types::NeighborSolicit, body)
+    }
+
+    /// Creates an `Icmpv6` represents a Neighbor Advertisement message for the given target
+    /// address with the target link-layer address option. The flags are the combination of
+    /// `NeighborAdvertFlags`.
+    pub fn new_neighbor_advertisement(
+        target_addr: Ipv6Addr,
+        hardware_addr: MacAddr,
+        flags: u8,
+    ) -> Icmpv6 {
+        let mut body = vec![flags, 0, 0, 0];
+        body.extend_from_slice(&target_addr.octets());
+        body.extend(link_layer_addr_option(
+            NdpOptionTypes::TargetLLAddr,
+            hardware_addr,
+        ));
+
+        Icmpv6::new_with_body(Icmpv6Types::NeighborAdvert, body)
+    }
+
+    /// Creates an `Icmpv6` represents an ICMPv6 time exceeded message for hop limit exceeded in
+    /// transit.
+    pub fn new_time_exceeded() -> Icmpv6 {
+        Icmpv6::new_with_body(Icmpv6Types::TimeExceeded, vec![0u8; REST_OF_HEADER_SIZE])
+    }
+
+    fn new_with_body(t: Icmpv6Type, body: Vec<u8>) -> Icmpv6 {
+        let d_icmpv6 = icmpv6::Icmpv6 {
+            icmpv6_type: t,
+            icmpv6_code: Icmpv6Code(0),
+            checksum: 0,
+            payload: body,
+        };
+        Icmpv6::from(d_icmpv6)
+    }
+
+    /// Creates an `Icmpv6` according to the given `Icmpv6`. The source and destination IP
+    /// address of the layer are left unspecified.
+    pub fn from(icmpv6: icmpv6::Icmpv6) -> Icmpv6 {
+        Icmpv6 {
+            layer: icmpv6,
+            src: Ipv6Addr::UNSPECIFIED,
+            dst: Ipv6Addr::UNSPECIFIED,
+            payload: vec![],
+        }
+    }
+
+    /// Creates an `Icmpv6` according to the given ICMPv6 packet and `Ipv6`.
+    pub fn parse(packet: &Icmpv6Packet, ipv6: &Ipv6) -> Result<Icmpv6, ParseError> {
+        let (mut icmpv6, _) = Icmpv6::deserialize(packet.packet())?;
+        icmpv6.set_ipv6_layer(ipv6);
+
+        Ok(icmpv6)
+    }
+
+    /// Deserializes an `Icmpv6` from the given byte-array and returns it with the number of bytes
+    /// consumed. The source and destination IP address of the layer are left unspecified, so the
+    /// checksum should be validated by `validate_checksum` after they are set.
+    pub fn deserialize(buffer: &[u8]) -> Result<(Icmpv6, usize), ParseError> {
+        let min_size = Icmpv6::min_size();
+        if buffer.len() < min_size {
+            return Err(ParseError::Truncated(LayerTypes::Icmpv6));
+        }
+        let packet = Icmpv6Packet::new(buffer).unwrap();
+        let t = packet.get_icmpv6_type();
+
+        // Neighbor Discovery messages are parsed as a whole, others keep the rest of the header
+        let size = match t {
+            Icmpv6Types::NeighborSolicit | Icmpv6Types::NeighborAdvert => {
+                if buffer.len() < min_size + TARGET_ADDR_SIZE {
+                    return Err(ParseError::Truncated(LayerTypes::Icmpv6));
+                }
+                validate_ndp_options(&buffer[min_size + TARGET_ADDR_SIZE..])?;
+                buffer.len()
+            }
+            _ => min_size,
+        };
+
+        let d_icmpv6 = icmpv6::Icmpv6 {
+            icmpv6_type: t,
+            icmpv6_code: packet.get_icmpv6_code(),
+            checksum: packet.get_checksum(),
+            payload: buffer[Icmpv6Packet::minimum_packet_size()..size].to_vec(),
+        };
+        let mut icmpv6 = Icmpv6::from(d_icmpv6);
+        icmpv6.payload = buffer[size..].to_vec();
+
+        Ok((icmpv6, size))
+    }
+
+    /// Returns if the checksum of the layer matches the given ICMPv6 message. The source and
+    /// destination IP address of the layer are used in the pseudo-header.
+    pub fn validate_checksum(&self, buffer: &[u8]) -> bool {
+        match Icmpv6Packet::new(buffer) {
+            Some(ref packet) => {
+                icmpv6::checksum(packet, &self.src, &self.dst) == self.layer.checksum
+            }
+            None => false,
+        }
+    }
+
+    /// Sets the source and destination IP address of the layer with the given `Ipv6`.
+    pub fn set_ipv6_layer(&mut self, ipv6: &Ipv6) {
+        self.src = ipv6.get_src();
+        self.dst = ipv6.get_dst();
+    }
+
+    /// Computes the checksum of the given serialized message carrying its payload in place,
+    /// including the pseudo-header of the given source and destination IP address, or of the IP
+    /// addresses of the layer if `None`.
+    pub fn patch_checksum(
+        &self,
+        buffer: &mut [u8],
+        addrs: Option<(Ipv6Addr, Ipv6Addr)>,
+    ) -> io::Result<()> {
+        let mut packet = MutableIcmpv6Packet::new(buffer)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::WriteZero, "buffer too small"))?;
+
+        let (src, dst) = addrs.unwrap_or((self.src, self.dst));
+        let checksum = icmpv6::checksum(&packet.to_immutable(), &src, &dst);
+        packet.set_checksum(checksum);
+
+        Ok(())
+    }
+
+    /// Get the type of the ICMPv6 message.
+    pub fn get_icmpv6_type(&self) -> Icmpv6Type {
+        self.layer.icmpv6_type
+    }
+
+    /// Get the code of the ICMPv6 message.
+    pub fn get_icmpv6_code(&self) -> Icmpv6Code {
+        self.layer.icmpv6_code
+    }
+
+    /// Get the checksum of the layer.
+    pub fn get_checksum(&self) -> u16 {
+        self.layer.checksum
+    }
+
+    /// Returns if the `Icmpv6` is a Neighbor Solicitation message.
+    pub fn is_neighbor_solicitation(&self) -> bool {
+        self.layer.icmpv6_type == Icmpv6Types::NeighborSolicit
+    }
+
+    /// Returns if the `Icmpv6` is a Neighbor Advertisement message.
+    pub fn is_neighbor_advertisement(&self) -> bool {
+        self.layer.icmpv6_type == Icmpv6Types::NeighborAdvert
+    }
+
+    fn is_ndp(&self) -> bool {
+        self.is_neighbor_solicitation() || self.is_neighbor_advertisement()
+    }
+
+    /// Get the flags of the layer, which is the combination of `NeighborAdvertFlags`. The flags
+    /// are only meaningful in Neighbor Advertisement messages.
+    pub fn get_flags(&self) -> u8 {
+        self.layer.payload[0]
+    }
+
+    /// Get the target address of the layer. Returns `None` if the `Icmpv6` is not a Neighbor
+    /// Solicitation or Neighbor Advertisement message.
+    pub fn get_target_addr(&self) -> Option<Ipv6Addr> {
+        if !self.is_ndp() {
+            return None;
+        }
+        let mut octets = [0u8; TARGET_ADDR_SIZE];
+        octets.copy_from_slice(
+            &self.layer.payload[REST_OF_HEADER_SIZE..REST_OF_HEADER_SIZE + TARGET_ADDR_SIZE],
+        );
+
+        Some(Ipv6Addr::from(octets))
+    }
+
+    /// Get the link-layer address in the options of the layer, which is the source link-layer
+    /// address of a Neighbor Solicitation message, or the target link-layer address of a Neighbor
+    /// Advertisement message. Returns `None` if the option is absent.
+    pub fn get_link_layer_addr(&self) -> Option<MacAddr> {
+        let t = match self.layer.icmpv6_type {
+            Icmpv6Types::NeighborSolicit => NdpOptionTypes::SourceLLAddr,
+            Icmpv6Types::NeighborAdvert => NdpOptionTypes::TargetLLAddr,
+            _ => return None,
+        };
+
+        let mut options = &self.layer.payload[REST_OF_HEADER_SIZE + TARGET_ADDR_SIZE..];
+        while options.len() >= 2 {
+            let length = options[1] as usize * NDP_OPTION_UNIT;
+            if length == 0 || options.len() < length {
+                return None;
+            }
+            if options[0] == t.0 && length >= LINK_LAYER_ADDR_OPTION_SIZE {
+                let b = &options[2..8];
+                return Some(MacAddr::new(b[0], b[1], b[2], b[3], b[4], b[5]));
+            }
+            options = &options[length..];
+        }
+
+        None
+    }
+
+    /// Get the payload of the layer when the layer is deserialized.
+    pub fn get_payload(&self) -> &[u8] {
+        &self.payload
+    }
+}
+
+/// Returns a link-layer address option of the given type for the given hardware address.
+fn link_layer_addr_option(t: NdpOptionType, hardware_addr: MacAddr) -> Vec<u8> {
+    vec![
+        t.0,
+        (LINK_LAYER_ADDR_OPTION_SIZE / NDP_OPTION_UNIT) as u8,
+        hardware_addr.0,
+        hardware_addr.1,
+        hardware_addr.2,
+        hardware_addr.3,
+        hardware_addr.4,
+        hardware_addr.5,
+    ]
+}
+
+/// Validates the Neighbor Discovery options in the given byte-array. Options of zero length are
+/// rejected as RFC 4861 requires.
+fn validate_ndp_options(buffer: &[u8]) -> Result<(), ParseError> {
+    let mut options = buffer;
+    while !options.is_empty() {
+        if options.len() < 2 {
+            return Err(ParseError::Truncated(LayerTypes::Icmpv6));
+        }
+        let length = options[1] as usize * NDP_OPTION_UNIT;
+        if length == 0 {
+            return Err(ParseError::InvalidValue(
+                LayerTypes::Icmpv6,
+                "option length",
+            ));
+        }
+        options = &options[min(length, options.len())..];
+    }
+
+    Ok(())
+}
+
+impl Display for Icmpv6 {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        if f.alternate() {
+            return fmt_hex_dump(self, f);
+        }
+
+        let t = match self.layer.icmpv6_type {
+            Icmpv6Types::NeighborSolicit => String::from("Neighbor Solicitation"),
+            Icmpv6Types::NeighborAdvert => String::from("Neighbor Advertisement"),
+            _ => format!(
+                "Type = {}, Code = {}",
+                self.layer.icmpv6_type.0, self.layer.icmpv6_code.0
+            ),
+        };
+        let target = match self.get_target_addr() {
+            Some(target_addr) => format!(", Target = {}", target_addr),
+            None => String::new(),
+        };
+
+        write!(f, "{}: {}{}", LayerTypes::Icmpv6, t, target)
+    }
+}
+
+impl SizeBounds for Icmpv6 {
+    fn min_size() -> usize {
+        Icmpv6Packet::minimum_packet_size() + REST_OF_HEADER_SIZE
+    }
+
+    fn max_size() -> usize {
+        u16::MAX as usize
+    }
+}
+
+impl Layer for Icmpv6 {
+    fn get_type(&self) -> LayerType {
+        LayerTypes::Icmpv6
+    }
+
+    fn get_size(&self) -> usize {
+        Icmpv6Packet::packet_size(&self.layer)
+    }
+
+    fn serialize(&self, buffer: &mut [u8], _: usize) -> io::Result<usize> {
+        self.serialize_with_payload(buffer, &[], 0)
+    }
+
+    fn serialize_with_payload(
+        &self,
+        buffer: &mut [u8],
+        payload: &[u8],
+        _: usize,
+    ) -> io::Result<usize> {
+        let header_length = self.get_size();
+        if buffer.len() < header_length + payload.len() {
+            return Err(io::Error::new(io::ErrorKind::WriteZero, "buffer too small"));
+        }
+
+        // Copies payload
+        buffer[header_length..header_length + payload.len()].copy_from_slice(payload);
+
+        let mut packet = MutableIcmpv6Packet::new(&mut buffer[..header_length + payload.len()])
+            .ok_or_else(|| io::Error::new(io::ErrorKind::WriteZero, "buffer too small"))?;
+
+        packet.populate(&self.layer);
+
+        // Compute checksum with the pseudo-header
+        let checksum = icmpv6::checksum(&packet.to_immutable(), &self.src, &self.dst);
+        packet.set_checksum(checksum);
+
+        Ok(header_length + payload.len())
+    }
+
+    fn clone_boxed(&self) -> Box<dyn Layer> {
+        Box::new(self.clone())
+    }
+}
+
+/// Builds a Neighbor Advertisement in answer to the given Neighbor Solicitation, advertising the
+/// target address of the solicitation with the given hardware address. The advertisement is
+/// addressed to the source of the solicitation with the solicited and override flags set, or to
+/// the all-nodes multicast address without the solicited flag if the source is unspecified, as
+/// in Duplicate Address Detection. Returns `None` if the `Icmpv6` is not a Neighbor Solicitation
+/// message.
+pub fn build_neighbor_advertisement(ns: &Icmpv6, our_mac: MacAddr) -> Option<Icmpv6> {
+    if !ns.is_neighbor_solicitation() {
+        return None;
+    }
+    let target_addr = ns.get_target_addr()?;
+
+    let (dst, flags) = match ns.src.is_unspecified() {
+        true => (ALL_NODES_MULTICAST, NeighborAdvertFlags::Override),
+        false => (
+            ns.src,
+            NeighborAdvertFlags::Solicited | NeighborAdvertFlags::Override,
+        ),
+    };
+    let mut na = Icmpv6::new_neighbor_advertisement(target_addr, our_mac, flags);
+    na.src = target_addr;
+    na.dst = dst;
+
+    Some(na)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MAC: MacAddr = MacAddr(0x02, 0x00, 0x00, 0x00, 0x00, 0x01);
+    const OUR_MAC: MacAddr = MacAddr(0x02, 0x00, 0x00, 0x00, 0x00, 0x02);
+
+    /// Serializes the layer and deserializes it back with the same addresses.
+    fn round_trip(icmpv6: &Icmpv6) -> (Icmpv6, Vec<u8>) {
+        let n = icmpv6.get_size();
+        let mut buffer = vec![0u8; n];
+        icmpv6.serialize(&mut buffer, n).unwrap();
+        let (mut deserialized, n) = Icmpv6::deserialize(&buffer).unwrap();
+        assert_eq!(n, buffer.len());
+        deserialized.src = icmpv6.src;
+        deserialized.dst = icmpv6.dst;
+
+        (deserialized, buffer)
+    }
+
+    /// Computes the one's complement sum of the given ICMPv6 message and its pseudo-header, which
+    /// is all ones for a valid checksum.
+    fn sum_with_pseudo_header(src: Ipv6Addr, dst: Ipv6Addr, buffer: &[u8]) -> u16 {
+        let mut data = Vec::new();
+        data.extend_from_slice(&src.octets());
+        data.extend_from_slice(&dst.octets());
+        data.extend_from_slice(&(buffer.len() as u32).to_be_bytes());
+        data.extend_from_slice(&[0, 0, 0, 58]);
+        data.extend_from_slice(buffer);
+        if data.len() % 2 == 1 {
+            data.push(0);
+        }
+
+        let mut sum: u32 = data
+            .chunks(2)
+            .map(|w| (w[0] as u32) << 8 | w[1] as u32)
+            .sum();
+        while sum >> 16 != 0 {
+            sum = (sum & 0xffff) + (sum >> 16);
+        }
+
+        sum as u16
+    }
+
+    #[test]
+    fn neighbor_solicitation_round_trip() {
+        let target_addr: Ipv6Addr = "fe80::2".parse().unwrap();
+        let mut ns = Icmpv6::new_neighbor_solicitation(target_addr, Some(MAC));
+        ns.src = "fe80::1".parse().unwrap();
+        ns.dst = "ff02::1:ff00:2".parse().unwrap();
+
+        let (ns, buffer) = round_trip(&ns);
+        assert!(ns.is_neighbor_solicitation());
+        assert_eq!(ns.get_target_addr(), Some(target_addr));
+        assert_eq!(ns.get_link_layer_addr(), Some(MAC));
+        assert!(ns.validate_checksum(&buffer));
+    }
+
+    #[test]
+    fn build_neighbor_advertisement_solicited() {
+        let target_addr: Ipv6Addr = "fe80::2".parse().unwrap();
+        let mut ns = Icmpv6::new_neighbor_solicitation(target_addr, Some(MAC));
+        ns.src = "fe80::1".parse().unwrap();
+        ns.dst = "ff02::1:ff00:2".parse().unwrap();
+        let na = build_neighbor_advertisement(&ns, OUR_MAC).unwrap();
+
+        assert_eq!(na.src, target_addr);
+        assert_eq!(na.dst, ns.src);
+        let (na, buffer) = round_trip(&na);
+        assert!(na.is_neighbor_advertisement());
+        assert_eq!(na.get_icmpv6_code(), Icmpv6Code(0));
+        assert_eq!(
+            na.get_flags(),
+            NeighborAdvertFlags::Solicited | NeighborAdvertFlags::Override
+        );
+        assert_eq!(na.get_target_addr(), Some(target_addr));
+        assert_eq!(na.get_link_layer_addr(), Some(OUR_MAC));
+
+        // Type, code, checksum, flags, reserved, target address and the target link-layer
+        // address option
+        assert_eq!(buffer.len(), 32);
+        assert_eq!(&buffer[..2], &[136, 0]);
+        assert_eq!(&buffer[24..], &[2, 1, 0x02, 0, 0, 0, 0, 0x02]);
+        assert!(na.validate_checksum(&buffer));
+        assert_eq!(sum_with_pseudo_header(na.src, na.dst, &buffer), 0xffff);
+    }
+
+    #[test]
+    fn build_neighbor_advertisement_duplicate_address_detection() {
+        let target_addr: Ipv6Addr = "fe80::2".parse().unwrap();
+        let mut ns = Icmpv6::new_neighbor_solicitation(target_addr, None);
+        ns.dst = "ff02::1:ff00:2".parse().unwrap();
+        let na = build_neighbor_advertisement(&ns, OUR_MAC).unwrap();
+
+        // The solicited flag is cleared for solicitations from the unspecified address
+        assert_eq!(na.dst, ALL_NODES_MULTICAST);
+        assert_eq!(na.get_flags(), NeighborAdvertFlags::Override);
+    }
+
+    #[test]
+    fn build_neighbor_advertisement_not_solicitation() {
+        let na = Icmpv6::new_neighbor_advertisement("fe80::2".parse().unwrap(), MAC, 0);
+        assert!(build_neighbor_advertisement(&na, OUR_MAC).is_none());
+        assert!(build_neighbor_advertisement(&Icmpv6::new_time_exceeded(), OUR_MAC).is_none());
+    }
+
+    #[test]
+    fn deserialize_zero_length_option() {
+        let ns = Icmpv6::new_neighbor_solicitation("fe80::2".parse().unwrap(), Some(MAC));
+        let n = ns.get_size();
+        let mut buffer = vec![0u8; n];
+        ns.serialize(&mut buffer, n).unwrap();
+        buffer[25] = 0;
+
+        assert!(matches!(
+            Icmpv6::deserialize(&buffer),
+            Err(ParseError::InvalidValue(
+                LayerTypes::Icmpv6,
+                "option length"
+            ))
+        ));
+        assert!(matches!(
+            Icmpv6::deserialize(&buffer[..20]),
+            Err(ParseError::Truncated(LayerTypes::Icmpv6))
+        ));
+    }
+}
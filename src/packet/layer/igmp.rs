@@ -0,0 +1,320 @@
+use super::{fmt_hex_dump, Layer, LayerType, LayerTypes, ParseError, SizeBounds};
+use pnet::util;
+use std::clone::Clone;
+use std::fmt::{self, Display, Formatter};
+use std::io;
+use std::net::Ipv4Addr;
+
+/// Represents the size of the IGMP header.
+const HEADER_SIZE: usize = 8;
+/// Represents the offset of the checksum field in 16-bit words.
+const CHECKSUM_OFFSET: usize = 1;
+
+/// Represents the type of an IGMP message.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum IgmpType {
+    /// Represents a membership query of any IGMP version.
+    MembershipQuery,
+    /// Represents an IGMPv1 membership report (RFC 1112).
+    V1MembershipReport,
+    /// Represents an IGMPv2 membership report (RFC 2236).
+    V2MembershipReport,
+    /// Represents an IGMPv3 membership report (RFC 3376).
+    V3MembershipReport,
+    /// Represents an IGMPv2 leave group message (RFC 2236).
+    LeaveGroup,
+    /// Represents an unknown message.
+    Unknown(u8),
+}
+
+impl IgmpType {
+    /// Get the `IgmpType` of the given value of the type field.
+    pub fn from_u8(t: u8) -> IgmpType {
+        match t {
+            0x11 => IgmpType::MembershipQuery,
+            0x12 => IgmpType::V1MembershipReport,
+            0x16 => IgmpType::V2MembershipReport,
+            0x22 => IgmpType::V3MembershipReport,
+            0x17 => IgmpType::LeaveGroup,
+            t => IgmpType::Unknown(t),
+        }
+    }
+
+    /// Get the value of the type field of the `IgmpType`.
+    pub fn to_u8(&self) -> u8 {
+        match *self {
+            IgmpType::MembershipQuery => 0x11,
+            IgmpType::V1MembershipReport => 0x12,
+            IgmpType::V2MembershipReport => 0x16,
+            IgmpType::V3MembershipReport => 0x22,
+            IgmpType::LeaveGroup => 0x17,
+            IgmpType::Unknown(t) => t,
+        }
+    }
+}
+
+impl Display for IgmpType {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            IgmpType::MembershipQuery => write!(f, "Membership Query"),
+            IgmpType::V1MembershipReport => write!(f, "Membership Report (v1)"),
+            IgmpType::V2MembershipReport => write!(f, "Membership Report (v2)"),
+            IgmpType::V3MembershipReport => write!(f, "Membership Report (v3)"),
+            IgmpType::LeaveGroup => write!(f, "Leave Group"),
+            IgmpType::Unknown(t) => write!(f, "Type = {}", t),
+        }
+    }
+}
+
+/// Represents an IGMP layer. Only the common header of IGMP messages is parsed, the remaining of
+/// IGMPv3 messages, e.g., the sources and the group records, is kept as the payload.
+#[derive(Clone, Debug)]
+pub struct Igmp {
+    igmp_type: IgmpType,
+    max_response_time: u8,
+    checksum: u16,
+    group: Ipv4Addr,
+    payload: Vec<u8>,
+}
+
+impl Igmp {
+    /// Creates an `Igmp` of the given type and group address.
+    pub fn new(igmp_type: IgmpType, max_response_time: u8, group: Ipv4Addr) -> Igmp {
+        Igmp {
+            igmp_type,
+            max_response_time,
+            checksum: 0,
+            group,
+            payload: vec![],
+        }
+    }
+
+    /// Deserializes an `Igmp` from the given byte-array and returns it with the number of bytes
+    /// consumed. The byte-array should contain the whole IGMP message for validating the checksum.
+    pub fn deserialize(buffer: &[u8]) -> Result<(Igmp, usize), ParseError> {
+        if buffer.len() < Igmp::min_size() {
+            return Err(ParseError::Truncated(LayerTypes::Igmp));
+        }
+
+        let igmp = Igmp {
+            igmp_type: IgmpType::from_u8(buffer[0]),
+            max_response_time: buffer[1],
+            checksum: (buffer[2] as u16) << 8 | buffer[3] as u16,
+            group: Ipv4Addr::new(buffer[4], buffer[5], buffer[6], buffer[7]),
+            payload: buffer[HEADER_SIZE..].to_vec(),
+        };
+        if !igmp.validate_checksum(buffer) {
+            return Err(ParseError::ChecksumMismatch(LayerTypes::Igmp));
+        }
+
+        Ok((igmp, HEADER_SIZE))
+    }
+
+    /// Returns if the checksum of the layer matches the given IGMP message.
+    pub fn validate_checksum(&self, buffer: &[u8]) -> bool {
+        util::checksum(buffer, CHECKSUM_OFFSET) == self.checksum
+    }
+
+    /// Get the type of the IGMP message.
+    pub fn get_igmp_type(&self) -> IgmpType {
+        self.igmp_type
+    }
+
+    /// Get the max response time of the layer in tenths of a second. The max response time is
+    /// only meaningful in membership queries.
+    pub fn get_max_response_time(&self) -> u8 {
+        self.max_response_time
+    }
+
+    /// Get the checksum of the layer.
+    pub fn get_checksum(&self) -> u16 {
+        self.checksum
+    }
+
+    /// Get the group address of the layer. The group address is unspecified in general queries
+    /// and IGMPv3 membership reports.
+    pub fn get_group(&self) -> Ipv4Addr {
+        self.group
+    }
+
+    /// Get the payload of the layer when the layer is deserialized.
+    pub fn get_payload(&self) -> &[u8] {
+        &self.payload
+    }
+
+    /// Returns if the `Igmp` is a membership query.
+    pub fn is_query(&self) -> bool {
+        self.igmp_type == IgmpType::MembershipQuery
+    }
+
+    /// Returns if the `Igmp` is a membership report of any IGMP version.
+    pub fn is_report(&self) -> bool {
+        matches!(
+            self.igmp_type,
+            IgmpType::V1MembershipReport
+                | IgmpType::V2MembershipReport
+                | IgmpType::V3MembershipReport
+        )
+    }
+
+    /// Returns if the `Igmp` is a leave group message.
+    pub fn is_leave(&self) -> bool {
+        self.igmp_type == IgmpType::LeaveGroup
+    }
+
+    fn serialize_header(&self, buffer: &mut [u8]) -> io::Result<usize> {
+        if buffer.len() < HEADER_SIZE {
+            return Err(io::Error::new(io::ErrorKind::WriteZero, "buffer too small"));
+        }
+
+        buffer[0] = self.igmp_type.to_u8();
+        buffer[1] = self.max_response_time;
+        // The checksum is computed after the payload is copied
+        buffer[2] = 0;
+        buffer[3] = 0;
+        buffer[4..HEADER_SIZE].copy_from_slice(&self.group.octets());
+
+        Ok(HEADER_SIZE)
+    }
+}
+
+impl Display for Igmp {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        if f.alternate() {
+            return fmt_hex_dump(self, f);
+        }
+
+        write!(
+            f,
+            "{}: {}, Group = {}",
+            LayerTypes::Igmp,
+            self.igmp_type,
+            self.group
+        )
+    }
+}
+
+impl SizeBounds for Igmp {
+    fn min_size() -> usize {
+        HEADER_SIZE
+    }
+
+    fn max_size() -> usize {
+        HEADER_SIZE
+    }
+}
+
+impl Layer for Igmp {
+    fn get_type(&self) -> LayerType {
+        LayerTypes::Igmp
+    }
+
+    fn get_size(&self) -> usize {
+        HEADER_SIZE
+    }
+
+    fn serialize(&self, buffer: &mut [u8], n: usize) -> io::Result<usize> {
+        let size = self.serialize_header(buffer)?;
+
+        // Compute checksum over the header and the payload following it
+        let end = n.max(size).min(buffer.len());
+        let checksum = util::checksum(&buffer[..end], CHECKSUM_OFFSET);
+        buffer[2] = (checksum >> 8) as u8;
+        buffer[3] = checksum as u8;
+
+        Ok(size)
+    }
+
+    fn serialize_with_payload(
+        &self,
+        buffer: &mut [u8],
+        payload: &[u8],
+        _: usize,
+    ) -> io::Result<usize> {
+        let size = self.get_size();
+        if buffer.len() < size + payload.len() {
+            return Err(io::Error::new(io::ErrorKind::WriteZero, "buffer too small"));
+        }
+
+        // Copies payload
+        buffer[size..size + payload.len()].copy_from_slice(payload);
+
+        self.serialize(buffer, size + payload.len())?;
+
+        Ok(size + payload.len())
+    }
+
+    fn clone_boxed(&self) -> Box<dyn Layer> {
+        Box::new(self.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[rustfmt::skip]
+    const V2_MEMBERSHIP_REPORT: [u8; 8] = [
+        0x16, 0x00, 0xf9, 0xfc,
+        0xef, 0x01, 0x01, 0x01,
+    ];
+
+    #[test]
+    fn deserialize_v2_membership_report() {
+        let (igmp, n) = Igmp::deserialize(&V2_MEMBERSHIP_REPORT).unwrap();
+        assert_eq!(n, 8);
+        assert_eq!(igmp.get_igmp_type(), IgmpType::V2MembershipReport);
+        assert_eq!(igmp.get_max_response_time(), 0);
+        assert_eq!(igmp.get_checksum(), 0xf9fc);
+        assert_eq!(igmp.get_group(), Ipv4Addr::new(239, 1, 1, 1));
+        assert!(igmp.is_report() && !igmp.is_query() && !igmp.is_leave());
+        assert_eq!(
+            igmp.to_string(),
+            "IGMP: Membership Report (v2), Group = 239.1.1.1"
+        );
+    }
+
+    #[test]
+    fn deserialize_invalid() {
+        let mut buffer = V2_MEMBERSHIP_REPORT;
+        buffer[7] = 0x02;
+        assert_eq!(
+            Igmp::deserialize(&buffer).unwrap_err(),
+            ParseError::ChecksumMismatch(LayerTypes::Igmp)
+        );
+        assert_eq!(
+            Igmp::deserialize(&V2_MEMBERSHIP_REPORT[..7]).unwrap_err(),
+            ParseError::Truncated(LayerTypes::Igmp)
+        );
+    }
+
+    #[test]
+    fn serialize_deserialize_with_payload() {
+        let igmp = Igmp::new(IgmpType::LeaveGroup, 0, Ipv4Addr::new(239, 1, 1, 1));
+        let mut buffer = vec![0u8; 8];
+        igmp.serialize(&mut buffer, 8).unwrap();
+        assert_eq!(buffer[..2], [0x17, 0x00]);
+        assert!(Igmp::deserialize(&buffer).unwrap().0.is_leave());
+
+        // IGMPv3 queries carry the sources after the header, which are covered by the checksum
+        let igmp = Igmp::new(IgmpType::MembershipQuery, 100, Ipv4Addr::UNSPECIFIED);
+        let payload = [0x02, 0x7d, 0x00, 0x01, 192, 0, 2, 1];
+        let mut buffer = vec![0u8; 16];
+        let n = igmp
+            .serialize_with_payload(&mut buffer, &payload, 16)
+            .unwrap();
+        assert_eq!(n, 16);
+        let (deserialized, _) = Igmp::deserialize(&buffer).unwrap();
+        assert!(deserialized.is_query());
+        assert_eq!(deserialized.get_max_response_time(), 100);
+        assert_eq!(deserialized.get_payload(), payload);
+    }
+
+    #[test]
+    fn igmp_type_from_u8() {
+        for t in [0x11, 0x12, 0x16, 0x17, 0x22, 0x30] {
+            assert_eq!(IgmpType::from_u8(t).to_u8(), t);
+        }
+        assert_eq!(IgmpType::from_u8(0x30), IgmpType::Unknown(0x30));
+    }
+}
@@ -1,15 +1,71 @@
-use super::{Layer, LayerType, LayerTypes};
-use pnet::packet::ip::IpNextHeaderProtocols;
-use pnet::packet::ipv4::{self, Ipv4Flags, Ipv4OptionPacket, Ipv4Packet, MutableIpv4Packet};
+use super::{
+    check_length, fmt_hex_dump, incremental_update, Layer, LayerType, LayerTypes, ParseError,
+    SizeBounds,
+};
+use pnet::packet::ip::{IpNextHeaderProtocol, IpNextHeaderProtocols};
+use pnet::packet::ipv4::{
+    self, Ipv4Flags, Ipv4OptionPacket, Ipv4Packet, MutableIpv4OptionPacket, MutableIpv4Packet,
+};
+use pnet::packet::Packet;
+use pnet::util;
 use std::clone::Clone;
+use std::cmp::min;
 use std::fmt::{self, Display, Formatter};
 use std::io;
 use std::net::Ipv4Addr;
 
+/// Represents the max size of the IPv4 options.
+const MAX_OPTIONS_SIZE: usize = 40;
+/// Represents the offset of the checksum field in 16-bit words.
+const CHECKSUM_OFFSET: usize = 5;
+
+/// Represents the maximum DSCP.
+pub const MAXIMUM_DSCP: u8 = 0x3f;
+/// Represents the mask of the DSCP field.
+const DSCP_MASK: u8 = MAXIMUM_DSCP;
+/// Represents the mask of the ECN field.
+const ECN_MASK: u8 = 0x03;
+/// Represents the ECN codepoint of congestion experienced (RFC 3168).
+pub const ECN_CE: u8 = 0x03;
+
+/// Represents how the time to live of IPv4 packets sent is set.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum TtlMode {
+    /// Keeps the initial time to live.
+    Initial,
+    /// Decrements the initial time to live, as packets forwarded by a router.
+    Decrement,
+    /// Sets the time to live to the fixed value, e.g., for a uniform time to live against OS
+    /// fingerprinting.
+    Fixed(u8),
+}
+
+impl Default for TtlMode {
+    fn default() -> Self {
+        TtlMode::Initial
+    }
+}
+
+impl Display for TtlMode {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            TtlMode::Initial => write!(f, "initial"),
+            TtlMode::Decrement => write!(f, "decrement"),
+            TtlMode::Fixed(ttl) => write!(f, "{}", ttl),
+        }
+    }
+}
+
 /// Represents an IPv4 layer.
 #[derive(Clone, Debug)]
 pub struct Ipv4 {
     layer: ipv4::Ipv4,
+    options: Vec<u8>,
+    payload: Vec<u8>,
+    checksum_offload: bool,
+    /// Represents if the checksum of the layer is maintained along with its header, e.g., parsed
+    /// and updated incrementally, which is kept in serialization without recomputation.
+    is_checksum_maintained: bool,
 }
 
 impl Ipv4 {
@@ -18,6 +74,9 @@ impl Ipv4 {
         let next_level_protocol = match t {
             LayerTypes::Tcp => IpNextHeaderProtocols::Tcp,
             LayerTypes::Udp => IpNextHeaderProtocols::Udp,
+            LayerTypes::Icmp => IpNextHeaderProtocols::Icmp,
+            LayerTypes::Igmp => IpNextHeaderProtocols::Igmp,
+            LayerTypes::Gre => IpNextHeaderProtocols::Gre,
             _ => return None,
         };
         let d_ipv4 = ipv4::Ipv4 {
@@ -75,9 +134,27 @@ impl Ipv4 {
         None
     }
 
-    /// Creates an `Ipv4` according to the given `Ipv4`.
+    /// Creates an `Ipv4` according to the given `Ipv4`. Options of the given `Ipv4` are kept as
+    /// raw bytes of the layer.
     pub fn from(ipv4: ipv4::Ipv4) -> Ipv4 {
-        Ipv4 { layer: ipv4 }
+        let mut d_ipv4 = ipv4;
+        let mut options = Vec::new();
+        for option in &d_ipv4.options {
+            let mut buffer = vec![0u8; Ipv4OptionPacket::packet_size(option)];
+            MutableIpv4OptionPacket::new(&mut buffer)
+                .unwrap()
+                .populate(option);
+            options.extend_from_slice(&buffer);
+        }
+        d_ipv4.options = vec![];
+
+        Ipv4 {
+            layer: d_ipv4,
+            options,
+            payload: vec![],
+            checksum_offload: false,
+            is_checksum_maintained: false,
+        }
     }
 
     /// Creates an `Ipv4` according to the given IPv4 packet.
@@ -96,10 +173,117 @@ impl Ipv4 {
             checksum: packet.get_checksum(),
             source: packet.get_source(),
             destination: packet.get_destination(),
-            options: packet.get_options(),
+            options: vec![],
             payload: vec![],
         };
-        Ipv4::from(d_ipv4)
+        let mut ipv4 = Ipv4::from(d_ipv4);
+        ipv4.options = packet.get_options_raw().to_vec();
+        ipv4.is_checksum_maintained = true;
+
+        ipv4
+    }
+
+    /// Deserializes an `Ipv4` from the given byte-array and returns it with the number of bytes
+    /// consumed.
+    pub fn deserialize(buffer: &[u8]) -> Result<(Ipv4, usize), ParseError> {
+        let (mut ipv4, header_length) = Ipv4::deserialize_header(buffer)?;
+        let end = min(ipv4.get_total_length() as usize, buffer.len());
+        ipv4.payload = buffer[header_length..end].to_vec();
+
+        Ok((ipv4, header_length))
+    }
+
+    /// Deserializes an `Ipv4` from the given byte-array without copying its payload, and returns
+    /// it with the number of bytes consumed. The payload of the layer is left empty.
+    pub fn deserialize_header(buffer: &[u8]) -> Result<(Ipv4, usize), ParseError> {
+        if buffer.len() < Ipv4::min_size() {
+            return Err(ParseError::Truncated(LayerTypes::Ipv4));
+        }
+        let packet = Ipv4Packet::new(buffer).ok_or(ParseError::Truncated(LayerTypes::Ipv4))?;
+        if packet.get_version() != 4 {
+            return Err(ParseError::InvalidValue(LayerTypes::Ipv4, "version"));
+        }
+        let header_length = packet.get_header_length() as usize * 4;
+        if header_length < Ipv4Packet::minimum_packet_size() {
+            return Err(ParseError::InvalidValue(LayerTypes::Ipv4, "header length"));
+        }
+        if buffer.len() < header_length {
+            return Err(ParseError::Truncated(LayerTypes::Ipv4));
+        }
+        if (packet.get_total_length() as usize) < header_length {
+            return Err(ParseError::InvalidValue(LayerTypes::Ipv4, "total length"));
+        }
+        if util::checksum(&buffer[..header_length], CHECKSUM_OFFSET) != packet.get_checksum() {
+            return Err(ParseError::ChecksumMismatch(LayerTypes::Ipv4));
+        }
+
+        Ok((Ipv4::parse(&packet), header_length))
+    }
+
+    /// Computes the checksum of the layer. The checksum field itself is treated as zero during the
+    /// computation. Returns an error if the header cannot be serialized, e.g., the options are too
+    /// long.
+    pub fn checksum(&self) -> io::Result<u16> {
+        let mut buffer = vec![0u8; self.get_size()];
+        let mut packet = MutableIpv4Packet::new(&mut buffer).unwrap();
+        self.populate(&mut packet)?;
+
+        Ok(util::checksum(&buffer, CHECKSUM_OFFSET))
+    }
+
+    /// Populates the header and options of the layer into the given packet.
+    fn populate(&self, packet: &mut MutableIpv4Packet) -> io::Result<()> {
+        packet.populate(&self.layer);
+
+        // Fix length
+        let header_length = self.get_size();
+        if header_length / 4 > 15 {
+            return Err(io::Error::new(io::ErrorKind::Other, "IPv4 too big"));
+        }
+        packet.set_header_length((header_length / 4) as u8);
+
+        // Options
+        let buffer = packet.get_options_raw_mut();
+        if buffer.len() < self.options.len() {
+            return Err(io::Error::new(io::ErrorKind::WriteZero, "buffer too small"));
+        }
+        buffer[..self.options.len()].copy_from_slice(&self.options);
+        // Padding
+        for b in &mut buffer[self.options.len()..] {
+            *b = 0;
+        }
+
+        Ok(())
+    }
+
+    /// Sets the options of the layer in raw bytes. The options will be padded to 4 bytes.
+    pub fn set_options(&mut self, options: Vec<u8>) {
+        self.options = options;
+        self.is_checksum_maintained = false;
+    }
+
+    /// Get the options of the layer in raw bytes, including the padding.
+    pub fn get_options(&self) -> &[u8] {
+        &self.options
+    }
+
+    /// Get the payload of the layer, which is the bytes after the header when the layer is
+    /// deserialized.
+    pub fn get_payload(&self) -> &[u8] {
+        &self.payload
+    }
+
+    /// Get the checksum of the layer.
+    pub fn get_checksum(&self) -> u16 {
+        self.layer.checksum
+    }
+
+    /// Returns if the checksum of the layer matches its content.
+    pub fn validate_checksum(&self) -> bool {
+        match self.checksum() {
+            Ok(checksum) => checksum == self.layer.checksum,
+            Err(_) => false,
+        }
     }
 
     /// Creates an `Ipv4` without fragmentation according to an `Ipv4`.
@@ -119,10 +303,47 @@ impl Ipv4 {
                 checksum: 0,
                 source: ipv4.get_src(),
                 destination: ipv4.get_dst(),
-                options: ipv4.layer.options.clone(),
+                options: vec![],
                 payload: vec![],
             },
+            options: ipv4.options.clone(),
+            payload: vec![],
+            checksum_offload: false,
+            is_checksum_maintained: false,
+        }
+    }
+
+    /// Splits the given payload into fragments which fit in the MTU, and returns `Ipv4`s represent
+    /// each fragment. The payload of the n-th fragment starts at the fragment offset of the
+    /// returned `Ipv4` relative to the fragment offset of this `Ipv4`.
+    pub fn fragment(&self, payload: &[u8], mtu: usize) -> Vec<Ipv4> {
+        let header_length = self.get_size();
+        let max_fragment_length = mtu.saturating_sub(header_length) / 8 * 8;
+        if max_fragment_length == 0 {
+            return vec![];
+        }
+
+        let mut frags = Vec::new();
+        let mut offset = 0;
+        loop {
+            let length = min(payload.len() - offset, max_fragment_length);
+            let is_last = offset + length >= payload.len();
+
+            let mut ipv4 = Ipv4::defrag(self);
+            ipv4.layer.total_length = (header_length + length) as u16;
+            ipv4.layer.fragment_offset = self.layer.fragment_offset + (offset / 8) as u16;
+            if !is_last || self.is_more_fragment() {
+                ipv4.layer.flags = Ipv4Flags::MoreFragments;
+            }
+            frags.push(ipv4);
+
+            if is_last {
+                break;
+            }
+            offset += length;
         }
+
+        frags
     }
 
     /// Get the total length of the layer.
@@ -135,6 +356,11 @@ impl Ipv4 {
         self.layer.identification
     }
 
+    /// Returns if the `Ipv4` should not be fragmented.
+    pub fn is_dont_fragment(&self) -> bool {
+        self.layer.flags & Ipv4Flags::DontFragment != 0
+    }
+
     /// Returns if more fragments are follows this `Ipv4`.
     pub fn is_more_fragment(&self) -> bool {
         self.layer.flags & Ipv4Flags::MoreFragments != 0
@@ -150,6 +376,103 @@ impl Ipv4 {
         self.is_more_fragment() || self.get_fragment_offset() > 0
     }
 
+    /// Get the next level protocol of the layer.
+    pub fn get_next_level_protocol(&self) -> IpNextHeaderProtocol {
+        self.layer.next_level_protocol
+    }
+
+    /// Sets the next level protocol of the layer and updates the checksum incrementally.
+    pub fn set_next_level_protocol(&mut self, protocol: IpNextHeaderProtocol) {
+        // The protocol shares a 16-bit word with TTL
+        let ttl = (self.layer.ttl as u16) << 8;
+        let old_word = ttl | self.layer.next_level_protocol.0 as u16;
+        self.layer.next_level_protocol = protocol;
+        let new_word = ttl | self.layer.next_level_protocol.0 as u16;
+        self.layer.checksum = incremental_update(self.layer.checksum, old_word, new_word);
+    }
+
+    /// Sets if the header checksum is left to the checksum offload of the link. The checksum will
+    /// be written as 0 if it is offloaded, and the NIC or the kernel fills it.
+    pub fn set_checksum_offload(&mut self, offload: bool) {
+        self.checksum_offload = offload;
+    }
+
+    /// Get the differentiated services code point of the layer.
+    pub fn get_dscp(&self) -> u8 {
+        self.layer.dscp
+    }
+
+    /// Sets the differentiated services code point of the layer. The ECN bits are left
+    /// unchanged.
+    pub fn set_dscp(&mut self, dscp: u8) {
+        if self.layer.dscp != dscp & DSCP_MASK {
+            self.layer.dscp = dscp & DSCP_MASK;
+            self.is_checksum_maintained = false;
+        }
+    }
+
+    /// Get the explicit congestion notification of the layer.
+    pub fn get_ecn(&self) -> u8 {
+        self.layer.ecn
+    }
+
+    /// Sets the explicit congestion notification of the layer. The DSCP bits are left unchanged.
+    pub fn set_ecn(&mut self, ecn: u8) {
+        if self.layer.ecn != ecn & ECN_MASK {
+            self.layer.ecn = ecn & ECN_MASK;
+            self.is_checksum_maintained = false;
+        }
+    }
+
+    /// Returns if the layer is marked congestion experienced.
+    pub fn is_congestion_experienced(&self) -> bool {
+        self.layer.ecn == ECN_CE
+    }
+
+    /// Get the time to live of the layer.
+    pub fn get_ttl(&self) -> u8 {
+        self.layer.ttl
+    }
+
+    /// Sets the time to live of the layer and updates the checksum incrementally.
+    pub fn set_ttl(&mut self, ttl: u8) {
+        // TTL shares a 16-bit word with the protocol
+        let protocol = self.layer.next_level_protocol.0 as u16;
+        let old_word = (self.layer.ttl as u16) << 8 | protocol;
+        self.layer.ttl = ttl;
+        let new_word = (self.layer.ttl as u16) << 8 | protocol;
+        self.layer.checksum = incremental_update(self.layer.checksum, old_word, new_word);
+    }
+
+    /// Applies the given mode to the time to live of the layer. The time to live is left
+    /// unchanged if it would expire by the decrement.
+    pub fn apply_ttl_mode(&mut self, mode: TtlMode) {
+        match mode {
+            TtlMode::Initial => {}
+            TtlMode::Decrement => {
+                self.decrement_ttl();
+            }
+            TtlMode::Fixed(ttl) => self.set_ttl(ttl),
+        }
+    }
+
+    /// Decrements the time to live of the layer and updates the checksum, as a router does when
+    /// forwarding the packet. Returns `false` and leaves the layer unchanged if the time to live
+    /// is expired, in which case the packet should be dropped.
+    pub fn decrement_ttl(&mut self) -> bool {
+        if self.layer.ttl <= 1 {
+            return false;
+        }
+        // TTL shares a 16-bit word with the protocol
+        let protocol = self.layer.next_level_protocol.0 as u16;
+        let old_word = (self.layer.ttl as u16) << 8 | protocol;
+        self.layer.ttl -= 1;
+        let new_word = (self.layer.ttl as u16) << 8 | protocol;
+        self.layer.checksum = incremental_update(self.layer.checksum, old_word, new_word);
+
+        true
+    }
+
     /// Get the source of the layer.
     pub fn get_src(&self) -> Ipv4Addr {
         self.layer.source
@@ -159,10 +482,66 @@ impl Ipv4 {
     pub fn get_dst(&self) -> Ipv4Addr {
         self.layer.destination
     }
+
+    /// Sets the source of the layer and updates the checksum incrementally. The source of the
+    /// transport layer encapsulated should be set with `set_ipv4_layer` for its checksum.
+    pub fn set_src(&mut self, src: Ipv4Addr) {
+        self.layer.checksum = update_addr_checksum(self.layer.checksum, self.layer.source, src);
+        self.layer.source = src;
+    }
+
+    /// Sets the destination of the layer and updates the checksum incrementally. The destination
+    /// of the transport layer encapsulated should be set with `set_ipv4_layer` for its checksum.
+    pub fn set_dst(&mut self, dst: Ipv4Addr) {
+        self.layer.checksum =
+            update_addr_checksum(self.layer.checksum, self.layer.destination, dst);
+        self.layer.destination = dst;
+    }
+}
+
+/// Updates the given checksum incrementally after an IPv4 address covered by it changes.
+fn update_addr_checksum(checksum: u16, old: Ipv4Addr, new: Ipv4Addr) -> u16 {
+    let (old, new) = (old.octets(), new.octets());
+
+    old.chunks(2)
+        .zip(new.chunks(2))
+        .fold(checksum, |checksum, (old_word, new_word)| {
+            incremental_update(
+                checksum,
+                (old_word[0] as u16) << 8 | old_word[1] as u16,
+                (new_word[0] as u16) << 8 | new_word[1] as u16,
+            )
+        })
+}
+
+/// Layers are compared by their fields, regardless of if their checksums are maintained.
+impl PartialEq for Ipv4 {
+    fn eq(&self, other: &Ipv4) -> bool {
+        self.layer.version == other.layer.version
+            && self.layer.header_length == other.layer.header_length
+            && self.layer.dscp == other.layer.dscp
+            && self.layer.ecn == other.layer.ecn
+            && self.layer.total_length == other.layer.total_length
+            && self.layer.identification == other.layer.identification
+            && self.layer.flags == other.layer.flags
+            && self.layer.fragment_offset == other.layer.fragment_offset
+            && self.layer.ttl == other.layer.ttl
+            && self.layer.next_level_protocol == other.layer.next_level_protocol
+            && self.layer.checksum == other.layer.checksum
+            && self.layer.source == other.layer.source
+            && self.layer.destination == other.layer.destination
+            && self.options == other.options
+            && self.payload == other.payload
+            && self.checksum_offload == other.checksum_offload
+    }
 }
 
 impl Display for Ipv4 {
     fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        if f.alternate() {
+            return fmt_hex_dump(self, f);
+        }
+
         let mut fragment = String::new();
         if self.is_fragment() {
             fragment = format!(", Fragment = {}", self.get_fragment_offset() * 8);
@@ -180,44 +559,55 @@ impl Display for Ipv4 {
     }
 }
 
+impl SizeBounds for Ipv4 {
+    fn min_size() -> usize {
+        Ipv4Packet::minimum_packet_size()
+    }
+
+    fn max_size() -> usize {
+        Ipv4Packet::minimum_packet_size() + MAX_OPTIONS_SIZE
+    }
+}
+
 impl Layer for Ipv4 {
     fn get_type(&self) -> LayerType {
         LayerTypes::Ipv4
     }
 
     fn get_size(&self) -> usize {
-        let mut ipv4_size = Ipv4Packet::packet_size(&self.layer);
-        let mut ipv4_options_size = 0;
-        for option in &self.layer.options {
-            ipv4_size -= 1;
-            ipv4_options_size += Ipv4OptionPacket::packet_size(option);
-        }
-
-        ipv4_size + ipv4_options_size
+        // Pad options to 4 bytes
+        Ipv4Packet::minimum_packet_size() + (self.options.len() + 3) / 4 * 4
     }
 
     fn serialize(&self, buffer: &mut [u8], n: usize) -> io::Result<usize> {
+        let present = buffer.len();
+        if buffer.len() < self.get_size() {
+            return Err(io::Error::new(io::ErrorKind::WriteZero, "buffer too small"));
+        }
         let mut packet = MutableIpv4Packet::new(buffer)
-            .ok_or(io::Error::new(io::ErrorKind::WriteZero, "buffer too small"))?;
+            .ok_or_else(|| io::Error::new(io::ErrorKind::WriteZero, "buffer too small"))?;
 
-        packet.populate(&self.layer);
+        self.populate(&mut packet)?;
 
         // Fix length
         let header_length = self.get_size();
-        if header_length / 4 > u8::MAX as usize {
-            return Err(io::Error::new(io::ErrorKind::Other, "IPv4 too big"));
-        }
-        packet.set_header_length((header_length / 4) as u8);
-        if n > u16::MAX as usize {
-            return Err(io::Error::new(
-                io::ErrorKind::InvalidInput,
-                "length too big",
-            ));
-        }
+        check_length(
+            LayerTypes::Ipv4,
+            n,
+            header_length,
+            present,
+            u16::MAX as usize,
+        )?;
         packet.set_total_length(n as u16);
 
-        // Compute checksum
-        let checksum = ipv4::checksum(&packet.to_immutable());
+        // Compute checksum, the checksum maintained is kept if the length is unchanged
+        let checksum = match self.checksum_offload {
+            true => 0,
+            false => match self.is_checksum_maintained && n as u16 == self.layer.total_length {
+                true => self.layer.checksum,
+                false => util::checksum(&packet.packet()[..header_length], CHECKSUM_OFFSET),
+            },
+        };
         packet.set_checksum(checksum);
 
         Ok(header_length)
@@ -226,4 +616,396 @@ impl Layer for Ipv4 {
     fn serialize_with_payload(&self, buffer: &mut [u8], _: &[u8], n: usize) -> io::Result<usize> {
         self.serialize(buffer, n)
     }
+
+    fn clone_boxed(&self) -> Box<dyn Layer> {
+        Box::new(self.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn deserialize_serialized() {
+        let ipv4 = Ipv4::new(
+            1,
+            LayerTypes::Tcp,
+            Ipv4Addr::new(192, 168, 1, 1),
+            Ipv4Addr::new(192, 168, 1, 2),
+        )
+        .unwrap();
+        let mut buffer = vec![0u8; ipv4.get_size()];
+        let n = ipv4.serialize(&mut buffer, ipv4.get_size()).unwrap();
+
+        // The checksum is only known once serialized
+        let (deserialized, size) = Ipv4::deserialize(&buffer).unwrap();
+        assert_eq!(size, n);
+        let mut reserialized = vec![0u8; deserialized.get_size()];
+        deserialized
+            .serialize(&mut reserialized, deserialized.get_size())
+            .unwrap();
+        assert_eq!(reserialized, buffer);
+        assert_eq!(Ipv4::deserialize(&reserialized).unwrap().0, deserialized);
+    }
+
+    #[test]
+    fn deserialize_checksum_mismatch() {
+        let ipv4 = Ipv4::new(
+            1,
+            LayerTypes::Tcp,
+            Ipv4Addr::new(192, 168, 1, 1),
+            Ipv4Addr::new(192, 168, 1, 2),
+        )
+        .unwrap();
+        let mut buffer = vec![0u8; ipv4.get_size()];
+        ipv4.serialize(&mut buffer, ipv4.get_size()).unwrap();
+        buffer[CHECKSUM_OFFSET] ^= 0xff;
+
+        assert!(matches!(
+            Ipv4::deserialize(&buffer),
+            Err(ParseError::ChecksumMismatch(LayerTypes::Ipv4))
+        ));
+    }
+
+    #[test]
+    fn fragment() {
+        let ipv4 = Ipv4::new(
+            1,
+            LayerTypes::Udp,
+            Ipv4Addr::new(192, 168, 1, 1),
+            Ipv4Addr::new(192, 168, 1, 2),
+        )
+        .unwrap();
+        let frags = ipv4.fragment(&[0u8; 3000], 1500);
+
+        assert_eq!(frags.len(), 3);
+        let lengths: Vec<_> = frags.iter().map(|frag| frag.get_total_length()).collect();
+        assert_eq!(lengths, [1500, 1500, 60]);
+        let offsets: Vec<_> = frags
+            .iter()
+            .map(|frag| frag.get_fragment_offset())
+            .collect();
+        assert_eq!(offsets, [0, 185, 370]);
+        let mores: Vec<_> = frags.iter().map(|frag| frag.is_more_fragment()).collect();
+        assert_eq!(mores, [true, true, false]);
+    }
+
+    #[test]
+    fn checksum_captured() {
+        // The header of a captured UDP datagram from 192.168.0.1 to 192.168.0.199
+        #[rustfmt::skip]
+        let buffer = [
+            0x45, 0x00, 0x00, 0x73, 0x00, 0x00, 0x40, 0x00, 0x40, 0x11,
+            0xb8, 0x61, 0xc0, 0xa8, 0x00, 0x01, 0xc0, 0xa8, 0x00, 0xc7,
+        ];
+
+        let (ipv4, _) = Ipv4::deserialize_header(&buffer).unwrap();
+        assert_eq!(ipv4.get_checksum(), 0xb861);
+        assert_eq!(ipv4.checksum().unwrap(), 0xb861);
+    }
+
+    #[test]
+    fn checksum_with_options() {
+        let mut ipv4 = Ipv4::new(
+            1,
+            LayerTypes::Udp,
+            Ipv4Addr::new(192, 168, 1, 1),
+            Ipv4Addr::new(192, 168, 1, 2),
+        )
+        .unwrap();
+        // An odd length of options is padded
+        ipv4.set_options(vec![0x01, 0x01, 0x01]);
+        let mut buffer = vec![0u8; ipv4.get_size()];
+        ipv4.serialize(&mut buffer, ipv4.get_size()).unwrap();
+
+        assert_eq!(buffer.len(), 24);
+        let (deserialized, _) = Ipv4::deserialize(&buffer).unwrap();
+        assert_eq!(
+            deserialized.checksum().unwrap(),
+            deserialized.get_checksum()
+        );
+    }
+
+    #[test]
+    fn serialize_record_route() {
+        let mut ipv4 = Ipv4::new(
+            1,
+            LayerTypes::Udp,
+            Ipv4Addr::new(192, 168, 1, 1),
+            Ipv4Addr::new(192, 168, 1, 2),
+        )
+        .unwrap();
+        // A record route option without any slot, padded with the end of options
+        ipv4.set_options(vec![0x07, 0x03, 0x04, 0x00]);
+        let mut buffer = vec![0u8; ipv4.get_size()];
+        ipv4.serialize(&mut buffer, ipv4.get_size()).unwrap();
+
+        assert_eq!(buffer.len(), 24);
+        assert_eq!(buffer[0] & 0x0f, 6);
+        let (deserialized, size) = Ipv4::deserialize(&buffer).unwrap();
+        assert_eq!(size, 24);
+        assert_eq!(deserialized.get_options(), [0x07, 0x03, 0x04, 0x00]);
+    }
+
+    #[test]
+    fn checksum_options_too_long() {
+        let mut ipv4 = Ipv4::new(
+            1,
+            LayerTypes::Udp,
+            Ipv4Addr::new(192, 168, 1, 1),
+            Ipv4Addr::new(192, 168, 1, 2),
+        )
+        .unwrap();
+        ipv4.set_options(vec![0x01; MAX_OPTIONS_SIZE + 4]);
+
+        assert!(ipv4.checksum().is_err());
+        assert!(!ipv4.validate_checksum());
+    }
+
+    #[test]
+    fn decrement_ttl() {
+        let ipv4 = Ipv4::new(
+            1,
+            LayerTypes::Udp,
+            Ipv4Addr::new(192, 168, 1, 1),
+            Ipv4Addr::new(192, 168, 1, 2),
+        )
+        .unwrap();
+        let mut buffer = vec![0u8; ipv4.get_size()];
+        ipv4.serialize(&mut buffer, ipv4.get_size()).unwrap();
+        let (mut ipv4, _) = Ipv4::deserialize(&buffer).unwrap();
+        let checksum = ipv4.get_checksum();
+
+        assert!(ipv4.decrement_ttl());
+        assert_eq!(ipv4.get_ttl(), 127);
+        assert_ne!(ipv4.get_checksum(), checksum);
+        assert!(ipv4.validate_checksum());
+    }
+
+    #[test]
+    fn decrement_ttl_expired() {
+        let mut ipv4 = Ipv4::new(
+            1,
+            LayerTypes::Udp,
+            Ipv4Addr::new(192, 168, 1, 1),
+            Ipv4Addr::new(192, 168, 1, 2),
+        )
+        .unwrap();
+        ipv4.set_ttl(1);
+        let checksum = ipv4.get_checksum();
+
+        assert!(!ipv4.decrement_ttl());
+        assert_eq!(ipv4.get_ttl(), 1);
+        assert_eq!(ipv4.get_checksum(), checksum);
+    }
+
+    #[test]
+    fn serialize_maintained_checksum() {
+        let ipv4 = Ipv4::new(
+            1,
+            LayerTypes::Udp,
+            Ipv4Addr::new(192, 168, 1, 1),
+            Ipv4Addr::new(192, 168, 1, 2),
+        )
+        .unwrap();
+        let mut buffer = vec![0u8; ipv4.get_size()];
+        ipv4.serialize(&mut buffer, ipv4.get_size()).unwrap();
+        let (mut ipv4, _) = Ipv4::deserialize(&buffer).unwrap();
+        ipv4.decrement_ttl();
+        ipv4.set_next_level_protocol(IpNextHeaderProtocols::Tcp);
+
+        // The checksum kept in serialization matches the full recomputation
+        ipv4.serialize(&mut buffer, ipv4.get_size()).unwrap();
+        assert_eq!(
+            u16::from_be_bytes([buffer[10], buffer[11]]),
+            util::checksum(&buffer, CHECKSUM_OFFSET)
+        );
+        assert_eq!(ipv4.get_checksum(), ipv4.checksum().unwrap());
+    }
+
+    #[test]
+    fn serialize_invalid_length() {
+        use crate::packet::layer::tests::unwrap_serialize_error;
+        use crate::packet::layer::SerializeError;
+
+        let ipv4 = Ipv4::new(
+            1,
+            LayerTypes::Udp,
+            Ipv4Addr::new(192, 168, 1, 1),
+            Ipv4Addr::new(192, 168, 1, 2),
+        )
+        .unwrap();
+        let mut buffer = vec![0u8; 70000];
+        ipv4.serialize(&mut buffer[..28], 28).unwrap();
+
+        // Shorter than the header, longer than the bytes present, and longer than the total
+        // length field
+        for (present, n) in [(28, 19), (28, 29), (70000, 65536)] {
+            let e = ipv4.serialize(&mut buffer[..present], n).unwrap_err();
+            assert_eq!(
+                unwrap_serialize_error(e),
+                SerializeError::InvalidLength(LayerTypes::Ipv4, n)
+            );
+        }
+    }
+
+    #[test]
+    fn dscp_and_ecn() {
+        let mut ipv4 = Ipv4::new(
+            1,
+            LayerTypes::Udp,
+            Ipv4Addr::new(192, 168, 1, 1),
+            Ipv4Addr::new(192, 168, 1, 2),
+        )
+        .unwrap();
+        ipv4.set_ecn(ECN_CE);
+        // Expedited forwarding
+        ipv4.set_dscp(46);
+        assert_eq!(ipv4.get_dscp(), 46);
+        assert!(ipv4.is_congestion_experienced());
+
+        // Setting the DSCP leaves the ECN bits untouched and vice versa
+        ipv4.set_dscp(0xff);
+        assert_eq!(ipv4.get_dscp(), MAXIMUM_DSCP);
+        assert_eq!(ipv4.get_ecn(), ECN_CE);
+        ipv4.set_ecn(0x05);
+        assert_eq!(ipv4.get_ecn(), 0x01);
+        assert_eq!(ipv4.get_dscp(), MAXIMUM_DSCP);
+        assert!(!ipv4.is_congestion_experienced());
+
+        ipv4.set_dscp(46);
+        ipv4.set_ecn(ECN_CE);
+        let mut buffer = vec![0u8; ipv4.get_size()];
+        let n = ipv4.get_size();
+        ipv4.serialize(&mut buffer, n).unwrap();
+        assert_eq!(buffer[1], 46 << 2 | ECN_CE);
+
+        let (ipv4, _) = Ipv4::deserialize(&buffer).unwrap();
+        assert_eq!(ipv4.get_dscp(), 46);
+        assert!(ipv4.is_congestion_experienced());
+    }
+
+    #[test]
+    fn set_addrs_and_protocol() {
+        let mut ipv4 = Ipv4::new(
+            1,
+            LayerTypes::Tcp,
+            Ipv4Addr::new(192, 168, 1, 1),
+            Ipv4Addr::new(192, 168, 1, 2),
+        )
+        .unwrap();
+        ipv4.set_src(Ipv4Addr::new(10, 0, 0, 1));
+        ipv4.set_dst(Ipv4Addr::new(10, 0, 0, 2));
+        ipv4.set_next_level_protocol(IpNextHeaderProtocols::Udp);
+        let mut buffer = vec![0u8; ipv4.get_size()];
+        let n = ipv4.get_size();
+        ipv4.serialize(&mut buffer, n).unwrap();
+
+        // Same as the layer created with the fields
+        let expected = Ipv4::new(
+            1,
+            LayerTypes::Udp,
+            Ipv4Addr::new(10, 0, 0, 1),
+            Ipv4Addr::new(10, 0, 0, 2),
+        )
+        .unwrap();
+        let mut expected_buffer = vec![0u8; n];
+        expected.serialize(&mut expected_buffer, n).unwrap();
+        assert_eq!(buffer, expected_buffer);
+
+        let (ipv4, _) = Ipv4::deserialize(&buffer).unwrap();
+        assert_eq!(ipv4.get_src(), Ipv4Addr::new(10, 0, 0, 1));
+        assert_eq!(ipv4.get_dst(), Ipv4Addr::new(10, 0, 0, 2));
+        assert_eq!(ipv4.get_next_level_protocol(), IpNextHeaderProtocols::Udp);
+    }
+
+    #[test]
+    fn size_bounds() {
+        assert_eq!(Ipv4::min_size(), 20);
+        assert_eq!(Ipv4::max_size(), 60);
+    }
+
+    #[test]
+    fn deserialize_shorter_than_min_size() {
+        let buffer = [0u8; 60];
+        for n in 0..Ipv4::min_size() {
+            assert_eq!(
+                Ipv4::deserialize(&buffer[..n]).unwrap_err(),
+                ParseError::Truncated(LayerTypes::Ipv4)
+            );
+        }
+    }
+
+    #[test]
+    fn serialize_checksum_offload() {
+        let mut ipv4 = Ipv4::new(
+            1,
+            LayerTypes::Udp,
+            Ipv4Addr::new(10, 6, 0, 1),
+            Ipv4Addr::new(93, 184, 216, 34),
+        )
+        .unwrap();
+        let mut buffer = vec![0u8; ipv4.get_size()];
+        ipv4.serialize(&mut buffer, 20).unwrap();
+        assert_ne!(buffer[10..12], [0, 0]);
+
+        ipv4.set_checksum_offload(true);
+        ipv4.serialize(&mut buffer, 20).unwrap();
+        assert_eq!(buffer[10..12], [0, 0]);
+    }
+
+    /// Builds an `Ipv4` deserialized from the serialized one, whose checksum is known.
+    fn build_deserialized() -> Ipv4 {
+        let ipv4 = Ipv4::new(
+            1,
+            LayerTypes::Udp,
+            Ipv4Addr::new(192, 168, 1, 1),
+            Ipv4Addr::new(192, 168, 1, 2),
+        )
+        .unwrap();
+        let mut buffer = vec![0u8; ipv4.get_size()];
+        ipv4.serialize(&mut buffer, ipv4.get_size()).unwrap();
+
+        Ipv4::deserialize(&buffer).unwrap().0
+    }
+
+    #[test]
+    fn set_ttl_checksum() {
+        let mut ipv4 = build_deserialized();
+        ipv4.set_ttl(64);
+
+        // The checksum updated incrementally matches the full recomputation
+        assert_eq!(ipv4.get_ttl(), 64);
+        assert!(ipv4.validate_checksum());
+        assert_eq!(ipv4.get_checksum(), ipv4.checksum().unwrap());
+    }
+
+    #[test]
+    fn apply_ttl_mode() {
+        let mut ipv4 = build_deserialized();
+        let checksum = ipv4.get_checksum();
+        ipv4.apply_ttl_mode(TtlMode::Initial);
+        assert_eq!(ipv4.get_ttl(), 128);
+        assert_eq!(ipv4.get_checksum(), checksum);
+
+        ipv4.apply_ttl_mode(TtlMode::Decrement);
+        assert_eq!(ipv4.get_ttl(), 127);
+        assert!(ipv4.validate_checksum());
+
+        ipv4.apply_ttl_mode(TtlMode::Fixed(1));
+        assert_eq!(ipv4.get_ttl(), 1);
+        assert!(ipv4.validate_checksum());
+        // The time to live is not decremented to expire
+        ipv4.apply_ttl_mode(TtlMode::Decrement);
+        assert_eq!(ipv4.get_ttl(), 1);
+    }
+
+    #[test]
+    fn ttl_mode_display() {
+        assert_eq!(TtlMode::default(), TtlMode::Initial);
+        assert_eq!(TtlMode::Initial.to_string(), "initial");
+        assert_eq!(TtlMode::Decrement.to_string(), "decrement");
+        assert_eq!(TtlMode::Fixed(64).to_string(), "64");
+    }
 }
@@ -0,0 +1,509 @@
+use super::{check_length, fmt_hex_dump, Layer, LayerType, LayerTypes, ParseError, SizeBounds};
+use pnet::packet::ip::{IpNextHeaderProtocol, IpNextHeaderProtocols};
+use pnet::packet::ipv6::{self, Ipv6Packet, MutableIpv6Packet};
+use pnet::util::MacAddr;
+use std::clone::Clone;
+use std::cmp::min;
+use std::fmt::{self, Display, Formatter};
+use std::io;
+use std::net::Ipv6Addr;
+
+/// Represents the max number of extension headers in an IPv6 packet.
+const MAX_EXTENSION_HEADERS: usize = 8;
+/// Represents the size of the Fragment extension header.
+pub const FRAGMENT_HEADER_SIZE: usize = 8;
+/// Represents the position of the next header field in the IPv6 header.
+const NEXT_HEADER_POSITION: usize = 6;
+
+/// Returns the link-local address of the modified EUI-64 interface identifier of the given
+/// hardware address (RFC 4291).
+pub fn link_local_addr(hardware_addr: MacAddr) -> Ipv6Addr {
+    let MacAddr(a, b, c, d, e, f) = hardware_addr;
+
+    Ipv6Addr::from([
+        0xfe,
+        0x80,
+        0,
+        0,
+        0,
+        0,
+        0,
+        0,
+        a ^ 0x02,
+        b,
+        c,
+        0xff,
+        0xfe,
+        d,
+        e,
+        f,
+    ])
+}
+
+/// Get the length of the extension header at the given offset of the byte-array. Returns `None`
+/// if the header is an upper-layer protocol.
+fn get_extension_header_length(
+    next_header: IpNextHeaderProtocol,
+    buffer: &[u8],
+    offset: usize,
+) -> Result<Option<usize>, ParseError> {
+    let length = match next_header {
+        IpNextHeaderProtocols::Hopopt
+        | IpNextHeaderProtocols::Ipv6Route
+        | IpNextHeaderProtocols::Ipv6Opts => {
+            let b = buffer
+                .get(offset + 1)
+                .ok_or(ParseError::Truncated(LayerTypes::Ipv6))?;
+            (*b as usize + 1) * 8
+        }
+        IpNextHeaderProtocols::Ipv6Frag => FRAGMENT_HEADER_SIZE,
+        IpNextHeaderProtocols::Ah => {
+            let b = buffer
+                .get(offset + 1)
+                .ok_or(ParseError::Truncated(LayerTypes::Ipv6))?;
+            (*b as usize + 2) * 4
+        }
+        IpNextHeaderProtocols::Tcp
+        | IpNextHeaderProtocols::Udp
+        | IpNextHeaderProtocols::Icmpv6
+        | IpNextHeaderProtocols::Ipv6NoNxt => return Ok(None),
+        _ => {
+            return Err(ParseError::InvalidValue(
+                LayerTypes::Ipv6,
+                "extension header",
+            ))
+        }
+    };
+    if buffer.len() < offset + length {
+        return Err(ParseError::Truncated(LayerTypes::Ipv6));
+    }
+
+    Ok(Some(length))
+}
+
+/// Walks the chain of IPv6 extension headers in the given byte-array, which starts right after
+/// the fixed header, and returns the upper-layer protocol with its offset in the byte-array.
+/// Unknown extension headers, or a chain too long, are rejected.
+pub fn walk_extension_headers(
+    next_header: IpNextHeaderProtocol,
+    buffer: &[u8],
+) -> Result<(IpNextHeaderProtocol, usize), ParseError> {
+    let mut next_header = next_header;
+    let mut offset = 0;
+    for _ in 0..MAX_EXTENSION_HEADERS {
+        match get_extension_header_length(next_header, buffer, offset)? {
+            Some(length) => {
+                next_header = IpNextHeaderProtocol(buffer[offset]);
+                offset += length;
+            }
+            None => return Ok((next_header, offset)),
+        }
+    }
+
+    Err(ParseError::InvalidValue(
+        LayerTypes::Ipv6,
+        "extension header chain",
+    ))
+}
+
+/// Finds the Fragment extension header in the given IPv6 packet, and returns the position of the
+/// next header field which points to the Fragment header, with the offset of the Fragment header.
+/// Returns `None` if the packet is not a fragment.
+pub fn find_fragment_header(buffer: &[u8]) -> Result<Option<(usize, usize)>, ParseError> {
+    let packet = Ipv6Packet::new(buffer).ok_or(ParseError::Truncated(LayerTypes::Ipv6))?;
+    let mut next_header = packet.get_next_header();
+    let mut position = NEXT_HEADER_POSITION;
+    let mut offset = Ipv6Packet::minimum_packet_size();
+    for _ in 0..MAX_EXTENSION_HEADERS {
+        if next_header == IpNextHeaderProtocols::Ipv6Frag {
+            if buffer.len() < offset + FRAGMENT_HEADER_SIZE {
+                return Err(ParseError::Truncated(LayerTypes::Ipv6));
+            }
+            return Ok(Some((position, offset)));
+        }
+        match get_extension_header_length(next_header, buffer, offset)? {
+            Some(length) => {
+                next_header = IpNextHeaderProtocol(buffer[offset]);
+                position = offset;
+                offset += length;
+            }
+            None => return Ok(None),
+        }
+    }
+
+    Err(ParseError::InvalidValue(
+        LayerTypes::Ipv6,
+        "extension header chain",
+    ))
+}
+
+/// Represents an IPv6 layer.
+#[derive(Clone, Debug)]
+pub struct Ipv6 {
+    layer: ipv6::Ipv6,
+    transport: IpNextHeaderProtocol,
+    extensions_length: usize,
+    payload: Vec<u8>,
+}
+
+impl Ipv6 {
+    /// Creates an `Ipv6`.
+    pub fn new(t: LayerType, src: Ipv6Addr, dst: Ipv6Addr) -> Option<Ipv6> {
+        let next_header = match t {
+            LayerTypes::Tcp => IpNextHeaderProtocols::Tcp,
+            LayerTypes::Udp => IpNextHeaderProtocols::Udp,
+            LayerTypes::Icmpv6 => IpNextHeaderProtocols::Icmpv6,
+            _ => return None,
+        };
+        let d_ipv6 = ipv6::Ipv6 {
+            version: 6,
+            traffic_class: 0,
+            flow_label: 0,
+            payload_length: 0,
+            next_header,
+            hop_limit: 128,
+            source: src,
+            destination: dst,
+            payload: vec![],
+        };
+        Some(Ipv6::from(d_ipv6))
+    }
+
+    /// Creates an `Ipv6` according to the given `Ipv6`.
+    pub fn from(ipv6: ipv6::Ipv6) -> Ipv6 {
+        let transport = ipv6.next_header;
+        Ipv6 {
+            layer: ipv6,
+            transport,
+            extensions_length: 0,
+            payload: vec![],
+        }
+    }
+
+    /// Creates an `Ipv6` according to the given IPv6 packet.
+    pub fn parse(packet: &Ipv6Packet) -> Ipv6 {
+        let d_ipv6 = ipv6::Ipv6 {
+            version: packet.get_version(),
+            traffic_class: packet.get_traffic_class(),
+            flow_label: packet.get_flow_label(),
+            payload_length: packet.get_payload_length(),
+            next_header: packet.get_next_header(),
+            hop_limit: packet.get_hop_limit(),
+            source: packet.get_source(),
+            destination: packet.get_destination(),
+            payload: vec![],
+        };
+        Ipv6::from(d_ipv6)
+    }
+
+    /// Deserializes an `Ipv6` from the given byte-array and returns it with the number of bytes
+    /// consumed. Extension headers are skipped and counted in the bytes consumed.
+    pub fn deserialize(buffer: &[u8]) -> Result<(Ipv6, usize), ParseError> {
+        let (mut ipv6, n) = Ipv6::deserialize_header(buffer)?;
+        let header_length = Ipv6Packet::minimum_packet_size();
+        let end = min(
+            header_length + ipv6.get_payload_length() as usize,
+            buffer.len(),
+        );
+        ipv6.payload = buffer[min(n, end)..end].to_vec();
+
+        Ok((ipv6, n))
+    }
+
+    /// Deserializes an `Ipv6` from the given byte-array without copying its payload, and returns
+    /// it with the number of bytes consumed, including the extension headers. The payload of the
+    /// layer is left empty.
+    pub fn deserialize_header(buffer: &[u8]) -> Result<(Ipv6, usize), ParseError> {
+        if buffer.len() < Ipv6::min_size() {
+            return Err(ParseError::Truncated(LayerTypes::Ipv6));
+        }
+        let packet = Ipv6Packet::new(buffer).ok_or(ParseError::Truncated(LayerTypes::Ipv6))?;
+        if packet.get_version() != 6 {
+            return Err(ParseError::InvalidValue(LayerTypes::Ipv6, "version"));
+        }
+        let header_length = Ipv6Packet::minimum_packet_size();
+        let (transport, extensions_length) =
+            walk_extension_headers(packet.get_next_header(), &buffer[header_length..])?;
+
+        let mut ipv6 = Ipv6::parse(&packet);
+        ipv6.transport = transport;
+        ipv6.extensions_length = extensions_length;
+
+        Ok((ipv6, header_length + extensions_length))
+    }
+
+    /// Get the upper-layer protocol after the extension headers of the layer.
+    pub fn get_transport_protocol(&self) -> IpNextHeaderProtocol {
+        self.transport
+    }
+
+    /// Get the total length of the extension headers of the layer. Extension headers are not
+    /// serialized.
+    pub fn get_extensions_length(&self) -> usize {
+        self.extensions_length
+    }
+
+    /// Get the payload of the layer, which is the bytes after the extension headers when the
+    /// layer is deserialized.
+    pub fn get_payload(&self) -> &[u8] {
+        &self.payload
+    }
+
+    /// Get the payload length of the layer.
+    pub fn get_payload_length(&self) -> u16 {
+        self.layer.payload_length
+    }
+
+    /// Get the next header of the layer.
+    pub fn get_next_header(&self) -> IpNextHeaderProtocol {
+        self.layer.next_header
+    }
+
+    /// Get the hop limit of the layer.
+    pub fn get_hop_limit(&self) -> u8 {
+        self.layer.hop_limit
+    }
+
+    /// Sets the hop limit of the layer.
+    pub fn set_hop_limit(&mut self, hop_limit: u8) {
+        self.layer.hop_limit = hop_limit;
+    }
+
+    /// Decrements the hop limit of the layer, as a router does when forwarding the packet.
+    /// Returns `false` and leaves the layer unchanged if the hop limit is exceeded, in which case
+    /// the packet should be dropped.
+    pub fn decrement_hop_limit(&mut self) -> bool {
+        if self.layer.hop_limit <= 1 {
+            return false;
+        }
+        self.layer.hop_limit -= 1;
+
+        true
+    }
+
+    /// Get the source of the layer.
+    pub fn get_src(&self) -> Ipv6Addr {
+        self.layer.source
+    }
+
+    /// Get the destination of the layer.
+    pub fn get_dst(&self) -> Ipv6Addr {
+        self.layer.destination
+    }
+}
+
+impl Display for Ipv6 {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        if f.alternate() {
+            return fmt_hex_dump(self, f);
+        }
+
+        write!(
+            f,
+            "{}: {} -> {}, Length = {}",
+            LayerTypes::Ipv6,
+            self.layer.source,
+            self.layer.destination,
+            self.layer.payload_length
+        )
+    }
+}
+
+impl SizeBounds for Ipv6 {
+    fn min_size() -> usize {
+        Ipv6Packet::minimum_packet_size()
+    }
+
+    fn max_size() -> usize {
+        Ipv6Packet::minimum_packet_size()
+    }
+}
+
+impl Layer for Ipv6 {
+    fn get_type(&self) -> LayerType {
+        LayerTypes::Ipv6
+    }
+
+    fn get_size(&self) -> usize {
+        Ipv6Packet::packet_size(&self.layer)
+    }
+
+    fn serialize(&self, buffer: &mut [u8], n: usize) -> io::Result<usize> {
+        let present = buffer.len();
+        let mut packet = MutableIpv6Packet::new(buffer)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::WriteZero, "buffer too small"))?;
+
+        packet.populate(&self.layer);
+
+        // Fix length
+        let header_length = self.get_size();
+        check_length(
+            LayerTypes::Ipv6,
+            n,
+            header_length,
+            present,
+            header_length + u16::MAX as usize,
+        )?;
+        packet.set_payload_length((n - header_length) as u16);
+
+        Ok(header_length)
+    }
+
+    fn serialize_with_payload(&self, buffer: &mut [u8], _: &[u8], n: usize) -> io::Result<usize> {
+        self.serialize(buffer, n)
+    }
+
+    fn clone_boxed(&self) -> Box<dyn Layer> {
+        Box::new(self.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn serialize_header() {
+        let ipv6 = Ipv6::new(
+            LayerTypes::Tcp,
+            "2001:db8::1".parse().unwrap(),
+            "2001:db8::2".parse().unwrap(),
+        )
+        .unwrap();
+        let mut buffer = vec![0u8; ipv6.get_size() + 20];
+        let n = ipv6.serialize(&mut buffer, ipv6.get_size() + 20).unwrap();
+
+        #[rustfmt::skip]
+        let expected = [
+            0x60, 0x00, 0x00, 0x00, // Version, traffic class and flow label
+            0x00, 0x14, 0x06, 0x80, // Payload length, next header and hop limit
+            0x20, 0x01, 0x0d, 0xb8, 0x00, 0x00, 0x00, 0x00,
+            0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x01, // Source
+            0x20, 0x01, 0x0d, 0xb8, 0x00, 0x00, 0x00, 0x00,
+            0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x02, // Destination
+        ];
+        assert_eq!(n, 40);
+        assert_eq!(buffer[..n], expected);
+    }
+
+    /// Builds an IPv6 header followed by the given extension headers and a 20-byte payload.
+    fn build_with_extensions(next_header: IpNextHeaderProtocol, extensions: &[u8]) -> Vec<u8> {
+        let ipv6 = Ipv6::new(
+            LayerTypes::Tcp,
+            "2001:db8::1".parse().unwrap(),
+            "2001:db8::2".parse().unwrap(),
+        )
+        .unwrap();
+        let mut buffer = vec![0u8; ipv6.get_size() + 20];
+        ipv6.serialize(&mut buffer, ipv6.get_size() + 20).unwrap();
+        buffer[6] = next_header.0;
+        buffer.splice(40..40, extensions.iter().cloned());
+        let payload_length = (extensions.len() + 20) as u16;
+        buffer[4..6].copy_from_slice(&payload_length.to_be_bytes());
+
+        buffer
+    }
+
+    #[test]
+    fn deserialize_hop_by_hop() {
+        // A Hop-by-Hop header of a PadN option followed by TCP
+        let buffer = build_with_extensions(
+            IpNextHeaderProtocols::Hopopt,
+            &[0x06, 0x00, 0x01, 0x04, 0x00, 0x00, 0x00, 0x00],
+        );
+
+        let (ipv6, size) = Ipv6::deserialize(&buffer).unwrap();
+        assert_eq!(size, 48);
+        assert_eq!(ipv6.get_transport_protocol(), IpNextHeaderProtocols::Tcp);
+        assert_eq!(ipv6.get_extensions_length(), 8);
+    }
+
+    #[test]
+    fn walk_extension_headers_chain() {
+        // A Hop-by-Hop header, a 16-byte destination options header and a fragment header
+        let mut extensions = vec![0x3c, 0x00, 0x01, 0x04, 0x00, 0x00, 0x00, 0x00];
+        extensions.extend_from_slice(&[0x2c, 0x01, 0x01, 0x0c]);
+        extensions.extend_from_slice(&[0x00; 12]);
+        extensions.extend_from_slice(&[0x11, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x01]);
+
+        assert_eq!(
+            walk_extension_headers(IpNextHeaderProtocols::Hopopt, &extensions).unwrap(),
+            (IpNextHeaderProtocols::Udp, 32)
+        );
+    }
+
+    #[test]
+    fn walk_extension_headers_unknown() {
+        assert!(matches!(
+            walk_extension_headers(IpNextHeaderProtocol(253), &[0x06; 8]),
+            Err(ParseError::InvalidValue(LayerTypes::Ipv6, _))
+        ));
+    }
+
+    #[test]
+    fn walk_extension_headers_too_long() {
+        // Every Hop-by-Hop header is followed by another one
+        let extensions = [0x00; 8 * (MAX_EXTENSION_HEADERS + 1)];
+
+        assert!(matches!(
+            walk_extension_headers(IpNextHeaderProtocols::Hopopt, &extensions),
+            Err(ParseError::InvalidValue(LayerTypes::Ipv6, _))
+        ));
+    }
+
+    #[test]
+    fn walk_extension_headers_truncated() {
+        assert!(matches!(
+            walk_extension_headers(IpNextHeaderProtocols::Hopopt, &[0x06, 0x01, 0x00, 0x00]),
+            Err(ParseError::Truncated(LayerTypes::Ipv6))
+        ));
+    }
+
+    #[test]
+    fn decrement_hop_limit() {
+        let mut ipv6 = Ipv6::new(
+            LayerTypes::Udp,
+            "2001:db8::1".parse().unwrap(),
+            "2001:db8::2".parse().unwrap(),
+        )
+        .unwrap();
+        ipv6.set_hop_limit(2);
+
+        assert!(ipv6.decrement_hop_limit());
+        assert_eq!(ipv6.get_hop_limit(), 1);
+        assert!(!ipv6.decrement_hop_limit());
+        assert_eq!(ipv6.get_hop_limit(), 1);
+    }
+
+    #[test]
+    fn link_local_addr_eui64() {
+        let ip_addr = link_local_addr(MacAddr::new(0x02, 0, 0, 0, 0, 0x02));
+        assert_eq!(ip_addr, "fe80::ff:fe00:2".parse::<Ipv6Addr>().unwrap());
+    }
+
+    #[test]
+    fn serialize_invalid_length() {
+        use crate::packet::layer::tests::unwrap_serialize_error;
+        use crate::packet::layer::SerializeError;
+
+        let ipv6 = Ipv6::new(
+            LayerTypes::Udp,
+            "2001:db8::1".parse().unwrap(),
+            "2001:db8::2".parse().unwrap(),
+        )
+        .unwrap();
+        let mut buffer = vec![0u8; 70000];
+        ipv6.serialize(&mut buffer[..48], 48).unwrap();
+        assert_eq!(buffer[4..6], [0, 8]);
+
+        // The payload length field excludes the header
+        ipv6.serialize(&mut buffer[..], 40 + 65535).unwrap();
+        for (present, n) in [(48, 39), (48, 49), (70000, 40 + 65536)] {
+            let e = ipv6.serialize(&mut buffer[..present], n).unwrap_err();
+            assert_eq!(
+                unwrap_serialize_error(e),
+                SerializeError::InvalidLength(LayerTypes::Ipv6, n)
+            );
+        }
+    }
+}
@@ -1,14 +1,74 @@
 use std::clone::Clone;
-use std::cmp::{Eq, PartialEq};
+use std::cmp::{min, Eq, PartialEq};
+use std::error::Error;
 use std::fmt::{self, Display, Formatter};
 use std::hash::Hash;
-use std::io;
+use std::io::{self, Write};
+use std::net::IpAddr;
 
 pub mod arp;
 pub mod ethernet;
+pub mod gre;
+pub mod icmp;
+pub mod icmpv6;
+pub mod igmp;
 pub mod ipv4;
+pub mod ipv6;
+pub mod sll;
 pub mod tcp;
 pub mod udp;
+pub mod unknown;
+
+/// Updates the given checksum incrementally after a 16-bit word covered by the checksum changes
+/// from `old_word` to `new_word` (RFC 1624). This is much cheaper than recomputing the checksum
+/// over the whole header. Words at an odd offset should be passed with their bytes swapped.
+pub fn incremental_update(old_checksum: u16, old_word: u16, new_word: u16) -> u16 {
+    // HC' = ~(~HC + ~m + m')
+    let mut sum = !old_checksum as u32 + !old_word as u32 + new_word as u32;
+    while sum >> 16 != 0 {
+        sum = (sum & 0xFFFF) + (sum >> 16);
+    }
+
+    !(sum as u16)
+}
+
+/// Represents the number of bytes in each line of a hex dump.
+const HEX_DUMP_LINE_SIZE: usize = 16;
+
+/// Writes the given layer as an offset-annotated hex and ASCII dump of its serialized bytes. It
+/// is used as the alternate form (`{:#}`) of `Display` of layers.
+pub fn fmt_hex_dump<L: Layer>(layer: &L, f: &mut Formatter) -> fmt::Result {
+    let size = layer.get_size();
+    let mut buffer = vec![0u8; size];
+    if let Err(ref e) = layer.serialize(&mut buffer, size) {
+        return write!(f, "{}: {}", layer.get_type(), e);
+    }
+
+    write!(f, "{}: {} Bytes", layer.get_type(), size)?;
+    for (i, line) in buffer.chunks(HEX_DUMP_LINE_SIZE).enumerate() {
+        write!(f, "\n{:04x} ", i * HEX_DUMP_LINE_SIZE)?;
+        for j in 0..HEX_DUMP_LINE_SIZE {
+            // Separate the line in halves
+            if j == HEX_DUMP_LINE_SIZE / 2 {
+                write!(f, " ")?;
+            }
+            match line.get(j) {
+                Some(b) => write!(f, " {:02x}", b)?,
+                None => write!(f, "   ")?,
+            }
+        }
+        let ascii: String = line
+            .iter()
+            .map(|b| match b.is_ascii_graphic() || *b == b' ' {
+                true => *b as char,
+                false => '.',
+            })
+            .collect();
+        write!(f, "  |{}|", ascii)?;
+    }
+
+    Ok(())
+}
 
 /// Represents the type of the layer.
 #[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
@@ -23,8 +83,15 @@ impl Display for LayerType {
                 LayerTypes::Ethernet => "Ethernet",
                 LayerTypes::Arp => "ARP",
                 LayerTypes::Ipv4 => "IPv4",
+                LayerTypes::Ipv6 => "IPv6",
+                LayerTypes::Icmp => "ICMP",
+                LayerTypes::Icmpv6 => "ICMPv6",
+                LayerTypes::Gre => "GRE",
                 LayerTypes::Tcp => "TCP",
                 LayerTypes::Udp => "UDP",
+                LayerTypes::Unknown => "Unknown",
+                LayerTypes::Sll => "SLL",
+                LayerTypes::Igmp => "IGMP",
                 _ => "unknown",
             }
         )
@@ -46,6 +113,109 @@ pub mod LayerTypes {
     pub const Tcp: LayerType = LayerType(3);
     // UDP
     pub const Udp: LayerType = LayerType(4);
+    // IPv6
+    pub const Ipv6: LayerType = LayerType(5);
+    // ICMP
+    pub const Icmp: LayerType = LayerType(6);
+    // Unknown
+    pub const Unknown: LayerType = LayerType(7);
+    // SLL
+    pub const Sll: LayerType = LayerType(8);
+    // ICMPv6
+    pub const Icmpv6: LayerType = LayerType(9);
+    // GRE
+    pub const Gre: LayerType = LayerType(10);
+    // IGMP
+    pub const Igmp: LayerType = LayerType(11);
+}
+
+/// Represents an error when parsing a layer.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum ParseError {
+    /// The buffer is too small for the layer.
+    Truncated(LayerType),
+    /// The layer contains a field with an invalid value.
+    InvalidValue(LayerType, &'static str),
+    /// The checksum of the layer does not match its content.
+    ChecksumMismatch(LayerType),
+}
+
+impl Display for ParseError {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            ParseError::Truncated(t) => write!(f, "{} truncated", t),
+            ParseError::InvalidValue(t, field) => write!(f, "{}: invalid {}", t, field),
+            ParseError::ChecksumMismatch(t) => write!(f, "{} checksum mismatch", t),
+        }
+    }
+}
+
+impl ParseError {
+    /// Get the type of the layer failed to be parsed.
+    pub fn get_type(&self) -> LayerType {
+        match self {
+            ParseError::Truncated(t)
+            | ParseError::InvalidValue(t, _)
+            | ParseError::ChecksumMismatch(t) => *t,
+        }
+    }
+}
+
+impl Error for ParseError {}
+
+impl From<ParseError> for io::Error {
+    fn from(e: ParseError) -> io::Error {
+        let kind = match e {
+            ParseError::Truncated(_) => io::ErrorKind::UnexpectedEof,
+            _ => io::ErrorKind::InvalidData,
+        };
+        io::Error::new(kind, e)
+    }
+}
+
+/// Represents an error when serializing a layer.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum SerializeError {
+    /// The source and the destination of the layer are the same address, which may cause the
+    /// frame to loop.
+    SelfAddressed(LayerType),
+    /// The length of the layer and its payload cannot be represented in the length field of the
+    /// layer, or is inconsistent with the header or the payload present.
+    InvalidLength(LayerType, usize),
+}
+
+impl Display for SerializeError {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            SerializeError::SelfAddressed(t) => write!(f, "{} self-addressed", t),
+            SerializeError::InvalidLength(t, n) => write!(f, "{} invalid length {}", t, n),
+        }
+    }
+}
+
+impl Error for SerializeError {}
+
+impl From<SerializeError> for io::Error {
+    fn from(e: SerializeError) -> io::Error {
+        io::Error::new(io::ErrorKind::InvalidInput, e)
+    }
+}
+
+/// Checks the length `n` of a layer and its payload, which is used for recomputing the length
+/// fields of the layer. The length should cover the header, not exceed the bytes present, and fit
+/// in the length field.
+fn check_length(
+    t: LayerType,
+    n: usize,
+    header_length: usize,
+    present: usize,
+    max: usize,
+) -> Result<(), SerializeError> {
+    if n < header_length || n > present || n > max {
+        return Err(SerializeError::InvalidLength(t, n));
+    }
+
+    Ok(())
 }
 
 /// Represents a layer.
@@ -66,25 +236,182 @@ pub trait Layer: Display {
         payload: &[u8],
         n: usize,
     ) -> io::Result<usize>;
+
+    // Clone the `Layer` into a boxed trait object.
+    fn clone_boxed(&self) -> Box<dyn Layer>;
+
+    // Serialize the `Layer` into the writer. The `Layer` is serialized into a buffer and then
+    // written by default, layers which can be written incrementally may write directly.
+    fn serialize_to_writer(&self, w: &mut dyn Write) -> io::Result<usize> {
+        let size = self.get_size();
+        let mut buffer = vec![0u8; size];
+        let n = self.serialize(&mut buffer, size)?;
+        w.write_all(&buffer[..n])?;
+
+        Ok(n)
+    }
+
+    // Serialize the `Layer` into the cursor and advance it. The remaining of the cursor is
+    // considered as the `Layer` and its payload.
+    fn serialize_into(&self, cursor: &mut PacketCursor) -> SerializeResult {
+        let n = cursor.remaining_len();
+        let m = self.serialize(cursor.remaining_mut(), n)?;
+        cursor.advance(m);
+
+        Ok(m)
+    }
+}
+
+/// Trait for the bounds of the size of a layer, which can be checked before a layer is parsed.
+pub trait SizeBounds {
+    // Get the minimum size of the `Layer`, which is the size of its header without any options.
+    fn min_size() -> usize;
+
+    // Get the maximum size of the `Layer`, including all of its options.
+    fn max_size() -> usize;
+}
+
+impl Clone for Box<dyn Layer> {
+    fn clone(&self) -> Box<dyn Layer> {
+        self.clone_boxed()
+    }
+}
+
+/// Represents the result of a serialization, which is the number of bytes written.
+pub type SerializeResult = io::Result<usize>;
+
+/// Represents a cursor over a byte-array for serializing layers one after another.
+#[derive(Debug)]
+pub struct PacketCursor<'a> {
+    buffer: &'a mut [u8],
+    offset: usize,
+}
+
+impl<'a> PacketCursor<'a> {
+    /// Creates a `PacketCursor` at the beginning of the given byte-array.
+    pub fn new(buffer: &'a mut [u8]) -> PacketCursor<'a> {
+        PacketCursor { buffer, offset: 0 }
+    }
+
+    /// Get the offset of the cursor.
+    pub fn get_offset(&self) -> usize {
+        self.offset
+    }
+
+    /// Get the length of the remaining byte-array after the cursor.
+    pub fn remaining_len(&self) -> usize {
+        self.buffer.len() - self.offset
+    }
+
+    /// Get the remaining byte-array after the cursor.
+    pub fn remaining_mut(&mut self) -> &mut [u8] {
+        &mut self.buffer[self.offset..]
+    }
+
+    /// Advances the cursor by the given number of bytes.
+    pub fn advance(&mut self, n: usize) {
+        self.offset = min(self.offset + n, self.buffer.len());
+    }
 }
 
 #[derive(Clone, Debug)]
 pub enum Layers {
     Ethernet(ethernet::Ethernet),
+    Sll(sll::Sll),
     Arp(arp::Arp),
     Ipv4(ipv4::Ipv4),
+    Ipv6(ipv6::Ipv6),
     Tcp(tcp::Tcp),
     Udp(udp::Udp),
+    Icmp(icmp::Icmp),
+    Icmpv6(icmpv6::Icmpv6),
+    Igmp(igmp::Igmp),
+    Gre(gre::Gre),
+    Unknown(unknown::Unknown),
+}
+
+impl Layers {
+    /// Get the payload encapsulated by the layer. Returns `None` for layers without payload, like
+    /// `Arp`. The payload is only kept when the layer is parsed or deserialized, layers of an
+    /// `Indicator` keep no payload.
+    pub fn payload(&self) -> Option<&[u8]> {
+        match self {
+            Layers::Ipv4(ref layer) => Some(layer.get_payload()),
+            Layers::Ipv6(ref layer) => Some(layer.get_payload()),
+            Layers::Tcp(ref layer) => Some(layer.get_payload()),
+            Layers::Udp(ref layer) => Some(layer.get_payload()),
+            Layers::Icmp(ref layer) => Some(layer.get_payload()),
+            Layers::Igmp(ref layer) => Some(layer.get_payload()),
+            Layers::Gre(ref layer) => Some(layer.get_payload()),
+            Layers::Unknown(ref layer) => Some(layer.get_payload()),
+            _ => None,
+        }
+    }
+
+    /// Serializes an ordered stack of layers into the given byte-array, from the link layer to
+    /// the transport layer. The bytes after the stack in the byte-array are considered as the
+    /// payload. Length fields are computed from the remaining of the byte-array, and checksums
+    /// of the transport layer are computed with the addresses of the network layer in the stack.
+    /// Layers are serialized in place from the innermost one, so the lengths and checksums of
+    /// every layer cover the serialized layers and the payload following it.
+    pub fn serialize_stack(layers: &[Layers], buffer: &mut [u8]) -> SerializeResult {
+        let size: usize = layers.iter().map(|layer| layer.get_size()).sum();
+        if buffer.len() < size {
+            return Err(io::Error::new(io::ErrorKind::WriteZero, "buffer too small"));
+        }
+        let n = buffer.len();
+
+        let mut end = size;
+        for (i, layer) in layers.iter().enumerate().rev() {
+            let begin = end - layer.get_size();
+            let buffer = &mut buffer[begin..];
+            layer.serialize(buffer, n - begin)?;
+
+            // Back-patch the checksum with the pseudo-header of the network layer
+            let addrs = layers[..i].iter().rev().find_map(|layer| match layer {
+                Layers::Ipv4(ref ipv4) => {
+                    Some((IpAddr::V4(ipv4.get_src()), IpAddr::V4(ipv4.get_dst())))
+                }
+                Layers::Ipv6(ref ipv6) => {
+                    Some((IpAddr::V6(ipv6.get_src()), IpAddr::V6(ipv6.get_dst())))
+                }
+                _ => None,
+            });
+            match layer {
+                Layers::Tcp(ref tcp) => tcp.patch_checksum(buffer, addrs)?,
+                Layers::Udp(ref udp) => udp.patch_checksum(buffer, addrs)?,
+                Layers::Icmpv6(ref icmpv6) => {
+                    let addrs = match addrs {
+                        Some((IpAddr::V6(src), IpAddr::V6(dst))) => Some((src, dst)),
+                        _ => None,
+                    };
+                    icmpv6.patch_checksum(buffer, addrs)?
+                }
+                _ => {}
+            }
+
+            end = begin;
+        }
+
+        Ok(n)
+    }
 }
 
 impl Display for Layers {
     fn fmt(&self, f: &mut Formatter) -> fmt::Result {
         match self {
             Layers::Ethernet(ref layer) => layer.fmt(f),
+            Layers::Sll(ref layer) => layer.fmt(f),
             Layers::Arp(ref layer) => layer.fmt(f),
             Layers::Ipv4(ref layer) => layer.fmt(f),
+            Layers::Ipv6(ref layer) => layer.fmt(f),
             Layers::Tcp(ref layer) => layer.fmt(f),
             Layers::Udp(ref layer) => layer.fmt(f),
+            Layers::Icmp(ref layer) => layer.fmt(f),
+            Layers::Icmpv6(ref layer) => layer.fmt(f),
+            Layers::Igmp(ref layer) => layer.fmt(f),
+            Layers::Gre(ref layer) => layer.fmt(f),
+            Layers::Unknown(ref layer) => layer.fmt(f),
         }
     }
 }
@@ -93,30 +420,51 @@ impl Layer for Layers {
     fn get_type(&self) -> LayerType {
         match self {
             Layers::Ethernet(ref layer) => layer.get_type(),
+            Layers::Sll(ref layer) => layer.get_type(),
             Layers::Arp(ref layer) => layer.get_type(),
             Layers::Ipv4(ref layer) => layer.get_type(),
+            Layers::Ipv6(ref layer) => layer.get_type(),
             Layers::Tcp(ref layer) => layer.get_type(),
             Layers::Udp(ref layer) => layer.get_type(),
+            Layers::Icmp(ref layer) => layer.get_type(),
+            Layers::Icmpv6(ref layer) => layer.get_type(),
+            Layers::Igmp(ref layer) => layer.get_type(),
+            Layers::Gre(ref layer) => layer.get_type(),
+            Layers::Unknown(ref layer) => layer.get_type(),
         }
     }
 
     fn get_size(&self) -> usize {
         match self {
             Layers::Ethernet(ref layer) => layer.get_size(),
+            Layers::Sll(ref layer) => layer.get_size(),
             Layers::Arp(ref layer) => layer.get_size(),
             Layers::Ipv4(ref layer) => layer.get_size(),
+            Layers::Ipv6(ref layer) => layer.get_size(),
             Layers::Tcp(ref layer) => layer.get_size(),
             Layers::Udp(ref layer) => layer.get_size(),
+            Layers::Icmp(ref layer) => layer.get_size(),
+            Layers::Icmpv6(ref layer) => layer.get_size(),
+            Layers::Igmp(ref layer) => layer.get_size(),
+            Layers::Gre(ref layer) => layer.get_size(),
+            Layers::Unknown(ref layer) => layer.get_size(),
         }
     }
 
     fn serialize(&self, buffer: &mut [u8], n: usize) -> io::Result<usize> {
         match self {
             Layers::Ethernet(ref layer) => layer.serialize(buffer, n),
+            Layers::Sll(ref layer) => layer.serialize(buffer, n),
             Layers::Arp(ref layer) => layer.serialize(buffer, n),
             Layers::Ipv4(ref layer) => layer.serialize(buffer, n),
+            Layers::Ipv6(ref layer) => layer.serialize(buffer, n),
             Layers::Tcp(ref layer) => layer.serialize(buffer, n),
             Layers::Udp(ref layer) => layer.serialize(buffer, n),
+            Layers::Icmp(ref layer) => layer.serialize(buffer, n),
+            Layers::Icmpv6(ref layer) => layer.serialize(buffer, n),
+            Layers::Igmp(ref layer) => layer.serialize(buffer, n),
+            Layers::Gre(ref layer) => layer.serialize(buffer, n),
+            Layers::Unknown(ref layer) => layer.serialize(buffer, n),
         }
     }
 
@@ -128,10 +476,421 @@ impl Layer for Layers {
     ) -> io::Result<usize> {
         match self {
             Layers::Ethernet(ref layer) => layer.serialize_with_payload(buffer, payload, n),
+            Layers::Sll(ref layer) => layer.serialize_with_payload(buffer, payload, n),
             Layers::Arp(ref layer) => layer.serialize_with_payload(buffer, payload, n),
             Layers::Ipv4(ref layer) => layer.serialize_with_payload(buffer, payload, n),
+            Layers::Ipv6(ref layer) => layer.serialize_with_payload(buffer, payload, n),
             Layers::Tcp(ref layer) => layer.serialize_with_payload(buffer, payload, n),
             Layers::Udp(ref layer) => layer.serialize_with_payload(buffer, payload, n),
+            Layers::Icmp(ref layer) => layer.serialize_with_payload(buffer, payload, n),
+            Layers::Icmpv6(ref layer) => layer.serialize_with_payload(buffer, payload, n),
+            Layers::Igmp(ref layer) => layer.serialize_with_payload(buffer, payload, n),
+            Layers::Gre(ref layer) => layer.serialize_with_payload(buffer, payload, n),
+            Layers::Unknown(ref layer) => layer.serialize_with_payload(buffer, payload, n),
         }
     }
+
+    fn clone_boxed(&self) -> Box<dyn Layer> {
+        match self {
+            Layers::Ethernet(ref layer) => layer.clone_boxed(),
+            Layers::Sll(ref layer) => layer.clone_boxed(),
+            Layers::Arp(ref layer) => layer.clone_boxed(),
+            Layers::Ipv4(ref layer) => layer.clone_boxed(),
+            Layers::Ipv6(ref layer) => layer.clone_boxed(),
+            Layers::Tcp(ref layer) => layer.clone_boxed(),
+            Layers::Udp(ref layer) => layer.clone_boxed(),
+            Layers::Icmp(ref layer) => layer.clone_boxed(),
+            Layers::Icmpv6(ref layer) => layer.clone_boxed(),
+            Layers::Igmp(ref layer) => layer.clone_boxed(),
+            Layers::Gre(ref layer) => layer.clone_boxed(),
+            Layers::Unknown(ref layer) => layer.clone_boxed(),
+        }
+    }
+
+    fn serialize_to_writer(&self, w: &mut dyn Write) -> io::Result<usize> {
+        match self {
+            Layers::Ethernet(ref layer) => layer.serialize_to_writer(w),
+            Layers::Sll(ref layer) => layer.serialize_to_writer(w),
+            Layers::Arp(ref layer) => layer.serialize_to_writer(w),
+            Layers::Ipv4(ref layer) => layer.serialize_to_writer(w),
+            Layers::Ipv6(ref layer) => layer.serialize_to_writer(w),
+            Layers::Tcp(ref layer) => layer.serialize_to_writer(w),
+            Layers::Udp(ref layer) => layer.serialize_to_writer(w),
+            Layers::Icmp(ref layer) => layer.serialize_to_writer(w),
+            Layers::Icmpv6(ref layer) => layer.serialize_to_writer(w),
+            Layers::Igmp(ref layer) => layer.serialize_to_writer(w),
+            Layers::Gre(ref layer) => layer.serialize_to_writer(w),
+            Layers::Unknown(ref layer) => layer.serialize_to_writer(w),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pnet::packet::ethernet::EtherTypes;
+    use pnet::util::MacAddr;
+    use std::net::{Ipv4Addr, Ipv6Addr};
+
+    #[test]
+    fn serialize_stack_ethernet_ipv4_tcp() {
+        let src = Ipv4Addr::new(192, 168, 1, 1);
+        let dst = Ipv4Addr::new(192, 168, 1, 2);
+        let layers = [
+            Layers::Ethernet(
+                ethernet::Ethernet::new(LayerTypes::Ipv4, MacAddr::zero(), MacAddr::broadcast())
+                    .unwrap(),
+            ),
+            Layers::Ipv4(ipv4::Ipv4::new(1, LayerTypes::Tcp, src, dst).unwrap()),
+            Layers::Tcp(tcp::Tcp::new_ack(1024, 80, 100, 200, 65535)),
+        ];
+        let mut buffer = vec![0u8; 14 + 20 + 20 + 5];
+        buffer[54..].copy_from_slice(b"hello");
+        let n = Layers::serialize_stack(&layers, &mut buffer).unwrap();
+        assert_eq!(n, buffer.len());
+
+        // The length and checksum of the network layer are back-patched
+        let (ethernet, m) = ethernet::Ethernet::deserialize(&buffer).unwrap();
+        assert_eq!(ethernet.get_ethertype(), EtherTypes::Ipv4);
+        let (ipv4, m2) = ipv4::Ipv4::deserialize(&buffer[m..]).unwrap();
+        assert_eq!(ipv4.get_total_length(), 45);
+        // The checksum of the transport layer covers the pseudo-header of the network layer
+        let (tcp, m3) = tcp::Tcp::deserialize(&buffer[m + m2..]).unwrap();
+        assert_eq!(tcp.get_payload(), b"hello");
+        let checksum = tcp
+            .compute_checksum(IpAddr::V4(src), IpAddr::V4(dst), b"hello")
+            .unwrap();
+        assert_eq!(tcp.get_checksum(), checksum);
+        assert_eq!(m + m2 + m3 + 5, n);
+    }
+
+    #[test]
+    fn serialize_stack_ipv6_udp() {
+        let src: Ipv6Addr = "2001:db8::1".parse().unwrap();
+        let dst: Ipv6Addr = "2001:db8::2".parse().unwrap();
+        let layers = [
+            Layers::Ipv6(ipv6::Ipv6::new(LayerTypes::Udp, src, dst).unwrap()),
+            Layers::Udp(udp::Udp::new(1024, 53)),
+        ];
+        let mut buffer = vec![0u8; 40 + 8 + 5];
+        buffer[48..].copy_from_slice(b"query");
+        Layers::serialize_stack(&layers, &mut buffer).unwrap();
+
+        let (ipv6, m) = ipv6::Ipv6::deserialize(&buffer).unwrap();
+        assert_eq!(ipv6.get_payload_length(), 8 + 5);
+        let (udp, _) = udp::Udp::deserialize(&buffer[m..]).unwrap();
+        assert_eq!(udp.get_length(), 8 + 5);
+        let checksum = udp
+            .compute_checksum(IpAddr::V6(src), IpAddr::V6(dst), b"query")
+            .unwrap();
+        assert_eq!(u16::from_be_bytes([buffer[m + 6], buffer[m + 7]]), checksum);
+    }
+
+    #[test]
+    fn serialize_stack_nested_checksums() {
+        let outer_src = Ipv4Addr::new(10, 0, 0, 1);
+        let outer_dst = Ipv4Addr::new(10, 0, 0, 2);
+        let src = Ipv4Addr::new(192, 168, 1, 1);
+        let dst = Ipv4Addr::new(192, 168, 1, 2);
+        let mut gre = gre::Gre::new(EtherTypes::Ipv4);
+        gre.set_checksum_present(true);
+        let layers = [
+            Layers::Ipv4(ipv4::Ipv4::new(1, LayerTypes::Gre, outer_src, outer_dst).unwrap()),
+            Layers::Gre(gre),
+            Layers::Ipv4(ipv4::Ipv4::new(2, LayerTypes::Udp, src, dst).unwrap()),
+            Layers::Udp(udp::Udp::new(1024, 53)),
+        ];
+        let size: usize = layers.iter().map(|layer| layer.get_size()).sum();
+        let mut buffer = vec![0u8; size + 5];
+        buffer[size..].copy_from_slice(b"query");
+        Layers::serialize_stack(&layers, &mut buffer).unwrap();
+
+        // The checksum of the GRE layer covers the inner layers back-patched before it
+        let (outer, m) = ipv4::Ipv4::deserialize(&buffer).unwrap();
+        assert_eq!(outer.get_total_length() as usize, buffer.len());
+        let (_, m2) = gre::Gre::deserialize(&buffer[m..]).unwrap();
+        let (inner, m3) = ipv4::Ipv4::deserialize(&buffer[m + m2..]).unwrap();
+        assert_eq!(inner.get_total_length() as usize, buffer.len() - m - m2);
+        // The inner transport layer takes the pseudo-header of the nearest network layer
+        let begin = m + m2 + m3;
+        let (udp, _) = udp::Udp::deserialize(&buffer[begin..]).unwrap();
+        let checksum = udp
+            .compute_checksum(IpAddr::V4(src), IpAddr::V4(dst), b"query")
+            .unwrap();
+        assert_eq!(
+            u16::from_be_bytes([buffer[begin + 6], buffer[begin + 7]]),
+            checksum
+        );
+    }
+
+    #[test]
+    fn serialize_stack_buffer_too_small() {
+        let layers = [Layers::Tcp(tcp::Tcp::new_ack(1024, 80, 100, 200, 65535))];
+        let mut buffer = vec![0u8; 19];
+
+        assert!(Layers::serialize_stack(&layers, &mut buffer).is_err());
+    }
+
+    #[test]
+    fn packet_cursor_advance() {
+        let mut buffer = [0u8; 8];
+        let mut cursor = PacketCursor::new(&mut buffer);
+        cursor.advance(6);
+        assert_eq!(cursor.get_offset(), 6);
+        assert_eq!(cursor.remaining_len(), 2);
+
+        // The cursor stops at the end of the byte-array
+        cursor.advance(6);
+        assert_eq!(cursor.get_offset(), 8);
+        assert!(cursor.remaining_mut().is_empty());
+    }
+
+    #[test]
+    fn payload_udp() {
+        let layers = [
+            Layers::Ipv4(
+                ipv4::Ipv4::new(
+                    1,
+                    LayerTypes::Udp,
+                    Ipv4Addr::new(192, 168, 1, 1),
+                    Ipv4Addr::new(192, 168, 1, 2),
+                )
+                .unwrap(),
+            ),
+            Layers::Udp(udp::Udp::new(1024, 53)),
+        ];
+        let mut buffer = vec![0u8; 20 + 8 + 5];
+        buffer[28..].copy_from_slice(b"hello");
+        Layers::serialize_stack(&layers, &mut buffer).unwrap();
+        // Pad like a short Ethernet frame
+        buffer.extend_from_slice(&[0u8; 6]);
+
+        // The padding is not a part of the payload
+        let (ipv4, n) = ipv4::Ipv4::deserialize(&buffer).unwrap();
+        let ipv4 = Layers::Ipv4(ipv4);
+        assert_eq!(ipv4.payload().unwrap(), &buffer[20..33]);
+        let (udp, _) = udp::Udp::deserialize(&buffer[n..]).unwrap();
+        let udp = Layers::Udp(udp);
+        assert_eq!(udp.payload().unwrap(), b"hello");
+    }
+
+    #[test]
+    fn payload_arp() {
+        let arp = Layers::Arp(arp::Arp::new_gratuitous(
+            MacAddr::zero(),
+            Ipv4Addr::new(192, 168, 1, 1),
+        ));
+        assert!(arp.payload().is_none());
+    }
+
+    #[test]
+    fn incremental_update_randomized() {
+        // Xorshift, which is enough for the test
+        let mut state = 0x2545_f491_4f6c_dd1du64;
+        let mut next = move || {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state
+        };
+
+        for _ in 0..10000 {
+            let mut header = [0u8; 20];
+            for b in header.iter_mut() {
+                *b = next() as u8;
+            }
+            header[10..12].copy_from_slice(&[0, 0]);
+            let checksum = pnet::util::checksum(&header, 5);
+
+            // Change a word other than the checksum
+            let i = match (next() % 9) as usize {
+                i if i >= 5 => i + 1,
+                i => i,
+            };
+            let old_word = u16::from_be_bytes([header[2 * i], header[2 * i + 1]]);
+            let new_word = next() as u16;
+            header[2 * i..2 * i + 2].copy_from_slice(&new_word.to_be_bytes());
+
+            assert_eq!(
+                incremental_update(checksum, old_word, new_word),
+                pnet::util::checksum(&header, 5)
+            );
+        }
+    }
+
+    #[test]
+    fn hex_dump_ethernet() {
+        let ethernet = ethernet::Ethernet::new(
+            LayerTypes::Ipv4,
+            MacAddr::new(0x02, 0, 0, 0, 0, 0x01),
+            MacAddr::broadcast(),
+        )
+        .unwrap();
+
+        assert_eq!(
+            format!("{:#}", ethernet),
+            "Ethernet: 14 Bytes\n\
+             0000  ff ff ff ff ff ff 02 00  00 00 00 01 08 00        |..............|"
+        );
+        // The default form is unchanged
+        assert_eq!(
+            format!("{}", ethernet),
+            "Ethernet: 02:00:00:00:00:01 -> ff:ff:ff:ff:ff:ff"
+        );
+    }
+
+    #[test]
+    fn hex_dump_multiple_lines() {
+        let src = Ipv4Addr::new(192, 168, 1, 1);
+        let dst = Ipv4Addr::new(192, 168, 1, 2);
+        let ipv4 = ipv4::Ipv4::new(0, LayerTypes::Udp, src, dst).unwrap();
+        let dump = format!("{:#}", Layers::Ipv4(ipv4));
+        let lines: Vec<&str> = dump.lines().collect();
+
+        // The alternate form is passed through `Layers`
+        assert_eq!(lines.len(), 3);
+        assert_eq!(lines[0], "IPv4: 20 Bytes");
+        assert!(lines[1].starts_with("0000  45 00 00 14"));
+        assert!(lines[2].starts_with("0010  c0 a8 01 02 "));
+        assert!(lines[2].ends_with("|....|"));
+    }
+
+    /// Returns the `SerializeError` wrapped in the given error.
+    pub(crate) fn unwrap_serialize_error(e: io::Error) -> SerializeError {
+        *e.get_ref()
+            .unwrap()
+            .downcast_ref::<SerializeError>()
+            .unwrap()
+    }
+
+    #[test]
+    fn check_length_bounds() {
+        assert!(check_length(LayerTypes::Udp, 8, 8, 8, 65535).is_ok());
+        assert!(check_length(LayerTypes::Udp, 100, 8, 100, 65535).is_ok());
+        // Shorter than the header
+        assert_eq!(
+            check_length(LayerTypes::Udp, 7, 8, 100, 65535),
+            Err(SerializeError::InvalidLength(LayerTypes::Udp, 7))
+        );
+        // Longer than the bytes present
+        assert_eq!(
+            check_length(LayerTypes::Udp, 101, 8, 100, 65535),
+            Err(SerializeError::InvalidLength(LayerTypes::Udp, 101))
+        );
+        // Longer than the length field
+        assert_eq!(
+            check_length(LayerTypes::Udp, 65536, 8, 70000, 65535),
+            Err(SerializeError::InvalidLength(LayerTypes::Udp, 65536))
+        );
+    }
+
+    #[test]
+    fn invalid_length_into_io() {
+        let e = io::Error::from(SerializeError::InvalidLength(LayerTypes::Ipv4, 70000));
+        assert_eq!(e.kind(), io::ErrorKind::InvalidInput);
+        assert_eq!(e.to_string(), "IPv4 invalid length 70000");
+        assert_eq!(
+            unwrap_serialize_error(e),
+            SerializeError::InvalidLength(LayerTypes::Ipv4, 70000)
+        );
+    }
+
+    /// Serializes the given layer into a new buffer.
+    fn serialize_layer(layer: &dyn Layer) -> Vec<u8> {
+        let mut buffer = vec![0u8; layer.get_size()];
+        let n = layer.get_size();
+        layer.serialize(&mut buffer, n).unwrap();
+
+        buffer
+    }
+
+    #[test]
+    fn clone_boxed_tcp() {
+        let mut tcp = tcp::Tcp::new_ack(1024, 80, 1001, 5001, 65535);
+        tcp.set_timestamps(Some((100, 200)));
+        let boxed: Box<dyn Layer> = Box::new(tcp.clone());
+        let cloned = boxed.clone();
+
+        assert_eq!(cloned.get_type(), LayerTypes::Tcp);
+        assert_eq!(cloned.to_string(), tcp.to_string());
+        assert_eq!(serialize_layer(cloned.as_ref()), serialize_layer(&tcp));
+    }
+
+    #[test]
+    fn clone_boxed_stack() {
+        let src = Ipv4Addr::new(192, 168, 1, 1);
+        let dst = Ipv4Addr::new(192, 168, 1, 2);
+        let ipv4 = ipv4::Ipv4::new(1, LayerTypes::Udp, src, dst).unwrap();
+        let mut udp = udp::Udp::new(1024, 53);
+        udp.set_ipv4_layer(&ipv4);
+        let stack: Vec<Box<dyn Layer>> = vec![
+            Layers::Ipv4(ipv4).clone_boxed(),
+            Layers::Udp(udp).clone_boxed(),
+        ];
+        let cloned = stack.clone();
+
+        // The enum forwards to the concrete layers
+        let types: Vec<LayerType> = cloned.iter().map(|layer| layer.get_type()).collect();
+        assert_eq!(types, [LayerTypes::Ipv4, LayerTypes::Udp]);
+        for (layer, cloned) in stack.iter().zip(cloned.iter()) {
+            assert_eq!(
+                serialize_layer(cloned.as_ref()),
+                serialize_layer(layer.as_ref())
+            );
+        }
+    }
+
+    /// Represents a writer which accepts a limited number of bytes.
+    struct LimitedWriter(usize);
+
+    impl Write for LimitedWriter {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            if self.0 == 0 {
+                return Err(io::Error::new(io::ErrorKind::WriteZero, "writer full"));
+            }
+            let n = min(self.0, buf.len());
+            self.0 -= n;
+
+            Ok(n)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn serialize_to_writer_tcp() {
+        let mut tcp = tcp::Tcp::new_ack(1024, 80, 1001, 5001, 65535);
+        tcp.set_timestamps(Some((100, 200)));
+        let mut w = Vec::new();
+        let n = Layers::Tcp(tcp.clone())
+            .serialize_to_writer(&mut w)
+            .unwrap();
+
+        assert_eq!(n, tcp.get_size());
+        assert_eq!(w, serialize_layer(&tcp));
+
+        // Appended after the bytes written before
+        tcp.serialize_to_writer(&mut w).unwrap();
+        assert_eq!(w.len(), 2 * n);
+    }
+
+    #[test]
+    fn serialize_to_writer_unknown() {
+        let unknown =
+            unknown::Unknown::new(pnet::packet::ethernet::EtherType(0x88b5), b"experimental");
+        let mut w = Vec::new();
+        let n = unknown.serialize_to_writer(&mut w).unwrap();
+
+        assert_eq!(n, 12);
+        assert_eq!(w, b"experimental");
+    }
+
+    #[test]
+    fn serialize_to_writer_error() {
+        let tcp = tcp::Tcp::new_ack(1024, 80, 1001, 5001, 65535);
+        let e = tcp.serialize_to_writer(&mut LimitedWriter(10)).unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::WriteZero);
+    }
 }
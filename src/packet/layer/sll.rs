@@ -0,0 +1,242 @@
+use super::ethernet::Ethernet;
+use super::{fmt_hex_dump, Layer, LayerType, LayerTypes, ParseError, SizeBounds};
+use pnet::packet::ethernet::{self, EtherType};
+use pnet::util::MacAddr;
+use std::clone::Clone;
+use std::fmt::{self, Display, Formatter};
+use std::io;
+
+/// Represents the size of the SLL header.
+pub const SLL_HEADER_SIZE: usize = 16;
+/// Represents the max length of the link-layer address in the SLL header.
+const SLL_ADDRESS_SIZE: usize = 8;
+
+/// Represents the packet type of an SLL frame sent to us.
+pub const SLL_PACKET_HOST: u16 = 0;
+/// Represents the packet type of an SLL frame broadcast by somebody else.
+pub const SLL_PACKET_BROADCAST: u16 = 1;
+/// Represents the packet type of an SLL frame multicast by somebody else.
+pub const SLL_PACKET_MULTICAST: u16 = 2;
+/// Represents the packet type of an SLL frame sent to somebody else by somebody else.
+pub const SLL_PACKET_OTHER_HOST: u16 = 3;
+/// Represents the packet type of an SLL frame sent by us.
+pub const SLL_PACKET_OUTGOING: u16 = 4;
+
+/// Represents a Linux cooked capture (SLL) layer, which is the link layer of frames captured on
+/// the Linux `any` pseudo-interface.
+#[derive(Clone, Debug)]
+pub struct Sll {
+    packet_type: u16,
+    hardware_type: u16,
+    address: Vec<u8>,
+    protocol: EtherType,
+}
+
+impl Sll {
+    /// Creates an `Sll`.
+    pub fn new(packet_type: u16, hardware_type: u16, address: &[u8], protocol: EtherType) -> Sll {
+        let length = address.len().min(SLL_ADDRESS_SIZE);
+        Sll {
+            packet_type,
+            hardware_type,
+            address: address[..length].to_vec(),
+            protocol,
+        }
+    }
+
+    /// Deserializes an `Sll` from the given byte-array and returns it with the number of bytes
+    /// consumed.
+    pub fn deserialize(buffer: &[u8]) -> Result<(Sll, usize), ParseError> {
+        if buffer.len() < Sll::min_size() {
+            return Err(ParseError::Truncated(LayerTypes::Sll));
+        }
+        let packet_type = (buffer[0] as u16) << 8 | buffer[1] as u16;
+        let hardware_type = (buffer[2] as u16) << 8 | buffer[3] as u16;
+        let address_length = (buffer[4] as u16) << 8 | buffer[5] as u16;
+        if address_length as usize > SLL_ADDRESS_SIZE {
+            return Err(ParseError::InvalidValue(LayerTypes::Sll, "address length"));
+        }
+        let address = &buffer[6..6 + address_length as usize];
+        let protocol = EtherType((buffer[14] as u16) << 8 | buffer[15] as u16);
+
+        Ok((
+            Sll::new(packet_type, hardware_type, address, protocol),
+            SLL_HEADER_SIZE,
+        ))
+    }
+
+    /// Get the packet type of the layer.
+    pub fn get_packet_type(&self) -> u16 {
+        self.packet_type
+    }
+
+    /// Get the ARPHRD type of the layer.
+    pub fn get_hardware_type(&self) -> u16 {
+        self.hardware_type
+    }
+
+    /// Get the link-layer address of the sender of the layer.
+    pub fn get_address(&self) -> &[u8] {
+        &self.address
+    }
+
+    /// Get the hardware address of the sender of the layer. Returns `None` if the address is not
+    /// a hardware address.
+    pub fn get_src(&self) -> Option<MacAddr> {
+        match self.address.len() {
+            6 => Some(MacAddr::new(
+                self.address[0],
+                self.address[1],
+                self.address[2],
+                self.address[3],
+                self.address[4],
+                self.address[5],
+            )),
+            _ => None,
+        }
+    }
+
+    /// Get the protocol of the layer, which is the EtherType of the payload.
+    pub fn get_protocol(&self) -> EtherType {
+        self.protocol
+    }
+
+    /// Converts the layer into an `Ethernet`. The destination is the broadcast address for
+    /// broadcast frames, and unspecified for others, since it is not recorded in the SLL header.
+    pub fn to_ethernet(&self) -> Ethernet {
+        let destination = match self.packet_type {
+            SLL_PACKET_BROADCAST => MacAddr::broadcast(),
+            _ => MacAddr::zero(),
+        };
+        let d_ethernet = ethernet::Ethernet {
+            destination,
+            source: self.get_src().unwrap_or_else(MacAddr::zero),
+            ethertype: self.protocol,
+            payload: vec![],
+        };
+
+        Ethernet::from(d_ethernet)
+    }
+}
+
+impl Display for Sll {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        if f.alternate() {
+            return fmt_hex_dump(self, f);
+        }
+
+        let src = match self.get_src() {
+            Some(src) => src.to_string(),
+            None => String::from("unknown"),
+        };
+
+        write!(
+            f,
+            "{}: {}, Packet Type = {}, Protocol = 0x{:04x}",
+            LayerTypes::Sll,
+            src,
+            self.packet_type,
+            self.protocol.0
+        )
+    }
+}
+
+impl SizeBounds for Sll {
+    fn min_size() -> usize {
+        SLL_HEADER_SIZE
+    }
+
+    fn max_size() -> usize {
+        SLL_HEADER_SIZE
+    }
+}
+
+impl Layer for Sll {
+    fn get_type(&self) -> LayerType {
+        LayerTypes::Sll
+    }
+
+    fn get_size(&self) -> usize {
+        SLL_HEADER_SIZE
+    }
+
+    fn serialize(&self, buffer: &mut [u8], _: usize) -> io::Result<usize> {
+        if buffer.len() < SLL_HEADER_SIZE {
+            return Err(io::Error::new(io::ErrorKind::WriteZero, "buffer too small"));
+        }
+
+        buffer[0..2].copy_from_slice(&self.packet_type.to_be_bytes());
+        buffer[2..4].copy_from_slice(&self.hardware_type.to_be_bytes());
+        buffer[4..6].copy_from_slice(&(self.address.len() as u16).to_be_bytes());
+        for b in &mut buffer[6..14] {
+            *b = 0;
+        }
+        buffer[6..6 + self.address.len()].copy_from_slice(&self.address);
+        buffer[14..16].copy_from_slice(&self.protocol.0.to_be_bytes());
+
+        Ok(self.get_size())
+    }
+
+    fn serialize_with_payload(&self, buffer: &mut [u8], _: &[u8], n: usize) -> io::Result<usize> {
+        self.serialize(buffer, n)
+    }
+
+    fn clone_boxed(&self) -> Box<dyn Layer> {
+        Box::new(self.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Represents an SLL header captured on the Linux `any` pseudo-interface, of an IPv4 frame
+    /// broadcast by 02:00:00:00:00:01.
+    #[rustfmt::skip]
+    const HEADER: [u8; SLL_HEADER_SIZE] = [
+        0x00, 0x01, 0x00, 0x01, 0x00, 0x06, 0x02, 0x00,
+        0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x08, 0x00,
+    ];
+
+    #[test]
+    fn deserialize_captured() {
+        let (sll, n) = Sll::deserialize(&HEADER).unwrap();
+
+        assert_eq!(n, SLL_HEADER_SIZE);
+        assert_eq!(sll.get_packet_type(), SLL_PACKET_BROADCAST);
+        assert_eq!(sll.get_hardware_type(), 1);
+        assert_eq!(sll.get_src(), Some(MacAddr::new(0x02, 0, 0, 0, 0, 0x01)));
+        assert_eq!(sll.get_protocol(), ethernet::EtherTypes::Ipv4);
+
+        let mut buffer = vec![0u8; sll.get_size()];
+        sll.serialize(&mut buffer, sll.get_size()).unwrap();
+        assert_eq!(buffer, HEADER);
+    }
+
+    #[test]
+    fn deserialize_invalid_address_length() {
+        let mut header = HEADER;
+        header[5] = 9;
+
+        assert!(matches!(
+            Sll::deserialize(&header),
+            Err(ParseError::InvalidValue(LayerTypes::Sll, _))
+        ));
+        assert!(matches!(
+            Sll::deserialize(&HEADER[..15]),
+            Err(ParseError::Truncated(LayerTypes::Sll))
+        ));
+    }
+
+    #[test]
+    fn to_ethernet() {
+        let (sll, _) = Sll::deserialize(&HEADER).unwrap();
+        let ethernet = sll.to_ethernet();
+        assert_eq!(ethernet.get_src(), MacAddr::new(0x02, 0, 0, 0, 0, 0x01));
+        assert_eq!(ethernet.get_dst(), MacAddr::broadcast());
+
+        // The destination of frames sent to us is not recorded
+        let sll = Sll::new(SLL_PACKET_HOST, 1, &[0; 6], ethernet::EtherTypes::Ipv4);
+        assert_eq!(sll.to_ethernet().get_dst(), MacAddr::zero());
+    }
+}
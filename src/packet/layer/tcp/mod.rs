@@ -0,0 +1,1939 @@
+use super::ipv4::Ipv4;
+use super::ipv6::Ipv6;
+use super::{
+    fmt_hex_dump, incremental_update, Layer, LayerType, LayerTypes, Layers, ParseError, SizeBounds,
+};
+use bitflags::bitflags;
+use pnet::packet::tcp::{self, MutableTcpPacket, TcpFlags, TcpOptionNumbers, TcpPacket};
+use pnet::packet::Packet;
+use std::clone::Clone;
+use std::cmp::{max, min};
+use std::fmt::{self, Display, Formatter};
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::str::FromStr;
+
+pub mod state;
+
+/// Represents the max shift count of the window scale option.
+pub const MAX_WINDOW_SCALE: u8 = 14;
+/// Represents the max size of the TCP options.
+const MAX_OPTIONS_SIZE: usize = 40;
+/// Represents the size of a block in the selective acknowledgement option.
+const SACK_BLOCK_SIZE: usize = 8;
+/// Represents the kind of the fast open option (RFC 7413).
+const FAST_OPEN: u8 = 34;
+/// Represents the min size of a fast open cookie.
+const MIN_FAST_OPEN_COOKIE_SIZE: usize = 4;
+/// Represents the max size of a fast open cookie.
+pub const MAX_FAST_OPEN_COOKIE_SIZE: usize = 16;
+
+bitflags! {
+    /// Represents the flags of a TCP layer.
+    pub struct Flags: u16 {
+        /// Represents the ECN-nonce concealment protection flag (RFC 3540).
+        const NS = TcpFlags::NS;
+        /// Represents the congestion window reduced flag (RFC 3168).
+        const CWR = TcpFlags::CWR;
+        /// Represents the ECN-echo flag (RFC 3168).
+        const ECE = TcpFlags::ECE;
+        /// Represents the urgent flag.
+        const URG = TcpFlags::URG;
+        /// Represents the acknowledgement flag.
+        const ACK = TcpFlags::ACK;
+        /// Represents the push flag.
+        const PSH = TcpFlags::PSH;
+        /// Represents the reset flag.
+        const RST = TcpFlags::RST;
+        /// Represents the synchronization flag.
+        const SYN = TcpFlags::SYN;
+        /// Represents the finish flag.
+        const FIN = TcpFlags::FIN;
+    }
+}
+
+/// Represents the interpretation of the TCP urgent pointer (RFC 6093).
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum UrgentPointer {
+    /// The urgent pointer points to the byte following the urgent data, as most implementations
+    /// derived from BSD.
+    Bsd,
+    /// The urgent pointer points to the last byte of the urgent data, as RFC 1122 specifies.
+    Rfc1122,
+}
+
+impl UrgentPointer {
+    /// Get the offset of the urgent pointer from the end of the urgent data.
+    fn get_offset(&self) -> u32 {
+        match self {
+            UrgentPointer::Bsd => 0,
+            UrgentPointer::Rfc1122 => 1,
+        }
+    }
+}
+
+impl Default for UrgentPointer {
+    fn default() -> Self {
+        UrgentPointer::Bsd
+    }
+}
+
+impl Display for UrgentPointer {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            UrgentPointer::Bsd => write!(f, "bsd"),
+            UrgentPointer::Rfc1122 => write!(f, "rfc1122"),
+        }
+    }
+}
+
+impl FromStr for UrgentPointer {
+    type Err = io::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "bsd" => Ok(UrgentPointer::Bsd),
+            "rfc1122" => Ok(UrgentPointer::Rfc1122),
+            _ => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "unknown urgent pointer",
+            )),
+        }
+    }
+}
+
+/// Represents a TCP option.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum TcpOption {
+    /// End of option list.
+    EndOfOptions,
+    /// No operation, used for padding between options.
+    NoOperation,
+    /// Maximum segment size.
+    MaximumSegmentSize(u16),
+    /// Window scale.
+    WindowScale(u8),
+    /// Selective acknowledgement permitted.
+    SackPermitted,
+    /// Selective acknowledgement with the left and right edges of blocks received.
+    Sack(Vec<(u32, u32)>),
+    /// Timestamps with the timestamp value and the timestamp echo reply (RFC 7323).
+    Timestamps(u32, u32),
+    /// Fast open with the cookie, or a cookie request if the cookie is empty (RFC 7413).
+    FastOpen(Vec<u8>),
+    /// Unknown option with its kind and data.
+    Unknown(u8, Vec<u8>),
+}
+
+impl TcpOption {
+    /// Parses TCP options from the given byte-array. Padding after the end of option list is
+    /// parsed as `EndOfOptions` too, and parsing stops at a malformed option.
+    pub fn parse_options(buffer: &[u8]) -> Vec<TcpOption> {
+        let mut options = Vec::new();
+        let mut i = 0;
+        while i < buffer.len() {
+            let number = buffer[i];
+            match number {
+                n if n == TcpOptionNumbers::EOL.0 => {
+                    options.push(TcpOption::EndOfOptions);
+                    i += 1;
+                    continue;
+                }
+                n if n == TcpOptionNumbers::NOP.0 => {
+                    options.push(TcpOption::NoOperation);
+                    i += 1;
+                    continue;
+                }
+                _ => {}
+            }
+
+            // Options with length
+            if i + 1 >= buffer.len() {
+                break;
+            }
+            let length = buffer[i + 1] as usize;
+            if length < 2 || i + length > buffer.len() {
+                break;
+            }
+            let payload = &buffer[i + 2..i + length];
+            match number {
+                n if n == TcpOptionNumbers::MSS.0 && payload.len() == 2 => options.push(
+                    TcpOption::MaximumSegmentSize((payload[0] as u16) << 8 | payload[1] as u16),
+                ),
+                n if n == TcpOptionNumbers::WSCALE.0 && payload.len() == 1 => {
+                    options.push(TcpOption::WindowScale(payload[0]))
+                }
+                n if n == TcpOptionNumbers::SACK_PERMITTED.0 && payload.is_empty() => {
+                    options.push(TcpOption::SackPermitted)
+                }
+                n if n == TcpOptionNumbers::SACK.0
+                    && !payload.is_empty()
+                    && payload.len() % SACK_BLOCK_SIZE == 0 =>
+                {
+                    let blocks = payload
+                        .chunks(SACK_BLOCK_SIZE)
+                        .map(|b| {
+                            (
+                                u32::from_be_bytes([b[0], b[1], b[2], b[3]]),
+                                u32::from_be_bytes([b[4], b[5], b[6], b[7]]),
+                            )
+                        })
+                        .collect();
+                    options.push(TcpOption::Sack(blocks))
+                }
+                n if n == TcpOptionNumbers::TIMESTAMPS.0 && payload.len() == 8 => {
+                    options.push(TcpOption::Timestamps(
+                        u32::from_be_bytes([payload[0], payload[1], payload[2], payload[3]]),
+                        u32::from_be_bytes([payload[4], payload[5], payload[6], payload[7]]),
+                    ))
+                }
+                FAST_OPEN
+                    if payload.is_empty()
+                        || (payload.len() >= MIN_FAST_OPEN_COOKIE_SIZE
+                            && payload.len() <= MAX_FAST_OPEN_COOKIE_SIZE
+                            && payload.len() % 2 == 0) =>
+                {
+                    options.push(TcpOption::FastOpen(payload.to_vec()))
+                }
+                _ => options.push(TcpOption::Unknown(number, payload.to_vec())),
+            }
+            i += length;
+        }
+
+        options
+    }
+
+    /// Get the size of the option.
+    pub fn get_size(&self) -> usize {
+        match self {
+            TcpOption::EndOfOptions | TcpOption::NoOperation => 1,
+            TcpOption::MaximumSegmentSize(_) => 4,
+            TcpOption::WindowScale(_) => 3,
+            TcpOption::SackPermitted => 2,
+            TcpOption::Sack(blocks) => 2 + blocks.len() * SACK_BLOCK_SIZE,
+            TcpOption::Timestamps(_, _) => 10,
+            TcpOption::FastOpen(cookie) => 2 + cookie.len(),
+            TcpOption::Unknown(_, data) => 2 + data.len(),
+        }
+    }
+
+    /// Serializes the option into the given byte-array and returns the number of bytes written.
+    pub fn serialize(&self, buffer: &mut [u8]) -> io::Result<usize> {
+        let size = self.get_size();
+        if buffer.len() < size {
+            return Err(io::Error::new(io::ErrorKind::WriteZero, "buffer too small"));
+        }
+
+        match self {
+            TcpOption::EndOfOptions => buffer[0] = TcpOptionNumbers::EOL.0,
+            TcpOption::NoOperation => buffer[0] = TcpOptionNumbers::NOP.0,
+            TcpOption::MaximumSegmentSize(mss) => {
+                buffer[0] = TcpOptionNumbers::MSS.0;
+                buffer[2] = (mss >> 8) as u8;
+                buffer[3] = *mss as u8;
+            }
+            TcpOption::WindowScale(wscale) => {
+                buffer[0] = TcpOptionNumbers::WSCALE.0;
+                buffer[2] = *wscale;
+            }
+            TcpOption::SackPermitted => buffer[0] = TcpOptionNumbers::SACK_PERMITTED.0,
+            TcpOption::Sack(blocks) => {
+                buffer[0] = TcpOptionNumbers::SACK.0;
+                for (i, (left, right)) in blocks.iter().enumerate() {
+                    let begin = 2 + i * SACK_BLOCK_SIZE;
+                    buffer[begin..begin + 4].copy_from_slice(&left.to_be_bytes());
+                    buffer[begin + 4..begin + 8].copy_from_slice(&right.to_be_bytes());
+                }
+            }
+            TcpOption::Timestamps(tsval, tsecr) => {
+                buffer[0] = TcpOptionNumbers::TIMESTAMPS.0;
+                buffer[2..6].copy_from_slice(&tsval.to_be_bytes());
+                buffer[6..10].copy_from_slice(&tsecr.to_be_bytes());
+            }
+            TcpOption::FastOpen(cookie) => {
+                buffer[0] = FAST_OPEN;
+                buffer[2..size].copy_from_slice(cookie);
+            }
+            TcpOption::Unknown(number, data) => {
+                buffer[0] = *number;
+                buffer[2..size].copy_from_slice(data);
+            }
+        }
+        if size > 1 {
+            buffer[1] = size as u8;
+        }
+
+        Ok(size)
+    }
+}
+
+impl Display for TcpOption {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            TcpOption::EndOfOptions => write!(f, "EOL"),
+            TcpOption::NoOperation => write!(f, "NOP"),
+            TcpOption::MaximumSegmentSize(mss) => write!(f, "MSS = {}", mss),
+            TcpOption::WindowScale(wscale) => write!(f, "WScale = {}", wscale),
+            TcpOption::SackPermitted => write!(f, "SACK Permitted"),
+            TcpOption::Sack(blocks) => {
+                let blocks: Vec<String> = blocks
+                    .iter()
+                    .map(|(left, right)| format!("{}-{}", left, right))
+                    .collect();
+                write!(f, "SACK = {}", blocks.join(", "))
+            }
+            TcpOption::Timestamps(tsval, tsecr) => write!(f, "TS = {}/{}", tsval, tsecr),
+            TcpOption::FastOpen(cookie) => match cookie.is_empty() {
+                true => write!(f, "TFO Cookie Request"),
+                false => write!(f, "TFO Cookie ({} Bytes)", cookie.len()),
+            },
+            TcpOption::Unknown(number, data) => {
+                write!(f, "Unknown = {} ({} Bytes)", number, data.len())
+            }
+        }
+    }
+}
+
+/// Represents a TCP packet.
+#[derive(Clone, Debug)]
+pub struct Tcp {
+    pub layer: tcp::Tcp,
+    pub src: Ipv4Addr,
+    pub dst: Ipv4Addr,
+    /// Represents the source and destination IP address if the layer is carried over IPv6.
+    pub ipv6: Option<(Ipv6Addr, Ipv6Addr)>,
+    options: Vec<TcpOption>,
+    payload: Vec<u8>,
+    checksum_offload: bool,
+    /// Represents if the checksum of the layer is maintained along with the segment, e.g., parsed
+    /// and updated incrementally, which is kept in serialization without recomputation.
+    is_checksum_maintained: bool,
+}
+
+impl Tcp {
+    /// Creates a `Tcp` represents a TCP ACK.
+    pub fn new_ack(src: u16, dst: u16, sequence: u32, acknowledgement: u32, window: u16) -> Tcp {
+        let d_tcp = tcp::Tcp {
+            source: src,
+            destination: dst,
+            sequence,
+            acknowledgement,
+            data_offset: 5,
+            reserved: 0,
+            flags: TcpFlags::ACK,
+            window,
+            checksum: 0,
+            urgent_ptr: 0,
+            options: vec![],
+            payload: vec![],
+        };
+        Tcp::from(d_tcp)
+    }
+
+    /// Creates a `Tcp` represents a TCP ACK/SYN.
+    pub fn new_ack_syn(
+        src: u16,
+        dst: u16,
+        sequence: u32,
+        acknowledgement: u32,
+        window: u16,
+    ) -> Tcp {
+        let mut tcp = Tcp::new_ack(src, dst, sequence, acknowledgement, window);
+        tcp.layer.flags |= TcpFlags::SYN;
+        tcp
+    }
+
+    /// Creates a `Tcp` represents a TCP ACK/RST.
+    pub fn new_ack_rst(
+        src: u16,
+        dst: u16,
+        sequence: u32,
+        acknowledgement: u32,
+        window: u16,
+    ) -> Tcp {
+        let mut tcp = Tcp::new_rst(src, dst, sequence, acknowledgement, window);
+        tcp.layer.flags |= TcpFlags::ACK;
+        tcp
+    }
+
+    /// Creates a `Tcp` represents a TCP ACK/FIN.
+    pub fn new_ack_fin(
+        src: u16,
+        dst: u16,
+        sequence: u32,
+        acknowledgement: u32,
+        window: u16,
+    ) -> Tcp {
+        let mut tcp = Tcp::new_ack(src, dst, sequence, acknowledgement, window);
+        tcp.layer.flags |= TcpFlags::FIN;
+        tcp
+    }
+
+    /// Creates a `Tcp` represents a TCP SYN.
+    pub fn new_syn(src: u16, dst: u16, sequence: u32, window: u16) -> Tcp {
+        let mut tcp = Tcp::new_ack(src, dst, sequence, 0, window);
+        tcp.layer.flags = TcpFlags::SYN;
+        tcp
+    }
+
+    /// Creates a `Tcp` represents a TCP RST.
+    pub fn new_rst(src: u16, dst: u16, sequence: u32, acknowledgement: u32, window: u16) -> Tcp {
+        let mut tcp = Tcp::new_ack(src, dst, sequence, acknowledgement, window);
+        tcp.layer.flags = TcpFlags::RST;
+        tcp
+    }
+
+    /// Creates a `Tcp` according to the given `Tcp`. Options of the given `Tcp` are replaced by
+    /// the `TcpOption`s of the layer.
+    pub fn from(tcp: tcp::Tcp) -> Tcp {
+        let mut d_tcp = tcp;
+        d_tcp.options = vec![];
+        // Options are carried by the layer, the data offset is fixed in serialization
+        d_tcp.data_offset = (TcpPacket::minimum_packet_size() / 4) as u8;
+        Tcp {
+            layer: d_tcp,
+            src: Ipv4Addr::UNSPECIFIED,
+            dst: Ipv4Addr::UNSPECIFIED,
+            ipv6: None,
+            options: vec![],
+            payload: vec![],
+            checksum_offload: false,
+            is_checksum_maintained: false,
+        }
+    }
+
+    /// Creates a `Tcp` according to the given TCP packet and the `Ipv4`.
+    pub fn parse(packet: &TcpPacket, ipv4: &Ipv4) -> Tcp {
+        let d_tcp = tcp::Tcp {
+            source: packet.get_source(),
+            destination: packet.get_destination(),
+            sequence: packet.get_sequence(),
+            acknowledgement: packet.get_acknowledgement(),
+            data_offset: packet.get_data_offset(),
+            reserved: packet.get_reserved(),
+            flags: packet.get_flags(),
+            window: packet.get_window(),
+            checksum: packet.get_checksum(),
+            urgent_ptr: packet.get_urgent_ptr(),
+            options: vec![],
+            payload: vec![],
+        };
+        let mut tcp = Tcp::from(d_tcp);
+        tcp.options = TcpOption::parse_options(packet.get_options_raw());
+        tcp.payload = packet.payload().to_vec();
+        tcp.set_ipv4_layer(ipv4);
+        tcp.is_checksum_maintained = tcp.is_options_intact(packet.get_options_raw());
+
+        tcp
+    }
+
+    /// Deserializes a `Tcp` from the given byte-array and returns it with the number of bytes
+    /// consumed. The source and destination IP address of the layer are left unspecified.
+    pub fn deserialize(buffer: &[u8]) -> Result<(Tcp, usize), ParseError> {
+        let (mut tcp, header_length) = Tcp::deserialize_header(buffer)?;
+        tcp.payload = buffer[header_length..].to_vec();
+
+        Ok((tcp, header_length))
+    }
+
+    /// Deserializes a `Tcp` from the given byte-array without copying its payload, and returns it
+    /// with the number of bytes consumed. The payload of the layer is left empty.
+    pub fn deserialize_header(buffer: &[u8]) -> Result<(Tcp, usize), ParseError> {
+        if buffer.len() < Tcp::min_size() {
+            return Err(ParseError::Truncated(LayerTypes::Tcp));
+        }
+        let packet = TcpPacket::new(buffer).ok_or(ParseError::Truncated(LayerTypes::Tcp))?;
+        let header_length = packet.get_data_offset() as usize * 4;
+        if header_length < TcpPacket::minimum_packet_size() {
+            return Err(ParseError::InvalidValue(LayerTypes::Tcp, "data offset"));
+        }
+        if buffer.len() < header_length {
+            return Err(ParseError::Truncated(LayerTypes::Tcp));
+        }
+        let d_tcp = tcp::Tcp {
+            source: packet.get_source(),
+            destination: packet.get_destination(),
+            sequence: packet.get_sequence(),
+            acknowledgement: packet.get_acknowledgement(),
+            data_offset: packet.get_data_offset(),
+            reserved: packet.get_reserved(),
+            flags: packet.get_flags(),
+            window: packet.get_window(),
+            checksum: packet.get_checksum(),
+            urgent_ptr: packet.get_urgent_ptr(),
+            options: vec![],
+            payload: vec![],
+        };
+        let mut tcp = Tcp::from(d_tcp);
+        tcp.options = TcpOption::parse_options(packet.get_options_raw());
+        tcp.is_checksum_maintained = tcp.is_options_intact(packet.get_options_raw());
+
+        Ok((tcp, header_length))
+    }
+
+    /// Returns if the options of the layer cover all of the given raw options. Malformed options
+    /// and the bytes following them are dropped in parsing, so the checksum has to be recomputed.
+    fn is_options_intact(&self, options_raw: &[u8]) -> bool {
+        let options_size: usize = self.options.iter().map(|option| option.get_size()).sum();
+
+        options_size == options_raw.len()
+    }
+
+    /// Get the payload of the layer when the layer is parsed or deserialized.
+    pub fn get_payload(&self) -> &[u8] {
+        &self.payload
+    }
+
+    /// Sets the options of the layer.
+    pub fn set_options(&mut self, options: Vec<TcpOption>) {
+        self.is_checksum_maintained = false;
+        self.options = options;
+    }
+
+    /// Get the options of the layer.
+    pub fn get_options(&self) -> &[TcpOption] {
+        &self.options
+    }
+
+    /// Get the checksum of the layer.
+    pub fn get_checksum(&self) -> u16 {
+        self.layer.checksum
+    }
+
+    /// Get the maximum segment size option of the layer.
+    pub fn get_mss(&self) -> Option<u16> {
+        self.options.iter().find_map(|option| match option {
+            TcpOption::MaximumSegmentSize(mss) => Some(*mss),
+            _ => None,
+        })
+    }
+
+    /// Clamps the maximum segment size option of a SYN to the given value. The option is
+    /// rewritten if it exceeds the value, or inserted if it is absent. Returns if the option is
+    /// changed. The checksum is updated incrementally if the option is rewritten, and is
+    /// recomputed in serialization if the option is inserted.
+    pub fn clamp_mss(&mut self, mss: u16) -> bool {
+        if !self.is_syn() {
+            return false;
+        }
+
+        // Offset of the option value in the header
+        let mut offset = TcpPacket::minimum_packet_size() + 2;
+        for option in &mut self.options {
+            if let TcpOption::MaximumSegmentSize(value) = option {
+                if *value > mss {
+                    let (old_word, new_word) = match offset % 2 {
+                        0 => (*value, mss),
+                        _ => (value.swap_bytes(), mss.swap_bytes()),
+                    };
+                    self.layer.checksum =
+                        incremental_update(self.layer.checksum, old_word, new_word);
+                    *value = mss;
+                    return true;
+                }
+                return false;
+            }
+            offset += option.get_size();
+        }
+
+        // Insert before other options
+        self.options.insert(0, TcpOption::MaximumSegmentSize(mss));
+        self.is_checksum_maintained = false;
+
+        true
+    }
+
+    /// Get the window scale option of the layer.
+    pub fn get_window_scale(&self) -> Option<u8> {
+        self.options.iter().find_map(|option| match option {
+            TcpOption::WindowScale(wscale) => Some(*wscale),
+            _ => None,
+        })
+    }
+
+    /// Sets the window scale option of the layer. The option is removed if `wscale` is `None`. A
+    /// shift count of 0 is still emitted, which signals the support of window scaling. The shift
+    /// count is limited to 14.
+    pub fn set_window_scale(&mut self, wscale: Option<u8>) {
+        self.is_checksum_maintained = false;
+        // Remove the option with its preceding padding
+        while let Some(i) = self
+            .options
+            .iter()
+            .position(|option| matches!(option, TcpOption::WindowScale(_)))
+        {
+            self.options.remove(i);
+            if i > 0 && self.options[i - 1] == TcpOption::NoOperation {
+                self.options.remove(i - 1);
+            }
+        }
+
+        if let Some(wscale) = wscale {
+            // Aligned to 4 bytes
+            self.options.push(TcpOption::NoOperation);
+            self.options
+                .push(TcpOption::WindowScale(min(wscale, MAX_WINDOW_SCALE)));
+        }
+    }
+
+    /// Returns if the layer contains the selective acknowledgement permitted option.
+    pub fn is_sack_permitted(&self) -> bool {
+        self.options.contains(&TcpOption::SackPermitted)
+    }
+
+    /// Sets the selective acknowledgement permitted option of the layer.
+    pub fn set_sack_permitted(&mut self, permitted: bool) {
+        self.is_checksum_maintained = false;
+        self.options
+            .retain(|option| *option != TcpOption::SackPermitted);
+        if permitted {
+            self.options.push(TcpOption::SackPermitted);
+        }
+    }
+
+    /// Get the blocks of the selective acknowledgement option of the layer.
+    pub fn get_sack_blocks(&self) -> &[(u32, u32)] {
+        self.options
+            .iter()
+            .find_map(|option| match option {
+                TcpOption::Sack(blocks) => Some(blocks.as_slice()),
+                _ => None,
+            })
+            .unwrap_or(&[])
+    }
+
+    /// Sets the blocks of the selective acknowledgement option of the layer. The option is
+    /// removed if `blocks` is empty. Blocks which do not fit in the options are dropped from the
+    /// end.
+    pub fn set_sack_blocks(&mut self, blocks: &[(u32, u32)]) {
+        self.is_checksum_maintained = false;
+        // Remove the option with its preceding padding
+        while let Some(i) = self
+            .options
+            .iter()
+            .position(|option| matches!(option, TcpOption::Sack(_)))
+        {
+            self.options.remove(i);
+            let mut i = i;
+            while i > 0 && self.options[i - 1] == TcpOption::NoOperation {
+                self.options.remove(i - 1);
+                i -= 1;
+            }
+        }
+
+        let size: usize = self.options.iter().map(|option| option.get_size()).sum();
+        let n = min(
+            blocks.len(),
+            MAX_OPTIONS_SIZE.saturating_sub(size + 4) / SACK_BLOCK_SIZE,
+        );
+        if n > 0 {
+            // Aligned to 4 bytes
+            self.options.push(TcpOption::NoOperation);
+            self.options.push(TcpOption::NoOperation);
+            self.options.push(TcpOption::Sack(blocks[..n].to_vec()));
+        }
+    }
+
+    /// Get the timestamp value and the timestamp echo reply of the timestamps option of the
+    /// layer.
+    pub fn get_timestamps(&self) -> Option<(u32, u32)> {
+        self.options.iter().find_map(|option| match option {
+            TcpOption::Timestamps(tsval, tsecr) => Some((*tsval, *tsecr)),
+            _ => None,
+        })
+    }
+
+    /// Sets the timestamps option of the layer with the timestamp value and the timestamp echo
+    /// reply. The option is removed if `timestamps` is `None`. The option is preceded by 2 NOPs
+    /// for the alignment, and should be set before the selective acknowledgement option so the
+    /// blocks are fit in the remaining space.
+    pub fn set_timestamps(&mut self, timestamps: Option<(u32, u32)>) {
+        self.is_checksum_maintained = false;
+        // Remove the option with its preceding padding
+        while let Some(i) = self
+            .options
+            .iter()
+            .position(|option| matches!(option, TcpOption::Timestamps(_, _)))
+        {
+            self.options.remove(i);
+            let mut i = i;
+            while i > 0 && self.options[i - 1] == TcpOption::NoOperation {
+                self.options.remove(i - 1);
+                i -= 1;
+            }
+        }
+
+        if let Some((tsval, tsecr)) = timestamps {
+            // Aligned to 4 bytes
+            self.options.push(TcpOption::NoOperation);
+            self.options.push(TcpOption::NoOperation);
+            self.options.push(TcpOption::Timestamps(tsval, tsecr));
+        }
+    }
+
+    /// Get the cookie of the fast open option of the layer. The cookie is empty if the option is
+    /// a cookie request.
+    pub fn get_fast_open_cookie(&self) -> Option<&[u8]> {
+        self.options.iter().find_map(|option| match option {
+            TcpOption::FastOpen(cookie) => Some(cookie.as_slice()),
+            _ => None,
+        })
+    }
+
+    /// Sets the fast open option of the layer with the cookie, or an empty cookie for a cookie
+    /// request. The option is removed if `cookie` is `None`. The option is preceded by 2 NOPs for
+    /// the alignment.
+    pub fn set_fast_open_cookie(&mut self, cookie: Option<&[u8]>) {
+        self.is_checksum_maintained = false;
+        // Remove the option with its preceding padding
+        while let Some(i) = self
+            .options
+            .iter()
+            .position(|option| matches!(option, TcpOption::FastOpen(_)))
+        {
+            self.options.remove(i);
+            let mut i = i;
+            while i > 0 && self.options[i - 1] == TcpOption::NoOperation {
+                self.options.remove(i - 1);
+                i -= 1;
+            }
+        }
+
+        if let Some(cookie) = cookie {
+            // Aligned to 4 bytes
+            self.options.push(TcpOption::NoOperation);
+            self.options.push(TcpOption::NoOperation);
+            self.options.push(TcpOption::FastOpen(
+                cookie[..min(cookie.len(), MAX_FAST_OPEN_COOKIE_SIZE)].to_vec(),
+            ));
+        }
+    }
+
+    fn serialize_options(&self, buffer: &mut [u8]) -> io::Result<()> {
+        let mut begin = 0;
+        for option in &self.options {
+            begin += option.serialize(&mut buffer[begin..])?;
+        }
+        // Padding
+        for b in &mut buffer[begin..] {
+            *b = 0;
+        }
+
+        Ok(())
+    }
+
+    /// Returns if the pseudo-header of the given source and destination IP address differs from
+    /// the one of the layer. A layer without IP addresses takes any pseudo-header.
+    fn is_pseudo_header_changed(&self, src: IpAddr, dst: IpAddr) -> bool {
+        match (src, dst) {
+            (IpAddr::V4(src), IpAddr::V4(dst)) => {
+                self.ipv6.is_some()
+                    || (!self.src.is_unspecified() && (self.src, self.dst) != (src, dst))
+            }
+            (IpAddr::V6(src), IpAddr::V6(dst)) => self
+                .ipv6
+                .map_or(!self.src.is_unspecified(), |ipv6| ipv6 != (src, dst)),
+            _ => true,
+        }
+    }
+
+    /// Sets the source and destination IP address for the layer with the given `Ipv4`.
+    pub fn set_ipv4_layer(&mut self, ipv4: &Ipv4) {
+        if self.is_pseudo_header_changed(IpAddr::V4(ipv4.get_src()), IpAddr::V4(ipv4.get_dst())) {
+            self.is_checksum_maintained = false;
+        }
+        self.src = ipv4.get_src();
+        self.dst = ipv4.get_dst();
+        self.ipv6 = None;
+    }
+
+    /// Sets the source and destination IP address for the layer with the given `Ipv6`. The
+    /// checksum of the layer will be computed with the pseudo-header of IPv6.
+    pub fn set_ipv6_layer(&mut self, ipv6: &Ipv6) {
+        let addrs = (ipv6.get_src(), ipv6.get_dst());
+        if self.is_pseudo_header_changed(IpAddr::V6(addrs.0), IpAddr::V6(addrs.1)) {
+            self.is_checksum_maintained = false;
+        }
+        self.ipv6 = Some(addrs);
+    }
+
+    /// Computes the checksum of the layer with the given payload, including the pseudo-header
+    /// of the given source and destination IP address. Returns an error if the source and the
+    /// destination are of different address families, or the options cannot be serialized.
+    pub fn compute_checksum(&self, src: IpAddr, dst: IpAddr, payload: &[u8]) -> io::Result<u16> {
+        let header_length = self.get_size();
+        let mut buffer = vec![0u8; header_length + payload.len()];
+        let mut packet = MutableTcpPacket::new(&mut buffer).unwrap();
+
+        packet.populate(&self.layer);
+        packet.set_data_offset((header_length / 4) as u8);
+        self.serialize_options(packet.get_options_raw_mut())?;
+        packet.set_payload(payload);
+
+        match (src, dst) {
+            (IpAddr::V4(src), IpAddr::V4(dst)) => {
+                Ok(tcp::ipv4_checksum(&packet.to_immutable(), &src, &dst))
+            }
+            (IpAddr::V6(src), IpAddr::V6(dst)) => {
+                Ok(tcp::ipv6_checksum(&packet.to_immutable(), &src, &dst))
+            }
+            _ => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "address families mismatch",
+            )),
+        }
+    }
+
+    /// Computes the checksum of the given serialized segment in place, including the
+    /// pseudo-header of the IP addresses of the layer. The checksum maintained is kept.
+    fn compute_checksum_in_place(&self, packet: &TcpPacket) -> u16 {
+        if self.checksum_offload {
+            return 0;
+        }
+        if self.is_checksum_maintained {
+            return self.layer.checksum;
+        }
+
+        match self.ipv6 {
+            Some((ref src, ref dst)) => tcp::ipv6_checksum(packet, src, dst),
+            None => tcp::ipv4_checksum(packet, &self.src, &self.dst),
+        }
+    }
+
+    /// Computes the checksum of the given serialized segment carrying its payload in place,
+    /// including the pseudo-header of the given source and destination IP address, or of the IP
+    /// addresses of the layer if `None`. The checksum maintained is kept if the pseudo-header is
+    /// unchanged.
+    pub fn patch_checksum(
+        &self,
+        buffer: &mut [u8],
+        addrs: Option<(IpAddr, IpAddr)>,
+    ) -> io::Result<()> {
+        let mut packet = MutableTcpPacket::new(buffer)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::WriteZero, "buffer too small"))?;
+
+        let checksum = match addrs {
+            None => self.compute_checksum_in_place(&packet.to_immutable()),
+            Some(_) if self.checksum_offload => 0,
+            Some((src, dst))
+                if self.is_checksum_maintained && !self.is_pseudo_header_changed(src, dst) =>
+            {
+                self.layer.checksum
+            }
+            Some((IpAddr::V4(src), IpAddr::V4(dst))) => {
+                tcp::ipv4_checksum(&packet.to_immutable(), &src, &dst)
+            }
+            Some((IpAddr::V6(src), IpAddr::V6(dst))) => {
+                tcp::ipv6_checksum(&packet.to_immutable(), &src, &dst)
+            }
+            Some(_) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "address families mismatch",
+                ))
+            }
+        };
+        packet.set_checksum(checksum);
+
+        Ok(())
+    }
+
+    /// Sets if the checksum is left to the checksum offload of the link. The checksum will be
+    /// written as 0 if it is offloaded, and the NIC or the kernel fills it.
+    pub fn set_checksum_offload(&mut self, offload: bool) {
+        self.checksum_offload = offload;
+    }
+
+    /// Get the source IP address of the layer.
+    pub fn get_src_ip_addr(&self) -> Ipv4Addr {
+        self.src
+    }
+
+    /// Get the destination IP address of the layer.
+    pub fn get_dst_ip_addr(&self) -> Ipv4Addr {
+        self.dst
+    }
+
+    /// Get the source of the layer.
+    pub fn get_src(&self) -> u16 {
+        self.layer.source
+    }
+
+    /// Get the destination of the layer.
+    pub fn get_dst(&self) -> u16 {
+        self.layer.destination
+    }
+
+    /// Get the sequence of the layer.
+    pub fn get_sequence(&self) -> u32 {
+        self.layer.sequence
+    }
+
+    /// Get the acknowledgement of the layer.
+    pub fn get_acknowledgement(&self) -> u32 {
+        self.layer.acknowledgement
+    }
+
+    /// Sets the source and destination port of the layer.
+    pub fn set_ports(&mut self, src: u16, dst: u16) {
+        self.is_checksum_maintained = false;
+        self.layer.source = src;
+        self.layer.destination = dst;
+    }
+
+    /// Sets the source of the layer.
+    pub fn set_src(&mut self, src: u16) {
+        self.is_checksum_maintained = false;
+        self.layer.source = src;
+    }
+
+    /// Sets the destination of the layer.
+    pub fn set_dst(&mut self, dst: u16) {
+        self.is_checksum_maintained = false;
+        self.layer.destination = dst;
+    }
+
+    /// Get the flags of the layer.
+    pub fn get_flags(&self) -> Flags {
+        Flags::from_bits_truncate(self.layer.flags)
+    }
+
+    /// Sets the flags of the layer. The urgent pointer is cleared if the URG flag is not set.
+    pub fn set_flags(&mut self, flags: Flags) {
+        self.is_checksum_maintained = false;
+        self.layer.flags = flags.bits();
+        if !flags.contains(Flags::URG) {
+            self.layer.urgent_ptr = 0;
+        }
+    }
+
+    /// Sets or clears the given flags of the layer.
+    pub fn set_flag(&mut self, flag: Flags, value: bool) {
+        let mut flags = self.get_flags();
+        flags.set(flag, value);
+        self.set_flags(flags);
+    }
+
+    /// Sets the acknowledgement of the layer.
+    pub fn set_acknowledgement(&mut self, acknowledgement: u32) {
+        self.is_checksum_maintained = false;
+        self.layer.acknowledgement = acknowledgement;
+    }
+
+    /// Sets the window size of the layer.
+    pub fn set_window(&mut self, window: u16) {
+        self.is_checksum_maintained = false;
+        self.layer.window = window;
+    }
+
+    /// Get the urgent pointer of the layer.
+    pub fn get_urgent_pointer(&self) -> u16 {
+        self.layer.urgent_ptr
+    }
+
+    /// Sets the urgent pointer of the layer, or `None` for clearing the URG flag.
+    pub fn set_urgent_pointer(&mut self, urgent_ptr: Option<u16>) {
+        self.is_checksum_maintained = false;
+        match urgent_ptr {
+            Some(urgent_ptr) => {
+                self.layer.flags |= TcpFlags::URG;
+                self.layer.urgent_ptr = urgent_ptr;
+            }
+            None => {
+                self.layer.flags &= !TcpFlags::URG;
+                self.layer.urgent_ptr = 0;
+            }
+        }
+    }
+
+    /// Get the sequence following the last byte of the urgent data with the given interpretation
+    /// of the urgent pointer. Returns `None` if the URG flag is not set.
+    pub fn get_urgent_end(&self, interpretation: UrgentPointer) -> Option<u32> {
+        match self.is_urg() {
+            true => Some(
+                self.layer
+                    .sequence
+                    .wrapping_add(self.layer.urgent_ptr as u32)
+                    .wrapping_add(interpretation.get_offset()),
+            ),
+            false => None,
+        }
+    }
+
+    /// Sets the urgent pointer of the layer pointing to the given sequence following the last
+    /// byte of the urgent data with the given interpretation of the urgent pointer. The URG flag
+    /// is cleared if the urgent data ends at or before the sequence of the layer, and the urgent
+    /// pointer is saturated if the urgent data ends beyond its range (RFC 6093).
+    pub fn set_urgent_end(&mut self, end: Option<u32>, interpretation: UrgentPointer) {
+        self.is_checksum_maintained = false;
+        let urgent_ptr = end.and_then(|end| {
+            let distance = end.wrapping_sub(self.layer.sequence);
+            match state::sequence_lt(self.layer.sequence, end) {
+                true => distance.checked_sub(interpretation.get_offset()),
+                false => None,
+            }
+        });
+
+        self.set_urgent_pointer(
+            urgent_ptr.map(|urgent_ptr| min(urgent_ptr, u16::MAX as u32) as u16),
+        );
+    }
+
+    /// Get the string represents the flags of the layer.
+    pub fn get_flag_string(&self) -> String {
+        let mut flags = String::from("[");
+        if self.is_urg() {
+            flags += "U";
+        }
+        if self.is_syn() {
+            flags = flags + "S";
+        }
+        if self.is_rst() {
+            flags = flags + "R";
+        }
+        if self.is_fin() {
+            flags = flags + "F";
+        }
+        if self.is_psh() {
+            flags = flags + "P";
+        }
+        if self.is_ack() {
+            flags = flags + ".";
+        }
+        if self.is_ece() {
+            flags += "E";
+        }
+        if self.is_cwr() {
+            flags += "W";
+        }
+        flags = flags + "]";
+
+        flags
+    }
+
+    /// Get the window size of the layer.
+    pub fn get_window(&self) -> u16 {
+        self.layer.window
+    }
+
+    /// Returns if the `Tcp` is a TCP acknowledgement.
+    pub fn is_ack(&self) -> bool {
+        self.layer.flags & TcpFlags::ACK != 0
+    }
+
+    /// Returns if the `Tcp` is a TCP acknowledgement and finish.
+    pub fn is_ack_fin(&self) -> bool {
+        self.is_ack() && self.is_fin()
+    }
+
+    /// Returns if the `Tcp` is a TCP reset.
+    pub fn is_rst(&self) -> bool {
+        self.layer.flags & TcpFlags::RST != 0
+    }
+
+    /// Returns if the `Tcp` is a TCP synchronization.
+    pub fn is_syn(&self) -> bool {
+        self.layer.flags & TcpFlags::SYN != 0
+    }
+
+    /// Returns if the `Tcp` is a TCP finish.
+    pub fn is_fin(&self) -> bool {
+        self.layer.flags & TcpFlags::FIN != 0
+    }
+
+    /// Returns if the `Tcp` is a TCP push.
+    pub fn is_psh(&self) -> bool {
+        self.layer.flags & TcpFlags::PSH != 0
+    }
+
+    /// Returns if the `Tcp` carries urgent data.
+    pub fn is_urg(&self) -> bool {
+        self.layer.flags & TcpFlags::URG != 0
+    }
+
+    /// Returns if the `Tcp` echoes an ECN congestion experienced mark.
+    pub fn is_ece(&self) -> bool {
+        self.layer.flags & TcpFlags::ECE != 0
+    }
+
+    /// Returns if the `Tcp` reduces the congestion window in response to an ECN echo.
+    pub fn is_cwr(&self) -> bool {
+        self.layer.flags & TcpFlags::CWR != 0
+    }
+
+    /// Returns if the `Tcp` is an ECN-setup SYN, which sets both the ECE and CWR flags
+    /// (RFC 3168).
+    pub fn is_ecn_setup_syn(&self) -> bool {
+        self.is_syn() && !self.is_ack() && self.is_ece() && self.is_cwr()
+    }
+
+    /// Returns if the `Tcp` is an ECN-setup ACK/SYN, which sets the ECE flag but not the CWR flag
+    /// (RFC 3168).
+    pub fn is_ecn_setup_ack_syn(&self) -> bool {
+        self.is_syn() && self.is_ack() && self.is_ece() && !self.is_cwr()
+    }
+
+    /// Sets the ECE flag of the layer.
+    pub fn set_ece(&mut self, ece: bool) {
+        self.is_checksum_maintained = false;
+        match ece {
+            true => self.layer.flags |= TcpFlags::ECE,
+            false => self.layer.flags &= !TcpFlags::ECE,
+        }
+    }
+
+    /// Returns if the `Tcp` is a TCP reset or finish.
+    pub fn is_rst_or_fin(&self) -> bool {
+        self.is_rst() || self.is_fin()
+    }
+
+    // Returns if the `Tcp` has zero window.
+    pub fn is_zero_window(&self) -> bool {
+        self.layer.window == 0
+    }
+}
+
+/// Layers are compared by their fields, regardless of if their checksums are maintained.
+impl PartialEq for Tcp {
+    fn eq(&self, other: &Tcp) -> bool {
+        self.layer.source == other.layer.source
+            && self.layer.destination == other.layer.destination
+            && self.layer.sequence == other.layer.sequence
+            && self.layer.acknowledgement == other.layer.acknowledgement
+            && self.layer.data_offset == other.layer.data_offset
+            && self.layer.reserved == other.layer.reserved
+            && self.layer.flags == other.layer.flags
+            && self.layer.window == other.layer.window
+            && self.layer.checksum == other.layer.checksum
+            && self.layer.urgent_ptr == other.layer.urgent_ptr
+            && self.src == other.src
+            && self.dst == other.dst
+            && self.ipv6 == other.ipv6
+            && self.options == other.options
+            && self.payload == other.payload
+            && self.checksum_offload == other.checksum_offload
+    }
+}
+
+impl Display for Tcp {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        if f.alternate() {
+            return fmt_hex_dump(self, f);
+        }
+
+        write!(
+            f,
+            "{}: {} -> {} {}",
+            LayerTypes::Tcp,
+            self.layer.source,
+            self.layer.destination,
+            self.get_flag_string()
+        )
+    }
+}
+
+impl SizeBounds for Tcp {
+    fn min_size() -> usize {
+        TcpPacket::minimum_packet_size()
+    }
+
+    fn max_size() -> usize {
+        TcpPacket::minimum_packet_size() + MAX_OPTIONS_SIZE
+    }
+}
+
+impl Layer for Tcp {
+    fn get_type(&self) -> LayerType {
+        LayerTypes::Tcp
+    }
+
+    fn get_size(&self) -> usize {
+        let options_size: usize = self.options.iter().map(|option| option.get_size()).sum();
+
+        // Pad options to 4 bytes
+        TcpPacket::minimum_packet_size() + (options_size + 3) / 4 * 4
+    }
+
+    fn serialize(&self, buffer: &mut [u8], _: usize) -> io::Result<usize> {
+        let header_length = self.get_size();
+        if header_length / 4 > 15 {
+            return Err(io::Error::new(io::ErrorKind::Other, "TCP too big"));
+        }
+        if buffer.len() < header_length {
+            return Err(io::Error::new(io::ErrorKind::WriteZero, "buffer too small"));
+        }
+        let mut packet = MutableTcpPacket::new(&mut buffer[..header_length])
+            .ok_or_else(|| io::Error::new(io::ErrorKind::WriteZero, "buffer too small"))?;
+
+        packet.populate(&self.layer);
+
+        // Fix length
+        packet.set_data_offset((header_length / 4) as u8);
+
+        // Options
+        self.serialize_options(packet.get_options_raw_mut())?;
+
+        // Compute checksum
+        let checksum = self.compute_checksum_in_place(&packet.to_immutable());
+        packet.set_checksum(checksum);
+
+        Ok(header_length)
+    }
+
+    fn serialize_with_payload(
+        &self,
+        buffer: &mut [u8],
+        payload: &[u8],
+        _: usize,
+    ) -> io::Result<usize> {
+        let header_length = self.get_size();
+        if header_length / 4 > 15 {
+            return Err(io::Error::new(io::ErrorKind::Other, "TCP too big"));
+        }
+        if buffer.len() < header_length + payload.len() {
+            return Err(io::Error::new(io::ErrorKind::WriteZero, "buffer too small"));
+        }
+        let mut packet = MutableTcpPacket::new(&mut buffer[..header_length + payload.len()])
+            .ok_or_else(|| io::Error::new(io::ErrorKind::WriteZero, "buffer too small"))?;
+
+        packet.populate(&self.layer);
+
+        // Fix length
+        packet.set_data_offset((header_length / 4) as u8);
+
+        // Options
+        self.serialize_options(packet.get_options_raw_mut())?;
+
+        // Copies payload
+        packet.set_payload(payload);
+
+        // Compute checksum in place
+        let checksum = self.compute_checksum_in_place(&packet.to_immutable());
+        packet.set_checksum(checksum);
+
+        Ok(header_length + payload.len())
+    }
+
+    fn clone_boxed(&self) -> Box<dyn Layer> {
+        Box::new(self.clone())
+    }
+}
+
+/// Builds a TCP RST in reply to the given segment with the given length of payload, which is
+/// sent from `src` to `dst`. The RST carries the acknowledgement of the segment as its sequence if
+/// the segment has the ACK flag, or acknowledges the segment with a sequence of 0 otherwise.
+pub fn build_rst(segment: &Tcp, payload_length: usize, src: Ipv4Addr, dst: Ipv4Addr) -> Layers {
+    let mut tcp = match segment.is_ack() {
+        true => Tcp::new_rst(
+            segment.get_dst(),
+            segment.get_src(),
+            segment.get_acknowledgement(),
+            0,
+            0,
+        ),
+        false => {
+            // SYN and FIN occupy a sequence
+            let length = payload_length + segment.is_syn() as usize + segment.is_fin() as usize;
+            Tcp::new_ack_rst(
+                segment.get_dst(),
+                segment.get_src(),
+                0,
+                segment.get_sequence().wrapping_add(length as u32),
+                0,
+            )
+        }
+    };
+    tcp.src = src;
+    tcp.dst = dst;
+
+    Layers::Tcp(tcp)
+}
+
+/// Splits the given data into TCP ACKs carrying at most `mss` bytes of payload each, starting
+/// from the given sequence. The sequence wraps around at `u32::MAX`, and the last segment is
+/// pushed. Ports, acknowledgement and window of the segments are left unspecified.
+pub fn segment(data: &[u8], mss: usize, start_seq: u32) -> Vec<Tcp> {
+    let mss = max(mss, 1);
+
+    let mut segments: Vec<Tcp> = data
+        .chunks(mss)
+        .enumerate()
+        .map(|(i, chunk)| {
+            let sequence = start_seq.wrapping_add((i * mss) as u32);
+            let mut tcp = Tcp::new_ack(0, 0, sequence, 0, 0);
+            tcp.payload = chunk.to_vec();
+            tcp
+        })
+        .collect();
+    if let Some(tcp) = segments.last_mut() {
+        tcp.layer.flags |= TcpFlags::PSH;
+    }
+
+    segments
+}
+
+/// Marks the segments carrying the urgent data which ends at the given sequence. Each segment
+/// before the end points its urgent pointer to the end with the given interpretation of the
+/// urgent pointer, as the urgent data may be split across segments.
+pub fn mark_urgent(segments: &mut [Tcp], end: u32, interpretation: UrgentPointer) {
+    for tcp in segments {
+        tcp.set_urgent_end(Some(end), interpretation);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn deserialize_serialized() {
+        let mut tcp = Tcp::new_ack(1024, 80, 100, 200, 65535);
+        tcp.set_window_scale(Some(7));
+        let mut buffer = vec![0u8; tcp.get_size()];
+        let n = tcp.serialize(&mut buffer, tcp.get_size()).unwrap();
+
+        // The checksum is only known once serialized
+        let (deserialized, size) = Tcp::deserialize(&buffer).unwrap();
+        assert_eq!(size, n);
+        assert_eq!(deserialized.get_window_scale(), Some(7));
+        let mut reserialized = vec![0u8; deserialized.get_size()];
+        deserialized
+            .serialize(&mut reserialized, deserialized.get_size())
+            .unwrap();
+        assert_eq!(reserialized, buffer);
+        assert_eq!(Tcp::deserialize(&reserialized).unwrap().0, deserialized);
+    }
+
+    #[test]
+    fn deserialize_truncated() {
+        assert!(matches!(
+            Tcp::deserialize(&[0u8; 19]),
+            Err(ParseError::Truncated(LayerTypes::Tcp))
+        ));
+    }
+
+    #[test]
+    fn compute_checksum_ipv4() {
+        let tcp = Tcp::new_ack(1024, 80, 1, 1, 4096);
+        let checksum = tcp
+            .compute_checksum(
+                IpAddr::V4(Ipv4Addr::new(192, 168, 1, 1)),
+                IpAddr::V4(Ipv4Addr::new(192, 168, 1, 2)),
+                &[],
+            )
+            .unwrap();
+
+        assert_eq!(checksum, 0x182f);
+    }
+
+    #[test]
+    fn compute_checksum_ipv6() {
+        let tcp = Tcp::new_ack(1024, 80, 1, 1, 4096);
+        let checksum = tcp
+            .compute_checksum(
+                IpAddr::V6("2001:db8::1".parse().unwrap()),
+                IpAddr::V6("2001:db8::2".parse().unwrap()),
+                &[],
+            )
+            .unwrap();
+
+        assert_eq!(checksum, 0x400e);
+    }
+
+    #[test]
+    fn compute_checksum_mismatched() {
+        let tcp = Tcp::new_ack(1024, 80, 1, 1, 4096);
+
+        assert!(tcp
+            .compute_checksum(
+                IpAddr::V4(Ipv4Addr::new(192, 168, 1, 1)),
+                IpAddr::V6("2001:db8::2".parse().unwrap()),
+                &[],
+            )
+            .is_err());
+    }
+
+    #[test]
+    fn deserialize_serialized_syn_options() {
+        let mut tcp = Tcp::new_syn(1024, 80, 100, 65535);
+        tcp.set_options(vec![
+            TcpOption::MaximumSegmentSize(1460),
+            TcpOption::WindowScale(7),
+            TcpOption::SackPermitted,
+        ]);
+        // 4 + 3 + 2 bytes of options are padded to 12 bytes
+        assert_eq!(tcp.get_size(), 32);
+        let mut buffer = vec![0u8; tcp.get_size()];
+        tcp.serialize(&mut buffer, tcp.get_size()).unwrap();
+        assert_eq!(buffer[12] >> 4, 8);
+
+        let (deserialized, size) = Tcp::deserialize(&buffer).unwrap();
+        assert_eq!(size, 32);
+        assert!(deserialized.is_syn());
+        assert_eq!(deserialized.get_mss(), Some(1460));
+        assert_eq!(deserialized.get_window_scale(), Some(7));
+        assert!(deserialized.is_sack_permitted());
+    }
+
+    #[test]
+    fn parse_options_malformed() {
+        // The length of the window scale option is truncated
+        let options = TcpOption::parse_options(&[0x02, 0x04, 0x05, 0xb4, 0x01, 0x03, 0x03]);
+
+        assert_eq!(
+            options,
+            [TcpOption::MaximumSegmentSize(1460), TcpOption::NoOperation]
+        );
+    }
+
+    #[test]
+    fn clamp_mss_oversized() {
+        let mut tcp = Tcp::new_syn(1024, 80, 100, 65535);
+        tcp.set_options(vec![
+            TcpOption::MaximumSegmentSize(1460),
+            TcpOption::WindowScale(7),
+        ]);
+
+        assert!(tcp.clamp_mss(1452));
+        let mut buffer = vec![0u8; tcp.get_size()];
+        tcp.serialize(&mut buffer, tcp.get_size()).unwrap();
+        let (deserialized, _) = Tcp::deserialize(&buffer).unwrap();
+        assert_eq!(deserialized.get_mss(), Some(1452));
+        assert_eq!(deserialized.get_window_scale(), Some(7));
+
+        // A smaller MSS is kept
+        assert!(!tcp.clamp_mss(1460));
+        assert_eq!(tcp.get_mss(), Some(1452));
+    }
+
+    #[test]
+    fn clamp_mss_absent() {
+        let mut tcp = Tcp::new_syn(1024, 80, 100, 65535);
+        tcp.set_options(vec![TcpOption::WindowScale(7)]);
+
+        assert!(tcp.clamp_mss(1452));
+        assert_eq!(tcp.get_options()[0], TcpOption::MaximumSegmentSize(1452));
+        assert_eq!(tcp.get_window_scale(), Some(7));
+    }
+
+    #[test]
+    fn clamp_mss_not_syn() {
+        let mut tcp = Tcp::new_ack(1024, 80, 1, 1, 4096);
+
+        assert!(!tcp.clamp_mss(1452));
+        assert_eq!(tcp.get_mss(), None);
+    }
+
+    #[test]
+    fn serialize_window_scale() {
+        for (wscale, expected) in [
+            (Some(0), Some([0x01, 0x03, 0x03, 0x00])),
+            (Some(7), Some([0x01, 0x03, 0x03, 0x07])),
+            (None, None),
+        ] {
+            let mut tcp = Tcp::new_ack_syn(80, 1024, 1000, 5001, 65535);
+            tcp.set_window_scale(wscale);
+            let mut buffer = vec![0u8; tcp.get_size()];
+            tcp.serialize(&mut buffer, tcp.get_size()).unwrap();
+
+            match expected {
+                Some(expected) => assert_eq!(buffer[20..], expected),
+                None => assert_eq!(buffer.len(), 20),
+            }
+        }
+    }
+
+    #[test]
+    fn set_window_scale_replace() {
+        let mut tcp = Tcp::new_ack_syn(80, 1024, 1000, 5001, 65535);
+        tcp.set_window_scale(Some(7));
+        tcp.set_window_scale(Some(MAX_WINDOW_SCALE + 1));
+
+        assert_eq!(tcp.get_window_scale(), Some(MAX_WINDOW_SCALE));
+        assert_eq!(tcp.get_size(), 24);
+    }
+
+    #[test]
+    fn build_rst_ack() {
+        let segment = Tcp::new_ack(1024, 80, 5001, 1001, 65535);
+        let src = Ipv4Addr::new(192, 168, 1, 2);
+        let dst = Ipv4Addr::new(192, 168, 1, 1);
+
+        let rst = match build_rst(&segment, 5, src, dst) {
+            Layers::Tcp(tcp) => tcp,
+            _ => unreachable!(),
+        };
+        assert!(rst.is_rst() && !rst.is_ack());
+        assert_eq!((rst.get_src(), rst.get_dst()), (80, 1024));
+        assert_eq!(rst.get_sequence(), 1001);
+        assert_eq!((rst.get_src_ip_addr(), rst.get_dst_ip_addr()), (src, dst));
+    }
+
+    #[test]
+    fn build_rst_no_ack() {
+        let mut segment = Tcp::new_ack_fin(1024, 80, 5001, 0, 65535);
+        segment.layer.flags = TcpFlags::FIN;
+        let src = Ipv4Addr::new(192, 168, 1, 2);
+        let dst = Ipv4Addr::new(192, 168, 1, 1);
+
+        let rst = match build_rst(&segment, 5, src, dst) {
+            Layers::Tcp(tcp) => tcp,
+            _ => unreachable!(),
+        };
+        assert!(rst.is_rst() && rst.is_ack());
+        assert_eq!(rst.get_sequence(), 0);
+        // The payload and the FIN are acknowledged
+        assert_eq!(rst.get_acknowledgement(), 5007);
+    }
+
+    #[test]
+    fn serialize_sack_blocks() {
+        let mut tcp = Tcp::new_ack(80, 1024, 1001, 5001, 65535);
+        tcp.set_sack_blocks(&[(5021, 5026), (5011, 5016)]);
+        let mut buffer = vec![0u8; tcp.get_size()];
+        tcp.serialize(&mut buffer, tcp.get_size()).unwrap();
+
+        #[rustfmt::skip]
+        assert_eq!(buffer[20..], [
+            0x01, 0x01, 0x05, 0x12,
+            0x00, 0x00, 0x13, 0x9d, 0x00, 0x00, 0x13, 0xa2,
+            0x00, 0x00, 0x13, 0x93, 0x00, 0x00, 0x13, 0x98,
+        ]);
+        let (deserialized, _) = Tcp::deserialize(&buffer).unwrap();
+        assert_eq!(deserialized.get_sack_blocks(), [(5021, 5026), (5011, 5016)]);
+    }
+
+    #[test]
+    fn set_sack_blocks_overflow() {
+        let blocks = [(100, 200), (300, 400), (500, 600), (700, 800), (900, 1000)];
+        let mut tcp = Tcp::new_ack(80, 1024, 1001, 5001, 65535);
+        tcp.set_sack_blocks(&blocks);
+        assert_eq!(tcp.get_sack_blocks(), &blocks[..4]);
+        assert_eq!(tcp.get_size(), 20 + 36);
+
+        // Fewer blocks fit with other options
+        tcp.set_options(vec![
+            TcpOption::MaximumSegmentSize(1460),
+            TcpOption::NoOperation,
+            TcpOption::WindowScale(7),
+        ]);
+        tcp.set_sack_blocks(&blocks);
+        assert_eq!(tcp.get_sack_blocks(), &blocks[..3]);
+
+        tcp.set_sack_blocks(&[]);
+        assert!(tcp.get_sack_blocks().is_empty());
+        assert_eq!(tcp.get_size(), 28);
+    }
+
+    #[test]
+    fn clamp_mss_incremental() {
+        let src = Ipv4Addr::new(192, 168, 1, 1);
+        let dst = Ipv4Addr::new(192, 168, 1, 2);
+        let ipv4 = Ipv4::new(1, LayerTypes::Tcp, src, dst).unwrap();
+        // MSS at an even and an odd offset
+        for options in [
+            vec![TcpOption::MaximumSegmentSize(1460)],
+            vec![
+                TcpOption::NoOperation,
+                TcpOption::MaximumSegmentSize(1460),
+                TcpOption::NoOperation,
+                TcpOption::NoOperation,
+                TcpOption::NoOperation,
+            ],
+        ] {
+            let mut tcp = Tcp::new_syn(1024, 80, 100, 65535);
+            tcp.set_options(options);
+            tcp.set_ipv4_layer(&ipv4);
+            let mut buffer = vec![0u8; tcp.get_size()];
+            tcp.serialize(&mut buffer, tcp.get_size()).unwrap();
+            let (mut tcp, _) = Tcp::deserialize(&buffer).unwrap();
+            tcp.set_ipv4_layer(&ipv4);
+
+            assert!(tcp.clamp_mss(1200));
+            let checksum = tcp
+                .compute_checksum(IpAddr::V4(src), IpAddr::V4(dst), &[])
+                .unwrap();
+            assert_eq!(tcp.get_checksum(), checksum);
+        }
+    }
+
+    #[test]
+    fn segment_mss() {
+        let data: Vec<u8> = (0..5000).map(|i| i as u8).collect();
+        let segments = segment(&data, 1460, 1000);
+
+        let sizes: Vec<usize> = segments.iter().map(|tcp| tcp.get_payload().len()).collect();
+        assert_eq!(sizes, [1460, 1460, 1460, 620]);
+        let sequences: Vec<u32> = segments.iter().map(|tcp| tcp.get_sequence()).collect();
+        assert_eq!(sequences, [1000, 2460, 3920, 5380]);
+        // Only the last segment is pushed
+        let pushes: Vec<bool> = segments.iter().map(|tcp| tcp.is_psh()).collect();
+        assert_eq!(pushes, [false, false, false, true]);
+        assert!(segments.iter().all(|tcp| tcp.is_ack()));
+
+        let concatenated: Vec<u8> = segments
+            .iter()
+            .flat_map(|tcp| tcp.get_payload().to_vec())
+            .collect();
+        assert_eq!(concatenated, data);
+    }
+
+    #[test]
+    fn segment_wraparound() {
+        let data = vec![0u8; 300];
+        let segments = segment(&data, 100, u32::MAX - 149);
+
+        let sequences: Vec<u32> = segments.iter().map(|tcp| tcp.get_sequence()).collect();
+        assert_eq!(sequences, [u32::MAX - 149, u32::MAX - 49, 50]);
+    }
+
+    #[test]
+    fn segment_edge_cases() {
+        assert!(segment(&[], 1460, 0).is_empty());
+
+        // A single segment is pushed
+        let segments = segment(b"hello", 1460, 0);
+        assert_eq!(segments.len(), 1);
+        assert!(segments[0].is_psh());
+        assert_eq!(segments[0].get_flag_string(), "[P.]");
+
+        // The MSS of 0 is treated as 1
+        assert_eq!(segment(b"hello", 0, 0).len(), 5);
+    }
+
+    #[test]
+    fn serialize_timestamps() {
+        let mut tcp = Tcp::new_ack(80, 1024, 1001, 5001, 65535);
+        tcp.set_timestamps(Some((100, 200)));
+        assert_eq!(tcp.get_size(), 32);
+        let mut buffer = vec![0u8; tcp.get_size()];
+        tcp.serialize(&mut buffer, tcp.get_size()).unwrap();
+
+        // Aligned by 2 NOPs
+        #[rustfmt::skip]
+        assert_eq!(buffer[20..], [
+            0x01, 0x01, 0x08, 0x0a,
+            0x00, 0x00, 0x00, 0x64, 0x00, 0x00, 0x00, 0xc8,
+        ]);
+        let (deserialized, _) = Tcp::deserialize(&buffer).unwrap();
+        assert_eq!(deserialized.get_timestamps(), Some((100, 200)));
+        assert_eq!(TcpOption::Timestamps(100, 200).to_string(), "TS = 100/200");
+    }
+
+    #[test]
+    fn set_timestamps_replace_and_remove() {
+        let mut tcp = Tcp::new_ack(80, 1024, 1001, 5001, 65535);
+        tcp.set_options(vec![TcpOption::MaximumSegmentSize(1460)]);
+        tcp.set_timestamps(Some((100, 200)));
+        tcp.set_timestamps(Some((300, 400)));
+
+        // The option is replaced with its padding
+        assert_eq!(tcp.get_timestamps(), Some((300, 400)));
+        assert_eq!(tcp.get_size(), 20 + 4 + 12);
+
+        tcp.set_timestamps(None);
+        assert_eq!(tcp.get_timestamps(), None);
+        assert_eq!(tcp.get_mss(), Some(1460));
+        assert_eq!(tcp.get_size(), 20 + 4);
+    }
+
+    #[test]
+    fn serialize_malformed_options() {
+        // The length of the option is less than 2
+        let mut buffer = [0u8; 24];
+        buffer[0..2].copy_from_slice(&1024u16.to_be_bytes());
+        buffer[2..4].copy_from_slice(&80u16.to_be_bytes());
+        buffer[12] = 6 << 4;
+        buffer[13] = TcpFlags::ACK as u8;
+        buffer[14..16].copy_from_slice(&65535u16.to_be_bytes());
+        buffer[20..24].copy_from_slice(&[0x08, 0x01, 0x00, 0x00]);
+
+        let (tcp, size) = Tcp::deserialize(&buffer).unwrap();
+        assert_eq!(size, 24);
+        assert!(tcp.get_options().is_empty());
+        assert_eq!(tcp.get_size(), 20);
+
+        let mut serialized = [0u8; 64];
+        let n = tcp.serialize(&mut serialized, tcp.get_size()).unwrap();
+        assert_eq!(n, 20);
+        assert_eq!(serialized[12] >> 4, 5);
+        assert!(!format!("{:#}", tcp).is_empty());
+
+        // The checksum is recomputed without the malformed option
+        let (reserialized, _) = Tcp::deserialize(&serialized[..n]).unwrap();
+        assert_eq!(
+            reserialized.get_checksum(),
+            tcp.compute_checksum(
+                IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+                IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+                &[]
+            )
+            .unwrap()
+        );
+    }
+
+    #[test]
+    fn deserialize_timestamps_invalid_length() {
+        let mut tcp = Tcp::new_ack(80, 1024, 1001, 5001, 65535);
+        tcp.set_options(vec![
+            TcpOption::Unknown(TcpOptionNumbers::TIMESTAMPS.0, vec![0u8; 6]),
+            TcpOption::NoOperation,
+            TcpOption::NoOperation,
+        ]);
+        let mut buffer = vec![0u8; tcp.get_size()];
+        tcp.serialize(&mut buffer, tcp.get_size()).unwrap();
+
+        // Timestamps options of a wrong length are kept as unknown
+        let (deserialized, _) = Tcp::deserialize(&buffer).unwrap();
+        assert_eq!(deserialized.get_timestamps(), None);
+    }
+
+    #[test]
+    fn urgent_pointer_round_trip() {
+        let mut tcp = Tcp::new_ack(1024, 80, 1001, 5001, 65535);
+        tcp.set_urgent_pointer(Some(3));
+        assert!(tcp.is_urg());
+        assert_eq!(tcp.get_flag_string(), "[U.]");
+        let mut buffer = vec![0u8; tcp.get_size()];
+        tcp.serialize(&mut buffer, tcp.get_size()).unwrap();
+        assert_eq!(buffer[18..20], [0x00, 0x03]);
+
+        let (deserialized, _) = Tcp::deserialize(&buffer).unwrap();
+        assert!(deserialized.is_urg());
+        assert_eq!(deserialized.get_urgent_pointer(), 3);
+        assert_eq!(deserialized.get_urgent_end(UrgentPointer::Bsd), Some(1004));
+        assert_eq!(
+            deserialized.get_urgent_end(UrgentPointer::Rfc1122),
+            Some(1005)
+        );
+
+        tcp.set_urgent_pointer(None);
+        assert!(!tcp.is_urg());
+        assert_eq!(tcp.get_urgent_pointer(), 0);
+        assert_eq!(tcp.get_urgent_end(UrgentPointer::Bsd), None);
+    }
+
+    #[test]
+    fn set_urgent_end() {
+        let mut tcp = Tcp::new_ack(1024, 80, u32::MAX - 1, 5001, 65535);
+        // Across the wraparound
+        tcp.set_urgent_end(Some(8), UrgentPointer::Bsd);
+        assert_eq!(tcp.get_urgent_pointer(), 10);
+        tcp.set_urgent_end(Some(8), UrgentPointer::Rfc1122);
+        assert_eq!(tcp.get_urgent_pointer(), 9);
+        assert_eq!(tcp.get_urgent_end(UrgentPointer::Rfc1122), Some(8));
+
+        // Saturated beyond the range
+        tcp.set_urgent_end(Some(100000), UrgentPointer::Bsd);
+        assert_eq!(tcp.get_urgent_pointer(), u16::MAX);
+
+        // Cleared at or before the sequence
+        tcp.set_urgent_end(Some(u32::MAX - 1), UrgentPointer::Bsd);
+        assert!(!tcp.is_urg());
+        tcp.set_urgent_end(Some(100), UrgentPointer::Bsd);
+        tcp.set_urgent_end(Some(0xffff_0000), UrgentPointer::Rfc1122);
+        assert!(!tcp.is_urg());
+    }
+
+    #[test]
+    fn mark_urgent_segments() {
+        let data = vec![0u8; 5000];
+        let mut segments = segment(&data, 1460, 1000);
+        mark_urgent(&mut segments, 3000, UrgentPointer::Bsd);
+
+        // Segments after the urgent data are not marked
+        let pointers: Vec<Option<u16>> = segments
+            .iter()
+            .map(|tcp| tcp.is_urg().then(|| tcp.get_urgent_pointer()))
+            .collect();
+        assert_eq!(pointers, [Some(2000), Some(540), None, None]);
+        assert!(segments[..2]
+            .iter()
+            .all(|tcp| tcp.get_urgent_end(UrgentPointer::Bsd) == Some(3000)));
+
+        mark_urgent(&mut segments, 3000, UrgentPointer::Rfc1122);
+        assert_eq!(segments[1].get_urgent_pointer(), 539);
+    }
+
+    #[test]
+    fn urgent_pointer_from_str() {
+        assert_eq!(UrgentPointer::default(), UrgentPointer::Bsd);
+        for interpretation in [UrgentPointer::Bsd, UrgentPointer::Rfc1122] {
+            let s = interpretation.to_string();
+            assert_eq!(UrgentPointer::from_str(&s).unwrap(), interpretation);
+        }
+        assert_eq!(
+            UrgentPointer::from_str("RFC1122").unwrap(),
+            UrgentPointer::Rfc1122
+        );
+        let e = UrgentPointer::from_str("rfc793").unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn set_flags_serialized() {
+        let mut tcp = Tcp::new_ack(1024, 80, 1001, 5001, 65535);
+        assert_eq!(tcp.get_flags(), Flags::ACK);
+        tcp.set_flag(Flags::ECE | Flags::PSH, true);
+        tcp.set_flag(Flags::NS, true);
+        let mut buffer = vec![0u8; tcp.get_size()];
+        tcp.serialize(&mut buffer, tcp.get_size()).unwrap();
+
+        // The NS flag is in the byte of the data offset
+        assert_eq!(buffer[12], 0x51);
+        assert_eq!(buffer[13], 0x58);
+        let (deserialized, _) = Tcp::deserialize(&buffer).unwrap();
+        assert_eq!(
+            deserialized.get_flags(),
+            Flags::NS | Flags::ECE | Flags::ACK | Flags::PSH
+        );
+
+        tcp.set_flag(Flags::NS | Flags::PSH, false);
+        assert_eq!(tcp.get_flags(), Flags::ECE | Flags::ACK);
+        assert!(tcp.is_ack() && !tcp.is_psh());
+    }
+
+    #[test]
+    fn set_flags_clear_urgent_pointer() {
+        let mut tcp = Tcp::new_ack(1024, 80, 1001, 5001, 65535);
+        tcp.set_urgent_pointer(Some(3));
+        tcp.set_flag(Flags::PSH, true);
+        assert_eq!(tcp.get_urgent_pointer(), 3);
+
+        tcp.set_flags(Flags::SYN);
+        assert!(tcp.is_syn() && !tcp.is_ack());
+        assert_eq!(tcp.get_urgent_pointer(), 0);
+    }
+
+    #[test]
+    fn set_ports() {
+        let mut tcp = Tcp::new_ack(1024, 80, 1001, 5001, 65535);
+        tcp.set_src(8080);
+        tcp.set_dst(2048);
+        let mut buffer = vec![0u8; tcp.get_size()];
+        tcp.serialize(&mut buffer, tcp.get_size()).unwrap();
+
+        assert_eq!(buffer[..4], [0x1f, 0x90, 0x08, 0x00]);
+    }
+
+    #[test]
+    fn size_bounds() {
+        assert_eq!(Tcp::min_size(), 20);
+        assert_eq!(Tcp::max_size(), 60);
+    }
+
+    #[test]
+    fn deserialize_shorter_than_min_size() {
+        let buffer = [0u8; 60];
+        for n in 0..Tcp::min_size() {
+            assert_eq!(
+                Tcp::deserialize(&buffer[..n]).unwrap_err(),
+                ParseError::Truncated(LayerTypes::Tcp)
+            );
+        }
+    }
+
+    #[test]
+    fn fast_open_round_trip() {
+        // A cookie request
+        let mut tcp = Tcp::new_syn(1024, 80, 100, 65535);
+        tcp.set_fast_open_cookie(Some(&[]));
+        let mut buffer = vec![0u8; tcp.get_size()];
+        tcp.serialize(&mut buffer, tcp.get_size()).unwrap();
+        let (deserialized, _) = Tcp::deserialize(&buffer).unwrap();
+        assert_eq!(deserialized.get_fast_open_cookie(), Some(&[][..]));
+
+        // A cookie
+        let cookie = [1, 2, 3, 4, 5, 6, 7, 8];
+        tcp.set_fast_open_cookie(Some(&cookie));
+        assert_eq!(
+            tcp.get_options(),
+            [
+                TcpOption::NoOperation,
+                TcpOption::NoOperation,
+                TcpOption::FastOpen(cookie.to_vec())
+            ]
+        );
+        assert_eq!(tcp.get_size(), 32);
+        let mut buffer = vec![0u8; tcp.get_size()];
+        tcp.serialize(&mut buffer, tcp.get_size()).unwrap();
+        assert_eq!(buffer[20..24], [1, 1, 34, 10]);
+        let (deserialized, _) = Tcp::deserialize(&buffer).unwrap();
+        assert_eq!(deserialized.get_fast_open_cookie(), Some(&cookie[..]));
+
+        // The option is removed with its padding
+        tcp.set_fast_open_cookie(None);
+        assert!(tcp.get_options().is_empty());
+        assert_eq!(tcp.get_fast_open_cookie(), None);
+    }
+
+    #[test]
+    fn parse_options_fast_open() {
+        // Cookies of an odd or too small size are not fast open cookies
+        assert_eq!(
+            TcpOption::parse_options(&[34, 5, 1, 2, 3]),
+            [TcpOption::Unknown(34, vec![1, 2, 3])]
+        );
+        assert_eq!(
+            TcpOption::parse_options(&[34, 4, 1, 2]),
+            [TcpOption::Unknown(34, vec![1, 2])]
+        );
+        assert_eq!(
+            TcpOption::parse_options(&[34, 2]),
+            [TcpOption::FastOpen(Vec::new())]
+        );
+    }
+
+    #[test]
+    fn ecn_flags() {
+        // An ECN-setup SYN
+        let mut tcp = Tcp::new_syn(1024, 80, 100, 65535);
+        assert!(!tcp.is_ecn_setup_syn());
+        tcp.set_flag(Flags::ECE | Flags::CWR, true);
+        assert!(tcp.is_ece() && tcp.is_cwr());
+        assert!(tcp.is_ecn_setup_syn());
+        assert!(!tcp.is_ecn_setup_ack_syn());
+        assert!(tcp.to_string().contains("[SEW]"));
+
+        // An ECN-setup ACK/SYN
+        let mut tcp = Tcp::new_ack_syn(80, 1024, 1000, 101, 65535);
+        tcp.set_ece(true);
+        let mut buffer = vec![0u8; tcp.get_size()];
+        tcp.serialize(&mut buffer, tcp.get_size()).unwrap();
+        let (deserialized, _) = Tcp::deserialize(&buffer).unwrap();
+        assert!(deserialized.is_ecn_setup_ack_syn());
+        assert!(!deserialized.is_ecn_setup_syn());
+
+        // An ACK/SYN setting both flags is not ECN-setup
+        tcp.set_flag(Flags::CWR, true);
+        assert!(!tcp.is_ecn_setup_ack_syn());
+        tcp.set_ece(false);
+        assert!(!tcp.is_ece() && tcp.is_cwr());
+    }
+
+    #[test]
+    fn serialize_checksum_offload() {
+        let ipv4 = Ipv4::new(
+            1,
+            LayerTypes::Tcp,
+            Ipv4Addr::new(10, 6, 0, 1),
+            Ipv4Addr::new(93, 184, 216, 34),
+        )
+        .unwrap();
+        let mut tcp = Tcp::new_ack(1024, 80, 1001, 5001, 65535);
+        tcp.set_ipv4_layer(&ipv4);
+        let n = tcp.get_size() + 5;
+        let mut buffer = vec![0u8; n];
+        tcp.serialize_with_payload(&mut buffer, b"hello", n)
+            .unwrap();
+        assert_ne!(buffer[16..18], [0, 0]);
+
+        tcp.set_checksum_offload(true);
+        tcp.serialize_with_payload(&mut buffer, b"hello", n)
+            .unwrap();
+        assert_eq!(buffer[16..18], [0, 0]);
+        let mut buffer = vec![0u8; tcp.get_size()];
+        tcp.serialize(&mut buffer, tcp.get_size()).unwrap();
+        assert_eq!(buffer[16..18], [0, 0]);
+    }
+}
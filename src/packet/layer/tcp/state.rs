@@ -0,0 +1,1555 @@
+use super::Tcp;
+use crate::stats::LatencyHistogram;
+use lru::LruCache;
+use std::cmp::{max, min};
+use std::collections::hash_map::RandomState;
+use std::collections::HashMap;
+use std::fmt::{self, Display, Formatter};
+use std::hash::{BuildHasher, Hash, Hasher};
+use std::net::Ipv4Addr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Represents the state of a TCP connection.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum State {
+    Listen,
+    SynReceived,
+    Established,
+    FinWait1,
+    FinWait2,
+    CloseWait,
+    Closing,
+    LastAck,
+    TimeWait,
+    Closed,
+}
+
+impl Display for State {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        let s = match self {
+            State::Listen => "LISTEN",
+            State::SynReceived => "SYN_RECEIVED",
+            State::Established => "ESTABLISHED",
+            State::FinWait1 => "FIN_WAIT_1",
+            State::FinWait2 => "FIN_WAIT_2",
+            State::CloseWait => "CLOSE_WAIT",
+            State::Closing => "CLOSING",
+            State::LastAck => "LAST_ACK",
+            State::TimeWait => "TIME_WAIT",
+            State::Closed => "CLOSED",
+        };
+
+        write!(f, "{}", s)
+    }
+}
+
+/// Represents an action should be taken after a TCP segment is handled by a `Connection`.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum Action {
+    /// Sends a TCP ACK.
+    SendAck,
+    /// Sends a TCP ACK/SYN.
+    SendAckSyn,
+    /// Sends a TCP ACK/FIN.
+    SendAckFin,
+    /// Sends a TCP RST.
+    SendRst,
+    /// Delivers the payload to the proxy, starting from the given offset of the payload with the
+    /// given size. Bytes before the offset are already delivered.
+    Deliver(usize, usize),
+    /// Closes the connection.
+    Close,
+}
+
+/// Represents the position of a TCP segment relative to the receive window.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum Segment {
+    /// The segment starts at the next expected sequence.
+    InOrder,
+    /// The segment is received before, and should be dropped.
+    Duplicate,
+    /// The first given bytes of the segment are received before.
+    Overlapped(usize),
+    /// The segment starts after the next expected sequence.
+    OutOfOrder,
+    /// The segment is a keep-alive probe, which should be acknowledged without changing the
+    /// receive window.
+    KeepAlive,
+}
+
+/// Returns if a TCP segment with the given sequence and length is a keep-alive probe to the given
+/// next expected sequence. A keep-alive probe carries the sequence right before the next expected
+/// sequence with no more than 1 byte of garbage payload.
+pub fn is_keep_alive(sequence: u32, length: usize, acknowledgement: u32) -> bool {
+    length <= 1 && sequence == acknowledgement.wrapping_sub(1)
+}
+
+/// Returns if sequence `a` is before sequence `b`, with respect to wraparound.
+pub fn sequence_lt(a: u32, b: u32) -> bool {
+    (a.wrapping_sub(b) as i32) < 0
+}
+
+/// Returns if sequence `a` is before or equal to sequence `b`, with respect to wraparound.
+pub fn sequence_le(a: u32, b: u32) -> bool {
+    a == b || sequence_lt(a, b)
+}
+
+/// Represents the receive window advertised to the peer of a TCP connection, which shrinks as the
+/// buffer fills up and closes to zero when the buffer is full. To avoid the silly window syndrome
+/// (RFC 1122 4.2.3.3), the window only opens when it increases by at least one MSS, or half of
+/// the buffer if the buffer is small.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct ReceiveWindow {
+    capacity: usize,
+    mss: usize,
+    window: usize,
+}
+
+impl ReceiveWindow {
+    /// Creates a new `ReceiveWindow` with the given buffer capacity and the MSS of the peer. The
+    /// window is fully open at the beginning.
+    pub fn new(capacity: usize, mss: usize) -> ReceiveWindow {
+        ReceiveWindow {
+            capacity,
+            mss,
+            window: capacity,
+        }
+    }
+
+    /// Updates the window with the number of bytes occupied in the buffer, and returns the window
+    /// to be advertised.
+    pub fn update(&mut self, occupied: usize) -> usize {
+        let available = self.capacity.saturating_sub(occupied);
+        let threshold = max(min(self.mss, self.capacity / 2), 1);
+
+        if available < self.window || available >= self.window + threshold {
+            self.window = available;
+        }
+
+        self.window
+    }
+
+    /// Get the window to be advertised.
+    pub fn get_window(&self) -> usize {
+        self.window
+    }
+
+    /// Get the capacity of the buffer.
+    pub fn get_capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Returns if the window is closed.
+    pub fn is_zero(&self) -> bool {
+        self.window == 0
+    }
+}
+
+/// Represents the max number of blocks in the selective acknowledgement option.
+pub const MAX_SACK_BLOCKS: usize = 4;
+
+/// Represents the ranges of out-of-order data received, which are reported as the blocks of the
+/// selective acknowledgement option. The most recently received range comes first.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct SackBlocks {
+    blocks: Vec<(u32, u32)>,
+}
+
+impl SackBlocks {
+    /// Creates an empty `SackBlocks`.
+    pub fn new() -> SackBlocks {
+        SackBlocks { blocks: Vec::new() }
+    }
+
+    /// Inserts the range of a segment received out of order. Overlapping or adjacent ranges are
+    /// coalesced with the range, which is moved to the front. The oldest range is dropped if
+    /// there are more than 4 ranges.
+    pub fn insert(&mut self, left: u32, right: u32) {
+        if !sequence_lt(left, right) {
+            return;
+        }
+
+        let (mut left, mut right) = (left, right);
+        while let Some(i) = self
+            .blocks
+            .iter()
+            .position(|&(l, r)| sequence_le(l, right) && sequence_le(left, r))
+        {
+            let (l, r) = self.blocks.remove(i);
+            if sequence_lt(l, left) {
+                left = l;
+            }
+            if sequence_lt(right, r) {
+                right = r;
+            }
+        }
+
+        self.blocks.insert(0, (left, right));
+        self.blocks.truncate(MAX_SACK_BLOCKS);
+    }
+
+    /// Removes the ranges covered by the given acknowledgement, and trims the range the
+    /// acknowledgement falls in.
+    pub fn acknowledge(&mut self, acknowledgement: u32) {
+        self.blocks
+            .retain(|&(_, right)| sequence_lt(acknowledgement, right));
+        for block in &mut self.blocks {
+            if sequence_lt(block.0, acknowledgement) {
+                block.0 = acknowledgement;
+            }
+        }
+    }
+
+    /// Get the blocks, the most recently received first.
+    pub fn get_blocks(&self) -> &[(u32, u32)] {
+        &self.blocks
+    }
+
+    /// Get the number of blocks.
+    pub fn len(&self) -> usize {
+        self.blocks.len()
+    }
+
+    /// Returns if there are no blocks.
+    pub fn is_empty(&self) -> bool {
+        self.blocks.is_empty()
+    }
+}
+
+/// Represents the result of inserting a segment into a `ReorderBuffer`.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum Reorder {
+    /// Some bytes of the segment are buffered.
+    Accepted,
+    /// All the bytes of the segment are received before, and the segment should be dropped.
+    Duplicate,
+    /// The segment starts beyond the window, and should be dropped and answered with an ACK of
+    /// the expected sequence.
+    OutOfWindow,
+}
+
+/// Represents a buffer reordering the payload of TCP segments received out of order into a
+/// contiguous byte stream. Segments are buffered by their sequences within a window from the
+/// expected sequence, which bounds the memory. Overlapping bytes are trimmed, the bytes received
+/// first are kept.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ReorderBuffer {
+    sequence: u32,
+    window: usize,
+    /// Represents the non-overlapping segments buffered and their offsets from the expected
+    /// sequence in ascending order. Adjacent segments are coalesced.
+    segments: Vec<(usize, Vec<u8>)>,
+}
+
+impl ReorderBuffer {
+    /// Creates a new `ReorderBuffer` expecting the given sequence. Bytes beyond the window from
+    /// the expected sequence are not buffered.
+    pub fn new(sequence: u32, window: usize) -> ReorderBuffer {
+        ReorderBuffer {
+            sequence,
+            window: min(window, i32::MAX as usize),
+            segments: Vec::new(),
+        }
+    }
+
+    /// Inserts the payload of a segment of the given sequence. Bytes before the expected
+    /// sequence, beyond the window or buffered before are trimmed.
+    pub fn insert(&mut self, sequence: u32, payload: &[u8]) -> Reorder {
+        let begin = sequence.wrapping_sub(self.sequence) as i32 as i64;
+        let end = begin + payload.len() as i64;
+        if begin >= self.window as i64 {
+            return Reorder::OutOfWindow;
+        }
+        let (trimmed_begin, trimmed_end) = (max(begin, 0), min(end, self.window as i64));
+        if trimmed_begin >= trimmed_end {
+            return Reorder::Duplicate;
+        }
+        let data = &payload[(trimmed_begin - begin) as usize..(trimmed_end - begin) as usize];
+        let (begin, end) = (trimmed_begin as usize, trimmed_end as usize);
+
+        // Fill the gaps between the segments buffered
+        let mut pieces = Vec::new();
+        let mut cursor = begin;
+        for (offset, segment) in &self.segments {
+            if *offset >= end {
+                break;
+            }
+            if *offset > cursor {
+                pieces.push((cursor, data[cursor - begin..*offset - begin].to_vec()));
+            }
+            cursor = max(cursor, offset + segment.len());
+        }
+        if cursor < end {
+            pieces.push((cursor, data[cursor - begin..].to_vec()));
+        }
+        if pieces.is_empty() {
+            return Reorder::Duplicate;
+        }
+
+        // Coalesce adjacent segments
+        self.segments.extend(pieces);
+        self.segments.sort_by_key(|(offset, _)| *offset);
+        let mut segments: Vec<(usize, Vec<u8>)> = Vec::with_capacity(self.segments.len());
+        for (offset, segment) in self.segments.drain(..) {
+            match segments.last_mut() {
+                Some((last_offset, last)) if *last_offset + last.len() == offset => {
+                    last.extend_from_slice(&segment)
+                }
+                _ => segments.push((offset, segment)),
+            }
+        }
+        self.segments = segments;
+
+        Reorder::Accepted
+    }
+
+    /// Removes and returns the contiguous bytes starting from the expected sequence, and
+    /// advances the expected sequence. Returns `None` if the bytes of the expected sequence are
+    /// not received.
+    pub fn pop(&mut self) -> Option<Vec<u8>> {
+        match self.segments.first() {
+            Some((0, _)) => {}
+            _ => return None,
+        }
+
+        let (_, bytes) = self.segments.remove(0);
+        for (offset, _) in &mut self.segments {
+            *offset -= bytes.len();
+        }
+        self.sequence = self.sequence.wrapping_add(bytes.len() as u32);
+
+        Some(bytes)
+    }
+
+    /// Get the expected sequence of the buffer.
+    pub fn get_sequence(&self) -> u32 {
+        self.sequence
+    }
+
+    /// Get the window of the buffer.
+    pub fn get_window(&self) -> usize {
+        self.window
+    }
+
+    /// Get the ranges of the sequences buffered in ascending order, which can be reported as
+    /// SACK blocks.
+    pub fn get_ranges(&self) -> Vec<(u32, u32)> {
+        self.segments
+            .iter()
+            .map(|(offset, segment)| {
+                let left = self.sequence.wrapping_add(*offset as u32);
+                (left, left.wrapping_add(segment.len() as u32))
+            })
+            .collect()
+    }
+
+    /// Get the number of bytes buffered.
+    pub fn len(&self) -> usize {
+        self.segments.iter().map(|(_, segment)| segment.len()).sum()
+    }
+
+    /// Returns if the buffer is empty.
+    pub fn is_empty(&self) -> bool {
+        self.segments.is_empty()
+    }
+}
+
+/// Represents a TCP connection from the source in pcap, pcap2socks acts as the passive side of
+/// the connection.
+#[derive(Clone, Debug)]
+pub struct Connection {
+    state: State,
+    sequence: u32,
+    acknowledgement: u32,
+    window: u16,
+    receive_window: u16,
+    duplicates: usize,
+    sack: Option<SackBlocks>,
+}
+
+impl Connection {
+    /// Creates a `Connection` in the listen state with the given initial sequence.
+    pub fn new(sequence: u32) -> Connection {
+        Connection {
+            state: State::Listen,
+            sequence,
+            acknowledgement: 0,
+            window: 0,
+            receive_window: u16::MAX,
+            duplicates: 0,
+            sack: None,
+        }
+    }
+
+    /// Creates a `Connection` in the established state with the given sequence and
+    /// acknowledgement, for connections which are tracked elsewhere before.
+    pub fn new_established(sequence: u32, acknowledgement: u32) -> Connection {
+        Connection {
+            state: State::Established,
+            sequence,
+            acknowledgement,
+            window: 0,
+            receive_window: u16::MAX,
+            duplicates: 0,
+            sack: None,
+        }
+    }
+
+    /// Get the state of the connection.
+    pub fn get_state(&self) -> State {
+        self.state
+    }
+
+    /// Returns if the connection is half-open, whose handshake is not completed.
+    pub fn is_half_open(&self) -> bool {
+        matches!(self.state, State::Listen | State::SynReceived)
+    }
+
+    /// Get the sequence of the connection, which is the next sequence will be sent. A sent SYN or
+    /// FIN is not counted until it is acknowledged.
+    pub fn get_sequence(&self) -> u32 {
+        self.sequence
+    }
+
+    /// Get the acknowledgement of the connection, which is the next sequence expected from the
+    /// source.
+    pub fn get_acknowledgement(&self) -> u32 {
+        self.acknowledgement
+    }
+
+    /// Get the number of duplicate segments dropped by the connection.
+    pub fn get_duplicates(&self) -> usize {
+        self.duplicates
+    }
+
+    /// Sets the receive window size advertised to the source. Payload received when the window
+    /// is 0 is treated as a zero window probe, which is acknowledged but not accepted.
+    pub fn set_receive_window(&mut self, window: u16) {
+        self.receive_window = window;
+    }
+
+    /// Get the receive window size advertised to the source.
+    pub fn get_receive_window(&self) -> u16 {
+        self.receive_window
+    }
+
+    /// Sets if selective acknowledgement is permitted by the source. It is set according to the
+    /// SYN for connections in the listen state.
+    pub fn set_sack_permitted(&mut self, permitted: bool) {
+        if permitted {
+            self.sack.get_or_insert_with(SackBlocks::new);
+        } else {
+            self.sack = None;
+        }
+    }
+
+    /// Returns if selective acknowledgement is permitted by the source.
+    pub fn is_sack_permitted(&self) -> bool {
+        self.sack.is_some()
+    }
+
+    /// Get the selective acknowledgement blocks of out-of-order data received, which should be
+    /// carried in ACKs. Returns an empty slice if selective acknowledgement is not permitted.
+    pub fn get_sack_blocks(&self) -> &[(u32, u32)] {
+        match self.sack {
+            Some(ref sack) => sack.get_blocks(),
+            None => &[],
+        }
+    }
+
+    /// Returns if a segment with the given sequence and length is a zero window probe, which
+    /// carries the next expected sequence while the receive window is 0.
+    pub fn is_zero_window_probe(&self, sequence: u32, length: usize) -> bool {
+        self.receive_window == 0 && length > 0 && sequence == self.acknowledgement
+    }
+
+    /// Returns the position of a segment with the given sequence and length relative to the
+    /// receive window, which starts at the next expected sequence.
+    pub fn check_segment(&self, sequence: u32, length: usize) -> Segment {
+        let end = sequence.wrapping_add(length as u32);
+        if is_keep_alive(sequence, length, self.acknowledgement) {
+            Segment::KeepAlive
+        } else if sequence == self.acknowledgement {
+            Segment::InOrder
+        } else if sequence_lt(sequence, self.acknowledgement) {
+            if sequence_le(end, self.acknowledgement) {
+                Segment::Duplicate
+            } else {
+                Segment::Overlapped(self.acknowledgement.wrapping_sub(sequence) as usize)
+            }
+        } else {
+            Segment::OutOfOrder
+        }
+    }
+
+    /// Get the window size advertised by the source.
+    pub fn get_window(&self) -> u16 {
+        self.window
+    }
+
+    /// Advances the sequence of the connection by the given size of payload sent.
+    pub fn on_send(&mut self, size: usize) {
+        self.sequence = self.sequence.wrapping_add(size as u32);
+    }
+
+    /// Handles a TCP segment without payload and returns the actions should be taken.
+    pub fn on_segment(&mut self, tcp: &Tcp) -> Vec<Action> {
+        self.on_segment_with_payload(tcp, &[])
+    }
+
+    /// Handles a TCP segment with payload and returns the actions should be taken.
+    pub fn on_segment_with_payload(&mut self, tcp: &Tcp, payload: &[u8]) -> Vec<Action> {
+        let mut actions = Vec::new();
+
+        // Reset
+        if tcp.is_rst() {
+            if self.state != State::Listen {
+                self.state = State::Closed;
+                actions.push(Action::Close);
+            }
+            return actions;
+        }
+
+        self.window = tcp.get_window();
+
+        match self.state {
+            State::Listen => {
+                if tcp.is_syn() && !tcp.is_ack() {
+                    self.acknowledgement = tcp.get_sequence().wrapping_add(1);
+                    self.set_sack_permitted(tcp.is_sack_permitted());
+                    self.state = State::SynReceived;
+                    actions.push(Action::SendAckSyn);
+                } else if tcp.is_ack() {
+                    actions.push(Action::SendRst);
+                }
+            }
+            State::SynReceived => {
+                if tcp.is_syn() {
+                    // Retransmission of SYN
+                    actions.push(Action::SendAckSyn);
+                } else if tcp.is_ack() && self.is_ack_of_syn_or_fin(tcp) {
+                    self.sequence = self.sequence.wrapping_add(1);
+                    self.state = State::Established;
+                    if self.receive(tcp, payload, &mut actions) {
+                        self.state = State::CloseWait;
+                    }
+                }
+            }
+            State::Established => {
+                if self.receive(tcp, payload, &mut actions) {
+                    self.state = State::CloseWait;
+                }
+            }
+            State::FinWait1 => {
+                let is_fin_acked = tcp.is_ack() && self.is_ack_of_syn_or_fin(tcp);
+                let is_fin_received = self.receive(tcp, payload, &mut actions);
+                if is_fin_acked {
+                    self.sequence = self.sequence.wrapping_add(1);
+                }
+                self.state = match (is_fin_acked, is_fin_received) {
+                    (true, true) => State::TimeWait,
+                    (true, false) => State::FinWait2,
+                    (false, true) => State::Closing,
+                    (false, false) => State::FinWait1,
+                };
+                if self.state == State::TimeWait {
+                    actions.push(Action::Close);
+                }
+            }
+            State::FinWait2 => {
+                if self.receive(tcp, payload, &mut actions) {
+                    self.state = State::TimeWait;
+                    actions.push(Action::Close);
+                }
+            }
+            State::CloseWait => {
+                // Retransmission of FIN
+                if tcp.is_fin() {
+                    actions.push(Action::SendAck);
+                }
+            }
+            State::Closing => {
+                if tcp.is_ack() && self.is_ack_of_syn_or_fin(tcp) {
+                    self.sequence = self.sequence.wrapping_add(1);
+                    self.state = State::TimeWait;
+                    actions.push(Action::Close);
+                }
+            }
+            State::LastAck => {
+                if tcp.is_ack() && self.is_ack_of_syn_or_fin(tcp) {
+                    self.sequence = self.sequence.wrapping_add(1);
+                    self.state = State::Closed;
+                    actions.push(Action::Close);
+                }
+            }
+            State::TimeWait => {
+                // Retransmission of FIN
+                if tcp.is_fin() {
+                    actions.push(Action::SendAck);
+                }
+            }
+            State::Closed => actions.push(Action::SendRst),
+        }
+
+        actions
+    }
+
+    /// Closes the connection actively and returns the actions should be taken.
+    pub fn close(&mut self) -> Vec<Action> {
+        match self.state {
+            State::SynReceived | State::Established => {
+                self.state = State::FinWait1;
+                vec![Action::SendAckFin]
+            }
+            State::CloseWait => {
+                self.state = State::LastAck;
+                vec![Action::SendAckFin]
+            }
+            State::Listen => {
+                self.state = State::Closed;
+                vec![Action::Close]
+            }
+            _ => vec![],
+        }
+    }
+
+    /// Receives the payload and FIN of a TCP segment, and returns if the FIN is accepted.
+    fn receive(&mut self, tcp: &Tcp, payload: &[u8], actions: &mut Vec<Action>) -> bool {
+        // A FIN occupies a sequence after the payload
+        let length = payload.len() + tcp.is_fin() as usize;
+        if !tcp.is_fin() && self.is_zero_window_probe(tcp.get_sequence(), length) {
+            // Zero window probe, sends an ACK with the current window
+            actions.push(Action::SendAck);
+            return false;
+        }
+        // A retransmitted FIN is not a keep-alive
+        let segment = match self.check_segment(tcp.get_sequence(), length) {
+            Segment::KeepAlive if tcp.is_fin() => Segment::Duplicate,
+            segment => segment,
+        };
+        let offset = match segment {
+            Segment::InOrder => 0,
+            Segment::Overlapped(n) => n,
+            Segment::Duplicate => {
+                // Retransmission, sends a duplicate ACK
+                if length > 0 {
+                    self.duplicates += 1;
+                    actions.push(Action::SendAck);
+                }
+                return false;
+            }
+            Segment::OutOfOrder => {
+                // Out of order, sends a duplicate ACK with the range received
+                if !payload.is_empty() {
+                    if let Some(ref mut sack) = self.sack {
+                        let sequence = tcp.get_sequence();
+                        sack.insert(sequence, sequence.wrapping_add(payload.len() as u32));
+                    }
+                }
+                if length > 0 {
+                    actions.push(Action::SendAck);
+                }
+                return false;
+            }
+            Segment::KeepAlive => {
+                // Keep-alive, sends an ACK without moving the stream position
+                actions.push(Action::SendAck);
+                return false;
+            }
+        };
+
+        if offset < payload.len() {
+            let size = payload.len() - offset;
+            self.acknowledgement = self.acknowledgement.wrapping_add(size as u32);
+            actions.push(Action::Deliver(offset, size));
+        }
+        if tcp.is_fin() {
+            self.acknowledgement = self.acknowledgement.wrapping_add(1);
+        }
+        if let Some(ref mut sack) = self.sack {
+            sack.acknowledge(self.acknowledgement);
+        }
+        if !payload.is_empty() || tcp.is_fin() {
+            actions.push(Action::SendAck);
+        }
+
+        tcp.is_fin()
+    }
+
+    fn is_ack_of_syn_or_fin(&self, tcp: &Tcp) -> bool {
+        tcp.get_acknowledgement() == self.sequence.wrapping_add(1)
+    }
+}
+
+/// Represents the size of the fast open cookies generated.
+pub const FAST_OPEN_COOKIE_SIZE: usize = 8;
+
+/// Represents the generator of TCP fast open cookies (RFC 7413). A cookie is a keyed hash of the
+/// source IP address, so it can be validated without keeping any state. The key is random, and
+/// cookies generated by another `FastOpenCookies` are invalid.
+#[derive(Clone, Debug, Default)]
+pub struct FastOpenCookies {
+    key: RandomState,
+}
+
+impl FastOpenCookies {
+    /// Creates a new `FastOpenCookies` with a random key.
+    pub fn new() -> FastOpenCookies {
+        FastOpenCookies::default()
+    }
+
+    /// Generates the cookie of the given source IP address.
+    pub fn generate(&self, ip_addr: Ipv4Addr) -> [u8; FAST_OPEN_COOKIE_SIZE] {
+        let mut hasher = self.key.build_hasher();
+        ip_addr.hash(&mut hasher);
+
+        hasher.finish().to_be_bytes()
+    }
+
+    /// Returns if the given cookie is generated of the given source IP address.
+    pub fn validate(&self, ip_addr: Ipv4Addr, cookie: &[u8]) -> bool {
+        cookie == self.generate(ip_addr)
+    }
+}
+
+/// Represents the counters of a TCP connection, which is shared between the directions of the
+/// connection. Only the payload is counted, and retransmissions are not counted again.
+#[derive(Debug, Default)]
+pub struct FlowCounters {
+    up_bytes: AtomicU64,
+    up_packets: AtomicU64,
+    down_bytes: AtomicU64,
+    down_packets: AtomicU64,
+    /// Represents the end sequence of the request awaiting its response and when it is forwarded.
+    request: Mutex<Option<(u32, Instant)>>,
+    latency: Option<Arc<LatencyHistogram>>,
+}
+
+impl FlowCounters {
+    /// Creates a new `FlowCounters`.
+    pub fn new() -> FlowCounters {
+        FlowCounters::default()
+    }
+
+    /// Creates a new `FlowCounters` which records the response latencies into the given
+    /// histogram.
+    pub fn with_latency(latency: Arc<LatencyHistogram>) -> FlowCounters {
+        FlowCounters {
+            latency: Some(latency),
+            ..FlowCounters::default()
+        }
+    }
+
+    /// Marks a request ending at the given sequence as forwarded to the proxy. Only the first
+    /// request awaiting its response is timed, the pipelined requests following it are not.
+    pub fn on_request(&self, sequence: u32) {
+        if self.latency.is_none() {
+            return;
+        }
+
+        let mut request = self.request.lock().unwrap();
+        if request.is_none() {
+            *request = Some((sequence, Instant::now()));
+        }
+    }
+
+    /// Marks a response acknowledging the given sequence as sent to the source. The latency of
+    /// the request awaiting its response is recorded if the response acknowledges the whole
+    /// request.
+    pub fn on_response(&self, acknowledgement: u32) {
+        let latency = match self.latency {
+            Some(ref latency) => latency,
+            None => return,
+        };
+
+        let mut request = self.request.lock().unwrap();
+        if let Some((sequence, instant)) = *request {
+            if sequence_le(sequence, acknowledgement) {
+                latency.record(instant.elapsed());
+                *request = None;
+            }
+        }
+    }
+
+    /// Adds the given bytes in the given packets sent from the source to the proxy.
+    pub fn add_up(&self, bytes: usize, packets: usize) {
+        self.up_bytes.fetch_add(bytes as u64, Ordering::Relaxed);
+        self.up_packets.fetch_add(packets as u64, Ordering::Relaxed);
+    }
+
+    /// Adds the given bytes in the given packets sent from the proxy to the source.
+    pub fn add_down(&self, bytes: usize, packets: usize) {
+        self.down_bytes.fetch_add(bytes as u64, Ordering::Relaxed);
+        self.down_packets
+            .fetch_add(packets as u64, Ordering::Relaxed);
+    }
+
+    /// Get the bytes sent from the source to the proxy.
+    pub fn get_up_bytes(&self) -> u64 {
+        self.up_bytes.load(Ordering::Relaxed)
+    }
+
+    /// Get the packets sent from the source to the proxy.
+    pub fn get_up_packets(&self) -> u64 {
+        self.up_packets.load(Ordering::Relaxed)
+    }
+
+    /// Get the bytes sent from the proxy to the source.
+    pub fn get_down_bytes(&self) -> u64 {
+        self.down_bytes.load(Ordering::Relaxed)
+    }
+
+    /// Get the packets sent from the proxy to the source.
+    pub fn get_down_packets(&self) -> u64 {
+        self.down_packets.load(Ordering::Relaxed)
+    }
+}
+
+/// Represents a snapshot of the counters of a TCP connection in a `ConnectionTable`.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct FlowStat<K> {
+    pub key: K,
+    pub state: State,
+    pub up_bytes: u64,
+    pub up_packets: u64,
+    pub down_bytes: u64,
+    pub down_packets: u64,
+}
+
+/// Represents the policy of rejecting new TCP connections when the connection limit is reached.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum ConnectionLimitPolicy {
+    /// Replies the SYN with a RST, the source fails immediately.
+    Reset,
+    /// Drops the SYN silently, the source retransmits until the capacity frees.
+    Drop,
+}
+
+/// Represents a table of TCP connections bounded by the capacity. The least recently used
+/// connections are evicted when the table is full, but only if they are idle.
+#[derive(Debug)]
+pub struct ConnectionTable<K: Hash + Eq> {
+    connections: LruCache<K, (Connection, Instant, Arc<FlowCounters>)>,
+    capacity: usize,
+    idle_timeout: Duration,
+    half_open_timeout: Duration,
+    keepalive_interval: Option<Duration>,
+    /// Represents the last keep-alive probe sent in each connection.
+    keepalives: HashMap<K, Instant>,
+    latency: Option<Arc<LatencyHistogram>>,
+}
+
+impl<K: Hash + Eq + Clone> ConnectionTable<K> {
+    /// Creates a `ConnectionTable` with the given capacity. Connections without any segment
+    /// within the idle timeout are considered idle. Half-open connections share the idle timeout
+    /// unless a half-open timeout is set.
+    pub fn new(capacity: usize, idle_timeout: Duration) -> ConnectionTable<K> {
+        ConnectionTable {
+            connections: LruCache::unbounded(),
+            capacity,
+            idle_timeout,
+            half_open_timeout: idle_timeout,
+            keepalive_interval: None,
+            keepalives: HashMap::new(),
+            latency: None,
+        }
+    }
+
+    /// Sets the histogram which records the response latencies of the connections inserted
+    /// afterwards, or `None` for not recording.
+    pub fn set_latency_histogram(&mut self, latency: Option<Arc<LatencyHistogram>>) {
+        self.latency = latency;
+    }
+
+    /// Sets the interval of keep-alive probes in established connections without any segment, or
+    /// `None` for not probing. The interval should be shorter than the idle timeout, so that
+    /// connections whose peers respond are never idle.
+    pub fn set_keepalive_interval(&mut self, interval: Option<Duration>) {
+        self.keepalive_interval = interval;
+    }
+
+    /// Returns the established connections without any segment or keep-alive probe within the
+    /// keep-alive interval, which are due to send a keep-alive probe. The probes are considered
+    /// sent.
+    pub fn keepalive(&mut self) -> Vec<K> {
+        let interval = match self.keepalive_interval {
+            Some(interval) => interval,
+            None => return Vec::new(),
+        };
+
+        let connections = &self.connections;
+        let keepalives = &mut self.keepalives;
+        keepalives.retain(|key, _| connections.contains(key));
+        let keys: Vec<K> = connections
+            .iter()
+            .filter(|(key, (connection, instant, _))| {
+                connection.get_state() == State::Established
+                    && instant.elapsed() >= interval
+                    && keepalives
+                        .get(key)
+                        .map_or(true, |last| last.elapsed() >= interval)
+            })
+            .map(|(key, _)| key.clone())
+            .collect();
+
+        let now = Instant::now();
+        for key in &keys {
+            keepalives.insert(key.clone(), now);
+        }
+
+        keys
+    }
+
+    /// Sets the timeout of half-open connections, which is usually shorter than the idle
+    /// timeout so that connections whose handshakes are never completed are reclaimed faster.
+    pub fn set_half_open_timeout(&mut self, timeout: Duration) {
+        self.half_open_timeout = timeout;
+    }
+
+    /// Inserts a connection into the table, and returns the connection evicted if the table is
+    /// full. The connection is given back as an error if the table is full and no connection is
+    /// idle.
+    pub fn insert(
+        &mut self,
+        key: K,
+        connection: Connection,
+    ) -> Result<Option<(K, Connection)>, Connection> {
+        let mut evicted = None;
+        if !self.connections.contains(&key) && self.connections.len() >= self.capacity {
+            let is_idle = match self.connections.peek_lru() {
+                Some((_, (_, instant, _))) => instant.elapsed() >= self.idle_timeout,
+                None => false,
+            };
+            if !is_idle {
+                return Err(connection);
+            }
+            evicted = self
+                .connections
+                .pop_lru()
+                .map(|(key, (connection, _, _))| (key, connection));
+        }
+
+        let counters = match self.connections.pop(&key) {
+            Some((_, _, counters)) => counters,
+            None => match self.latency {
+                Some(ref latency) => Arc::new(FlowCounters::with_latency(Arc::clone(latency))),
+                None => Arc::new(FlowCounters::new()),
+            },
+        };
+        self.connections
+            .put(key, (connection, Instant::now(), counters));
+
+        Ok(evicted)
+    }
+
+    /// Get the connection of the given key, and marks the connection as the most recently used.
+    pub fn get(&mut self, key: &K) -> Option<&mut Connection> {
+        match self.connections.get_mut(key) {
+            Some((connection, instant, _)) => {
+                *instant = Instant::now();
+                Some(connection)
+            }
+            None => None,
+        }
+    }
+
+    /// Get the connection of the given key without updating the LRU ordering.
+    pub fn peek(&self, key: &K) -> Option<&Connection> {
+        self.connections
+            .peek(key)
+            .map(|(connection, _, _)| connection)
+    }
+
+    /// Get the counters of the connection of the given key without updating the LRU ordering.
+    pub fn get_counters(&self, key: &K) -> Option<Arc<FlowCounters>> {
+        self.connections
+            .peek(key)
+            .map(|(_, _, counters)| Arc::clone(counters))
+    }
+
+    /// Returns a snapshot of the counters of all the connections in the table, the most recently
+    /// used first.
+    pub fn flows(&self) -> Vec<FlowStat<K>> {
+        self.connections
+            .iter()
+            .map(|(key, (connection, _, counters))| FlowStat {
+                key: key.clone(),
+                state: connection.get_state(),
+                up_bytes: counters.get_up_bytes(),
+                up_packets: counters.get_up_packets(),
+                down_bytes: counters.get_down_bytes(),
+                down_packets: counters.get_down_packets(),
+            })
+            .collect()
+    }
+
+    /// Returns if the table contains the connection of the given key.
+    pub fn contains(&self, key: &K) -> bool {
+        self.connections.contains(key)
+    }
+
+    /// Removes the connection of the given key.
+    pub fn remove(&mut self, key: &K) -> Option<Connection> {
+        self.connections
+            .pop(key)
+            .map(|(connection, _, _)| connection)
+    }
+
+    /// Removes all the idle connections and returns them.
+    pub fn purge(&mut self) -> Vec<(K, Connection)> {
+        let mut purged = Vec::new();
+        while let Some((_, (_, instant, _))) = self.connections.peek_lru() {
+            if instant.elapsed() < self.idle_timeout {
+                break;
+            }
+            if let Some((key, (connection, _, _))) = self.connections.pop_lru() {
+                purged.push((key, connection));
+            }
+        }
+
+        purged
+    }
+
+    /// Removes all the half-open connections without any segment within the half-open timeout
+    /// and returns them.
+    pub fn purge_half_open(&mut self) -> Vec<(K, Connection)> {
+        let timeout = self.half_open_timeout;
+        let keys: Vec<K> = self
+            .connections
+            .iter()
+            .filter(|(_, (connection, instant, _))| {
+                connection.is_half_open() && instant.elapsed() >= timeout
+            })
+            .map(|(key, _)| key.clone())
+            .collect();
+
+        keys.into_iter()
+            .filter_map(|key| {
+                self.connections
+                    .pop(&key)
+                    .map(|(connection, _, _)| (key, connection))
+            })
+            .collect()
+    }
+
+    /// Get the capacity of the table.
+    pub fn get_capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Get the idle timeout of the table.
+    pub fn get_idle_timeout(&self) -> Duration {
+        self.idle_timeout
+    }
+
+    /// Get the half-open timeout of the table.
+    pub fn get_half_open_timeout(&self) -> Duration {
+        self.half_open_timeout
+    }
+
+    /// Get the keep-alive interval of the table.
+    pub fn get_keepalive_interval(&self) -> Option<Duration> {
+        self.keepalive_interval
+    }
+
+    /// Returns the number of connections in the table.
+    pub fn len(&self) -> usize {
+        self.connections.len()
+    }
+
+    /// Returns if the table is empty.
+    pub fn is_empty(&self) -> bool {
+        self.connections.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Creates an established `Connection` with the local initial sequence 1000 and the source
+    /// initial sequence 5000.
+    fn handshake() -> Connection {
+        let mut connection = Connection::new(1000);
+        let actions = connection.on_segment(&Tcp::new_syn(1024, 80, 5000, 65535));
+        assert_eq!(actions, [Action::SendAckSyn]);
+        assert_eq!(connection.get_state(), State::SynReceived);
+        assert_eq!(connection.get_acknowledgement(), 5001);
+
+        let actions = connection.on_segment(&Tcp::new_ack(1024, 80, 5001, 1001, 65535));
+        assert!(actions.is_empty());
+        assert_eq!(connection.get_state(), State::Established);
+        assert_eq!(connection.get_sequence(), 1001);
+
+        connection
+    }
+
+    #[test]
+    fn handshake_and_passive_close() {
+        let mut connection = handshake();
+
+        let actions = connection
+            .on_segment_with_payload(&Tcp::new_ack(1024, 80, 5001, 1001, 65535), b"hello");
+        assert_eq!(actions, [Action::Deliver(0, 5), Action::SendAck]);
+        assert_eq!(connection.get_acknowledgement(), 5006);
+
+        let actions = connection.on_segment(&Tcp::new_ack_fin(1024, 80, 5006, 1001, 65535));
+        assert_eq!(actions, [Action::SendAck]);
+        assert_eq!(connection.get_state(), State::CloseWait);
+        assert_eq!(connection.get_acknowledgement(), 5007);
+
+        assert_eq!(connection.close(), [Action::SendAckFin]);
+        assert_eq!(connection.get_state(), State::LastAck);
+
+        let actions = connection.on_segment(&Tcp::new_ack(1024, 80, 5007, 1002, 65535));
+        assert_eq!(actions, [Action::Close]);
+        assert_eq!(connection.get_state(), State::Closed);
+    }
+
+    #[test]
+    fn handshake_and_active_close() {
+        let mut connection = handshake();
+
+        assert_eq!(connection.close(), [Action::SendAckFin]);
+        assert_eq!(connection.get_state(), State::FinWait1);
+
+        let actions = connection.on_segment(&Tcp::new_ack(1024, 80, 5001, 1002, 65535));
+        assert!(actions.is_empty());
+        assert_eq!(connection.get_state(), State::FinWait2);
+
+        let actions = connection.on_segment(&Tcp::new_ack_fin(1024, 80, 5001, 1002, 65535));
+        assert_eq!(actions, [Action::SendAck, Action::Close]);
+        assert_eq!(connection.get_state(), State::TimeWait);
+    }
+
+    #[test]
+    fn reset() {
+        let mut connection = handshake();
+
+        let actions = connection.on_segment(&Tcp::new_rst(1024, 80, 5001, 0, 0));
+        assert_eq!(actions, [Action::Close]);
+        assert_eq!(connection.get_state(), State::Closed);
+    }
+
+    #[test]
+    fn receive_in_order() {
+        let mut connection = handshake();
+
+        for (sequence, payload) in [(5001, b"hel"), (5004, b"lo!")] {
+            let actions = connection
+                .on_segment_with_payload(&Tcp::new_ack(1024, 80, sequence, 1001, 65535), payload);
+            assert_eq!(actions, [Action::Deliver(0, 3), Action::SendAck]);
+        }
+        assert_eq!(connection.get_acknowledgement(), 5007);
+        assert_eq!(connection.get_duplicates(), 0);
+    }
+
+    #[test]
+    fn receive_duplicate() {
+        let mut connection = handshake();
+        connection.on_segment_with_payload(&Tcp::new_ack(1024, 80, 5001, 1001, 65535), b"hello");
+
+        let actions = connection
+            .on_segment_with_payload(&Tcp::new_ack(1024, 80, 5001, 1001, 65535), b"hello");
+        assert_eq!(actions, [Action::SendAck]);
+        assert_eq!(connection.get_acknowledgement(), 5006);
+        assert_eq!(connection.get_duplicates(), 1);
+
+        // Only the new bytes of an overlapped segment are delivered
+        let actions =
+            connection.on_segment_with_payload(&Tcp::new_ack(1024, 80, 5004, 1001, 65535), b"lo!");
+        assert_eq!(actions, [Action::Deliver(2, 1), Action::SendAck]);
+        assert_eq!(connection.get_acknowledgement(), 5007);
+    }
+
+    #[test]
+    fn receive_wraparound() {
+        let mut connection = Connection::new_established(1001, 0xffff_fffe);
+
+        let actions = connection
+            .on_segment_with_payload(&Tcp::new_ack(1024, 80, 0xffff_fffe, 1001, 65535), b"hello");
+        assert_eq!(actions, [Action::Deliver(0, 5), Action::SendAck]);
+        assert_eq!(connection.get_acknowledgement(), 3);
+
+        assert_eq!(connection.check_segment(0xffff_fffe, 5), Segment::Duplicate);
+        assert_eq!(
+            connection.check_segment(0xffff_ffff, 5),
+            Segment::Overlapped(4)
+        );
+        assert_eq!(connection.check_segment(3, 5), Segment::InOrder);
+        assert_eq!(connection.check_segment(4, 5), Segment::OutOfOrder);
+    }
+
+    #[test]
+    fn sequence_compare() {
+        assert!(sequence_lt(1, 2));
+        assert!(sequence_lt(0xffff_ffff, 0));
+        assert!(!sequence_lt(0, 0xffff_ffff));
+        assert!(sequence_le(2, 2));
+    }
+
+    #[test]
+    fn receive_keep_alive() {
+        let mut connection = handshake();
+        connection.on_segment_with_payload(&Tcp::new_ack(1024, 80, 5001, 1001, 65535), b"hello");
+
+        // With or without a byte of garbage
+        for payload in [&b""[..], &b"\0"[..]] {
+            let actions = connection
+                .on_segment_with_payload(&Tcp::new_ack(1024, 80, 5005, 1001, 65535), payload);
+            assert_eq!(actions, [Action::SendAck]);
+            assert_eq!(connection.get_acknowledgement(), 5006);
+        }
+        assert_eq!(connection.get_duplicates(), 0);
+    }
+
+    #[test]
+    fn receive_zero_window_probe() {
+        let mut connection = handshake();
+        connection.on_segment_with_payload(&Tcp::new_ack(1024, 80, 5001, 1001, 65535), b"hello");
+        connection.set_receive_window(0);
+
+        // A zero window probe carries the next expected sequence
+        assert!(connection.is_zero_window_probe(5006, 1));
+        assert!(!is_keep_alive(5006, 1, connection.get_acknowledgement()));
+        let actions =
+            connection.on_segment_with_payload(&Tcp::new_ack(1024, 80, 5006, 1001, 65535), b"!");
+        assert_eq!(actions, [Action::SendAck]);
+        assert_eq!(connection.get_acknowledgement(), 5006);
+
+        // The payload is accepted once the window opens
+        connection.set_receive_window(u16::MAX);
+        let actions =
+            connection.on_segment_with_payload(&Tcp::new_ack(1024, 80, 5006, 1001, 65535), b"!");
+        assert_eq!(actions, [Action::Deliver(0, 1), Action::SendAck]);
+        assert_eq!(connection.get_acknowledgement(), 5007);
+    }
+
+    #[test]
+    fn table_evict_least_recently_used() {
+        // Every connection is idle
+        let mut table = ConnectionTable::new(2, Duration::from_secs(0));
+        assert!(matches!(table.insert(1, Connection::new(1000)), Ok(None)));
+        assert!(matches!(table.insert(2, Connection::new(1000)), Ok(None)));
+        table.get(&1).unwrap();
+
+        let evicted = table.insert(3, Connection::new(1000)).unwrap();
+        assert_eq!(evicted.map(|(key, _)| key), Some(2));
+        assert_eq!(table.len(), 2);
+        assert!(table.contains(&1) && table.contains(&3));
+    }
+
+    #[test]
+    fn table_full() {
+        let mut table = ConnectionTable::new(1, Duration::from_secs(60));
+        table.insert(1, Connection::new(1000)).unwrap();
+
+        assert!(table.insert(2, Connection::new(1000)).is_err());
+        // Connections in the table are replaced
+        assert!(matches!(table.insert(1, Connection::new(2000)), Ok(None)));
+        assert_eq!(table.peek(&1).unwrap().get_sequence(), 2000);
+    }
+
+    #[test]
+    fn table_purge() {
+        let mut table = ConnectionTable::new(2, Duration::from_millis(20));
+        table.insert(1, Connection::new(1000)).unwrap();
+        std::thread::sleep(Duration::from_millis(40));
+        table.insert(2, Connection::new(1000)).unwrap();
+
+        let purged: Vec<_> = table.purge().into_iter().map(|(key, _)| key).collect();
+        assert_eq!(purged, [1]);
+        assert_eq!(table.len(), 1);
+    }
+
+    #[test]
+    fn table_purge_half_open() {
+        let mut table = ConnectionTable::new(4, Duration::from_secs(60));
+        assert_eq!(table.get_half_open_timeout(), Duration::from_secs(60));
+        table.set_half_open_timeout(Duration::from_millis(20));
+        table.insert(1, Connection::new(1000)).unwrap();
+        let mut connection = Connection::new(1000);
+        connection.on_segment(&Tcp::new_syn(1024, 80, 5000, 65535));
+        assert!(connection.is_half_open());
+        table.insert(2, connection).unwrap();
+        table.insert(3, handshake()).unwrap();
+        std::thread::sleep(Duration::from_millis(40));
+        table.insert(4, Connection::new(1000)).unwrap();
+
+        // Established connections are left to the idle timeout
+        let mut purged: Vec<_> = table
+            .purge_half_open()
+            .into_iter()
+            .map(|(key, _)| key)
+            .collect();
+        purged.sort_unstable();
+        assert_eq!(purged, [1, 2]);
+        assert!(table.contains(&3) && table.contains(&4));
+        assert!(table.purge().is_empty());
+    }
+
+    #[test]
+    fn reorder_out_of_order() {
+        let mut buffer = ReorderBuffer::new(1000, 65535);
+        assert_eq!(buffer.insert(1010, b"klmno"), Reorder::Accepted);
+        assert_eq!(buffer.insert(1005, b"fghij"), Reorder::Accepted);
+        assert_eq!(buffer.pop(), None);
+        // Adjacent segments are coalesced
+        assert_eq!(buffer.get_ranges(), [(1005, 1015)]);
+        assert_eq!(buffer.len(), 10);
+
+        assert_eq!(buffer.insert(1000, b"abcde"), Reorder::Accepted);
+        assert_eq!(buffer.pop().unwrap(), b"abcdefghijklmno");
+        assert_eq!(buffer.get_sequence(), 1015);
+        assert!(buffer.is_empty());
+        assert_eq!(buffer.pop(), None);
+    }
+
+    #[test]
+    fn reorder_trim_overlap() {
+        let mut buffer = ReorderBuffer::new(1000, 65535);
+        buffer.insert(1004, b"EFGH");
+        buffer.insert(1010, b"KL");
+        // The bytes received first are kept, only the gaps are filled
+        assert_eq!(buffer.insert(1002, b"cdefghijklmn"), Reorder::Accepted);
+        assert_eq!(buffer.get_ranges(), [(1002, 1014)]);
+        assert_eq!(buffer.insert(1005, b"fgh"), Reorder::Duplicate);
+        // Bytes before the expected sequence are trimmed
+        assert_eq!(buffer.insert(998, b"xxab"), Reorder::Accepted);
+        assert_eq!(buffer.pop().unwrap(), b"abcdEFGHijKLmn");
+        assert_eq!(buffer.insert(1010, b"kl"), Reorder::Duplicate);
+    }
+
+    #[test]
+    fn reorder_window() {
+        let mut buffer = ReorderBuffer::new(u32::MAX - 3, 8);
+        assert_eq!(buffer.insert(4, b"ij"), Reorder::OutOfWindow);
+        // Bytes beyond the window are trimmed, across the wraparound
+        assert_eq!(buffer.insert(2, b"ghij"), Reorder::Accepted);
+        assert_eq!(buffer.get_ranges(), [(2, 4)]);
+        assert_eq!(buffer.len(), 2);
+
+        assert_eq!(buffer.insert(u32::MAX - 3, b"abcdef"), Reorder::Accepted);
+        assert_eq!(buffer.pop().unwrap(), b"abcdefgh");
+        assert_eq!(buffer.get_sequence(), 4);
+        // The window moves forward
+        assert_eq!(buffer.insert(4, b"ij"), Reorder::Accepted);
+        assert_eq!(buffer.pop().unwrap(), b"ij");
+    }
+
+    #[test]
+    fn sack_coalesce() {
+        let mut sack = SackBlocks::new();
+        sack.insert(100, 200);
+        sack.insert(300, 400);
+        assert_eq!(sack.get_blocks(), [(300, 400), (100, 200)]);
+
+        // Adjacent and overlapping ranges are coalesced, and moved to the front
+        sack.insert(200, 250);
+        assert_eq!(sack.get_blocks(), [(100, 250), (300, 400)]);
+        sack.insert(240, 310);
+        assert_eq!(sack.get_blocks(), [(100, 400)]);
+    }
+
+    #[test]
+    fn sack_drop_oldest() {
+        let mut sack = SackBlocks::new();
+        for i in 0..5 {
+            sack.insert(i * 100, i * 100 + 50);
+        }
+
+        assert_eq!(sack.len(), MAX_SACK_BLOCKS);
+        assert_eq!(
+            sack.get_blocks(),
+            [(400, 450), (300, 350), (200, 250), (100, 150)]
+        );
+    }
+
+    #[test]
+    fn sack_acknowledge() {
+        let mut sack = SackBlocks::new();
+        sack.insert(100, 200);
+        sack.insert(300, 400);
+
+        sack.acknowledge(350);
+        assert_eq!(sack.get_blocks(), [(350, 400)]);
+        sack.acknowledge(400);
+        assert!(sack.is_empty());
+    }
+
+    #[test]
+    fn receive_out_of_order_sack() {
+        let mut connection = Connection::new(1000);
+        let mut syn = Tcp::new_syn(1024, 80, 5000, 65535);
+        syn.set_sack_permitted(true);
+        connection.on_segment(&syn);
+        connection.on_segment(&Tcp::new_ack(1024, 80, 5001, 1001, 65535));
+        assert!(connection.is_sack_permitted());
+
+        // Two gaps of 5001-5011 and 5016-5021
+        connection.on_segment_with_payload(&Tcp::new_ack(1024, 80, 5011, 1001, 65535), b"hello");
+        connection.on_segment_with_payload(&Tcp::new_ack(1024, 80, 5021, 1001, 65535), b"world");
+        assert_eq!(connection.get_acknowledgement(), 5001);
+        assert_eq!(connection.get_sack_blocks(), [(5021, 5026), (5011, 5016)]);
+
+        // The block acknowledged is removed
+        connection.on_segment_with_payload(
+            &Tcp::new_ack(1024, 80, 5001, 1001, 65535),
+            b"0123456789hello",
+        );
+        assert_eq!(connection.get_acknowledgement(), 5016);
+        assert_eq!(connection.get_sack_blocks(), [(5021, 5026)]);
+    }
+
+    #[test]
+    fn receive_out_of_order_sack_not_permitted() {
+        let mut connection = handshake();
+        connection.on_segment_with_payload(&Tcp::new_ack(1024, 80, 5011, 1001, 65535), b"hello");
+
+        assert!(connection.get_sack_blocks().is_empty());
+    }
+
+    #[test]
+    fn table_flows() {
+        let mut table = ConnectionTable::new(2, Duration::from_secs(60));
+        table.insert(1, Connection::new(1000)).unwrap();
+        let counters = table.get_counters(&1).unwrap();
+        counters.add_up(5, 1);
+        counters.add_down(1460 * 2, 2);
+
+        // The counters are kept when the connection is replaced
+        table.insert(1, Connection::new(2000)).unwrap();
+        table.insert(2, Connection::new(1000)).unwrap();
+        let flows = table.flows();
+        assert_eq!(
+            flows,
+            [
+                FlowStat {
+                    key: 2,
+                    state: State::Listen,
+                    up_bytes: 0,
+                    up_packets: 0,
+                    down_bytes: 0,
+                    down_packets: 0,
+                },
+                FlowStat {
+                    key: 1,
+                    state: State::Listen,
+                    up_bytes: 5,
+                    up_packets: 1,
+                    down_bytes: 2920,
+                    down_packets: 2,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn receive_window_full_and_back() {
+        let mut window = ReceiveWindow::new(65535, 1460);
+        assert_eq!(window.update(0), 65535);
+        assert_eq!(window.update(30000), 35535);
+        assert_eq!(window.update(65535), 0);
+        assert!(window.is_zero());
+
+        // The window only opens by at least one MSS
+        assert_eq!(window.update(65535 - 100), 0);
+        assert_eq!(window.update(65535 - 1459), 0);
+        assert_eq!(window.update(65535 - 1460), 1460);
+        assert_eq!(window.update(65535 - 2000), 1460);
+        assert_eq!(window.update(0), 65535);
+    }
+
+    #[test]
+    fn receive_window_small_buffer() {
+        // The window opens by half of the buffer which is smaller than the MSS
+        let mut window = ReceiveWindow::new(1000, 1460);
+        assert_eq!(window.update(1000), 0);
+        assert_eq!(window.update(600), 0);
+        assert_eq!(window.update(500), 500);
+        assert_eq!(window.update(2000), 0);
+    }
+
+    #[test]
+    fn fast_open_cookies_validate() {
+        let cookies = FastOpenCookies::new();
+        let src = Ipv4Addr::new(10, 6, 0, 1);
+        let cookie = cookies.generate(src);
+        assert_eq!(cookie, cookies.generate(src));
+
+        assert!(cookies.validate(src, &cookie));
+        assert!(!cookies.validate(Ipv4Addr::new(10, 6, 0, 2), &cookie));
+        assert!(!cookies.validate(src, &cookie[..4]));
+        // Cookies of another key are invalid
+        assert!(!FastOpenCookies::new().validate(src, &cookie));
+    }
+
+    #[test]
+    fn table_keepalive() {
+        let mut table = ConnectionTable::new(4, Duration::from_secs(60));
+        table.insert(1, handshake()).unwrap();
+        table.insert(2, Connection::new(1000)).unwrap();
+        // Probes are not sent without an interval
+        std::thread::sleep(Duration::from_millis(40));
+        assert!(table.keepalive().is_empty());
+
+        table.set_keepalive_interval(Some(Duration::from_millis(20)));
+        assert_eq!(
+            table.get_keepalive_interval(),
+            Some(Duration::from_millis(20))
+        );
+        // Only established connections are probed, once in each interval
+        assert_eq!(table.keepalive(), [1]);
+        assert!(table.keepalive().is_empty());
+        std::thread::sleep(Duration::from_millis(40));
+        assert_eq!(table.keepalive(), [1]);
+
+        // Connections with segments are not idle
+        std::thread::sleep(Duration::from_millis(40));
+        table.get(&1).unwrap();
+        assert!(table.keepalive().is_empty());
+    }
+
+    #[test]
+    fn flow_counters_latency() {
+        let latency = Arc::new(LatencyHistogram::new());
+        let counters = FlowCounters::with_latency(Arc::clone(&latency));
+
+        // The response acknowledging a part of the request is not timed
+        counters.on_request(1006);
+        std::thread::sleep(Duration::from_millis(20));
+        counters.on_response(1003);
+        assert_eq!(latency.get_count(), 0);
+        // The pipelined request is not timed
+        counters.on_request(1011);
+        counters.on_response(1006);
+        assert_eq!(latency.get_count(), 1);
+        assert!(latency.get_max() >= Duration::from_millis(20));
+        counters.on_response(1011);
+        assert_eq!(latency.get_count(), 1);
+
+        // Counters without a histogram do not time
+        let counters = FlowCounters::new();
+        counters.on_request(1006);
+        counters.on_response(1006);
+    }
+
+    #[test]
+    fn table_latency_histogram() {
+        let latency = Arc::new(LatencyHistogram::new());
+        let mut table = ConnectionTable::new(4, Duration::from_secs(60));
+        table.insert(1, handshake()).unwrap();
+        table.set_latency_histogram(Some(Arc::clone(&latency)));
+        table.insert(2, handshake()).unwrap();
+
+        // Only the connections inserted afterwards record latencies
+        for key in [1, 2] {
+            let counters = table.get_counters(&key).unwrap();
+            counters.on_request(1);
+            counters.on_response(1);
+        }
+        assert_eq!(latency.get_count(), 1);
+    }
+}
@@ -1,10 +1,17 @@
 use super::ipv4::Ipv4;
-use super::{Layer, LayerType, LayerTypes};
+use super::ipv6::Ipv6;
+use super::{check_length, fmt_hex_dump, Layer, LayerType, LayerTypes, ParseError, SizeBounds};
+use lru::LruCache;
 use pnet::packet::udp::{self, MutableUdpPacket, UdpPacket};
+use pnet::packet::Packet;
 use std::clone::Clone;
+use std::cmp::min;
+use std::collections::hash_map::RandomState;
 use std::fmt::{self, Display, Formatter};
+use std::hash::{BuildHasher, Hash, Hasher};
 use std::io;
-use std::net::Ipv4Addr;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddrV4};
+use std::time::{Duration, Instant};
 
 /// Represents an UDP packet.
 #[derive(Clone, Debug)]
@@ -12,6 +19,11 @@ pub struct Udp {
     pub layer: udp::Udp,
     pub src: Ipv4Addr,
     pub dst: Ipv4Addr,
+    /// Represents the source and destination IP address if the layer is carried over IPv6.
+    pub ipv6: Option<(Ipv6Addr, Ipv6Addr)>,
+    ipv4_checksum: bool,
+    checksum_offload: bool,
+    payload: Vec<u8>,
 }
 
 impl Udp {
@@ -33,6 +45,10 @@ impl Udp {
             layer: udp,
             src: Ipv4Addr::UNSPECIFIED,
             dst: Ipv4Addr::UNSPECIFIED,
+            ipv6: None,
+            ipv4_checksum: true,
+            checksum_offload: false,
+            payload: vec![],
         }
     }
 
@@ -46,15 +62,155 @@ impl Udp {
             payload: vec![],
         };
         let mut udp = Udp::from(d_udp);
+        udp.payload = packet.payload().to_vec();
         udp.set_ipv4_layer(ipv4);
 
         udp
     }
 
+    /// Deserializes an `Udp` from the given byte-array and returns it with the number of bytes
+    /// consumed. The source and destination IP address of the layer are left unspecified.
+    pub fn deserialize(buffer: &[u8]) -> Result<(Udp, usize), ParseError> {
+        let (mut udp, header_length) = Udp::deserialize_header(buffer)?;
+        let end = min(udp.get_length() as usize, buffer.len());
+        udp.payload = buffer[header_length..end].to_vec();
+
+        Ok((udp, header_length))
+    }
+
+    /// Deserializes an `Udp` from the given byte-array without copying its payload, and returns
+    /// it with the number of bytes consumed. The payload of the layer is left empty.
+    pub fn deserialize_header(buffer: &[u8]) -> Result<(Udp, usize), ParseError> {
+        if buffer.len() < Udp::min_size() {
+            return Err(ParseError::Truncated(LayerTypes::Udp));
+        }
+        let packet = UdpPacket::new(buffer).ok_or(ParseError::Truncated(LayerTypes::Udp))?;
+        if (packet.get_length() as usize) < UdpPacket::minimum_packet_size() {
+            return Err(ParseError::InvalidValue(LayerTypes::Udp, "length"));
+        }
+        let d_udp = udp::Udp {
+            source: packet.get_source(),
+            destination: packet.get_destination(),
+            length: packet.get_length(),
+            checksum: packet.get_checksum(),
+            payload: vec![],
+        };
+
+        Ok((Udp::from(d_udp), UdpPacket::minimum_packet_size()))
+    }
+
+    /// Get the payload of the layer when the layer is parsed or deserialized.
+    pub fn get_payload(&self) -> &[u8] {
+        &self.payload
+    }
+
+    /// Sets if the checksum is computed when the layer is carried over IPv4. The checksum is
+    /// optional in IPv4 and will be written as 0 if it is disabled.
+    pub fn set_ipv4_checksum(&mut self, enabled: bool) {
+        self.ipv4_checksum = enabled;
+    }
+
+    /// Computes the checksum of the layer with the given payload, including the pseudo-header
+    /// of the given source and destination IP address. A computed checksum of 0 is returned as
+    /// 0xFFFF because 0 means no checksum is transmitted. Returns an error if the source and the
+    /// destination are of different address families.
+    pub fn compute_checksum(&self, src: IpAddr, dst: IpAddr, payload: &[u8]) -> io::Result<u16> {
+        let size = self.get_size() + payload.len();
+        let mut buffer = vec![0u8; size];
+        let mut packet = MutableUdpPacket::new(&mut buffer).unwrap();
+
+        packet.populate(&self.layer);
+        packet.set_length(size as u16);
+        packet.set_payload(payload);
+
+        let checksum = match (src, dst) {
+            (IpAddr::V4(src), IpAddr::V4(dst)) => {
+                udp::ipv4_checksum(&packet.to_immutable(), &src, &dst)
+            }
+            (IpAddr::V6(src), IpAddr::V6(dst)) => {
+                udp::ipv6_checksum(&packet.to_immutable(), &src, &dst)
+            }
+            _ => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "address families mismatch",
+                ))
+            }
+        };
+
+        Ok(transmitted_checksum(checksum))
+    }
+
+    /// Sets if the checksum is left to the checksum offload of the link. The checksum will be
+    /// written as 0 if it is offloaded, and the NIC or the kernel fills it.
+    pub fn set_checksum_offload(&mut self, offload: bool) {
+        self.checksum_offload = offload;
+    }
+
+    /// Computes the checksum of the given serialized datagram in place, including the
+    /// pseudo-header of the IP addresses of the layer. The checksum is mandatory in IPv6.
+    fn compute_checksum_in_place(&self, packet: &UdpPacket) -> u16 {
+        if self.checksum_offload {
+            return 0;
+        }
+
+        let checksum = match self.ipv6 {
+            Some((ref src, ref dst)) => udp::ipv6_checksum(packet, src, dst),
+            None => {
+                if !self.ipv4_checksum {
+                    return 0;
+                }
+                udp::ipv4_checksum(packet, &self.src, &self.dst)
+            }
+        };
+
+        transmitted_checksum(checksum)
+    }
+
+    /// Computes the checksum of the given serialized datagram carrying its payload in place,
+    /// including the pseudo-header of the given source and destination IP address, or of the IP
+    /// addresses of the layer if `None`.
+    pub fn patch_checksum(
+        &self,
+        buffer: &mut [u8],
+        addrs: Option<(IpAddr, IpAddr)>,
+    ) -> io::Result<()> {
+        let mut packet = MutableUdpPacket::new(buffer)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::WriteZero, "buffer too small"))?;
+
+        let checksum = match addrs {
+            None => self.compute_checksum_in_place(&packet.to_immutable()),
+            Some(_) if self.checksum_offload => 0,
+            Some((IpAddr::V4(_), IpAddr::V4(_))) if !self.ipv4_checksum => 0,
+            Some((IpAddr::V4(src), IpAddr::V4(dst))) => {
+                transmitted_checksum(udp::ipv4_checksum(&packet.to_immutable(), &src, &dst))
+            }
+            Some((IpAddr::V6(src), IpAddr::V6(dst))) => {
+                transmitted_checksum(udp::ipv6_checksum(&packet.to_immutable(), &src, &dst))
+            }
+            Some(_) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "address families mismatch",
+                ))
+            }
+        };
+        packet.set_checksum(checksum);
+
+        Ok(())
+    }
+
     /// Sets the source and destination IP address for the layer with the given `Ipv4`.
     pub fn set_ipv4_layer(&mut self, ipv4: &Ipv4) {
         self.src = ipv4.get_src();
         self.dst = ipv4.get_dst();
+        self.ipv6 = None;
+    }
+
+    /// Sets the source and destination IP address for the layer with the given `Ipv6`. The
+    /// checksum of the layer will be computed with the pseudo-header of IPv6.
+    pub fn set_ipv6_layer(&mut self, ipv6: &Ipv6) {
+        self.ipv6 = Some((ipv6.get_src(), ipv6.get_dst()));
     }
 
     /// Get the source IP address of the layer.
@@ -77,14 +233,66 @@ impl Udp {
         self.layer.destination
     }
 
+    /// Sets the source of the layer.
+    pub fn set_src(&mut self, src: u16) {
+        self.layer.source = src;
+    }
+
+    /// Sets the destination of the layer.
+    pub fn set_dst(&mut self, dst: u16) {
+        self.layer.destination = dst;
+    }
+
     /// Get the length of the layer.
     pub fn get_length(&self) -> u16 {
         self.layer.length
     }
+
+    /// Sets the length of the layer, which covers the header and the payload. The length is
+    /// fixed again when the layer is serialized.
+    pub fn set_length(&mut self, length: u16) -> io::Result<()> {
+        if (length as usize) < UdpPacket::minimum_packet_size() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "length too small",
+            ));
+        }
+        self.layer.length = length;
+
+        Ok(())
+    }
+}
+
+impl PartialEq for Udp {
+    fn eq(&self, other: &Udp) -> bool {
+        self.layer.source == other.layer.source
+            && self.layer.destination == other.layer.destination
+            && self.layer.length == other.layer.length
+            && self.layer.checksum == other.layer.checksum
+            && self.src == other.src
+            && self.dst == other.dst
+            && self.ipv6 == other.ipv6
+            && self.ipv4_checksum == other.ipv4_checksum
+            && self.checksum_offload == other.checksum_offload
+            && self.payload == other.payload
+    }
+}
+
+/// Get the checksum transmitted of the given computed checksum. A computed checksum of 0 is
+/// transmitted as 0xFFFF, because 0 means no checksum is transmitted.
+fn transmitted_checksum(checksum: u16) -> u16 {
+    match checksum {
+        0 => 0xFFFF,
+        checksum => checksum,
+    }
 }
 
 impl Display for Udp {
     fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        if f.alternate() {
+            return fmt_hex_dump(self, f);
+        }
+
         write!(
             f,
             "{}: {} -> {}, Length = {}",
@@ -96,6 +304,16 @@ impl Display for Udp {
     }
 }
 
+impl SizeBounds for Udp {
+    fn min_size() -> usize {
+        UdpPacket::minimum_packet_size()
+    }
+
+    fn max_size() -> usize {
+        UdpPacket::minimum_packet_size()
+    }
+}
+
 impl Layer for Udp {
     fn get_type(&self) -> LayerType {
         LayerTypes::Udp
@@ -106,26 +324,25 @@ impl Layer for Udp {
     }
 
     fn serialize(&self, buffer: &mut [u8], n: usize) -> io::Result<usize> {
-        let mut packet = MutableUdpPacket::new(buffer)
-            .ok_or(io::Error::new(io::ErrorKind::WriteZero, "buffer too small"))?;
+        let present = buffer.len();
+        let header_length = min(self.get_size(), present);
+        let mut packet = MutableUdpPacket::new(&mut buffer[..header_length])
+            .ok_or_else(|| io::Error::new(io::ErrorKind::WriteZero, "buffer too small"))?;
 
         packet.populate(&self.layer);
 
         // Fix length
-        if n > u16::MAX as usize {
-            return Err(io::Error::new(
-                io::ErrorKind::InvalidInput,
-                "length too big",
-            ));
-        }
+        check_length(
+            LayerTypes::Udp,
+            n,
+            self.get_size(),
+            present,
+            u16::MAX as usize,
+        )?;
         packet.set_length(n as u16);
 
         // Compute checksum
-        let checksum = udp::ipv4_checksum(
-            &packet.to_immutable(),
-            &self.get_src_ip_addr(),
-            &self.get_dst_ip_addr(),
-        );
+        let checksum = self.compute_checksum_in_place(&packet.to_immutable());
         packet.set_checksum(checksum);
 
         Ok(self.get_size())
@@ -137,8 +354,12 @@ impl Layer for Udp {
         payload: &[u8],
         n: usize,
     ) -> io::Result<usize> {
-        let mut packet = MutableUdpPacket::new(buffer)
-            .ok_or(io::Error::new(io::ErrorKind::WriteZero, "buffer too small"))?;
+        let length = self.get_size() + payload.len();
+        if buffer.len() < length {
+            return Err(io::Error::new(io::ErrorKind::WriteZero, "buffer too small"));
+        }
+        let mut packet = MutableUdpPacket::new(&mut buffer[..length])
+            .ok_or_else(|| io::Error::new(io::ErrorKind::WriteZero, "buffer too small"))?;
 
         packet.populate(&self.layer);
 
@@ -146,22 +367,274 @@ impl Layer for Udp {
         packet.set_payload(payload);
 
         // Fix length
-        if n > u16::MAX as usize {
-            return Err(io::Error::new(
-                io::ErrorKind::InvalidInput,
-                "length too big",
-            ));
-        }
+        check_length(
+            LayerTypes::Udp,
+            n,
+            self.get_size(),
+            self.get_size() + payload.len(),
+            u16::MAX as usize,
+        )?;
         packet.set_length(n as u16);
 
-        // Compute checksum
-        let checksum = udp::ipv4_checksum(
-            &packet.to_immutable(),
-            &self.get_src_ip_addr(),
-            &self.get_dst_ip_addr(),
-        );
+        // Compute checksum in place
+        let checksum = self.compute_checksum_in_place(&packet.to_immutable());
         packet.set_checksum(checksum);
 
-        Ok(self.get_size() + n)
+        Ok(length)
+    }
+
+    fn clone_boxed(&self) -> Box<dyn Layer> {
+        Box::new(self.clone())
+    }
+}
+
+/// Represents the default number of recent datagrams remembered by a `DuplicateGuard`.
+pub const DEFAULT_DUPLICATE_GUARD_CAPACITY: usize = 4096;
+
+/// Represents a guard against duplicate UDP datagrams, e.g., duplicated by the network or
+/// retransmitted by the source. Each datagram is remembered by a keyed hash of its flow and
+/// payload, and an identical datagram of the same flow within the window is a duplicate. The
+/// least recently seen datagrams are forgotten when the guard is full.
+#[derive(Debug)]
+pub struct DuplicateGuard {
+    window: Duration,
+    key: RandomState,
+    seen: LruCache<u64, Instant>,
+}
+
+impl DuplicateGuard {
+    /// Creates a new `DuplicateGuard` with the given window.
+    pub fn new(window: Duration) -> DuplicateGuard {
+        DuplicateGuard::with_capacity(window, DEFAULT_DUPLICATE_GUARD_CAPACITY)
+    }
+
+    /// Creates a new `DuplicateGuard` with the given window and number of datagrams remembered.
+    pub fn with_capacity(window: Duration, capacity: usize) -> DuplicateGuard {
+        DuplicateGuard {
+            window,
+            key: RandomState::new(),
+            seen: LruCache::new(capacity.max(1)),
+        }
+    }
+
+    /// Get the window of the guard.
+    pub fn get_window(&self) -> Duration {
+        self.window
+    }
+
+    /// Records the given datagram, and returns if it duplicates a datagram seen within the
+    /// window.
+    pub fn check(&mut self, src: SocketAddrV4, dst: SocketAddrV4, payload: &[u8]) -> bool {
+        let mut hasher = self.key.build_hasher();
+        src.hash(&mut hasher);
+        dst.hash(&mut hasher);
+        payload.hash(&mut hasher);
+        let hash = hasher.finish();
+
+        let now = Instant::now();
+        let is_duplicate = match self.seen.get(&hash) {
+            Some(instant) => now.duration_since(*instant) <= self.window,
+            None => false,
+        };
+        // The window starts from the datagram last passed
+        if !is_duplicate {
+            self.seen.put(hash, now);
+        }
+
+        is_duplicate
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn deserialize_serialized() {
+        let udp = Udp::new(1024, 53);
+        let mut buffer = vec![0u8; udp.get_size()];
+        let n = udp.serialize(&mut buffer, udp.get_size()).unwrap();
+
+        // The length and checksum are only known once serialized
+        let (deserialized, size) = Udp::deserialize(&buffer).unwrap();
+        assert_eq!(size, n);
+        let mut reserialized = vec![0u8; deserialized.get_size()];
+        deserialized
+            .serialize(&mut reserialized, deserialized.get_size())
+            .unwrap();
+        assert_eq!(reserialized, buffer);
+        assert_eq!(Udp::deserialize(&reserialized).unwrap().0, deserialized);
+    }
+
+    #[test]
+    fn deserialize_invalid_length() {
+        let mut buffer = vec![0u8; 8];
+        buffer[5] = 7;
+
+        assert!(matches!(
+            Udp::deserialize(&buffer),
+            Err(ParseError::InvalidValue(LayerTypes::Udp, _))
+        ));
+    }
+
+    #[test]
+    fn compute_checksum_ipv6_zero() {
+        let mut udp = Udp::new(1024, 53);
+        // The payload complements the sum of the datagram to 0xFFFF, so the checksum is 0
+        let payload = [0xa0, 0x30];
+        let checksum = udp
+            .compute_checksum(
+                IpAddr::V6("2001:db8::1".parse().unwrap()),
+                IpAddr::V6("2001:db8::2".parse().unwrap()),
+                &payload,
+            )
+            .unwrap();
+        assert_eq!(checksum, 0xffff);
+
+        let ipv6 = Ipv6::new(
+            LayerTypes::Udp,
+            "2001:db8::1".parse().unwrap(),
+            "2001:db8::2".parse().unwrap(),
+        )
+        .unwrap();
+        udp.set_ipv6_layer(&ipv6);
+        let mut buffer = vec![0u8; udp.get_size() + payload.len()];
+        udp.serialize_with_payload(&mut buffer, &payload, udp.get_size() + payload.len())
+            .unwrap();
+        assert_eq!(buffer[6..8], [0xff, 0xff]);
+    }
+
+    #[test]
+    fn serialize_ipv4_without_checksum() {
+        let ipv4 = Ipv4::new(
+            1,
+            LayerTypes::Udp,
+            Ipv4Addr::new(192, 168, 1, 1),
+            Ipv4Addr::new(192, 168, 1, 2),
+        )
+        .unwrap();
+        let mut udp = Udp::new(1024, 53);
+        udp.set_ipv4_layer(&ipv4);
+        udp.set_ipv4_checksum(false);
+        let mut buffer = vec![0u8; udp.get_size() + 4];
+        udp.serialize_with_payload(&mut buffer, b"ping", udp.get_size() + 4)
+            .unwrap();
+
+        assert_eq!(buffer[6..8], [0x00, 0x00]);
+    }
+
+    #[test]
+    fn compute_checksum_mismatched() {
+        let udp = Udp::new(1024, 53);
+
+        assert!(udp
+            .compute_checksum(
+                IpAddr::V4(Ipv4Addr::new(192, 168, 1, 1)),
+                IpAddr::V6("2001:db8::2".parse().unwrap()),
+                &[],
+            )
+            .is_err());
+    }
+
+    #[test]
+    fn serialize_invalid_length() {
+        use crate::packet::layer::tests::unwrap_serialize_error;
+        use crate::packet::layer::SerializeError;
+
+        let udp = Udp::new(1024, 53);
+        let mut buffer = vec![0u8; 70000];
+        udp.serialize_with_payload(&mut buffer, b"query", 13)
+            .unwrap();
+
+        // The length covers more than the payload
+        let e = udp
+            .serialize_with_payload(&mut buffer, b"query", 14)
+            .unwrap_err();
+        assert_eq!(
+            unwrap_serialize_error(e),
+            SerializeError::InvalidLength(LayerTypes::Udp, 14)
+        );
+        for (present, n) in [(16, 7), (16, 17), (70000, 65536)] {
+            let e = udp.serialize(&mut buffer[..present], n).unwrap_err();
+            assert_eq!(
+                unwrap_serialize_error(e),
+                SerializeError::InvalidLength(LayerTypes::Udp, n)
+            );
+        }
+    }
+
+    #[test]
+    fn set_ports_and_length() {
+        let mut udp = Udp::new(1024, 53);
+        udp.set_src(5353);
+        udp.set_dst(5354);
+        udp.set_length(13).unwrap();
+        assert_eq!(udp.get_length(), 13);
+        let e = udp.set_length(7).unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::InvalidInput);
+        assert_eq!(udp.get_length(), 13);
+
+        // The length is fixed when serialized
+        let mut buffer = vec![0u8; 12];
+        udp.serialize_with_payload(&mut buffer, b"ping", 12)
+            .unwrap();
+        assert_eq!(buffer[..6], [0x14, 0xe9, 0x14, 0xea, 0x00, 0x0c]);
+    }
+
+    #[test]
+    fn duplicate_guard_window() {
+        let src = SocketAddrV4::new(Ipv4Addr::new(10, 6, 0, 1), 1024);
+        let dst = SocketAddrV4::new(Ipv4Addr::new(93, 184, 216, 34), 53);
+        let mut guard = DuplicateGuard::new(Duration::from_millis(100));
+        assert_eq!(guard.get_window(), Duration::from_millis(100));
+
+        assert!(!guard.check(src, dst, b"query"));
+        assert!(guard.check(src, dst, b"query"));
+        // Datagrams of other payloads or flows are not duplicates
+        assert!(!guard.check(src, dst, b"other"));
+        let other = SocketAddrV4::new(Ipv4Addr::new(10, 6, 0, 1), 1025);
+        assert!(!guard.check(other, dst, b"query"));
+
+        // The datagram outside the window passes
+        std::thread::sleep(Duration::from_millis(150));
+        assert!(!guard.check(src, dst, b"query"));
+        assert!(guard.check(src, dst, b"query"));
+    }
+
+    #[test]
+    fn duplicate_guard_capacity() {
+        let src = SocketAddrV4::new(Ipv4Addr::new(10, 6, 0, 1), 1024);
+        let dst = SocketAddrV4::new(Ipv4Addr::new(93, 184, 216, 34), 53);
+        let mut guard = DuplicateGuard::with_capacity(Duration::from_secs(60), 2);
+
+        assert!(!guard.check(src, dst, b"first"));
+        assert!(!guard.check(src, dst, b"second"));
+        assert!(!guard.check(src, dst, b"third"));
+        // The least recently seen datagram is forgotten
+        assert!(!guard.check(src, dst, b"first"));
+        assert!(guard.check(src, dst, b"third"));
+    }
+
+    #[test]
+    fn serialize_checksum_offload() {
+        let ipv4 = Ipv4::new(
+            1,
+            LayerTypes::Udp,
+            Ipv4Addr::new(10, 6, 0, 1),
+            Ipv4Addr::new(93, 184, 216, 34),
+        )
+        .unwrap();
+        let mut udp = Udp::new(1024, 53);
+        udp.set_ipv4_layer(&ipv4);
+        let n = udp.get_size() + 5;
+        let mut buffer = vec![0u8; n];
+        udp.serialize_with_payload(&mut buffer, b"query", n)
+            .unwrap();
+        assert_ne!(buffer[6..8], [0, 0]);
+
+        udp.set_checksum_offload(true);
+        udp.serialize_with_payload(&mut buffer, b"query", n)
+            .unwrap();
+        assert_eq!(buffer[6..8], [0, 0]);
     }
 }
@@ -0,0 +1,100 @@
+use super::{fmt_hex_dump, Layer, LayerType, LayerTypes};
+use pnet::packet::ethernet::EtherType;
+use std::clone::Clone;
+use std::fmt::{self, Display, Formatter};
+use std::io::{self, Write};
+
+/// Represents a network layer of an unrecognized EtherType, which is kept as raw bytes.
+#[derive(Clone, Debug)]
+pub struct Unknown {
+    ethertype: EtherType,
+    payload: Vec<u8>,
+}
+
+impl Unknown {
+    /// Creates an `Unknown` with the given EtherType and the bytes following the link layer.
+    pub fn new(ethertype: EtherType, payload: &[u8]) -> Unknown {
+        Unknown {
+            ethertype,
+            payload: payload.to_vec(),
+        }
+    }
+
+    /// Get the EtherType of the layer.
+    pub fn get_ethertype(&self) -> EtherType {
+        self.ethertype
+    }
+
+    /// Get the raw bytes of the layer.
+    pub fn get_payload(&self) -> &[u8] {
+        &self.payload
+    }
+}
+
+impl Display for Unknown {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        if f.alternate() {
+            return fmt_hex_dump(self, f);
+        }
+
+        write!(
+            f,
+            "{}: EtherType = 0x{:04x}, Length = {}",
+            LayerTypes::Unknown,
+            self.ethertype.0,
+            self.payload.len()
+        )
+    }
+}
+
+impl Layer for Unknown {
+    fn get_type(&self) -> LayerType {
+        LayerTypes::Unknown
+    }
+
+    fn get_size(&self) -> usize {
+        self.payload.len()
+    }
+
+    fn serialize(&self, buffer: &mut [u8], _: usize) -> io::Result<usize> {
+        if buffer.len() < self.payload.len() {
+            return Err(io::Error::new(io::ErrorKind::WriteZero, "buffer too small"));
+        }
+
+        // Copies raw bytes
+        buffer[..self.payload.len()].copy_from_slice(&self.payload);
+
+        Ok(self.get_size())
+    }
+
+    fn serialize_with_payload(&self, buffer: &mut [u8], _: &[u8], n: usize) -> io::Result<usize> {
+        self.serialize(buffer, n)
+    }
+
+    fn clone_boxed(&self) -> Box<dyn Layer> {
+        Box::new(self.clone())
+    }
+
+    fn serialize_to_writer(&self, w: &mut dyn Write) -> io::Result<usize> {
+        // Writes raw bytes
+        w.write_all(&self.payload)?;
+
+        Ok(self.get_size())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn serialize_raw() {
+        let unknown = Unknown::new(EtherType(0x88b5), b"raw");
+        let mut buffer = vec![0u8; unknown.get_size()];
+        assert_eq!(unknown.serialize(&mut buffer, 0).unwrap(), 3);
+        assert_eq!(buffer, b"raw");
+
+        let mut buffer = vec![0u8; 2];
+        assert!(unknown.serialize(&mut buffer, 0).is_err());
+    }
+}
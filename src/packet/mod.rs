@@ -1,23 +1,75 @@
+use log::debug;
 use pnet::packet::arp::ArpPacket;
-use pnet::packet::ethernet::{EtherTypes, EthernetPacket};
-use pnet::packet::ip::IpNextHeaderProtocols;
+use pnet::packet::ethernet::{EtherType, EtherTypes, EthernetPacket};
+use pnet::packet::ip::{IpNextHeaderProtocol, IpNextHeaderProtocols};
 use pnet::packet::ipv4::{Ipv4Flags, Ipv4Packet};
-use pnet::packet::tcp::TcpPacket;
-use pnet::packet::udp::UdpPacket;
+use pnet::packet::ipv6::Ipv6Packet;
 use pnet::packet::Packet;
-use std::collections::HashMap;
+use pnet::util::MacAddr;
+use std::cmp::min;
+use std::collections::hash_map::Entry;
+use std::collections::{HashMap, HashSet};
 use std::fmt::{self, Display, Formatter};
-use std::io;
-use std::net::Ipv4Addr;
+use std::io::{self, Write};
+use std::net::{Ipv4Addr, Ipv6Addr};
 use std::time::Instant;
 
+pub mod builder;
+pub mod flow;
+pub mod iter;
 pub mod layer;
+pub mod nat64;
+pub mod rewrite;
+pub use builder::PacketBuilder;
+pub use flow::FlowKey;
+pub use iter::{layers, parse_ethernet_stack, LayerIter};
 use layer::arp::Arp;
 use layer::ethernet::Ethernet;
+use layer::icmp::Icmp;
+use layer::icmpv6::Icmpv6;
+use layer::igmp::Igmp;
 use layer::ipv4::Ipv4;
+use layer::ipv6::{self, Ipv6, FRAGMENT_HEADER_SIZE};
+use layer::sll::Sll;
 use layer::tcp::Tcp;
 use layer::udp::Udp;
-use layer::{Layer, LayerType, LayerTypes, Layers};
+use layer::unknown::Unknown;
+use layer::{Layer, LayerType, LayerTypes, Layers, ParseError};
+pub use rewrite::Rewriter;
+
+/// Represents the link type of frames.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum LinkType {
+    /// Ethernet frames.
+    Ethernet,
+    /// Linux cooked capture (SLL) frames, captured on the Linux `any` pseudo-interface.
+    LinuxSll,
+}
+
+/// Represents the pcap link type of Ethernet.
+pub const LINKTYPE_ETHERNET: u32 = 1;
+/// Represents the pcap link type of Linux cooked capture (SLL).
+pub const LINKTYPE_LINUX_SLL: u32 = 113;
+
+impl LinkType {
+    /// Get the `LinkType` of the given pcap link type. Returns `None` if the link type is not
+    /// supported.
+    pub fn from_pcap(link_type: u32) -> Option<LinkType> {
+        match link_type {
+            LINKTYPE_ETHERNET => Some(LinkType::Ethernet),
+            LINKTYPE_LINUX_SLL => Some(LinkType::LinuxSll),
+            _ => None,
+        }
+    }
+
+    /// Get the pcap link type of the `LinkType`.
+    pub fn to_pcap(&self) -> u32 {
+        match self {
+            LinkType::Ethernet => LINKTYPE_ETHERNET,
+            LinkType::LinuxSll => LINKTYPE_LINUX_SLL,
+        }
+    }
+}
 
 /// Represents a packet indicator.
 #[derive(Clone, Debug)]
@@ -25,6 +77,8 @@ pub struct Indicator {
     pub link: Layers,
     pub network: Option<Layers>,
     pub transport: Option<Layers>,
+    /// Represents the error of the layer failed to be parsed, if any.
+    pub error: Option<ParseError>,
 }
 
 impl Indicator {
@@ -34,59 +88,167 @@ impl Indicator {
             link,
             network,
             transport,
+            error: None,
         }
     }
 
-    /// Creates a `Indicator` by the given Ethernet packet.
+    /// Creates a `Indicator` by the given Ethernet packet. The payload is not copied into the
+    /// layers, it follows the `Indicator::get_size` bytes of the packet.
     pub fn parse(packet: &EthernetPacket) -> Indicator {
+        let ethernet = Ethernet::parse(packet);
+        // Skip the VLAN tags
+        let payload = &packet.packet()[min(ethernet.get_size(), packet.packet().len())..];
+        let (network, transport, error) =
+            Indicator::parse_network(ethernet.get_ethertype(), payload);
+
+        Indicator {
+            link: Layers::Ethernet(ethernet),
+            network,
+            transport,
+            error,
+        }
+    }
+
+    /// Creates a `Indicator` by the given SLL frame.
+    pub fn parse_sll(frame: &[u8]) -> Result<Indicator, ParseError> {
+        let (sll, n) = Sll::deserialize(frame)?;
+        let (network, transport, error) = Indicator::parse_network(sll.get_protocol(), &frame[n..]);
+
+        Ok(Indicator {
+            link: Layers::Sll(sll),
+            network,
+            transport,
+            error,
+        })
+    }
+
+    /// Parses the network and transport layer of the given EtherType from the bytes following
+    /// the link layer, and returns the error of the layer failed to be parsed.
+    fn parse_network(
+        ethertype: EtherType,
+        payload: &[u8],
+    ) -> (Option<Layers>, Option<Layers>, Option<ParseError>) {
         let mut transport = None;
+        let mut error = None;
 
-        let link = Layers::Ethernet(Ethernet::parse(packet));
-        let network = match packet.get_ethertype() {
-            EtherTypes::Arp => match ArpPacket::new(packet.payload()) {
+        let network = match ethertype {
+            EtherTypes::Arp => match ArpPacket::new(payload) {
                 Some(ref arp_packet) => Some(Layers::Arp(Arp::parse(arp_packet))),
                 None => None,
             },
-            EtherTypes::Ipv4 => match Ipv4Packet::new(packet.payload()) {
-                Some(ref ipv4_packet) => {
-                    let ipv4 = Ipv4::parse(ipv4_packet);
+            EtherTypes::Ipv4 => match Ipv4::deserialize_header(payload) {
+                Ok((ipv4, _)) => {
+                    let ipv4_packet = Ipv4Packet::new(payload).unwrap();
                     // Fragment
                     if ipv4_packet.get_flags() & Ipv4Flags::MoreFragments == 0
                         && ipv4_packet.get_fragment_offset() <= 0
                     {
                         transport = match ipv4_packet.get_next_level_protocol() {
+                            // The header of a truncated frame may claim more bytes than captured
                             IpNextHeaderProtocols::Tcp => {
-                                match TcpPacket::new(ipv4_packet.payload()) {
-                                    Some(ref tcp_packet) => {
-                                        Some(Layers::Tcp(Tcp::parse(tcp_packet, &ipv4)))
+                                match Tcp::deserialize_header(ipv4_packet.payload()) {
+                                    Ok((mut tcp, _)) => {
+                                        tcp.set_ipv4_layer(&ipv4);
+                                        Some(Layers::Tcp(tcp))
+                                    }
+                                    Err(e) => {
+                                        error = Some(e);
+                                        None
                                     }
-                                    None => None,
                                 }
                             }
                             IpNextHeaderProtocols::Udp => {
-                                match UdpPacket::new(ipv4_packet.payload()) {
-                                    Some(ref udp_packet) => {
-                                        Some(Layers::Udp(Udp::parse(udp_packet, &ipv4)))
+                                match Udp::deserialize_header(ipv4_packet.payload()) {
+                                    Ok((mut udp, _)) => {
+                                        udp.set_ipv4_layer(&ipv4);
+                                        Some(Layers::Udp(udp))
+                                    }
+                                    Err(e) => {
+                                        error = Some(e);
+                                        None
+                                    }
+                                }
+                            }
+                            IpNextHeaderProtocols::Icmp => {
+                                match Icmp::deserialize(ipv4_packet.payload()) {
+                                    Ok((icmp, _)) => Some(Layers::Icmp(icmp)),
+                                    Err(e) => {
+                                        error = Some(e);
+                                        None
+                                    }
+                                }
+                            }
+                            IpNextHeaderProtocols::Igmp => {
+                                match Igmp::deserialize(ipv4_packet.payload()) {
+                                    Ok((igmp, _)) => Some(Layers::Igmp(igmp)),
+                                    Err(e) => {
+                                        error = Some(e);
+                                        None
                                     }
-                                    None => None,
                                 }
                             }
                             _ => None,
                         };
                     }
+                    // The bytes past the captured length are missing
+                    if error.is_none() && ipv4.get_total_length() as usize > payload.len() {
+                        error = Some(ParseError::Truncated(LayerTypes::Ipv4));
+                    }
 
                     Some(Layers::Ipv4(ipv4))
                 }
-                None => None,
+                Err(e) => {
+                    error = Some(e);
+                    None
+                }
             },
-            _ => None,
+            EtherTypes::Ipv6 => match Ipv6::deserialize_header(payload) {
+                Ok((ipv6, n)) => {
+                    if ipv6.get_transport_protocol() == IpNextHeaderProtocols::Icmpv6 {
+                        let end = min(
+                            Ipv6Packet::minimum_packet_size() + ipv6.get_payload_length() as usize,
+                            payload.len(),
+                        );
+                        let buffer = &payload[min(n, end)..end];
+                        transport = match Icmpv6::deserialize(buffer) {
+                            Ok((mut icmpv6, _)) => {
+                                icmpv6.set_ipv6_layer(&ipv6);
+                                if icmpv6.validate_checksum(buffer) {
+                                    Some(Layers::Icmpv6(icmpv6))
+                                } else {
+                                    error = Some(ParseError::ChecksumMismatch(LayerTypes::Icmpv6));
+                                    None
+                                }
+                            }
+                            Err(e) => {
+                                error = Some(e);
+                                None
+                            }
+                        };
+                    }
+                    // The bytes past the captured length are missing
+                    let length =
+                        Ipv6Packet::minimum_packet_size() + ipv6.get_payload_length() as usize;
+                    if error.is_none() && length > payload.len() {
+                        error = Some(ParseError::Truncated(LayerTypes::Ipv6));
+                    }
+
+                    Some(Layers::Ipv6(ipv6))
+                }
+                Err(e) => {
+                    error = Some(e);
+                    None
+                }
+            },
+            t => Some(Layers::Unknown(Unknown::new(t, payload))),
         };
 
-        Indicator {
-            link,
-            network,
-            transport,
+        // Malformed packets are common on the wire, they are counted instead of warned
+        if let Some(ref e) = error {
+            debug!("parse: {}", e);
         }
+
+        (network, transport, error)
     }
 
     /// Creates a `Indicator` by the given frame.
@@ -97,6 +259,20 @@ impl Indicator {
         }
     }
 
+    /// Creates a `Indicator` by the given frame of the given link type.
+    pub fn from_link_type(frame: &[u8], link_type: LinkType) -> Option<Indicator> {
+        match link_type {
+            LinkType::Ethernet => Indicator::from(frame),
+            LinkType::LinuxSll => match Indicator::parse_sll(frame) {
+                Ok(indicator) => Some(indicator),
+                Err(ref e) => {
+                    debug!("parse: {}", e);
+                    None
+                }
+            },
+        }
+    }
+
     /// Get the brief of the `Indicator`.
     pub fn brief(&self) -> String {
         match self.get_network_type() {
@@ -131,22 +307,34 @@ impl Indicator {
                                 layer.get_length(),
                             )
                         }
-                        _ => unreachable!(),
+                        LayerTypes::Icmp => {
+                            let ipv4 = self.get_ipv4().unwrap();
+                            let layer = self.get_icmp().unwrap();
+                            format!("{}: {} -> {}", layer, ipv4.get_src(), ipv4.get_dst())
+                        }
+                        LayerTypes::Igmp => {
+                            let ipv4 = self.get_ipv4().unwrap();
+                            let layer = self.get_igmp().unwrap();
+                            format!("{}: {} -> {}", layer, ipv4.get_src(), ipv4.get_dst())
+                        }
+                        _ => {
+                            let ipv4 = self.get_ipv4().unwrap();
+                            format!(
+                                "{} -> {} ({})",
+                                ipv4.get_src(),
+                                ipv4.get_dst(),
+                                ipv4.get_next_level_protocol()
+                            )
+                        }
                     },
                     None => {
                         let layer = self.get_ipv4().unwrap();
                         format!("{}", layer)
                     }
                 },
-                _ => unreachable!(),
-            },
-            None => match self.get_link_type() {
-                LayerTypes::Ethernet => {
-                    let layer = self.get_ethernet().unwrap();
-                    format!("{}", layer)
-                }
-                _ => unreachable!(),
+                _ => format!("{}", self.get_network().unwrap()),
             },
+            None => format!("{}", self.get_link()),
         }
     }
 
@@ -210,14 +398,56 @@ impl Indicator {
             total = total - m;
         };
         // Transport
-        if let Some(transport) = self.get_transport() {
-            let m = transport.serialize_with_payload(&mut buffer[begin..], payload, total)?;
-            begin = begin + m;
+        match self.get_transport() {
+            Some(transport) => {
+                let m = transport.serialize_with_payload(&mut buffer[begin..], payload, total)?;
+                begin = begin + m;
+            }
+            None => {
+                // Copies payload
+                if buffer.len() < begin + payload.len() {
+                    return Err(io::Error::new(io::ErrorKind::WriteZero, "buffer too small"));
+                }
+                buffer[begin..begin + payload.len()].copy_from_slice(payload);
+                begin += payload.len();
+            }
         };
 
         Ok(begin)
     }
 
+    /// Serialize the `Indicator` into the writer. The layers are serialized into a buffer at
+    /// once, because the lengths and checksums of a layer cover the following layers.
+    pub fn serialize_to_writer(&self, w: &mut dyn Write) -> io::Result<usize> {
+        let mut buffer = vec![0u8; self.get_size()];
+        let n = self.serialize(&mut buffer)?;
+        w.write_all(&buffer[..n])?;
+
+        Ok(n)
+    }
+
+    /// Serialize the `Indicator` into the writer with payload.
+    pub fn serialize_with_payload_to_writer(
+        &self,
+        w: &mut dyn Write,
+        payload: &[u8],
+    ) -> io::Result<usize> {
+        let mut buffer = vec![0u8; self.get_size() + payload.len()];
+        let n = self.serialize_with_payload(&mut buffer, payload)?;
+        w.write_all(&buffer[..n])?;
+
+        Ok(n)
+    }
+
+    /// Get the layers of the `Indicator` in encapsulation order.
+    pub fn get_layers(&self) -> Vec<Layers> {
+        let mut layers = vec![self.get_link().clone()];
+        layers.extend(self.get_network().cloned());
+        layers.extend(self.get_transport().cloned());
+
+        layers
+    }
+
     /// Get the link layer.
     pub fn get_link(&self) -> &Layers {
         &self.link
@@ -237,6 +467,25 @@ impl Indicator {
         None
     }
 
+    /// Get the `Sll`.
+    pub fn get_sll(&self) -> Option<&Sll> {
+        if let Layers::Sll(layer) = &self.get_link() {
+            return Some(layer);
+        }
+
+        None
+    }
+
+    /// Get the source hardware address of the link layer. Returns `None` if the link layer
+    /// carries no hardware address, e.g., an SLL frame of a non-Ethernet device.
+    pub fn get_src_hardware_addr(&self) -> Option<MacAddr> {
+        match self.get_link() {
+            Layers::Ethernet(layer) => Some(layer.get_src()),
+            Layers::Sll(layer) => layer.get_src(),
+            _ => None,
+        }
+    }
+
     /// Get the network layer.
     pub fn get_network(&self) -> Option<&Layers> {
         if let Some(layer) = &self.network {
@@ -277,6 +526,15 @@ impl Indicator {
         None
     }
 
+    /// Get the IPv6.
+    pub fn get_ipv6(&self) -> Option<&Ipv6> {
+        if let Some(Layers::Ipv6(layer)) = self.get_network() {
+            return Some(layer);
+        }
+
+        None
+    }
+
     /// Get the transport layer.
     pub fn get_transport(&self) -> Option<&Layers> {
         if let Some(layer) = &self.transport {
@@ -295,6 +553,11 @@ impl Indicator {
         None
     }
 
+    /// Get the error of the layer failed to be parsed.
+    pub fn get_error(&self) -> Option<ParseError> {
+        self.error
+    }
+
     /// Get the TCP.
     pub fn get_tcp(&self) -> Option<&Tcp> {
         if let Some(layer) = self.get_transport() {
@@ -316,6 +579,39 @@ impl Indicator {
 
         None
     }
+
+    /// Get the ICMP.
+    pub fn get_icmp(&self) -> Option<&Icmp> {
+        if let Some(Layers::Icmp(layer)) = self.get_transport() {
+            return Some(layer);
+        }
+
+        None
+    }
+
+    /// Get the IGMP.
+    pub fn get_igmp(&self) -> Option<&Igmp> {
+        if let Some(Layers::Igmp(layer)) = self.get_transport() {
+            return Some(layer);
+        }
+
+        None
+    }
+
+    /// Get the `FlowKey` of the transport layer. Returns `None` if the transport layer is not TCP
+    /// or UDP.
+    pub fn get_flow_key(&self) -> Option<FlowKey> {
+        self.get_transport()?.flow_key()
+    }
+
+    /// Get the ICMPv6.
+    pub fn get_icmpv6(&self) -> Option<&Icmpv6> {
+        if let Some(Layers::Icmpv6(layer)) = self.get_transport() {
+            return Some(layer);
+        }
+
+        None
+    }
 }
 
 impl Display for Indicator {
@@ -343,12 +639,13 @@ const EXPIRE_TIME: u128 = 10000;
 /// Represents a fragmentation.
 #[derive(Debug)]
 pub struct Fragmentation {
-    ethernet: Ethernet,
+    link: Layers,
     ipv4: Ipv4,
-    transport: Option<Layers>,
     buffer: Vec<u8>,
     last_seen: Instant,
+    offsets: HashSet<usize>,
     length: usize,
+    total: Option<usize>,
 }
 
 impl Fragmentation {
@@ -358,30 +655,24 @@ impl Fragmentation {
             Some(ref ipv4) => Ipv4::defrag(ipv4),
             None => return None,
         };
-        let ethernet = match indicator.get_ethernet() {
-            Some(ethernet) => ethernet,
-            None => return None,
-        };
+        let link = indicator.get_link().clone();
 
         let mut frag = Fragmentation {
-            ethernet: ethernet.clone(),
+            link: link.clone(),
             ipv4: new_ipv4.clone(),
-            transport: None,
             // TODO: u16 is not safe
             buffer: vec![0; u16::MAX as usize],
             last_seen: Instant::now(),
+            offsets: HashSet::new(),
             length: 0,
+            total: None,
         };
 
         // Indicator
-        let new_indicator = Indicator::new(
-            Layers::Ethernet(ethernet.clone()),
-            Some(Layers::Ipv4(new_ipv4)),
-            None,
-        );
+        let new_indicator = Indicator::new(link, Some(Layers::Ipv4(new_ipv4)), None);
 
         // Serialize
-        if let Err(_) = new_indicator.serialize(&mut frag.buffer[0..]) {
+        if new_indicator.serialize(&mut frag.buffer[0..]).is_err() {
             return None;
         }
 
@@ -390,41 +681,69 @@ impl Fragmentation {
 
     /// Adds a fragmentation.
     pub fn add(&mut self, indicator: &Indicator, payload: &[u8]) {
-        // Transport
-        if let None = self.transport {
-            if let Some(transport) = indicator.get_transport() {
-                self.transport = Some(transport.clone());
-            }
-        }
-
-        // Payload
         let ipv4 = match indicator.get_ipv4() {
             Some(ipv4) => ipv4,
             None => return,
         };
         let offset = (ipv4.get_fragment_offset() as usize) * 8;
-        let header_size = self.ethernet.get_size() + self.ipv4.get_size();
+        let header_size = self.link.get_size() + self.ipv4.get_size();
+        if header_size + offset + payload.len() > self.buffer.len() {
+            return;
+        }
+        self.last_seen = Instant::now();
+
+        // Last fragment
+        if !ipv4.is_more_fragment() {
+            self.total = Some(offset + payload.len());
+        }
+
+        // Duplicate
+        if !self.offsets.insert(offset) {
+            return;
+        }
 
-        self.buffer[header_size + offset..].copy_from_slice(payload);
+        self.buffer[header_size + offset..header_size + offset + payload.len()]
+            .copy_from_slice(payload);
         self.length += payload.len();
     }
 
     /// Concatenates fragmentations and returns an indicator of the buffer and the buffer itself.
     pub fn concatenate(&self) -> (Indicator, &[u8]) {
+        let header_size = self.link.get_size() + self.ipv4.get_size();
+        let payload = &self.buffer[header_size..header_size + self.length];
+
+        // Transport
+        let transport = match self.ipv4.get_next_level_protocol() {
+            IpNextHeaderProtocols::Tcp => {
+                Tcp::deserialize_header(payload).ok().map(|(mut tcp, _)| {
+                    tcp.set_ipv4_layer(&self.ipv4);
+                    Layers::Tcp(tcp)
+                })
+            }
+            IpNextHeaderProtocols::Udp => {
+                Udp::deserialize_header(payload).ok().map(|(mut udp, _)| {
+                    udp.set_ipv4_layer(&self.ipv4);
+                    Layers::Udp(udp)
+                })
+            }
+            IpNextHeaderProtocols::Icmp => Icmp::deserialize(payload)
+                .ok()
+                .map(|(icmp, _)| Layers::Icmp(icmp)),
+            _ => None,
+        };
+
         let new_indicator = Indicator::new(
-            Layers::Ethernet(self.ethernet.clone()),
+            self.link.clone(),
             Some(Layers::Ipv4(self.ipv4.clone())),
-            self.transport.clone(),
+            transport,
         );
 
-        let header_size = self.ethernet.get_size() + self.ipv4.get_size();
-
         (new_indicator, &self.buffer[0..header_size + self.length])
     }
 
     /// Returns if the `Fragmentation` is completed.
     pub fn is_completed(&self) -> bool {
-        self.length == self.ipv4.get_total_length() as usize - self.ipv4.get_size()
+        self.total == Some(self.length)
     }
 
     /// Returns if the `Fragmentation` is expired.
@@ -436,7 +755,8 @@ impl Fragmentation {
 /// Represents a defragmentation machine.
 #[derive(Debug)]
 pub struct Defraggler {
-    frags: HashMap<(Ipv4Addr, Ipv4Addr, u16), Fragmentation>,
+    frags: HashMap<(Ipv4Addr, Ipv4Addr, u16, IpNextHeaderProtocol), Fragmentation>,
+    expired: usize,
 }
 
 impl Defraggler {
@@ -444,9 +764,18 @@ impl Defraggler {
     pub fn new() -> Defraggler {
         Defraggler {
             frags: HashMap::new(),
+            expired: 0,
         }
     }
 
+    /// Returns the number of fragmentations expired before completed since the last call.
+    pub fn take_expired(&mut self) -> usize {
+        let expired = self.expired;
+        self.expired = 0;
+
+        expired
+    }
+
     /// Adds a fragmentation and returns the fragmentation if it is completed.
     pub fn add(&mut self, indicator: &Indicator, buffer: &[u8]) -> Option<Fragmentation> {
         let ipv4 = match indicator.get_ipv4() {
@@ -454,36 +783,665 @@ impl Defraggler {
             None => return None,
         };
 
-        let key = (ipv4.get_src(), ipv4.get_dst(), ipv4.get_identification());
+        // Clean up expired fragmentations
+        let len = self.frags.len();
+        self.frags.retain(|_, frag| !frag.is_expired());
+        self.expired += len - self.frags.len();
 
-        let mut is_create = false;
-        if self.frags.contains_key(&key) {
-            if self.frags.get(&key).unwrap().is_expired() {
-                // Expired
-                is_create = true;
-            }
+        let key = (
+            ipv4.get_src(),
+            ipv4.get_dst(),
+            ipv4.get_identification(),
+            ipv4.get_next_level_protocol(),
+        );
+
+        let frag = match self.frags.entry(key) {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => match Fragmentation::new(indicator) {
+                Some(frag) => entry.insert(frag),
+                None => return None,
+            },
+        };
+
+        // Add fragmentation
+        let header_size = indicator.get_link().get_size() + ipv4.get_size();
+        frag.add(indicator, &buffer[header_size..]);
+        if frag.is_completed() {
+            self.frags.remove(&key)
         } else {
-            is_create = true;
+            None
         }
+    }
+}
 
-        if is_create {
-            let frag = match Fragmentation::new(indicator) {
-                Some(frag) => frag,
-                None => return None,
-            };
+/// Represents an IPv6 fragmentation.
+#[derive(Debug)]
+pub struct Ipv6Fragmentation {
+    header: Vec<u8>,
+    buffer: Vec<u8>,
+    last_seen: Instant,
+    ranges: Vec<(usize, usize)>,
+    length: usize,
+    total: Option<usize>,
+}
 
-            self.frags.insert(key, frag);
+impl Ipv6Fragmentation {
+    /// Creates an empty `Ipv6Fragmentation`.
+    pub fn new() -> Ipv6Fragmentation {
+        Ipv6Fragmentation {
+            header: vec![],
+            buffer: vec![],
+            last_seen: Instant::now(),
+            ranges: vec![],
+            length: 0,
+            total: None,
         }
+    }
 
-        let frag = self.frags.get_mut(&key).unwrap();
+    /// Adds a fragment of the given IPv6 packet, whose Fragment header is found at the given
+    /// position. Overlapping fragments are rejected.
+    fn add(&mut self, buffer: &[u8], position: usize, offset: usize) -> Result<(), ParseError> {
+        let field = (buffer[offset + 2] as u16) << 8 | buffer[offset + 3] as u16;
+        let fragment_offset = (field >> 3) as usize * 8;
+        let is_more_fragment = field & 1 != 0;
+        let payload = &buffer[offset + FRAGMENT_HEADER_SIZE..];
+        if is_more_fragment && payload.len() % 8 != 0 {
+            return Err(ParseError::InvalidValue(
+                LayerTypes::Ipv6,
+                "fragment length",
+            ));
+        }
+        let begin = fragment_offset;
+        let end = fragment_offset + payload.len();
+        if end > u16::MAX as usize {
+            return Err(ParseError::InvalidValue(
+                LayerTypes::Ipv6,
+                "fragment offset",
+            ));
+        }
+        self.last_seen = Instant::now();
+
+        // Overlap
+        if self
+            .ranges
+            .iter()
+            .any(|(prev_begin, prev_end)| begin < *prev_end && *prev_begin < end)
+        {
+            return Err(ParseError::InvalidValue(
+                LayerTypes::Ipv6,
+                "overlapping fragment",
+            ));
+        }
+
+        // Last fragment
+        if let Some(total) = self.total {
+            if end > total || (!is_more_fragment && end != total) {
+                return Err(ParseError::InvalidValue(
+                    LayerTypes::Ipv6,
+                    "fragment offset",
+                ));
+            }
+        }
+        if !is_more_fragment {
+            if self.ranges.iter().any(|(_, prev_end)| *prev_end > end) {
+                return Err(ParseError::InvalidValue(
+                    LayerTypes::Ipv6,
+                    "fragment offset",
+                ));
+            }
+            self.total = Some(end);
+        }
+        self.ranges.push((begin, end));
+
+        // Unfragmentable part
+        if fragment_offset == 0 {
+            let mut header = buffer[..offset].to_vec();
+            header[position] = buffer[offset];
+            self.header = header;
+        }
+
+        if self.buffer.len() < end {
+            self.buffer.resize(end, 0);
+        }
+        self.buffer[begin..end].copy_from_slice(payload);
+        self.length += payload.len();
+
+        Ok(())
+    }
+
+    /// Concatenates fragments and returns the reassembled IPv6 packet. The Fragment header is
+    /// stripped from the packet.
+    pub fn concatenate(&self) -> Vec<u8> {
+        let mut packet = self.header.clone();
+        packet.extend_from_slice(&self.buffer[..self.length]);
+
+        // Fix length
+        let payload_length = packet.len() - Ipv6Packet::minimum_packet_size();
+        packet[4] = (payload_length >> 8) as u8;
+        packet[5] = payload_length as u8;
+
+        packet
+    }
+
+    /// Returns if the `Ipv6Fragmentation` is completed.
+    pub fn is_completed(&self) -> bool {
+        !self.header.is_empty() && self.total == Some(self.length)
+    }
+
+    /// Returns if the `Ipv6Fragmentation` is expired.
+    pub fn is_expired(&self) -> bool {
+        self.last_seen.elapsed().as_millis() > EXPIRE_TIME
+    }
+}
+
+impl Default for Ipv6Fragmentation {
+    fn default() -> Self {
+        Ipv6Fragmentation::new()
+    }
+}
+
+/// Represents a defragmentation machine of IPv6.
+#[derive(Debug, Default)]
+pub struct Ipv6Defraggler {
+    frags: HashMap<(Ipv6Addr, Ipv6Addr, u32), Ipv6Fragmentation>,
+    expired: usize,
+}
+
+impl Ipv6Defraggler {
+    /// Creates a new empty `Ipv6Defraggler`.
+    pub fn new() -> Ipv6Defraggler {
+        Ipv6Defraggler {
+            frags: HashMap::new(),
+            expired: 0,
+        }
+    }
+
+    /// Returns the number of fragmentations expired before completed since the last call.
+    pub fn take_expired(&mut self) -> usize {
+        let expired = self.expired;
+        self.expired = 0;
+
+        expired
+    }
+
+    /// Adds an IPv6 fragment without padding, and returns the reassembled IPv6 packet if it is
+    /// completed. The whole fragmentation is dropped if the fragment overlaps with others.
+    pub fn add(&mut self, buffer: &[u8]) -> Result<Option<Vec<u8>>, ParseError> {
+        let packet = Ipv6Packet::new(buffer).ok_or(ParseError::Truncated(LayerTypes::Ipv6))?;
+        let size = Ipv6Packet::minimum_packet_size() + packet.get_payload_length() as usize;
+        if buffer.len() < size {
+            return Err(ParseError::Truncated(LayerTypes::Ipv6));
+        }
+        let buffer = &buffer[..size];
+        let (position, offset) = match ipv6::find_fragment_header(buffer)? {
+            Some(pair) => pair,
+            None => return Err(ParseError::InvalidValue(LayerTypes::Ipv6, "fragment")),
+        };
+        let identification = (buffer[offset + 4] as u32) << 24
+            | (buffer[offset + 5] as u32) << 16
+            | (buffer[offset + 6] as u32) << 8
+            | buffer[offset + 7] as u32;
+
+        // Clean up expired fragmentations
+        let len = self.frags.len();
+        self.frags.retain(|_, frag| !frag.is_expired());
+        self.expired += len - self.frags.len();
+
+        let key = (
+            packet.get_source(),
+            packet.get_destination(),
+            identification,
+        );
+        let frag = self.frags.entry(key).or_insert_with(Ipv6Fragmentation::new);
 
         // Add fragmentation
-        let header_size = indicator.get_ethernet().unwrap().get_size() + ipv4.get_size();
-        frag.add(indicator, &buffer[header_size..]);
+        if let Err(e) = frag.add(buffer, position, offset) {
+            self.frags.remove(&key);
+            return Err(e);
+        }
         if frag.is_completed() {
-            self.frags.remove(&key)
+            Ok(self.frags.remove(&key).map(|frag| frag.concatenate()))
         } else {
-            None
+            Ok(None)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use layer::ethernet::VlanTag;
+
+    #[test]
+    fn parse_ipv6() {
+        let src = "2001:db8::1".parse().unwrap();
+        let dst = "2001:db8::2".parse().unwrap();
+        let ethernet =
+            Ethernet::new(LayerTypes::Ipv6, MacAddr::zero(), MacAddr::broadcast()).unwrap();
+        let ipv6 = Ipv6::new(LayerTypes::Tcp, src, dst).unwrap();
+        let mut tcp = Tcp::new_ack(1024, 80, 100, 200, 65535);
+        tcp.set_ipv6_layer(&ipv6);
+        let indicator = Indicator::new(
+            Layers::Ethernet(ethernet),
+            Some(Layers::Ipv6(ipv6)),
+            Some(Layers::Tcp(tcp)),
+        );
+        let mut buffer = vec![0u8; indicator.get_size()];
+        indicator.serialize(&mut buffer).unwrap();
+
+        let indicator = Indicator::from(&buffer).unwrap();
+        assert_eq!(indicator.get_network_type(), Some(LayerTypes::Ipv6));
+        let ipv6 = indicator.get_ipv6().unwrap();
+        assert_eq!(ipv6.get_src(), src);
+        assert_eq!(ipv6.get_transport_protocol(), IpNextHeaderProtocols::Tcp);
+        assert!(indicator.get_error().is_none());
+    }
+
+    #[test]
+    fn parse_vlan() {
+        let src = Ipv4Addr::new(192, 168, 1, 1);
+        let mut ethernet =
+            Ethernet::new(LayerTypes::Ipv4, MacAddr::zero(), MacAddr::broadcast()).unwrap();
+        ethernet.set_vlan(Some(VlanTag::new(0, false, 100)));
+        let ipv4 = Ipv4::new(1, LayerTypes::Udp, src, Ipv4Addr::new(192, 168, 1, 2)).unwrap();
+        let mut udp = Udp::new(1024, 53);
+        udp.set_ipv4_layer(&ipv4);
+        let indicator = Indicator::new(
+            Layers::Ethernet(ethernet),
+            Some(Layers::Ipv4(ipv4)),
+            Some(Layers::Udp(udp)),
+        );
+        let mut buffer = vec![0u8; indicator.get_size()];
+        indicator.serialize(&mut buffer).unwrap();
+
+        // The IPv4 header follows the 4-byte tag
+        assert_eq!(buffer[18] >> 4, 4);
+        let indicator = Indicator::from(&buffer).unwrap();
+        assert_eq!(indicator.get_ipv4().unwrap().get_src(), src);
+        assert_eq!(indicator.get_udp().unwrap().get_dst(), 53);
+        assert_eq!(indicator.get_size(), 18 + 20 + 8);
+    }
+
+    #[test]
+    fn parse_icmp_checksum_mismatch() {
+        let ethernet =
+            Ethernet::new(LayerTypes::Ipv4, MacAddr::zero(), MacAddr::broadcast()).unwrap();
+        let ipv4 = Ipv4::new(
+            1,
+            LayerTypes::Icmp,
+            Ipv4Addr::new(10, 6, 0, 1),
+            Ipv4Addr::new(10, 6, 0, 254),
+        )
+        .unwrap();
+        let mut frame = PacketBuilder::new()
+            .ethernet(ethernet)
+            .ipv4(ipv4)
+            .icmp(Icmp::new_echo_request(0x1234, 1))
+            .payload(b"ping")
+            .build()
+            .unwrap();
+        let n = frame.len();
+        frame[n - 1] ^= 0x01;
+
+        let indicator = Indicator::from(&frame).unwrap();
+        assert!(indicator.get_ipv4().is_some());
+        assert!(indicator.get_icmp().is_none());
+        assert!(matches!(
+            indicator.get_error(),
+            Some(ParseError::ChecksumMismatch(LayerTypes::Icmp))
+        ));
+    }
+
+    #[test]
+    fn fragment_and_reassemble() {
+        let ethernet =
+            Ethernet::new(LayerTypes::Ipv4, MacAddr::zero(), MacAddr::broadcast()).unwrap();
+        let ipv4 = Ipv4::new(
+            1,
+            LayerTypes::Udp,
+            Ipv4Addr::new(10, 6, 0, 1),
+            Ipv4Addr::new(10, 6, 0, 254),
+        )
+        .unwrap();
+        let payload: Vec<u8> = (0..3000).map(|i| i as u8).collect();
+
+        let mut defrag = Defraggler::new();
+        let mut offset = 0;
+        let mut frag = None;
+        for ipv4 in ipv4.fragment(&payload, 1500) {
+            let length = ipv4.get_total_length() as usize - ipv4.get_size();
+            let frame = PacketBuilder::new()
+                .ethernet(ethernet.clone())
+                .ipv4(ipv4)
+                .payload(&payload[offset..offset + length])
+                .build()
+                .unwrap();
+            offset += length;
+
+            assert!(frag.is_none());
+            frag = defrag.add(&Indicator::from(&frame).unwrap(), &frame);
+        }
+
+        let frag = frag.unwrap();
+        let (indicator, buffer) = frag.concatenate();
+        let header_size =
+            indicator.get_ethernet().unwrap().get_size() + indicator.get_ipv4().unwrap().get_size();
+        assert_eq!(buffer.len(), header_size + payload.len());
+        assert!(buffer[header_size..] == payload[..]);
+    }
+
+    #[test]
+    fn fragment_and_reassemble_sll() {
+        let ethernet =
+            Ethernet::new(LayerTypes::Ipv4, MacAddr::zero(), MacAddr::broadcast()).unwrap();
+        let ipv4 = Ipv4::new(
+            1,
+            LayerTypes::Udp,
+            Ipv4Addr::new(10, 6, 0, 1),
+            Ipv4Addr::new(10, 6, 0, 254),
+        )
+        .unwrap();
+        let payload: Vec<u8> = (0..3000).map(|i| i as u8).collect();
+        // SLL header of the sender 02:00:00:00:00:01
+        #[rustfmt::skip]
+        let sll_header = [
+            0x00, 0x00, 0x00, 0x01, 0x00, 0x06, 0x02, 0x00,
+            0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x08, 0x00,
+        ];
+
+        let mut defrag = Defraggler::new();
+        let mut offset = 0;
+        let mut frag = None;
+        for ipv4 in ipv4.fragment(&payload, 1500) {
+            let length = ipv4.get_total_length() as usize - ipv4.get_size();
+            let frame = PacketBuilder::new()
+                .ethernet(ethernet.clone())
+                .ipv4(ipv4)
+                .payload(&payload[offset..offset + length])
+                .build()
+                .unwrap();
+            offset += length;
+            // Replace the Ethernet header with the SLL header
+            let mut sll_frame = sll_header.to_vec();
+            sll_frame.extend_from_slice(&frame[14..]);
+
+            assert!(frag.is_none());
+            let indicator = Indicator::from_link_type(&sll_frame, LinkType::LinuxSll).unwrap();
+            frag = defrag.add(&indicator, &sll_frame);
+        }
+
+        let frag = frag.unwrap();
+        let (indicator, buffer) = frag.concatenate();
+        assert!(indicator.get_sll().is_some());
+        let header_size = sll_header.len() + indicator.get_ipv4().unwrap().get_size();
+        assert_eq!(buffer.len(), header_size + payload.len());
+        assert!(buffer[..sll_header.len()] == sll_header[..]);
+        assert!(buffer[header_size..] == payload[..]);
+    }
+
+    /// Builds an IPv6 fragment of a UDP datagram from 2001:db8::1 to 2001:db8::2.
+    fn build_ipv6_fragment(offset: u16, is_more_fragment: bool, payload: &[u8]) -> Vec<u8> {
+        let ipv6 = Ipv6::new(
+            LayerTypes::Udp,
+            "2001:db8::1".parse().unwrap(),
+            "2001:db8::2".parse().unwrap(),
+        )
+        .unwrap();
+        let mut buffer = vec![0u8; ipv6.get_size()];
+        ipv6.serialize(&mut buffer, ipv6.get_size()).unwrap();
+        buffer[6] = IpNextHeaderProtocols::Ipv6Frag.0;
+        let payload_length = (FRAGMENT_HEADER_SIZE + payload.len()) as u16;
+        buffer[4..6].copy_from_slice(&payload_length.to_be_bytes());
+
+        let field = ((offset / 8) << 3) | is_more_fragment as u16;
+        buffer.extend_from_slice(&[IpNextHeaderProtocols::Udp.0, 0]);
+        buffer.extend_from_slice(&field.to_be_bytes());
+        buffer.extend_from_slice(&0x1234u32.to_be_bytes());
+        buffer.extend_from_slice(payload);
+
+        buffer
+    }
+
+    #[test]
+    fn reassemble_ipv6() {
+        let mut defraggler = Ipv6Defraggler::new();
+        let first = build_ipv6_fragment(0, true, &[0x01; 8]);
+        let last = build_ipv6_fragment(8, false, &[0x02; 8]);
+
+        assert_eq!(defraggler.add(&last).unwrap(), None);
+        let packet = defraggler.add(&first).unwrap().unwrap();
+        // The Fragment header is stripped
+        assert_eq!(packet.len(), 40 + 16);
+        assert_eq!(packet[4..6], [0x00, 0x10]);
+        assert_eq!(packet[6], IpNextHeaderProtocols::Udp.0);
+        assert_eq!(packet[40..48], [0x01; 8]);
+        assert_eq!(packet[48..], [0x02; 8]);
+    }
+
+    #[test]
+    fn reassemble_ipv6_overlapped() {
+        let mut defraggler = Ipv6Defraggler::new();
+        let first = build_ipv6_fragment(0, true, &[0x01; 16]);
+        let last = build_ipv6_fragment(8, false, &[0x02; 16]);
+
+        assert_eq!(defraggler.add(&first).unwrap(), None);
+        assert!(matches!(
+            defraggler.add(&last),
+            Err(ParseError::InvalidValue(LayerTypes::Ipv6, _))
+        ));
+        // The whole fragmentation is dropped
+        let last = build_ipv6_fragment(16, false, &[0x02; 8]);
+        assert_eq!(defraggler.add(&last).unwrap(), None);
+    }
+
+    #[test]
+    fn parse_unknown_ethertype() {
+        let mut frame = vec![0u8; 14];
+        frame[..6].copy_from_slice(&[0xff; 6]);
+        frame[6..12].copy_from_slice(&[0x02, 0, 0, 0, 0, 0x01]);
+        frame[12..14].copy_from_slice(&[0x88, 0xb5]);
+        frame.extend_from_slice(b"experimental");
+        let indicator = Indicator::from(&frame).unwrap();
+
+        match indicator.get_network() {
+            Some(Layers::Unknown(unknown)) => {
+                assert_eq!(unknown.get_ethertype(), EtherType(0x88b5));
+                assert_eq!(unknown.get_payload(), b"experimental");
+            }
+            _ => panic!("expected an unknown network layer"),
         }
+        assert!(indicator.get_transport().is_none());
+    }
+
+    #[test]
+    fn parse_sll() {
+        let src = Ipv4Addr::new(192, 168, 1, 1);
+        let ipv4 = Ipv4::new(1, LayerTypes::Udp, src, Ipv4Addr::new(192, 168, 1, 2)).unwrap();
+        let mut udp = Udp::new(1024, 53);
+        udp.set_ipv4_layer(&ipv4);
+        let ethernet =
+            Ethernet::new(LayerTypes::Ipv4, MacAddr::zero(), MacAddr::broadcast()).unwrap();
+        let frame = PacketBuilder::new()
+            .ethernet(ethernet)
+            .ipv4(ipv4)
+            .udp(udp)
+            .payload(b"query")
+            .build()
+            .unwrap();
+        // Replace the Ethernet header with an SLL header of the sender 02:00:00:00:00:01
+        #[rustfmt::skip]
+        let mut sll_frame = vec![
+            0x00, 0x00, 0x00, 0x01, 0x00, 0x06, 0x02, 0x00,
+            0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x08, 0x00,
+        ];
+        sll_frame.extend_from_slice(&frame[14..]);
+
+        let link_type = LinkType::from_pcap(LINKTYPE_LINUX_SLL).unwrap();
+        let indicator = Indicator::from_link_type(&sll_frame, link_type).unwrap();
+        let sll = indicator.get_sll().unwrap();
+        assert_eq!(sll.get_src(), Some(MacAddr::new(0x02, 0, 0, 0, 0, 0x01)));
+        assert_eq!(indicator.get_src_hardware_addr(), sll.get_src());
+        assert_eq!(indicator.get_ipv4().unwrap().get_src(), src);
+        let udp = indicator.get_udp().unwrap();
+        assert_eq!(udp.get_dst(), 53);
+        assert_eq!(indicator.get_size(), 16 + 20 + 8);
+
+        // Not an SLL frame
+        assert!(Indicator::from_link_type(&sll_frame[..15], link_type).is_none());
+    }
+
+    #[test]
+    fn serialize_with_payload_to_writer() {
+        let src = Ipv4Addr::new(192, 168, 1, 1);
+        let dst = Ipv4Addr::new(192, 168, 1, 2);
+        let ethernet =
+            Ethernet::new(LayerTypes::Ipv4, MacAddr::zero(), MacAddr::broadcast()).unwrap();
+        let ipv4 = Ipv4::new(1, LayerTypes::Udp, src, dst).unwrap();
+        let mut udp = Udp::new(1024, 53);
+        udp.set_ipv4_layer(&ipv4);
+        let indicator = Indicator::new(
+            Layers::Ethernet(ethernet),
+            Some(Layers::Ipv4(ipv4)),
+            Some(Layers::Udp(udp)),
+        );
+        let mut buffer = vec![0u8; indicator.get_size() + 5];
+        let n = indicator
+            .serialize_with_payload(&mut buffer, b"query")
+            .unwrap();
+
+        let mut w = Vec::new();
+        let written = indicator
+            .serialize_with_payload_to_writer(&mut w, b"query")
+            .unwrap();
+        assert_eq!(written, n);
+        assert_eq!(w, buffer);
+
+        // The checksums cover the payload
+        let mut w = Vec::new();
+        indicator.serialize_to_writer(&mut w).unwrap();
+        assert_eq!(w.len(), indicator.get_size());
+        assert_ne!(w[..], buffer[..indicator.get_size()]);
+    }
+
+    #[test]
+    fn parse_truncated_tcp() {
+        let ethernet =
+            Ethernet::new(LayerTypes::Ipv4, MacAddr::zero(), MacAddr::broadcast()).unwrap();
+        let ipv4 = Ipv4::new(
+            1,
+            LayerTypes::Tcp,
+            Ipv4Addr::new(10, 6, 0, 1),
+            Ipv4Addr::new(10, 6, 0, 254),
+        )
+        .unwrap();
+        let mut tcp = Tcp::new_syn(1024, 80, 0, 65535);
+        tcp.set_ipv4_layer(&ipv4);
+        let frame = PacketBuilder::new()
+            .ethernet(ethernet)
+            .ipv4(ipv4)
+            .tcp(tcp)
+            .payload(b"hello")
+            .build()
+            .unwrap();
+
+        // The frame is truncated in the middle of the TCP header
+        let indicator = Indicator::from(&frame[..14 + 20 + 10]).unwrap();
+        assert_eq!(
+            indicator.get_ipv4().unwrap().get_total_length(),
+            20 + 20 + 5
+        );
+        assert!(indicator.get_tcp().is_none());
+        assert_eq!(
+            indicator.get_error(),
+            Some(ParseError::Truncated(LayerTypes::Tcp))
+        );
+        assert_eq!(indicator.get_error().unwrap().get_type(), LayerTypes::Tcp);
+
+        // The TCP header is complete but the frame is cut below its IPv4 total length
+        let indicator = Indicator::from(&frame[..14 + 20 + 20 + 2]).unwrap();
+        assert!(indicator.get_tcp().is_some());
+        assert_eq!(
+            indicator.get_error(),
+            Some(ParseError::Truncated(LayerTypes::Ipv4))
+        );
+
+        // The frame is captured entirely
+        let indicator = Indicator::from(&frame).unwrap();
+        assert!(indicator.get_error().is_none());
+    }
+
+    #[test]
+    fn parse_qinq() {
+        let src = Ipv4Addr::new(192, 168, 1, 1);
+        let mut ethernet =
+            Ethernet::new(LayerTypes::Ipv4, MacAddr::zero(), MacAddr::broadcast()).unwrap();
+        ethernet.set_vlans(vec![
+            VlanTag::new_service(0, false, 100),
+            VlanTag::new(0, false, 200),
+        ]);
+        let ipv4 = Ipv4::new(1, LayerTypes::Udp, src, Ipv4Addr::new(192, 168, 1, 2)).unwrap();
+        let mut udp = Udp::new(1024, 53);
+        udp.set_ipv4_layer(&ipv4);
+        let indicator = Indicator::new(
+            Layers::Ethernet(ethernet),
+            Some(Layers::Ipv4(ipv4)),
+            Some(Layers::Udp(udp)),
+        );
+        let mut buffer = vec![0u8; indicator.get_size()];
+        indicator.serialize(&mut buffer).unwrap();
+        assert_eq!(buffer[12..14], [0x88, 0xa8]);
+        assert_eq!(buffer[16..18], [0x81, 0x00]);
+
+        // The IPv4 header follows both tags
+        assert_eq!(buffer[22] >> 4, 4);
+        let indicator = Indicator::from(&buffer).unwrap();
+        let vids: Vec<u16> = indicator
+            .get_ethernet()
+            .unwrap()
+            .get_vlans()
+            .iter()
+            .map(|tag| tag.vid)
+            .collect();
+        assert_eq!(vids, [100, 200]);
+        assert_eq!(indicator.get_ipv4().unwrap().get_src(), src);
+        assert_eq!(indicator.get_udp().unwrap().get_dst(), 53);
+        assert_eq!(indicator.get_size(), 22 + 20 + 8);
+    }
+
+    #[test]
+    fn brief_igmp() {
+        use layer::igmp::IgmpType;
+
+        let group = Ipv4Addr::new(239, 1, 1, 1);
+        let ethernet =
+            Ethernet::new(LayerTypes::Ipv4, MacAddr::zero(), MacAddr::broadcast()).unwrap();
+        let ipv4 = Ipv4::new(1, LayerTypes::Igmp, Ipv4Addr::new(192, 168, 1, 1), group).unwrap();
+        let igmp = Igmp::new(IgmpType::V2MembershipReport, 0, group);
+        let indicator = Indicator::new(
+            Layers::Ethernet(ethernet),
+            Some(Layers::Ipv4(ipv4)),
+            Some(Layers::Igmp(igmp)),
+        );
+        let mut buffer = vec![0u8; indicator.get_size()];
+        indicator.serialize(&mut buffer).unwrap();
+
+        let indicator = Indicator::from(&buffer).unwrap();
+        assert!(indicator.get_igmp().is_some());
+        assert_eq!(
+            indicator.brief(),
+            "IGMP: Membership Report (v2), Group = 239.1.1.1: 192.168.1.1 -> 239.1.1.1"
+        );
+
+        // Other transport layers are briefed by the IPv4 layer
+        let ethernet =
+            Ethernet::new(LayerTypes::Ipv4, MacAddr::zero(), MacAddr::broadcast()).unwrap();
+        let ipv4 = Ipv4::new(1, LayerTypes::Gre, Ipv4Addr::new(192, 168, 1, 1), group).unwrap();
+        let gre = layer::gre::Gre::new(EtherTypes::Ipv4);
+        let indicator = Indicator::new(
+            Layers::Ethernet(ethernet),
+            Some(Layers::Ipv4(ipv4)),
+            Some(Layers::Gre(gre)),
+        );
+        assert_eq!(indicator.brief(), "192.168.1.1 -> 239.1.1.1 (Gre)");
     }
 }
@@ -0,0 +1,522 @@
+use super::iter::layers;
+use super::layer::ipv6::find_fragment_header;
+use super::layer::Layers;
+use ipnetwork::Ipv4Network;
+use lru::LruCache;
+use pnet::packet::ethernet::EtherTypes;
+use pnet::packet::ip::{IpNextHeaderProtocol, IpNextHeaderProtocols};
+use pnet::util::{self, MacAddr};
+use std::collections::HashMap;
+use std::io;
+use std::net::{Ipv4Addr, Ipv6Addr};
+
+/// Represents the well-known prefix of NAT64 (RFC 6052).
+pub const NAT64_WELL_KNOWN_PREFIX: Ipv6Addr = Ipv6Addr::new(0x64, 0xff9b, 0, 0, 0, 0, 0, 0);
+/// Represents the additional size of an IPv6 header over an IPv4 header without options.
+pub const NAT64_HEADER_OVERHEAD: u16 = 20;
+/// Represents the default pool of the IPv4 addresses mapped to the IPv6 sources, which is in
+/// the shared address space (RFC 6598).
+pub const DEFAULT_NAT64_POOL: Ipv4Addr = Ipv4Addr::new(100, 64, 0, 0);
+/// Represents the prefix length of the default pool of `Nat64`.
+pub const DEFAULT_NAT64_POOL_PREFIX: u8 = 24;
+
+/// Represents the size of the IPv4 header without options.
+const IPV4_HEADER_SIZE: usize = 20;
+/// Represents the size of the fixed IPv6 header.
+const IPV6_HEADER_SIZE: usize = 40;
+/// Represents the don't fragment flag of the IPv4 header.
+const IPV4_DONT_FRAGMENT: u16 = 0x4000;
+/// Represents the offset of the checksum in the TCP header.
+const TCP_CHECKSUM_OFFSET: usize = 16;
+/// Represents the offset of the checksum in the UDP header.
+const UDP_CHECKSUM_OFFSET: usize = 6;
+
+/// Returns the IPv4 address embedded in the given IPv6 address of the given /96 prefix. Returns
+/// `None` if the address is not of the prefix.
+pub fn extract_ipv4(ip_addr: &Ipv6Addr, prefix: &Ipv6Addr) -> Option<Ipv4Addr> {
+    let octets = ip_addr.octets();
+    if octets[..12] != prefix.octets()[..12] {
+        return None;
+    }
+
+    Some(Ipv4Addr::new(
+        octets[12], octets[13], octets[14], octets[15],
+    ))
+}
+
+/// Returns the IPv6 address of the given /96 prefix embedding the given IPv4 address.
+pub fn embed_ipv4(ip_addr: &Ipv4Addr, prefix: &Ipv6Addr) -> Ipv6Addr {
+    let mut octets = prefix.octets();
+    octets[12..].copy_from_slice(&ip_addr.octets());
+
+    Ipv6Addr::from(octets)
+}
+
+/// Represents a stateful NAT64 translator of TCP and UDP flows (RFC 6146). IPv6 frames from
+/// the sources to the destinations of the prefix are translated into IPv4 frames from an IPv4
+/// address of the pool dedicated to each IPv6 source, and the IPv4 frames sent back to the
+/// addresses of the pool are translated into IPv6 frames. The checksums are recomputed across
+/// the address family change.
+#[derive(Debug)]
+pub struct Nat64 {
+    prefix: Ipv6Addr,
+    pool: Ipv4Network,
+    /// Represents the IPv4 address of the pool mapped to each IPv6 source.
+    sources: LruCache<Ipv6Addr, Ipv4Addr>,
+    /// Represents the IPv6 source and its hardware address mapped to each IPv4 address of the
+    /// pool.
+    ip_addrs: HashMap<Ipv4Addr, (Ipv6Addr, MacAddr)>,
+}
+
+impl Nat64 {
+    /// Creates a new `Nat64` translating the destinations of the given /96 prefix, with the
+    /// default pool 100.64.0.0/24 of the IPv4 addresses mapped to the IPv6 sources.
+    pub fn new(prefix: Ipv6Addr) -> Nat64 {
+        let pool = Ipv4Network::new(DEFAULT_NAT64_POOL, DEFAULT_NAT64_POOL_PREFIX).unwrap();
+
+        Nat64::with_pool(prefix, pool)
+    }
+
+    /// Creates a new `Nat64` with the given pool of the IPv4 addresses mapped to the IPv6
+    /// sources. The network and the broadcast addresses of the pool are never mapped, and the
+    /// address of the least recently used source is remapped when the pool is exhausted.
+    pub fn with_pool(prefix: Ipv6Addr, pool: Ipv4Network) -> Nat64 {
+        let capacity = (pool.size() as usize).saturating_sub(2).max(1);
+
+        Nat64 {
+            prefix,
+            pool,
+            sources: LruCache::new(capacity),
+            ip_addrs: HashMap::new(),
+        }
+    }
+
+    /// Maps the given IPv6 source of the given hardware address to an IPv4 address of the pool.
+    fn map(&mut self, ip_addr: Ipv6Addr, hardware_addr: MacAddr) -> Ipv4Addr {
+        let mapped = match self.sources.get(&ip_addr) {
+            Some(mapped) => *mapped,
+            None => {
+                let mapped = if self.sources.len() < self.sources.cap() {
+                    // Skip the network address
+                    let offset = self.sources.len() as u32 + 1;
+                    Ipv4Addr::from(u32::from(self.pool.network()) + offset)
+                } else {
+                    // Remap the address of the least recently used source
+                    let (_, mapped) = self.sources.pop_lru().unwrap();
+                    mapped
+                };
+                self.sources.put(ip_addr, mapped);
+
+                mapped
+            }
+        };
+        self.ip_addrs.insert(mapped, (ip_addr, hardware_addr));
+
+        mapped
+    }
+
+    /// Translates the given IPv6 frame into an IPv4 frame. Returns `None` if the frame is not an
+    /// IPv6 TCP or UDP frame to a destination of the prefix. Returns an error if the frame cannot
+    /// be translated, e.g., a fragment.
+    pub fn translate_ipv6(&mut self, frame: &[u8]) -> io::Result<Option<Vec<u8>>> {
+        let mut iter = layers(frame);
+        let (ethernet_size, ipv6, range) = match (iter.next(), iter.next()) {
+            (Some((Layers::Ethernet(_), ethernet_range)), Some((Layers::Ipv6(ipv6), range))) => {
+                (ethernet_range.end, ipv6, range)
+            }
+            _ => return Ok(None),
+        };
+        let dst_ip_addr = match extract_ipv4(&ipv6.get_dst(), &self.prefix) {
+            Some(ip_addr) => ip_addr,
+            None => return Ok(None),
+        };
+        let protocol = ipv6.get_transport_protocol();
+        let checksum_offset = match protocol {
+            IpNextHeaderProtocols::Tcp => TCP_CHECKSUM_OFFSET,
+            IpNextHeaderProtocols::Udp => UDP_CHECKSUM_OFFSET,
+            _ => return Ok(None),
+        };
+        if find_fragment_header(&frame[range.start..])?.is_some() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "IPv6 fragment cannot be translated",
+            ));
+        }
+        let end =
+            (range.start + IPV6_HEADER_SIZE + ipv6.get_payload_length() as usize).min(frame.len());
+        let transport = &frame[range.end.min(end)..end];
+        if transport.len() < checksum_offset + 2 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "transport layer truncated",
+            ));
+        }
+        let hardware_addr =
+            MacAddr::new(frame[6], frame[7], frame[8], frame[9], frame[10], frame[11]);
+        let src_ip_addr = self.map(ipv6.get_src(), hardware_addr);
+
+        let mut translated = Vec::with_capacity(ethernet_size + IPV4_HEADER_SIZE + transport.len());
+        translated.extend_from_slice(&frame[..ethernet_size]);
+        set_ethertype(&mut translated, EtherTypes::Ipv4.0);
+        // Traffic class and hop limit are kept in TOS and TTL
+        let traffic_class = ((frame[range.start] & 0x0f) << 4) | (frame[range.start + 1] >> 4);
+        translated.extend_from_slice(&ipv4_header(
+            traffic_class,
+            (IPV4_HEADER_SIZE + transport.len()) as u16,
+            ipv6.get_hop_limit(),
+            protocol,
+            src_ip_addr,
+            dst_ip_addr,
+        ));
+        let offset = translated.len();
+        translated.extend_from_slice(transport);
+        write_checksum(&mut translated[offset..], checksum_offset, |data| {
+            util::ipv4_checksum(
+                data,
+                checksum_offset / 2,
+                &[],
+                &src_ip_addr,
+                &dst_ip_addr,
+                protocol,
+            )
+        });
+
+        Ok(Some(translated))
+    }
+
+    /// Translates the given IPv4 frame into an IPv6 frame. Returns `None` if the frame is not an
+    /// IPv4 TCP or UDP frame to an address of the pool mapped. Returns an error if the frame cannot be
+    /// translated, e.g., a fragment.
+    pub fn translate_ipv4(&mut self, frame: &[u8]) -> io::Result<Option<Vec<u8>>> {
+        let mut iter = layers(frame);
+        let (ethernet_size, ipv4, range) = match (iter.next(), iter.next()) {
+            (Some((Layers::Ethernet(_), ethernet_range)), Some((Layers::Ipv4(ipv4), range))) => {
+                (ethernet_range.end, ipv4, range)
+            }
+            _ => return Ok(None),
+        };
+        let dst_ip_addr = match self.ip_addrs.get(&ipv4.get_dst()) {
+            Some((ip_addr, _)) => *ip_addr,
+            None => return Ok(None),
+        };
+        let protocol = ipv4.get_next_level_protocol();
+        let checksum_offset = match protocol {
+            IpNextHeaderProtocols::Tcp => TCP_CHECKSUM_OFFSET,
+            IpNextHeaderProtocols::Udp => UDP_CHECKSUM_OFFSET,
+            _ => return Ok(None),
+        };
+        let end = (range.start + ipv4.get_total_length() as usize).min(frame.len());
+        let transport = &frame[range.end.min(end)..end];
+        if transport.len() < checksum_offset + 2 {
+            return Ok(None);
+        }
+        if ipv4.is_fragment() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "IPv4 fragment cannot be translated",
+            ));
+        }
+        let src_ip_addr = embed_ipv4(&ipv4.get_src(), &self.prefix);
+
+        let mut translated = Vec::with_capacity(ethernet_size + IPV6_HEADER_SIZE + transport.len());
+        translated.extend_from_slice(&frame[..ethernet_size]);
+        set_ethertype(&mut translated, EtherTypes::Ipv6.0);
+        translated.extend_from_slice(&ipv6_header(
+            ipv4.get_dscp() << 2 | ipv4.get_ecn(),
+            transport.len() as u16,
+            ipv4.get_ttl(),
+            protocol,
+            src_ip_addr,
+            dst_ip_addr,
+        ));
+        let offset = translated.len();
+        translated.extend_from_slice(transport);
+        // The UDP checksum is mandatory in IPv6
+        write_checksum(&mut translated[offset..], checksum_offset, |data| {
+            util::ipv6_checksum(
+                data,
+                checksum_offset / 2,
+                &[],
+                &src_ip_addr,
+                &dst_ip_addr,
+                protocol,
+            )
+        });
+
+        Ok(Some(translated))
+    }
+
+    /// Get the prefix of the translator.
+    pub fn get_prefix(&self) -> Ipv6Addr {
+        self.prefix
+    }
+
+    /// Get the pool of the IPv4 addresses mapped to the IPv6 sources.
+    pub fn get_pool(&self) -> Ipv4Network {
+        self.pool
+    }
+
+    /// Returns if the given IPv4 address is mapped to an IPv6 source.
+    pub fn contains(&self, ip_addr: Ipv4Addr) -> bool {
+        self.ip_addrs.contains_key(&ip_addr)
+    }
+
+    /// Get the IPv6 source mapped to the given IPv4 address.
+    pub fn get_source(&self, ip_addr: Ipv4Addr) -> Option<Ipv6Addr> {
+        self.ip_addrs.get(&ip_addr).map(|(ip_addr, _)| *ip_addr)
+    }
+
+    /// Get the hardware address of the IPv6 source mapped to the given IPv4 address.
+    pub fn get_hardware_addr(&self, ip_addr: Ipv4Addr) -> Option<MacAddr> {
+        self.ip_addrs
+            .get(&ip_addr)
+            .map(|(_, hardware_addr)| *hardware_addr)
+    }
+
+    /// Get the number of the IPv6 sources mapped.
+    pub fn len(&self) -> usize {
+        self.sources.len()
+    }
+
+    /// Returns if no IPv6 source is mapped.
+    pub fn is_empty(&self) -> bool {
+        self.sources.is_empty()
+    }
+}
+
+/// Sets the EtherType in the tail of the given Ethernet header, which follows the VLAN tags if
+/// any.
+fn set_ethertype(header: &mut [u8], ethertype: u16) {
+    let len = header.len();
+    header[len - 2..].copy_from_slice(&ethertype.to_be_bytes());
+}
+
+/// Computes the checksum of the given transport layer with the given function, and writes it
+/// at the given offset. A zero UDP checksum is written as 0xFFFF.
+fn write_checksum<F: Fn(&[u8]) -> u16>(transport: &mut [u8], offset: usize, f: F) {
+    transport[offset..offset + 2].copy_from_slice(&[0, 0]);
+    let mut checksum = f(transport);
+    if offset == UDP_CHECKSUM_OFFSET && checksum == 0 {
+        checksum = 0xFFFF;
+    }
+    transport[offset..offset + 2].copy_from_slice(&checksum.to_be_bytes());
+}
+
+fn ipv4_header(
+    tos: u8,
+    total_length: u16,
+    ttl: u8,
+    protocol: IpNextHeaderProtocol,
+    src: Ipv4Addr,
+    dst: Ipv4Addr,
+) -> [u8; IPV4_HEADER_SIZE] {
+    let mut header = [0u8; IPV4_HEADER_SIZE];
+    header[0] = 0x45;
+    header[1] = tos;
+    header[2..4].copy_from_slice(&total_length.to_be_bytes());
+    // Translated packets are not fragmented (RFC 7915)
+    header[6..8].copy_from_slice(&IPV4_DONT_FRAGMENT.to_be_bytes());
+    header[8] = ttl;
+    header[9] = protocol.0;
+    header[12..16].copy_from_slice(&src.octets());
+    header[16..20].copy_from_slice(&dst.octets());
+    let checksum = util::checksum(&header, 5);
+    header[10..12].copy_from_slice(&checksum.to_be_bytes());
+
+    header
+}
+
+fn ipv6_header(
+    traffic_class: u8,
+    payload_length: u16,
+    hop_limit: u8,
+    protocol: IpNextHeaderProtocol,
+    src: Ipv6Addr,
+    dst: Ipv6Addr,
+) -> [u8; IPV6_HEADER_SIZE] {
+    let mut header = [0u8; IPV6_HEADER_SIZE];
+    header[0] = 0x60 | traffic_class >> 4;
+    header[1] = traffic_class << 4;
+    header[4..6].copy_from_slice(&payload_length.to_be_bytes());
+    header[6] = protocol.0;
+    header[7] = hop_limit;
+    header[8..24].copy_from_slice(&src.octets());
+    header[24..40].copy_from_slice(&dst.octets());
+
+    header
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::layer::ethernet::Ethernet;
+    use super::super::layer::ipv4::Ipv4;
+    use super::super::layer::ipv6::Ipv6;
+    use super::super::layer::tcp::Tcp;
+    use super::super::layer::LayerTypes;
+    use super::super::{Indicator, PacketBuilder};
+    use super::*;
+
+    const SRC_HARDWARE_ADDR: MacAddr = MacAddr(0x02, 0, 0, 0, 0, 0x01);
+    const LOCAL_HARDWARE_ADDR: MacAddr = MacAddr(0x02, 0, 0, 0, 0, 0xfe);
+
+    fn src() -> Ipv6Addr {
+        "2001:db8::1".parse().unwrap()
+    }
+
+    fn dst() -> Ipv6Addr {
+        "64:ff9b::5db8:d822".parse().unwrap()
+    }
+
+    /// Builds an IPv6 TCP SYN frame from the given source to the given destination.
+    fn build_ipv6_syn_frame(src: Ipv6Addr, dst: Ipv6Addr) -> Vec<u8> {
+        let ethernet =
+            Ethernet::new(LayerTypes::Ipv6, SRC_HARDWARE_ADDR, LOCAL_HARDWARE_ADDR).unwrap();
+        let ipv6 = Ipv6::new(LayerTypes::Tcp, src, dst).unwrap();
+        let mut tcp = Tcp::new_syn(1024, 80, 1000, 65535);
+        tcp.set_ipv6_layer(&ipv6);
+
+        PacketBuilder::new()
+            .ethernet(ethernet)
+            .ipv6(ipv6)
+            .tcp(tcp)
+            .build()
+            .unwrap()
+    }
+
+    #[test]
+    fn embed_extract_round_trip() {
+        let ip_addr = Ipv4Addr::new(93, 184, 216, 34);
+        let embedded = embed_ipv4(&ip_addr, &NAT64_WELL_KNOWN_PREFIX);
+        assert_eq!(embedded, dst());
+        assert_eq!(
+            extract_ipv4(&embedded, &NAT64_WELL_KNOWN_PREFIX),
+            Some(ip_addr)
+        );
+        assert_eq!(extract_ipv4(&src(), &NAT64_WELL_KNOWN_PREFIX), None);
+    }
+
+    #[test]
+    fn translate_ipv6_syn() {
+        let mut nat64 = Nat64::new(NAT64_WELL_KNOWN_PREFIX);
+        let frame = build_ipv6_syn_frame(src(), dst());
+        let translated = nat64.translate_ipv6(&frame).unwrap().unwrap();
+
+        let indicator = Indicator::from(&translated).unwrap();
+        let ipv4 = indicator.get_ipv4().unwrap();
+        let mapped = Ipv4Addr::new(100, 64, 0, 1);
+        assert_eq!(ipv4.get_src(), mapped);
+        assert_eq!(ipv4.get_dst(), Ipv4Addr::new(93, 184, 216, 34));
+        assert_eq!(ipv4.get_total_length(), 20 + 20);
+        let tcp = indicator.get_tcp().unwrap();
+        assert!(tcp.is_syn());
+        assert_eq!(tcp.get_dst(), 80);
+        // The checksum is of the IPv4 pseudo header
+        let transport = &translated[14 + IPV4_HEADER_SIZE..];
+        let checksum = util::ipv4_checksum(
+            transport,
+            TCP_CHECKSUM_OFFSET / 2,
+            &[],
+            &mapped,
+            &ipv4.get_dst(),
+            IpNextHeaderProtocols::Tcp,
+        );
+        assert_eq!(
+            &transport[TCP_CHECKSUM_OFFSET..TCP_CHECKSUM_OFFSET + 2],
+            &checksum.to_be_bytes()
+        );
+
+        assert_eq!(nat64.get_source(mapped), Some(src()));
+        assert_eq!(nat64.get_hardware_addr(mapped), Some(SRC_HARDWARE_ADDR));
+    }
+
+    #[test]
+    fn translate_ipv4_reply() {
+        let mut nat64 = Nat64::new(NAT64_WELL_KNOWN_PREFIX);
+        nat64
+            .translate_ipv6(&build_ipv6_syn_frame(src(), dst()))
+            .unwrap()
+            .unwrap();
+
+        let ethernet =
+            Ethernet::new(LayerTypes::Ipv4, LOCAL_HARDWARE_ADDR, SRC_HARDWARE_ADDR).unwrap();
+        let ipv4 = Ipv4::new(
+            0,
+            LayerTypes::Tcp,
+            Ipv4Addr::new(93, 184, 216, 34),
+            Ipv4Addr::new(100, 64, 0, 1),
+        )
+        .unwrap();
+        let mut tcp = Tcp::new_ack(80, 1024, 5000, 1001, 65535);
+        tcp.set_ipv4_layer(&ipv4);
+        let frame = PacketBuilder::new()
+            .ethernet(ethernet)
+            .ipv4(ipv4)
+            .tcp(tcp)
+            .build()
+            .unwrap();
+        let translated = nat64.translate_ipv4(&frame).unwrap().unwrap();
+
+        let indicator = Indicator::from(&translated).unwrap();
+        let ipv6 = indicator.get_ipv6().unwrap();
+        assert_eq!(ipv6.get_src(), dst());
+        assert_eq!(ipv6.get_dst(), src());
+        assert_eq!(ipv6.get_payload_length(), 20);
+        let transport = &translated[14 + IPV6_HEADER_SIZE..];
+        let checksum = util::ipv6_checksum(
+            transport,
+            TCP_CHECKSUM_OFFSET / 2,
+            &[],
+            &dst(),
+            &src(),
+            IpNextHeaderProtocols::Tcp,
+        );
+        assert_eq!(
+            &transport[TCP_CHECKSUM_OFFSET..TCP_CHECKSUM_OFFSET + 2],
+            &checksum.to_be_bytes()
+        );
+    }
+
+    #[test]
+    fn translate_untranslatable() {
+        let mut nat64 = Nat64::new(NAT64_WELL_KNOWN_PREFIX);
+
+        // Destinations out of the prefix are not translated
+        let frame = build_ipv6_syn_frame(src(), "2001:db8::2".parse().unwrap());
+        assert!(nat64.translate_ipv6(&frame).unwrap().is_none());
+        assert!(nat64.is_empty());
+        // Nor the IPv4 frames to addresses not mapped
+        let frame = build_ipv6_syn_frame(src(), dst());
+        let translated = nat64.translate_ipv6(&frame).unwrap().unwrap();
+        assert!(nat64.translate_ipv4(&translated).unwrap().is_none());
+    }
+
+    #[test]
+    fn pool_remap_lru() {
+        // A pool of 2 addresses mapped
+        let pool = Ipv4Network::new(Ipv4Addr::new(100, 64, 0, 0), 30).unwrap();
+        let mut nat64 = Nat64::with_pool(NAT64_WELL_KNOWN_PREFIX, pool);
+        let srcs: Vec<Ipv6Addr> = (1..=3)
+            .map(|i| format!("2001:db8::{}", i).parse().unwrap())
+            .collect();
+
+        let mapped: Vec<Ipv4Addr> = srcs
+            .iter()
+            .map(|src| {
+                let frame = build_ipv6_syn_frame(*src, dst());
+                let translated = nat64.translate_ipv6(&frame).unwrap().unwrap();
+                Indicator::from(&translated)
+                    .unwrap()
+                    .get_ipv4()
+                    .unwrap()
+                    .get_src()
+            })
+            .collect();
+
+        // Each source is of a dedicated address until the pool is exhausted
+        assert_eq!(mapped[0], Ipv4Addr::new(100, 64, 0, 1));
+        assert_eq!(mapped[1], Ipv4Addr::new(100, 64, 0, 2));
+        assert_eq!(mapped[2], mapped[0]);
+        assert_eq!(nat64.len(), 2);
+        assert_eq!(nat64.get_source(mapped[0]), Some(srcs[2]));
+    }
+}
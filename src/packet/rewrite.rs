@@ -0,0 +1,342 @@
+use super::iter::layers;
+use super::layer::{incremental_update, Layers};
+use std::io;
+use std::net::Ipv4Addr;
+
+/// Represents the offset of the checksum in the IPv4 header.
+const IPV4_CHECKSUM_OFFSET: usize = 10;
+/// Represents the offset of the time to live in the IPv4 header.
+const IPV4_TTL_OFFSET: usize = 8;
+/// Represents the offset of the source in the IPv4 header.
+const IPV4_SRC_OFFSET: usize = 12;
+/// Represents the offset of the destination in the IPv4 header.
+const IPV4_DST_OFFSET: usize = 16;
+/// Represents the offset of the checksum in the TCP header.
+const TCP_CHECKSUM_OFFSET: usize = 16;
+/// Represents the offset of the checksum in the UDP header.
+const UDP_CHECKSUM_OFFSET: usize = 6;
+
+/// Represents the transport layer of a frame being rewritten.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+enum Transport {
+    Tcp(usize),
+    Udp(usize),
+}
+
+/// Represents a rewriter of a parsed IPv4 frame, e.g., for NAT-ing the source of the frame. The
+/// mutations are applied to the frame in place when the frame is rebuilt, and the IPv4, TCP and
+/// UDP checksums covering the fields mutated are updated incrementally (RFC 1624). None of the
+/// mutations changes the size of a layer, so the lengths are kept.
+#[derive(Clone, Debug)]
+pub struct Rewriter {
+    frame: Vec<u8>,
+    ipv4: usize,
+    transport: Option<Transport>,
+    src_ip_addr: Option<Ipv4Addr>,
+    dst_ip_addr: Option<Ipv4Addr>,
+    src_port: Option<u16>,
+    dst_port: Option<u16>,
+    ttl: Option<u8>,
+}
+
+impl Rewriter {
+    /// Creates a new `Rewriter` of the given Ethernet frame. The first IPv4 layer of the frame
+    /// and the TCP or UDP layer it carries are rewritten.
+    pub fn new(frame: &[u8]) -> io::Result<Rewriter> {
+        let mut iter = layers(frame);
+        let mut ipv4 = None;
+        let mut transport = None;
+        for (layer, range) in iter.by_ref() {
+            match (ipv4, layer) {
+                (None, Layers::Ipv4(_)) => ipv4 = Some(range.start),
+                (Some(_), Layers::Tcp(_)) => {
+                    transport = Some(Transport::Tcp(range.start));
+                    break;
+                }
+                (Some(_), Layers::Udp(_)) => {
+                    transport = Some(Transport::Udp(range.start));
+                    break;
+                }
+                (Some(_), _) => break,
+                _ => {}
+            }
+        }
+        if let Some(e) = iter.get_error() {
+            return Err(e.into());
+        }
+        let ipv4 =
+            ipv4.ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "no IPv4 layer"))?;
+
+        Ok(Rewriter {
+            frame: frame.to_vec(),
+            ipv4,
+            transport,
+            src_ip_addr: None,
+            dst_ip_addr: None,
+            src_port: None,
+            dst_port: None,
+            ttl: None,
+        })
+    }
+
+    /// Rewrites the source IP address.
+    pub fn with_src_ip(mut self, ip_addr: Ipv4Addr) -> Rewriter {
+        self.src_ip_addr = Some(ip_addr);
+        self
+    }
+
+    /// Rewrites the destination IP address.
+    pub fn with_dst_ip(mut self, ip_addr: Ipv4Addr) -> Rewriter {
+        self.dst_ip_addr = Some(ip_addr);
+        self
+    }
+
+    /// Rewrites the source port of the TCP or UDP layer.
+    pub fn with_src_port(mut self, port: u16) -> Rewriter {
+        self.src_port = Some(port);
+        self
+    }
+
+    /// Rewrites the destination port of the TCP or UDP layer.
+    pub fn with_dst_port(mut self, port: u16) -> Rewriter {
+        self.dst_port = Some(port);
+        self
+    }
+
+    /// Rewrites the time to live.
+    pub fn with_ttl(mut self, ttl: u8) -> Rewriter {
+        self.ttl = Some(ttl);
+        self
+    }
+
+    /// Applies the mutations and returns the frame rewritten. Returns an error if a port is
+    /// rewritten but the frame carries no TCP or UDP layer, e.g., a non-first fragment.
+    pub fn rebuild(mut self) -> io::Result<Vec<u8>> {
+        if (self.src_port.is_some() || self.dst_port.is_some()) && self.transport.is_none() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "no transport layer",
+            ));
+        }
+
+        // IPv4 addresses are also covered by the pseudo-header of the transport layer
+        if let Some(ip_addr) = self.src_ip_addr {
+            self.write(self.ipv4 + IPV4_SRC_OFFSET, &ip_addr.octets(), true);
+        }
+        if let Some(ip_addr) = self.dst_ip_addr {
+            self.write(self.ipv4 + IPV4_DST_OFFSET, &ip_addr.octets(), true);
+        }
+        if let Some(ttl) = self.ttl {
+            // TTL shares a 16-bit word with the protocol
+            let protocol = self.frame[self.ipv4 + IPV4_TTL_OFFSET + 1];
+            self.write(self.ipv4 + IPV4_TTL_OFFSET, &[ttl, protocol], false);
+        }
+
+        // Ports are only covered by the transport layer
+        if let Some(transport) = self.transport {
+            let offset = match transport {
+                Transport::Tcp(offset) | Transport::Udp(offset) => offset,
+            };
+            if let Some(port) = self.src_port {
+                self.write_transport(offset, &port.to_be_bytes());
+            }
+            if let Some(port) = self.dst_port {
+                self.write_transport(offset + 2, &port.to_be_bytes());
+            }
+        }
+
+        Ok(self.frame)
+    }
+
+    /// Writes the given 16-bit aligned bytes at the given offset of the IPv4 header, and updates
+    /// the IPv4 checksum, and also the transport checksum if the bytes are in the pseudo-header.
+    fn write(&mut self, offset: usize, bytes: &[u8], is_pseudo_header: bool) {
+        let old = self.frame[offset..offset + bytes.len()].to_vec();
+        self.frame[offset..offset + bytes.len()].copy_from_slice(bytes);
+
+        self.update_checksum(self.ipv4 + IPV4_CHECKSUM_OFFSET, &old, bytes, false);
+        if is_pseudo_header {
+            self.update_transport_checksum(&old, bytes);
+        }
+    }
+
+    /// Writes the given 16-bit aligned bytes at the given offset of the transport layer, and
+    /// updates the transport checksum.
+    fn write_transport(&mut self, offset: usize, bytes: &[u8]) {
+        let old = self.frame[offset..offset + bytes.len()].to_vec();
+        self.frame[offset..offset + bytes.len()].copy_from_slice(bytes);
+
+        self.update_transport_checksum(&old, bytes);
+    }
+
+    fn update_transport_checksum(&mut self, old: &[u8], new: &[u8]) {
+        match self.transport {
+            Some(Transport::Tcp(offset)) => {
+                self.update_checksum(offset + TCP_CHECKSUM_OFFSET, old, new, false)
+            }
+            // A zero UDP checksum means no checksum is transmitted
+            Some(Transport::Udp(offset)) => {
+                self.update_checksum(offset + UDP_CHECKSUM_OFFSET, old, new, true)
+            }
+            None => {}
+        }
+    }
+
+    /// Updates the checksum at the given offset after the given 16-bit words change. An optional
+    /// checksum is left unchanged if it is not transmitted, and a zero result is written as 0xFFFF.
+    fn update_checksum(&mut self, offset: usize, old: &[u8], new: &[u8], is_optional: bool) {
+        let mut checksum = (self.frame[offset] as u16) << 8 | self.frame[offset + 1] as u16;
+        if is_optional && checksum == 0 {
+            return;
+        }
+
+        for (old_word, new_word) in old.chunks(2).zip(new.chunks(2)) {
+            checksum = incremental_update(
+                checksum,
+                (old_word[0] as u16) << 8 | old_word[1] as u16,
+                (new_word[0] as u16) << 8 | new_word[1] as u16,
+            );
+        }
+        if is_optional && checksum == 0 {
+            checksum = 0xFFFF;
+        }
+
+        self.frame[offset..offset + 2].copy_from_slice(&checksum.to_be_bytes());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::packet::layer::arp::Arp;
+    use crate::packet::layer::ethernet::Ethernet;
+    use crate::packet::layer::icmp::Icmp;
+    use crate::packet::layer::ipv4::Ipv4;
+    use crate::packet::layer::tcp::Tcp;
+    use crate::packet::layer::udp::Udp;
+    use crate::packet::layer::{LayerType, LayerTypes};
+    use crate::packet::PacketBuilder;
+    use pnet::util::MacAddr;
+
+    const SRC: Ipv4Addr = Ipv4Addr::new(10, 6, 0, 1);
+    const DST: Ipv4Addr = Ipv4Addr::new(93, 184, 216, 34);
+
+    fn ethernet(t: LayerType) -> Ethernet {
+        Ethernet::new(
+            t,
+            MacAddr::new(0x02, 0, 0, 0, 0, 0x01),
+            MacAddr::new(0x02, 0, 0, 0, 0, 0x02),
+        )
+        .unwrap()
+    }
+
+    /// Builds a frame of a TCP segment, whose checksums are computed from scratch.
+    fn build_tcp_frame(src: Ipv4Addr, src_port: u16) -> Vec<u8> {
+        let ipv4 = Ipv4::new(1, LayerTypes::Tcp, src, DST).unwrap();
+        let mut tcp = Tcp::new_ack(src_port, 80, 1001, 5001, 65535);
+        tcp.set_ipv4_layer(&ipv4);
+
+        PacketBuilder::new()
+            .ethernet(ethernet(LayerTypes::Ipv4))
+            .ipv4(ipv4)
+            .tcp(tcp)
+            .payload(b"hello")
+            .build()
+            .unwrap()
+    }
+
+    /// Builds a frame of a UDP datagram, whose checksums are computed from scratch.
+    fn build_udp_frame(dst: Ipv4Addr, dst_port: u16, ttl: u8, is_checksum: bool) -> Vec<u8> {
+        let mut ipv4 = Ipv4::new(1, LayerTypes::Udp, SRC, dst).unwrap();
+        ipv4.set_ttl(ttl);
+        let mut udp = Udp::new(1024, dst_port);
+        udp.set_ipv4_layer(&ipv4);
+        udp.set_ipv4_checksum(is_checksum);
+
+        PacketBuilder::new()
+            .ethernet(ethernet(LayerTypes::Ipv4))
+            .ipv4(ipv4)
+            .udp(udp)
+            .payload(b"query")
+            .build()
+            .unwrap()
+    }
+
+    #[test]
+    fn rewrite_tcp_src() {
+        let src = Ipv4Addr::new(192, 168, 1, 9);
+        let frame = Rewriter::new(&build_tcp_frame(SRC, 1024))
+            .unwrap()
+            .with_src_ip(src)
+            .with_src_port(40000)
+            .rebuild()
+            .unwrap();
+
+        // Both the IPv4 and TCP checksums are the same as computed from scratch
+        assert_eq!(frame, build_tcp_frame(src, 40000));
+    }
+
+    #[test]
+    fn rewrite_udp_dst_and_ttl() {
+        let dst = Ipv4Addr::new(8, 8, 8, 8);
+        let frame = Rewriter::new(&build_udp_frame(DST, 53, 64, true))
+            .unwrap()
+            .with_dst_ip(dst)
+            .with_dst_port(5353)
+            .with_ttl(63)
+            .rebuild()
+            .unwrap();
+        assert_eq!(frame, build_udp_frame(dst, 5353, 63, true));
+
+        // A zero UDP checksum is not transmitted, and is left zero
+        let frame = Rewriter::new(&build_udp_frame(DST, 53, 64, false))
+            .unwrap()
+            .with_dst_ip(dst)
+            .rebuild()
+            .unwrap();
+        assert_eq!(frame, build_udp_frame(dst, 53, 64, false));
+        assert_eq!(frame[14 + 20 + 6..14 + 20 + 8], [0, 0]);
+    }
+
+    #[test]
+    fn rewrite_without_transport() {
+        let ipv4 = Ipv4::new(1, LayerTypes::Icmp, SRC, DST).unwrap();
+        let frame = PacketBuilder::new()
+            .ethernet(ethernet(LayerTypes::Ipv4))
+            .ipv4(ipv4)
+            .icmp(Icmp::new_echo_request(0x1234, 1))
+            .payload(b"ping")
+            .build()
+            .unwrap();
+
+        // Addresses are rewritten, but not ports
+        let src = Ipv4Addr::new(192, 168, 1, 9);
+        let rewritten = Rewriter::new(&frame)
+            .unwrap()
+            .with_src_ip(src)
+            .rebuild()
+            .unwrap();
+        assert_eq!(rewritten[14 + 12..14 + 16], src.octets());
+        let e = Rewriter::new(&frame)
+            .unwrap()
+            .with_src_port(40000)
+            .rebuild()
+            .unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn rewrite_without_ipv4() {
+        let frame = PacketBuilder::new()
+            .ethernet(ethernet(LayerTypes::Arp))
+            .arp(Arp::new_gratuitous(
+                MacAddr::new(0x02, 0, 0, 0, 0, 0x01),
+                SRC,
+            ))
+            .build()
+            .unwrap();
+
+        let e = Rewriter::new(&frame).unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::InvalidInput);
+    }
+}
@@ -0,0 +1,933 @@
+use super::{Precision, Receiver, Sender, Timestamped, DEFAULT_SNAPLEN};
+use crate::packet::iter;
+use crate::packet::layer::sll::Sll;
+use crate::packet::layer::Layers;
+use crate::packet::{LinkType, LINKTYPE_ETHERNET};
+use pnet::datalink::{DataLinkReceiver, DataLinkSender, NetworkInterface};
+use pnet::packet::ethernet::{EthernetPacket, MutableEthernetPacket};
+use std::fs::File;
+use std::io::{self, BufReader, Read, Write};
+use std::path::Path;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// Represents the magic number of pcap files with timestamps in microseconds.
+const PCAP_MAGIC: u32 = 0xa1b2_c3d4;
+/// Represents the magic number of pcap files with timestamps in nanoseconds.
+const PCAP_NANO_MAGIC: u32 = 0xa1b2_3c4d;
+/// Represents the block type of the pcapng section header block, which is also the magic number
+/// of pcapng files.
+const PCAPNG_SECTION_HEADER: u32 = 0x0a0d_0d0a;
+/// Represents the byte-order magic of pcapng files.
+const PCAPNG_BYTE_ORDER_MAGIC: u32 = 0x1a2b_3c4d;
+/// Represents the block type of the pcapng interface description block.
+const PCAPNG_INTERFACE_DESCRIPTION: u32 = 1;
+/// Represents the block type of the pcapng simple packet block.
+const PCAPNG_SIMPLE_PACKET: u32 = 3;
+/// Represents the block type of the pcapng enhanced packet block.
+const PCAPNG_ENHANCED_PACKET: u32 = 6;
+/// Represents the option code of the timestamp resolution in pcapng interface description blocks.
+const PCAPNG_OPTION_TSRESOL: u16 = 9;
+/// Represents the default max length of frames in pcap files.
+pub const PCAP_SNAPLEN: u32 = 65535;
+
+/// Represents the format of a capture file.
+#[derive(Clone, Debug, Eq, PartialEq)]
+enum Format {
+    Pcap {
+        nanosecond: bool,
+    },
+    /// Represents a pcapng file, with the timestamp resolution in units per second of each
+    /// interface.
+    Pcapng {
+        resolutions: Vec<u64>,
+    },
+}
+
+/// Represents a capture reading frames from a pcap or pcapng file.
+pub struct Capture {
+    reader: BufReader<File>,
+    format: Format,
+    is_big_endian: bool,
+    buffer: Vec<u8>,
+    /// Represents the length of the last frame on the wire, which exceeds the buffer if the
+    /// frame is truncated.
+    original_length: usize,
+    /// Represents the max bytes read of each frame, the bytes beyond are truncated as a capture
+    /// of the snaplen.
+    snaplen: usize,
+    /// Represents the link type of each interface, pcap files have only 1 interface.
+    link_types: Vec<LinkType>,
+    link_type: LinkType,
+    realtime: bool,
+    /// Represents the timestamp of the first frame and when it is read.
+    start: Option<(Duration, Instant)>,
+    /// Represents the timestamp of the last frame and its precision.
+    timestamp: Option<(Duration, Precision)>,
+}
+
+impl Capture {
+    /// Opens a pcap or pcapng file for reading frames. Ethernet and Linux cooked capture (SLL)
+    /// frames are supported, and SLL frames are converted into Ethernet frames when read.
+    pub fn from_file<P: AsRef<Path>>(path: P) -> io::Result<Capture> {
+        let mut reader = BufReader::new(File::open(path)?);
+
+        let mut magic = [0u8; 4];
+        reader.read_exact(&mut magic)?;
+        let (format, is_big_endian) = match (u32::from_le_bytes(magic), u32::from_be_bytes(magic)) {
+            (PCAP_MAGIC, _) => (Format::Pcap { nanosecond: false }, false),
+            (_, PCAP_MAGIC) => (Format::Pcap { nanosecond: false }, true),
+            (PCAP_NANO_MAGIC, _) => (Format::Pcap { nanosecond: true }, false),
+            (_, PCAP_NANO_MAGIC) => (Format::Pcap { nanosecond: true }, true),
+            (PCAPNG_SECTION_HEADER, _) => (
+                Format::Pcapng {
+                    resolutions: vec![],
+                },
+                false,
+            ),
+            _ => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "unknown capture file format",
+                ))
+            }
+        };
+
+        let mut capture = Capture {
+            reader,
+            format,
+            is_big_endian,
+            buffer: vec![],
+            original_length: 0,
+            snaplen: DEFAULT_SNAPLEN,
+            link_types: vec![],
+            link_type: LinkType::Ethernet,
+            realtime: false,
+            start: None,
+            timestamp: None,
+        };
+        match capture.format {
+            Format::Pcap { .. } => capture.read_pcap_header()?,
+            Format::Pcapng { .. } => capture.read_section_header()?,
+        }
+
+        Ok(capture)
+    }
+
+    /// Sets if frames are read according to the timing recorded in the file.
+    pub fn set_realtime(&mut self, realtime: bool) {
+        self.realtime = realtime;
+    }
+
+    /// Sets the max bytes read of each frame. Frames are truncated to the snaplen as if they were
+    /// captured with it.
+    pub fn set_snaplen(&mut self, snaplen: usize) {
+        self.snaplen = snaplen;
+    }
+
+    /// Get the link types of the interfaces in the file seen so far.
+    pub fn get_link_types(&self) -> &[LinkType] {
+        &self.link_types
+    }
+
+    /// Get the timestamp of the last frame read and its precision. Timestamps are in nanoseconds
+    /// if the file records them so, and in microseconds otherwise.
+    pub fn get_timestamp(&self) -> Option<(Duration, Precision)> {
+        self.timestamp
+    }
+
+    /// Get the length of the last frame read on the wire. The frame is truncated if its
+    /// original length exceeds the bytes captured, e.g., by the snaplen of the capture.
+    pub fn get_original_length(&self) -> usize {
+        self.original_length
+    }
+
+    /// Get the length of the last frame read in bytes captured.
+    pub fn get_captured_length(&self) -> usize {
+        self.buffer.len()
+    }
+
+    /// Returns if the last frame read is truncated.
+    pub fn is_truncated(&self) -> bool {
+        self.original_length > self.buffer.len()
+    }
+
+    /// Reads the next frame and returns its layers with its timestamp. Returns an error if the
+    /// frame cannot be parsed.
+    pub fn next_timestamped(&mut self) -> io::Result<Timestamped<Vec<Layers>>> {
+        let layers = iter::parse_ethernet_stack(self.next()?)?;
+        let (timestamp, precision) = self.timestamp.unwrap();
+
+        Ok(Timestamped::new(layers, timestamp, precision))
+    }
+
+    /// Converts the capture into a `Receiver`.
+    pub fn into_receiver(self) -> Receiver {
+        Box::new(self)
+    }
+
+    fn read_bytes(&mut self, n: usize) -> io::Result<Vec<u8>> {
+        let mut buffer = vec![0u8; n];
+        self.reader.read_exact(&mut buffer)?;
+
+        Ok(buffer)
+    }
+
+    fn read_u32(&mut self) -> io::Result<u32> {
+        let mut buffer = [0u8; 4];
+        self.reader.read_exact(&mut buffer)?;
+
+        Ok(self.to_u32(buffer))
+    }
+
+    fn to_u16(&self, buffer: [u8; 2]) -> u16 {
+        match self.is_big_endian {
+            true => u16::from_be_bytes(buffer),
+            false => u16::from_le_bytes(buffer),
+        }
+    }
+
+    fn to_u32(&self, buffer: [u8; 4]) -> u32 {
+        match self.is_big_endian {
+            true => u32::from_be_bytes(buffer),
+            false => u32::from_le_bytes(buffer),
+        }
+    }
+
+    fn read_pcap_header(&mut self) -> io::Result<()> {
+        // Version, time zone, sigfigs and snaplen
+        self.read_bytes(16)?;
+        let link_type = LinkType::from_pcap(self.read_u32()?)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "unsupported link type"))?;
+        self.link_types = vec![link_type];
+
+        Ok(())
+    }
+
+    /// Reads the section header block after its block type.
+    fn read_section_header(&mut self) -> io::Result<()> {
+        let mut length = [0u8; 4];
+        self.reader.read_exact(&mut length)?;
+        let mut magic = [0u8; 4];
+        self.reader.read_exact(&mut magic)?;
+        self.is_big_endian = match (u32::from_le_bytes(magic), u32::from_be_bytes(magic)) {
+            (PCAPNG_BYTE_ORDER_MAGIC, _) => false,
+            (_, PCAPNG_BYTE_ORDER_MAGIC) => true,
+            _ => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "invalid pcapng byte-order magic",
+                ))
+            }
+        };
+        let length = self.to_u32(length) as usize;
+        if length < 28 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "invalid pcapng block length",
+            ));
+        }
+        // Rest of the block
+        self.read_bytes(length - 12)?;
+
+        // Interfaces are described per section
+        self.format = Format::Pcapng {
+            resolutions: vec![],
+        };
+        self.link_types.clear();
+
+        Ok(())
+    }
+
+    /// Reads the body of an interface description block and returns its link type and timestamp
+    /// resolution.
+    fn read_interface_description(&self, body: &[u8]) -> io::Result<(LinkType, u64)> {
+        if body.len() < 8 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "invalid pcapng interface description",
+            ));
+        }
+        let link_type = LinkType::from_pcap(self.to_u16([body[0], body[1]]) as u32)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "unsupported link type"))?;
+
+        // Options
+        let mut resolution = 1_000_000;
+        let mut i = 8;
+        while i + 4 <= body.len() {
+            let code = self.to_u16([body[i], body[i + 1]]);
+            let length = self.to_u16([body[i + 2], body[i + 3]]) as usize;
+            if code == PCAPNG_OPTION_TSRESOL && length >= 1 && i + 4 < body.len() {
+                let value = body[i + 4];
+                let exponent = (value & 0x7f) as u32;
+                resolution = match value & 0x80 {
+                    0 => 10u64.checked_pow(exponent),
+                    _ => 2u64.checked_pow(exponent),
+                }
+                .unwrap_or(resolution);
+            }
+            if code == 0 {
+                break;
+            }
+            i += 4 + (length + 3) / 4 * 4;
+        }
+
+        Ok((link_type, resolution))
+    }
+
+    /// Reads the next frame and returns it with its timestamp and the precision.
+    fn read_frame(&mut self) -> io::Result<(Duration, Precision)> {
+        match self.format {
+            Format::Pcap { nanosecond } => {
+                let seconds = self.read_u32()? as u64;
+                let fraction = self.read_u32()? as u64;
+                let captured_length = self.read_u32()? as usize;
+                self.original_length = self.read_u32()? as usize;
+                self.buffer = self.read_bytes(captured_length)?;
+                self.link_type = self.link_types[0];
+
+                Ok(match nanosecond {
+                    true => (
+                        Duration::new(seconds, fraction as u32),
+                        Precision::Nanosecond,
+                    ),
+                    false => (
+                        Duration::new(seconds, (fraction * 1000) as u32),
+                        Precision::Microsecond,
+                    ),
+                })
+            }
+            Format::Pcapng { .. } => loop {
+                let block_type = self.read_u32()?;
+                if block_type == PCAPNG_SECTION_HEADER {
+                    self.read_section_header()?;
+                    continue;
+                }
+                let length = self.read_u32()? as usize;
+                if length < 12 || length % 4 != 0 {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        "invalid pcapng block length",
+                    ));
+                }
+                let body = self.read_bytes(length - 12)?;
+                // Trailing block length
+                self.read_u32()?;
+
+                match block_type {
+                    PCAPNG_INTERFACE_DESCRIPTION => {
+                        let (link_type, resolution) = self.read_interface_description(&body)?;
+                        self.link_types.push(link_type);
+                        if let Format::Pcapng {
+                            ref mut resolutions,
+                        } = self.format
+                        {
+                            resolutions.push(resolution);
+                        }
+                    }
+                    PCAPNG_ENHANCED_PACKET => {
+                        if body.len() < 20 {
+                            return Err(io::Error::new(
+                                io::ErrorKind::InvalidData,
+                                "invalid pcapng enhanced packet",
+                            ));
+                        }
+                        let interface = self.to_u32([body[0], body[1], body[2], body[3]]) as usize;
+                        let high = self.to_u32([body[4], body[5], body[6], body[7]]) as u64;
+                        let low = self.to_u32([body[8], body[9], body[10], body[11]]) as u64;
+                        let captured_length =
+                            self.to_u32([body[12], body[13], body[14], body[15]]) as usize;
+                        if body.len() < 20 + captured_length {
+                            return Err(io::Error::new(
+                                io::ErrorKind::InvalidData,
+                                "invalid pcapng enhanced packet",
+                            ));
+                        }
+                        self.buffer = body[20..20 + captured_length].to_vec();
+                        self.original_length =
+                            self.to_u32([body[16], body[17], body[18], body[19]]) as usize;
+                        self.link_type = *self
+                            .link_types
+                            .get(interface)
+                            .unwrap_or(&LinkType::Ethernet);
+
+                        let resolution = match self.format {
+                            Format::Pcapng { ref resolutions } => {
+                                *resolutions.get(interface).unwrap_or(&1_000_000)
+                            }
+                            _ => unreachable!(),
+                        };
+                        let timestamp = high << 32 | low;
+                        let precision = match resolution >= 1_000_000_000 {
+                            true => Precision::Nanosecond,
+                            false => Precision::Microsecond,
+                        };
+                        return Ok((
+                            Duration::new(
+                                timestamp / resolution,
+                                ((timestamp % resolution) * 1_000_000_000 / resolution) as u32,
+                            ),
+                            precision,
+                        ));
+                    }
+                    PCAPNG_SIMPLE_PACKET => {
+                        if body.len() < 4 {
+                            return Err(io::Error::new(
+                                io::ErrorKind::InvalidData,
+                                "invalid pcapng simple packet",
+                            ));
+                        }
+                        let original_length =
+                            self.to_u32([body[0], body[1], body[2], body[3]]) as usize;
+                        let captured_length = original_length.min(body.len() - 4);
+                        self.buffer = body[4..4 + captured_length].to_vec();
+                        self.original_length = original_length;
+                        self.link_type = *self.link_types.first().unwrap_or(&LinkType::Ethernet);
+
+                        // Simple packet blocks have no timestamp
+                        return Ok(match self.timestamp {
+                            Some(timestamp) => timestamp,
+                            None => (Duration::from_secs(0), Precision::Microsecond),
+                        });
+                    }
+                    // Other blocks are ignored
+                    _ => {}
+                }
+            },
+        }
+    }
+
+    /// Converts the SLL frame in the buffer into an Ethernet frame.
+    fn convert_sll(&mut self) -> io::Result<()> {
+        let (sll, n) = Sll::deserialize(&self.buffer)?;
+
+        let size = EthernetPacket::minimum_packet_size();
+        let mut frame = vec![0u8; size + self.buffer.len() - n];
+        let mut packet = MutableEthernetPacket::new(&mut frame).unwrap();
+        packet.populate(&sll.to_ethernet().layer);
+        frame[size..].copy_from_slice(&self.buffer[n..]);
+        self.buffer = frame;
+        self.original_length = (self.original_length + size).saturating_sub(n);
+
+        Ok(())
+    }
+}
+
+impl DataLinkReceiver for Capture {
+    fn next(&mut self) -> io::Result<&[u8]> {
+        let (timestamp, precision) = match self.read_frame() {
+            Ok(timestamp) => timestamp,
+            Err(e) => {
+                if e.kind() == io::ErrorKind::UnexpectedEof {
+                    return Err(io::Error::new(
+                        io::ErrorKind::UnexpectedEof,
+                        "end of capture file",
+                    ));
+                }
+                return Err(e);
+            }
+        };
+
+        // Timing
+        match self.start {
+            Some((start_timestamp, start)) => {
+                if self.realtime {
+                    let elapsed = timestamp
+                        .checked_sub(start_timestamp)
+                        .unwrap_or_else(|| Duration::from_secs(0));
+                    if let Some(wait) = elapsed.checked_sub(start.elapsed()) {
+                        thread::sleep(wait);
+                    }
+                }
+            }
+            None => self.start = Some((timestamp, Instant::now())),
+        }
+        self.timestamp = Some((timestamp, precision));
+
+        if self.link_type == LinkType::LinuxSll {
+            self.convert_sll()?;
+        }
+        self.buffer.truncate(self.snaplen);
+
+        Ok(&self.buffer)
+    }
+}
+
+/// Represents a sender which discards every frame.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct NullSender;
+
+impl NullSender {
+    /// Converts the sender into a `Sender`.
+    pub fn into_sender(self) -> Sender {
+        Box::new(self)
+    }
+}
+
+impl DataLinkSender for NullSender {
+    fn build_and_send(
+        &mut self,
+        num_packets: usize,
+        packet_size: usize,
+        func: &mut dyn FnMut(&mut [u8]),
+    ) -> Option<io::Result<()>> {
+        let mut buffer = vec![0u8; packet_size];
+        for _ in 0..num_packets {
+            func(&mut buffer);
+        }
+
+        Some(Ok(()))
+    }
+
+    fn send_to(&mut self, _: &[u8], _: Option<NetworkInterface>) -> Option<io::Result<()>> {
+        Some(Ok(()))
+    }
+}
+
+/// Represents a writer writing frames into a pcap file.
+#[derive(Debug)]
+pub struct PcapWriter<W: Write> {
+    writer: W,
+    snaplen: u32,
+    last_timestamp: Duration,
+}
+
+impl PcapWriter<File> {
+    /// Creates a pcap file for writing frames. The file will be truncated if it exists.
+    pub fn create<P: AsRef<Path>>(path: P) -> io::Result<PcapWriter<File>> {
+        PcapWriter::new(File::create(path)?)
+    }
+
+    /// Creates a pcap file for writing frames truncated to the given snaplen. The file will be
+    /// truncated if it exists.
+    pub fn create_with_snaplen<P: AsRef<Path>>(
+        path: P,
+        snaplen: u32,
+    ) -> io::Result<PcapWriter<File>> {
+        PcapWriter::with_snaplen(File::create(path)?, snaplen)
+    }
+}
+
+impl<W: Write> PcapWriter<W> {
+    /// Creates a `PcapWriter` and writes the pcap global header into the given writer.
+    pub fn new(writer: W) -> io::Result<PcapWriter<W>> {
+        PcapWriter::with_snaplen(writer, PCAP_SNAPLEN)
+    }
+
+    /// Creates a `PcapWriter` writing frames truncated to the given snaplen, and writes the pcap
+    /// global header into the given writer. The snaplen is limited by `PCAP_SNAPLEN`.
+    pub fn with_snaplen(writer: W, snaplen: u32) -> io::Result<PcapWriter<W>> {
+        let snaplen = snaplen.min(PCAP_SNAPLEN);
+        let mut writer = PcapWriter {
+            writer,
+            snaplen,
+            last_timestamp: Duration::from_secs(0),
+        };
+
+        let mut header = Vec::with_capacity(24);
+        header.extend_from_slice(&PCAP_MAGIC.to_le_bytes());
+        // Version 2.4
+        header.extend_from_slice(&2u16.to_le_bytes());
+        header.extend_from_slice(&4u16.to_le_bytes());
+        // Time zone and sigfigs
+        header.extend_from_slice(&[0u8; 8]);
+        header.extend_from_slice(&snaplen.to_le_bytes());
+        header.extend_from_slice(&LINKTYPE_ETHERNET.to_le_bytes());
+        writer.writer.write_all(&header)?;
+
+        Ok(writer)
+    }
+
+    /// Writes a frame with the current time into the pcap file. Frames longer than the snaplen
+    /// are truncated.
+    pub fn write(&mut self, frame: &[u8]) -> io::Result<()> {
+        // Timestamps never go backwards even if the system time does
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_else(|_| Duration::from_secs(0));
+        if now > self.last_timestamp {
+            self.last_timestamp = now;
+        }
+
+        let captured_length = frame.len().min(self.snaplen as usize);
+        let mut record = Vec::with_capacity(16 + captured_length);
+        record.extend_from_slice(&(self.last_timestamp.as_secs() as u32).to_le_bytes());
+        record.extend_from_slice(&self.last_timestamp.subsec_micros().to_le_bytes());
+        record.extend_from_slice(&(captured_length as u32).to_le_bytes());
+        record.extend_from_slice(&(frame.len() as u32).to_le_bytes());
+        record.extend_from_slice(&frame[..captured_length]);
+
+        // Record is written at once so the file stays readable if the application exits
+        self.writer.write_all(&record)?;
+        self.writer.flush()
+    }
+
+    /// Get the snaplen of the `PcapWriter`.
+    pub fn get_snaplen(&self) -> u32 {
+        self.snaplen
+    }
+
+    /// Consumes the `PcapWriter` and returns the underlying writer.
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::packet::layer::arp::Arp;
+    use crate::packet::layer::ethernet::Ethernet;
+    use crate::packet::layer::ipv4::Ipv4;
+    use crate::packet::layer::sll::SLL_HEADER_SIZE;
+    use crate::packet::layer::udp::Udp;
+    use crate::packet::layer::{LayerTypes, ParseError};
+    use crate::packet::{Indicator, PacketBuilder, LINKTYPE_LINUX_SLL};
+    use pnet::util::MacAddr;
+    use std::fs;
+    use std::net::Ipv4Addr;
+    use std::path::PathBuf;
+
+    /// Get a path of a temporary file of the given name.
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("pcap2socks-{}-{}", std::process::id(), name))
+    }
+
+    /// Builds 2 frames of UDP datagrams and a frame of a gratuitous ARP.
+    fn build_frames() -> Vec<Vec<u8>> {
+        let src_hardware_addr = MacAddr::new(0x02, 0, 0, 0, 0, 0x01);
+        let src = Ipv4Addr::new(192, 168, 1, 1);
+        let mut frames = Vec::new();
+        for i in 0..2 {
+            let ipv4 = Ipv4::new(i, LayerTypes::Udp, src, Ipv4Addr::new(192, 168, 1, 2)).unwrap();
+            let mut udp = Udp::new(1024, 53);
+            udp.set_ipv4_layer(&ipv4);
+            let frame = PacketBuilder::new()
+                .ethernet(
+                    Ethernet::new(LayerTypes::Ipv4, src_hardware_addr, MacAddr::broadcast())
+                        .unwrap(),
+                )
+                .ipv4(ipv4)
+                .udp(udp)
+                .payload(b"query")
+                .build()
+                .unwrap();
+            frames.push(frame);
+        }
+        let frame = PacketBuilder::new()
+            .ethernet(
+                Ethernet::new(LayerTypes::Arp, src_hardware_addr, MacAddr::broadcast()).unwrap(),
+            )
+            .arp(Arp::new_gratuitous(src_hardware_addr, src))
+            .build()
+            .unwrap();
+        frames.push(frame);
+
+        frames
+    }
+
+    /// Reads all frames from the file of the given path.
+    fn read_frames(path: &Path) -> Vec<Vec<u8>> {
+        let mut capture = Capture::from_file(path).unwrap();
+        let mut frames = Vec::new();
+        loop {
+            match capture.next() {
+                Ok(frame) => frames.push(frame.to_vec()),
+                Err(e) => {
+                    assert_eq!(e.kind(), io::ErrorKind::UnexpectedEof);
+                    break;
+                }
+            }
+        }
+
+        frames
+    }
+
+    #[test]
+    fn replay_pcap() {
+        let path = temp_path("replay.pcap");
+        let mut writer = PcapWriter::create(&path).unwrap();
+        for frame in build_frames() {
+            writer.write(&frame).unwrap();
+        }
+        drop(writer);
+
+        let frames = read_frames(&path);
+        fs::remove_file(&path).unwrap();
+        assert_eq!(frames, build_frames());
+        let count = frames
+            .iter()
+            .filter(|frame| Indicator::from(frame).unwrap().get_ipv4().is_some())
+            .count();
+        assert_eq!(count, 2);
+    }
+
+    #[test]
+    fn replay_sll() {
+        let mut buffer = Vec::new();
+        buffer.extend_from_slice(&PCAP_MAGIC.to_le_bytes());
+        buffer.extend_from_slice(&[0x02, 0x00, 0x04, 0x00]);
+        buffer.extend_from_slice(&[0x00; 8]);
+        buffer.extend_from_slice(&PCAP_SNAPLEN.to_le_bytes());
+        buffer.extend_from_slice(&LINKTYPE_LINUX_SLL.to_le_bytes());
+        // Broadcast frames of the sender 02:00:00:00:00:01
+        let frames = build_frames();
+        for frame in &frames {
+            let length = (SLL_HEADER_SIZE + frame.len() - 14) as u32;
+            buffer.extend_from_slice(&[0x00; 8]);
+            buffer.extend_from_slice(&length.to_le_bytes());
+            buffer.extend_from_slice(&length.to_le_bytes());
+            buffer.extend_from_slice(&[0x00, 0x01, 0x00, 0x01, 0x00, 0x06]);
+            buffer.extend_from_slice(&frame[6..12]);
+            buffer.extend_from_slice(&[0x00, 0x00]);
+            buffer.extend_from_slice(&frame[12..]);
+        }
+        let path = temp_path("replay-sll.pcap");
+        fs::write(&path, &buffer).unwrap();
+
+        // SLL frames are converted into Ethernet frames
+        let capture = Capture::from_file(&path).unwrap();
+        assert_eq!(capture.get_link_types(), [LinkType::LinuxSll]);
+        let replayed = read_frames(&path);
+        fs::remove_file(&path).unwrap();
+        assert_eq!(replayed, frames);
+    }
+
+    #[test]
+    fn replay_pcapng() {
+        let mut buffer = Vec::new();
+        // Section header block
+        buffer.extend_from_slice(&PCAPNG_SECTION_HEADER.to_le_bytes());
+        buffer.extend_from_slice(&28u32.to_le_bytes());
+        buffer.extend_from_slice(&PCAPNG_BYTE_ORDER_MAGIC.to_le_bytes());
+        buffer.extend_from_slice(&[0x01, 0x00, 0x00, 0x00]);
+        buffer.extend_from_slice(&[0xff; 8]);
+        buffer.extend_from_slice(&28u32.to_le_bytes());
+        // Interface description block of Ethernet
+        buffer.extend_from_slice(&PCAPNG_INTERFACE_DESCRIPTION.to_le_bytes());
+        buffer.extend_from_slice(&20u32.to_le_bytes());
+        buffer.extend_from_slice(&(LINKTYPE_ETHERNET as u16).to_le_bytes());
+        buffer.extend_from_slice(&[0x00, 0x00]);
+        buffer.extend_from_slice(&PCAP_SNAPLEN.to_le_bytes());
+        buffer.extend_from_slice(&20u32.to_le_bytes());
+        // Enhanced packet blocks at 1.5 seconds
+        let frames = build_frames();
+        for frame in &frames {
+            let padding = (4 - frame.len() % 4) % 4;
+            let length = (32 + frame.len() + padding) as u32;
+            buffer.extend_from_slice(&PCAPNG_ENHANCED_PACKET.to_le_bytes());
+            buffer.extend_from_slice(&length.to_le_bytes());
+            buffer.extend_from_slice(&0u32.to_le_bytes());
+            buffer.extend_from_slice(&0u32.to_le_bytes());
+            buffer.extend_from_slice(&1_500_000u32.to_le_bytes());
+            buffer.extend_from_slice(&(frame.len() as u32).to_le_bytes());
+            buffer.extend_from_slice(&(frame.len() as u32).to_le_bytes());
+            buffer.extend_from_slice(frame);
+            buffer.extend_from_slice(&vec![0u8; padding]);
+            buffer.extend_from_slice(&length.to_le_bytes());
+        }
+        let path = temp_path("replay.pcapng");
+        fs::write(&path, &buffer).unwrap();
+
+        let mut capture = Capture::from_file(&path).unwrap();
+        assert_eq!(capture.next().unwrap(), &frames[0][..]);
+        assert_eq!(
+            capture.get_timestamp(),
+            Some((Duration::from_millis(1500), Precision::Microsecond))
+        );
+        assert_eq!(capture.get_link_types(), [LinkType::Ethernet]);
+        drop(capture);
+        let replayed = read_frames(&path);
+        fs::remove_file(&path).unwrap();
+        assert_eq!(replayed, frames);
+    }
+
+    #[test]
+    fn from_file_unknown_format() {
+        let path = temp_path("unknown.pcap");
+        fs::write(&path, [0u8; 24]).unwrap();
+
+        let result = Capture::from_file(&path);
+        fs::remove_file(&path).unwrap();
+        assert_eq!(result.err().unwrap().kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn write_pcap() {
+        let mut writer = PcapWriter::new(Vec::new()).unwrap();
+        let frames = build_frames();
+        writer.write(&frames[0]).unwrap();
+        writer.write(&frames[2]).unwrap();
+        let buffer = writer.into_inner();
+
+        // Global header
+        let read_u32 = |offset: usize| {
+            u32::from_le_bytes([
+                buffer[offset],
+                buffer[offset + 1],
+                buffer[offset + 2],
+                buffer[offset + 3],
+            ])
+        };
+        assert_eq!(read_u32(0), PCAP_MAGIC);
+        assert_eq!(read_u32(20), LINKTYPE_ETHERNET);
+
+        // Records
+        let mut offset = 24;
+        let mut last_timestamp = (0, 0);
+        for frame in [&frames[0], &frames[2]] {
+            let timestamp = (read_u32(offset), read_u32(offset + 4));
+            assert!(timestamp >= last_timestamp);
+            last_timestamp = timestamp;
+            assert_eq!(read_u32(offset + 8) as usize, frame.len());
+            assert_eq!(read_u32(offset + 12) as usize, frame.len());
+            offset += 16;
+            assert_eq!(buffer[offset..offset + frame.len()], frame[..]);
+            offset += frame.len();
+        }
+        assert_eq!(offset, buffer.len());
+    }
+
+    /// Reads all frames with their timestamps from the file of the given path.
+    fn read_timestamped(path: &Path) -> Vec<Timestamped<Vec<Layers>>> {
+        let mut capture = Capture::from_file(path).unwrap();
+        let mut frames = Vec::new();
+        loop {
+            match capture.next_timestamped() {
+                Ok(frame) => frames.push(frame),
+                Err(e) => {
+                    assert_eq!(e.kind(), io::ErrorKind::UnexpectedEof);
+                    break;
+                }
+            }
+        }
+
+        frames
+    }
+
+    #[test]
+    fn replay_nanosecond_timestamps() {
+        let mut buffer = Vec::new();
+        buffer.extend_from_slice(&PCAP_NANO_MAGIC.to_le_bytes());
+        buffer.extend_from_slice(&[0x02, 0x00, 0x04, 0x00]);
+        buffer.extend_from_slice(&[0x00; 8]);
+        buffer.extend_from_slice(&PCAP_SNAPLEN.to_le_bytes());
+        buffer.extend_from_slice(&LINKTYPE_ETHERNET.to_le_bytes());
+        let frames = build_frames();
+        let timestamps = [(1u32, 999_999_999u32), (2, 1), (2, 500)];
+        for (frame, (seconds, nanoseconds)) in frames.iter().zip(timestamps.iter()) {
+            buffer.extend_from_slice(&seconds.to_le_bytes());
+            buffer.extend_from_slice(&nanoseconds.to_le_bytes());
+            buffer.extend_from_slice(&(frame.len() as u32).to_le_bytes());
+            buffer.extend_from_slice(&(frame.len() as u32).to_le_bytes());
+            buffer.extend_from_slice(frame);
+        }
+        let path = temp_path("replay-nanosecond.pcap");
+        fs::write(&path, &buffer).unwrap();
+
+        let replayed = read_timestamped(&path);
+        fs::remove_file(&path).unwrap();
+        let replayed_timestamps: Vec<Duration> =
+            replayed.iter().map(|frame| frame.get_timestamp()).collect();
+        assert_eq!(
+            replayed_timestamps,
+            [
+                Duration::new(1, 999_999_999),
+                Duration::new(2, 1),
+                Duration::new(2, 500)
+            ]
+        );
+        assert!(replayed
+            .iter()
+            .all(|frame| frame.get_precision() == Precision::Nanosecond));
+        assert_eq!(replayed[0].get_value().len(), 3);
+        assert!(matches!(replayed[2].get_value()[1], Layers::Arp(_)));
+    }
+
+    #[test]
+    fn replay_monotonic_timestamps() {
+        let path = temp_path("replay-monotonic.pcap");
+        let mut writer = PcapWriter::create(&path).unwrap();
+        for frame in build_frames() {
+            writer.write(&frame).unwrap();
+        }
+        drop(writer);
+
+        let replayed = read_timestamped(&path);
+        fs::remove_file(&path).unwrap();
+        assert_eq!(replayed.len(), 3);
+        assert!(replayed
+            .windows(2)
+            .all(|frames| frames[0].get_timestamp() <= frames[1].get_timestamp()));
+        assert!(replayed
+            .iter()
+            .all(|frame| frame.get_precision() == Precision::Microsecond
+                && frame.get_timestamp().subsec_nanos() % 1000 == 0));
+    }
+
+    #[test]
+    fn write_snaplen() {
+        let frames = build_frames();
+        let mut writer = PcapWriter::with_snaplen(Vec::new(), 40).unwrap();
+        assert_eq!(writer.get_snaplen(), 40);
+        writer.write(&frames[0]).unwrap();
+        let buffer = writer.into_inner();
+        assert_eq!(buffer[16..20], 40u32.to_le_bytes());
+        // The captured length is limited, but not the original length
+        assert_eq!(buffer[32..36], 40u32.to_le_bytes());
+        assert_eq!(buffer[36..40], (frames[0].len() as u32).to_le_bytes());
+        assert_eq!(buffer.len(), 24 + 16 + 40);
+
+        // The snaplen is limited by the default
+        let writer = PcapWriter::with_snaplen(Vec::new(), u32::MAX).unwrap();
+        assert_eq!(writer.get_snaplen(), PCAP_SNAPLEN);
+    }
+
+    #[test]
+    fn replay_truncated() {
+        let path = temp_path("truncated.pcap");
+        let frames = build_frames();
+        let mut writer = PcapWriter::create_with_snaplen(&path, 40).unwrap();
+        writer.write(&frames[0]).unwrap();
+        writer.write(&frames[2][..40]).unwrap();
+        drop(writer);
+
+        let mut capture = Capture::from_file(&path).unwrap();
+        let frame = capture.next().unwrap().to_vec();
+        assert_eq!(frame, frames[0][..40]);
+        assert!(capture.is_truncated());
+        assert_eq!(capture.get_original_length(), frames[0].len());
+        capture.next().unwrap();
+        assert!(!capture.is_truncated());
+        assert_eq!(capture.get_original_length(), 40);
+        drop(capture);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn replay_snaplen() {
+        let path = temp_path("replay-snaplen.pcap");
+        let frames = build_frames();
+        let mut writer = PcapWriter::create(&path).unwrap();
+        writer.write(&frames[0]).unwrap();
+        drop(writer);
+
+        let mut capture = Capture::from_file(&path).unwrap();
+        capture.set_snaplen(44);
+        let frame = capture.next().unwrap().to_vec();
+        assert_eq!(frame, frames[0][..44]);
+        assert_eq!(capture.get_captured_length(), 44);
+        assert_eq!(capture.get_original_length(), frames[0].len());
+        assert!(capture.is_truncated());
+        drop(capture);
+        fs::remove_file(&path).unwrap();
+
+        // The frame is cut below its IPv4 total length after the UDP header
+        let indicator = Indicator::from(&frame).unwrap();
+        assert!(indicator.get_ipv4().is_some());
+        assert_eq!(
+            indicator.get_error(),
+            Some(ParseError::Truncated(LayerTypes::Ipv4))
+        );
+    }
+}
@@ -0,0 +1,717 @@
+use super::file::NullSender;
+use super::Sender;
+use std::collections::VecDeque;
+use std::fmt::{self, Display, Formatter};
+use std::io;
+use std::str::FromStr;
+
+/// Trait for injecting frames into a link.
+pub trait Injector: Send {
+    /// Injects a frame into the link.
+    fn inject(&mut self, frame: &[u8]) -> io::Result<()>;
+
+    /// Injects the frames held by the injector, if any.
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Represents the backend injecting frames.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum InjectorKind {
+    /// Injects frames through the pcap channel of the interface.
+    Pcap,
+    /// Injects frames through a raw `AF_PACKET` socket bound to the interface, which is only
+    /// supported on Linux.
+    RawSocket,
+}
+
+impl Display for InjectorKind {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            InjectorKind::Pcap => write!(f, "pcap"),
+            InjectorKind::RawSocket => write!(f, "raw"),
+        }
+    }
+}
+
+impl FromStr for InjectorKind {
+    type Err = io::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "pcap" => Ok(InjectorKind::Pcap),
+            "raw" => Ok(InjectorKind::RawSocket),
+            _ => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "unknown injector",
+            )),
+        }
+    }
+}
+
+/// Represents an injector sending frames through a pcap channel.
+pub struct PcapInjector {
+    tx: Sender,
+}
+
+impl PcapInjector {
+    /// Creates a new `PcapInjector`.
+    pub fn new(tx: Sender) -> PcapInjector {
+        PcapInjector { tx }
+    }
+}
+
+impl Injector for PcapInjector {
+    fn inject(&mut self, frame: &[u8]) -> io::Result<()> {
+        self.tx.send_to(frame, None).unwrap_or(Ok(()))
+    }
+}
+
+impl Injector for NullSender {
+    fn inject(&mut self, _: &[u8]) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Represents an injector sending frames through a raw `AF_PACKET` socket.
+#[derive(Debug)]
+pub struct RawSocketInjector {
+    fd: i32,
+}
+
+impl RawSocketInjector {
+    /// Opens a raw socket bound to the interface of the given name. The socket only sends
+    /// frames, and receives nothing.
+    #[cfg(target_os = "linux")]
+    pub fn open(name: &str) -> io::Result<RawSocketInjector> {
+        use std::ffi::CString;
+        use std::mem;
+
+        let c_name = CString::new(name)
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "invalid interface name"))?;
+        let index = unsafe { libc::if_nametoindex(c_name.as_ptr()) };
+        if index == 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        // Protocol 0 receives no frames
+        let fd = unsafe { libc::socket(libc::AF_PACKET, libc::SOCK_RAW, 0) };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        // The socket is closed when the injector is dropped
+        let injector = RawSocketInjector { fd };
+
+        let mut addr: libc::sockaddr_ll = unsafe { mem::zeroed() };
+        addr.sll_family = libc::AF_PACKET as libc::c_ushort;
+        addr.sll_ifindex = index as libc::c_int;
+        let ret = unsafe {
+            libc::bind(
+                fd,
+                &addr as *const libc::sockaddr_ll as *const libc::sockaddr,
+                mem::size_of::<libc::sockaddr_ll>() as libc::socklen_t,
+            )
+        };
+        if ret < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(injector)
+    }
+
+    /// Opens a raw socket bound to the interface of the given name, which is not supported on
+    /// this platform.
+    #[cfg(not(target_os = "linux"))]
+    pub fn open(_: &str) -> io::Result<RawSocketInjector> {
+        Err(io::Error::new(
+            io::ErrorKind::Other,
+            "raw socket injection not supported",
+        ))
+    }
+}
+
+impl Injector for RawSocketInjector {
+    #[cfg(target_os = "linux")]
+    fn inject(&mut self, frame: &[u8]) -> io::Result<()> {
+        let ret = unsafe {
+            libc::send(
+                self.fd,
+                frame.as_ptr() as *const libc::c_void,
+                frame.len(),
+                0,
+            )
+        };
+        if ret < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(())
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    fn inject(&mut self, _: &[u8]) -> io::Result<()> {
+        Err(io::Error::new(
+            io::ErrorKind::Other,
+            "raw socket injection not supported",
+        ))
+    }
+}
+
+#[cfg(target_os = "linux")]
+impl Drop for RawSocketInjector {
+    fn drop(&mut self) {
+        unsafe {
+            libc::close(self.fd);
+        }
+    }
+}
+
+/// Represents the default capacity of each class of an `EgressQueue` in frames.
+pub const DEFAULT_EGRESS_QUEUE_CAPACITY: usize = 1024;
+/// Represents the bytes a class of weight 1 may send in each round of an `EgressQueue`.
+const EGRESS_QUEUE_QUANTUM: usize = 1514;
+/// Represents the max size of a frame classified as high priority without a DSCP.
+const SMALL_FRAME_SIZE: usize = 128;
+
+/// Represents the priority class of a frame in an `EgressQueue`.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum Class {
+    /// Represents interactive or control traffic.
+    High,
+    /// Represents the default traffic.
+    Normal,
+    /// Represents bulk traffic.
+    Low,
+}
+
+impl Class {
+    /// Classifies the given Ethernet frame. IPv4 and IPv6 frames are classified by the DSCP of
+    /// their traffic class if it is set, where EF and CS5 and above are high priority and CS1 is
+    /// low priority. Otherwise, small frames, e.g., bare ACKs, are high priority.
+    pub fn classify(frame: &[u8]) -> Class {
+        if let Some(dscp) = get_dscp(frame) {
+            if dscp >= 40 {
+                return Class::High;
+            } else if dscp == 8 {
+                return Class::Low;
+            }
+        }
+
+        match frame.len() <= SMALL_FRAME_SIZE {
+            true => Class::High,
+            false => Class::Normal,
+        }
+    }
+
+    fn index(&self) -> usize {
+        match self {
+            Class::High => 0,
+            Class::Normal => 1,
+            Class::Low => 2,
+        }
+    }
+}
+
+impl Display for Class {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            Class::High => write!(f, "high"),
+            Class::Normal => write!(f, "normal"),
+            Class::Low => write!(f, "low"),
+        }
+    }
+}
+
+/// Represents all the classes in the order of priority.
+const CLASSES: [Class; 3] = [Class::High, Class::Normal, Class::Low];
+
+/// Get the DSCP of the given Ethernet frame, which may carry VLAN tags.
+fn get_dscp(frame: &[u8]) -> Option<u8> {
+    let mut offset = 12;
+    let mut ethertype = (*frame.get(offset)? as u16) << 8 | *frame.get(offset + 1)? as u16;
+    while ethertype == 0x8100 || ethertype == 0x88a8 {
+        offset += 4;
+        ethertype = (*frame.get(offset)? as u16) << 8 | *frame.get(offset + 1)? as u16;
+    }
+    offset += 2;
+
+    match ethertype {
+        0x0800 => Some(*frame.get(offset + 1)? >> 2),
+        0x86dd => {
+            let traffic_class = (*frame.get(offset)? & 0x0f) << 4 | *frame.get(offset + 1)? >> 4;
+            Some(traffic_class >> 2)
+        }
+        _ => None,
+    }
+}
+
+/// Represents the weights of the classes of an `EgressQueue`.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct ClassWeights {
+    high: usize,
+    normal: usize,
+    low: usize,
+}
+
+impl ClassWeights {
+    /// Creates a `ClassWeights`. Weights of 0 are treated as 1, so no class starves.
+    pub fn new(high: usize, normal: usize, low: usize) -> ClassWeights {
+        ClassWeights {
+            high: high.max(1),
+            normal: normal.max(1),
+            low: low.max(1),
+        }
+    }
+
+    /// Get the weight of the given class.
+    pub fn get(&self, class: Class) -> usize {
+        match class {
+            Class::High => self.high,
+            Class::Normal => self.normal,
+            Class::Low => self.low,
+        }
+    }
+}
+
+impl Default for ClassWeights {
+    fn default() -> Self {
+        ClassWeights::new(4, 2, 1)
+    }
+}
+
+impl Display for ClassWeights {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "{},{},{}", self.high, self.normal, self.low)
+    }
+}
+
+impl FromStr for ClassWeights {
+    type Err = io::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let weights = s
+            .split(',')
+            .map(|weight| weight.trim().parse::<usize>())
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "invalid weight"))?;
+        match weights.as_slice() {
+            [high, normal, low] => Ok(ClassWeights::new(*high, *normal, *low)),
+            _ => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "expect weights of high, normal and low classes",
+            )),
+        }
+    }
+}
+
+/// Represents a queue of frames in priority classes. Frames are dequeued across the classes by
+/// deficit round robin, so each class gets a share of the bytes sent in proportion to its weight
+/// under contention.
+#[derive(Debug)]
+pub struct EgressQueue {
+    queues: [VecDeque<Vec<u8>>; 3],
+    deficits: [usize; 3],
+    weights: ClassWeights,
+    capacity: usize,
+    current: usize,
+    visited: bool,
+}
+
+impl EgressQueue {
+    /// Creates a new `EgressQueue` with the given weights and capacity of each class.
+    pub fn new(weights: ClassWeights, capacity: usize) -> EgressQueue {
+        EgressQueue {
+            queues: [VecDeque::new(), VecDeque::new(), VecDeque::new()],
+            deficits: [0; 3],
+            weights,
+            capacity,
+            current: 0,
+            visited: false,
+        }
+    }
+
+    /// Enqueues the given frame into its class. Returns `false` if the class is full and the
+    /// frame is dropped.
+    pub fn push(&mut self, frame: Vec<u8>) -> bool {
+        let class = Class::classify(&frame);
+
+        self.push_class(class, frame)
+    }
+
+    /// Enqueues the given frame into the given class. Returns `false` if the class is full and
+    /// the frame is dropped.
+    pub fn push_class(&mut self, class: Class, frame: Vec<u8>) -> bool {
+        let queue = &mut self.queues[class.index()];
+        if queue.len() >= self.capacity {
+            return false;
+        }
+        queue.push_back(frame);
+
+        true
+    }
+
+    /// Puts the given frame dequeued back to the head of its class. The next frame is dequeued
+    /// in a new round starting from the high priority class, so frames enqueued while the link is
+    /// congested do not wait for the turn of the class interrupted.
+    pub fn requeue(&mut self, class: Class, frame: Vec<u8>) {
+        let i = class.index();
+        self.deficits[i] += frame.len();
+        self.queues[i].push_front(frame);
+        self.current = 0;
+        self.visited = false;
+    }
+
+    /// Dequeues the next frame with its class. Returns `None` if the queue is empty.
+    pub fn pop(&mut self) -> Option<(Class, Vec<u8>)> {
+        if self.is_empty() {
+            return None;
+        }
+
+        loop {
+            let i = self.current;
+            match self.queues[i].front().map(|frame| frame.len()) {
+                Some(size) if size <= self.deficits[i] => {
+                    self.deficits[i] -= size;
+                    let frame = self.queues[i].pop_front().unwrap();
+                    if self.queues[i].is_empty() {
+                        self.deficits[i] = 0;
+                    }
+
+                    return Some((CLASSES[i], frame));
+                }
+                // Start the turn of the class
+                Some(_) if !self.visited => {
+                    self.deficits[i] += self.weights.get(CLASSES[i]) * EGRESS_QUEUE_QUANTUM;
+                    self.visited = true;
+                }
+                // End the turn of the class
+                _ => {
+                    if self.queues[i].is_empty() {
+                        self.deficits[i] = 0;
+                    }
+                    self.current = (i + 1) % CLASSES.len();
+                    self.visited = false;
+                }
+            }
+        }
+    }
+
+    /// Get the number of frames in the given class.
+    pub fn get_class_len(&self, class: Class) -> usize {
+        self.queues[class.index()].len()
+    }
+
+    /// Get the number of frames in the queue.
+    pub fn len(&self) -> usize {
+        self.queues.iter().map(|queue| queue.len()).sum()
+    }
+
+    /// Returns if the queue is empty.
+    pub fn is_empty(&self) -> bool {
+        self.queues.iter().all(|queue| queue.is_empty())
+    }
+}
+
+/// Represents an injector feeding another injector through an `EgressQueue`. Frames are held in
+/// the queue while the link is congested, and are injected in the order of the queue when the
+/// injector is flushed.
+pub struct QueuedInjector {
+    injector: Box<dyn Injector>,
+    queue: EgressQueue,
+}
+
+impl QueuedInjector {
+    /// Creates a new `QueuedInjector` with the given weights of the classes.
+    pub fn new(injector: Box<dyn Injector>, weights: ClassWeights) -> QueuedInjector {
+        QueuedInjector::with_capacity(injector, weights, DEFAULT_EGRESS_QUEUE_CAPACITY)
+    }
+
+    /// Creates a new `QueuedInjector` with the given weights and capacity of each class.
+    pub fn with_capacity(
+        injector: Box<dyn Injector>,
+        weights: ClassWeights,
+        capacity: usize,
+    ) -> QueuedInjector {
+        QueuedInjector {
+            injector,
+            queue: EgressQueue::new(weights, capacity),
+        }
+    }
+
+    /// Get the queue of the injector.
+    pub fn get_queue(&self) -> &EgressQueue {
+        &self.queue
+    }
+}
+
+/// Returns if the given error indicates the link is congested and the frame may be sent later.
+fn is_congested(e: &io::Error) -> bool {
+    if e.kind() == io::ErrorKind::WouldBlock {
+        return true;
+    }
+    #[cfg(unix)]
+    if e.raw_os_error() == Some(libc::ENOBUFS) {
+        return true;
+    }
+
+    false
+}
+
+impl Injector for QueuedInjector {
+    fn inject(&mut self, frame: &[u8]) -> io::Result<()> {
+        if !self.queue.push(frame.to_vec()) {
+            return Err(io::Error::new(
+                io::ErrorKind::WouldBlock,
+                "egress queue full",
+            ));
+        }
+
+        self.flush()
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        while let Some((class, frame)) = self.queue.pop() {
+            if let Err(e) = self.injector.inject(&frame) {
+                if is_congested(&e) {
+                    self.queue.requeue(class, frame);
+                    return Ok(());
+                }
+                return Err(e);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+
+    /// Represents an injector keeping the frames injected.
+    #[derive(Default)]
+    struct MockInjector {
+        frames: Vec<Vec<u8>>,
+    }
+
+    impl Injector for MockInjector {
+        fn inject(&mut self, frame: &[u8]) -> io::Result<()> {
+            self.frames.push(frame.to_vec());
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn injector_kind_from_str() {
+        assert_eq!("pcap".parse::<InjectorKind>().unwrap(), InjectorKind::Pcap);
+        assert_eq!(
+            "RAW".parse::<InjectorKind>().unwrap(),
+            InjectorKind::RawSocket
+        );
+        assert_eq!(
+            "tun".parse::<InjectorKind>().unwrap_err().kind(),
+            io::ErrorKind::InvalidInput
+        );
+
+        // Displayed kinds are parsed back
+        for kind in [InjectorKind::Pcap, InjectorKind::RawSocket] {
+            assert_eq!(kind.to_string().parse::<InjectorKind>().unwrap(), kind);
+        }
+    }
+
+    #[test]
+    fn inject_mock() {
+        let mut injector = MockInjector::default();
+        injector.inject(b"first").unwrap();
+        injector.inject(b"second").unwrap();
+        // Flushing an injector holding no frames does nothing
+        injector.flush().unwrap();
+
+        assert_eq!(injector.frames, vec![b"first".to_vec(), b"second".to_vec()]);
+    }
+
+    #[test]
+    fn inject_boxed() {
+        let mut injector: Box<dyn Injector> = Box::new(NullSender);
+        injector.inject(b"frame").unwrap();
+        injector.flush().unwrap();
+    }
+
+    #[test]
+    fn raw_socket_open_unknown_interface() {
+        assert!(RawSocketInjector::open("does-not-exist0").is_err());
+        assert!(RawSocketInjector::open("invalid\0name").is_err());
+    }
+
+    /// Builds an IPv4 frame of the given DSCP and size.
+    fn build_frame(dscp: u8, size: usize) -> Vec<u8> {
+        let mut frame = vec![0u8; size];
+        frame[12..14].copy_from_slice(&[0x08, 0x00]);
+        frame[14] = 0x45;
+        frame[15] = dscp << 2;
+
+        frame
+    }
+
+    /// Represents an injector which is congested for the given number of frames.
+    #[derive(Default)]
+    struct CongestedInjector {
+        congested: usize,
+        frames: Arc<Mutex<Vec<Vec<u8>>>>,
+    }
+
+    impl Injector for CongestedInjector {
+        fn inject(&mut self, frame: &[u8]) -> io::Result<()> {
+            if self.congested > 0 {
+                self.congested -= 1;
+                return Err(io::Error::from(io::ErrorKind::WouldBlock));
+            }
+            self.frames.lock().unwrap().push(frame.to_vec());
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn classify() {
+        // By DSCP
+        assert_eq!(Class::classify(&build_frame(46, 1500)), Class::High);
+        assert_eq!(Class::classify(&build_frame(40, 1500)), Class::High);
+        assert_eq!(Class::classify(&build_frame(8, 64)), Class::Low);
+        // By size
+        assert_eq!(Class::classify(&build_frame(0, 64)), Class::High);
+        assert_eq!(Class::classify(&build_frame(0, 1500)), Class::Normal);
+        assert_eq!(Class::classify(&build_frame(10, 1500)), Class::Normal);
+
+        // VLAN tagged
+        let mut frame = vec![0u8; 1500];
+        frame[12..14].copy_from_slice(&[0x81, 0x00]);
+        frame[16..18].copy_from_slice(&[0x08, 0x00]);
+        frame[19] = 46 << 2;
+        assert_eq!(Class::classify(&frame), Class::High);
+
+        // QinQ tagged
+        let mut frame = vec![0u8; 1500];
+        frame[12..14].copy_from_slice(&[0x88, 0xa8]);
+        frame[16..18].copy_from_slice(&[0x81, 0x00]);
+        frame[20..22].copy_from_slice(&[0x08, 0x00]);
+        frame[23] = 8 << 2;
+        assert_eq!(Class::classify(&frame), Class::Low);
+
+        // IPv6 of the traffic class of CS1
+        let mut frame = vec![0u8; 1500];
+        frame[12..14].copy_from_slice(&[0x86, 0xdd]);
+        frame[14] = 0x60 | (8 << 2) >> 4;
+        frame[15] = (8 << 2) << 4;
+        assert_eq!(Class::classify(&frame), Class::Low);
+    }
+
+    #[test]
+    fn class_weights_from_str() {
+        let weights = "8, 2, 1".parse::<ClassWeights>().unwrap();
+        assert_eq!(weights.get(Class::High), 8);
+        assert_eq!(weights.get(Class::Normal), 2);
+        assert_eq!(weights.get(Class::Low), 1);
+        assert_eq!(weights.to_string(), "8,2,1");
+        // Weights of 0 are treated as 1
+        assert_eq!("0,0,0".parse::<ClassWeights>().unwrap().get(Class::Low), 1);
+
+        assert!("1,2".parse::<ClassWeights>().is_err());
+        assert!("a,b,c".parse::<ClassWeights>().is_err());
+        assert_eq!(ClassWeights::default().to_string(), "4,2,1");
+    }
+
+    #[test]
+    fn egress_queue_priority() {
+        let mut queue = EgressQueue::new(ClassWeights::default(), DEFAULT_EGRESS_QUEUE_CAPACITY);
+        for _ in 0..10 {
+            assert!(queue.push(build_frame(0, 1500)));
+            assert!(queue.push(build_frame(0, 64)));
+        }
+        assert_eq!(queue.len(), 20);
+        assert_eq!(queue.get_class_len(Class::High), 10);
+        assert_eq!(queue.get_class_len(Class::Normal), 10);
+
+        // The small frames are dequeued ahead of the bulk
+        for _ in 0..10 {
+            let (class, frame) = queue.pop().unwrap();
+            assert_eq!(class, Class::High);
+            assert_eq!(frame.len(), 64);
+        }
+        for _ in 0..10 {
+            assert_eq!(queue.pop().unwrap().0, Class::Normal);
+        }
+        assert!(queue.pop().is_none());
+        assert!(queue.is_empty());
+    }
+
+    #[test]
+    fn egress_queue_weighted_share() {
+        let mut queue = EgressQueue::new(ClassWeights::new(4, 2, 1), DEFAULT_EGRESS_QUEUE_CAPACITY);
+        for _ in 0..100 {
+            queue.push_class(Class::High, build_frame(46, 1000));
+            queue.push_class(Class::Normal, build_frame(0, 1000));
+            queue.push_class(Class::Low, build_frame(8, 1000));
+        }
+
+        // Under contention, the bytes sent are in proportion to the weights
+        let mut bytes = [0usize; 3];
+        for _ in 0..70 {
+            let (class, frame) = queue.pop().unwrap();
+            bytes[class.index()] += frame.len();
+        }
+        assert!(bytes[0] > bytes[1] && bytes[1] > bytes[2] && bytes[2] > 0);
+        let ratio = bytes[0] as f64 / bytes[2] as f64;
+        assert!((3.0..=5.0).contains(&ratio), "{}", ratio);
+    }
+
+    #[test]
+    fn egress_queue_capacity() {
+        let mut queue = EgressQueue::new(ClassWeights::default(), 2);
+        assert!(queue.push(build_frame(0, 1500)));
+        assert!(queue.push(build_frame(0, 1500)));
+        assert!(!queue.push(build_frame(0, 1500)));
+        // Other classes are not affected
+        assert!(queue.push(build_frame(0, 64)));
+        assert_eq!(queue.len(), 3);
+    }
+
+    #[test]
+    fn queued_injector_congested() {
+        let frames = Arc::new(Mutex::new(Vec::new()));
+        let injector = CongestedInjector {
+            congested: 1,
+            frames: Arc::clone(&frames),
+        };
+        let mut injector = QueuedInjector::new(Box::new(injector), ClassWeights::default());
+
+        // The frame is held while the link is congested
+        injector.inject(&build_frame(0, 1500)).unwrap();
+        assert_eq!(injector.get_queue().len(), 1);
+        assert!(frames.lock().unwrap().is_empty());
+
+        // The small frame overtakes the bulk held
+        injector.inject(&build_frame(0, 64)).unwrap();
+        assert!(injector.get_queue().is_empty());
+        let sizes: Vec<usize> = frames.lock().unwrap().iter().map(|f| f.len()).collect();
+        assert_eq!(sizes, [64, 1500]);
+    }
+
+    #[test]
+    fn queued_injector_full() {
+        let injector = CongestedInjector {
+            congested: usize::MAX,
+            frames: Arc::new(Mutex::new(Vec::new())),
+        };
+        let mut injector =
+            QueuedInjector::with_capacity(Box::new(injector), ClassWeights::default(), 1);
+
+        injector.inject(&build_frame(0, 1500)).unwrap();
+        assert_eq!(
+            injector.inject(&build_frame(0, 1500)).unwrap_err().kind(),
+            io::ErrorKind::WouldBlock
+        );
+        assert_eq!(injector.get_queue().len(), 1);
+    }
+}
@@ -1,8 +1,17 @@
+use crate::packet::LinkType;
 use pnet::datalink::{self, Channel, Config, DataLinkReceiver, DataLinkSender, MacAddr};
 use std::clone::Clone;
 use std::fmt::{self, Display, Formatter};
+#[cfg(target_os = "linux")]
+use std::fs;
 use std::io;
-use std::net::Ipv4Addr;
+use std::net::{IpAddr, Ipv4Addr};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+pub mod file;
+pub mod inject;
+#[cfg(feature = "async")]
+pub mod stream;
 
 pub type HardwareAddr = pnet::datalink::MacAddr;
 
@@ -11,8 +20,149 @@ pub const HARDWARE_ADDR_UNSPECIFIED: HardwareAddr = pnet::datalink::MacAddr(0, 0
 pub type Sender = Box<dyn DataLinkSender>;
 pub type Receiver = Box<dyn DataLinkReceiver>;
 
-/// Represents the buffer size of pcap channels.
-const BUFFER_SIZE: usize = 256 * 1024;
+/// Represents the default buffer size of pcap channels.
+pub const DEFAULT_BUFFER_SIZE: usize = 256 * 1024;
+/// Represents the max read buffer size of pcap channels in immediate mode, which holds a few
+/// jumbo frames.
+const IMMEDIATE_BUFFER_SIZE: usize = 64 * 1024;
+/// Represents the default snaplen of pcap channels, which captures frames entirely.
+pub const DEFAULT_SNAPLEN: usize = 65535;
+
+/// Represents the ARP hardware type of Ethernet devices in Linux.
+#[cfg(target_os = "linux")]
+const ARPHRD_ETHER: u16 = 1;
+/// Represents the ARP hardware type of loopback devices in Linux, which carry Ethernet headers.
+#[cfg(target_os = "linux")]
+const ARPHRD_LOOPBACK: u16 = 772;
+
+/// Represents the options of opening a network interface.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct CaptureOptions {
+    /// Represents if frames are delivered as soon as they arrive. The pcap backends already turn
+    /// on immediate delivery where the platform supports it, e.g., `BIOCIMMEDIATE` on BPF, and the
+    /// immediate mode further caps the read buffer so that a read never returns a large batch
+    /// of frames. A smaller buffer lowers the latency, but frames may be dropped by the kernel
+    /// in bursts.
+    pub immediate: bool,
+    /// Represents the size of the read and write buffer in bytes.
+    pub buffer_size: usize,
+    /// Represents the max bytes captured of each frame. The bytes beyond are truncated, and the
+    /// length of a frame received is its captured length.
+    pub snaplen: usize,
+}
+
+impl CaptureOptions {
+    /// Creates a new `CaptureOptions`.
+    pub fn new(immediate: bool, buffer_size: usize, snaplen: usize) -> CaptureOptions {
+        CaptureOptions {
+            immediate,
+            buffer_size,
+            snaplen,
+        }
+    }
+
+    /// Get the datalink configuration of the options.
+    pub fn get_config(&self) -> Config {
+        let read_buffer_size = match self.immediate {
+            true => self.buffer_size.min(IMMEDIATE_BUFFER_SIZE),
+            false => self.buffer_size,
+        };
+
+        let mut config = Config::default();
+        config.write_buffer_size = self.buffer_size;
+        config.read_buffer_size = read_buffer_size;
+
+        config
+    }
+}
+
+impl Default for CaptureOptions {
+    fn default() -> CaptureOptions {
+        CaptureOptions::new(false, DEFAULT_BUFFER_SIZE, DEFAULT_SNAPLEN)
+    }
+}
+
+/// Represents the precision of capture timestamps.
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub enum Precision {
+    Microsecond,
+    Nanosecond,
+}
+
+impl Display for Precision {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            Precision::Microsecond => write!(f, "us"),
+            Precision::Nanosecond => write!(f, "ns"),
+        }
+    }
+}
+
+/// Represents a value with the time it is captured, e.g., the layers of a frame.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct Timestamped<T> {
+    timestamp: Duration,
+    precision: Precision,
+    value: T,
+}
+
+impl<T> Timestamped<T> {
+    /// Creates a new `Timestamped` with the given timestamp since the Unix epoch. The timestamp
+    /// is truncated to the precision.
+    pub fn new(value: T, timestamp: Duration, precision: Precision) -> Timestamped<T> {
+        let timestamp = match precision {
+            Precision::Microsecond => {
+                Duration::new(timestamp.as_secs(), timestamp.subsec_micros() * 1000)
+            }
+            Precision::Nanosecond => timestamp,
+        };
+
+        Timestamped {
+            timestamp,
+            precision,
+            value,
+        }
+    }
+
+    /// Creates a new `Timestamped` with the current time in microseconds. This is used when the
+    /// capture backend does not expose the timestamps of frames.
+    pub fn now(value: T) -> Timestamped<T> {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_else(|_| Duration::from_secs(0));
+
+        Timestamped::new(value, now, Precision::Microsecond)
+    }
+
+    /// Get the timestamp since the Unix epoch.
+    pub fn get_timestamp(&self) -> Duration {
+        self.timestamp
+    }
+
+    /// Get the precision of the timestamp.
+    pub fn get_precision(&self) -> Precision {
+        self.precision
+    }
+
+    /// Get the value.
+    pub fn get_value(&self) -> &T {
+        &self.value
+    }
+
+    /// Consumes the `Timestamped` and returns the value.
+    pub fn into_value(self) -> T {
+        self.value
+    }
+
+    /// Maps the value with the given function, and keeps the timestamp.
+    pub fn map<U, F: FnOnce(T) -> U>(self, f: F) -> Timestamped<U> {
+        Timestamped {
+            timestamp: self.timestamp,
+            precision: self.precision,
+            value: f(self.value),
+        }
+    }
+}
 
 /// Represents a network interface and its associated addresses.
 #[derive(Clone, Debug, Eq, Hash, PartialEq)]
@@ -23,6 +173,8 @@ pub struct Interface {
     pub ip_addrs: Vec<Ipv4Addr>,
     pub is_up: bool,
     pub is_loopback: bool,
+    /// Represents the link type of frames captured on the interface.
+    pub link_type: LinkType,
 }
 
 impl Interface {
@@ -35,31 +187,74 @@ impl Interface {
             ip_addrs: vec![],
             is_up: false,
             is_loopback: false,
+            link_type: LinkType::Ethernet,
         }
     }
 
     // Opens the network interface for sending and receiving data.
     pub fn open(&self) -> io::Result<(Sender, Receiver)> {
+        self.open_with_options(&CaptureOptions::default())
+    }
+
+    // Opens the network interface for sending and receiving data with the given options.
+    pub fn open_with_options(&self, options: &CaptureOptions) -> io::Result<(Sender, Receiver)> {
         let inters = datalink::interfaces();
         let inter = inters
             .into_iter()
             .filter(|current_inter| current_inter.name == self.name)
             .next()
-            .ok_or(io::Error::new(
-                io::ErrorKind::NotFound,
-                "interface not found",
-            ))?;
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "interface not found"))?;
 
-        let mut config = Config::default();
-        config.write_buffer_size = BUFFER_SIZE;
-        config.read_buffer_size = BUFFER_SIZE;
-        let channel = datalink::channel(&inter, config)?;
-        let channel = match channel {
+        if link_type(&inter.name).is_none() {
+            return Err(io::Error::new(
+                io::ErrorKind::Other,
+                "unsupported link type",
+            ));
+        }
+
+        let channel = datalink::channel(&inter, options.get_config())?;
+        let (tx, rx) = match channel {
             Channel::Ethernet(tx, rx) => (tx, rx),
             _ => return Err(io::Error::new(io::ErrorKind::Other, "unknown link type")),
         };
 
-        Ok(channel)
+        // The datalink channels have no snaplen, frames are truncated when received
+        let rx = match options.snaplen {
+            snaplen if snaplen < DEFAULT_SNAPLEN => {
+                SnaplenReceiver::new(rx, snaplen).into_receiver()
+            }
+            _ => rx,
+        };
+
+        Ok((tx, rx))
+    }
+}
+
+/// Represents a receiver which truncates the frames of the underlying `Receiver` to the
+/// snaplen.
+pub struct SnaplenReceiver {
+    rx: Receiver,
+    snaplen: usize,
+}
+
+impl SnaplenReceiver {
+    /// Creates a new `SnaplenReceiver`.
+    pub fn new(rx: Receiver, snaplen: usize) -> SnaplenReceiver {
+        SnaplenReceiver { rx, snaplen }
+    }
+
+    /// Converts the `SnaplenReceiver` into a `Receiver`.
+    pub fn into_receiver(self) -> Receiver {
+        Box::new(self)
+    }
+}
+
+impl DataLinkReceiver for SnaplenReceiver {
+    fn next(&mut self) -> io::Result<&[u8]> {
+        let snaplen = self.snaplen;
+        let frame = self.rx.next()?;
+
+        Ok(&frame[..frame.len().min(snaplen)])
     }
 }
 
@@ -92,7 +287,59 @@ impl Display for Interface {
     }
 }
 
-/// Gets a list of available network interfaces for the current machine.
+/// Returns if the given IP address is a link-local address.
+fn is_link_local(ip_addr: &IpAddr) -> bool {
+    match ip_addr {
+        IpAddr::V4(ip_addr) => ip_addr.is_link_local(),
+        IpAddr::V6(ip_addr) => ip_addr.segments()[0] & 0xffc0 == 0xfe80,
+    }
+}
+
+/// Gets the link type of frames captured on the network interface of the given name from the ARP
+/// hardware type of the device. Returns `None` if the link type is not supported, e.g., frames of
+/// tun devices carry no link layer header.
+#[cfg(target_os = "linux")]
+pub fn link_type(name: &str) -> Option<LinkType> {
+    let hardware_type = match fs::read_to_string(format!("/sys/class/net/{}/type", name)) {
+        Ok(hardware_type) => hardware_type,
+        // The pcap backend captures Ethernet frames if the type is unknown
+        Err(_) => return Some(LinkType::Ethernet),
+    };
+
+    match hardware_type.trim().parse::<u16>() {
+        Ok(ARPHRD_ETHER) | Ok(ARPHRD_LOOPBACK) => Some(LinkType::Ethernet),
+        Ok(_) => None,
+        Err(_) => Some(LinkType::Ethernet),
+    }
+}
+
+/// Gets the link type of frames captured on the network interface of the given name. The pcap
+/// backends of other platforms only open Ethernet channels.
+#[cfg(not(target_os = "linux"))]
+pub fn link_type(_: &str) -> Option<LinkType> {
+    Some(LinkType::Ethernet)
+}
+
+/// Gets the hardware address and IP addresses of the network interface of the given name. The IP
+/// addresses are sorted by preference, where the first non-link-local address comes first.
+pub fn interface_addrs(name: &str) -> io::Result<(MacAddr, Vec<IpAddr>)> {
+    let inter = datalink::interfaces()
+        .into_iter()
+        .find(|inter| inter.name == name)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "interface not found"))?;
+    let hardware_addr = inter
+        .mac
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "hardware address not found"))?;
+
+    let mut ip_addrs: Vec<IpAddr> = inter.ips.iter().map(|ip| ip.ip()).collect();
+    // Prefer non-link-local addresses
+    ip_addrs.sort_by_key(is_link_local);
+
+    Ok((hardware_addr, ip_addrs))
+}
+
+/// Gets a list of available network interfaces for the current machine. The IPv4 addresses of
+/// interfaces are sorted by preference, where the first non-link-local address comes first.
 pub fn interfaces() -> Vec<Interface> {
     let inters = datalink::interfaces();
 
@@ -125,9 +372,16 @@ pub fn interfaces() -> Vec<Interface> {
             if i.ip_addrs.len() <= 0 {
                 return Err(());
             }
+            // Prefer non-link-local addresses
+            i.ip_addrs.sort_by_key(|ip_addr| ip_addr.is_link_local());
 
             i.is_up = inter.is_up();
             i.is_loopback = inter.is_loopback();
+            // Exclude interface of unsupported link type
+            i.link_type = match link_type(&inter.name) {
+                Some(link_type) => link_type,
+                None => return Err(()),
+            };
 
             Ok(i)
         })
@@ -136,3 +390,125 @@ pub fn interfaces() -> Vec<Interface> {
 
     ifs
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::Ipv6Addr;
+
+    #[test]
+    fn link_local() {
+        assert!(is_link_local(&IpAddr::V4(Ipv4Addr::new(169, 254, 1, 1))));
+        assert!(!is_link_local(&IpAddr::V4(Ipv4Addr::new(192, 168, 1, 1))));
+        assert!(is_link_local(&IpAddr::V6("fe80::1".parse().unwrap())));
+        assert!(is_link_local(&IpAddr::V6("febf::1".parse().unwrap())));
+        assert!(!is_link_local(&IpAddr::V6("fec0::1".parse().unwrap())));
+        assert!(!is_link_local(&IpAddr::V6(Ipv6Addr::LOCALHOST)));
+    }
+
+    #[test]
+    fn receive_snaplen() {
+        let path = std::env::temp_dir().join(format!(
+            "pcap2socks-{}-receive-snaplen.pcap",
+            std::process::id()
+        ));
+        let mut writer = file::PcapWriter::create(&path).unwrap();
+        writer.write(&[0x01; 60]).unwrap();
+        writer.write(&[0x02; 20]).unwrap();
+        drop(writer);
+
+        let rx = file::Capture::from_file(&path).unwrap().into_receiver();
+        let mut rx = SnaplenReceiver::new(rx, 40).into_receiver();
+        assert_eq!(rx.next().unwrap(), &[0x01; 40][..]);
+        assert_eq!(rx.next().unwrap(), &[0x02; 20][..]);
+        drop(rx);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn interface_addrs_loopback() {
+        // Skip if the machine has no loopback interface with an address
+        let inter = match datalink::interfaces()
+            .into_iter()
+            .find(|inter| inter.is_loopback() && inter.mac.is_some() && !inter.ips.is_empty())
+        {
+            Some(inter) => inter,
+            None => return,
+        };
+
+        let (hardware_addr, ip_addrs) = interface_addrs(&inter.name).unwrap();
+        assert_eq!(hardware_addr.to_string().split(':').count(), 6);
+        assert_eq!(ip_addrs.len(), inter.ips.len());
+        assert!(ip_addrs.iter().all(|ip_addr| ip_addr.is_loopback()));
+    }
+
+    #[test]
+    fn link_type_of_interfaces() {
+        // Interfaces of unsupported link types are excluded
+        assert!(interfaces()
+            .iter()
+            .all(|inter| link_type(&inter.name) == Some(inter.link_type)));
+        assert!(interfaces()
+            .iter()
+            .filter(|inter| inter.is_loopback)
+            .all(|inter| inter.link_type == LinkType::Ethernet));
+    }
+
+    #[test]
+    fn interface_addrs_not_found() {
+        let e = interface_addrs("does-not-exist0").unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::NotFound);
+    }
+
+    #[test]
+    fn capture_options_config() {
+        let config = CaptureOptions::default().get_config();
+        assert_eq!(config.read_buffer_size, DEFAULT_BUFFER_SIZE);
+        assert_eq!(config.write_buffer_size, DEFAULT_BUFFER_SIZE);
+
+        // The read buffer is capped in immediate mode
+        let options = CaptureOptions::new(true, DEFAULT_BUFFER_SIZE, DEFAULT_SNAPLEN);
+        assert!(options.immediate);
+        let config = options.get_config();
+        assert_eq!(config.read_buffer_size, IMMEDIATE_BUFFER_SIZE);
+        assert_eq!(config.write_buffer_size, DEFAULT_BUFFER_SIZE);
+
+        // Smaller buffers are kept
+        let config = CaptureOptions::new(true, 4096, DEFAULT_SNAPLEN).get_config();
+        assert_eq!(config.read_buffer_size, 4096);
+        assert_eq!(config.write_buffer_size, 4096);
+        let config = CaptureOptions::new(false, 1024 * 1024, DEFAULT_SNAPLEN).get_config();
+        assert_eq!(config.read_buffer_size, 1024 * 1024);
+    }
+
+    #[test]
+    fn open_with_options_not_found() {
+        let mut inter = Interface::new();
+        inter.name = String::from("does-not-exist0");
+        let options = CaptureOptions::new(true, DEFAULT_BUFFER_SIZE, DEFAULT_SNAPLEN);
+        let e = inter.open_with_options(&options).err().unwrap();
+        assert_eq!(e.kind(), io::ErrorKind::NotFound);
+    }
+
+    #[test]
+    fn timestamped_precision() {
+        let timestamp = Duration::new(1, 123_456_789);
+        let timestamped = Timestamped::new("frame", timestamp, Precision::Microsecond);
+        assert_eq!(timestamped.get_timestamp(), Duration::new(1, 123_456_000));
+        let timestamped = Timestamped::new("frame", timestamp, Precision::Nanosecond);
+        assert_eq!(timestamped.get_timestamp(), timestamp);
+
+        // The timestamp is kept when mapped
+        let mapped = timestamped.map(|value| value.len());
+        assert_eq!(*mapped.get_value(), 5);
+        assert_eq!(mapped.get_timestamp(), timestamp);
+        assert_eq!(mapped.get_precision(), Precision::Nanosecond);
+        assert_eq!(mapped.into_value(), 5);
+
+        assert!(Precision::Microsecond < Precision::Nanosecond);
+        assert_eq!(Precision::Nanosecond.to_string(), "ns");
+        let now = Timestamped::now(());
+        assert_eq!(now.get_precision(), Precision::Microsecond);
+        assert!(now.get_timestamp() > Duration::from_secs(0));
+    }
+}
@@ -0,0 +1,162 @@
+use super::Receiver;
+use crate::packet::Indicator;
+use crate::pool::{BufferPool, ExhaustedPolicy, PooledBuffer};
+use crate::CAPTURE_BUFFER_SIZE;
+use futures::{Stream, StreamExt};
+use log::warn;
+use std::future::Future;
+use std::io;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use tokio::task::{self, JoinHandle};
+
+/// Represents the number of frames received by a `CaptureStream` which may be held at a time.
+const STREAM_POOL_CAPACITY: usize = 4;
+
+/// Represents a read of a frame running on the blocking thread pool, which returns the
+/// `Receiver` with the frame read.
+type PendingRead = JoinHandle<(Receiver, io::Result<PooledBuffer>)>;
+
+/// Represents an asynchronous stream of frames over a `Receiver`. Reading from the `Receiver`
+/// blocks, so it runs on the blocking thread pool of tokio. A frame is only read when the next
+/// frame is requested, which lets the pace of handling frames propagate back to the capture.
+///
+/// An error of kind `TimedOut` is yielded if there is no frame before the read timeout of the
+/// `Receiver`, and the stream can be polled again. The stream ends after any other error.
+pub struct CaptureStream {
+    rx: Option<Receiver>,
+    pool: BufferPool,
+    pending: Option<PendingRead>,
+}
+
+impl CaptureStream {
+    /// Creates a `CaptureStream` over the given `Receiver`.
+    pub fn new(rx: Receiver) -> CaptureStream {
+        CaptureStream {
+            rx: Some(rx),
+            pool: BufferPool::new(
+                CAPTURE_BUFFER_SIZE,
+                STREAM_POOL_CAPACITY,
+                ExhaustedPolicy::Block,
+            ),
+            pending: None,
+        }
+    }
+
+    /// Reads the next frame from the given `Receiver` on the blocking thread pool.
+    fn spawn_read(mut rx: Receiver, pool: BufferPool) -> PendingRead {
+        task::spawn_blocking(move || {
+            let result = loop {
+                match rx.next() {
+                    Ok(frame) => {
+                        if frame.len() > pool.get_size() {
+                            warn!("capture: frame of {} Bytes is too large", frame.len());
+                            continue;
+                        }
+                        // The policy of the pool is `Block`
+                        let mut buffer = pool.get().unwrap();
+                        buffer.truncate(frame.len());
+                        buffer.copy_from_slice(frame);
+                        break Ok(buffer);
+                    }
+                    Err(e) => break Err(e),
+                }
+            };
+            (rx, result)
+        })
+    }
+
+    /// Receives the next frame. An error of kind `TimedOut` is returned if there is no frame
+    /// before the read timeout of the `Receiver`, and the stream can be polled again. Returns
+    /// `None` if the stream is closed because of any other error. The frame is held in a pooled
+    /// buffer, receiving blocks while too many frames are held.
+    pub async fn next(&mut self) -> Option<io::Result<PooledBuffer>> {
+        StreamExt::next(self).await
+    }
+
+    /// Receives the next frame which can be parsed, and returns it as an `Indicator`. Frames
+    /// which cannot be parsed are skipped.
+    pub async fn next_indicator(&mut self) -> Option<io::Result<Indicator>> {
+        loop {
+            match self.next().await? {
+                Ok(frame) => {
+                    if let Some(indicator) = Indicator::from(&frame) {
+                        return Some(Ok(indicator));
+                    }
+                }
+                Err(e) => return Some(Err(e)),
+            }
+        }
+    }
+
+    /// Returns if the stream is closed.
+    pub fn is_closed(&self) -> bool {
+        self.rx.is_none() && self.pending.is_none()
+    }
+}
+
+impl Stream for CaptureStream {
+    type Item = io::Result<PooledBuffer>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
+        if self.pending.is_none() {
+            let rx = match self.rx.take() {
+                Some(rx) => rx,
+                None => return Poll::Ready(None),
+            };
+            self.pending = Some(CaptureStream::spawn_read(rx, self.pool.clone()));
+        }
+
+        let result = match Pin::new(self.pending.as_mut().unwrap()).poll(cx) {
+            Poll::Ready(result) => result,
+            Poll::Pending => return Poll::Pending,
+        };
+        self.pending = None;
+
+        let item = match result {
+            Ok((rx, Ok(frame))) => {
+                self.rx = Some(rx);
+                Ok(frame)
+            }
+            Ok((rx, Err(e))) => {
+                if e.kind() == io::ErrorKind::TimedOut {
+                    self.rx = Some(rx);
+                }
+                Err(e)
+            }
+            Err(e) => Err(io::Error::new(io::ErrorKind::Other, e)),
+        };
+
+        Poll::Ready(Some(item))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pcap::file::{Capture, PcapWriter};
+    use std::fs;
+
+    #[tokio::test]
+    async fn collect_frames() {
+        let path = std::env::temp_dir().join(format!(
+            "pcap2socks-{}-capture-stream.pcap",
+            std::process::id()
+        ));
+        let mut writer = PcapWriter::create(&path).unwrap();
+        writer.write(&[0x01; 60]).unwrap();
+        writer.write(&[0x02; 64]).unwrap();
+        drop(writer);
+
+        let stream = CaptureStream::new(Capture::from_file(&path).unwrap().into_receiver());
+        let items: Vec<io::Result<PooledBuffer>> = stream.collect().await;
+        fs::remove_file(&path).unwrap();
+
+        // The stream ends after the end of the capture file
+        assert_eq!(items.len(), 3);
+        assert_eq!(&items[0].as_ref().unwrap()[..], &[0x01; 60][..]);
+        assert_eq!(&items[1].as_ref().unwrap()[..], &[0x02; 64][..]);
+        let e = items[2].as_ref().unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::UnexpectedEof);
+    }
+}
@@ -0,0 +1,206 @@
+use std::io;
+use std::ops::{Deref, DerefMut};
+use std::str::FromStr;
+use std::sync::{Arc, Condvar, Mutex};
+
+/// Represents the policy of a `BufferPool` when all of its buffers are in use.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum ExhaustedPolicy {
+    /// Blocks until a buffer is recycled.
+    Block,
+    /// Returns no buffer, the caller should drop what it is going to write.
+    Drop,
+}
+
+impl FromStr for ExhaustedPolicy {
+    type Err = io::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "block" => Ok(ExhaustedPolicy::Block),
+            "drop" => Ok(ExhaustedPolicy::Drop),
+            _ => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "unknown exhausted policy",
+            )),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Buffers {
+    free: Vec<Vec<u8>>,
+    allocated: usize,
+}
+
+#[derive(Debug)]
+struct Inner {
+    buffers: Mutex<Buffers>,
+    recycled: Condvar,
+    size: usize,
+    capacity: usize,
+    policy: ExhaustedPolicy,
+}
+
+/// Represents a bounded pool of fixed-size buffers.
+#[derive(Clone, Debug)]
+pub struct BufferPool {
+    inner: Arc<Inner>,
+}
+
+impl BufferPool {
+    /// Creates a new `BufferPool` which hands out at most `capacity` buffers of `size` bytes.
+    pub fn new(size: usize, capacity: usize, policy: ExhaustedPolicy) -> BufferPool {
+        BufferPool {
+            inner: Arc::new(Inner {
+                buffers: Mutex::new(Buffers {
+                    free: Vec::with_capacity(capacity),
+                    allocated: 0,
+                }),
+                recycled: Condvar::new(),
+                size,
+                capacity,
+                policy,
+            }),
+        }
+    }
+
+    /// Get a zeroed buffer from the pool. The buffer is recycled into the pool when it is
+    /// dropped. Returns `None` if the pool is exhausted and its policy is `Drop`.
+    pub fn get(&self) -> Option<PooledBuffer> {
+        let mut buffers = self.inner.buffers.lock().unwrap();
+        loop {
+            if let Some(mut buffer) = buffers.free.pop() {
+                for b in buffer.iter_mut() {
+                    *b = 0;
+                }
+                return Some(PooledBuffer::new(buffer, Arc::clone(&self.inner)));
+            }
+            if buffers.allocated < self.inner.capacity {
+                buffers.allocated += 1;
+                let buffer = vec![0u8; self.inner.size];
+                return Some(PooledBuffer::new(buffer, Arc::clone(&self.inner)));
+            }
+
+            match self.inner.policy {
+                ExhaustedPolicy::Block => buffers = self.inner.recycled.wait(buffers).unwrap(),
+                ExhaustedPolicy::Drop => return None,
+            }
+        }
+    }
+
+    /// Get the size of buffers in the pool.
+    pub fn get_size(&self) -> usize {
+        self.inner.size
+    }
+
+    /// Get the number of buffers allocated by the pool.
+    pub fn get_allocated(&self) -> usize {
+        self.inner.buffers.lock().unwrap().allocated
+    }
+}
+
+/// Represents a buffer borrowed from a `BufferPool`.
+#[derive(Debug)]
+pub struct PooledBuffer {
+    buffer: Vec<u8>,
+    len: usize,
+    pool: Arc<Inner>,
+}
+
+impl PooledBuffer {
+    fn new(buffer: Vec<u8>, pool: Arc<Inner>) -> PooledBuffer {
+        let len = buffer.len();
+        PooledBuffer { buffer, len, pool }
+    }
+
+    /// Shortens the buffer to the given length. The buffer is restored to its full size when it
+    /// is recycled. Has no effect if `len` is greater than the current length.
+    pub fn truncate(&mut self, len: usize) {
+        if len < self.len {
+            self.len = len;
+        }
+    }
+}
+
+impl Deref for PooledBuffer {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.buffer[..self.len]
+    }
+}
+
+impl DerefMut for PooledBuffer {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        &mut self.buffer[..self.len]
+    }
+}
+
+impl Drop for PooledBuffer {
+    fn drop(&mut self) {
+        let buffer = std::mem::take(&mut self.buffer);
+        if let Ok(mut buffers) = self.pool.buffers.lock() {
+            buffers.free.push(buffer);
+            self.pool.recycled.notify_one();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn exhausted_drop() {
+        let pool = BufferPool::new(1514, 1, ExhaustedPolicy::Drop);
+        let buffer = pool.get().unwrap();
+        assert!(pool.get().is_none());
+
+        drop(buffer);
+        assert!(pool.get().is_some());
+        assert_eq!(pool.get_allocated(), 1);
+    }
+
+    #[test]
+    fn exhausted_block() {
+        let pool = BufferPool::new(1514, 1, ExhaustedPolicy::Block);
+        let buffer = pool.get().unwrap();
+
+        let handle = {
+            let pool = pool.clone();
+            std::thread::spawn(move || pool.get().is_some())
+        };
+        drop(buffer);
+        assert!(handle.join().unwrap());
+        assert_eq!(pool.get_allocated(), 1);
+    }
+
+    #[test]
+    fn recycle_zeroed() {
+        let pool = BufferPool::new(16, 1, ExhaustedPolicy::Drop);
+        let mut buffer = pool.get().unwrap();
+        buffer[0] = 0xff;
+        buffer.truncate(4);
+        assert_eq!(buffer.len(), 4);
+        drop(buffer);
+
+        // The buffer is restored to its full size
+        let buffer = pool.get().unwrap();
+        assert_eq!(buffer.len(), 16);
+        assert!(buffer.iter().all(|b| *b == 0));
+    }
+
+    #[test]
+    fn exhausted_policy_from_str() {
+        assert_eq!(
+            "block".parse::<ExhaustedPolicy>().unwrap(),
+            ExhaustedPolicy::Block
+        );
+        assert_eq!(
+            "Drop".parse::<ExhaustedPolicy>().unwrap(),
+            ExhaustedPolicy::Drop
+        );
+        assert!("wait".parse::<ExhaustedPolicy>().is_err());
+    }
+}
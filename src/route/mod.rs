@@ -0,0 +1,243 @@
+use ipnetwork::IpNetwork;
+use std::fmt::{self, Display, Formatter};
+use std::io;
+use std::net::IpAddr;
+use std::str::FromStr;
+
+/// Represents the action taken on traffic to a destination.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum Route {
+    /// Forwards the traffic through the proxy.
+    Proxy,
+    /// Connects to the destination directly without the proxy. Only TCP is connected directly,
+    /// UDP is still forwarded through the proxy.
+    Direct,
+    /// Drops the traffic.
+    Drop,
+}
+
+impl Display for Route {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            Route::Proxy => write!(f, "proxy"),
+            Route::Direct => write!(f, "direct"),
+            Route::Drop => write!(f, "drop"),
+        }
+    }
+}
+
+impl FromStr for Route {
+    type Err = io::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "proxy" => Ok(Route::Proxy),
+            "direct" => Ok(Route::Direct),
+            "drop" => Ok(Route::Drop),
+            _ => Err(io::Error::new(io::ErrorKind::InvalidInput, "unknown route")),
+        }
+    }
+}
+
+/// Represents a rule routing destinations in the network, in the form of `CIDR=ROUTE`, e.g.,
+/// `10.0.0.0/8=direct`.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct RouteRule {
+    pub network: IpNetwork,
+    pub route: Route,
+}
+
+impl Display for RouteRule {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "{}={}", self.network, self.route)
+    }
+}
+
+impl FromStr for RouteRule {
+    type Err = io::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.splitn(2, '=');
+        let network = parts.next().unwrap_or_default();
+        let route = parts
+            .next()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "missing route"))?;
+
+        let network = IpNetwork::from_str(network.trim())
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "invalid network"))?;
+        let route = Route::from_str(route.trim())?;
+
+        Ok(RouteRule { network, route })
+    }
+}
+
+/// Represents a routing table which decides the `Route` of destinations by the longest prefix
+/// match. Destinations matching no rules take the default route.
+#[derive(Clone, Debug)]
+pub struct RouteTable {
+    rules: Vec<RouteRule>,
+    default: Route,
+}
+
+impl RouteTable {
+    /// Creates a new `RouteTable` with the given default route.
+    pub fn new(default: Route) -> RouteTable {
+        RouteTable {
+            rules: Vec::new(),
+            default,
+        }
+    }
+
+    /// Inserts a rule into the table. An existing rule of the same network will be overwritten.
+    pub fn insert(&mut self, network: IpNetwork, route: Route) {
+        match self.rules.iter_mut().find(|rule| rule.network == network) {
+            Some(rule) => rule.route = route,
+            None => self.rules.push(RouteRule { network, route }),
+        }
+    }
+
+    /// Removes the rule of the given network.
+    pub fn remove(&mut self, network: IpNetwork) -> Option<Route> {
+        let index = self.rules.iter().position(|rule| rule.network == network)?;
+
+        Some(self.rules.remove(index).route)
+    }
+
+    /// Looks up the route of the given IP address by the longest prefix match. Returns the
+    /// default route if no rules match.
+    pub fn lookup(&self, ip_addr: IpAddr) -> Route {
+        self.rules
+            .iter()
+            .filter(|rule| rule.network.contains(ip_addr))
+            .max_by_key(|rule| rule.network.prefix())
+            .map(|rule| rule.route)
+            .unwrap_or(self.default)
+    }
+
+    /// Get the default route of the table.
+    pub fn get_default(&self) -> Route {
+        self.default
+    }
+
+    /// Sets the default route of the table.
+    pub fn set_default(&mut self, route: Route) {
+        self.default = route;
+    }
+
+    /// Get the rules of the table.
+    pub fn get_rules(&self) -> &[RouteRule] {
+        &self.rules
+    }
+
+    /// Get the number of rules in the table.
+    pub fn len(&self) -> usize {
+        self.rules.len()
+    }
+
+    /// Returns if the table contains no rules.
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    /// Returns if any destination is routed directly, either by a rule or by the default route.
+    pub fn has_direct(&self) -> bool {
+        self.default == Route::Direct || self.rules.iter().any(|rule| rule.route == Route::Direct)
+    }
+}
+
+impl Default for RouteTable {
+    fn default() -> Self {
+        RouteTable::new(Route::Proxy)
+    }
+}
+
+impl Extend<RouteRule> for RouteTable {
+    fn extend<T: IntoIterator<Item = RouteRule>>(&mut self, iter: T) {
+        for rule in iter {
+            self.insert(rule.network, rule.route);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Creates a `RouteTable` from the given rules.
+    fn build_table(default: Route, rules: &[&str]) -> RouteTable {
+        let mut table = RouteTable::new(default);
+        table.extend(rules.iter().map(|rule| RouteRule::from_str(rule).unwrap()));
+
+        table
+    }
+
+    #[test]
+    fn lookup_longest_match() {
+        let table = build_table(
+            Route::Proxy,
+            &[
+                "10.0.0.0/8=direct",
+                "10.1.0.0/16=drop",
+                "10.1.2.0/24=proxy",
+                "fd00::/8=direct",
+                "fd00:1::/32=drop",
+            ],
+        );
+
+        for (ip_addr, route) in [
+            ("10.0.0.1", Route::Direct),
+            ("10.1.0.1", Route::Drop),
+            ("10.1.2.1", Route::Proxy),
+            ("fd00::1", Route::Direct),
+            ("fd00:1::1", Route::Drop),
+        ] {
+            assert_eq!(table.lookup(ip_addr.parse().unwrap()), route, "{}", ip_addr);
+        }
+    }
+
+    #[test]
+    fn lookup_default() {
+        let table = build_table(Route::Drop, &["10.0.0.0/8=direct", "fd00::/8=proxy"]);
+
+        assert_eq!(table.lookup("192.168.1.1".parse().unwrap()), Route::Drop);
+        assert_eq!(table.lookup("2001:db8::1".parse().unwrap()), Route::Drop);
+        assert_eq!(
+            RouteTable::default().lookup("10.0.0.1".parse().unwrap()),
+            Route::Proxy
+        );
+    }
+
+    #[test]
+    fn insert_overwrite() {
+        let mut table = build_table(Route::Proxy, &["10.0.0.0/8=direct"]);
+        let network = IpNetwork::from_str("10.0.0.0/8").unwrap();
+        table.insert(network, Route::Drop);
+        assert_eq!(table.len(), 1);
+        assert_eq!(table.lookup("10.0.0.1".parse().unwrap()), Route::Drop);
+
+        assert_eq!(table.remove(network), Some(Route::Drop));
+        assert!(table.is_empty());
+    }
+
+    #[test]
+    fn has_direct() {
+        assert!(!build_table(Route::Proxy, &["10.0.0.0/8=drop"]).has_direct());
+        assert!(build_table(Route::Proxy, &["10.0.0.0/8=direct"]).has_direct());
+        assert!(build_table(Route::Direct, &[]).has_direct());
+    }
+
+    #[test]
+    fn parse_rule() {
+        let rule = RouteRule::from_str(" 10.0.0.0/8 = Direct ").unwrap();
+        assert_eq!(rule.to_string(), "10.0.0.0/8=direct");
+
+        for s in ["10.0.0.0/8", "10.0.0.0/33=proxy", "10.0.0.0/8=reject"] {
+            assert_eq!(
+                RouteRule::from_str(s).unwrap_err().kind(),
+                io::ErrorKind::InvalidInput,
+                "{}",
+                s
+            );
+        }
+    }
+}
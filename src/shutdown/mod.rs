@@ -0,0 +1,61 @@
+use std::io;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+/// Represents if a shutdown signal is received.
+static SIGNALED: AtomicBool = AtomicBool::new(false);
+
+/// Represents a coordinator of the graceful shutdown.
+#[derive(Clone, Debug, Default)]
+pub struct Shutdown {
+    triggered: Arc<AtomicBool>,
+}
+
+impl Shutdown {
+    /// Creates a new `Shutdown`.
+    pub fn new() -> Shutdown {
+        Shutdown {
+            triggered: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    /// Triggers the shutdown.
+    pub fn trigger(&self) {
+        self.triggered.store(true, Ordering::SeqCst);
+    }
+
+    /// Returns if the shutdown is triggered, or a shutdown signal is received.
+    pub fn is_triggered(&self) -> bool {
+        self.triggered.load(Ordering::SeqCst) || SIGNALED.load(Ordering::SeqCst)
+    }
+}
+
+#[cfg(unix)]
+extern "C" fn handle_signal(_: libc::c_int) {
+    // Exit immediately on the second signal
+    if SIGNALED.swap(true, Ordering::SeqCst) {
+        unsafe { libc::_exit(130) };
+    }
+}
+
+/// Sets the handler of SIGINT and SIGTERM, which triggers every `Shutdown`.
+#[cfg(unix)]
+pub fn set_signal_handler() -> io::Result<()> {
+    for signal in &[libc::SIGINT, libc::SIGTERM] {
+        let handler = handle_signal as extern "C" fn(libc::c_int) as libc::sighandler_t;
+        if unsafe { libc::signal(*signal, handler) } == libc::SIG_ERR {
+            return Err(io::Error::last_os_error());
+        }
+    }
+
+    Ok(())
+}
+
+/// Sets the handler of shutdown signals, which is not supported on this platform.
+#[cfg(not(unix))]
+pub fn set_signal_handler() -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Other,
+        "signal handler not supported",
+    ))
+}
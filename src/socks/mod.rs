@@ -1,23 +1,160 @@
 use log::{debug, trace, warn};
-use std::net::SocketAddrV4;
-use std::sync::atomic::{AtomicBool, AtomicU16, Ordering};
+use std::cmp::min;
+use std::collections::HashMap;
+use std::fmt::{self, Display, Formatter};
+use std::future;
+use std::net::{Ipv4Addr, SocketAddrV4};
+use std::pin::Pin;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::{Arc, Mutex};
-use std::time::Duration;
+use std::task::Poll;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 use tokio::io;
 use tokio::net::tcp::OwnedWriteHalf;
+use tokio::net::TcpStream;
 use tokio::prelude::*;
 use tokio::time;
 
 mod socks;
 use self::socks::SocksSendHalf;
+pub use self::socks::{
+    decode_socks4_reply, decode_udp_datagram, encode_socks4_bind_request, encode_socks4_request,
+    encode_udp_datagram, listen, Address, SocksListener,
+};
+
+/// Represents the authentication of a SOCKS5 proxy.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub enum SocksAuth {
+    /// No authentication.
+    None,
+    /// Username/password authentication (RFC 1929).
+    UserPass { username: String, password: String },
+}
+
+/// Represents the version of the SOCKS protocol spoken to the proxy.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum SocksVersion {
+    /// SOCKS4 with the SOCKS4a extension, which only supports TCP. Domain names are resolved by
+    /// the proxy following SOCKS4a, and the username is sent as the user ID.
+    V4,
+    /// SOCKS5 (RFC 1928).
+    V5,
+}
+
+impl Default for SocksVersion {
+    fn default() -> Self {
+        SocksVersion::V5
+    }
+}
+
+impl Display for SocksVersion {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            SocksVersion::V4 => write!(f, "4"),
+            SocksVersion::V5 => write!(f, "5"),
+        }
+    }
+}
+
+impl FromStr for SocksVersion {
+    type Err = io::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "4" | "4a" => Ok(SocksVersion::V4),
+            "5" => Ok(SocksVersion::V5),
+            _ => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "unknown SOCKS version",
+            )),
+        }
+    }
+}
+
+/// Represents the options of connecting to a SOCKS5 proxy.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct ConnectOptions {
+    /// Represents the local IP address the connection is bound to.
+    pub local: Option<Ipv4Addr>,
+    /// Represents the timeout of each connecting attempt. The system default is used if it is
+    /// not specified.
+    pub timeout: Option<Duration>,
+    /// Represents the maximum count of retries after the first attempt fails.
+    pub max_retries: usize,
+    /// Represents the delay before the first retry, which is doubled after each retry.
+    pub base_delay: Duration,
+    /// Represents the upper bound of the delay between retries.
+    pub max_delay: Duration,
+    /// Represents if a random jitter of up to half the delay is subtracted from the delay.
+    pub jitter: bool,
+    /// Represents the idle time before TCP keep-alive probes are sent to the proxy, or `None`
+    /// for not probing.
+    pub keepalive: Option<Duration>,
+    /// Represents the version of the SOCKS protocol.
+    pub version: SocksVersion,
+}
+
+impl ConnectOptions {
+    /// Get the delay before the given retry, counting from 0.
+    pub fn get_delay(&self, retry: usize) -> Duration {
+        let shift = min(retry, 31) as u32;
+        let delay = self
+            .base_delay
+            .checked_mul(1 << shift)
+            .unwrap_or(self.max_delay);
+        let delay = min(delay, self.max_delay);
+        if !self.jitter {
+            return delay;
+        }
+
+        // A cheap source of randomness is sufficient for spreading retries
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.subsec_nanos())
+            .unwrap_or_default();
+        let jitter = delay / 2 * (nanos % 1024) / 1024;
+
+        delay - jitter
+    }
+}
+
+impl Default for ConnectOptions {
+    fn default() -> Self {
+        ConnectOptions {
+            local: None,
+            timeout: None,
+            max_retries: 0,
+            base_delay: Duration::from_millis(200),
+            max_delay: Duration::from_secs(10),
+            jitter: true,
+            keepalive: None,
+            version: SocksVersion::V5,
+        }
+    }
+}
 
 /// Trait for forwarding transport layer payload.
 pub trait Forward: Send {
-    /// Forward TCP payload.
-    fn forward_tcp(&mut self, dst: SocketAddrV4, src_port: u16, payload: &[u8]) -> io::Result<()>;
+    /// Forward TCP payload from the destination to the source.
+    fn forward_tcp(
+        &mut self,
+        dst: SocketAddrV4,
+        src: SocketAddrV4,
+        payload: &[u8],
+    ) -> io::Result<()>;
+
+    /// Forward UDP payload from the destination to the source.
+    fn forward_udp(
+        &mut self,
+        dst: SocketAddrV4,
+        src: SocketAddrV4,
+        payload: &[u8],
+    ) -> io::Result<()>;
 
-    /// Forward UDP payload.
-    fn forward_udp(&mut self, dst: SocketAddrV4, src_port: u16, payload: &[u8]) -> io::Result<()>;
+    /// Opens a TCP connection from the destination to the source, e.g., for the inbound
+    /// connection of a SOCKS BIND command.
+    fn open_tcp(&mut self, dst: SocketAddrV4, src: SocketAddrV4) -> io::Result<()>;
 }
 
 /// Represents the wait time after a `TimedOut` `IoError`.
@@ -26,25 +163,102 @@ const TIMEDOUT_WAIT: u64 = 20;
 const RECV_ZERO_WAIT: u64 = 100;
 /// Represents the maximum count of receiving 0 byte from the stream before closing it.
 const MAX_RECV_ZERO: usize = 3;
+/// Represents the interval a `DatagramWorker` checks if it is closed while receiving.
+const DATAGRAM_CLOSE_CHECK_INTERVAL: u64 = 1000;
+
+/// Represents the default time waiting for the inbound connection of a SOCKS BIND command.
+pub const DEFAULT_BIND_TIMEOUT: Duration = Duration::from_secs(120);
+
+/// Represents the max size of the data buffered to a SOCKS5 TCP stream. Sending waits for the
+/// data to be written once the buffer is over it.
+pub const MAX_PENDING_SIZE: usize = u16::MAX as usize;
 
 /// Represents a worker of a SOCKS5 TCP stream.
 pub struct StreamWorker {
     dst: SocketAddrV4,
     stream_tx: OwnedWriteHalf,
+    /// Represents the data buffered which are not written to the stream yet.
+    pending: Vec<u8>,
     is_finished: Arc<AtomicBool>,
     is_closed: Arc<AtomicBool>,
 }
 
 impl StreamWorker {
-    /// Opens a new `StreamWorker`.
+    /// Opens a new `StreamWorker`. The stream to the proxy is connected with the given options.
     pub async fn connect(
         tx: Arc<Mutex<dyn Forward>>,
-        src_port: u16,
+        src: SocketAddrV4,
         dst: SocketAddrV4,
         remote: SocketAddrV4,
+        options: &ConnectOptions,
+        auth: &SocksAuth,
+    ) -> io::Result<StreamWorker> {
+        StreamWorker::connect_with_address(
+            tx,
+            src,
+            dst,
+            &Address::from(*dst.ip()),
+            remote,
+            options,
+            auth,
+        )
+        .await
+    }
+
+    /// Opens a new `StreamWorker` which connects to the given address through the proxy, e.g., a
+    /// domain name resolved by the proxy. The destination is used for forwarding TCP payload
+    /// back to the source.
+    pub async fn connect_with_address(
+        tx: Arc<Mutex<dyn Forward>>,
+        src: SocketAddrV4,
+        dst: SocketAddrV4,
+        addr: &Address,
+        remote: SocketAddrV4,
+        options: &ConnectOptions,
+        auth: &SocksAuth,
+    ) -> io::Result<StreamWorker> {
+        let stream = socks::connect(remote, options, addr, dst.port(), auth).await?;
+
+        Ok(StreamWorker::spawn(tx, src, dst, stream.into_inner()))
+    }
+
+    /// Opens a new `StreamWorker` which connects to the destination directly without the proxy.
+    pub async fn connect_direct(
+        tx: Arc<Mutex<dyn Forward>>,
+        src: SocketAddrV4,
+        dst: SocketAddrV4,
     ) -> io::Result<StreamWorker> {
-        let stream = socks::connect(remote, dst).await?;
-        let stream = stream.into_inner();
+        let stream = TcpStream::connect(dst).await?;
+
+        Ok(StreamWorker::spawn(tx, src, dst, stream))
+    }
+
+    /// Opens a new `StreamWorker` from the inbound connection of the given listener, and returns
+    /// it with the address of the connecting host. A TCP connection from the destination to the
+    /// source is opened once the inbound connection arrives, and TCP payload is forwarded back to
+    /// the source over it. Returns a `TimedOut` error if no inbound connection arrives in the
+    /// given time.
+    pub async fn accept(
+        tx: Arc<Mutex<dyn Forward>>,
+        src: SocketAddrV4,
+        dst: SocketAddrV4,
+        listener: SocksListener,
+        timeout: Duration,
+    ) -> io::Result<(StreamWorker, SocketAddrV4)> {
+        let (stream, peer) = listener.accept(timeout).await?;
+
+        // Open before any payload is forwarded
+        tx.lock().unwrap().open_tcp(dst, src)?;
+
+        Ok((StreamWorker::spawn(tx, src, dst, stream), peer))
+    }
+
+    fn spawn(
+        tx: Arc<Mutex<dyn Forward>>,
+        src: SocketAddrV4,
+        dst: SocketAddrV4,
+        stream: TcpStream,
+    ) -> StreamWorker {
         let (mut stream_rx, stream_tx) = stream.into_split();
 
         let is_finished = Arc::new(AtomicBool::new(false));
@@ -91,9 +305,7 @@ impl StreamWorker {
 
                         // Send
                         if let Err(ref e) =
-                            tx.lock()
-                                .unwrap()
-                                .forward_tcp(dst, src_port, &buffer[..size])
+                            tx.lock().unwrap().forward_tcp(dst, src, &buffer[..size])
                         {
                             warn!("handle {}: {}", "TCP", e);
                         }
@@ -113,15 +325,19 @@ impl StreamWorker {
 
         trace!("open stream {} -> {}", 0, dst);
 
-        Ok(StreamWorker {
+        StreamWorker {
             dst,
             stream_tx,
+            pending: Vec::new(),
             is_finished,
             is_closed: is_closed,
-        })
+        }
     }
 
-    /// Sends data on the SOCKS5 in TCP to the destination.
+    /// Sends data on the SOCKS5 in TCP to the destination. The data is written without waiting
+    /// as much as possible, and the rest is buffered until the stream is writable. If more than
+    /// `MAX_PENDING_SIZE` bytes are buffered, sending waits until all of them are written, so the
+    /// pace of the proxy propagates back to the capture.
     pub async fn send(&mut self, buffer: &[u8]) -> io::Result<()> {
         debug!(
             "send to SOCKS {}: {} -> {} ({} Bytes)",
@@ -132,7 +348,81 @@ impl StreamWorker {
         );
 
         // Send
-        self.stream_tx.write_all(buffer).await
+        self.pending.extend_from_slice(buffer);
+        self.try_flush().await?;
+        if self.pending.len() > MAX_PENDING_SIZE {
+            trace!(
+                "wait stream {} -> {} ({} Bytes)",
+                0,
+                self.dst,
+                self.pending.len()
+            );
+            self.flush().await?;
+        }
+
+        Ok(())
+    }
+
+    /// Writes the data buffered to the stream without waiting, and returns the number of bytes
+    /// written.
+    pub async fn try_flush(&mut self) -> io::Result<usize> {
+        let mut written = 0;
+        while written < self.pending.len() {
+            // Poll the write only once, the stream is not writable if it is pending
+            let stream_tx = &mut self.stream_tx;
+            let pending = &self.pending[written..];
+            let result =
+                future::poll_fn(
+                    |cx| match Pin::new(&mut *stream_tx).poll_write(cx, pending) {
+                        Poll::Ready(result) => Poll::Ready(Some(result)),
+                        Poll::Pending => Poll::Ready(None),
+                    },
+                )
+                .await;
+            match result {
+                Some(Ok(0)) => {
+                    self.is_closed.store(true, Ordering::Relaxed);
+                    return Err(io::Error::from(io::ErrorKind::WriteZero));
+                }
+                Some(Ok(size)) => written += size,
+                Some(Err(e)) => {
+                    self.is_closed.store(true, Ordering::Relaxed);
+                    return Err(e);
+                }
+                // Not writable
+                None => break,
+            }
+        }
+        self.pending.drain(..written);
+        if !self.pending.is_empty() {
+            trace!(
+                "buffer stream {} -> {} ({} Bytes)",
+                0,
+                self.dst,
+                self.pending.len()
+            );
+        }
+
+        Ok(written)
+    }
+
+    /// Writes all the data buffered to the stream.
+    pub async fn flush(&mut self) -> io::Result<()> {
+        if self.pending.is_empty() {
+            return Ok(());
+        }
+        let result = self.stream_tx.write_all(&self.pending).await;
+        self.pending.clear();
+        if result.is_err() {
+            self.is_closed.store(true, Ordering::Relaxed);
+        }
+
+        result
+    }
+
+    /// Get the size of the data buffered which are not written to the stream yet.
+    pub fn get_pending_size(&self) -> usize {
+        self.pending.len()
     }
 
     /// Announces to finish the worker.
@@ -162,23 +452,29 @@ impl Drop for StreamWorker {
 
 /// Represents a worker of a SOCKS5 UDP client.
 pub struct DatagramWorker {
-    src_port: Arc<AtomicU16>,
+    src: Arc<Mutex<Option<SocketAddrV4>>>,
     local_port: u16,
+    remote: SocketAddrV4,
     socks_tx: SocksSendHalf,
     is_closed: Arc<AtomicBool>,
+    last_active: Instant,
 }
 
 impl DatagramWorker {
-    /// Creates a new `DatagramWorker`.
+    /// Creates a new `DatagramWorker`. The association with the proxy is connected and the
+    /// datagrams are bound with the given options. The association is closed when the worker is
+    /// dropped.
     pub async fn bind(
         tx: Arc<Mutex<dyn Forward>>,
-        src_port: u16,
+        src: SocketAddrV4,
         remote: SocketAddrV4,
+        options: &ConnectOptions,
+        auth: &SocksAuth,
     ) -> io::Result<(DatagramWorker, u16)> {
-        let (mut socks_rx, socks_tx, local_port) = socks::bind(remote).await?;
+        let (mut socks_rx, socks_tx, local_port) = socks::bind(remote, options, auth).await?;
 
-        let a_src_port = Arc::new(AtomicU16::from(src_port));
-        let a_src_port_cloned = Arc::clone(&a_src_port);
+        let a_src = Arc::new(Mutex::new(Some(src)));
+        let a_src_cloned = Arc::clone(&a_src);
         let is_closed = Arc::new(AtomicBool::new(false));
         let is_closed_cloned = Arc::clone(&is_closed);
         tokio::spawn(async move {
@@ -187,7 +483,17 @@ impl DatagramWorker {
                 if is_closed_cloned.load(Ordering::Relaxed) {
                     break;
                 }
-                match socks_rx.recv_from(&mut buffer).await {
+                let result = time::timeout(
+                    Duration::from_millis(DATAGRAM_CLOSE_CHECK_INTERVAL),
+                    socks_rx.recv_from(&mut buffer),
+                )
+                .await;
+                let result = match result {
+                    Ok(result) => result,
+                    // Check if the worker is closed
+                    Err(_) => continue,
+                };
+                match result {
                     Ok((size, addr)) => {
                         if is_closed_cloned.load(Ordering::Relaxed) {
                             break;
@@ -197,12 +503,19 @@ impl DatagramWorker {
                             "UDP", addr, local_port, size
                         );
 
+                        // The association is idle in a pool
+                        let src = match *a_src_cloned.lock().unwrap() {
+                            Some(src) => src,
+                            None => {
+                                trace!("drop datagram from {} in idle association", addr);
+                                continue;
+                            }
+                        };
+
                         // Send
-                        if let Err(ref e) = tx.lock().unwrap().forward_udp(
-                            addr,
-                            a_src_port_cloned.load(Ordering::Relaxed),
-                            &buffer[..size],
-                        ) {
+                        if let Err(ref e) =
+                            tx.lock().unwrap().forward_udp(addr, src, &buffer[..size])
+                        {
                             warn!("handle {}: {}", "UDP", e);
                         }
                     }
@@ -211,13 +524,7 @@ impl DatagramWorker {
                             time::delay_for(Duration::from_millis(TIMEDOUT_WAIT)).await;
                             continue;
                         }
-                        warn!(
-                            "SOCKS: {}: {} = {}: {}",
-                            "UDP",
-                            local_port,
-                            a_src_port_cloned.load(Ordering::Relaxed),
-                            e
-                        );
+                        warn!("SOCKS: {}: {}: {}", "UDP", local_port, e);
                         is_closed_cloned.store(true, Ordering::Relaxed);
 
                         break;
@@ -226,14 +533,16 @@ impl DatagramWorker {
             }
         });
 
-        trace!("create datagram {} = {}", src_port, local_port);
+        trace!("create datagram {} = {}", src, local_port);
 
         Ok((
             DatagramWorker {
-                src_port: a_src_port,
+                src: a_src,
                 local_port,
+                remote,
                 socks_tx,
                 is_closed: is_closed,
+                last_active: Instant::now(),
             },
             local_port,
         ))
@@ -250,18 +559,37 @@ impl DatagramWorker {
         );
 
         // Send
+        self.last_active = Instant::now();
         self.socks_tx.send_to(buffer, dst).await
     }
 
-    /// Sets the source port of the `DatagramWorker`.
-    pub fn set_src_port(&mut self, src_port: u16) {
-        self.src_port.store(src_port, Ordering::Relaxed);
-        trace!("set datagram {} = {}", src_port, self.local_port);
+    /// Sets the source of the `DatagramWorker`, or `None` if the worker is idle.
+    pub fn set_src(&mut self, src: Option<SocketAddrV4>) {
+        *self.src.lock().unwrap() = src;
+        match src {
+            Some(src) => trace!("set datagram {} = {}", src, self.local_port),
+            None => trace!("unset datagram {}", self.local_port),
+        }
+    }
+
+    /// Get the source of the `DatagramWorker`, or `None` if the worker is idle.
+    pub fn get_src(&self) -> Option<SocketAddrV4> {
+        *self.src.lock().unwrap()
     }
 
-    /// Get the source port of the `DatagramWorker`.
-    pub fn get_src_port(&self) -> u16 {
-        self.src_port.load(Ordering::Relaxed)
+    /// Get the local port of the `DatagramWorker`.
+    pub fn get_local_port(&self) -> u16 {
+        self.local_port
+    }
+
+    /// Get the proxy the `DatagramWorker` is associated with.
+    pub fn get_remote(&self) -> SocketAddrV4 {
+        self.remote
+    }
+
+    /// Get the time when the last datagram is sent on the `DatagramWorker`.
+    pub fn get_last_active(&self) -> Instant {
+        self.last_active
     }
 
     /// Returns if the worker is closed.
@@ -269,3 +597,553 @@ impl DatagramWorker {
         self.is_closed.load(Ordering::Relaxed)
     }
 }
+
+impl Drop for DatagramWorker {
+    fn drop(&mut self) {
+        self.is_closed.store(true, Ordering::Relaxed);
+        trace!("drop datagram {}", self.local_port);
+    }
+}
+
+/// Represents the default capacity of idle associations to each proxy in a `DatagramPool`.
+pub const DEFAULT_DATAGRAM_POOL_CAPACITY: usize = 16;
+/// Represents the default time before an idle association in a `DatagramPool` is recycled.
+pub const DEFAULT_DATAGRAM_POOL_IDLE_TIMEOUT: Duration = Duration::from_secs(120);
+
+/// Represents a pool of idle SOCKS5 UDP associations. The associations are pooled per proxy and
+/// reused by new UDP flows, which saves the control connection and the handshake to the proxy.
+pub struct DatagramPool {
+    idle: HashMap<SocketAddrV4, Vec<(DatagramWorker, Instant)>>,
+    capacity: usize,
+    idle_timeout: Duration,
+}
+
+impl DatagramPool {
+    /// Creates a new `DatagramPool` holding up to the given number of idle associations to each
+    /// proxy.
+    pub fn new(capacity: usize, idle_timeout: Duration) -> DatagramPool {
+        DatagramPool {
+            idle: HashMap::new(),
+            capacity,
+            idle_timeout,
+        }
+    }
+
+    /// Puts an idle association to the given proxy into the pool. The association is detached
+    /// from its source. Returns if the association is pooled, associations which are
+    /// closed or exceed the capacity are dropped.
+    pub fn put(&mut self, remote: SocketAddrV4, mut worker: DatagramWorker) -> bool {
+        if worker.is_closed() {
+            return false;
+        }
+        let workers = self.idle.entry(remote).or_insert_with(Vec::new);
+        if workers.len() >= self.capacity {
+            return false;
+        }
+
+        worker.set_src(None);
+        workers.push((worker, Instant::now()));
+
+        true
+    }
+
+    /// Takes an idle association to the given proxy from the pool, the most recently pooled
+    /// first. Associations which are closed or expired are discarded instead of being reused.
+    pub fn take(&mut self, remote: SocketAddrV4) -> Option<DatagramWorker> {
+        let workers = self.idle.get_mut(&remote)?;
+        while let Some((worker, instant)) = workers.pop() {
+            if worker.is_closed() || instant.elapsed() > self.idle_timeout {
+                trace!("discard pooled datagram {}", worker.get_local_port());
+                continue;
+            }
+
+            return Some(worker);
+        }
+
+        None
+    }
+
+    /// Recycles the associations which are closed or expired, and returns the number of the
+    /// associations recycled.
+    pub fn purge(&mut self) -> usize {
+        let idle_timeout = self.idle_timeout;
+        let mut count = 0;
+        for workers in self.idle.values_mut() {
+            let len = workers.len();
+            workers.retain(|(worker, instant)| {
+                !worker.is_closed() && instant.elapsed() <= idle_timeout
+            });
+            count += len - workers.len();
+        }
+        self.idle.retain(|_, workers| !workers.is_empty());
+
+        count
+    }
+
+    /// Returns the number of idle associations in the pool.
+    pub fn len(&self) -> usize {
+        self.idle.values().map(|workers| workers.len()).sum()
+    }
+
+    /// Returns if the pool contains no idle association.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// Represents the default time a failing proxy in a `SocksPool` is skipped for.
+pub const DEFAULT_SOCKS_POOL_COOLDOWN: Duration = Duration::from_secs(30);
+
+/// Represents the mode of selecting proxies in a `SocksPool`.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum SelectMode {
+    /// Selects the first healthy proxy in order, the others are only backups.
+    Failover,
+    /// Selects healthy proxies in turn for spreading the load.
+    LoadBalance,
+}
+
+impl Display for SelectMode {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            SelectMode::Failover => write!(f, "failover"),
+            SelectMode::LoadBalance => write!(f, "load-balance"),
+        }
+    }
+}
+
+impl FromStr for SelectMode {
+    type Err = io::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "failover" => Ok(SelectMode::Failover),
+            "load-balance" => Ok(SelectMode::LoadBalance),
+            _ => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "unknown select mode",
+            )),
+        }
+    }
+}
+
+/// Represents an ordered list of SOCKS5 proxies with their health. A proxy which fails is marked
+/// down and skipped for a cooldown, unless all the proxies are down.
+#[derive(Clone, Debug)]
+pub struct SocksPool {
+    /// Represents the proxies and the time until which each of them is down.
+    remotes: Vec<(SocketAddrV4, Option<Instant>)>,
+    mode: SelectMode,
+    cooldown: Duration,
+    next: usize,
+}
+
+impl SocksPool {
+    /// Creates a new `SocksPool` of the given proxy.
+    pub fn new(remote: SocketAddrV4, mode: SelectMode, cooldown: Duration) -> SocksPool {
+        SocksPool {
+            remotes: vec![(remote, None)],
+            mode,
+            cooldown,
+            next: 0,
+        }
+    }
+
+    /// Appends a proxy to the pool, which follows the proxies already in the pool.
+    pub fn insert(&mut self, remote: SocketAddrV4) {
+        if !self.remotes.iter().any(|(r, _)| *r == remote) {
+            self.remotes.push((remote, None));
+        }
+    }
+
+    /// Get the proxies in the pool in order.
+    pub fn get_remotes(&self) -> Vec<SocketAddrV4> {
+        self.remotes.iter().map(|(remote, _)| *remote).collect()
+    }
+
+    /// Get the mode of selecting proxies.
+    pub fn get_mode(&self) -> SelectMode {
+        self.mode
+    }
+
+    /// Returns if the given proxy is healthy, which is not marked down or its cooldown expires.
+    pub fn is_up(&self, remote: SocketAddrV4) -> bool {
+        self.remotes
+            .iter()
+            .find(|(r, _)| *r == remote)
+            .map_or(false, |(_, until)| is_up(*until))
+    }
+
+    /// Marks the given proxy down for the cooldown.
+    pub fn mark_down(&mut self, remote: SocketAddrV4) {
+        let until = Instant::now() + self.cooldown;
+        if let Some((_, down)) = self.remotes.iter_mut().find(|(r, _)| *r == remote) {
+            if is_up(*down) {
+                warn!("SOCKS: mark {} down for {:?}", remote, self.cooldown);
+            }
+            *down = Some(until);
+        }
+    }
+
+    /// Marks the given proxy healthy.
+    pub fn mark_up(&mut self, remote: SocketAddrV4) {
+        if let Some((_, down)) = self.remotes.iter_mut().find(|(r, _)| *r == remote) {
+            if !is_up(*down) {
+                debug!("SOCKS: mark {} up", remote);
+            }
+            *down = None;
+        }
+    }
+
+    /// Get the proxy which would be attempted first without selecting it.
+    pub fn peek(&self) -> SocketAddrV4 {
+        self.order(self.next)[0]
+    }
+
+    /// Selects the proxies to be attempted in order for a new connection. The healthy proxies
+    /// come first, rotated in the load balance mode, followed by the proxies which are down in
+    /// the order of their recovery.
+    pub fn select(&mut self) -> Vec<SocketAddrV4> {
+        let remotes = self.order(self.next);
+        if self.mode == SelectMode::LoadBalance {
+            self.next = self.next.wrapping_add(1);
+        }
+
+        remotes
+    }
+
+    fn order(&self, next: usize) -> Vec<SocketAddrV4> {
+        let mut up: Vec<SocketAddrV4> = self
+            .remotes
+            .iter()
+            .filter(|(_, until)| is_up(*until))
+            .map(|(remote, _)| *remote)
+            .collect();
+        if self.mode == SelectMode::LoadBalance && !up.is_empty() {
+            let len = up.len();
+            up.rotate_left(next % len);
+        }
+
+        let mut down: Vec<(SocketAddrV4, Instant)> = self
+            .remotes
+            .iter()
+            .filter_map(|(remote, until)| match until {
+                Some(until) if !is_up(Some(*until)) => Some((*remote, *until)),
+                _ => None,
+            })
+            .collect();
+        down.sort_by_key(|(_, until)| *until);
+        up.extend(down.into_iter().map(|(remote, _)| remote));
+
+        up
+    }
+}
+
+/// Returns if a proxy down until the given time is healthy now.
+fn is_up(until: Option<Instant>) -> bool {
+    until.map_or(true, |until| Instant::now() >= until)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Read, Write};
+    use std::net::{SocketAddr, TcpListener};
+    use std::sync::mpsc;
+    use std::thread;
+
+    #[test]
+    fn get_delay_backoff() {
+        let options = ConnectOptions {
+            base_delay: Duration::from_millis(200),
+            max_delay: Duration::from_secs(1),
+            jitter: false,
+            ..ConnectOptions::default()
+        };
+
+        assert_eq!(options.get_delay(0), Duration::from_millis(200));
+        assert_eq!(options.get_delay(1), Duration::from_millis(400));
+        assert_eq!(options.get_delay(2), Duration::from_millis(800));
+        assert_eq!(options.get_delay(3), Duration::from_secs(1));
+        // The multiplication does not overflow
+        assert_eq!(options.get_delay(100), Duration::from_secs(1));
+    }
+
+    #[test]
+    fn get_delay_jitter() {
+        let options = ConnectOptions {
+            base_delay: Duration::from_millis(200),
+            jitter: true,
+            ..ConnectOptions::default()
+        };
+
+        for retry in 0..4 {
+            let delay = Duration::from_millis(200 << retry);
+            let jittered = options.get_delay(retry);
+            assert!(jittered > delay / 2 && jittered <= delay);
+        }
+    }
+
+    /// Represents a `Forward` discarding everything.
+    struct NullForward;
+
+    impl Forward for NullForward {
+        fn forward_tcp(&mut self, _: SocketAddrV4, _: SocketAddrV4, _: &[u8]) -> io::Result<()> {
+            Ok(())
+        }
+
+        fn forward_udp(&mut self, _: SocketAddrV4, _: SocketAddrV4, _: &[u8]) -> io::Result<()> {
+            Ok(())
+        }
+
+        fn open_tcp(&mut self, _: SocketAddrV4, _: SocketAddrV4) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn stream_worker_cap_pending() {
+        // A destination which starts reading late
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let dst = match listener.local_addr().unwrap() {
+            SocketAddr::V4(addr) => addr,
+            _ => unreachable!(),
+        };
+        let (count_tx, count_rx) = mpsc::channel();
+        thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            thread::sleep(Duration::from_millis(200));
+            let mut buffer = Vec::new();
+            stream.read_to_end(&mut buffer).unwrap();
+            count_tx.send(buffer.len()).unwrap();
+        });
+
+        let tx: Arc<Mutex<dyn Forward>> = Arc::new(Mutex::new(NullForward));
+        let src = SocketAddrV4::new(Ipv4Addr::new(10, 6, 0, 1), 1024);
+        let mut worker = StreamWorker::connect_direct(tx, src, dst).await.unwrap();
+        // Overwhelm the buffers of the socket, sending waits instead of buffering more
+        let chunk = vec![0u8; 16384];
+        let count = 1024;
+        for _ in 0..count {
+            worker.send(&chunk).await.unwrap();
+            assert!(worker.get_pending_size() <= MAX_PENDING_SIZE);
+        }
+        worker.flush().await.unwrap();
+        drop(worker);
+
+        assert_eq!(count_rx.recv().unwrap(), chunk.len() * count);
+    }
+
+    /// Spawns a mock SOCKS5 proxy accepting UDP associations without authentication, and returns
+    /// the address of the proxy with the control connections accepted by it. The relay is
+    /// advertised at the address of the proxy.
+    fn spawn_udp_proxy() -> (SocketAddrV4, mpsc::Receiver<std::net::TcpStream>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = match listener.local_addr().unwrap() {
+            SocketAddr::V4(addr) => addr,
+            _ => unreachable!(),
+        };
+        let (tx, rx) = mpsc::channel();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let mut buffer = [0u8; 10];
+                stream.read_exact(&mut buffer[..2]).unwrap();
+                let n = buffer[1] as usize;
+                stream.read_exact(&mut buffer[..n]).unwrap();
+                stream.write_all(&[0x05, 0x00]).unwrap();
+                // A request of UDP ASSOCIATE
+                stream.read_exact(&mut buffer).unwrap();
+                assert_eq!(buffer[1], 0x03);
+                let port = addr.port().to_be_bytes();
+                stream
+                    .write_all(&[0x05, 0x00, 0x00, 0x01, 127, 0, 0, 1, port[0], port[1]])
+                    .unwrap();
+
+                if tx.send(stream).is_err() {
+                    break;
+                }
+            }
+        });
+
+        (addr, rx)
+    }
+
+    /// Associates with the given proxy for the given source port.
+    async fn bind_worker(remote: SocketAddrV4, src_port: u16) -> DatagramWorker {
+        let tx: Arc<Mutex<dyn Forward>> = Arc::new(Mutex::new(NullForward));
+        let src = SocketAddrV4::new(Ipv4Addr::new(10, 6, 0, 1), src_port);
+        let (worker, _) = DatagramWorker::bind(
+            tx,
+            src,
+            remote,
+            &ConnectOptions::default(),
+            &SocksAuth::None,
+        )
+        .await
+        .unwrap();
+
+        worker
+    }
+
+    #[tokio::test]
+    async fn datagram_pool_reuse() {
+        let (remote, connections) = spawn_udp_proxy();
+        let worker = bind_worker(remote, 1024).await;
+        let local_port = worker.get_local_port();
+        let mut pool = DatagramPool::new(
+            DEFAULT_DATAGRAM_POOL_CAPACITY,
+            DEFAULT_DATAGRAM_POOL_IDLE_TIMEOUT,
+        );
+        assert!(pool.put(remote, worker));
+        assert_eq!(pool.len(), 1);
+
+        // The pooled association is detached from its source
+        let mut worker = pool.take(remote).unwrap();
+        assert_eq!(worker.get_local_port(), local_port);
+        assert_eq!(worker.get_src(), None);
+        assert!(pool.is_empty());
+        let src = SocketAddrV4::new(Ipv4Addr::new(10, 6, 0, 1), 1025);
+        worker.set_src(Some(src));
+        assert_eq!(worker.get_src(), Some(src));
+
+        // No other control connection is made
+        let streams: Vec<_> = connections.try_iter().collect();
+        assert_eq!(streams.len(), 1);
+        assert!(pool.take(remote).is_none());
+        let other = SocketAddrV4::new(Ipv4Addr::LOCALHOST, 1);
+        assert!(pool.take(other).is_none());
+    }
+
+    #[tokio::test]
+    async fn datagram_pool_discard_broken() {
+        let (remote, connections) = spawn_udp_proxy();
+        let broken = bind_worker(remote, 1024).await;
+        let healthy = bind_worker(remote, 1025).await;
+        let healthy_port = healthy.get_local_port();
+        let mut pool = DatagramPool::new(
+            DEFAULT_DATAGRAM_POOL_CAPACITY,
+            DEFAULT_DATAGRAM_POOL_IDLE_TIMEOUT,
+        );
+        assert!(pool.put(remote, healthy));
+        assert!(pool.put(remote, broken));
+
+        // The proxy closes the control connection of the first association
+        let streams: Vec<_> = connections.try_iter().collect();
+        streams[0].shutdown(std::net::Shutdown::Both).unwrap();
+        time::delay_for(Duration::from_millis(200)).await;
+
+        assert_eq!(pool.purge(), 1);
+        assert_eq!(pool.len(), 1);
+        assert_eq!(pool.take(remote).unwrap().get_local_port(), healthy_port);
+    }
+
+    #[tokio::test]
+    async fn datagram_pool_expire_and_capacity() {
+        let (remote, _connections) = spawn_udp_proxy();
+        let mut pool = DatagramPool::new(1, Duration::from_millis(50));
+        assert!(pool.put(remote, bind_worker(remote, 1024).await));
+        // Associations exceeding the capacity are dropped
+        assert!(!pool.put(remote, bind_worker(remote, 1025).await));
+        assert_eq!(pool.len(), 1);
+
+        time::delay_for(Duration::from_millis(100)).await;
+        assert!(pool.take(remote).is_none());
+        assert!(pool.is_empty());
+    }
+
+    /// Returns the address of the proxy of the given port on the loopback interface.
+    fn proxy(port: u16) -> SocketAddrV4 {
+        SocketAddrV4::new(Ipv4Addr::LOCALHOST, port)
+    }
+
+    #[test]
+    fn socks_pool_failover() {
+        let mut pool = SocksPool::new(proxy(1080), SelectMode::Failover, Duration::from_secs(30));
+        pool.insert(proxy(1081));
+        pool.insert(proxy(1080));
+        assert_eq!(pool.get_remotes(), vec![proxy(1080), proxy(1081)]);
+
+        // The first proxy is always selected first
+        assert_eq!(pool.select(), vec![proxy(1080), proxy(1081)]);
+        assert_eq!(pool.select(), vec![proxy(1080), proxy(1081)]);
+
+        // A proxy which is down is skipped until it is up
+        pool.mark_down(proxy(1080));
+        assert!(!pool.is_up(proxy(1080)));
+        assert_eq!(pool.peek(), proxy(1081));
+        assert_eq!(pool.select(), vec![proxy(1081), proxy(1080)]);
+        pool.mark_up(proxy(1080));
+        assert!(pool.is_up(proxy(1080)));
+        assert_eq!(pool.select(), vec![proxy(1080), proxy(1081)]);
+    }
+
+    #[test]
+    fn socks_pool_load_balance() {
+        let mut pool = SocksPool::new(
+            proxy(1080),
+            SelectMode::LoadBalance,
+            Duration::from_secs(30),
+        );
+        pool.insert(proxy(1081));
+        pool.insert(proxy(1082));
+
+        assert_eq!(pool.select()[0], proxy(1080));
+        assert_eq!(pool.select()[0], proxy(1081));
+        assert_eq!(pool.select()[0], proxy(1082));
+        assert_eq!(pool.select()[0], proxy(1080));
+
+        // Only the healthy proxies are selected in turn
+        pool.mark_down(proxy(1081));
+        let selected: Vec<SocketAddrV4> = (0..4).map(|_| pool.select()[0]).collect();
+        assert!(!selected.contains(&proxy(1081)));
+        assert!(selected.contains(&proxy(1080)) && selected.contains(&proxy(1082)));
+    }
+
+    #[test]
+    fn socks_pool_all_down() {
+        let mut pool = SocksPool::new(proxy(1080), SelectMode::Failover, Duration::from_secs(30));
+        pool.insert(proxy(1081));
+        pool.mark_down(proxy(1081));
+        pool.mark_down(proxy(1080));
+
+        // The proxies down are still attempted in the order of their recovery
+        assert_eq!(pool.select(), vec![proxy(1081), proxy(1080)]);
+    }
+
+    #[test]
+    fn socks_pool_cooldown_expire() {
+        let mut pool = SocksPool::new(proxy(1080), SelectMode::Failover, Duration::from_millis(0));
+        pool.insert(proxy(1081));
+        pool.mark_down(proxy(1080));
+
+        assert!(pool.is_up(proxy(1080)));
+        assert_eq!(pool.peek(), proxy(1080));
+        assert!(!pool.is_up(proxy(1082)));
+    }
+
+    #[test]
+    fn select_mode_from_str() {
+        assert_eq!(
+            "failover".parse::<SelectMode>().unwrap(),
+            SelectMode::Failover
+        );
+        assert_eq!(
+            "Load-Balance".parse::<SelectMode>().unwrap(),
+            SelectMode::LoadBalance
+        );
+        assert!("random".parse::<SelectMode>().is_err());
+        assert_eq!(SelectMode::LoadBalance.to_string(), "load-balance");
+    }
+
+    #[test]
+    fn socks_version_from_str() {
+        assert_eq!("4".parse::<SocksVersion>().unwrap(), SocksVersion::V4);
+        assert_eq!("4A".parse::<SocksVersion>().unwrap(), SocksVersion::V4);
+        assert_eq!("5".parse::<SocksVersion>().unwrap(), SocksVersion::V5);
+        assert!("6".parse::<SocksVersion>().is_err());
+        assert_eq!(SocksVersion::default(), SocksVersion::V5);
+        assert_eq!(ConnectOptions::default().version, SocksVersion::V5);
+        assert_eq!(SocksVersion::V4.to_string(), "4");
+    }
+}
@@ -1,76 +1,528 @@
-use async_socks5;
-use std::net::{Ipv4Addr, SocketAddrV4};
-use std::sync::Arc;
-use tokio::io::{self, BufStream};
+use super::{ConnectOptions, SocksAuth, SocksVersion};
+use async_socks5::{self, AddrKind, Auth};
+use log::{debug, warn};
+use std::fmt::{self, Display, Formatter};
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV4, SocketAddrV6};
+use std::time::Duration;
+use tokio::io::{self, AsyncRead, AsyncReadExt, AsyncWriteExt, BufStream};
 use tokio::net::udp::{RecvHalf, SendHalf};
 use tokio::net::{TcpStream, UdpSocket};
+use tokio::time;
 
-/// Connects to a target server through a SOCKS5 proxy.
-pub async fn connect(remote: SocketAddrV4, dst: SocketAddrV4) -> io::Result<BufStream<TcpStream>> {
-    let stream = TcpStream::connect(remote).await?;
+impl SocksAuth {
+    fn to_auth(&self) -> Option<Auth> {
+        match self {
+            SocksAuth::None => None,
+            SocksAuth::UserPass { username, password } => {
+                Some(Auth::new(username.clone(), password.clone()))
+            }
+        }
+    }
+}
+
+/// Converts an `async_socks5::Error` into an `io::Error`. Authentication failures are reported
+/// as `PermissionDenied`.
+fn to_io_error(e: async_socks5::Error) -> io::Error {
+    match e {
+        async_socks5::Error::Io(e) => e,
+        async_socks5::Error::InvalidAuthStatus(_)
+        | async_socks5::Error::InvalidAuthSubnegotiation(_)
+        | async_socks5::Error::NoAcceptableMethods => {
+            io::Error::new(io::ErrorKind::PermissionDenied, e)
+        }
+        _ => io::Error::new(io::ErrorKind::Other, e),
+    }
+}
+
+/// Connects to the SOCKS5 proxy with the given options. Failed attempts are retried with an
+/// exponential backoff, and the error of the last attempt is returned if all the retries fail.
+async fn connect_remote(remote: SocketAddrV4, options: &ConnectOptions) -> io::Result<TcpStream> {
+    let mut retry = 0;
+    loop {
+        let result = match options.timeout {
+            Some(timeout) => {
+                match time::timeout(timeout, connect_once(remote, options.local)).await {
+                    Ok(result) => result,
+                    Err(_) => Err(io::Error::new(
+                        io::ErrorKind::TimedOut,
+                        format!("connect timed out after {} ms", timeout.as_millis()),
+                    )),
+                }
+            }
+            None => connect_once(remote, options.local).await,
+        };
+
+        let e = match result {
+            Ok(stream) => return Ok(stream),
+            Err(e) => e,
+        };
+        // The local address will not become available by retrying
+        if retry >= options.max_retries || e.kind() == io::ErrorKind::AddrNotAvailable {
+            return Err(e);
+        }
+
+        let delay = options.get_delay(retry);
+        retry += 1;
+        debug!(
+            "connect to SOCKS proxy {}: {}, retry {}/{} in {} ms",
+            remote,
+            e,
+            retry,
+            options.max_retries,
+            delay.as_millis()
+        );
+        time::delay_for(delay).await;
+    }
+}
+
+/// Connects to the SOCKS5 proxy once. The stream is bound to the given local IP address before
+/// connecting if it is specified.
+async fn connect_once(remote: SocketAddrV4, local: Option<Ipv4Addr>) -> io::Result<TcpStream> {
+    let local = match local {
+        Some(local) => local,
+        None => return TcpStream::connect(remote).await,
+    };
+
+    let stream = match bind_stream(local) {
+        Ok(stream) => stream,
+        Err(e) => {
+            return Err(io::Error::new(
+                e.kind(),
+                format!("bind local address {}: {}", local, e),
+            ))
+        }
+    };
+
+    TcpStream::connect_std(stream, &SocketAddr::V4(remote)).await
+}
+
+/// Creates a TCP socket bound to the given local IP address which is not connected yet.
+#[cfg(unix)]
+fn bind_stream(local: Ipv4Addr) -> io::Result<std::net::TcpStream> {
+    use std::mem;
+    use std::os::unix::io::FromRawFd;
+
+    let fd = unsafe { libc::socket(libc::AF_INET, libc::SOCK_STREAM, 0) };
+    if fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    // The socket is closed when the stream is dropped
+    let stream = unsafe { std::net::TcpStream::from_raw_fd(fd) };
+
+    let mut addr: libc::sockaddr_in = unsafe { mem::zeroed() };
+    addr.sin_family = libc::AF_INET as libc::sa_family_t;
+    addr.sin_addr.s_addr = u32::from_ne_bytes(local.octets());
+    let ret = unsafe {
+        libc::bind(
+            fd,
+            &addr as *const libc::sockaddr_in as *const libc::sockaddr,
+            mem::size_of::<libc::sockaddr_in>() as libc::socklen_t,
+        )
+    };
+    if ret < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(stream)
+}
+
+/// Creates a TCP socket bound to the given local IP address, which is not supported on this
+/// platform.
+#[cfg(not(unix))]
+fn bind_stream(_: Ipv4Addr) -> io::Result<std::net::TcpStream> {
+    Err(io::Error::new(
+        io::ErrorKind::Other,
+        "binding local address not supported",
+    ))
+}
+
+/// Connects to a target server of the given address and port through a SOCKS5 proxy. Domain
+/// names are resolved by the proxy.
+pub async fn connect(
+    remote: SocketAddrV4,
+    options: &ConnectOptions,
+    addr: &Address,
+    port: u16,
+    auth: &SocksAuth,
+) -> io::Result<BufStream<TcpStream>> {
+    // Only connecting to the proxy is retried, the CONNECT command is never sent twice
+    let stream = connect_remote(remote, options).await?;
+    stream.set_keepalive(options.keepalive)?;
     let mut stream = BufStream::new(stream);
-    if let Err(e) = async_socks5::connect(&mut stream, dst, None).await {
-        match e {
-            async_socks5::Error::Io(e) => return Err(e),
-            _ => return Err(io::Error::new(io::ErrorKind::Other, e)),
+    match options.version {
+        SocksVersion::V4 => connect_v4(&mut stream, addr, port, auth).await?,
+        SocksVersion::V5 => {
+            let addr = addr.to_addr_kind(port)?;
+            if let Err(e) = async_socks5::connect(&mut stream, addr, auth.to_auth()).await {
+                return Err(to_io_error(e));
+            }
         }
     }
 
     Ok(stream)
 }
 
+/// Sends a SOCKS4 CONNECT request over the given stream, and waits for the reply.
+async fn connect_v4(
+    stream: &mut BufStream<TcpStream>,
+    addr: &Address,
+    port: u16,
+    auth: &SocksAuth,
+) -> io::Result<()> {
+    let user_id = match auth {
+        SocksAuth::None => "",
+        SocksAuth::UserPass { username, .. } => username.as_str(),
+    };
+    let request = encode_socks4_request(addr, port, user_id)?;
+    stream.write_all(&request).await?;
+    stream.flush().await?;
+
+    read_socks4_reply(stream).await?;
+
+    Ok(())
+}
+
+/// Reads a SOCKS4 reply from the given stream, and returns the address in the reply.
+async fn read_socks4_reply<S>(stream: &mut S) -> io::Result<SocketAddrV4>
+where
+    S: AsyncRead + Unpin,
+{
+    let mut reply = [0u8; SOCKS4_REPLY_SIZE];
+    stream.read_exact(&mut reply).await?;
+
+    decode_socks4_reply(&reply)
+}
+
+/// Represents the version of SOCKS4 requests.
+const SOCKS4_VERSION: u8 = 4;
+/// Represents the command code of SOCKS4 CONNECT requests.
+const SOCKS4_CONNECT: u8 = 1;
+/// Represents the command code of SOCKS4 BIND requests.
+const SOCKS4_BIND: u8 = 2;
+/// Represents the size of SOCKS4 replies.
+const SOCKS4_REPLY_SIZE: usize = 8;
+
+/// Encodes a SOCKS4 CONNECT request to the given address and port with the given user ID. A
+/// domain name is sent following the SOCKS4a extension, with an invalid IP address 0.0.0.1.
+/// Returns an error if the address is an IPv6 address, which SOCKS4 does not support.
+pub fn encode_socks4_request(addr: &Address, port: u16, user_id: &str) -> io::Result<Vec<u8>> {
+    encode_socks4(SOCKS4_CONNECT, addr, port, user_id)
+}
+
+/// Encodes a SOCKS4 BIND request with the given user ID. The address and port are of the server
+/// expected to connect to the proxy, and are encoded as the ones of a CONNECT request.
+pub fn encode_socks4_bind_request(addr: &Address, port: u16, user_id: &str) -> io::Result<Vec<u8>> {
+    encode_socks4(SOCKS4_BIND, addr, port, user_id)
+}
+
+fn encode_socks4(command: u8, addr: &Address, port: u16, user_id: &str) -> io::Result<Vec<u8>> {
+    let (ip_addr, domain) = match addr {
+        Address::Ipv4(ip_addr) => (*ip_addr, None),
+        Address::Domain(domain) => (Ipv4Addr::new(0, 0, 0, 1), Some(domain)),
+        Address::Ipv6(_) => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "IPv6 not supported by SOCKS4",
+            ))
+        }
+    };
+    if user_id.contains('\0') || domain.map_or(false, |domain| domain.contains('\0')) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "null byte in SOCKS4 request",
+        ));
+    }
+
+    // VN, CD, DSTPORT and DSTIP
+    let mut buffer = vec![SOCKS4_VERSION, command];
+    buffer.extend_from_slice(&port.to_be_bytes());
+    buffer.extend_from_slice(&ip_addr.octets());
+    // USERID
+    buffer.extend_from_slice(user_id.as_bytes());
+    buffer.push(0);
+    // Domain name of SOCKS4a
+    if let Some(domain) = domain {
+        buffer.extend_from_slice(domain.as_bytes());
+        buffer.push(0);
+    }
+
+    Ok(buffer)
+}
+
+/// Decodes a SOCKS4 reply, and returns the address in the reply. Returns an error if the
+/// request is not granted.
+pub fn decode_socks4_reply(buffer: &[u8]) -> io::Result<SocketAddrV4> {
+    if buffer.len() < SOCKS4_REPLY_SIZE {
+        return Err(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            "SOCKS4 reply truncated",
+        ));
+    }
+    // VN
+    if buffer[0] != 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("invalid SOCKS4 reply version {}", buffer[0]),
+        ));
+    }
+
+    // CD
+    match buffer[1] {
+        0x5a => {}
+        0x5b => {
+            return Err(io::Error::new(
+                io::ErrorKind::ConnectionRefused,
+                "SOCKS4 request rejected or failed",
+            ))
+        }
+        0x5c => {
+            return Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                "SOCKS4 request rejected because identd is unreachable",
+            ))
+        }
+        0x5d => {
+            return Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                "SOCKS4 request rejected because user IDs mismatch",
+            ))
+        }
+        code => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("unknown SOCKS4 reply code {}", code),
+            ))
+        }
+    }
+
+    // DSTPORT and DSTIP
+    let port = (buffer[2] as u16) << 8 | buffer[3] as u16;
+    let ip_addr = Ipv4Addr::new(buffer[4], buffer[5], buffer[6], buffer[7]);
+
+    Ok(SocketAddrV4::new(ip_addr, port))
+}
+
 const RSV_SIZE: usize = 2;
 const FRAG_SIZE: usize = 1;
-const ATYP_SIZE: usize = 1;
-const DST_ADDR_SIZE: usize = 4;
 const DST_PORT_SIZE: usize = 2;
-const HEADER_SIZE: usize = RSV_SIZE + FRAG_SIZE + ATYP_SIZE + DST_ADDR_SIZE + DST_PORT_SIZE;
 
 const ATYP_IPV4: u8 = 1;
+const ATYP_DOMAIN: u8 = 3;
+const ATYP_IPV6: u8 = 4;
+
+/// Represents the maximum length of a domain name in SOCKS5.
+const MAX_DOMAIN_LENGTH: usize = 255;
+
+/// Represents an address in SOCKS5.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub enum Address {
+    Ipv4(Ipv4Addr),
+    Ipv6(Ipv6Addr),
+    Domain(String),
+}
+
+impl Address {
+    /// Get the size of the address in bytes, including the ATYP.
+    pub fn get_size(&self) -> usize {
+        match self {
+            Address::Ipv4(_) => 1 + 4,
+            Address::Ipv6(_) => 1 + 16,
+            Address::Domain(domain) => 1 + 1 + domain.len(),
+        }
+    }
+
+    /// Converts the address with the given port into an `AddrKind`.
+    fn to_addr_kind(&self, port: u16) -> io::Result<AddrKind> {
+        match self {
+            Address::Ipv4(ip_addr) => Ok(AddrKind::Ip(SocketAddr::V4(SocketAddrV4::new(
+                *ip_addr, port,
+            )))),
+            Address::Ipv6(ip_addr) => Ok(AddrKind::Ip(SocketAddr::V6(SocketAddrV6::new(
+                *ip_addr, port, 0, 0,
+            )))),
+            Address::Domain(domain) => {
+                if domain.len() > MAX_DOMAIN_LENGTH {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        "domain too long",
+                    ));
+                }
+
+                Ok(AddrKind::Domain(domain.clone(), port))
+            }
+        }
+    }
+
+    /// Serializes the address into the given byte-array and returns the number of bytes written.
+    pub fn serialize(&self, buffer: &mut [u8]) -> io::Result<usize> {
+        let size = self.get_size();
+        if let Address::Domain(domain) = self {
+            if domain.len() > MAX_DOMAIN_LENGTH {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "domain too long",
+                ));
+            }
+        }
+        if buffer.len() < size {
+            return Err(io::Error::new(io::ErrorKind::WriteZero, "buffer too small"));
+        }
+
+        match self {
+            Address::Ipv4(ip_addr) => {
+                buffer[0] = ATYP_IPV4;
+                buffer[1..size].copy_from_slice(&ip_addr.octets());
+            }
+            Address::Ipv6(ip_addr) => {
+                buffer[0] = ATYP_IPV6;
+                buffer[1..size].copy_from_slice(&ip_addr.octets());
+            }
+            Address::Domain(domain) => {
+                buffer[0] = ATYP_DOMAIN;
+                buffer[1] = domain.len() as u8;
+                buffer[2..size].copy_from_slice(domain.as_bytes());
+            }
+        }
+
+        Ok(size)
+    }
+
+    /// Deserializes an `Address` from the given byte-array and returns it with the number of
+    /// bytes consumed.
+    pub fn deserialize(buffer: &[u8]) -> io::Result<(Address, usize)> {
+        let eof = || io::Error::new(io::ErrorKind::UnexpectedEof, "address truncated");
+        let atyp = *buffer.first().ok_or_else(eof)?;
+        match atyp {
+            ATYP_IPV4 => {
+                if buffer.len() < 1 + 4 {
+                    return Err(eof());
+                }
+                let ip_addr = Ipv4Addr::new(buffer[1], buffer[2], buffer[3], buffer[4]);
+
+                Ok((Address::Ipv4(ip_addr), 1 + 4))
+            }
+            ATYP_IPV6 => {
+                if buffer.len() < 1 + 16 {
+                    return Err(eof());
+                }
+                let mut octets = [0u8; 16];
+                octets.copy_from_slice(&buffer[1..1 + 16]);
+
+                Ok((Address::Ipv6(Ipv6Addr::from(octets)), 1 + 16))
+            }
+            ATYP_DOMAIN => {
+                let length = *buffer.get(1).ok_or_else(eof)? as usize;
+                if buffer.len() < 2 + length {
+                    return Err(eof());
+                }
+                let domain = String::from_utf8(buffer[2..2 + length].to_vec())
+                    .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+
+                Ok((Address::Domain(domain), 2 + length))
+            }
+            _ => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "unknown address type",
+            )),
+        }
+    }
+}
+
+impl From<Ipv4Addr> for Address {
+    fn from(ip_addr: Ipv4Addr) -> Self {
+        Address::Ipv4(ip_addr)
+    }
+}
+
+impl Display for Address {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            Address::Ipv4(ip_addr) => write!(f, "{}", ip_addr),
+            Address::Ipv6(ip_addr) => write!(f, "{}", ip_addr),
+            Address::Domain(domain) => write!(f, "{}", domain),
+        }
+    }
+}
+
+/// Wraps the given payload with a SOCKS5 UDP request header to the given address and port.
+pub fn encode_udp_datagram(addr: &Address, port: u16, payload: &[u8]) -> io::Result<Vec<u8>> {
+    let header_size = RSV_SIZE + FRAG_SIZE + addr.get_size() + DST_PORT_SIZE;
+    let mut buffer = vec![0u8; header_size + payload.len()];
+    // RSV
+    // FRAG
+    // ATYP and DST.ADDR
+    let n = addr.serialize(&mut buffer[RSV_SIZE + FRAG_SIZE..])?;
+    // DST.PORT
+    let begin = RSV_SIZE + FRAG_SIZE + n;
+    buffer[begin] = (port / 256) as u8;
+    buffer[begin + 1] = (port % 256) as u8;
+    // Data
+    buffer[header_size..].copy_from_slice(payload);
+
+    Ok(buffer)
+}
+
+/// Unwraps a SOCKS5 UDP request header from the given datagram, and returns the address, the
+/// port and the size of the header. Returns `None` if the datagram is a fragment, which is not
+/// supported and should be dropped.
+pub fn decode_udp_datagram(buffer: &[u8]) -> io::Result<Option<(Address, u16, usize)>> {
+    if buffer.len() < RSV_SIZE + FRAG_SIZE {
+        return Err(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            "datagram truncated",
+        ));
+    }
+    // FRAG
+    if buffer[RSV_SIZE] != 0 {
+        return Ok(None);
+    }
+    // ATYP and DST.ADDR
+    let (addr, n) = Address::deserialize(&buffer[RSV_SIZE + FRAG_SIZE..])?;
+    // DST.PORT
+    let begin = RSV_SIZE + FRAG_SIZE + n;
+    if buffer.len() < begin + DST_PORT_SIZE {
+        return Err(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            "datagram truncated",
+        ));
+    }
+    let port = buffer[begin] as u16 * 256 + buffer[begin + 1] as u16;
+
+    Ok(Some((addr, port, begin + DST_PORT_SIZE)))
+}
 
 /// Represents the send half of a SOCKS5 UDP client.
 #[derive(Debug)]
 pub struct SocksSendHalf {
-    stream: Arc<BufStream<TcpStream>>,
     send_half: SendHalf,
 }
 
 impl SocksSendHalf {
     /// Creates a new `SocksSendHalf`.
-    pub fn new(stream: Arc<BufStream<TcpStream>>, send_half: SendHalf) -> SocksSendHalf {
-        SocksSendHalf { stream, send_half }
+    pub fn new(send_half: SendHalf) -> SocksSendHalf {
+        SocksSendHalf { send_half }
     }
 
     /// Sends data on the socket to the given address.
     pub async fn send_to(&mut self, buffer: &[u8], dst: SocketAddrV4) -> io::Result<usize> {
-        let mut buf = vec![0u8; HEADER_SIZE + buffer.len()];
-        // RSV
-        // FRAG
-        // ATYP
-        buf[3] = ATYP_IPV4;
-        // DST.ADDR
-        &buf[4..8].copy_from_slice(&dst.ip().octets());
-        // DST.PORT
-        buf[8] = (dst.port() / 256) as u8;
-        buf[9] = (dst.port() % 256) as u8;
-        // Data
-        &buf[10..].copy_from_slice(buffer);
+        let buf = encode_udp_datagram(&Address::from(*dst.ip()), dst.port(), buffer)?;
 
         self.send_half.send(buf.as_slice()).await
     }
 }
 
-/// Represents the receive half of a SOCKS5 UDP client.
+/// Represents the receive half of a SOCKS5 UDP client. The half owns the control connection of
+/// the association, which is closed when the half is dropped.
 #[derive(Debug)]
 pub struct SocksRecvHalf {
-    stream: Arc<BufStream<TcpStream>>,
+    stream: BufStream<TcpStream>,
     recv_half: RecvHalf,
     buffer: Vec<u8>,
 }
 
 impl SocksRecvHalf {
     /// Creates a new `SocksRecvHalf`.
-    pub fn new(stream: Arc<BufStream<TcpStream>>, recv_half: RecvHalf) -> SocksRecvHalf {
+    pub fn new(stream: BufStream<TcpStream>, recv_half: RecvHalf) -> SocksRecvHalf {
         SocksRecvHalf {
             stream,
             recv_half,
@@ -78,58 +530,796 @@ impl SocksRecvHalf {
         }
     }
 
-    /// Receives a single datagram message on the socket.
+    /// Receives a single datagram message on the socket. Fragmented datagrams and datagrams not
+    /// from an IPv4 address are dropped. Returns a `ConnectionAborted` error if the control
+    /// connection is closed by the proxy, which terminates the association.
     pub async fn recv_from(&mut self, buffer: &mut [u8]) -> io::Result<(usize, SocketAddrV4)> {
-        let n = self.recv_half.recv(&mut self.buffer).await?;
-        // ATYP and address
-        match self.buffer[3] {
-            ATYP_IPV4 => {}
-            _ => unreachable!(),
-        }
-        let addr = SocketAddrV4::new(
-            Ipv4Addr::new(
-                self.buffer[4],
-                self.buffer[5],
-                self.buffer[6],
-                self.buffer[7],
-            ),
-            self.buffer[8] as u16 * 256 + self.buffer[9] as u16,
-        );
-        // Buffer
-        let size = n - HEADER_SIZE;
-        &buffer[..size].copy_from_slice(&self.buffer[HEADER_SIZE..n]);
+        let mut control = [0u8; 1];
+        loop {
+            let n = tokio::select! {
+                result = self.recv_half.recv(&mut self.buffer) => result?,
+                result = self.stream.read(&mut control) => match result {
+                    // Nothing is expected on the control connection after the association
+                    Ok(n) if n > 0 => continue,
+                    Ok(_) => {
+                        return Err(io::Error::new(
+                            io::ErrorKind::ConnectionAborted,
+                            "SOCKS control connection closed",
+                        ))
+                    }
+                    Err(e) => return Err(e),
+                },
+            };
+            let (addr, port, header_size) = match decode_udp_datagram(&self.buffer[..n]) {
+                Ok(Some(header)) => header,
+                Ok(None) => {
+                    debug!("drop fragmented SOCKS datagram ({} Bytes)", n);
+                    continue;
+                }
+                Err(ref e) => {
+                    warn!("drop SOCKS datagram: {}", e);
+                    continue;
+                }
+            };
+            let addr = match addr {
+                Address::Ipv4(ip_addr) => SocketAddrV4::new(ip_addr, port),
+                _ => {
+                    warn!("drop SOCKS datagram from {}:{}", addr, port);
+                    continue;
+                }
+            };
 
-        Ok((size, addr))
+            // Buffer
+            let size = n - header_size;
+            buffer[..size].copy_from_slice(&self.buffer[header_size..n]);
+
+            return Ok((size, addr));
+        }
     }
 }
 
 /// Bind a local address to a target server through a SOCKS5 proxy.
-pub async fn bind(remote: SocketAddrV4) -> io::Result<(SocksRecvHalf, SocksSendHalf, u16)> {
+pub async fn bind(
+    remote: SocketAddrV4,
+    options: &ConnectOptions,
+    auth: &SocksAuth,
+) -> io::Result<(SocksRecvHalf, SocksSendHalf, u16)> {
+    if options.version == SocksVersion::V4 {
+        return Err(io::Error::new(
+            io::ErrorKind::Other,
+            "UDP not supported by SOCKS4",
+        ));
+    }
+
     // Connect
-    let stream = TcpStream::connect(remote).await?;
+    let stream = connect_remote(remote, options).await?;
     let stream = BufStream::new(stream);
-    let local = SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, 0);
-    let socket = UdpSocket::bind(local).await?;
+    let local = SocketAddrV4::new(options.local.unwrap_or(Ipv4Addr::UNSPECIFIED), 0);
+    let socket = match UdpSocket::bind(local).await {
+        Ok(socket) => socket,
+        Err(e) => {
+            return Err(io::Error::new(
+                e.kind(),
+                format!("bind local address {}: {}", local.ip(), e),
+            ))
+        }
+    };
     let local_port = socket.local_addr().unwrap().port();
-    let datagram =
-        match async_socks5::SocksDatagram::associate::<SocketAddrV4>(stream, socket, None, None)
-            .await
-        {
-            Ok(datagram) => datagram,
-            Err(e) => match e {
-                async_socks5::Error::Io(e) => return Err(e),
-                _ => return Err(io::Error::new(io::ErrorKind::Other, e)),
-            },
-        };
+    let datagram = match async_socks5::SocksDatagram::associate::<SocketAddrV4>(
+        stream,
+        socket,
+        auth.to_auth(),
+        None,
+    )
+    .await
+    {
+        Ok(datagram) => datagram,
+        Err(e) => return Err(to_io_error(e)),
+    };
 
     let (stream, socket) = datagram.into_inner();
     let (socket_rx, socket_tx) = socket.split();
-    let a_stream = Arc::new(stream);
-    let a_stream_cloned = Arc::clone(&a_stream);
 
     Ok((
-        SocksRecvHalf::new(a_stream, socket_rx),
-        SocksSendHalf::new(a_stream_cloned, socket_tx),
+        SocksRecvHalf::new(stream, socket_rx),
+        SocksSendHalf::new(socket_tx),
         local_port,
     ))
 }
+
+/// Represents the SOCKS stream of a BIND command, whose first reply is received.
+enum BindStream {
+    V4(TcpStream),
+    V5(async_socks5::SocksListener<TcpStream>),
+}
+
+/// Represents a listener of an inbound connection through a SOCKS proxy, which is opened with the
+/// BIND command, e.g., for the data connection of active-mode FTP. The proxy accepts only one
+/// inbound connection for each BIND command. The stream is not buffered, so no data sent by the
+/// connecting host after the second reply is read ahead.
+pub struct SocksListener {
+    stream: BindStream,
+    bound: SocketAddrV4,
+}
+
+impl SocksListener {
+    /// Sends a BIND command over the given stream connected to the proxy, and waits for the first
+    /// reply, which carries the address the proxy listens on. The address and port are of the
+    /// server expected to connect to the proxy. An unspecified IP address in the reply is
+    /// replaced by the IP address of the proxy.
+    pub async fn bind(
+        mut stream: TcpStream,
+        version: SocksVersion,
+        addr: &Address,
+        port: u16,
+        auth: &SocksAuth,
+    ) -> io::Result<SocksListener> {
+        let remote = stream.peer_addr()?;
+        let (stream, mut bound) = match version {
+            SocksVersion::V4 => {
+                let user_id = match auth {
+                    SocksAuth::None => "",
+                    SocksAuth::UserPass { username, .. } => username.as_str(),
+                };
+                let request = encode_socks4_bind_request(addr, port, user_id)?;
+                stream.write_all(&request).await?;
+                stream.flush().await?;
+
+                let bound = read_socks4_reply(&mut stream).await?;
+
+                (BindStream::V4(stream), bound)
+            }
+            SocksVersion::V5 => {
+                let addr = addr.to_addr_kind(port)?;
+                let listener =
+                    match async_socks5::SocksListener::bind(stream, addr, auth.to_auth()).await {
+                        Ok(listener) => listener,
+                        Err(e) => return Err(to_io_error(e)),
+                    };
+                let bound = to_socket_addr_v4(listener.proxy_addr())?;
+
+                (BindStream::V5(listener), bound)
+            }
+        };
+
+        if let SocketAddr::V4(remote) = remote {
+            if bound.ip().is_unspecified() {
+                bound.set_ip(*remote.ip());
+            }
+        }
+
+        Ok(SocksListener { stream, bound })
+    }
+
+    /// Get the address the proxy listens on, which should be advertised to the server.
+    pub fn get_bound_addr(&self) -> SocketAddrV4 {
+        self.bound
+    }
+
+    /// Waits for the second reply of the BIND command, which is sent when the inbound connection
+    /// is established, and returns the stream with the address of the connecting host. Returns a
+    /// `TimedOut` error if no inbound connection arrives in the given time.
+    pub async fn accept(self, timeout: Duration) -> io::Result<(TcpStream, SocketAddrV4)> {
+        let accept = async {
+            match self.stream {
+                BindStream::V4(mut stream) => {
+                    let peer = read_socks4_reply(&mut stream).await?;
+
+                    Ok((stream, peer))
+                }
+                BindStream::V5(listener) => match listener.accept().await {
+                    Ok((stream, peer)) => Ok((stream, to_socket_addr_v4(&peer)?)),
+                    Err(e) => Err(to_io_error(e)),
+                },
+            }
+        };
+
+        match time::timeout(timeout, accept).await {
+            Ok(result) => result,
+            Err(_) => Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                "no inbound connection to SOCKS BIND",
+            )),
+        }
+    }
+}
+
+/// Converts the address in a SOCKS5 reply to a `SocketAddrV4`. Returns an error if the address
+/// is not an IPv4 address.
+fn to_socket_addr_v4(addr: &AddrKind) -> io::Result<SocketAddrV4> {
+    match addr {
+        AddrKind::Ip(SocketAddr::V4(addr)) => Ok(*addr),
+        _ => Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("address {:?} in SOCKS reply not an IPv4 address", addr),
+        )),
+    }
+}
+
+/// Opens a listener of an inbound connection from the server of the given address and port
+/// through a SOCKS proxy.
+pub async fn listen(
+    remote: SocketAddrV4,
+    options: &ConnectOptions,
+    addr: &Address,
+    port: u16,
+    auth: &SocksAuth,
+) -> io::Result<SocksListener> {
+    let stream = connect_remote(remote, options).await?;
+    stream.set_keepalive(options.keepalive)?;
+
+    SocksListener::bind(stream, options.version, addr, port, auth).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Read, Write};
+    use std::net::{IpAddr, TcpListener};
+    use std::thread::{self, JoinHandle};
+
+    /// Spawns a mock proxy accepting a single connection, which is served by the given function.
+    /// Returns the address of the proxy and the handle of the serving thread.
+    fn mock_proxy<F, T>(serve: F) -> (SocketAddrV4, JoinHandle<T>)
+    where
+        F: FnOnce(std::net::TcpStream) -> T + Send + 'static,
+        T: Send + 'static,
+    {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = match listener.local_addr().unwrap() {
+            SocketAddr::V4(addr) => addr,
+            _ => unreachable!(),
+        };
+        let handle = thread::spawn(move || serve(listener.accept().unwrap().0));
+
+        (addr, handle)
+    }
+
+    /// Reads the given number of bytes from the stream.
+    fn read_exact(stream: &mut std::net::TcpStream, n: usize) -> Vec<u8> {
+        let mut buffer = vec![0u8; n];
+        stream.read_exact(&mut buffer).unwrap();
+        buffer
+    }
+
+    /// Serves the method selection and the username/password sub-negotiation of SOCKS5, and
+    /// returns the bytes received.
+    fn serve_user_pass(stream: &mut std::net::TcpStream, status: u8) -> (Vec<u8>, Vec<u8>) {
+        let mut selection = read_exact(stream, 2);
+        let n = selection[1] as usize;
+        selection.extend(read_exact(stream, n));
+        stream.write_all(&[0x05, 0x02]).unwrap();
+
+        let mut negotiation = read_exact(stream, 2);
+        let n = negotiation[1] as usize;
+        negotiation.extend(read_exact(stream, n + 1));
+        let n = negotiation[negotiation.len() - 1] as usize;
+        negotiation.extend(read_exact(stream, n));
+        stream.write_all(&[0x01, status]).unwrap();
+
+        (selection, negotiation)
+    }
+
+    fn user_pass() -> SocksAuth {
+        SocksAuth::UserPass {
+            username: String::from("user"),
+            password: String::from("pass"),
+        }
+    }
+
+    #[tokio::test]
+    async fn connect_user_pass() {
+        let (remote, handle) = mock_proxy(|mut stream| {
+            let (selection, negotiation) = serve_user_pass(&mut stream, 0x00);
+            let request = read_exact(&mut stream, 10);
+            stream
+                .write_all(&[0x05, 0x00, 0x00, 0x01, 0, 0, 0, 0, 0, 0])
+                .unwrap();
+
+            (selection, negotiation, request)
+        });
+
+        let addr = Address::Ipv4(Ipv4Addr::new(93, 184, 216, 34));
+        connect(remote, &ConnectOptions::default(), &addr, 80, &user_pass())
+            .await
+            .unwrap();
+
+        let (selection, negotiation, request) = handle.join().unwrap();
+        // No authentication and username/password
+        assert_eq!(selection, [0x05, 0x02, 0x00, 0x02]);
+        assert_eq!(negotiation, b"\x01\x04user\x04pass");
+        assert_eq!(request, [0x05, 0x01, 0x00, 0x01, 93, 184, 216, 34, 0, 80]);
+    }
+
+    #[tokio::test]
+    async fn connect_user_pass_denied() {
+        let (remote, handle) = mock_proxy(|mut stream| {
+            serve_user_pass(&mut stream, 0x01);
+        });
+
+        let addr = Address::Ipv4(Ipv4Addr::new(93, 184, 216, 34));
+        let e = connect(remote, &ConnectOptions::default(), &addr, 80, &user_pass())
+            .await
+            .unwrap_err();
+
+        handle.join().unwrap();
+        assert_eq!(e.kind(), io::ErrorKind::PermissionDenied);
+    }
+
+    #[tokio::test]
+    async fn connect_bound_local() {
+        let (remote, handle) = mock_proxy(|mut stream| {
+            let peer = stream.peer_addr().unwrap();
+            let mut selection = read_exact(&mut stream, 2);
+            let n = selection[1] as usize;
+            selection.extend(read_exact(&mut stream, n));
+            stream.write_all(&[0x05, 0x00]).unwrap();
+            read_exact(&mut stream, 10);
+            stream
+                .write_all(&[0x05, 0x00, 0x00, 0x01, 0, 0, 0, 0, 0, 0])
+                .unwrap();
+
+            peer
+        });
+
+        // Every address in 127.0.0.0/8 is assigned to the loopback interface
+        let options = ConnectOptions {
+            local: Some(Ipv4Addr::new(127, 0, 0, 2)),
+            ..ConnectOptions::default()
+        };
+        let addr = Address::Ipv4(Ipv4Addr::new(93, 184, 216, 34));
+        connect(remote, &options, &addr, 80, &SocksAuth::None)
+            .await
+            .unwrap();
+
+        let peer = handle.join().unwrap();
+        assert_eq!(peer.ip(), IpAddr::V4(Ipv4Addr::new(127, 0, 0, 2)));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn bind_stream_local() {
+        let stream = bind_stream(Ipv4Addr::LOCALHOST).unwrap();
+
+        // The socket is bound before connecting
+        assert_eq!(
+            stream.local_addr().unwrap().ip(),
+            IpAddr::V4(Ipv4Addr::LOCALHOST)
+        );
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn connect_bound_not_assigned() {
+        // TEST-NET-1 is never assigned
+        let options = ConnectOptions {
+            local: Some(Ipv4Addr::new(192, 0, 2, 1)),
+            ..ConnectOptions::default()
+        };
+        let addr = Address::Ipv4(Ipv4Addr::new(93, 184, 216, 34));
+        let remote = SocketAddrV4::new(Ipv4Addr::LOCALHOST, 1080);
+        let e = connect(remote, &options, &addr, 80, &SocksAuth::None)
+            .await
+            .unwrap_err();
+
+        assert_eq!(e.kind(), io::ErrorKind::AddrNotAvailable);
+        assert!(e.to_string().starts_with("bind local address 192.0.2.1"));
+    }
+
+    /// Returns a local address no one is listening on.
+    fn refused_addr() -> SocketAddrV4 {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        match listener.local_addr().unwrap() {
+            SocketAddr::V4(addr) => addr,
+            _ => unreachable!(),
+        }
+    }
+
+    #[tokio::test]
+    async fn connect_retry() {
+        let remote = refused_addr();
+        // The proxy comes up after a few attempts are refused
+        let handle = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(250));
+            let listener = TcpListener::bind(remote).unwrap();
+            let mut stream = listener.accept().unwrap().0;
+            let mut selection = read_exact(&mut stream, 2);
+            let n = selection[1] as usize;
+            selection.extend(read_exact(&mut stream, n));
+            stream.write_all(&[0x05, 0x00]).unwrap();
+            let request = read_exact(&mut stream, 10);
+            stream
+                .write_all(&[0x05, 0x00, 0x00, 0x01, 0, 0, 0, 0, 0, 0])
+                .unwrap();
+
+            // No other connection is made after the successful one
+            listener.set_nonblocking(true).unwrap();
+            let e = listener.accept().unwrap_err();
+            assert_eq!(e.kind(), std::io::ErrorKind::WouldBlock);
+
+            request
+        });
+
+        let options = ConnectOptions {
+            max_retries: 8,
+            base_delay: Duration::from_millis(50),
+            max_delay: Duration::from_millis(100),
+            jitter: false,
+            ..ConnectOptions::default()
+        };
+        let addr = Address::Ipv4(Ipv4Addr::new(93, 184, 216, 34));
+        let stream = connect(remote, &options, &addr, 80, &SocksAuth::None)
+            .await
+            .unwrap();
+
+        let request = handle.join().unwrap();
+        assert_eq!(request, [0x05, 0x01, 0x00, 0x01, 93, 184, 216, 34, 0, 80]);
+        drop(stream);
+    }
+
+    #[tokio::test]
+    async fn connect_retry_exhausted() {
+        let remote = refused_addr();
+        let options = ConnectOptions {
+            max_retries: 2,
+            base_delay: Duration::from_millis(50),
+            jitter: false,
+            ..ConnectOptions::default()
+        };
+        let addr = Address::Ipv4(Ipv4Addr::new(93, 184, 216, 34));
+        let instant = std::time::Instant::now();
+        let e = connect(remote, &options, &addr, 80, &SocksAuth::None)
+            .await
+            .unwrap_err();
+
+        // 2 retries are delayed by 50 and 100 ms respectively
+        assert_eq!(e.kind(), io::ErrorKind::ConnectionRefused);
+        assert!(instant.elapsed() >= Duration::from_millis(150));
+    }
+
+    #[test]
+    fn encode_decode_udp_datagram_ipv4() {
+        let addr = Address::Ipv4(Ipv4Addr::new(8, 8, 8, 8));
+        let datagram = encode_udp_datagram(&addr, 53, b"query").unwrap();
+        assert_eq!(datagram, b"\x00\x00\x00\x01\x08\x08\x08\x08\x00\x35query");
+
+        let (decoded, port, n) = decode_udp_datagram(&datagram).unwrap().unwrap();
+        assert_eq!(decoded, addr);
+        assert_eq!(port, 53);
+        assert_eq!(&datagram[n..], b"query");
+    }
+
+    #[test]
+    fn encode_decode_udp_datagram_domain() {
+        let addr = Address::Domain(String::from("example.com"));
+        let datagram = encode_udp_datagram(&addr, 443, b"hello").unwrap();
+        assert_eq!(datagram, b"\x00\x00\x00\x03\x0bexample.com\x01\xbbhello");
+
+        let (decoded, port, n) = decode_udp_datagram(&datagram).unwrap().unwrap();
+        assert_eq!(decoded, addr);
+        assert_eq!(port, 443);
+        assert_eq!(&datagram[n..], b"hello");
+    }
+
+    #[test]
+    fn decode_udp_datagram_fragment() {
+        let mut datagram =
+            encode_udp_datagram(&Address::Ipv4(Ipv4Addr::new(8, 8, 8, 8)), 53, b"query").unwrap();
+        datagram[2] = 1;
+
+        assert!(decode_udp_datagram(&datagram).unwrap().is_none());
+    }
+
+    #[test]
+    fn decode_udp_datagram_truncated() {
+        let e = decode_udp_datagram(&[0x00, 0x00, 0x00, 0x01, 0x08, 0x08]).unwrap_err();
+
+        assert_eq!(e.kind(), io::ErrorKind::UnexpectedEof);
+    }
+
+    /// Serializes the given address into a byte-array.
+    fn serialize_address(addr: &Address) -> io::Result<Vec<u8>> {
+        let mut buffer = vec![0u8; addr.get_size()];
+        addr.serialize(&mut buffer)?;
+
+        Ok(buffer)
+    }
+
+    #[test]
+    fn serialize_address_ipv4() {
+        let addr = Address::Ipv4(Ipv4Addr::new(192, 168, 1, 1));
+
+        assert_eq!(serialize_address(&addr).unwrap(), [0x01, 192, 168, 1, 1]);
+    }
+
+    #[test]
+    fn serialize_address_ipv6() {
+        let addr = Address::Ipv6("2001:db8::1".parse().unwrap());
+
+        assert_eq!(
+            serialize_address(&addr).unwrap(),
+            [
+                0x04, 0x20, 0x01, 0x0d, 0xb8, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+                0x00, 0x00, 0x01
+            ]
+        );
+    }
+
+    #[test]
+    fn serialize_address_domain() {
+        let addr = Address::Domain(String::from("example.com"));
+
+        assert_eq!(serialize_address(&addr).unwrap(), b"\x03\x0bexample.com");
+    }
+
+    #[test]
+    fn serialize_address_domain_too_long() {
+        let addr = Address::Domain("a".repeat(MAX_DOMAIN_LENGTH + 1));
+
+        let e = serialize_address(&addr).unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::InvalidInput);
+        assert!(addr.to_addr_kind(80).is_err());
+    }
+
+    #[test]
+    fn encode_socks4_request_ipv4() {
+        let addr = Address::Ipv4(Ipv4Addr::new(93, 184, 216, 34));
+
+        assert_eq!(
+            encode_socks4_request(&addr, 80, "user").unwrap(),
+            b"\x04\x01\x00\x50\x5d\xb8\xd8\x22user\x00"
+        );
+        assert_eq!(
+            encode_socks4_request(&addr, 443, "").unwrap(),
+            [0x04, 0x01, 0x01, 0xbb, 93, 184, 216, 34, 0x00]
+        );
+    }
+
+    #[test]
+    fn encode_socks4a_request() {
+        let addr = Address::Domain(String::from("example.com"));
+
+        assert_eq!(
+            encode_socks4_request(&addr, 80, "user").unwrap(),
+            b"\x04\x01\x00\x50\x00\x00\x00\x01user\x00example.com\x00"
+        );
+    }
+
+    #[test]
+    fn encode_socks4_request_invalid() {
+        let addr = Address::Ipv6("2001:db8::1".parse().unwrap());
+        assert_eq!(
+            encode_socks4_request(&addr, 80, "").unwrap_err().kind(),
+            io::ErrorKind::InvalidInput
+        );
+
+        let addr = Address::Ipv4(Ipv4Addr::new(93, 184, 216, 34));
+        assert!(encode_socks4_request(&addr, 80, "us\0er").is_err());
+        let addr = Address::Domain(String::from("example\0.com"));
+        assert!(encode_socks4_request(&addr, 80, "").is_err());
+    }
+
+    #[test]
+    fn decode_socks4_reply_granted() {
+        let reply = [0x00, 0x5a, 0x04, 0x38, 192, 0, 2, 10];
+
+        assert_eq!(
+            decode_socks4_reply(&reply).unwrap(),
+            SocketAddrV4::new(Ipv4Addr::new(192, 0, 2, 10), 1080)
+        );
+    }
+
+    #[test]
+    fn decode_socks4_reply_rejected() {
+        let kind = |code: u8| {
+            decode_socks4_reply(&[0x00, code, 0, 0, 0, 0, 0, 0])
+                .unwrap_err()
+                .kind()
+        };
+
+        assert_eq!(kind(0x5b), io::ErrorKind::ConnectionRefused);
+        assert_eq!(kind(0x5c), io::ErrorKind::PermissionDenied);
+        assert_eq!(kind(0x5d), io::ErrorKind::PermissionDenied);
+        assert_eq!(kind(0x42), io::ErrorKind::InvalidData);
+        // An invalid version or a truncated reply
+        assert_eq!(
+            decode_socks4_reply(&[0x04, 0x5a, 0, 0, 0, 0, 0, 0])
+                .unwrap_err()
+                .kind(),
+            io::ErrorKind::InvalidData
+        );
+        assert_eq!(
+            decode_socks4_reply(&[0x00, 0x5a, 0, 0]).unwrap_err().kind(),
+            io::ErrorKind::UnexpectedEof
+        );
+    }
+
+    #[tokio::test]
+    async fn connect_socks4() {
+        let (remote, handle) = mock_proxy(|mut stream| {
+            let request = read_exact(&mut stream, 13);
+            stream.write_all(&[0x00, 0x5a, 0, 0, 0, 0, 0, 0]).unwrap();
+
+            request
+        });
+
+        let options = ConnectOptions {
+            version: SocksVersion::V4,
+            ..ConnectOptions::default()
+        };
+        let addr = Address::Ipv4(Ipv4Addr::new(93, 184, 216, 34));
+        connect(remote, &options, &addr, 80, &user_pass())
+            .await
+            .unwrap();
+
+        // The username is sent as the user ID
+        let request = handle.join().unwrap();
+        assert_eq!(request, b"\x04\x01\x00\x50\x5d\xb8\xd8\x22user\x00");
+    }
+
+    #[tokio::test]
+    async fn connect_socks4_rejected() {
+        let (remote, handle) = mock_proxy(|mut stream| {
+            read_exact(&mut stream, 9);
+            stream.write_all(&[0x00, 0x5b, 0, 0, 0, 0, 0, 0]).unwrap();
+        });
+
+        let options = ConnectOptions {
+            version: SocksVersion::V4,
+            ..ConnectOptions::default()
+        };
+        let addr = Address::Ipv4(Ipv4Addr::new(93, 184, 216, 34));
+        let e = connect(remote, &options, &addr, 80, &SocksAuth::None)
+            .await
+            .unwrap_err();
+
+        handle.join().unwrap();
+        assert_eq!(e.kind(), io::ErrorKind::ConnectionRefused);
+    }
+
+    #[tokio::test]
+    async fn bind_socks4_unsupported() {
+        let options = ConnectOptions {
+            version: SocksVersion::V4,
+            ..ConnectOptions::default()
+        };
+        let remote = SocketAddrV4::new(Ipv4Addr::LOCALHOST, 1080);
+
+        assert!(bind(remote, &options, &SocksAuth::None).await.is_err());
+    }
+
+    /// Serves the method selection without authentication and a BIND request of SOCKS5, replies
+    /// the first reply of the given bound address, and returns the request.
+    fn serve_bind(stream: &mut std::net::TcpStream, bound: SocketAddrV4) -> Vec<u8> {
+        let selection = read_exact(stream, 2);
+        read_exact(stream, selection[1] as usize);
+        stream.write_all(&[0x05, 0x00]).unwrap();
+        let request = read_exact(stream, 10);
+        let mut reply = vec![0x05, 0x00, 0x00, 0x01];
+        reply.extend_from_slice(&bound.ip().octets());
+        reply.extend_from_slice(&bound.port().to_be_bytes());
+        stream.write_all(&reply).unwrap();
+
+        request
+    }
+
+    #[tokio::test]
+    async fn listen_bind() {
+        let (remote, handle) = mock_proxy(|mut stream| {
+            // An unspecified IP address is of the proxy
+            let request = serve_bind(&mut stream, SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, 2000));
+            stream
+                .write_all(&[0x05, 0x00, 0x00, 0x01, 93, 184, 216, 34, 0, 20])
+                .unwrap();
+            stream.write_all(b"data").unwrap();
+
+            request
+        });
+
+        let addr = Address::Ipv4(Ipv4Addr::new(93, 184, 216, 34));
+        let listener = listen(
+            remote,
+            &ConnectOptions::default(),
+            &addr,
+            20,
+            &SocksAuth::None,
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            listener.get_bound_addr(),
+            SocketAddrV4::new(Ipv4Addr::LOCALHOST, 2000)
+        );
+        let (mut stream, peer) = listener.accept(Duration::from_secs(1)).await.unwrap();
+        assert_eq!(peer, SocketAddrV4::new(Ipv4Addr::new(93, 184, 216, 34), 20));
+        // No data after the second reply is read ahead
+        let mut data = Vec::new();
+        stream.read_to_end(&mut data).await.unwrap();
+        assert_eq!(data, b"data");
+
+        let request = handle.join().unwrap();
+        assert_eq!(request, [0x05, 0x02, 0x00, 0x01, 93, 184, 216, 34, 0, 20]);
+    }
+
+    #[tokio::test]
+    async fn listen_bind_socks4() {
+        let (remote, handle) = mock_proxy(|mut stream| {
+            let request = read_exact(&mut stream, 9);
+            stream
+                .write_all(&[0x00, 0x5a, 0x07, 0xd0, 127, 0, 0, 1])
+                .unwrap();
+            stream
+                .write_all(&[0x00, 0x5a, 0, 20, 93, 184, 216, 34])
+                .unwrap();
+
+            request
+        });
+
+        let options = ConnectOptions {
+            version: SocksVersion::V4,
+            ..ConnectOptions::default()
+        };
+        let addr = Address::Ipv4(Ipv4Addr::new(93, 184, 216, 34));
+        let listener = listen(remote, &options, &addr, 20, &SocksAuth::None)
+            .await
+            .unwrap();
+        assert_eq!(
+            listener.get_bound_addr(),
+            SocketAddrV4::new(Ipv4Addr::LOCALHOST, 2000)
+        );
+        let (_, peer) = listener.accept(Duration::from_secs(1)).await.unwrap();
+        assert_eq!(peer, SocketAddrV4::new(Ipv4Addr::new(93, 184, 216, 34), 20));
+
+        let request = handle.join().unwrap();
+        assert_eq!(request, b"\x04\x02\x00\x14\x5d\xb8\xd8\x22\x00");
+    }
+
+    #[tokio::test]
+    async fn listen_bind_socks4_rejected() {
+        let (remote, handle) = mock_proxy(|mut stream| {
+            read_exact(&mut stream, 9);
+            stream.write_all(&[0x00, 0x5b, 0, 0, 0, 0, 0, 0]).unwrap();
+        });
+
+        let options = ConnectOptions {
+            version: SocksVersion::V4,
+            ..ConnectOptions::default()
+        };
+        let addr = Address::Ipv4(Ipv4Addr::new(93, 184, 216, 34));
+        let result = listen(remote, &options, &addr, 20, &SocksAuth::None).await;
+
+        handle.join().unwrap();
+        assert_eq!(
+            result.err().unwrap().kind(),
+            io::ErrorKind::ConnectionRefused
+        );
+    }
+
+    #[tokio::test]
+    async fn listen_accept_timeout() {
+        let (remote, handle) = mock_proxy(|mut stream| {
+            serve_bind(&mut stream, SocketAddrV4::new(Ipv4Addr::LOCALHOST, 2000));
+
+            // The connection is kept open without the second reply
+            stream
+        });
+
+        let addr = Address::Ipv4(Ipv4Addr::new(93, 184, 216, 34));
+        let listener = listen(
+            remote,
+            &ConnectOptions::default(),
+            &addr,
+            20,
+            &SocksAuth::None,
+        )
+        .await
+        .unwrap();
+        let e = listener
+            .accept(Duration::from_millis(100))
+            .await
+            .unwrap_err();
+
+        handle.join().unwrap();
+        assert_eq!(e.kind(), io::ErrorKind::TimedOut);
+    }
+}
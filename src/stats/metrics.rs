@@ -0,0 +1,536 @@
+use super::Stats;
+use crate::packet::layer::tcp::state::FlowStat;
+use log::{trace, warn};
+use lru::LruCache;
+use std::collections::HashMap;
+use std::fmt::Write as FmtWrite;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{Ipv4Addr, SocketAddr, SocketAddrV4, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+/// Represents the path of the metrics endpoint.
+const METRICS_PATH: &str = "/metrics";
+/// Represents the timeout of reading a request or writing a response of the metrics endpoint.
+const METRICS_TIMEOUT: Duration = Duration::from_secs(5);
+/// Represents the max size of a request of the metrics endpoint, including the headers.
+const MAX_REQUEST_SIZE: u64 = 8192;
+/// Represents the max number of requests of the metrics endpoint served at a time. Connections
+/// beyond are closed without a response.
+const MAX_CONCURRENT_REQUESTS: usize = 8;
+
+/// Represents the max number of destinations whose totals are kept. The least recently used
+/// destination is evicted beyond.
+pub const DEFAULT_DESTINATION_CAPACITY: usize = 1024;
+
+/// Represents the bytes transferred in each TCP connection and the totals of each destination.
+#[derive(Debug)]
+struct Transfers {
+    connections: usize,
+    last: HashMap<(SocketAddrV4, SocketAddrV4), (u64, u64)>,
+    destinations: LruCache<Ipv4Addr, (u64, u64)>,
+}
+
+impl Transfers {
+    fn new(capacity: usize) -> Transfers {
+        Transfers {
+            connections: 0,
+            last: HashMap::new(),
+            destinations: LruCache::new(capacity),
+        }
+    }
+
+    fn add(&mut self, key: (SocketAddrV4, SocketAddrV4), up_bytes: u64, down_bytes: u64) {
+        let (last_up_bytes, last_down_bytes) = self.last.remove(&key).unwrap_or((0, 0));
+        let up_bytes = up_bytes.saturating_sub(last_up_bytes);
+        let down_bytes = down_bytes.saturating_sub(last_down_bytes);
+        match self.destinations.get_mut(key.1.ip()) {
+            Some(totals) => {
+                totals.0 += up_bytes;
+                totals.1 += down_bytes;
+            }
+            None => {
+                self.destinations.put(*key.1.ip(), (up_bytes, down_bytes));
+            }
+        }
+    }
+}
+
+/// Represents the metrics of a redirector exported in the Prometheus text exposition format.
+#[derive(Debug)]
+pub struct Metrics {
+    interface: String,
+    stats: Arc<Stats>,
+    transfers: Mutex<Transfers>,
+}
+
+impl Metrics {
+    /// Creates a new `Metrics` of the redirector on the given interface with its statistics.
+    pub fn new(interface: &str, stats: Arc<Stats>) -> Metrics {
+        Metrics::with_capacity(interface, stats, DEFAULT_DESTINATION_CAPACITY)
+    }
+
+    /// Creates a new `Metrics` of the redirector on the given interface with its statistics,
+    /// keeping the totals of at most the given number of destinations.
+    pub fn with_capacity(interface: &str, stats: Arc<Stats>, capacity: usize) -> Metrics {
+        Metrics {
+            interface: interface.to_string(),
+            stats,
+            transfers: Mutex::new(Transfers::new(capacity)),
+        }
+    }
+
+    /// Updates the metrics with a snapshot of the TCP connections tracked. The bytes transferred
+    /// since the last update are added to the totals of each destination.
+    pub fn update(&self, flows: &[FlowStat<(SocketAddrV4, SocketAddrV4)>]) {
+        let mut transfers = self.transfers.lock().unwrap();
+        let mut last = HashMap::with_capacity(flows.len());
+        for flow in flows {
+            transfers.add(flow.key, flow.up_bytes, flow.down_bytes);
+            last.insert(flow.key, (flow.up_bytes, flow.down_bytes));
+        }
+        transfers.last = last;
+        transfers.connections = flows.len();
+    }
+
+    /// Updates the metrics with the final bytes transferred in the given TCP connection, which is
+    /// closed.
+    pub fn close(&self, key: (SocketAddrV4, SocketAddrV4), up_bytes: u64, down_bytes: u64) {
+        let mut transfers = self.transfers.lock().unwrap();
+        transfers.add(key, up_bytes, down_bytes);
+    }
+
+    /// Get the total bytes sent to and received from the given destination.
+    pub fn get_destination_bytes(&self, ip_addr: Ipv4Addr) -> (u64, u64) {
+        *self
+            .transfers
+            .lock()
+            .unwrap()
+            .destinations
+            .peek(&ip_addr)
+            .unwrap_or(&(0, 0))
+    }
+}
+
+/// Escapes the given label value.
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+/// Converts the given name into the form of a label value.
+fn to_label(name: &str) -> String {
+    name.to_ascii_lowercase()
+        .replace(|c: char| !c.is_ascii_alphanumeric(), "_")
+}
+
+/// Renders the given metrics in the Prometheus text exposition format.
+pub fn render(metrics: &[Arc<Metrics>]) -> String {
+    let mut s = String::new();
+
+    s.push_str("# HELP pcap2socks_packets_seen_total Packets seen of each layer.\n");
+    s.push_str("# TYPE pcap2socks_packets_seen_total counter\n");
+    for m in metrics {
+        for (t, n) in m.stats.snapshot().seen {
+            let _ = writeln!(
+                s,
+                "pcap2socks_packets_seen_total{{interface=\"{}\",layer=\"{}\"}} {}",
+                escape(&m.interface),
+                to_label(&t.to_string()),
+                n
+            );
+        }
+    }
+
+    s.push_str("# HELP pcap2socks_packets_forwarded_total Packets forwarded of each layer.\n");
+    s.push_str("# TYPE pcap2socks_packets_forwarded_total counter\n");
+    for m in metrics {
+        for (t, n) in m.stats.snapshot().forwarded {
+            let _ = writeln!(
+                s,
+                "pcap2socks_packets_forwarded_total{{interface=\"{}\",layer=\"{}\"}} {}",
+                escape(&m.interface),
+                to_label(&t.to_string()),
+                n
+            );
+        }
+    }
+
+    s.push_str("# HELP pcap2socks_packets_dropped_total Packets dropped of each reason.\n");
+    s.push_str("# TYPE pcap2socks_packets_dropped_total counter\n");
+    for m in metrics {
+        for (reason, n) in m.stats.snapshot().dropped {
+            let _ = writeln!(
+                s,
+                "pcap2socks_packets_dropped_total{{interface=\"{}\",reason=\"{}\"}} {}",
+                escape(&m.interface),
+                to_label(&reason.to_string()),
+                n
+            );
+        }
+    }
+
+    s.push_str("# HELP pcap2socks_parse_errors_total Layers failed to be parsed of each layer.\n");
+    s.push_str("# TYPE pcap2socks_parse_errors_total counter\n");
+    for m in metrics {
+        for (t, n) in m.stats.snapshot().parse_errors {
+            let _ = writeln!(
+                s,
+                "pcap2socks_parse_errors_total{{interface=\"{}\",layer=\"{}\"}} {}",
+                escape(&m.interface),
+                to_label(&t.to_string()),
+                n
+            );
+        }
+    }
+
+    s.push_str("# HELP pcap2socks_tcp_connections TCP connections in the connection table.\n");
+    s.push_str("# TYPE pcap2socks_tcp_connections gauge\n");
+    for m in metrics {
+        let _ = writeln!(
+            s,
+            "pcap2socks_tcp_connections{{interface=\"{}\"}} {}",
+            escape(&m.interface),
+            m.transfers.lock().unwrap().connections
+        );
+    }
+
+    s.push_str(
+        "# HELP pcap2socks_tcp_response_latency_seconds Latencies between TCP requests and responses.\n",
+    );
+    s.push_str("# TYPE pcap2socks_tcp_response_latency_seconds summary\n");
+    for m in metrics {
+        let latency = m.stats.get_latency();
+        for quantile in &[0.5, 0.9, 0.99] {
+            if let Some(value) = latency.get_percentile(quantile * 100.0) {
+                let _ = writeln!(
+                    s,
+                    "pcap2socks_tcp_response_latency_seconds{{interface=\"{}\",quantile=\"{}\"}} {}",
+                    escape(&m.interface),
+                    quantile,
+                    value.as_secs_f64()
+                );
+            }
+        }
+        let _ = writeln!(
+            s,
+            "pcap2socks_tcp_response_latency_seconds_sum{{interface=\"{}\"}} {}",
+            escape(&m.interface),
+            latency.get_sum().as_secs_f64()
+        );
+        let _ = writeln!(
+            s,
+            "pcap2socks_tcp_response_latency_seconds_count{{interface=\"{}\"}} {}",
+            escape(&m.interface),
+            latency.get_count()
+        );
+    }
+
+    s.push_str(
+        "# HELP pcap2socks_destination_bytes_total TCP bytes transferred of each destination.\n",
+    );
+    s.push_str("# TYPE pcap2socks_destination_bytes_total counter\n");
+    for m in metrics {
+        let transfers = m.transfers.lock().unwrap();
+        let mut destinations: Vec<_> = transfers.destinations.iter().collect();
+        destinations.sort();
+        for (ip_addr, (up_bytes, down_bytes)) in destinations {
+            for (direction, n) in &[("up", up_bytes), ("down", down_bytes)] {
+                let _ = writeln!(
+                    s,
+                    "pcap2socks_destination_bytes_total{{interface=\"{}\",destination=\"{}\",direction=\"{}\"}} {}",
+                    escape(&m.interface),
+                    ip_addr,
+                    direction,
+                    n
+                );
+            }
+        }
+    }
+
+    s
+}
+
+/// Handles the given HTTP request line, and returns the status and the body of the response.
+/// Only `GET` of the metrics path is served.
+pub fn handle(request_line: &str, metrics: &[Arc<Metrics>]) -> (&'static str, String) {
+    let mut parts = request_line.split_whitespace();
+    let method = parts.next().unwrap_or_default();
+    let path = parts.next().unwrap_or_default();
+    let path = path.split('?').next().unwrap_or_default();
+
+    match (method, path) {
+        ("GET", METRICS_PATH) => ("200 OK", render(metrics)),
+        ("GET", _) => ("404 Not Found", String::from("not found\n")),
+        _ => (
+            "405 Method Not Allowed",
+            String::from("method not allowed\n"),
+        ),
+    }
+}
+
+fn serve_connection(stream: TcpStream, metrics: &[Arc<Metrics>]) -> io::Result<()> {
+    stream.set_read_timeout(Some(METRICS_TIMEOUT))?;
+    stream.set_write_timeout(Some(METRICS_TIMEOUT))?;
+
+    // The request is capped in case of a client blocking the endpoint with an endless request
+    let mut reader = BufReader::new(stream.take(MAX_REQUEST_SIZE));
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+    // Skip headers
+    let mut is_complete = request_line.ends_with('\n');
+    while is_complete {
+        let mut line = String::new();
+        if reader.read_line(&mut line)? == 0 || line.trim().is_empty() {
+            break;
+        }
+        is_complete = line.ends_with('\n');
+    }
+    // The request is incomplete only if the cap is hit
+    let is_complete = is_complete || reader.get_ref().limit() > 0;
+
+    let (status, body) = match is_complete {
+        true => handle(&request_line, metrics),
+        false => (
+            "431 Request Header Fields Too Large",
+            String::from("request too large\n"),
+        ),
+    };
+    let mut stream = reader.into_inner().into_inner();
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    )?;
+
+    stream.flush()
+}
+
+/// Serves the given metrics on the given address in a new thread. Each connection is served in
+/// a thread of its own, so a slow client does not stall the others. The endpoint has no
+/// authentication, and should be bound to a loopback address.
+pub fn serve(addr: SocketAddr, metrics: Vec<Arc<Metrics>>) -> io::Result<thread::JoinHandle<()>> {
+    let listener = TcpListener::bind(addr)?;
+    let metrics = Arc::new(metrics);
+    let active = Arc::new(AtomicUsize::new(0));
+
+    Ok(thread::spawn(move || {
+        for stream in listener.incoming() {
+            match stream {
+                Ok(stream) => {
+                    if let Ok(peer) = stream.peer_addr() {
+                        trace!("metrics request from {}", peer);
+                    }
+                    if active.fetch_add(1, Ordering::Relaxed) >= MAX_CONCURRENT_REQUESTS {
+                        active.fetch_sub(1, Ordering::Relaxed);
+                        warn!("metrics: too many requests");
+                        continue;
+                    }
+
+                    let metrics = Arc::clone(&metrics);
+                    let active = Arc::clone(&active);
+                    thread::spawn(move || {
+                        if let Err(ref e) = serve_connection(stream, &metrics) {
+                            warn!("metrics: {}", e);
+                        }
+                        active.fetch_sub(1, Ordering::Relaxed);
+                    });
+                }
+                Err(ref e) => warn!("metrics: {}", e),
+            }
+        }
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::packet::layer::tcp::state::State;
+    use crate::packet::layer::LayerTypes;
+    use crate::stats::DropReason;
+
+    /// Creates a snapshot of an established TCP connection.
+    fn flow(
+        dst: Ipv4Addr,
+        up_bytes: u64,
+        down_bytes: u64,
+    ) -> FlowStat<(SocketAddrV4, SocketAddrV4)> {
+        FlowStat {
+            key: (
+                SocketAddrV4::new(Ipv4Addr::new(10, 6, 0, 1), 1024),
+                SocketAddrV4::new(dst, 80),
+            ),
+            state: State::Established,
+            up_bytes,
+            up_packets: 1,
+            down_bytes,
+            down_packets: 1,
+        }
+    }
+
+    #[test]
+    fn render_counters() {
+        let stats = Arc::new(Stats::new());
+        stats.add_seen(LayerTypes::Tcp);
+        stats.add_seen(LayerTypes::Tcp);
+        stats.add_forwarded(LayerTypes::Tcp);
+        stats.add_dropped(DropReason::ChecksumMismatch, 3);
+        let metrics = Arc::new(Metrics::new("eth\"0", stats));
+        metrics.update(&[flow(Ipv4Addr::new(93, 184, 216, 34), 100, 2000)]);
+
+        let s = render(&[metrics]);
+        assert!(s.contains("# TYPE pcap2socks_packets_seen_total counter\n"));
+        assert!(
+            s.contains("pcap2socks_packets_seen_total{interface=\"eth\\\"0\",layer=\"tcp\"} 2\n")
+        );
+        assert!(s.contains(
+            "pcap2socks_packets_forwarded_total{interface=\"eth\\\"0\",layer=\"tcp\"} 1\n"
+        ));
+        assert!(s.contains(
+            "pcap2socks_packets_dropped_total{interface=\"eth\\\"0\",reason=\"checksum_mismatch\"} 3\n"
+        ));
+        assert!(s.contains("pcap2socks_tcp_connections{interface=\"eth\\\"0\"} 1\n"));
+        assert!(
+            s.contains("pcap2socks_tcp_response_latency_seconds_count{interface=\"eth\\\"0\"} 0\n")
+        );
+        assert!(s.contains("destination=\"93.184.216.34\",direction=\"up\"} 100\n"));
+        assert!(s.contains("destination=\"93.184.216.34\",direction=\"down\"} 2000\n"));
+    }
+
+    #[test]
+    fn render_parse_errors() {
+        let stats = Arc::new(Stats::new());
+        stats.add_parse_error(LayerTypes::Tcp);
+        stats.add_dropped(DropReason::Truncated, 2);
+        let metrics = Arc::new(Metrics::new("eth0", stats));
+
+        let s = render(&[metrics]);
+        assert!(s.contains("# TYPE pcap2socks_parse_errors_total counter\n"));
+        assert!(s.contains("pcap2socks_parse_errors_total{interface=\"eth0\",layer=\"tcp\"} 1\n"));
+        assert!(s.contains("pcap2socks_parse_errors_total{interface=\"eth0\",layer=\"udp\"} 0\n"));
+        assert!(s.contains(
+            "pcap2socks_packets_dropped_total{interface=\"eth0\",reason=\"truncated\"} 2\n"
+        ));
+    }
+
+    #[test]
+    fn update_adds_deltas() {
+        let dst = Ipv4Addr::new(93, 184, 216, 34);
+        let metrics = Metrics::new("eth0", Arc::new(Stats::new()));
+        metrics.update(&[flow(dst, 100, 2000)]);
+        metrics.update(&[flow(dst, 150, 2000)]);
+        assert_eq!(metrics.get_destination_bytes(dst), (150, 2000));
+
+        // The final bytes of a closed connection are added once
+        metrics.close(flow(dst, 0, 0).key, 200, 3000);
+        metrics.update(&[]);
+        assert_eq!(metrics.get_destination_bytes(dst), (200, 3000));
+        assert_eq!(metrics.transfers.lock().unwrap().connections, 0);
+    }
+
+    #[test]
+    fn destination_capacity() {
+        let metrics = Metrics::with_capacity("eth0", Arc::new(Stats::new()), 2);
+        for i in 1..=3 {
+            let flow = flow(Ipv4Addr::new(192, 0, 2, i), 10, 10);
+            metrics.close(flow.key, 10, 10);
+        }
+
+        // The least recently used destination is evicted
+        assert_eq!(metrics.transfers.lock().unwrap().destinations.len(), 2);
+        assert_eq!(
+            metrics.get_destination_bytes(Ipv4Addr::new(192, 0, 2, 1)),
+            (0, 0)
+        );
+        assert_eq!(
+            metrics.get_destination_bytes(Ipv4Addr::new(192, 0, 2, 3)),
+            (10, 10)
+        );
+    }
+
+    #[test]
+    fn handle_request_line() {
+        let metrics = vec![Arc::new(Metrics::new("eth0", Arc::new(Stats::new())))];
+        let (status, body) = handle("GET /metrics?debug=1 HTTP/1.1\r\n", &metrics);
+        assert_eq!(status, "200 OK");
+        assert!(body.contains("pcap2socks_tcp_connections{interface=\"eth0\"} 0\n"));
+        assert_eq!(handle("GET / HTTP/1.1\r\n", &metrics).0, "404 Not Found");
+        assert_eq!(
+            handle("POST /metrics HTTP/1.1\r\n", &metrics).0,
+            "405 Method Not Allowed"
+        );
+    }
+
+    /// Sends the given request to the endpoint serving the given metrics, and returns the
+    /// response.
+    fn request(metrics: Vec<Arc<Metrics>>, request: &[u8]) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        drop(listener);
+        serve(addr, metrics).unwrap();
+
+        let mut stream = TcpStream::connect(addr).unwrap();
+        // The endpoint may close the connection before the request is written fully
+        let _ = stream.write_all(request);
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+
+        response
+    }
+
+    #[test]
+    fn serve_metrics() {
+        let stats = Arc::new(Stats::new());
+        stats.add_seen(LayerTypes::Udp);
+        let metrics = vec![Arc::new(Metrics::new("eth0", stats))];
+        let response = request(
+            metrics,
+            b"GET /metrics HTTP/1.1\r\nHost: localhost\r\nAccept: */*\r\n\r\n",
+        );
+
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.contains("Content-Type: text/plain; version=0.0.4\r\n"));
+        assert!(response
+            .contains("pcap2socks_packets_seen_total{interface=\"eth0\",layer=\"udp\"} 1\n"));
+    }
+
+    #[test]
+    fn serve_slow_client() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        drop(listener);
+        let metrics = vec![Arc::new(Metrics::new("eth0", Arc::new(Stats::new())))];
+        serve(addr, metrics).unwrap();
+
+        // A client which never completes its request
+        let mut slow = TcpStream::connect(addr).unwrap();
+        slow.write_all(b"GET /metrics HTTP/1.1\r\n").unwrap();
+
+        // Another client is served meanwhile
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream.set_read_timeout(Some(METRICS_TIMEOUT / 2)).unwrap();
+        stream.write_all(b"GET /metrics HTTP/1.1\r\n\r\n").unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+    }
+
+    #[test]
+    fn serve_request_too_large() {
+        let metrics = vec![Arc::new(Metrics::new("eth0", Arc::new(Stats::new())))];
+        // The header never ends within the cap, and is not followed by anything unread which
+        // would reset the connection
+        let mut req = b"GET /metrics HTTP/1.1\r\nCookie: ".to_vec();
+        req.resize(MAX_REQUEST_SIZE as usize, b'a');
+        let response = request(metrics, &req);
+
+        assert!(response.starts_with("HTTP/1.1 431 Request Header Fields Too Large\r\n"));
+    }
+}
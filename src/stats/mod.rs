@@ -0,0 +1,508 @@
+use crate::packet::layer::{LayerType, LayerTypes};
+use std::fmt::{self, Display, Formatter};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+#[cfg(feature = "metrics")]
+pub mod metrics;
+
+/// Represents the layer types counted in `Stats`.
+const LAYER_TYPES: [LayerType; 12] = [
+    LayerTypes::Ethernet,
+    LayerTypes::Sll,
+    LayerTypes::Arp,
+    LayerTypes::Ipv4,
+    LayerTypes::Ipv6,
+    LayerTypes::Icmp,
+    LayerTypes::Icmpv6,
+    LayerTypes::Igmp,
+    LayerTypes::Gre,
+    LayerTypes::Tcp,
+    LayerTypes::Udp,
+    LayerTypes::Unknown,
+];
+
+/// Represents the reason of dropping a packet.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum DropReason {
+    /// The checksum of the packet does not match its content.
+    ChecksumMismatch,
+    /// The packet cannot be parsed.
+    Malformed,
+    /// The protocol of the packet is not supported.
+    Unsupported,
+    /// The fragments of the packet are not completed before timeout.
+    ReassemblyTimeout,
+    /// The destination of the packet is routed to be dropped.
+    Route,
+    /// The time to live of the packet is exceeded.
+    TtlExceeded,
+    /// The packet opens a connection over the connection limit.
+    ConnectionLimit,
+    /// The packet is rejected by the filter.
+    Filtered,
+    /// The handshake of the connection is not completed before timeout.
+    HalfOpenTimeout,
+    /// The packet is from or to the proxy, which would be redirected to the proxy again.
+    Loopback,
+    /// The packet is a multicast control message, e.g., IGMP, which is not routed.
+    MulticastControl,
+    /// The packet exceeds the MTU and cannot be fragmented.
+    FragmentationNeeded,
+    /// The source address of the packet cannot appear on the wire.
+    Martian,
+    /// The packet is truncated by the snaplen of the capture.
+    Truncated,
+    /// The packet duplicates a UDP datagram seen recently.
+    Duplicate,
+    /// The packet cannot be sent because the buffer pool is exhausted.
+    BufferExhausted,
+}
+
+/// Represents the drop reasons counted in `Stats`.
+const DROP_REASONS: [DropReason; 16] = [
+    DropReason::ChecksumMismatch,
+    DropReason::Malformed,
+    DropReason::Unsupported,
+    DropReason::ReassemblyTimeout,
+    DropReason::Route,
+    DropReason::TtlExceeded,
+    DropReason::ConnectionLimit,
+    DropReason::Filtered,
+    DropReason::HalfOpenTimeout,
+    DropReason::Loopback,
+    DropReason::MulticastControl,
+    DropReason::FragmentationNeeded,
+    DropReason::Martian,
+    DropReason::Truncated,
+    DropReason::Duplicate,
+    DropReason::BufferExhausted,
+];
+
+impl Display for DropReason {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}",
+            match self {
+                DropReason::ChecksumMismatch => "checksum mismatch",
+                DropReason::Malformed => "malformed",
+                DropReason::Unsupported => "unsupported protocol",
+                DropReason::ReassemblyTimeout => "reassembly timeout",
+                DropReason::Route => "routed to drop",
+                DropReason::TtlExceeded => "TTL exceeded",
+                DropReason::ConnectionLimit => "connection limit",
+                DropReason::Filtered => "filtered",
+                DropReason::HalfOpenTimeout => "half-open timeout",
+                DropReason::Loopback => "loopback",
+                DropReason::MulticastControl => "multicast control",
+                DropReason::FragmentationNeeded => "fragmentation needed",
+                DropReason::Martian => "martian source",
+                DropReason::Truncated => "truncated",
+                DropReason::Duplicate => "duplicate",
+                DropReason::BufferExhausted => "buffer exhausted",
+            }
+        )
+    }
+}
+
+fn layer_index(t: LayerType) -> Option<usize> {
+    LAYER_TYPES.iter().position(|layer_type| *layer_type == t)
+}
+
+fn drop_index(reason: DropReason) -> usize {
+    DROP_REASONS.iter().position(|r| *r == reason).unwrap()
+}
+
+/// Represents the bits of the linear sub-buckets in each magnitude of `LatencyHistogram`, which
+/// bounds the relative error of a recorded latency to 1/32.
+const LATENCY_SUB_BUCKET_BITS: u32 = 5;
+/// Represents the number of the linear sub-buckets in each magnitude of `LatencyHistogram`.
+const LATENCY_SUB_BUCKETS: usize = 1 << LATENCY_SUB_BUCKET_BITS;
+/// Represents the max latency in microseconds recorded by `LatencyHistogram`, the larger
+/// latencies are clamped.
+const LATENCY_MAX_MICROS: u64 = u32::MAX as u64;
+/// Represents the number of the buckets of `LatencyHistogram`.
+const LATENCY_BUCKETS: usize = (32 - LATENCY_SUB_BUCKET_BITS as usize + 1) * LATENCY_SUB_BUCKETS;
+
+/// Get the index of the bucket of the given latency in microseconds.
+fn latency_index(micros: u64) -> usize {
+    let micros = micros.min(LATENCY_MAX_MICROS);
+    if micros < LATENCY_SUB_BUCKETS as u64 {
+        return micros as usize;
+    }
+
+    let magnitude = 63 - micros.leading_zeros();
+    let shift = magnitude - LATENCY_SUB_BUCKET_BITS;
+    let group = (shift + 1) as usize;
+    let sub = (micros >> shift) as usize - LATENCY_SUB_BUCKETS;
+
+    group * LATENCY_SUB_BUCKETS + sub
+}
+
+/// Get the highest latency in microseconds of the bucket of the given index.
+fn latency_highest(index: usize) -> u64 {
+    let group = index / LATENCY_SUB_BUCKETS;
+    let sub = (index % LATENCY_SUB_BUCKETS) as u64;
+    if group == 0 {
+        return sub;
+    }
+
+    let shift = group as u32 - 1;
+    ((LATENCY_SUB_BUCKETS as u64 + sub) << shift) + (1 << shift) - 1
+}
+
+/// Represents a lock-free histogram of latencies in the manner of HDR histograms. Latencies are
+/// counted in buckets of logarithmic magnitudes, each divided into linear sub-buckets, so the
+/// histogram keeps a fixed size and is updated with a few atomic operations.
+#[derive(Debug)]
+pub struct LatencyHistogram {
+    buckets: Vec<AtomicU64>,
+    count: AtomicU64,
+    sum: AtomicU64,
+    max: AtomicU64,
+}
+
+impl LatencyHistogram {
+    /// Creates a new `LatencyHistogram`.
+    pub fn new() -> LatencyHistogram {
+        LatencyHistogram {
+            buckets: (0..LATENCY_BUCKETS).map(|_| AtomicU64::new(0)).collect(),
+            count: AtomicU64::new(0),
+            sum: AtomicU64::new(0),
+            max: AtomicU64::new(0),
+        }
+    }
+
+    /// Records the given latency. Latencies are recorded in microseconds.
+    pub fn record(&self, latency: Duration) {
+        let micros = (latency.as_micros() as u64).min(LATENCY_MAX_MICROS);
+        self.buckets[latency_index(micros)].fetch_add(1, Ordering::Relaxed);
+        self.count.fetch_add(1, Ordering::Relaxed);
+        self.sum.fetch_add(micros, Ordering::Relaxed);
+        self.max.fetch_max(micros, Ordering::Relaxed);
+    }
+
+    /// Get the number of latencies recorded.
+    pub fn get_count(&self) -> u64 {
+        self.count.load(Ordering::Relaxed)
+    }
+
+    /// Get the sum of the latencies recorded.
+    pub fn get_sum(&self) -> Duration {
+        Duration::from_micros(self.sum.load(Ordering::Relaxed))
+    }
+
+    /// Get the max latency recorded.
+    pub fn get_max(&self) -> Duration {
+        Duration::from_micros(self.max.load(Ordering::Relaxed))
+    }
+
+    /// Get the latency at the given percentile in [0, 100], which is the highest latency of the
+    /// bucket the percentile falls into, but not higher than the max latency recorded. Returns
+    /// `None` if no latency is recorded.
+    pub fn get_percentile(&self, percentile: f64) -> Option<Duration> {
+        let count = self.get_count();
+        if count == 0 {
+            return None;
+        }
+
+        let rank = ((percentile.max(0.0).min(100.0) / 100.0 * count as f64).ceil() as u64).max(1);
+        let max = self.max.load(Ordering::Relaxed);
+        let mut n = 0;
+        for (i, bucket) in self.buckets.iter().enumerate() {
+            n += bucket.load(Ordering::Relaxed);
+            if n >= rank {
+                return Some(Duration::from_micros(latency_highest(i).min(max)));
+            }
+        }
+
+        Some(Duration::from_micros(max))
+    }
+
+    /// Get a snapshot of the histogram.
+    pub fn snapshot(&self) -> LatencySnapshot {
+        LatencySnapshot {
+            count: self.get_count(),
+            p50: self.get_percentile(50.0),
+            p90: self.get_percentile(90.0),
+            p99: self.get_percentile(99.0),
+            max: self.get_max(),
+        }
+    }
+}
+
+impl Default for LatencyHistogram {
+    fn default() -> Self {
+        LatencyHistogram::new()
+    }
+}
+
+/// Represents a snapshot of `LatencyHistogram`.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct LatencySnapshot {
+    pub count: u64,
+    pub p50: Option<Duration>,
+    pub p90: Option<Duration>,
+    pub p99: Option<Duration>,
+    pub max: Duration,
+}
+
+impl Display for LatencySnapshot {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match (self.p50, self.p90, self.p99) {
+            (Some(p50), Some(p90), Some(p99)) => write!(
+                f,
+                "count = {}, p50 = {:?}, p90 = {:?}, p99 = {:?}, max = {:?}",
+                self.count, p50, p90, p99, self.max
+            ),
+            _ => write!(f, "count = {}", self.count),
+        }
+    }
+}
+
+/// Represents the lock-free counters of captured packets, and the histogram of the TCP response
+/// latencies.
+#[derive(Debug, Default)]
+pub struct Stats {
+    seen: [AtomicU64; LAYER_TYPES.len()],
+    forwarded: [AtomicU64; LAYER_TYPES.len()],
+    dropped: [AtomicU64; DROP_REASONS.len()],
+    parse_errors: [AtomicU64; LAYER_TYPES.len()],
+    latency: Arc<LatencyHistogram>,
+}
+
+impl Stats {
+    /// Creates a new `Stats`.
+    pub fn new() -> Stats {
+        Stats::default()
+    }
+
+    /// Increases the counter of seen packets of the given layer type.
+    pub fn add_seen(&self, t: LayerType) {
+        if let Some(i) = layer_index(t) {
+            self.seen[i].fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Increases the counter of forwarded packets of the given layer type.
+    pub fn add_forwarded(&self, t: LayerType) {
+        if let Some(i) = layer_index(t) {
+            self.forwarded[i].fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Increases the counter of dropped packets of the given reason by the given number.
+    pub fn add_dropped(&self, reason: DropReason, n: u64) {
+        self.dropped[drop_index(reason)].fetch_add(n, Ordering::Relaxed);
+    }
+
+    /// Increases the counter of parse errors of the given layer type.
+    pub fn add_parse_error(&self, t: LayerType) {
+        if let Some(i) = layer_index(t) {
+            self.parse_errors[i].fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Get the number of seen packets of the given layer type.
+    pub fn get_seen(&self, t: LayerType) -> u64 {
+        layer_index(t).map_or(0, |i| self.seen[i].load(Ordering::Relaxed))
+    }
+
+    /// Get the number of forwarded packets of the given layer type.
+    pub fn get_forwarded(&self, t: LayerType) -> u64 {
+        layer_index(t).map_or(0, |i| self.forwarded[i].load(Ordering::Relaxed))
+    }
+
+    /// Get the number of dropped packets of the given reason.
+    pub fn get_dropped(&self, reason: DropReason) -> u64 {
+        self.dropped[drop_index(reason)].load(Ordering::Relaxed)
+    }
+
+    /// Get the number of parse errors of the given layer type.
+    pub fn get_parse_errors(&self, t: LayerType) -> u64 {
+        layer_index(t).map_or(0, |i| self.parse_errors[i].load(Ordering::Relaxed))
+    }
+
+    /// Get the histogram of the TCP response latencies, which are the time between a request
+    /// segment forwarded to the proxy and the first response segment sent back to the source.
+    pub fn get_latency(&self) -> Arc<LatencyHistogram> {
+        Arc::clone(&self.latency)
+    }
+
+    /// Get a snapshot of the counters.
+    pub fn snapshot(&self) -> StatsSnapshot {
+        StatsSnapshot {
+            seen: LAYER_TYPES
+                .iter()
+                .map(|t| (*t, self.get_seen(*t)))
+                .collect(),
+            forwarded: LAYER_TYPES
+                .iter()
+                .map(|t| (*t, self.get_forwarded(*t)))
+                .collect(),
+            dropped: DROP_REASONS
+                .iter()
+                .map(|reason| (*reason, self.get_dropped(*reason)))
+                .collect(),
+            parse_errors: LAYER_TYPES
+                .iter()
+                .map(|t| (*t, self.get_parse_errors(*t)))
+                .collect(),
+            latency: self.latency.snapshot(),
+        }
+    }
+}
+
+/// Represents a snapshot of `Stats`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct StatsSnapshot {
+    pub seen: Vec<(LayerType, u64)>,
+    pub forwarded: Vec<(LayerType, u64)>,
+    pub dropped: Vec<(DropReason, u64)>,
+    pub parse_errors: Vec<(LayerType, u64)>,
+    pub latency: LatencySnapshot,
+}
+
+impl Display for StatsSnapshot {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        let seen = self
+            .seen
+            .iter()
+            .map(|(t, n)| format!("{} = {}", t, n))
+            .collect::<Vec<_>>()
+            .join(", ");
+        let forwarded = self
+            .forwarded
+            .iter()
+            .map(|(t, n)| format!("{} = {}", t, n))
+            .collect::<Vec<_>>()
+            .join(", ");
+        let dropped = self
+            .dropped
+            .iter()
+            .map(|(reason, n)| format!("{} = {}", reason, n))
+            .collect::<Vec<_>>()
+            .join(", ");
+        let parse_errors = self
+            .parse_errors
+            .iter()
+            .map(|(t, n)| format!("{} = {}", t, n))
+            .collect::<Vec<_>>()
+            .join(", ");
+
+        write!(
+            f,
+            "Seen: {}\nForwarded: {}\nDropped: {}\nParse errors: {}\nLatency: {}",
+            seen, forwarded, dropped, parse_errors, self.latency
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn add_and_snapshot() {
+        let stats = Stats::new();
+        stats.add_seen(LayerTypes::Ipv4);
+        stats.add_seen(LayerTypes::Ipv4);
+        stats.add_forwarded(LayerTypes::Tcp);
+        stats.add_dropped(DropReason::ChecksumMismatch, 3);
+        stats.add_parse_error(LayerTypes::Udp);
+
+        assert_eq!(stats.get_seen(LayerTypes::Ipv4), 2);
+        assert_eq!(stats.get_seen(LayerTypes::Tcp), 0);
+        assert_eq!(stats.get_forwarded(LayerTypes::Tcp), 1);
+        assert_eq!(stats.get_dropped(DropReason::ChecksumMismatch), 3);
+        assert_eq!(stats.get_parse_errors(LayerTypes::Udp), 1);
+
+        let snapshot = stats.snapshot();
+        assert!(snapshot.seen.contains(&(LayerTypes::Ipv4, 2)));
+        assert!(snapshot.forwarded.contains(&(LayerTypes::Tcp, 1)));
+        assert!(snapshot
+            .dropped
+            .contains(&(DropReason::ChecksumMismatch, 3)));
+        assert_eq!(snapshot.dropped.len(), DROP_REASONS.len());
+    }
+
+    #[test]
+    fn add_concurrently() {
+        let stats = Arc::new(Stats::new());
+        let handles: Vec<_> = (0..4)
+            .map(|_| {
+                let stats = Arc::clone(&stats);
+                std::thread::spawn(move || {
+                    for _ in 0..1000 {
+                        stats.add_seen(LayerTypes::Udp);
+                    }
+                })
+            })
+            .collect();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+
+        assert_eq!(stats.get_seen(LayerTypes::Udp), 4000);
+    }
+
+    #[test]
+    fn latency_buckets() {
+        // Latencies in a bucket are within 1/32 of the highest latency of the bucket
+        for micros in [
+            0,
+            1,
+            31,
+            32,
+            33,
+            63,
+            64,
+            1000,
+            123_456,
+            10_000_000,
+            LATENCY_MAX_MICROS,
+        ] {
+            let index = latency_index(micros);
+            assert!(index < LATENCY_BUCKETS);
+            let highest = latency_highest(index);
+            assert!(highest >= micros, "{}", micros);
+            assert!(highest - micros <= micros / 32, "{}", micros);
+        }
+        // Larger latencies are clamped
+        assert_eq!(latency_index(u64::MAX), latency_index(LATENCY_MAX_MICROS));
+    }
+
+    #[test]
+    fn latency_percentiles() {
+        let latency = LatencyHistogram::new();
+        assert_eq!(latency.get_percentile(50.0), None);
+        assert_eq!(latency.snapshot().to_string(), "count = 0");
+
+        for ms in 1..=100 {
+            latency.record(Duration::from_millis(ms));
+        }
+        assert_eq!(latency.get_count(), 100);
+        assert_eq!(latency.get_sum(), Duration::from_millis(5050));
+        assert_eq!(latency.get_max(), Duration::from_millis(100));
+
+        let within = |percentile: f64, ms: u64| {
+            let p = latency.get_percentile(percentile).unwrap();
+            let expected = Duration::from_millis(ms);
+            assert!(p >= expected && p <= expected + expected / 32, "{:?}", p);
+        };
+        within(50.0, 50);
+        within(90.0, 90);
+        within(99.0, 99);
+        // The percentiles are never higher than the max
+        assert_eq!(
+            latency.get_percentile(100.0),
+            Some(Duration::from_millis(100))
+        );
+
+        let snapshot = latency.snapshot();
+        assert_eq!(snapshot.count, 100);
+        assert!(snapshot.to_string().starts_with("count = 100, p50 = "));
+    }
+}
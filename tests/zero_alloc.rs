@@ -0,0 +1,93 @@
+use pcap2socks::packet::layer::ethernet::Ethernet;
+use pcap2socks::packet::layer::ipv4::Ipv4;
+use pcap2socks::packet::layer::tcp::Tcp;
+use pcap2socks::packet::layer::udp::Udp;
+use pcap2socks::packet::layer::{LayerType, LayerTypes, Layers};
+use pcap2socks::packet::Indicator;
+use pcap2socks::pool::{BufferPool, ExhaustedPolicy};
+use pnet::datalink::MacAddr;
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+use std::net::Ipv4Addr;
+
+struct CountingAllocator;
+
+thread_local! {
+    static ALLOCATIONS: Cell<usize> = Cell::new(0);
+}
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.with(|allocations| allocations.set(allocations.get() + 1));
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+fn allocations() -> usize {
+    ALLOCATIONS.with(|allocations| allocations.get())
+}
+
+fn new_indicator(t: LayerType) -> Indicator {
+    let src = Ipv4Addr::new(10, 0, 0, 1);
+    let dst = Ipv4Addr::new(10, 0, 0, 2);
+    let ethernet = Ethernet::new(LayerTypes::Ipv4, MacAddr::zero(), MacAddr::broadcast());
+    let ipv4 = Ipv4::new(0, t, src, dst).unwrap();
+    let transport = match t {
+        LayerTypes::Tcp => {
+            let mut tcp = Tcp::new_ack(1000, 2000, 0, 0, 65535);
+            tcp.set_ipv4_layer(&ipv4);
+            Layers::Tcp(tcp)
+        }
+        _ => {
+            let mut udp = Udp::new(1000, 2000);
+            udp.set_ipv4_layer(&ipv4);
+            Layers::Udp(udp)
+        }
+    };
+
+    Indicator::new(
+        Layers::Ethernet(ethernet.unwrap()),
+        Some(Layers::Ipv4(ipv4)),
+        Some(transport),
+    )
+}
+
+#[test]
+fn zero_allocation_serialize() {
+    let pool = BufferPool::new(1514, 2, ExhaustedPolicy::Drop);
+    let indicators = [
+        new_indicator(LayerTypes::Tcp),
+        new_indicator(LayerTypes::Udp),
+    ];
+    let payload = [0x5au8; 1000];
+
+    let cycle = |indicator: &Indicator| {
+        let mut buffer = pool.get().unwrap();
+        let n = indicator
+            .serialize_with_payload(&mut buffer, &payload)
+            .unwrap();
+        let parsed = Indicator::from(&buffer[..n]).unwrap();
+        assert_eq!(parsed.get_size(), indicator.get_size());
+    };
+
+    // Warm up
+    for indicator in indicators.iter() {
+        cycle(indicator);
+    }
+
+    let before = allocations();
+    for _ in 0..1000 {
+        for indicator in indicators.iter() {
+            cycle(indicator);
+        }
+    }
+    assert_eq!(allocations() - before, 0);
+    assert_eq!(pool.get_allocated(), 1);
+}